@@ -0,0 +1,1025 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest as _, Sha256};
+
+use crate::{command::Command, glob::glob_match};
+
+pub const DEFAULT_USER: &str = "default";
+
+//entries kept by ACL LOG, the oldest are dropped first
+const LOG_MAX_LEN: usize = 128;
+
+pub const CATEGORIES: [&str; 21] = [
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+
+struct CommandSpec {
+    name: &'static str,
+    subcommands: &'static [&'static str],
+    categories: &'static [&'static str],
+}
+
+const fn spec(
+    name: &'static str,
+    subcommands: &'static [&'static str],
+    categories: &'static [&'static str],
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        subcommands,
+        categories,
+    }
+}
+
+//every command the server knows, the unit ACL rules allow or deny
+const COMMANDS: &[CommandSpec] = &[
+    spec("ping", &[], &["fast", "connection"]),
+    spec("echo", &[], &["fast", "connection"]),
+    spec("set", &[], &["write", "string", "slow"]),
+    spec("get", &[], &["read", "string", "fast"]),
+    spec("rpush", &[], &["write", "list", "fast"]),
+    spec("lpush", &[], &["write", "list", "fast"]),
+    spec("lrange", &[], &["read", "list", "slow"]),
+    spec("llen", &[], &["read", "list", "fast"]),
+    spec("lpop", &[], &["write", "list", "fast"]),
+    spec("blpop", &[], &["write", "list", "slow", "blocking"]),
+    spec("type", &[], &["keyspace", "read", "fast"]),
+    spec("xadd", &[], &["write", "stream", "fast"]),
+    spec("xtrim", &[], &["write", "stream", "slow"]),
+    spec("xdel", &[], &["write", "stream", "fast"]),
+    spec("xsetid", &[], &["write", "stream", "fast"]),
+    spec(
+        "xinfo",
+        &["stream", "groups", "consumers"],
+        &["read", "stream", "slow"],
+    ),
+    spec("xrange", &[], &["read", "stream", "slow"]),
+    spec("xrevrange", &[], &["read", "stream", "slow"]),
+    spec("xlen", &[], &["read", "stream", "fast"]),
+    spec("xread", &[], &["read", "stream", "slow", "blocking"]),
+    spec(
+        "xgroup",
+        &[
+            "create",
+            "setid",
+            "destroy",
+            "createconsumer",
+            "delconsumer",
+        ],
+        &["write", "stream", "slow"],
+    ),
+    spec("xreadgroup", &[], &["write", "stream", "slow", "blocking"]),
+    spec("xack", &[], &["write", "stream", "fast"]),
+    spec("xpending", &[], &["read", "stream", "slow"]),
+    spec("xclaim", &[], &["write", "stream", "fast"]),
+    spec("xautoclaim", &[], &["write", "stream", "fast"]),
+    spec("hello", &[], &["fast", "connection"]),
+    spec("auth", &[], &["fast", "connection"]),
+    spec("subscribe", &[], &["pubsub", "slow"]),
+    spec("unsubscribe", &[], &["pubsub", "slow"]),
+    spec("psubscribe", &[], &["pubsub", "slow"]),
+    spec("punsubscribe", &[], &["pubsub", "slow"]),
+    spec("publish", &[], &["pubsub", "fast"]),
+    spec("ssubscribe", &[], &["pubsub", "slow"]),
+    spec("sunsubscribe", &[], &["pubsub", "slow"]),
+    spec("spublish", &[], &["pubsub", "fast"]),
+    spec(
+        "pubsub",
+        &[
+            "channels",
+            "numsub",
+            "numpat",
+            "shardchannels",
+            "shardnumsub",
+        ],
+        &["pubsub", "slow"],
+    ),
+    spec(
+        "config",
+        &["get", "set", "resetstat", "rewrite"],
+        &["admin", "slow", "dangerous"],
+    ),
+    spec("info", &[], &["slow", "dangerous"]),
+    spec("save", &[], &["admin", "slow", "dangerous"]),
+    spec("bgsave", &[], &["admin", "slow", "dangerous"]),
+    spec("lastsave", &[], &["fast", "dangerous"]),
+    spec("bgrewriteaof", &[], &["admin", "slow", "dangerous"]),
+    spec("replicaof", &[], &["admin", "slow", "dangerous"]),
+    spec("replconf", &[], &["admin", "slow", "dangerous"]),
+    spec("psync", &[], &["admin", "slow", "dangerous"]),
+    spec("role", &[], &["admin", "fast", "dangerous"]),
+    spec(
+        "cluster",
+        &[
+            "info",
+            "myid",
+            "nodes",
+            "slots",
+            "shards",
+            "keyslot",
+            "countkeysinslot",
+            "getkeysinslot",
+            "addslots",
+            "addslotsrange",
+            "delslots",
+            "setslot",
+            "meet",
+            "replicate",
+            "replicas",
+            "count-failure-reports",
+        ],
+        &["admin", "slow", "dangerous"],
+    ),
+    spec("asking", &[], &["fast", "connection"]),
+    spec(
+        "sentinel",
+        &[
+            "masters",
+            "master",
+            "replicas",
+            "sentinels",
+            "get-master-addr-by-name",
+            "is-master-down-by-addr",
+            "failover",
+            "monitor",
+            "remove",
+            "set",
+            "myid",
+        ],
+        &["admin", "slow", "dangerous"],
+    ),
+    spec("wait", &[], &["slow", "connection"]),
+    spec("waitaof", &[], &["slow", "connection"]),
+    spec("multi", &[], &["fast", "transaction"]),
+    spec("exec", &[], &["slow", "transaction"]),
+    spec("discard", &[], &["fast", "transaction"]),
+    spec("watch", &[], &["fast", "transaction"]),
+    spec("unwatch", &[], &["fast", "transaction"]),
+    spec("flushall", &[], &["keyspace", "write", "slow", "dangerous"]),
+    spec("flushdb", &[], &["keyspace", "write", "slow", "dangerous"]),
+    spec("swapdb", &[], &["keyspace", "write", "fast", "dangerous"]),
+    spec("del", &[], &["keyspace", "write", "slow"]),
+    spec("dump", &[], &["keyspace", "read", "slow"]),
+    spec("restore", &[], &["keyspace", "write", "slow", "dangerous"]),
+    spec(
+        "restore-asking",
+        &[],
+        &["keyspace", "write", "slow", "dangerous"],
+    ),
+    spec("migrate", &[], &["keyspace", "write", "slow", "dangerous"]),
+    spec("eval", &[], &["slow", "scripting"]),
+    spec("eval_ro", &[], &["slow", "scripting"]),
+    spec("evalsha", &[], &["slow", "scripting"]),
+    spec("evalsha_ro", &[], &["slow", "scripting"]),
+    spec(
+        "script",
+        &["load", "exists", "flush", "kill"],
+        &["slow", "scripting"],
+    ),
+    spec("fcall", &[], &["slow", "scripting"]),
+    spec("fcall_ro", &[], &["slow", "scripting"]),
+    spec(
+        "function",
+        &["load", "delete", "list", "dump", "restore", "flush", "kill"],
+        &["slow", "scripting"],
+    ),
+    spec(
+        "acl",
+        &[
+            "setuser", "getuser", "deluser", "list", "users", "whoami", "cat", "dryrun", "log",
+            "load", "save",
+        ],
+        &["admin", "slow", "dangerous"],
+    ),
+];
+
+impl CommandSpec {
+    //the names rules are applied to, a container command stands for all of its subcommands
+    fn leaves(&self) -> Vec<String> {
+        if self.subcommands.is_empty() {
+            return vec![self.name.to_string()];
+        }
+        self.subcommands
+            .iter()
+            .map(|subcommand| format!("{}|{subcommand}", self.name))
+            .collect()
+    }
+}
+
+/// Names of the commands in the category, None when the category doesn't exist.
+pub fn category_commands(category: &str) -> Option<Vec<&'static str>> {
+    if !CATEGORIES.contains(&category) {
+        return None;
+    }
+
+    Some(
+        COMMANDS
+            .iter()
+            .filter(|spec| spec.categories.contains(&category))
+            .map(|spec| spec.name)
+            .collect(),
+    )
+}
+
+/// Where a denied command was issued from, as reported by ACL LOG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    TopLevel,
+    Multi,
+    Lua,
+}
+
+impl Context {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Context::TopLevel => "toplevel",
+            Context::Multi => "multi",
+            Context::Lua => "lua",
+        }
+    }
+}
+
+/// What made a user fail a permission check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denial {
+    Command(String),
+    Key(String),
+    Channel(String),
+}
+
+impl Denial {
+    /// The error replied to the client.
+    pub fn error(&self, username: &str) -> String {
+        match self {
+            Denial::Command(name) => {
+                format!("NOPERM User {username} has no permissions to run the '{name}' command")
+            }
+            Denial::Key(_) => "NOPERM No permissions to access a key".into(),
+            Denial::Channel(_) => "NOPERM No permissions to access a channel".into(),
+        }
+    }
+
+    /// The message ACL DRYRUN replies with.
+    pub fn dry_run_message(&self, username: &str) -> String {
+        match self {
+            Denial::Command(name) => {
+                format!("User {username} has no permissions to run the '{name}' command")
+            }
+            Denial::Key(key) => {
+                format!("User {username} has no permissions to access the '{key}' key")
+            }
+            Denial::Channel(channel) => {
+                format!("User {username} has no permissions to access the '{channel}' channel")
+            }
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self {
+            Denial::Command(_) => "command",
+            Denial::Key(_) => "key",
+            Denial::Channel(_) => "channel",
+        }
+    }
+
+    fn object(&self) -> &str {
+        match self {
+            Denial::Command(object) | Denial::Key(object) | Denial::Channel(object) => object,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+impl KeyPattern {
+    fn describe(&self) -> String {
+        match (self.read, self.write) {
+            (true, false) => format!("%R~{}", self.pattern),
+            (false, true) => format!("%W~{}", self.pattern),
+            _ => format!("~{}", self.pattern),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    enabled: bool,
+    nopass: bool,
+    //sha256 of the passwords, hex encoded
+    passwords: BTreeSet<String>,
+    //names of the commands, or command|subcommand, the user can run
+    allowed: HashSet<String>,
+    //the command rules applied since the last -@all, to describe the user
+    command_rules: Vec<String>,
+    keys: Vec<KeyPattern>,
+    channels: Vec<String>,
+}
+
+impl Default for User {
+    //new users can't do anything until rules are given
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            allowed: HashSet::new(),
+            command_rules: vec!["-@all".into()],
+            keys: vec![],
+            channels: vec![],
+        }
+    }
+}
+
+impl User {
+    /// The default user of a fresh server: no password and every permission.
+    pub fn unrestricted() -> Self {
+        let mut user = User::default();
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            user.apply(rule).expect("valid rule");
+        }
+        user
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_nopass(&self) -> bool {
+        self.nopass
+    }
+
+    /// Applies a single ACL SETUSER rule, returning why it is invalid otherwise.
+    pub fn apply(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_ascii_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => return self.apply("~*"),
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => return self.apply("&*"),
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "reset" => *self = User::default(),
+            _ => return self.apply_pattern(rule),
+        }
+
+        Ok(())
+    }
+
+    //the rules carrying a value: passwords, key and channel patterns, commands
+    fn apply_pattern(&mut self, rule: &str) -> Result<(), String> {
+        let Some(first) = rule.chars().next() else {
+            return Err("Syntax error".into());
+        };
+        let value = &rule[1..];
+
+        match first {
+            '>' => {
+                self.passwords.insert(hash_password(value));
+                self.nopass = false;
+            }
+            '<' => {
+                if !self.passwords.remove(&hash_password(value)) {
+                    return Err(
+                        "The password you are trying to remove from the user does not exist".into(),
+                    );
+                }
+            }
+            '#' => {
+                if value.len() != 64
+                    || !value
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+                {
+                    return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".into());
+                }
+                self.passwords.insert(value.to_string());
+                self.nopass = false;
+            }
+            '!' => {
+                if !self.passwords.remove(value) {
+                    return Err(
+                        "The password you are trying to remove from the user does not exist".into(),
+                    );
+                }
+            }
+            '~' => self.add_key_pattern(value, true, true),
+            '%' => {
+                let Some((permissions, pattern)) = value.split_once('~') else {
+                    return Err("Syntax error".into());
+                };
+                let (mut read, mut write) = (false, false);
+                for permission in permissions.chars() {
+                    match permission.to_ascii_uppercase() {
+                        'R' => read = true,
+                        'W' => write = true,
+                        _ => return Err("Syntax error".into()),
+                    }
+                }
+                if !read && !write {
+                    return Err("Syntax error".into());
+                }
+                self.add_key_pattern(pattern, read, write);
+            }
+            '&' => {
+                if !self.channels.iter().any(|channel| channel == value) {
+                    self.channels.push(value.to_string());
+                }
+            }
+            '+' | '-' => self.apply_command_rule(first == '+', &value.to_ascii_lowercase())?,
+            _ => return Err("Syntax error".into()),
+        }
+
+        Ok(())
+    }
+
+    fn add_key_pattern(&mut self, pattern: &str, read: bool, write: bool) {
+        match self.keys.iter_mut().find(|key| key.pattern == pattern) {
+            Some(key) => {
+                key.read |= read;
+                key.write |= write;
+            }
+            None => self.keys.push(KeyPattern {
+                pattern: pattern.to_string(),
+                read,
+                write,
+            }),
+        }
+    }
+
+    // +<command> | +<command>|<subcommand> | +@<category>, and their - counterparts
+    fn apply_command_rule(&mut self, allow: bool, name: &str) -> Result<(), String> {
+        let leaves: Vec<String> = match name.strip_prefix('@') {
+            Some("all") => COMMANDS.iter().flat_map(CommandSpec::leaves).collect(),
+            Some(category) => {
+                if !CATEGORIES.contains(&category) {
+                    return Err("Unknown command or category name in ACL".into());
+                }
+                COMMANDS
+                    .iter()
+                    .filter(|spec| spec.categories.contains(&category))
+                    .flat_map(CommandSpec::leaves)
+                    .collect()
+            }
+            None => {
+                let (command, subcommand) = match name.split_once('|') {
+                    Some((command, subcommand)) => (command, Some(subcommand)),
+                    None => (name, None),
+                };
+                let spec = COMMANDS
+                    .iter()
+                    .find(|spec| spec.name == command)
+                    .ok_or("Unknown command or category name in ACL")?;
+                match subcommand {
+                    None => spec.leaves(),
+                    Some(subcommand) if spec.subcommands.contains(&subcommand) => {
+                        vec![name.to_string()]
+                    }
+                    Some(_) => return Err("Unknown command or category name in ACL".into()),
+                }
+            }
+        };
+
+        for leaf in leaves {
+            if allow {
+                self.allowed.insert(leaf);
+            } else {
+                self.allowed.remove(&leaf);
+            }
+        }
+
+        //the rules before a blanket one are overridden by it
+        if name == "@all" {
+            self.command_rules.clear();
+        }
+        self.command_rules
+            .push(format!("{}{name}", if allow { '+' } else { '-' }));
+        Ok(())
+    }
+
+    pub fn check_password(&self, password: &str) -> bool {
+        self.nopass || self.passwords.contains(&hash_password(password))
+    }
+
+    /// Checks that the user can run the command, on the keys and channels it refers to.
+    pub fn check(&self, cmd: &Command) -> Result<(), Denial> {
+        //connections need these to authenticate in the first place
+        if matches!(cmd, Command::Auth { .. } | Command::Hello { .. }) {
+            return Ok(());
+        }
+        let Some(name) = cmd.full_name() else {
+            return Ok(());
+        };
+        if !self.allowed.contains(&name) {
+            return Err(Denial::Command(name));
+        }
+
+        let (read, write) = key_access(cmd);
+        for key in cmd.keys() {
+            let permitted = self.keys.iter().any(|pattern| {
+                (pattern.read || !read)
+                    && (pattern.write || !write)
+                    && glob_match(pattern.pattern.as_bytes(), key.as_bytes())
+            });
+            if !permitted {
+                return Err(Denial::Key(key.to_string()));
+            }
+        }
+
+        let (channels, patterns) = cmd.channels();
+        for channel in channels {
+            let permitted = self
+                .channels
+                .iter()
+                .any(|pattern| glob_match(pattern.as_bytes(), channel.as_bytes()));
+            if !permitted {
+                return Err(Denial::Channel(channel.to_string()));
+            }
+        }
+        //patterns can't be matched against patterns, they have to be allowed as they are
+        for pattern in patterns {
+            if !self
+                .channels
+                .iter()
+                .any(|allowed| allowed == "*" || allowed == pattern)
+            {
+                return Err(Denial::Channel(pattern.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    pub fn passwords(&self) -> impl Iterator<Item = &String> {
+        self.passwords.iter()
+    }
+
+    pub fn describe_commands(&self) -> String {
+        self.command_rules.join(" ")
+    }
+
+    pub fn describe_keys(&self) -> String {
+        self.keys
+            .iter()
+            .map(KeyPattern::describe)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn describe_channels(&self) -> String {
+        self.channels
+            .iter()
+            .map(|channel| format!("&{channel}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The rules recreating the user, as listed by ACL LIST and saved in the ACL file.
+    pub fn describe(&self) -> String {
+        let mut rules: Vec<String> = self.flags().into_iter().map(String::from).collect();
+        rules.extend(self.passwords.iter().map(|hash| format!("#{hash}")));
+        if !self.keys.is_empty() {
+            rules.push(self.describe_keys());
+        }
+        rules.push(match self.channels.is_empty() {
+            true => "resetchannels".into(),
+            false => self.describe_channels(),
+        });
+        rules.push(self.describe_commands());
+        rules.join(" ")
+    }
+}
+
+//the access to the keys required by the command: read, write or both
+fn key_access(cmd: &Command) -> (bool, bool) {
+    let returns_values = matches!(
+        cmd,
+        Command::LPop { .. }
+            | Command::BlPop { .. }
+            | Command::XReadGroup { .. }
+            | Command::XClaim { .. }
+            | Command::XAutoClaim { .. }
+            | Command::Migrate { .. }
+    );
+    match cmd {
+        //the keys of a script are checked by the commands it runs
+        Command::Eval { .. } | Command::EvalSha { .. } | Command::FCall { .. } => (false, false),
+        cmd if cmd.is_write() => (returns_values, true),
+        _ => (true, false),
+    }
+}
+
+fn hash_password(password: &str) -> String {
+    Sha256::digest(password.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct LogEntry {
+    pub count: u64,
+    pub reason: &'static str,
+    pub context: &'static str,
+    pub object: String,
+    pub username: String,
+    pub client_info: String,
+    pub entry_id: u64,
+    pub created: SystemTime,
+    pub updated: SystemTime,
+}
+
+impl LogEntry {
+    pub fn age(&self) -> Duration {
+        self.created.elapsed().unwrap_or_default()
+    }
+
+    pub fn timestamp(time: SystemTime) -> i64 {
+        time.duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as i64)
+            .unwrap_or_default()
+    }
+}
+
+/// The users of the server, along with the log of the permission checks they failed.
+#[derive(Debug)]
+pub struct Acl {
+    users: BTreeMap<String, User>,
+    //newest entries first
+    log: VecDeque<LogEntry>,
+    next_entry_id: u64,
+    file: Option<PathBuf>,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        Self {
+            users: BTreeMap::from([(DEFAULT_USER.to_string(), User::unrestricted())]),
+            log: VecDeque::new(),
+            next_entry_id: 0,
+            file: None,
+        }
+    }
+}
+
+impl Acl {
+    /// Loads the users of the ACL file, which is used from then on by ACL LOAD and ACL SAVE.
+    pub fn with_file(path: &Path) -> Result<Self, String> {
+        Ok(Self {
+            users: read_file(path)?,
+            file: Some(path.to_path_buf()),
+            ..Self::default()
+        })
+    }
+
+    pub fn user(&self, username: &str) -> Option<&User> {
+        self.users.get(username)
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = (&String, &User)> {
+        self.users.iter()
+    }
+
+    /// The enabled user matching the password, None if authentication fails.
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<&User> {
+        self.users
+            .get(username)
+            .filter(|user| user.enabled && user.check_password(password))
+    }
+
+    /// Sets the only password of the default user, an empty one lets anyone in.
+    pub fn set_default_password(&mut self, password: &str) {
+        let rules = match password.is_empty() {
+            true => ["resetpass".to_string(), "nopass".to_string()],
+            false => ["resetpass".to_string(), format!(">{password}")],
+        };
+        self.set_user(DEFAULT_USER, &rules)
+            .expect("password rules are valid");
+    }
+
+    /// Creates the user if needed and applies the rules, all of them or none.
+    pub fn set_user(&mut self, username: &str, rules: &[String]) -> Result<(), String> {
+        let mut user = self.users.get(username).cloned().unwrap_or_default();
+        for rule in rules {
+            user.apply(rule)
+                .map_err(|err| format!("ERR Error in ACL SETUSER modifier '{rule}': {err}"))?;
+        }
+
+        self.users.insert(username.to_string(), user);
+        Ok(())
+    }
+
+    /// Removes the users, the default one excepted, returning how many existed.
+    pub fn delete_users(&mut self, usernames: &[String]) -> Result<usize, String> {
+        if usernames.iter().any(|username| username == DEFAULT_USER) {
+            return Err("ERR The 'default' user cannot be removed".into());
+        }
+
+        Ok(usernames
+            .iter()
+            .filter(|username| self.users.remove(*username).is_some())
+            .count())
+    }
+
+    pub fn log_denial(
+        &mut self,
+        denial: &Denial,
+        context: Context,
+        username: &str,
+        client_info: String,
+    ) {
+        self.log_entry(
+            denial.reason(),
+            context,
+            denial.object(),
+            username,
+            client_info,
+        );
+    }
+
+    pub fn log_auth_failure(&mut self, username: &str, client_info: String) {
+        self.log_entry("auth", Context::TopLevel, "AUTH", username, client_info);
+    }
+
+    //failures repeating the last ones are counted in the existing entry
+    fn log_entry(
+        &mut self,
+        reason: &'static str,
+        context: Context,
+        object: &str,
+        username: &str,
+        client_info: String,
+    ) {
+        let now = SystemTime::now();
+        let context = context.as_str();
+
+        if let Some(entry) = self.log.iter_mut().find(|entry| {
+            entry.reason == reason
+                && entry.context == context
+                && entry.object == object
+                && entry.username == username
+        }) {
+            entry.count += 1;
+            entry.updated = now;
+            entry.client_info = client_info;
+            return;
+        }
+
+        self.log.push_front(LogEntry {
+            count: 1,
+            reason,
+            context,
+            object: object.to_string(),
+            username: username.to_string(),
+            client_info,
+            entry_id: self.next_entry_id,
+            created: now,
+            updated: now,
+        });
+        self.next_entry_id += 1;
+        self.log.truncate(LOG_MAX_LEN);
+    }
+
+    pub fn log(&self) -> impl Iterator<Item = &LogEntry> {
+        self.log.iter()
+    }
+
+    pub fn reset_log(&mut self) {
+        self.log.clear();
+    }
+
+    /// Replaces the users with the ones of the ACL file, left untouched if the file is invalid.
+    pub fn load(&mut self) -> Result<(), String> {
+        let path = self.file.as_ref().ok_or_else(no_acl_file)?;
+        self.users = read_file(path)?;
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = self.file.as_ref().ok_or_else(no_acl_file)?;
+        let content: String = self
+            .users
+            .iter()
+            .map(|(name, user)| format!("user {name} {}\n", user.describe()))
+            .collect();
+
+        fs::write(path, content).map_err(|err| format!("ERR There was an error trying to save the ACLs. Please check the server logs for more information: {err}"))
+    }
+}
+
+fn no_acl_file() -> String {
+    "ERR This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.".into()
+}
+
+// user <username> [<rule> ...], one per line
+fn read_file(path: &Path) -> Result<BTreeMap<String, User>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("ERR Error loading ACL file {}: {err}", path.display()))?;
+
+    let mut users = BTreeMap::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |reason: String| format!("ERR {}:{}: {reason}", path.display(), number + 1);
+        let mut parts = line.split_whitespace();
+        if parts.next() != Some("user") {
+            return Err(error("line should start with user keyword".into()));
+        }
+        let Some(username) = parts.next() else {
+            return Err(error("missing user name".into()));
+        };
+        if users.contains_key(username) {
+            return Err(error(format!("Duplicate user '{username}' found")));
+        }
+
+        let mut user = User::default();
+        for rule in parts {
+            user.apply(rule)
+                .map_err(|err| error(format!("Error in applying operation '{rule}': {err}")))?;
+        }
+        users.insert(username.to_string(), user);
+    }
+
+    //the file may leave the default user out, it keeps its usual permissions then
+    users
+        .entry(DEFAULT_USER.to_string())
+        .or_insert_with(User::unrestricted);
+
+    Ok(users)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Acl, Context, Denial, User, category_commands};
+    use crate::command::Command;
+
+    fn get(key: &str) -> Command {
+        Command::Get { key: key.into() }
+    }
+
+    fn rules(rules: &[&str]) -> Vec<String> {
+        rules.iter().map(|rule| rule.to_string()).collect()
+    }
+
+    #[test]
+    fn test_user_rules() {
+        let mut acl = Acl::default();
+        acl.set_user(
+            "alice",
+            &rules(&[
+                "on",
+                ">secret",
+                "+@read",
+                "-lrange",
+                "%R~cache:*",
+                "~own:*",
+                "&news.*",
+            ]),
+        )
+        .unwrap();
+        let alice = acl.user("alice").unwrap();
+
+        assert!(acl.authenticate("alice", "secret").is_some());
+        assert!(acl.authenticate("alice", "wrong").is_none());
+        assert!(alice.check(&get("cache:1")).is_ok());
+        assert_eq!(alice.check(&get("other")), Err(Denial::Key("other".into())));
+        assert_eq!(
+            alice.check(&Command::LRange {
+                key: "own:1".into(),
+                start: 0,
+                stop: -1
+            }),
+            Err(Denial::Command("lrange".into()))
+        );
+        //read only patterns don't allow writes
+        let set = Command::from(crate::resp::RespType::Array {
+            elements: ["SET", "cache:1", "v"]
+                .iter()
+                .map(|arg| crate::resp::RespType::BulkString {
+                    data: arg.as_bytes().to_vec(),
+                })
+                .collect(),
+        });
+        assert_eq!(alice.check(&set), Err(Denial::Command("set".into())));
+        acl.set_user("alice", &rules(&["+set"])).unwrap();
+        let alice = acl.user("alice").unwrap();
+        assert_eq!(alice.check(&set), Err(Denial::Key("cache:1".into())));
+
+        let publish = Command::Publish {
+            channel: "sports".into(),
+            message: "goal".into(),
+        };
+        assert_eq!(
+            alice.check(&publish),
+            Err(Denial::Command("publish".into()))
+        );
+        assert_eq!(
+            alice.describe(),
+            format!(
+                "on #{} %R~cache:* ~own:* &news.* -@all +@read -lrange +set",
+                super::hash_password("secret")
+            )
+        );
+
+        //a failing rule leaves the user as it was
+        let err = acl
+            .set_user("alice", &rules(&["off", "+nosuchcommand"]))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "ERR Error in ACL SETUSER modifier '+nosuchcommand': Unknown command or category name in ACL"
+        );
+        assert!(acl.user("alice").unwrap().is_enabled());
+
+        assert!(acl.delete_users(&rules(&["default"])).is_err());
+        assert_eq!(acl.delete_users(&rules(&["alice", "bob"])), Ok(1));
+    }
+
+    #[test]
+    fn test_subcommands_and_categories() {
+        let mut user = User::unrestricted();
+        user.apply("-config|set").unwrap();
+        assert!(user.apply("+config|nope").is_err());
+
+        let config_get = Command::Config {
+            subcommand: crate::command::ConfigSubcommand::Get {
+                parameters: vec!["*".into()],
+            },
+        };
+        assert!(user.check(&config_get).is_ok());
+        user.apply("-@admin").unwrap();
+        assert_eq!(
+            user.check(&config_get),
+            Err(Denial::Command("config|get".into()))
+        );
+
+        assert!(category_commands("stream").unwrap().contains(&"xadd"));
+        assert!(category_commands("nope").is_none());
+    }
+
+    #[test]
+    fn test_log() {
+        let mut acl = Acl::default();
+        let denial = Denial::Key("secret".into());
+        acl.log_denial(&denial, Context::TopLevel, "alice", "id=1".into());
+        acl.log_denial(&denial, Context::TopLevel, "alice", "id=1".into());
+        acl.log_auth_failure("bob", "id=2".into());
+
+        let entries: Vec<_> = acl.log().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].reason, "auth");
+        assert_eq!(entries[1].count, 2);
+
+        acl.reset_log();
+        assert_eq!(acl.log().count(), 0);
+    }
+}
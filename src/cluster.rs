@@ -0,0 +1,753 @@
+//! Cluster mode. The keyspace is split into hash slots, each served by one of the master nodes
+//! of the cluster, which may have replicas. A node knows the others and the slots they serve,
+//! requests for the keys of the slots it doesn't serve are redirected to the right node.
+//!
+//! Conflicting claims on a slot are settled by epochs: every master has a configuration epoch,
+//! unique in the cluster, and the claim of the master with the greatest one wins. A replica
+//! replacing a failed master gets a new epoch, greater than any other, from the votes of the
+//! majority of the masters.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+mod message;
+
+pub use message::{Gossip, Message, MessageType};
+
+/// Number of hash slots the keyspace is split into.
+pub const SLOTS: u16 = 16384;
+
+/// CRC16 (XMODEM variant, polynomial 0x1021) as specified by the redis cluster.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Slot a key (or a shard channel) belongs to. When the key contains a non empty hashtag, i.e.
+/// the part between the first `{` and the following `}`, only the hashtag is hashed so that
+/// related keys can be forced into the same slot.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let hashed = key
+        .iter()
+        .position(|c| *c == b'{')
+        .and_then(|open| {
+            key[open + 1..]
+                .iter()
+                .position(|c| *c == b'}')
+                .filter(|len| *len > 0)
+                .map(|len| &key[open + 1..open + 1 + len])
+        })
+        .unwrap_or(key);
+
+    crc16(hashed) % SLOTS
+}
+
+/// A node of the cluster, as this one knows it.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: String,
+    //empty until it is known, this node learns its own from the connections of the others
+    pub ip: String,
+    pub port: u16,
+    pub cport: u16,
+    //added by CLUSTER MEET, the id is made up until the node answers
+    pub handshake: bool,
+    //the node doesn't know this one yet, it is sent a MEET rather than a PING
+    pub meet: bool,
+    //unix times in milliseconds, 0 for never
+    pub ping_sent: u64,
+    pub pong_received: u64,
+    //whether the link this node opened to it is established
+    pub connected: bool,
+    pub created: Instant,
+    //the master it replicates, None for a master
+    pub master: Option<String>,
+    pub config_epoch: u64,
+    //its replication offset, as it last told
+    pub repl_offset: u64,
+    //it didn't answer a ping in time, which is only this node's opinion
+    pub pfail: bool,
+    //when a majority of the masters agreed it failed
+    pub fail_time: Option<Instant>,
+    //by the id of the master reporting it, when the node was last reported as failing
+    pub fail_reports: HashMap<String, Instant>,
+    //for a master, when this node last voted for one of its replicas to replace it
+    pub voted_time: Option<Instant>,
+}
+
+impl Node {
+    pub fn new(id: String, ip: String, port: u16, cport: u16) -> Self {
+        Self {
+            id,
+            ip,
+            port,
+            cport,
+            handshake: false,
+            meet: false,
+            ping_sent: 0,
+            pong_received: 0,
+            connected: false,
+            created: Instant::now(),
+            master: None,
+            config_epoch: 0,
+            repl_offset: 0,
+            pfail: false,
+            fail_time: None,
+            fail_reports: HashMap::new(),
+            voted_time: None,
+        }
+    }
+
+    /// Where clients connect to it, as given in redirections.
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+
+    pub fn is_master(&self) -> bool {
+        self.master.is_none()
+    }
+
+    pub fn failed(&self) -> bool {
+        self.fail_time.is_some()
+    }
+
+    /// The number of masters that reported it as failing lately, older reports are dropped.
+    pub fn failure_reports(&mut self, validity: Duration) -> usize {
+        self.fail_reports
+            .retain(|_, reported| reported.elapsed() <= validity);
+        self.fail_reports.len()
+    }
+}
+
+/// The nodes of the cluster and the slots they serve, as this node knows them.
+#[derive(Debug)]
+pub struct Cluster {
+    myself: String,
+    nodes: BTreeMap<String, Node>,
+    //the id of the node serving each slot
+    owners: Vec<Option<String>>,
+    //slots moving from this node to another and from another node to this one, with the id of
+    //the other node
+    migrating: BTreeMap<u16, String>,
+    importing: BTreeMap<u16, String>,
+    //the greatest epoch seen in the cluster
+    pub current_epoch: u64,
+    //the epoch in which this node last voted for a replica
+    pub last_vote_epoch: u64,
+}
+
+impl Cluster {
+    pub fn new(myself: Node) -> Self {
+        Self {
+            myself: myself.id.clone(),
+            nodes: BTreeMap::from([(myself.id.clone(), myself)]),
+            owners: vec![None; SLOTS as usize],
+            migrating: BTreeMap::new(),
+            importing: BTreeMap::new(),
+            current_epoch: 0,
+            last_vote_epoch: 0,
+        }
+    }
+
+    pub fn myself(&self) -> &Node {
+        &self.nodes[&self.myself]
+    }
+
+    pub fn myself_mut(&mut self) -> &mut Node {
+        self.nodes
+            .get_mut(&self.myself)
+            .expect("myself is never removed")
+    }
+
+    pub fn node(&self, id: &str) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+
+    pub fn node_mut(&mut self, id: &str) -> Option<&mut Node> {
+        self.nodes.get_mut(id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    pub fn add_node(&mut self, node: Node) {
+        self.nodes.insert(node.id.clone(), node);
+    }
+
+    /// Forgets the node, the slots it served are left unassigned.
+    pub fn remove_node(&mut self, id: &str) {
+        if id == self.myself || self.nodes.remove(id).is_none() {
+            return;
+        }
+        for owner in self.owners.iter_mut() {
+            if owner.as_deref() == Some(id) {
+                *owner = None;
+            }
+        }
+        self.migrating.retain(|_, node| node != id);
+        self.importing.retain(|_, node| node != id);
+    }
+
+    /// Starts the handshake with a node known by its address only, unless one already is.
+    /// The node gets a made up id until it answers. Returns whether it started.
+    pub fn start_handshake(&mut self, id: String, ip: String, port: u16, cport: u16) -> bool {
+        if self
+            .nodes
+            .values()
+            .any(|node| node.handshake && node.ip == ip && node.port == port)
+        {
+            return false;
+        }
+        let mut node = Node::new(id, ip, port, cport);
+        node.handshake = true;
+        node.meet = true;
+        self.add_node(node);
+        true
+    }
+
+    /// Gives a node met through a handshake the id it actually has.
+    pub fn rename_node(&mut self, from: &str, to: &str) {
+        if let Some(mut node) = self.nodes.remove(from) {
+            node.id = to.to_string();
+            self.nodes.insert(to.to_string(), node);
+        }
+    }
+
+    pub fn owner(&self, slot: u16) -> Option<&Node> {
+        self.owners[slot as usize]
+            .as_ref()
+            .and_then(|id| self.nodes.get(id))
+    }
+
+    pub fn assign(&mut self, slot: u16, id: &str) {
+        self.owners[slot as usize] = Some(id.to_string());
+    }
+
+    pub fn unassign(&mut self, slot: u16) {
+        self.owners[slot as usize] = None;
+    }
+
+    /// The replicas of the master.
+    pub fn replicas(&self, id: &str) -> impl Iterator<Item = &Node> {
+        self.nodes
+            .values()
+            .filter(move |node| node.master.as_deref() == Some(id))
+    }
+
+    /// Makes the node a replica of the master, or a master with None. A master becoming a
+    /// replica no longer serves any slot. Returns whether anything changed.
+    pub fn set_master(&mut self, id: &str, master: Option<String>) -> bool {
+        let Some(node) = self.nodes.get_mut(id) else {
+            return false;
+        };
+        if node.master == master {
+            return false;
+        }
+        let was_master = node.is_master();
+        node.master = master;
+        if was_master && !node.is_master() {
+            for owner in self.owners.iter_mut() {
+                if owner.as_deref() == Some(id) {
+                    *owner = None;
+                }
+            }
+        }
+        true
+    }
+
+    /// The node answered a ping: it is no longer possibly failing, nor failing when no replica
+    /// has to replace it or none did in time. Returns whether it was failing.
+    pub fn pong_received(&mut self, id: &str, undo_time: Duration) -> bool {
+        let serving = !self.slots(id).is_empty();
+        let Some(node) = self.nodes.get_mut(id) else {
+            return false;
+        };
+        node.pong_received = unix_time_ms();
+        node.ping_sent = 0;
+        node.pfail = false;
+
+        let replaceable = node.is_master() && serving;
+        let Some(fail_time) = node.fail_time else {
+            return false;
+        };
+        if replaceable && fail_time.elapsed() <= undo_time {
+            return false;
+        }
+        node.fail_time = None;
+        true
+    }
+
+    /// What to tell the other nodes about some of the nodes in a ping: a tenth of them, at least
+    /// 3, taken in order from a random position, and all the ones possibly failing. This node
+    /// and the ones it is not sure of are left out.
+    pub fn gossip(&self, start: usize) -> Vec<Gossip> {
+        let known: Vec<&Node> = self
+            .nodes
+            .values()
+            .filter(|node| node.id != self.myself && !node.handshake && !node.ip.is_empty())
+            .collect();
+        let wanted = (known.len() / 10).max(3).min(known.len());
+
+        let mut gossip: Vec<Gossip> = vec![];
+        for (i, node) in known.iter().enumerate() {
+            let picked = (i + known.len() - start % known.len().max(1)) % known.len() < wanted;
+            if picked || node.pfail {
+                gossip.push(Gossip {
+                    id: node.id.clone(),
+                    ip: node.ip.clone(),
+                    port: node.port,
+                    cport: node.cport,
+                    pfail: node.pfail,
+                    fail: node.failed(),
+                });
+            }
+        }
+        gossip
+    }
+
+    /// The greatest configuration epoch of the nodes.
+    pub fn max_epoch(&self) -> u64 {
+        self.nodes
+            .values()
+            .map(|node| node.config_epoch)
+            .max()
+            .unwrap_or(0)
+            .max(self.current_epoch)
+    }
+
+    /// The number of masters agreeing on something for the cluster to do it.
+    pub fn quorum(&self) -> usize {
+        self.size() / 2 + 1
+    }
+
+    /// The slots the node serves, in order.
+    pub fn slots(&self, id: &str) -> Vec<u16> {
+        (0..SLOTS)
+            .filter(|slot| self.owners[*slot as usize].as_deref() == Some(id))
+            .collect()
+    }
+
+    /// The slots the node serves as ranges of consecutive slots, bounds included.
+    pub fn slot_ranges(&self, id: &str) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = vec![];
+        for slot in self.slots(id) {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot)),
+            }
+        }
+        ranges
+    }
+
+    /// The node the slot is being moved to, when this node serves it.
+    pub fn migrating(&self, slot: u16) -> Option<&str> {
+        self.migrating.get(&slot).map(String::as_str)
+    }
+
+    /// The node the slot is being moved from, to this node.
+    pub fn importing(&self, slot: u16) -> Option<&str> {
+        self.importing.get(&slot).map(String::as_str)
+    }
+
+    pub fn set_migrating(&mut self, slot: u16, to: Option<String>) {
+        match to {
+            Some(to) => self.migrating.insert(slot, to),
+            None => self.migrating.remove(&slot),
+        };
+    }
+
+    pub fn set_importing(&mut self, slot: u16, from: Option<String>) {
+        match from {
+            Some(from) => self.importing.insert(slot, from),
+            None => self.importing.remove(&slot),
+        };
+    }
+
+    /// Takes into account the slots a master claims to serve in one of its messages: the
+    /// unassigned ones become its own, like the ones served by masters with a lower
+    /// configuration epoch, and the ones it no longer claims unassigned. The slots this node is
+    /// importing are left alone. Returns whether anything changed.
+    pub fn update_slots(&mut self, sender: &str, claimed: &[u16]) -> bool {
+        let Some(epoch) = self.nodes.get(sender).map(|node| node.config_epoch) else {
+            return false;
+        };
+        let mut is_claimed = vec![false; SLOTS as usize];
+        for slot in claimed {
+            is_claimed[*slot as usize] = true;
+        }
+
+        let mut changed = false;
+        for slot in 0..SLOTS {
+            let owner = self.owners[slot as usize].as_deref();
+            let claimed = is_claimed[slot as usize];
+            let update = match owner {
+                Some(id) if id == sender => !claimed,
+                _ if !claimed || self.importing.contains_key(&slot) => false,
+                None => true,
+                Some(id) => self
+                    .nodes
+                    .get(id)
+                    .is_none_or(|node| node.config_epoch < epoch),
+            };
+            if update {
+                self.owners[slot as usize] = claimed.then(|| sender.to_string());
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    pub fn slots_assigned(&self) -> usize {
+        self.owners.iter().filter(|owner| owner.is_some()).count()
+    }
+
+    /// The number of slots served by a node that is failing, in this node's opinion only or
+    /// according to the majority of the masters.
+    pub fn slots_failing(&self, failed: bool) -> usize {
+        self.owners
+            .iter()
+            .filter_map(|owner| self.nodes.get(owner.as_ref()?))
+            .filter(|node| match failed {
+                true => node.failed(),
+                false => node.pfail && !node.failed(),
+            })
+            .count()
+    }
+
+    /// Whether keys can be served: every slot has to be served by a master that didn't fail,
+    /// unless full coverage is not required, and this node must be able to reach the majority
+    /// of the masters, or it could be in a minority that is being failed over.
+    pub fn is_ok(&self, require_full_coverage: bool) -> bool {
+        if require_full_coverage
+            && (self.slots_assigned() < SLOTS as usize || self.slots_failing(true) > 0)
+        {
+            return false;
+        }
+        let reachable = self
+            .nodes
+            .values()
+            .filter(|node| !node.pfail && !node.failed() && !self.slots(&node.id).is_empty())
+            .count();
+        reachable >= self.quorum()
+    }
+
+    /// The number of nodes serving at least a slot.
+    pub fn size(&self) -> usize {
+        self.nodes
+            .keys()
+            .filter(|id| self.owners.iter().any(|owner| owner.as_ref() == Some(*id)))
+            .count()
+    }
+
+    /// One line per node, as CLUSTER NODES lists them: id, address, flags, master, the last
+    /// ping sent and pong received, the configuration epoch, the state of the link and the
+    /// slots served.
+    pub fn describe(&self) -> String {
+        self.nodes
+            .values()
+            .map(|node| self.describe_node(node) + "\n")
+            .collect()
+    }
+
+    /// The line of CLUSTER NODES about the node.
+    pub fn describe_node(&self, node: &Node) -> String {
+        let myself = node.id == self.myself;
+        let mut flags = vec![];
+        if myself {
+            flags.push("myself");
+        }
+        if node.handshake {
+            flags.push("handshake");
+        } else if node.is_master() {
+            flags.push("master");
+        } else {
+            flags.push("slave");
+        }
+        if node.failed() {
+            flags.push("fail");
+        } else if node.pfail {
+            flags.push("fail?");
+        }
+        let link = match myself || node.connected {
+            true => "connected",
+            false => "disconnected",
+        };
+
+        let mut line = format!(
+            "{} {}:{}@{} {} {} {} {} {} {link}",
+            node.id,
+            node.ip,
+            node.port,
+            node.cport,
+            flags.join(","),
+            node.master.as_deref().unwrap_or("-"),
+            node.ping_sent,
+            node.pong_received,
+            node.config_epoch
+        );
+        for (start, end) in self.slot_ranges(&node.id) {
+            match start == end {
+                true => line.push_str(&format!(" {start}")),
+                false => line.push_str(&format!(" {start}-{end}")),
+            }
+        }
+        if myself {
+            for (slot, to) in &self.migrating {
+                line.push_str(&format!(" [{slot}->-{to}]"));
+            }
+            for (slot, from) in &self.importing {
+                line.push_str(&format!(" [{slot}-<-{from}]"));
+            }
+        }
+        line
+    }
+
+    /// The content of the file the cluster configuration is saved to, the nodes met through a
+    /// handshake that didn't complete are left out.
+    pub fn to_config(&self) -> String {
+        let mut config: String = self
+            .nodes
+            .values()
+            .filter(|node| !node.handshake)
+            .map(|node| self.describe_node(node) + "\n")
+            .collect();
+        config.push_str(&format!(
+            "vars currentEpoch {} lastVoteEpoch {}\n",
+            self.current_epoch, self.last_vote_epoch
+        ));
+        config
+    }
+
+    /// Loads a configuration saved by to_config.
+    pub fn from_config(config: &str) -> Result<Self, String> {
+        let mut myself = None;
+        let mut nodes = BTreeMap::new();
+        let mut owners = vec![None; SLOTS as usize];
+        let mut migrating = BTreeMap::new();
+        let mut importing = BTreeMap::new();
+        let (mut current_epoch, mut last_vote_epoch) = (0, 0);
+
+        for line in config.lines().filter(|line| !line.trim().is_empty()) {
+            let corrupted =
+                || format!("Unrecoverable error: corrupted cluster config file \"{line}\".");
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields[0] == "vars" {
+                for var in fields[1..].chunks(2) {
+                    let [name, value] = var else {
+                        return Err(corrupted());
+                    };
+                    let value = value.parse().map_err(|_| corrupted())?;
+                    match *name {
+                        "currentEpoch" => current_epoch = value,
+                        "lastVoteEpoch" => last_vote_epoch = value,
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            let [
+                id,
+                address,
+                flags,
+                master,
+                ping_sent,
+                pong_received,
+                epoch,
+                _link,
+                slots @ ..,
+            ] = fields.as_slice()
+            else {
+                return Err(corrupted());
+            };
+
+            let (ip, ports) = address.rsplit_once(':').ok_or_else(corrupted)?;
+            let (port, cport) = ports.split_once('@').ok_or_else(corrupted)?;
+            let mut node = Node::new(
+                id.to_string(),
+                ip.to_string(),
+                port.parse().map_err(|_| corrupted())?,
+                cport.parse().map_err(|_| corrupted())?,
+            );
+            node.ping_sent = ping_sent.parse().map_err(|_| corrupted())?;
+            node.pong_received = pong_received.parse().map_err(|_| corrupted())?;
+            node.config_epoch = epoch.parse().map_err(|_| corrupted())?;
+            for flag in flags.split(',') {
+                match flag {
+                    "myself" => myself = Some(node.id.clone()),
+                    "slave" => node.master = Some(master.to_string()),
+                    _ => {}
+                }
+            }
+
+            for slot in slots {
+                let parse_slot = |slot: &str| {
+                    slot.parse::<u16>()
+                        .ok()
+                        .filter(|slot| *slot < SLOTS)
+                        .ok_or_else(corrupted)
+                };
+
+                if let Some(moving) = slot.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                    if let Some((slot, to)) = moving.split_once("->-") {
+                        migrating.insert(parse_slot(slot)?, to.to_string());
+                    } else if let Some((slot, from)) = moving.split_once("-<-") {
+                        importing.insert(parse_slot(slot)?, from.to_string());
+                    } else {
+                        return Err(corrupted());
+                    }
+                    continue;
+                }
+
+                let (start, end) = slot.split_once('-').unwrap_or((slot, slot));
+                for slot in parse_slot(start)?..=parse_slot(end)? {
+                    owners[slot as usize] = Some(node.id.clone());
+                }
+            }
+            nodes.insert(node.id.clone(), node);
+        }
+
+        let Some(myself) = myself else {
+            return Err("Unrecoverable error: the cluster config file has no myself node.".into());
+        };
+        Ok(Self {
+            myself,
+            nodes,
+            owners,
+            migrating,
+            importing,
+            current_epoch,
+            last_vote_epoch,
+        })
+    }
+}
+
+/// The current unix time in milliseconds, as the times of the pings are kept.
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{Cluster, Node, SLOTS, crc16, key_hash_slot};
+
+    #[test]
+    fn test_key_hash_slot() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"user1000")
+        );
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"{user1000}.followers")
+        );
+        //empty hashtags are ignored and the whole key is hashed
+        assert_eq!(key_hash_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") % 16384);
+        assert_eq!(key_hash_slot(b"foo{{bar}}zap"), key_hash_slot(b"{bar"));
+    }
+
+    #[test]
+    fn test_cluster_config() {
+        let mut cluster = Cluster::new(Node::new("a".repeat(40), "127.0.0.1".into(), 7000, 17000));
+        cluster.add_node(Node::new("b".repeat(40), "127.0.0.1".into(), 7001, 17001));
+        for slot in (0..=100).chain([200]) {
+            cluster.assign(slot, &"a".repeat(40));
+        }
+        cluster.set_migrating(200, Some("b".repeat(40)));
+        assert_eq!(
+            cluster.slot_ranges(&"a".repeat(40)),
+            vec![(0, 100), (200, 200)]
+        );
+
+        //the other node claims the unassigned slots, not the ones of this node
+        assert!(cluster.update_slots(&"b".repeat(40), &[50, 300, 301]));
+        assert_eq!(cluster.slots(&"b".repeat(40)), vec![300, 301]);
+        assert!(cluster.update_slots(&"b".repeat(40), &[301]));
+        assert_eq!(cluster.owner(300).map(|node| node.port), None);
+        assert!(!cluster.update_slots(&"b".repeat(40), &[301]));
+
+        let config = cluster.to_config();
+        assert_eq!(
+            config.lines().next(),
+            Some(format!(
+                "{} 127.0.0.1:7000@17000 myself,master - 0 0 0 connected 0-100 200 [200->-{}]",
+                "a".repeat(40),
+                "b".repeat(40)
+            ))
+            .as_deref()
+        );
+        let loaded = Cluster::from_config(&config).unwrap();
+        assert_eq!(loaded.to_config(), config);
+        assert_eq!(loaded.myself().port, 7000);
+        assert_eq!(loaded.migrating(200), Some("b".repeat(40).as_str()));
+        assert_eq!(loaded.size(), 2);
+        assert_eq!(loaded.slots_assigned(), 103);
+
+        assert!(Cluster::from_config("garbage\n").is_err());
+    }
+
+    #[test]
+    fn test_failover() {
+        let (a, b, c) = ("a".repeat(40), "b".repeat(40), "c".repeat(40));
+        let mut cluster = Cluster::new(Node::new(a.clone(), "127.0.0.1".into(), 7000, 17000));
+        cluster.add_node(Node::new(b.clone(), "127.0.0.1".into(), 7001, 17001));
+        cluster.add_node(Node::new(c.clone(), "127.0.0.1".into(), 7002, 17002));
+        for slot in 0..SLOTS {
+            cluster.assign(slot, if slot < 8192 { &a } else { &b });
+        }
+        assert!(cluster.set_master(&c, Some(b.clone())));
+        assert_eq!(cluster.replicas(&b).count(), 1);
+        assert_eq!(cluster.quorum(), 2);
+        assert!(cluster.is_ok(true));
+
+        //the master fails
+        cluster.node_mut(&b).unwrap().pfail = true;
+        assert_eq!(cluster.slots_failing(false), 8192);
+        assert!(!cluster.is_ok(true));
+        let node = cluster.node_mut(&b).unwrap();
+        node.pfail = false;
+        node.fail_time = Some(Instant::now());
+        assert_eq!(cluster.slots_failing(true), 8192);
+        //it answers again, but can't be cleared until its replica had the time to replace it
+        assert!(!cluster.pong_received(&b, Duration::from_secs(30)));
+        assert!(cluster.node(&b).unwrap().failed());
+
+        //the replica replaced it with a greater epoch, the other master is out of date
+        cluster.node_mut(&a).unwrap().config_epoch = 1;
+        cluster.node_mut(&b).unwrap().config_epoch = 2;
+        assert!(cluster.set_master(&c, None));
+        cluster.node_mut(&c).unwrap().config_epoch = 3;
+        let claimed: Vec<u16> = (8192..SLOTS).collect();
+        assert!(cluster.update_slots(&c, &claimed));
+        assert_eq!(cluster.slot_ranges(&c), vec![(8192, 16383)]);
+        assert!(cluster.slots(&b).is_empty());
+        assert!(cluster.is_ok(true));
+        //the old master claiming them back is ignored, and cleared as it no longer serves any
+        assert!(!cluster.update_slots(&b, &claimed));
+        assert!(cluster.pong_received(&b, Duration::from_secs(30)));
+        assert!(cluster.set_master(&b, Some(c.clone())));
+
+        let config = cluster.to_config();
+        assert!(config.ends_with("vars currentEpoch 0 lastVoteEpoch 0\n"));
+        let loaded = Cluster::from_config(&config).unwrap();
+        assert_eq!(loaded.node(&b).unwrap().master.as_deref(), Some(c.as_str()));
+        assert_eq!(loaded.node(&c).unwrap().config_epoch, 3);
+    }
+}
@@ -0,0 +1,335 @@
+use std::io;
+
+use crate::cluster::SLOTS;
+
+/// Every message of the cluster bus starts with it.
+pub const SIGNATURE: &[u8; 4] = b"RCmb";
+const VERSION: u16 = 2;
+
+//node ids are 40 hex characters
+const ID_LEN: usize = 40;
+const BITMAP_LEN: usize = SLOTS as usize / 8;
+//long enough for any textual IPv6 address
+const IP_LEN: usize = 46;
+//signature, total length, version, type, sender, epochs, replication offset, master, ports,
+//slots
+const HEADER_LEN: usize = 4 + 4 + 2 + 2 + ID_LEN + 8 + 8 + 8 + ID_LEN + 2 + 2 + BITMAP_LEN;
+//id, ip, ports, flags
+const GOSSIP_LEN: usize = ID_LEN + IP_LEN + 2 + 2 + 2;
+
+//the flags of a node in a gossip section
+const FLAG_PFAIL: u16 = 1;
+const FLAG_FAIL: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Ping,
+    Pong,
+    //a ping that also asks the receiver to add the sender to the nodes it knows
+    Meet,
+    //the node in the message failed, according to a majority of the masters
+    Fail,
+    //a replica of a failed master asks the masters for their vote to replace it
+    FailoverAuthRequest,
+    FailoverAuthAck,
+}
+
+impl MessageType {
+    fn code(self) -> u16 {
+        match self {
+            MessageType::Ping => 0,
+            MessageType::Pong => 1,
+            MessageType::Meet => 2,
+            MessageType::Fail => 3,
+            MessageType::FailoverAuthRequest => 5,
+            MessageType::FailoverAuthAck => 6,
+        }
+    }
+
+    fn from_code(code: u16) -> Option<Self> {
+        match code {
+            0 => Some(MessageType::Ping),
+            1 => Some(MessageType::Pong),
+            2 => Some(MessageType::Meet),
+            3 => Some(MessageType::Fail),
+            5 => Some(MessageType::FailoverAuthRequest),
+            6 => Some(MessageType::FailoverAuthAck),
+            _ => None,
+        }
+    }
+
+    //the types that carry a gossip section
+    fn has_gossip(self) -> bool {
+        matches!(
+            self,
+            MessageType::Ping | MessageType::Pong | MessageType::Meet
+        )
+    }
+}
+
+/// What the sender of a ping knows about another node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gossip {
+    pub id: String,
+    pub ip: String,
+    pub port: u16,
+    pub cport: u16,
+    pub pfail: bool,
+    pub fail: bool,
+}
+
+/// A message exchanged by two nodes over the cluster bus. It tells the receiver who the sender
+/// is, its view of the epochs and which slots it serves, or its master serves for a replica.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub kind: MessageType,
+    pub sender: String,
+    pub current_epoch: u64,
+    pub config_epoch: u64,
+    //the replication offset of the sender, replicas of a failed master compare them
+    pub offset: u64,
+    //the master of the sender, when it is a replica
+    pub master: Option<String>,
+    //the client and cluster bus ports of the sender, its address is the one it connects from
+    pub port: u16,
+    pub cport: u16,
+    pub slots: Vec<u16>,
+    //for pings, pongs and meets
+    pub gossip: Vec<Gossip>,
+    //for fail messages, the node that failed
+    pub failing: Option<String>,
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bitmap = [0u8; BITMAP_LEN];
+        for slot in &self.slots {
+            bitmap[*slot as usize / 8] |= 1 << (slot % 8);
+        }
+
+        let capacity = HEADER_LEN + 2 + self.gossip.len() * GOSSIP_LEN + ID_LEN;
+        let mut out = Vec::with_capacity(capacity);
+        out.extend_from_slice(SIGNATURE);
+        //the total length is only known at the end
+        out.extend_from_slice(&0u32.to_be_bytes());
+        out.extend_from_slice(&VERSION.to_be_bytes());
+        out.extend_from_slice(&self.kind.code().to_be_bytes());
+        put_fixed(&mut out, &self.sender, ID_LEN);
+        out.extend_from_slice(&self.current_epoch.to_be_bytes());
+        out.extend_from_slice(&self.config_epoch.to_be_bytes());
+        out.extend_from_slice(&self.offset.to_be_bytes());
+        put_fixed(&mut out, self.master.as_deref().unwrap_or_default(), ID_LEN);
+        out.extend_from_slice(&self.port.to_be_bytes());
+        out.extend_from_slice(&self.cport.to_be_bytes());
+        out.extend_from_slice(&bitmap);
+
+        if self.kind.has_gossip() {
+            out.extend_from_slice(&(self.gossip.len() as u16).to_be_bytes());
+            for gossip in &self.gossip {
+                put_fixed(&mut out, &gossip.id, ID_LEN);
+                put_fixed(&mut out, &gossip.ip, IP_LEN);
+                out.extend_from_slice(&gossip.port.to_be_bytes());
+                out.extend_from_slice(&gossip.cport.to_be_bytes());
+                let flags = match (gossip.pfail, gossip.fail) {
+                    (_, true) => FLAG_FAIL,
+                    (true, false) => FLAG_PFAIL,
+                    (false, false) => 0,
+                };
+                out.extend_from_slice(&flags.to_be_bytes());
+            }
+        }
+        if self.kind == MessageType::Fail {
+            put_fixed(
+                &mut out,
+                self.failing.as_deref().unwrap_or_default(),
+                ID_LEN,
+            );
+        }
+
+        let len = out.len() as u32;
+        out[4..8].copy_from_slice(&len.to_be_bytes());
+        out
+    }
+
+    /// Decodes the message data starts with, along with its length. None while the message is
+    /// not complete.
+    pub fn decode(data: &[u8]) -> io::Result<Option<(Message, usize)>> {
+        if data.len() < 8 {
+            return Ok(None);
+        }
+        if &data[..4] != SIGNATURE {
+            return Err(io::Error::other("Bad cluster bus message signature"));
+        }
+        let len = u32::from_be_bytes(data[4..8].try_into().expect("4 bytes")) as usize;
+        if len < HEADER_LEN {
+            return Err(io::Error::other(format!(
+                "Cluster bus message too short: {len} bytes"
+            )));
+        }
+        if data.len() < len {
+            return Ok(None);
+        }
+
+        let mut reader = Reader {
+            data: &data[..len],
+            at: 8,
+        };
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(io::Error::other(format!(
+                "Unsupported cluster bus version {version}"
+            )));
+        }
+        let code = reader.u16()?;
+        let kind = MessageType::from_code(code)
+            .ok_or_else(|| io::Error::other(format!("Unknown cluster bus message type {code}")))?;
+        let sender = reader.string(ID_LEN)?;
+        let current_epoch = reader.u64()?;
+        let config_epoch = reader.u64()?;
+        let offset = reader.u64()?;
+        let master = Some(reader.string(ID_LEN)?).filter(|master| !master.is_empty());
+        let port = reader.u16()?;
+        let cport = reader.u16()?;
+        let bitmap = reader.take(BITMAP_LEN)?;
+        let slots = (0..SLOTS)
+            .filter(|slot| bitmap[*slot as usize / 8] & (1 << (slot % 8)) != 0)
+            .collect();
+
+        let mut gossip = vec![];
+        if kind.has_gossip() {
+            for _ in 0..reader.u16()? {
+                let id = reader.string(ID_LEN)?;
+                let ip = reader.string(IP_LEN)?;
+                let port = reader.u16()?;
+                let cport = reader.u16()?;
+                let flags = reader.u16()?;
+                gossip.push(Gossip {
+                    id,
+                    ip,
+                    port,
+                    cport,
+                    pfail: flags & FLAG_PFAIL != 0,
+                    fail: flags & FLAG_FAIL != 0,
+                });
+            }
+        }
+        let failing = match kind {
+            MessageType::Fail => Some(reader.string(ID_LEN)?),
+            _ => None,
+        };
+
+        let message = Message {
+            kind,
+            sender,
+            current_epoch,
+            config_epoch,
+            offset,
+            master,
+            port,
+            cport,
+            slots,
+            gossip,
+            failing,
+        };
+        Ok(Some((message, len)))
+    }
+}
+
+//a string in a field of fixed length, padded with zeros
+fn put_fixed(out: &mut Vec<u8>, value: &str, len: usize) {
+    let mut field = vec![0u8; len];
+    let used = value.len().min(len);
+    field[..used].copy_from_slice(&value.as_bytes()[..used]);
+    out.extend_from_slice(&field);
+}
+
+//reads the fields of a message, which may be shorter than its type requires
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let field = self
+            .data
+            .get(self.at..self.at + len)
+            .ok_or_else(|| io::Error::other("Truncated cluster bus message"))?;
+        self.at += len;
+        Ok(field)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(
+            self.take(2)?.try_into().expect("2 bytes"),
+        ))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(
+            self.take(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+
+    fn string(&mut self, len: usize) -> io::Result<String> {
+        let field = self.take(len)?;
+        let used = field.iter().position(|c| *c == 0).unwrap_or(len);
+        String::from_utf8(field[..used].to_vec())
+            .map_err(|_| io::Error::other("Invalid string in cluster bus message"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Gossip, Message, MessageType};
+
+    #[test]
+    fn test_message_round_trip() {
+        let message = Message {
+            kind: MessageType::Meet,
+            sender: "a".repeat(40),
+            current_epoch: 7,
+            config_epoch: 3,
+            offset: 1234,
+            master: None,
+            port: 7000,
+            cport: 17000,
+            slots: vec![0, 7, 8, 16383],
+            gossip: vec![Gossip {
+                id: "b".repeat(40),
+                ip: "127.0.0.1".into(),
+                port: 7001,
+                cport: 17001,
+                pfail: true,
+                fail: false,
+            }],
+            failing: None,
+        };
+        let encoded = message.encode();
+
+        assert_eq!(Message::decode(&encoded[..100]).unwrap(), None);
+        let mut data = encoded.clone();
+        data.extend_from_slice(b"RCmb");
+        assert_eq!(
+            Message::decode(&data).unwrap(),
+            Some((message, encoded.len()))
+        );
+
+        let fail = Message {
+            kind: MessageType::Fail,
+            master: Some("c".repeat(40)),
+            slots: vec![],
+            gossip: vec![],
+            failing: Some("b".repeat(40)),
+            ..Message::decode(&encoded).unwrap().unwrap().0
+        };
+        let encoded = fail.encode();
+        assert_eq!(
+            Message::decode(&encoded).unwrap(),
+            Some((fail, encoded.len()))
+        );
+
+        data[0] = b'X';
+        assert!(Message::decode(&data).is_err());
+    }
+}
@@ -1,11 +1,48 @@
 use core::time;
 use std::{io, time::Duration};
 
-use crate::resp::{self, RespType};
+use crate::{
+    redis::stream::StreamId,
+    resp::{self, Protocol, RespType},
+};
+
+mod acl;
+mod cluster;
+mod config;
+mod keyspace;
+mod pubsub;
+mod scripting;
+mod sentinel;
+mod stream;
+
+use acl::{parse_acl_cmd, parse_auth_cmd};
+use cluster::parse_cluster_cmd;
+use config::parse_config_cmd;
+use keyspace::{parse_del_cmd, parse_dump_cmd, parse_migrate_cmd, parse_restore_cmd};
+
+use pubsub::{
+    parse_psubscribe_cmd, parse_publish_cmd, parse_pubsub_cmd, parse_punsubscribe_cmd,
+    parse_spublish_cmd, parse_ssubscribe_cmd, parse_subscribe_cmd, parse_sunsubscribe_cmd,
+    parse_unsubscribe_cmd,
+};
+
+use scripting::{
+    parse_eval_cmd, parse_evalsha_cmd, parse_fcall_cmd, parse_function_cmd, parse_script_cmd,
+};
+
+use sentinel::parse_sentinel_cmd;
+
+use stream::{
+    parse_stream_trim, parse_xack_cmd, parse_xautoclaim_cmd, parse_xclaim_cmd, parse_xdel_cmd,
+    parse_xgroup_cmd, parse_xinfo_cmd, parse_xlen_cmd, parse_xpending_cmd, parse_xrange_cmd,
+    parse_xread_cmd, parse_xreadgroup_cmd, parse_xrevrange_cmd, parse_xsetid_cmd, parse_xtrim_cmd,
+};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Command {
-    Ping,
+    Ping {
+        message: Option<String>,
+    },
     Echo {
         to_echo: String,
     },
@@ -46,8 +83,244 @@ pub enum Command {
     },
     XAdd {
         key: String,
-        id: String,
+        id: XAddId,
         elements: Vec<(String, String)>,
+        options: XAddOptions,
+    },
+    XTrim {
+        key: String,
+        trim: StreamTrim,
+    },
+    XDel {
+        key: String,
+        ids: Vec<StreamId>,
+    },
+    XSetId {
+        key: String,
+        last_id: StreamId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamId>,
+    },
+    XInfo {
+        subcommand: XInfoSubcommand,
+    },
+    XRange {
+        key: String,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    },
+    XRevRange {
+        key: String,
+        end: StreamId,
+        start: StreamId,
+        count: Option<usize>,
+    },
+    XLen {
+        key: String,
+    },
+    XRead {
+        count: Option<usize>,
+        block: Option<time::Duration>,
+        keys: Vec<String>,
+        ids: Vec<XReadId>,
+    },
+    XGroup {
+        subcommand: XGroupSubcommand,
+    },
+    XReadGroup {
+        group: String,
+        consumer: String,
+        count: Option<usize>,
+        block: Option<time::Duration>,
+        noack: bool,
+        keys: Vec<String>,
+        ids: Vec<XReadGroupId>,
+    },
+    XAck {
+        key: String,
+        group: String,
+        ids: Vec<StreamId>,
+    },
+    XPending {
+        key: String,
+        group: String,
+        range: Option<XPendingRange>,
+    },
+    XClaim {
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+        options: XClaimOptions,
+    },
+    XAutoClaim {
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: u64,
+        start: StreamId,
+        count: usize,
+        justid: bool,
+    },
+    Hello {
+        protover: Option<Protocol>,
+        //the username and password to authenticate with first
+        auth: Option<(String, String)>,
+        setname: Option<String>,
+    },
+    Subscribe {
+        channels: Vec<String>,
+    },
+    Unsubscribe {
+        channels: Vec<String>,
+    },
+    PSubscribe {
+        patterns: Vec<String>,
+    },
+    PUnsubscribe {
+        patterns: Vec<String>,
+    },
+    Publish {
+        channel: String,
+        message: String,
+    },
+    SSubscribe {
+        channels: Vec<String>,
+    },
+    SUnsubscribe {
+        channels: Vec<String>,
+    },
+    SPublish {
+        channel: String,
+        message: String,
+    },
+    PubSub {
+        subcommand: PubSubSubcommand,
+    },
+    Config {
+        subcommand: ConfigSubcommand,
+    },
+    Multi,
+    Exec,
+    Discard,
+    Watch {
+        keys: Vec<String>,
+    },
+    Unwatch,
+    FlushAll,
+    FlushDb,
+    SwapDb {
+        index1: i64,
+        index2: i64,
+    },
+    Del {
+        keys: Vec<String>,
+    },
+    Dump {
+        key: String,
+    },
+    Restore {
+        key: String,
+        //in milliseconds, 0 for no time to live, a unix time with ABSTTL
+        ttl: u64,
+        //as returned by DUMP
+        payload: Vec<u8>,
+        replace: bool,
+        absttl: bool,
+        //RESTORE-ASKING, sent by MIGRATE to a node importing the slot of the key
+        asking: bool,
+    },
+    Migrate {
+        host: String,
+        port: u16,
+        keys: Vec<String>,
+        timeout: time::Duration,
+        //the keys are kept on this server
+        copy: bool,
+        //the keys already on the target are replaced
+        replace: bool,
+        //(username, password) to authenticate with the target, no username for AUTH
+        auth: Option<(Option<String>, String)>,
+    },
+    Info {
+        sections: Vec<String>,
+    },
+    Save,
+    BgSave {
+        //only meaningful while another child process runs, waits for it instead of failing
+        schedule: bool,
+    },
+    LastSave,
+    BgRewriteAof,
+    ReplicaOf {
+        //None for NO ONE, the server stops replicating and becomes a master
+        master: Option<(String, u16)>,
+    },
+    ReplConf {
+        //option names are lowercase
+        options: Vec<(String, String)>,
+    },
+    PSync {
+        replid: String,
+        //-1 along with the replid ? asks for a full resynchronization
+        offset: i64,
+    },
+    Role,
+    Cluster {
+        subcommand: ClusterSubcommand,
+    },
+    //the next command is for a slot being imported by this node
+    Asking,
+    //only in sentinel mode
+    Sentinel {
+        subcommand: SentinelSubcommand,
+    },
+    Wait {
+        numreplicas: u64,
+        //None to wait for as long as it takes
+        timeout: Option<time::Duration>,
+    },
+    WaitAof {
+        //0 or 1, whether the AOF of this server has to be synced
+        numlocal: u64,
+        numreplicas: u64,
+        timeout: Option<time::Duration>,
+    },
+    Eval {
+        script: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+        //EVAL_RO, the script can't run write commands
+        read_only: bool,
+    },
+    EvalSha {
+        sha1: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+        read_only: bool,
+    },
+    Script {
+        subcommand: ScriptSubcommand,
+    },
+    FCall {
+        function: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+        //FCALL_RO, only functions flagged no-writes can be called
+        read_only: bool,
+    },
+    Function {
+        subcommand: FunctionSubcommand,
+    },
+    Auth {
+        //None for the legacy form, authenticating the default user
+        username: Option<String>,
+        password: String,
+    },
+    Acl {
+        subcommand: AclSubcommand,
     },
     ErrorCmd {
         msg: String,
@@ -65,6 +338,337 @@ impl SetOptions {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XAddId {
+    //*
+    Auto,
+    //<ms>-*
+    AutoSeq { ms: u64 },
+    //<ms>-<seq>
+    Explicit { id: StreamId },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XReadId {
+    //$, only entries added after the command was issued
+    NewEntries,
+    //+, the last entry of the stream
+    LastEntry,
+    //entries with an id greater than the given one
+    After { id: StreamId },
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct XAddOptions {
+    pub nomkstream: bool,
+    pub trim: Option<StreamTrim>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TrimStrategy {
+    MaxLen { len: u64 },
+    MinId { id: StreamId },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct StreamTrim {
+    pub strategy: TrimStrategy,
+    //~, only whole nodes are evicted
+    pub approx: bool,
+    //maximum number of evicted entries, only allowed when approx
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum XInfoSubcommand {
+    Stream {
+        key: String,
+        //Some(count) when FULL is given, count 0 means all the entries
+        full: Option<usize>,
+    },
+    Groups {
+        key: String,
+    },
+    Consumers {
+        key: String,
+        group: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum XGroupSubcommand {
+    Create {
+        key: String,
+        group: String,
+        id: XGroupId,
+        mkstream: bool,
+        entries_read: Option<u64>,
+    },
+    SetId {
+        key: String,
+        group: String,
+        id: XGroupId,
+        entries_read: Option<u64>,
+    },
+    Destroy {
+        key: String,
+        group: String,
+    },
+    CreateConsumer {
+        key: String,
+        group: String,
+        consumer: String,
+    },
+    DelConsumer {
+        key: String,
+        group: String,
+        consumer: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XGroupId {
+    //$, the last id of the stream
+    LastEntry,
+    Id { id: StreamId },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XReadGroupId {
+    //>, entries never delivered to any consumer of the group
+    Undelivered,
+    //the consumer pending entries with an id greater than the given one
+    Pending { after: StreamId },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct XPendingRange {
+    pub min_idle: Option<u64>,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: usize,
+    pub consumer: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct XClaimOptions {
+    //idle time (ms) to set on the claimed entries
+    pub idle: Option<u64>,
+    //unix time (ms) to use as delivery time of the claimed entries
+    pub time: Option<u64>,
+    pub retry_count: Option<u64>,
+    pub force: bool,
+    pub justid: bool,
+    pub last_id: Option<StreamId>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ConfigSubcommand {
+    Get { parameters: Vec<String> },
+    Set { parameters: Vec<(String, String)> },
+    ResetStat,
+    Rewrite,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ClusterSubcommand {
+    Info,
+    MyId,
+    Nodes,
+    Slots,
+    Shards,
+    KeySlot {
+        key: String,
+    },
+    CountKeysInSlot {
+        slot: u16,
+    },
+    GetKeysInSlot {
+        slot: u16,
+        count: usize,
+    },
+    AddSlots {
+        slots: Vec<u16>,
+    },
+    //bounds included
+    AddSlotsRange {
+        ranges: Vec<(u16, u16)>,
+    },
+    DelSlots {
+        slots: Vec<u16>,
+    },
+    SetSlot {
+        slot: u16,
+        action: SetSlotAction,
+    },
+    //the cluster bus port defaults to the one of the clients + 10000
+    Meet {
+        ip: String,
+        port: u16,
+        cport: Option<u16>,
+    },
+    //this node becomes a replica of the node
+    Replicate {
+        node: String,
+    },
+    Replicas {
+        node: String,
+    },
+    CountFailureReports {
+        node: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SetSlotAction {
+    Importing { node: String },
+    Migrating { node: String },
+    //cancels importing and migrating
+    Stable,
+    Node { node: String },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SentinelSubcommand {
+    Masters,
+    Master {
+        name: String,
+    },
+    Replicas {
+        name: String,
+    },
+    Sentinels {
+        name: String,
+    },
+    GetMasterAddrByName {
+        name: String,
+    },
+    //asked by the other sentinels, along with their vote when the run id is not *
+    IsMasterDownByAddr {
+        ip: String,
+        port: u16,
+        current_epoch: u64,
+        run_id: String,
+    },
+    //without the agreement of the other sentinels
+    Failover {
+        name: String,
+    },
+    //checked when the master is added, like the directive of the same name
+    Monitor {
+        name: String,
+        ip: String,
+        port: String,
+        quorum: String,
+    },
+    Remove {
+        name: String,
+    },
+    Set {
+        name: String,
+        options: Vec<(String, String)>,
+    },
+    MyId,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ScriptSubcommand {
+    Load { script: String },
+    Exists { sha1s: Vec<String> },
+    Flush,
+    Kill,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FunctionSubcommand {
+    Load {
+        code: String,
+        replace: bool,
+    },
+    Delete {
+        library: String,
+    },
+    List {
+        pattern: Option<String>,
+        with_code: bool,
+    },
+    Dump,
+    Restore {
+        payload: Vec<u8>,
+        policy: RestorePolicy,
+    },
+    Flush,
+    Kill,
+}
+
+//what FUNCTION RESTORE does with the libraries already loaded
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RestorePolicy {
+    Append,
+    Replace,
+    Flush,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AclSubcommand {
+    SetUser {
+        username: String,
+        rules: Vec<String>,
+    },
+    GetUser {
+        username: String,
+    },
+    DelUser {
+        usernames: Vec<String>,
+    },
+    List,
+    Users,
+    WhoAmI,
+    Cat {
+        category: Option<String>,
+    },
+    DryRun {
+        username: String,
+        cmd: Box<Command>,
+    },
+    Log {
+        count: Option<usize>,
+    },
+    LogReset,
+    Load,
+    Save,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PubSubSubcommand {
+    Channels { pattern: Option<String> },
+    NumSub { channels: Vec<String> },
+    NumPat,
+    ShardChannels { pattern: Option<String> },
+    ShardNumSub { channels: Vec<String> },
+}
+
+impl std::str::FromStr for XAddId {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "*" => Ok(XAddId::Auto),
+            _ => match s.strip_suffix("-*") {
+                Some(ms) => ms
+                    .parse::<u64>()
+                    .map(|ms| XAddId::AutoSeq { ms })
+                    .map_err(|_| {
+                        io::Error::other(
+                            "ERR Invalid stream ID specified as stream command argument",
+                        )
+                    }),
+                None => s.parse::<StreamId>().map(|id| XAddId::Explicit { id }),
+            },
+        }
+    }
+}
+
 impl From<resp::RespType> for Command {
     fn from(value: resp::RespType) -> Self {
         match Self::try_from(value) {
@@ -91,7 +695,7 @@ impl Command {
 
                         match cmd.as_str() {
                             "ECHO" => parse_echo_cmd(&elements),
-                            "PING" => Ok(Self::Ping),
+                            "PING" => parse_ping_cmd(&elements),
                             "SET" => parse_set_cmd(&elements),
                             "GET" => parse_get_cmd(&elements),
                             "RPUSH" => parse_rpush_cmd(&elements),
@@ -102,7 +706,73 @@ impl Command {
                             "BLPOP" => parse_blpop_cmd(&elements),
                             "TYPE" => parse_type_cmd(&elements),
                             "XADD" => parse_xadd_cmd(&elements),
-                            _ => Err(io::Error::other("NYI")),
+                            "XTRIM" => parse_xtrim_cmd(&elements),
+                            "XDEL" => parse_xdel_cmd(&elements),
+                            "XSETID" => parse_xsetid_cmd(&elements),
+                            "XINFO" => parse_xinfo_cmd(&elements),
+                            "XRANGE" => parse_xrange_cmd(&elements),
+                            "XREVRANGE" => parse_xrevrange_cmd(&elements),
+                            "XLEN" => parse_xlen_cmd(&elements),
+                            "XREAD" => parse_xread_cmd(&elements),
+                            "XGROUP" => parse_xgroup_cmd(&elements),
+                            "XREADGROUP" => parse_xreadgroup_cmd(&elements),
+                            "XACK" => parse_xack_cmd(&elements),
+                            "XPENDING" => parse_xpending_cmd(&elements),
+                            "XCLAIM" => parse_xclaim_cmd(&elements),
+                            "XAUTOCLAIM" => parse_xautoclaim_cmd(&elements),
+                            "HELLO" => parse_hello_cmd(&elements),
+                            "SUBSCRIBE" => parse_subscribe_cmd(&elements),
+                            "UNSUBSCRIBE" => parse_unsubscribe_cmd(&elements),
+                            "PSUBSCRIBE" => parse_psubscribe_cmd(&elements),
+                            "PUNSUBSCRIBE" => parse_punsubscribe_cmd(&elements),
+                            "PUBLISH" => parse_publish_cmd(&elements),
+                            "PUBSUB" => parse_pubsub_cmd(&elements),
+                            "SSUBSCRIBE" => parse_ssubscribe_cmd(&elements),
+                            "SUNSUBSCRIBE" => parse_sunsubscribe_cmd(&elements),
+                            "SPUBLISH" => parse_spublish_cmd(&elements),
+                            "CONFIG" => parse_config_cmd(&elements),
+                            "MULTI" => parse_no_args_cmd(&elements, "MULTI", Command::Multi),
+                            "EXEC" => parse_no_args_cmd(&elements, "EXEC", Command::Exec),
+                            "DISCARD" => parse_no_args_cmd(&elements, "DISCARD", Command::Discard),
+                            "WATCH" => parse_watch_cmd(&elements),
+                            "UNWATCH" => parse_no_args_cmd(&elements, "UNWATCH", Command::Unwatch),
+                            "FLUSHALL" => parse_flush_cmd(&elements, "FLUSHALL", Command::FlushAll),
+                            "FLUSHDB" => parse_flush_cmd(&elements, "FLUSHDB", Command::FlushDb),
+                            "SWAPDB" => parse_swapdb_cmd(&elements),
+                            "DEL" => parse_del_cmd(&elements),
+                            "DUMP" => parse_dump_cmd(&elements),
+                            "RESTORE" => parse_restore_cmd(&elements, false),
+                            "RESTORE-ASKING" => parse_restore_cmd(&elements, true),
+                            "MIGRATE" => parse_migrate_cmd(&elements),
+                            "INFO" => parse_info_cmd(&elements),
+                            "SAVE" => parse_no_args_cmd(&elements, "SAVE", Command::Save),
+                            "BGSAVE" => parse_bgsave_cmd(&elements),
+                            "LASTSAVE" => {
+                                parse_no_args_cmd(&elements, "LASTSAVE", Command::LastSave)
+                            }
+                            "BGREWRITEAOF" => {
+                                parse_no_args_cmd(&elements, "BGREWRITEAOF", Command::BgRewriteAof)
+                            }
+                            "REPLICAOF" | "SLAVEOF" => parse_replicaof_cmd(&elements),
+                            "REPLCONF" => parse_replconf_cmd(&elements),
+                            "PSYNC" => parse_psync_cmd(&elements),
+                            "ROLE" => parse_no_args_cmd(&elements, "ROLE", Command::Role),
+                            "CLUSTER" => parse_cluster_cmd(&elements),
+                            "ASKING" => parse_no_args_cmd(&elements, "ASKING", Command::Asking),
+                            "SENTINEL" => parse_sentinel_cmd(&elements),
+                            "WAIT" => parse_wait_cmd(&elements),
+                            "WAITAOF" => parse_waitaof_cmd(&elements),
+                            "EVAL" => parse_eval_cmd(&elements, false),
+                            "EVAL_RO" => parse_eval_cmd(&elements, true),
+                            "EVALSHA" => parse_evalsha_cmd(&elements, false),
+                            "EVALSHA_RO" => parse_evalsha_cmd(&elements, true),
+                            "SCRIPT" => parse_script_cmd(&elements),
+                            "FCALL" => parse_fcall_cmd(&elements, false),
+                            "FCALL_RO" => parse_fcall_cmd(&elements, true),
+                            "FUNCTION" => parse_function_cmd(&elements),
+                            "AUTH" => parse_auth_cmd(&elements),
+                            "ACL" => parse_acl_cmd(&elements),
+                            _ => Err(unknown_command(&elements)),
                         }
                     }
                     _ => todo!(),
@@ -111,8 +781,561 @@ impl Command {
             _ => Err(io::Error::other("Redis Commands should be RESP arrays")),
         }
     }
+
+    /// True for the commands that may modify the dataset, the ones read-only scripts can't run.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set { .. }
+                | Command::RPush { .. }
+                | Command::LPush { .. }
+                | Command::LPop { .. }
+                | Command::BlPop { .. }
+                | Command::XAdd { .. }
+                | Command::XTrim { .. }
+                | Command::XDel { .. }
+                | Command::XSetId { .. }
+                | Command::XGroup { .. }
+                | Command::XReadGroup { .. }
+                | Command::XAck { .. }
+                | Command::XClaim { .. }
+                | Command::XAutoClaim { .. }
+                | Command::FlushAll
+                | Command::FlushDb
+                | Command::SwapDb { .. }
+                | Command::Del { .. }
+                | Command::Restore { .. }
+                | Command::Migrate { .. }
+        )
+    }
+
+    /// True for the commands that can grow the dataset, the ones refused once the memory limit
+    /// is reached. Removing data is always allowed.
+    pub fn grows_dataset(&self) -> bool {
+        matches!(
+            self,
+            Command::Set { .. }
+                | Command::RPush { .. }
+                | Command::LPush { .. }
+                | Command::XAdd { .. }
+                | Command::XSetId { .. }
+                | Command::XGroup { .. }
+                | Command::Restore { .. }
+        )
+    }
+
+    /// Name of the command as ACL rules refer to it, with the subcommand after a pipe for
+    /// container commands (config|get). None for requests that could not be parsed.
+    pub fn full_name(&self) -> Option<String> {
+        let (name, subcommand) = match self {
+            Command::Ping { .. } => ("ping", None),
+            Command::Echo { .. } => ("echo", None),
+            Command::Set { .. } => ("set", None),
+            Command::Get { .. } => ("get", None),
+            Command::RPush { .. } => ("rpush", None),
+            Command::LPush { .. } => ("lpush", None),
+            Command::LRange { .. } => ("lrange", None),
+            Command::LLen { .. } => ("llen", None),
+            Command::LPop { .. } => ("lpop", None),
+            Command::BlPop { .. } => ("blpop", None),
+            Command::Type { .. } => ("type", None),
+            Command::XAdd { .. } => ("xadd", None),
+            Command::XTrim { .. } => ("xtrim", None),
+            Command::XDel { .. } => ("xdel", None),
+            Command::XSetId { .. } => ("xsetid", None),
+            Command::XInfo { subcommand } => (
+                "xinfo",
+                Some(match subcommand {
+                    XInfoSubcommand::Stream { .. } => "stream",
+                    XInfoSubcommand::Groups { .. } => "groups",
+                    XInfoSubcommand::Consumers { .. } => "consumers",
+                }),
+            ),
+            Command::XRange { .. } => ("xrange", None),
+            Command::XRevRange { .. } => ("xrevrange", None),
+            Command::XLen { .. } => ("xlen", None),
+            Command::XRead { .. } => ("xread", None),
+            Command::XGroup { subcommand } => (
+                "xgroup",
+                Some(match subcommand {
+                    XGroupSubcommand::Create { .. } => "create",
+                    XGroupSubcommand::SetId { .. } => "setid",
+                    XGroupSubcommand::Destroy { .. } => "destroy",
+                    XGroupSubcommand::CreateConsumer { .. } => "createconsumer",
+                    XGroupSubcommand::DelConsumer { .. } => "delconsumer",
+                }),
+            ),
+            Command::XReadGroup { .. } => ("xreadgroup", None),
+            Command::XAck { .. } => ("xack", None),
+            Command::XPending { .. } => ("xpending", None),
+            Command::XClaim { .. } => ("xclaim", None),
+            Command::XAutoClaim { .. } => ("xautoclaim", None),
+            Command::Hello { .. } => ("hello", None),
+            Command::Subscribe { .. } => ("subscribe", None),
+            Command::Unsubscribe { .. } => ("unsubscribe", None),
+            Command::PSubscribe { .. } => ("psubscribe", None),
+            Command::PUnsubscribe { .. } => ("punsubscribe", None),
+            Command::Publish { .. } => ("publish", None),
+            Command::SSubscribe { .. } => ("ssubscribe", None),
+            Command::SUnsubscribe { .. } => ("sunsubscribe", None),
+            Command::SPublish { .. } => ("spublish", None),
+            Command::PubSub { subcommand } => (
+                "pubsub",
+                Some(match subcommand {
+                    PubSubSubcommand::Channels { .. } => "channels",
+                    PubSubSubcommand::NumSub { .. } => "numsub",
+                    PubSubSubcommand::NumPat => "numpat",
+                    PubSubSubcommand::ShardChannels { .. } => "shardchannels",
+                    PubSubSubcommand::ShardNumSub { .. } => "shardnumsub",
+                }),
+            ),
+            Command::Config { subcommand } => (
+                "config",
+                Some(match subcommand {
+                    ConfigSubcommand::Get { .. } => "get",
+                    ConfigSubcommand::Set { .. } => "set",
+                    ConfigSubcommand::ResetStat => "resetstat",
+                    ConfigSubcommand::Rewrite => "rewrite",
+                }),
+            ),
+            Command::Multi => ("multi", None),
+            Command::Exec => ("exec", None),
+            Command::Discard => ("discard", None),
+            Command::Watch { .. } => ("watch", None),
+            Command::Unwatch => ("unwatch", None),
+            Command::FlushAll => ("flushall", None),
+            Command::FlushDb => ("flushdb", None),
+            Command::SwapDb { .. } => ("swapdb", None),
+            Command::Del { .. } => ("del", None),
+            Command::Dump { .. } => ("dump", None),
+            Command::Restore { asking, .. } => {
+                (if *asking { "restore-asking" } else { "restore" }, None)
+            }
+            Command::Migrate { .. } => ("migrate", None),
+            Command::Info { .. } => ("info", None),
+            Command::Save => ("save", None),
+            Command::BgSave { .. } => ("bgsave", None),
+            Command::LastSave => ("lastsave", None),
+            Command::BgRewriteAof => ("bgrewriteaof", None),
+            Command::ReplicaOf { .. } => ("replicaof", None),
+            Command::ReplConf { .. } => ("replconf", None),
+            Command::PSync { .. } => ("psync", None),
+            Command::Role => ("role", None),
+            Command::Cluster { subcommand } => (
+                "cluster",
+                Some(match subcommand {
+                    ClusterSubcommand::Info => "info",
+                    ClusterSubcommand::MyId => "myid",
+                    ClusterSubcommand::Nodes => "nodes",
+                    ClusterSubcommand::Slots => "slots",
+                    ClusterSubcommand::Shards => "shards",
+                    ClusterSubcommand::KeySlot { .. } => "keyslot",
+                    ClusterSubcommand::CountKeysInSlot { .. } => "countkeysinslot",
+                    ClusterSubcommand::GetKeysInSlot { .. } => "getkeysinslot",
+                    ClusterSubcommand::AddSlots { .. } => "addslots",
+                    ClusterSubcommand::AddSlotsRange { .. } => "addslotsrange",
+                    ClusterSubcommand::DelSlots { .. } => "delslots",
+                    ClusterSubcommand::SetSlot { .. } => "setslot",
+                    ClusterSubcommand::Meet { .. } => "meet",
+                    ClusterSubcommand::Replicate { .. } => "replicate",
+                    ClusterSubcommand::Replicas { .. } => "replicas",
+                    ClusterSubcommand::CountFailureReports { .. } => "count-failure-reports",
+                }),
+            ),
+            Command::Asking => ("asking", None),
+            Command::Sentinel { subcommand } => (
+                "sentinel",
+                Some(match subcommand {
+                    SentinelSubcommand::Masters => "masters",
+                    SentinelSubcommand::Master { .. } => "master",
+                    SentinelSubcommand::Replicas { .. } => "replicas",
+                    SentinelSubcommand::Sentinels { .. } => "sentinels",
+                    SentinelSubcommand::GetMasterAddrByName { .. } => "get-master-addr-by-name",
+                    SentinelSubcommand::IsMasterDownByAddr { .. } => "is-master-down-by-addr",
+                    SentinelSubcommand::Failover { .. } => "failover",
+                    SentinelSubcommand::Monitor { .. } => "monitor",
+                    SentinelSubcommand::Remove { .. } => "remove",
+                    SentinelSubcommand::Set { .. } => "set",
+                    SentinelSubcommand::MyId => "myid",
+                }),
+            ),
+            Command::Wait { .. } => ("wait", None),
+            Command::WaitAof { .. } => ("waitaof", None),
+            Command::Eval { read_only, .. } => (if *read_only { "eval_ro" } else { "eval" }, None),
+            Command::EvalSha { read_only, .. } => {
+                (if *read_only { "evalsha_ro" } else { "evalsha" }, None)
+            }
+            Command::Script { subcommand } => (
+                "script",
+                Some(match subcommand {
+                    ScriptSubcommand::Load { .. } => "load",
+                    ScriptSubcommand::Exists { .. } => "exists",
+                    ScriptSubcommand::Flush => "flush",
+                    ScriptSubcommand::Kill => "kill",
+                }),
+            ),
+            Command::FCall { read_only, .. } => {
+                (if *read_only { "fcall_ro" } else { "fcall" }, None)
+            }
+            Command::Function { subcommand } => (
+                "function",
+                Some(match subcommand {
+                    FunctionSubcommand::Load { .. } => "load",
+                    FunctionSubcommand::Delete { .. } => "delete",
+                    FunctionSubcommand::List { .. } => "list",
+                    FunctionSubcommand::Dump => "dump",
+                    FunctionSubcommand::Restore { .. } => "restore",
+                    FunctionSubcommand::Flush => "flush",
+                    FunctionSubcommand::Kill => "kill",
+                }),
+            ),
+            Command::Auth { .. } => ("auth", None),
+            Command::Acl { subcommand } => (
+                "acl",
+                Some(match subcommand {
+                    AclSubcommand::SetUser { .. } => "setuser",
+                    AclSubcommand::GetUser { .. } => "getuser",
+                    AclSubcommand::DelUser { .. } => "deluser",
+                    AclSubcommand::List => "list",
+                    AclSubcommand::Users => "users",
+                    AclSubcommand::WhoAmI => "whoami",
+                    AclSubcommand::Cat { .. } => "cat",
+                    AclSubcommand::DryRun { .. } => "dryrun",
+                    AclSubcommand::Log { .. } | AclSubcommand::LogReset => "log",
+                    AclSubcommand::Load => "load",
+                    AclSubcommand::Save => "save",
+                }),
+            ),
+            Command::ErrorCmd { .. } => return None,
+        };
+
+        Some(match subcommand {
+            Some(subcommand) => format!("{name}|{subcommand}"),
+            None => name.to_string(),
+        })
+    }
+
+    /// The keys the command reads or writes.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Set { key, .. }
+            | Command::Get { key }
+            | Command::RPush { key, .. }
+            | Command::LPush { key, .. }
+            | Command::LRange { key, .. }
+            | Command::LLen { key }
+            | Command::LPop { key, .. }
+            | Command::Type { key }
+            | Command::XAdd { key, .. }
+            | Command::XTrim { key, .. }
+            | Command::XDel { key, .. }
+            | Command::XSetId { key, .. }
+            | Command::XRange { key, .. }
+            | Command::XRevRange { key, .. }
+            | Command::XLen { key }
+            | Command::XAck { key, .. }
+            | Command::XPending { key, .. }
+            | Command::XClaim { key, .. }
+            | Command::XAutoClaim { key, .. }
+            | Command::Dump { key }
+            | Command::Restore { key, .. } => vec![key],
+            Command::XInfo { subcommand } => match subcommand {
+                XInfoSubcommand::Stream { key, .. }
+                | XInfoSubcommand::Groups { key }
+                | XInfoSubcommand::Consumers { key, .. } => vec![key],
+            },
+            Command::XGroup { subcommand } => match subcommand {
+                XGroupSubcommand::Create { key, .. }
+                | XGroupSubcommand::SetId { key, .. }
+                | XGroupSubcommand::Destroy { key, .. }
+                | XGroupSubcommand::CreateConsumer { key, .. }
+                | XGroupSubcommand::DelConsumer { key, .. } => vec![key],
+            },
+            Command::BlPop { keys, .. }
+            | Command::XRead { keys, .. }
+            | Command::XReadGroup { keys, .. }
+            | Command::Watch { keys }
+            | Command::Del { keys }
+            | Command::Migrate { keys, .. }
+            | Command::Eval { keys, .. }
+            | Command::EvalSha { keys, .. }
+            | Command::FCall { keys, .. } => keys.iter().map(String::as_str).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The channels the command publishes or subscribes to, patterns are told apart as they
+    /// are checked literally.
+    pub fn channels(&self) -> (Vec<&str>, Vec<&str>) {
+        match self {
+            Command::Subscribe { channels } | Command::SSubscribe { channels } => {
+                (channels.iter().map(String::as_str).collect(), vec![])
+            }
+            Command::Publish { channel, .. } | Command::SPublish { channel, .. } => {
+                (vec![channel], vec![])
+            }
+            Command::PSubscribe { patterns } => {
+                (vec![], patterns.iter().map(String::as_str).collect())
+            }
+            _ => (vec![], vec![]),
+        }
+    }
+}
+
+/// Name of the command carried by a request, lowercased as redis reports it in errors.
+pub fn command_name(request: &RespType) -> Option<String> {
+    match request {
+        RespType::Array { elements } => match elements.first() {
+            Some(RespType::BulkString { data }) => String::from_utf8(data.clone())
+                .ok()
+                .map(|name| name.to_ascii_lowercase()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Decodes every argument of the command (name excluded) as an utf8 bulk string.
+fn string_args(elements: &[RespType], cmd: &str) -> Result<Vec<String>, io::Error> {
+    elements
+        .iter()
+        .skip(1)
+        .map(|el| match el {
+            RespType::BulkString { data } => String::from_utf8(data.clone()).map_err(|_| {
+                io::Error::other(format!(
+                    "Invalid {cmd} command: arguments must be valid utf8"
+                ))
+            }),
+            _ => Err(io::Error::other(format!(
+                "Invalid {cmd} command: arguments must be RESP bulk strings"
+            ))),
+        })
+        .collect()
+}
+
+fn unknown_command(elements: &[RespType]) -> io::Error {
+    let quoted = elements
+        .iter()
+        .map(|el| match el {
+            RespType::BulkString { data } => format!("'{}'", String::from_utf8_lossy(data)),
+            _ => "''".into(),
+        })
+        .collect::<Vec<_>>();
+
+    let (name, args) = quoted.split_first().expect("commands have a name");
+    let args = args.iter().map(|arg| format!("{arg} ")).collect::<String>();
+
+    io::Error::other(format!(
+        "ERR unknown command {name}, with args beginning with: {args}"
+    ))
+}
+
+fn wrong_arity(cmd: &str) -> io::Error {
+    io::Error::other(format!(
+        "ERR wrong number of arguments for '{}' command",
+        cmd.to_ascii_lowercase()
+    ))
+}
+
+fn parse_no_args_cmd(
+    elements: &[RespType],
+    name: &str,
+    cmd: Command,
+) -> Result<Command, io::Error> {
+    match elements.len() {
+        1 => Ok(cmd),
+        _ => Err(wrong_arity(name)),
+    }
+}
+
+// <key> [<key> ...]
+fn parse_watch_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let keys = string_args(elements, "WATCH")?;
+    if keys.is_empty() {
+        return Err(wrong_arity("WATCH"));
+    }
+
+    Ok(Command::Watch { keys })
+}
+
+// [ASYNC | SYNC], flushing is always synchronous
+fn parse_flush_cmd(elements: &[RespType], name: &str, cmd: Command) -> Result<Command, io::Error> {
+    match string_args(elements, name)?.as_slice() {
+        [] => Ok(cmd),
+        [mode] if mode.eq_ignore_ascii_case("ASYNC") || mode.eq_ignore_ascii_case("SYNC") => {
+            Ok(cmd)
+        }
+        _ => Err(io::Error::other("ERR syntax error")),
+    }
+}
+
+// <index1> <index2>
+fn parse_swapdb_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let [index1, index2] = string_args(elements, "SWAPDB")?
+        .try_into()
+        .map_err(|_| wrong_arity("SWAPDB"))?;
+    let index = |arg: &str, which: &str| {
+        arg.parse::<i64>()
+            .map_err(|_| io::Error::other(format!("ERR invalid {which} DB index")))
+    };
+
+    Ok(Command::SwapDb {
+        index1: index(&index1, "first")?,
+        index2: index(&index2, "second")?,
+    })
+}
+
+// [SCHEDULE]
+fn parse_bgsave_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    match string_args(elements, "BGSAVE")?.as_slice() {
+        [] => Ok(Command::BgSave { schedule: false }),
+        [option] if option.eq_ignore_ascii_case("SCHEDULE") => {
+            Ok(Command::BgSave { schedule: true })
+        }
+        _ => Err(io::Error::other("ERR syntax error")),
+    }
+}
+
+// [<section> ...]
+fn parse_info_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let sections = string_args(elements, "INFO")?
+        .iter()
+        .map(|section| section.to_ascii_lowercase())
+        .collect();
+
+    Ok(Command::Info { sections })
+}
+
+// <host> <port> | NO ONE
+fn parse_replicaof_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    match string_args(elements, "REPLICAOF")?.as_slice() {
+        [no, one] if no.eq_ignore_ascii_case("NO") && one.eq_ignore_ascii_case("ONE") => {
+            Ok(Command::ReplicaOf { master: None })
+        }
+        [host, port] => {
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| io::Error::other("ERR Invalid master port"))?;
+            Ok(Command::ReplicaOf {
+                master: Some((host.clone(), port)),
+            })
+        }
+        _ => Err(wrong_arity("REPLICAOF")),
+    }
+}
+
+// [<option> <value> ...]
+fn parse_replconf_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "REPLCONF")?;
+    if !args.len().is_multiple_of(2) {
+        return Err(io::Error::other("ERR syntax error"));
+    }
+
+    let options = args
+        .chunks(2)
+        .map(|pair| (pair[0].to_ascii_lowercase(), pair[1].clone()))
+        .collect();
+    Ok(Command::ReplConf { options })
+}
+
+// <replid> <offset>
+fn parse_psync_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let [replid, offset] = string_args(elements, "PSYNC")?
+        .try_into()
+        .map_err(|_| wrong_arity("PSYNC"))?;
+    let offset = offset
+        .parse::<i64>()
+        .map_err(|_| io::Error::other("ERR value is not an integer or out of range"))?;
+
+    Ok(Command::PSync { replid, offset })
+}
+
+// <numreplicas> <timeout>
+fn parse_wait_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let [numreplicas, timeout] = string_args(elements, "WAIT")?
+        .try_into()
+        .map_err(|_| wrong_arity("WAIT"))?;
+
+    Ok(Command::Wait {
+        numreplicas: parse_count(&numreplicas)?,
+        timeout: parse_wait_timeout(&timeout)?,
+    })
+}
+
+// <numlocal> <numreplicas> <timeout>
+fn parse_waitaof_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let [numlocal, numreplicas, timeout] = string_args(elements, "WAITAOF")?
+        .try_into()
+        .map_err(|_| wrong_arity("WAITAOF"))?;
+
+    Ok(Command::WaitAof {
+        numlocal: parse_count(&numlocal)?,
+        numreplicas: parse_count(&numreplicas)?,
+        timeout: parse_wait_timeout(&timeout)?,
+    })
+}
+
+fn parse_count(arg: &str) -> Result<u64, io::Error> {
+    arg.parse::<u64>()
+        .map_err(|_| io::Error::other("ERR value is not an integer or out of range"))
+}
+
+//in milliseconds, 0 to wait forever
+fn parse_wait_timeout(arg: &str) -> Result<Option<Duration>, io::Error> {
+    let millis = arg
+        .parse::<i64>()
+        .map_err(|_| io::Error::other("ERR timeout is not an integer or out of range"))?;
+    if millis < 0 {
+        return Err(io::Error::other("ERR timeout is negative"));
+    }
+
+    Ok((millis > 0).then(|| Duration::from_millis(millis as u64)))
 }
 
+// [protover [AUTH <username> <password>] [SETNAME <clientname>]]
+fn parse_hello_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "HELLO")?;
+
+    let protover = match args.first() {
+        None => None,
+        Some(protover) => match protover.parse::<i64>() {
+            Ok(2) => Some(Protocol::Resp2),
+            Ok(3) => Some(Protocol::Resp3),
+            Ok(_) => return Err(io::Error::other("NOPROTO unsupported protocol version")),
+            Err(_) => {
+                return Err(io::Error::other(
+                    "ERR Protocol version is not an integer or out of range",
+                ));
+            }
+        },
+    };
+
+    let (mut auth, mut setname) = (None, None);
+    let mut options = args.iter().skip(1);
+    while let Some(option) = options.next() {
+        match option.to_uppercase().as_str() {
+            "AUTH" if options.len() >= 2 => {
+                let username = options.next().unwrap().clone();
+                let password = options.next().unwrap().clone();
+                auth = Some((username, password));
+            }
+            "SETNAME" if options.len() >= 1 => setname = options.next().cloned(),
+            _ => {
+                return Err(io::Error::other(format!(
+                    "ERR Syntax error in HELLO option '{option}'"
+                )));
+            }
+        }
+    }
+
+    Ok(Command::Hello {
+        protover,
+        auth,
+        setname,
+    })
+}
+
+// <key> [NOMKSTREAM] [<MAXLEN | MINID> [= | ~] <threshold> [LIMIT <count>]] <* | id> <field> <value> ...
 fn parse_xadd_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
     if elements.len() < 5 {
         return Err(io::Error::other(
@@ -120,40 +1343,42 @@ fn parse_xadd_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
         ));
     }
 
-    let key = elements
-        .get(1)
-        .and_then(|k| match k {
-            RespType::BulkString { data } => Some(data),
-            _ => None,
-        })
-        .and_then(|utf8| String::from_utf8(utf8.clone()).ok())
-        .ok_or(io::Error::other(
-            "Invalid XADD command: absent or invalid key",
-        ))?;
+    let args = string_args(elements, "XADD")?;
 
-    let id = elements
-        .get(2)
-        .and_then(|k| match k {
-            RespType::BulkString { data } => Some(data),
-            _ => None,
-        })
-        .and_then(|utf8| String::from_utf8(utf8.clone()).ok())
+    let key = args[0].clone();
+    let mut options = XAddOptions::default();
+    let mut idx = 1;
+
+    loop {
+        match args.get(idx) {
+            Some(opt) if opt.eq_ignore_ascii_case("NOMKSTREAM") => {
+                options.nomkstream = true;
+                idx += 1;
+            }
+            Some(opt)
+                if opt.eq_ignore_ascii_case("MAXLEN") || opt.eq_ignore_ascii_case("MINID") =>
+            {
+                let (trim, consumed) = parse_stream_trim(&args[idx..])?;
+                options.trim = Some(trim);
+                idx += consumed;
+            }
+            _ => break,
+        }
+    }
+
+    let id = args
+        .get(idx)
         .ok_or(io::Error::other(
             "Invalid XADD command: absent or invalid id",
-        ))?;
+        ))?
+        .parse::<XAddId>()?;
 
-    let elements = elements
-        .iter()
-        .skip(3)
-        .map(|k| match k {
-            RespType::BulkString { data } => Ok(data),
-            _ => Err(io::Error::other("Invalid resp type while parsing XADD cmd")),
-        })
-        .map(|utf8| match utf8 {
-            Ok(utf8) => String::from_utf8(utf8.clone()).map_err(|_| io::Error::other("")),
-            Err(err) => Err(err),
-        })
-        .collect::<Result<Vec<String>, io::Error>>()?
+    let fields = &args[idx + 1..];
+    if fields.is_empty() {
+        return Err(wrong_arity("XADD"));
+    }
+
+    let elements = fields
         .chunks(2)
         .map(|v| match v {
             [k, v] => Ok((k.clone(), v.clone())),
@@ -163,7 +1388,12 @@ fn parse_xadd_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
         })
         .collect::<Result<Vec<(String, String)>, io::Error>>()?;
 
-    Ok(Command::XAdd { key, id, elements })
+    Ok(Command::XAdd {
+        key,
+        id,
+        elements,
+        options,
+    })
 }
 
 fn parse_type_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
@@ -194,7 +1424,7 @@ fn parse_blpop_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
         .take_while(|str_repr| str_repr.parse::<u64>().is_err())
         .collect::<Vec<String>>();
 
-    if keys.len() == 0 {
+    if keys.is_empty() {
         return Err(io::Error::other(
             "Invalid BLPOP command: absent or invalid key",
         ));
@@ -349,6 +1579,19 @@ fn parse_push_cmd(raw_elements: &[RespType], kind: PushKind) -> Result<Command,
     }
 }
 
+// [message]
+fn parse_ping_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "PING")?;
+
+    match args.as_slice() {
+        [] => Ok(Command::Ping { message: None }),
+        [message] => Ok(Command::Ping {
+            message: Some(message.clone()),
+        }),
+        _ => Err(wrong_arity("PING")),
+    }
+}
+
 fn parse_echo_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
     let msg = elements.get(1).unwrap();
     let msg = match msg {
@@ -450,6 +1693,20 @@ fn parse_set_options(elements: &[RespType]) -> Result<SetOptions, io::Error> {
 
                         options.expire = Some(std::time::Duration::from_millis(ttl));
                     }
+                    "EXAT" => {
+                        let time = value.parse::<u64>().map_err(|err| {
+                            io::Error::other(format!("invalid expiry value: {err}"))
+                        })?;
+
+                        options.expire = Some(until(std::time::Duration::from_secs(time)));
+                    }
+                    "PXAT" => {
+                        let time = value.parse::<u64>().map_err(|err| {
+                            io::Error::other(format!("invalid expiry value: {err}"))
+                        })?;
+
+                        options.expire = Some(until(std::time::Duration::from_millis(time)));
+                    }
                     _ => { /*Skip iteration, it's probably a value which we already considered before*/
                     }
                 }
@@ -465,6 +1722,23 @@ fn parse_set_options(elements: &[RespType]) -> Result<SetOptions, io::Error> {
     Ok(options)
 }
 
+//the time left until the given unix time, zero if it is already past
+fn until(unix_time: std::time::Duration) -> std::time::Duration {
+    (std::time::UNIX_EPOCH + unix_time)
+        .duration_since(std::time::SystemTime::now())
+        .unwrap_or_default()
+}
+
+//the request of a command as clients send it, for the tests of the parsers
+#[cfg(test)]
+fn bulk_strings(args: &[&str]) -> Vec<RespType> {
+    args.iter()
+        .map(|arg| RespType::BulkString {
+            data: arg.as_bytes().to_vec(),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use core::time;
@@ -598,7 +1872,7 @@ mod test {
                 data: "key".as_bytes().to_vec(),
             },
             RespType::BulkString {
-                data: "id".as_bytes().to_vec(),
+                data: "1526919030474-0".as_bytes().to_vec(),
             },
             RespType::BulkString {
                 data: "first_el".as_bytes().to_vec(),
@@ -616,11 +1890,14 @@ mod test {
 
         let expected = Command::XAdd {
             key: "key".into(),
-            id: "id".into(),
+            id: XAddId::Explicit {
+                id: StreamId::new(1526919030474, 0),
+            },
             elements: vec![
                 ("first_el".into(), "first_val".into()),
                 ("second_el".into(), "second_val".into()),
             ],
+            options: XAddOptions::default(),
         };
 
         let parsed = parse_xadd_cmd(&elements);
@@ -639,7 +1916,7 @@ mod test {
                 data: "key".as_bytes().to_vec(),
             },
             RespType::BulkString {
-                data: "id".as_bytes().to_vec(),
+                data: "0-1".as_bytes().to_vec(),
             },
             RespType::BulkString {
                 data: "first_el".as_bytes().to_vec(),
@@ -657,4 +1934,171 @@ mod test {
 
         assert!(parsed.is_err_and(|err| err.to_string() == expected));
     }
+
+    #[test]
+    fn test_parse_xadd_id() {
+        assert_eq!(XAddId::Auto, "*".parse().unwrap());
+        assert_eq!(XAddId::AutoSeq { ms: 12 }, "12-*".parse().unwrap());
+        assert_eq!(
+            XAddId::Explicit {
+                id: StreamId::new(12, 3)
+            },
+            "12-3".parse().unwrap()
+        );
+
+        let invalid = "ab-*".parse::<XAddId>();
+        assert!(invalid.is_err_and(|err| {
+            err.to_string() == "ERR Invalid stream ID specified as stream command argument"
+        }));
+    }
+
+    #[test]
+    fn test_parse_xadd_command_with_options() {
+        let elements: Vec<RespType> = [
+            "XADD",
+            "key",
+            "NOMKSTREAM",
+            "MAXLEN",
+            "~",
+            "1000",
+            "LIMIT",
+            "10",
+            "*",
+            "f",
+            "v",
+        ]
+        .iter()
+        .map(|arg| RespType::BulkString {
+            data: arg.as_bytes().to_vec(),
+        })
+        .collect();
+
+        let expected = Command::XAdd {
+            key: "key".into(),
+            id: XAddId::Auto,
+            elements: vec![("f".into(), "v".into())],
+            options: XAddOptions {
+                nomkstream: true,
+                trim: Some(StreamTrim {
+                    strategy: TrimStrategy::MaxLen { len: 1000 },
+                    approx: true,
+                    limit: Some(10),
+                }),
+            },
+        };
+
+        assert_eq!(parse_xadd_cmd(&elements).unwrap(), expected);
+
+        let elements: Vec<RespType> = ["XADD", "key", "MINID", "5", "LIMIT", "10", "*", "f", "v"]
+            .iter()
+            .map(|arg| RespType::BulkString {
+                data: arg.as_bytes().to_vec(),
+            })
+            .collect();
+
+        let expected = "ERR syntax error, LIMIT cannot be used without the special ~ option";
+        assert!(parse_xadd_cmd(&elements).is_err_and(|err| err.to_string() == expected));
+    }
+
+    #[test]
+    fn test_parse_transaction_commands() {
+        let request = |args: &[&str]| RespType::Array {
+            elements: args
+                .iter()
+                .map(|arg| RespType::BulkString {
+                    data: arg.as_bytes().to_vec(),
+                })
+                .collect(),
+        };
+
+        assert_eq!(Command::from(request(&["multi"])), Command::Multi);
+        assert_eq!(Command::from(request(&["EXEC"])), Command::Exec);
+        assert_eq!(
+            Command::from(request(&["DISCARD", "now"])),
+            Command::ErrorCmd {
+                msg: "ERR wrong number of arguments for 'discard' command".into()
+            }
+        );
+        assert_eq!(
+            Command::from(request(&["flushdb", "async"])),
+            Command::FlushDb
+        );
+        assert_eq!(
+            Command::from(request(&["SWAPDB", "0", "1"])),
+            Command::SwapDb {
+                index1: 0,
+                index2: 1
+            }
+        );
+        assert_eq!(
+            Command::from(request(&["SWAPDB", "0", "x"])),
+            Command::ErrorCmd {
+                msg: "ERR invalid second DB index".into()
+            }
+        );
+        assert_eq!(
+            Command::from(request(&["SWAPDB", "0"])),
+            Command::ErrorCmd {
+                msg: "ERR wrong number of arguments for 'swapdb' command".into()
+            }
+        );
+        assert_eq!(
+            Command::from(request(&["FOO", "a", "b"])),
+            Command::ErrorCmd {
+                msg: "ERR unknown command 'FOO', with args beginning with: 'a' 'b' ".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_wait_commands() {
+        let parse = |args: &[&str]| {
+            Command::from(RespType::Array {
+                elements: bulk_strings(args),
+            })
+        };
+        let error = |msg: &str| Command::ErrorCmd { msg: msg.into() };
+
+        //a timeout of 0 waits forever
+        assert_eq!(
+            parse(&["WAIT", "1", "0"]),
+            Command::Wait {
+                numreplicas: 1,
+                timeout: None
+            }
+        );
+        assert_eq!(
+            parse(&["waitaof", "1", "2", "100"]),
+            Command::WaitAof {
+                numlocal: 1,
+                numreplicas: 2,
+                timeout: Some(time::Duration::from_millis(100))
+            }
+        );
+
+        assert_eq!(
+            parse(&["WAIT", "1"]),
+            error("ERR wrong number of arguments for 'wait' command")
+        );
+        assert_eq!(
+            parse(&["WAITAOF", "1", "0"]),
+            error("ERR wrong number of arguments for 'waitaof' command")
+        );
+        assert_eq!(
+            parse(&["WAIT", "-1", "0"]),
+            error("ERR value is not an integer or out of range")
+        );
+        assert_eq!(
+            parse(&["WAITAOF", "1", "x", "0"]),
+            error("ERR value is not an integer or out of range")
+        );
+        assert_eq!(
+            parse(&["WAIT", "1", "soon"]),
+            error("ERR timeout is not an integer or out of range")
+        );
+        assert_eq!(
+            parse(&["WAITAOF", "0", "1", "-5"]),
+            error("ERR timeout is negative")
+        );
+    }
 }
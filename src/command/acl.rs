@@ -0,0 +1,187 @@
+use std::io;
+
+use crate::{
+    command::{AclSubcommand, Command, string_args, wrong_arity},
+    resp::RespType,
+};
+
+// [<username>] <password>
+pub(super) fn parse_auth_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "AUTH")?;
+
+    match args.as_slice() {
+        [password] => Ok(Command::Auth {
+            username: None,
+            password: password.clone(),
+        }),
+        [username, password] => Ok(Command::Auth {
+            username: Some(username.clone()),
+            password: password.clone(),
+        }),
+        [] => Err(wrong_arity("AUTH")),
+        _ => Err(io::Error::other("ERR syntax error")),
+    }
+}
+
+// SETUSER <username> [<rule> ...] | GETUSER <username> | DELUSER <username> [<username> ...] |
+// LIST | USERS | WHOAMI | CAT [<category>] | DRYRUN <username> <command> [<arg> ...] |
+// LOG [<count> | RESET] | LOAD | SAVE
+pub(super) fn parse_acl_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    const SUBCOMMANDS: [&str; 10] = [
+        "SETUSER", "GETUSER", "DELUSER", "LIST", "USERS", "WHOAMI", "CAT", "LOG", "LOAD", "SAVE",
+    ];
+
+    //the command checked by DRYRUN is parsed as if it came from a client
+    if let Some(RespType::BulkString { data }) = elements.get(1)
+        && data.eq_ignore_ascii_case(b"DRYRUN")
+    {
+        let args = string_args(elements.get(..3).unwrap_or(elements), "ACL")?;
+        let ([_, username], Some(request)) = (args.as_slice(), elements.get(3..)) else {
+            return Err(wrong_arity("ACL|DRYRUN"));
+        };
+        if request.is_empty() {
+            return Err(wrong_arity("ACL|DRYRUN"));
+        }
+
+        return Ok(Command::Acl {
+            subcommand: AclSubcommand::DryRun {
+                username: username.clone(),
+                cmd: Box::new(Command::from(RespType::Array {
+                    elements: request.to_vec(),
+                })),
+            },
+        });
+    }
+
+    let args = string_args(elements, "ACL")?;
+    let Some((subcommand, args)) = args.split_first() else {
+        return Err(wrong_arity("ACL"));
+    };
+
+    let subcommand = match (subcommand.to_ascii_uppercase().as_str(), args) {
+        ("SETUSER", [username, rules @ ..]) => AclSubcommand::SetUser {
+            username: username.clone(),
+            rules: rules.to_vec(),
+        },
+        ("GETUSER", [username]) => AclSubcommand::GetUser {
+            username: username.clone(),
+        },
+        ("DELUSER", usernames) if !usernames.is_empty() => AclSubcommand::DelUser {
+            usernames: usernames.to_vec(),
+        },
+        ("LIST", []) => AclSubcommand::List,
+        ("USERS", []) => AclSubcommand::Users,
+        ("WHOAMI", []) => AclSubcommand::WhoAmI,
+        ("CAT", []) => AclSubcommand::Cat { category: None },
+        ("CAT", [category]) => AclSubcommand::Cat {
+            category: Some(category.to_ascii_lowercase()),
+        },
+        ("LOG", []) => AclSubcommand::Log { count: None },
+        ("LOG", [option]) if option.eq_ignore_ascii_case("RESET") => AclSubcommand::LogReset,
+        ("LOG", [count]) => AclSubcommand::Log {
+            count: Some(parse_log_count(count)?),
+        },
+        ("LOAD", []) => AclSubcommand::Load,
+        ("SAVE", []) => AclSubcommand::Save,
+        (name, _) if SUBCOMMANDS.contains(&name) => {
+            return Err(wrong_arity(&format!("ACL|{name}")));
+        }
+        _ => {
+            return Err(io::Error::other(format!(
+                "ERR unknown subcommand '{subcommand}'. Try ACL HELP."
+            )));
+        }
+    };
+
+    Ok(Command::Acl { subcommand })
+}
+
+fn parse_log_count(count: &str) -> Result<usize, io::Error> {
+    count
+        .parse::<usize>()
+        .map_err(|_| io::Error::other("ERR value is out of range, must be positive"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_acl_cmd, parse_auth_cmd};
+    use crate::command::{AclSubcommand, Command, bulk_strings};
+
+    #[test]
+    fn test_parse_auth_cmd() {
+        let cmd = parse_auth_cmd(&bulk_strings(&["AUTH", "secret"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Auth {
+                username: None,
+                password: "secret".into()
+            }
+        );
+
+        let cmd = parse_auth_cmd(&bulk_strings(&["AUTH", "alice", "secret"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Auth {
+                username: Some("alice".into()),
+                password: "secret".into()
+            }
+        );
+
+        let err = parse_auth_cmd(&bulk_strings(&["AUTH", "a", "b", "c"])).unwrap_err();
+        assert_eq!(err.to_string(), "ERR syntax error");
+    }
+
+    #[test]
+    fn test_parse_acl_cmd() {
+        let cmd = parse_acl_cmd(&bulk_strings(&[
+            "ACL", "setuser", "alice", "on", ">pw", "~k*",
+        ]))
+        .unwrap();
+        assert_eq!(
+            cmd,
+            Command::Acl {
+                subcommand: AclSubcommand::SetUser {
+                    username: "alice".into(),
+                    rules: vec!["on".into(), ">pw".into(), "~k*".into()]
+                }
+            }
+        );
+
+        let cmd = parse_acl_cmd(&bulk_strings(&["ACL", "DRYRUN", "alice", "get", "key"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Acl {
+                subcommand: AclSubcommand::DryRun {
+                    username: "alice".into(),
+                    cmd: Box::new(Command::Get { key: "key".into() })
+                }
+            }
+        );
+
+        let cmd = parse_acl_cmd(&bulk_strings(&["ACL", "LOG", "reset"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Acl {
+                subcommand: AclSubcommand::LogReset
+            }
+        );
+
+        let err = parse_acl_cmd(&bulk_strings(&["ACL", "LOG", "-1"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR value is out of range, must be positive"
+        );
+
+        let err = parse_acl_cmd(&bulk_strings(&["ACL", "DRYRUN", "alice"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'acl|dryrun' command"
+        );
+
+        let err = parse_acl_cmd(&bulk_strings(&["ACL", "WHOAMI", "x"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'acl|whoami' command"
+        );
+    }
+}
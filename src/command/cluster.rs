@@ -0,0 +1,232 @@
+use std::{collections::HashSet, io};
+
+use crate::{
+    cluster::SLOTS,
+    command::{ClusterSubcommand, Command, SetSlotAction, string_args, wrong_arity},
+    resp::RespType,
+};
+
+// INFO | MYID | NODES | SLOTS | SHARDS | KEYSLOT <key> | COUNTKEYSINSLOT <slot> |
+// GETKEYSINSLOT <slot> <count> | ADDSLOTS <slot> [<slot> ...] |
+// ADDSLOTSRANGE <start> <end> [<start> <end> ...] | DELSLOTS <slot> [<slot> ...] |
+// SETSLOT <slot> IMPORTING <node> | MIGRATING <node> | STABLE | NODE <node> |
+// MEET <ip> <port> [<cluster bus port>] | REPLICATE <node> | REPLICAS <node> |
+// COUNT-FAILURE-REPORTS <node>
+pub(super) fn parse_cluster_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "CLUSTER")?;
+    let Some((subcommand, args)) = args.split_first() else {
+        return Err(wrong_arity("CLUSTER"));
+    };
+    let name = subcommand.to_ascii_uppercase();
+    let arity = || wrong_arity(&format!("CLUSTER|{name}"));
+
+    let subcommand = match (name.as_str(), args) {
+        ("INFO", []) => ClusterSubcommand::Info,
+        ("MYID", []) => ClusterSubcommand::MyId,
+        ("NODES", []) => ClusterSubcommand::Nodes,
+        ("SLOTS", []) => ClusterSubcommand::Slots,
+        ("SHARDS", []) => ClusterSubcommand::Shards,
+        ("KEYSLOT", [key]) => ClusterSubcommand::KeySlot { key: key.clone() },
+        ("COUNTKEYSINSLOT", [slot]) => ClusterSubcommand::CountKeysInSlot {
+            slot: parse_slot(slot, "ERR Invalid slot")?,
+        },
+        ("GETKEYSINSLOT", [slot, count]) => ClusterSubcommand::GetKeysInSlot {
+            slot: parse_slot(slot, "ERR Invalid slot")?,
+            count: count
+                .parse()
+                .map_err(|_| io::Error::other("ERR Invalid number of keys"))?,
+        },
+        ("ADDSLOTS", [_, ..]) => ClusterSubcommand::AddSlots {
+            slots: parse_slots(args)?,
+        },
+        ("ADDSLOTSRANGE", [_, _, ..]) if args.len() % 2 == 0 => {
+            let ranges = args
+                .chunks_exact(2)
+                .map(|range| {
+                    let start = parse_slot(&range[0], "ERR Invalid or out of range slot")?;
+                    let end = parse_slot(&range[1], "ERR Invalid or out of range slot")?;
+                    if start > end {
+                        return Err(io::Error::other(format!(
+                            "ERR start slot number {start} is greater than end slot number {end}"
+                        )));
+                    }
+                    Ok((start, end))
+                })
+                .collect::<Result<Vec<_>, io::Error>>()?;
+
+            let mut seen = HashSet::new();
+            if let Some(slot) = ranges
+                .iter()
+                .flat_map(|(start, end)| *start..=*end)
+                .find(|slot| !seen.insert(*slot))
+            {
+                return Err(io::Error::other(format!(
+                    "ERR Slot {slot} specified multiple times"
+                )));
+            }
+            ClusterSubcommand::AddSlotsRange { ranges }
+        }
+        ("DELSLOTS", [_, ..]) => ClusterSubcommand::DelSlots {
+            slots: parse_slots(args)?,
+        },
+        ("SETSLOT", [slot, action, rest @ ..]) => {
+            let slot = parse_slot(slot, "ERR Invalid or out of range slot")?;
+            let action = match (action.to_ascii_uppercase().as_str(), rest) {
+                ("IMPORTING", [node]) => SetSlotAction::Importing { node: node.clone() },
+                ("MIGRATING", [node]) => SetSlotAction::Migrating { node: node.clone() },
+                ("STABLE", []) => SetSlotAction::Stable,
+                ("NODE", [node]) => SetSlotAction::Node { node: node.clone() },
+                _ => {
+                    return Err(io::Error::other(
+                        "ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP",
+                    ));
+                }
+            };
+            ClusterSubcommand::SetSlot { slot, action }
+        }
+        ("MEET", [ip, port, cport @ ..]) if cport.len() <= 1 => ClusterSubcommand::Meet {
+            ip: ip.clone(),
+            port: port.parse().map_err(|_| {
+                io::Error::other(format!("ERR Invalid base port specified: {port}"))
+            })?,
+            cport: cport
+                .first()
+                .map(|cport| {
+                    cport.parse().map_err(|_| {
+                        io::Error::other(format!("ERR Invalid bus port specified: {cport}"))
+                    })
+                })
+                .transpose()?,
+        },
+        ("REPLICATE", [node]) => ClusterSubcommand::Replicate { node: node.clone() },
+        ("REPLICAS", [node]) => ClusterSubcommand::Replicas { node: node.clone() },
+        ("COUNT-FAILURE-REPORTS", [node]) => {
+            ClusterSubcommand::CountFailureReports { node: node.clone() }
+        }
+        (
+            "INFO"
+            | "MYID"
+            | "NODES"
+            | "SLOTS"
+            | "SHARDS"
+            | "KEYSLOT"
+            | "COUNTKEYSINSLOT"
+            | "GETKEYSINSLOT"
+            | "ADDSLOTS"
+            | "ADDSLOTSRANGE"
+            | "DELSLOTS"
+            | "SETSLOT"
+            | "MEET"
+            | "REPLICATE"
+            | "REPLICAS"
+            | "COUNT-FAILURE-REPORTS",
+            _,
+        ) => return Err(arity()),
+        _ => {
+            return Err(io::Error::other(format!(
+                "ERR unknown subcommand '{subcommand}'. Try CLUSTER HELP."
+            )));
+        }
+    };
+
+    Ok(Command::Cluster { subcommand })
+}
+
+fn parse_slot(arg: &str, error: &str) -> Result<u16, io::Error> {
+    arg.parse::<u16>()
+        .ok()
+        .filter(|slot| *slot < SLOTS)
+        .ok_or_else(|| io::Error::other(error.to_string()))
+}
+
+//a list of distinct slots
+fn parse_slots(args: &[String]) -> Result<Vec<u16>, io::Error> {
+    let mut seen = HashSet::new();
+    args.iter()
+        .map(|arg| {
+            let slot = parse_slot(arg, "ERR Invalid or out of range slot")?;
+            match seen.insert(slot) {
+                true => Ok(slot),
+                false => Err(io::Error::other(format!(
+                    "ERR Slot {slot} specified multiple times"
+                ))),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_cluster_cmd;
+    use crate::command::{ClusterSubcommand, Command, SetSlotAction, bulk_strings};
+
+    #[test]
+    fn test_parse_cluster_cmd() {
+        let cmd =
+            parse_cluster_cmd(&bulk_strings(&["CLUSTER", "addslotsrange", "0", "2"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Cluster {
+                subcommand: ClusterSubcommand::AddSlotsRange {
+                    ranges: vec![(0, 2)]
+                }
+            }
+        );
+
+        let err =
+            parse_cluster_cmd(&bulk_strings(&["CLUSTER", "ADDSLOTS", "1", "16384"])).unwrap_err();
+        assert_eq!(err.to_string(), "ERR Invalid or out of range slot");
+        let err = parse_cluster_cmd(&bulk_strings(&["CLUSTER", "DELSLOTS", "1", "1"])).unwrap_err();
+        assert_eq!(err.to_string(), "ERR Slot 1 specified multiple times");
+
+        let cmd = parse_cluster_cmd(&bulk_strings(&[
+            "CLUSTER",
+            "SETSLOT",
+            "12",
+            "migrating",
+            "abc",
+        ]))
+        .unwrap();
+        assert_eq!(
+            cmd,
+            Command::Cluster {
+                subcommand: ClusterSubcommand::SetSlot {
+                    slot: 12,
+                    action: SetSlotAction::Migrating { node: "abc".into() }
+                }
+            }
+        );
+
+        let cmd =
+            parse_cluster_cmd(&bulk_strings(&["CLUSTER", "MEET", "127.0.0.1", "7001"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Cluster {
+                subcommand: ClusterSubcommand::Meet {
+                    ip: "127.0.0.1".into(),
+                    port: 7001,
+                    cport: None
+                }
+            }
+        );
+
+        let cmd = parse_cluster_cmd(&bulk_strings(&["CLUSTER", "replicate", "abc"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Cluster {
+                subcommand: ClusterSubcommand::Replicate { node: "abc".into() }
+            }
+        );
+
+        let err = parse_cluster_cmd(&bulk_strings(&["CLUSTER", "KEYSLOT"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'cluster|keyslot' command"
+        );
+        let err = parse_cluster_cmd(&bulk_strings(&["CLUSTER", "foo"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR unknown subcommand 'foo'. Try CLUSTER HELP."
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use std::io;
+
+use crate::{
+    command::{Command, ConfigSubcommand, string_args, wrong_arity},
+    resp::RespType,
+};
+
+// GET <parameter> [<parameter> ...] | SET <parameter> <value> [<parameter> <value> ...] |
+// RESETSTAT | REWRITE
+pub(super) fn parse_config_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "CONFIG")?;
+    let Some((subcommand, args)) = args.split_first() else {
+        return Err(wrong_arity("CONFIG"));
+    };
+
+    let subcommand = match subcommand.to_ascii_uppercase().as_str() {
+        "GET" if !args.is_empty() => ConfigSubcommand::Get {
+            parameters: args.to_vec(),
+        },
+        "SET" if !args.is_empty() && args.len() % 2 == 0 => ConfigSubcommand::Set {
+            parameters: args
+                .chunks_exact(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect(),
+        },
+        "RESETSTAT" if args.is_empty() => ConfigSubcommand::ResetStat,
+        "REWRITE" if args.is_empty() => ConfigSubcommand::Rewrite,
+        name @ ("GET" | "SET" | "RESETSTAT" | "REWRITE") => {
+            return Err(wrong_arity(&format!("CONFIG|{name}")));
+        }
+        _ => {
+            return Err(io::Error::other(format!(
+                "ERR unknown subcommand '{subcommand}'. Try CONFIG HELP."
+            )));
+        }
+    };
+
+    Ok(Command::Config { subcommand })
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_config_cmd;
+    use crate::command::{Command, ConfigSubcommand, bulk_strings};
+
+    #[test]
+    fn test_parse_config_cmd() {
+        let cmd = parse_config_cmd(&bulk_strings(&[
+            "CONFIG",
+            "set",
+            "notify-keyspace-events",
+            "KEA",
+        ]))
+        .unwrap();
+        assert_eq!(
+            cmd,
+            Command::Config {
+                subcommand: ConfigSubcommand::Set {
+                    parameters: vec![("notify-keyspace-events".into(), "KEA".into())]
+                }
+            }
+        );
+
+        let err = parse_config_cmd(&bulk_strings(&["CONFIG", "SET", "a"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'config|set' command"
+        );
+
+        let cmd = parse_config_cmd(&bulk_strings(&["CONFIG", "rewrite"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Config {
+                subcommand: ConfigSubcommand::Rewrite
+            }
+        );
+
+        let err = parse_config_cmd(&bulk_strings(&["CONFIG", "RESETSTAT", "x"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'config|resetstat' command"
+        );
+
+        let err = parse_config_cmd(&bulk_strings(&["CONFIG", "foo"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR unknown subcommand 'foo'. Try CONFIG HELP."
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use std::{io, time::Duration};
+
+use crate::{
+    command::{Command, string_args, wrong_arity},
+    resp::RespType,
+};
+
+// <key> [<key> ...]
+pub(super) fn parse_del_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let keys = string_args(elements, "DEL")?;
+    if keys.is_empty() {
+        return Err(wrong_arity("DEL"));
+    }
+
+    Ok(Command::Del { keys })
+}
+
+// <key>
+pub(super) fn parse_dump_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    match string_args(elements, "DUMP")?.as_slice() {
+        [key] => Ok(Command::Dump { key: key.clone() }),
+        _ => Err(wrong_arity("DUMP")),
+    }
+}
+
+// <key> <ttl> <serialized value> [REPLACE] [ABSTTL] [IDLETIME <seconds>] [FREQ <frequency>],
+// the eviction hints are checked but unused, no LRU or LFU policy is there to need them
+pub(super) fn parse_restore_cmd(elements: &[RespType], asking: bool) -> Result<Command, io::Error> {
+    let name = if asking { "RESTORE-ASKING" } else { "RESTORE" };
+    let Some(RespType::BulkString { data: payload }) = elements.get(3) else {
+        return Err(wrong_arity(name));
+    };
+    let args = string_args(&elements[..3], name)?;
+    let [key, ttl] = args.as_slice() else {
+        return Err(wrong_arity(name));
+    };
+    let ttl = match ttl.parse::<i64>().map_err(|_| not_an_integer())? {
+        ttl if ttl < 0 => return Err(io::Error::other("ERR Invalid TTL value, must be >= 0")),
+        ttl => ttl as u64,
+    };
+
+    let (mut replace, mut absttl) = (false, false);
+    let (mut idletime, mut freq) = (false, false);
+    let options = string_args(&elements[3..], name)?;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_str() {
+            "REPLACE" => replace = true,
+            "ABSTTL" => absttl = true,
+            "IDLETIME" if !freq => {
+                let idle = options.next().ok_or_else(syntax_error)?;
+                if idle.parse::<i64>().map_err(|_| not_an_integer())? < 0 {
+                    return Err(io::Error::other("ERR Invalid IDLETIME value, must be >= 0"));
+                }
+                idletime = true;
+            }
+            "FREQ" if !idletime => {
+                let frequency = options.next().ok_or_else(syntax_error)?;
+                let frequency = frequency.parse::<i64>().map_err(|_| not_an_integer())?;
+                if !(0..=255).contains(&frequency) {
+                    return Err(io::Error::other(
+                        "ERR Invalid FREQ value, must be >= 0 and <= 255",
+                    ));
+                }
+                freq = true;
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    Ok(Command::Restore {
+        key: key.clone(),
+        ttl,
+        payload: payload.clone(),
+        replace,
+        absttl,
+        asking,
+    })
+}
+
+// <host> <port> <key> | "" <destination-db> <timeout> [COPY] [REPLACE] [AUTH <password>]
+// [AUTH2 <username> <password>] [KEYS <key> [<key> ...]]
+pub(super) fn parse_migrate_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "MIGRATE")?;
+    let [host, port, key, db, timeout, options @ ..] = args.as_slice() else {
+        return Err(wrong_arity("MIGRATE"));
+    };
+    let port = port.parse::<u16>().map_err(|_| not_an_integer())?;
+    //SELECT does not exist here, so keys can only be moved to the database 0 of the target
+    if db.parse::<u64>().map_err(|_| not_an_integer())? != 0 {
+        return Err(io::Error::other(
+            "ERR Only database 0 is supported as the destination db",
+        ));
+    }
+    //like redis, a timeout that is not positive means a second
+    let timeout = match timeout.parse::<i64>().map_err(|_| not_an_integer())? {
+        timeout if timeout <= 0 => Duration::from_secs(1),
+        timeout => Duration::from_millis(timeout as u64),
+    };
+
+    let (mut copy, mut replace, mut auth) = (false, false, None);
+    let mut keys = vec![];
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_str() {
+            "COPY" => copy = true,
+            "REPLACE" => replace = true,
+            "AUTH" => {
+                let password = options.next().ok_or_else(syntax_error)?;
+                auth = Some((None, password.clone()));
+            }
+            "AUTH2" => {
+                let (Some(username), Some(password)) = (options.next(), options.next()) else {
+                    return Err(syntax_error());
+                };
+                auth = Some((Some(username.clone()), password.clone()));
+            }
+            "KEYS" => {
+                if !key.is_empty() {
+                    return Err(io::Error::other(
+                        "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string",
+                    ));
+                }
+                keys = options.by_ref().cloned().collect();
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    if keys.is_empty() {
+        keys.push(key.clone());
+    }
+
+    Ok(Command::Migrate {
+        host: host.clone(),
+        port,
+        keys,
+        timeout,
+        copy,
+        replace,
+        auth,
+    })
+}
+
+fn syntax_error() -> io::Error {
+    io::Error::other("ERR syntax error")
+}
+
+fn not_an_integer() -> io::Error {
+    io::Error::other("ERR value is not an integer or out of range")
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{parse_migrate_cmd, parse_restore_cmd};
+    use crate::{
+        command::{Command, bulk_strings},
+        resp::RespType,
+    };
+
+    #[test]
+    fn test_parse_restore_and_migrate() {
+        let mut elements = bulk_strings(&["RESTORE", "key", "100"]);
+        elements.push(RespType::BulkString {
+            data: vec![0, 0xff, b'\r', b'\n'],
+        });
+        elements.extend(bulk_strings(&["replace", "IDLETIME", "10"]));
+        assert_eq!(
+            parse_restore_cmd(&elements, false).unwrap(),
+            Command::Restore {
+                key: "key".into(),
+                ttl: 100,
+                payload: vec![0, 0xff, b'\r', b'\n'],
+                replace: true,
+                absttl: false,
+                asking: false,
+            }
+        );
+
+        for (options, error) in [
+            (
+                &["FREQ", "256"][..],
+                "ERR Invalid FREQ value, must be >= 0 and <= 255",
+            ),
+            (
+                &["IDLETIME", "-1"],
+                "ERR Invalid IDLETIME value, must be >= 0",
+            ),
+            (&["IDLETIME", "1", "FREQ", "1"], "ERR syntax error"),
+        ] {
+            let mut elements = bulk_strings(&["RESTORE", "key", "0", "payload"]);
+            elements.extend(bulk_strings(options));
+            let err = parse_restore_cmd(&elements, false).unwrap_err();
+            assert_eq!(err.to_string(), error);
+        }
+        let err = parse_restore_cmd(&bulk_strings(&["RESTORE", "key", "-1", "payload"]), false)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "ERR Invalid TTL value, must be >= 0");
+
+        let cmd = parse_migrate_cmd(&bulk_strings(&[
+            "MIGRATE",
+            "127.0.0.1",
+            "6380",
+            "",
+            "0",
+            "0",
+            "COPY",
+            "AUTH2",
+            "user",
+            "pass",
+            "KEYS",
+            "a",
+            "b",
+        ]))
+        .unwrap();
+        assert_eq!(
+            cmd,
+            Command::Migrate {
+                host: "127.0.0.1".into(),
+                port: 6380,
+                keys: vec!["a".into(), "b".into()],
+                timeout: Duration::from_secs(1),
+                copy: true,
+                replace: false,
+                auth: Some((Some("user".into()), "pass".into())),
+            }
+        );
+
+        let err = parse_migrate_cmd(&bulk_strings(&[
+            "MIGRATE", "host", "6380", "key", "0", "1000", "KEYS", "a",
+        ]))
+        .unwrap_err();
+        assert!(err.to_string().contains("must be set to the empty string"));
+
+        let err = parse_migrate_cmd(&bulk_strings(&[
+            "MIGRATE", "host", "6380", "key", "1", "1000",
+        ]))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR Only database 0 is supported as the destination db"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::io;
+
+use crate::{
+    command::{Command, PubSubSubcommand, string_args, wrong_arity},
+    resp::RespType,
+};
+
+// <channel> [<channel> ...]
+pub(super) fn parse_subscribe_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let channels = string_args(elements, "SUBSCRIBE")?;
+    if channels.is_empty() {
+        return Err(wrong_arity("SUBSCRIBE"));
+    }
+
+    Ok(Command::Subscribe { channels })
+}
+
+// [<channel> [<channel> ...]], no channel means all of them
+pub(super) fn parse_unsubscribe_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    Ok(Command::Unsubscribe {
+        channels: string_args(elements, "UNSUBSCRIBE")?,
+    })
+}
+
+// <pattern> [<pattern> ...]
+pub(super) fn parse_psubscribe_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let patterns = string_args(elements, "PSUBSCRIBE")?;
+    if patterns.is_empty() {
+        return Err(wrong_arity("PSUBSCRIBE"));
+    }
+
+    Ok(Command::PSubscribe { patterns })
+}
+
+// [<pattern> [<pattern> ...]], no pattern means all of them
+pub(super) fn parse_punsubscribe_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    Ok(Command::PUnsubscribe {
+        patterns: string_args(elements, "PUNSUBSCRIBE")?,
+    })
+}
+
+// <channel> <message>
+pub(super) fn parse_publish_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    match string_args(elements, "PUBLISH")?.as_slice() {
+        [channel, message] => Ok(Command::Publish {
+            channel: channel.clone(),
+            message: message.clone(),
+        }),
+        _ => Err(wrong_arity("PUBLISH")),
+    }
+}
+
+// <shardchannel> [<shardchannel> ...]
+pub(super) fn parse_ssubscribe_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let channels = string_args(elements, "SSUBSCRIBE")?;
+    if channels.is_empty() {
+        return Err(wrong_arity("SSUBSCRIBE"));
+    }
+
+    Ok(Command::SSubscribe { channels })
+}
+
+// [<shardchannel> [<shardchannel> ...]], no channel means all of them
+pub(super) fn parse_sunsubscribe_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    Ok(Command::SUnsubscribe {
+        channels: string_args(elements, "SUNSUBSCRIBE")?,
+    })
+}
+
+// <shardchannel> <message>
+pub(super) fn parse_spublish_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    match string_args(elements, "SPUBLISH")?.as_slice() {
+        [channel, message] => Ok(Command::SPublish {
+            channel: channel.clone(),
+            message: message.clone(),
+        }),
+        _ => Err(wrong_arity("SPUBLISH")),
+    }
+}
+
+// CHANNELS [<pattern>] | NUMSUB [<channel> ...] | NUMPAT | SHARDCHANNELS [<pattern>]
+// | SHARDNUMSUB [<shardchannel> ...]
+pub(super) fn parse_pubsub_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "PUBSUB")?;
+    let Some((subcommand, args)) = args.split_first() else {
+        return Err(wrong_arity("PUBSUB"));
+    };
+
+    let subcommand = match (subcommand.to_ascii_uppercase().as_str(), args) {
+        ("CHANNELS", []) => PubSubSubcommand::Channels { pattern: None },
+        ("CHANNELS", [pattern]) => PubSubSubcommand::Channels {
+            pattern: Some(pattern.clone()),
+        },
+        ("NUMSUB", channels) => PubSubSubcommand::NumSub {
+            channels: channels.to_vec(),
+        },
+        ("NUMPAT", []) => PubSubSubcommand::NumPat,
+        ("SHARDCHANNELS", []) => PubSubSubcommand::ShardChannels { pattern: None },
+        ("SHARDCHANNELS", [pattern]) => PubSubSubcommand::ShardChannels {
+            pattern: Some(pattern.clone()),
+        },
+        ("SHARDNUMSUB", channels) => PubSubSubcommand::ShardNumSub {
+            channels: channels.to_vec(),
+        },
+        ("CHANNELS" | "NUMPAT" | "SHARDCHANNELS", _) => {
+            return Err(wrong_arity(&format!(
+                "PUBSUB|{}",
+                subcommand.to_ascii_uppercase()
+            )));
+        }
+        _ => {
+            return Err(io::Error::other(format!(
+                "ERR unknown subcommand '{subcommand}'. Try PUBSUB HELP."
+            )));
+        }
+    };
+
+    Ok(Command::PubSub { subcommand })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_pubsub_cmd, parse_subscribe_cmd, parse_unsubscribe_cmd};
+    use crate::command::{Command, PubSubSubcommand, bulk_strings};
+
+    #[test]
+    fn test_parse_subscribe_commands() {
+        let cmd = parse_subscribe_cmd(&bulk_strings(&["SUBSCRIBE", "a", "b"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Subscribe {
+                channels: vec!["a".into(), "b".into()]
+            }
+        );
+
+        let err = parse_subscribe_cmd(&bulk_strings(&["SUBSCRIBE"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'subscribe' command"
+        );
+
+        let cmd = parse_unsubscribe_cmd(&bulk_strings(&["UNSUBSCRIBE"])).unwrap();
+        assert_eq!(cmd, Command::Unsubscribe { channels: vec![] });
+    }
+
+    #[test]
+    fn test_parse_pubsub_cmd() {
+        let cmd = parse_pubsub_cmd(&bulk_strings(&["PUBSUB", "channels", "news.*"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::PubSub {
+                subcommand: PubSubSubcommand::Channels {
+                    pattern: Some("news.*".into())
+                }
+            }
+        );
+
+        let cmd = parse_pubsub_cmd(&bulk_strings(&["PUBSUB", "NUMSUB"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::PubSub {
+                subcommand: PubSubSubcommand::NumSub { channels: vec![] }
+            }
+        );
+
+        let err = parse_pubsub_cmd(&bulk_strings(&["PUBSUB", "NUMPAT", "x"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'pubsub|numpat' command"
+        );
+
+        let cmd = parse_pubsub_cmd(&bulk_strings(&["PUBSUB", "SHARDNUMSUB", "a"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::PubSub {
+                subcommand: PubSubSubcommand::ShardNumSub {
+                    channels: vec!["a".into()]
+                }
+            }
+        );
+
+        let err = parse_pubsub_cmd(&bulk_strings(&["PUBSUB", "foo"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR unknown subcommand 'foo'. Try PUBSUB HELP."
+        );
+    }
+}
@@ -0,0 +1,407 @@
+use std::io;
+
+use crate::{
+    command::{
+        Command, FunctionSubcommand, RestorePolicy, ScriptSubcommand, string_args, wrong_arity,
+    },
+    resp::RespType,
+};
+
+// <script> <numkeys> [<key> ...] [<arg> ...]
+pub(super) fn parse_eval_cmd(elements: &[RespType], read_only: bool) -> Result<Command, io::Error> {
+    let name = if read_only { "EVAL_RO" } else { "EVAL" };
+    let args = string_args(elements, name)?;
+    let Some((script, args)) = args.split_first() else {
+        return Err(wrong_arity(name));
+    };
+    let (keys, args) = parse_keys_and_args(args, name)?;
+
+    Ok(Command::Eval {
+        script: script.clone(),
+        keys,
+        args,
+        read_only,
+    })
+}
+
+// <sha1> <numkeys> [<key> ...] [<arg> ...]
+pub(super) fn parse_evalsha_cmd(
+    elements: &[RespType],
+    read_only: bool,
+) -> Result<Command, io::Error> {
+    let name = if read_only { "EVALSHA_RO" } else { "EVALSHA" };
+    let args = string_args(elements, name)?;
+    let Some((sha1, args)) = args.split_first() else {
+        return Err(wrong_arity(name));
+    };
+    let (keys, args) = parse_keys_and_args(args, name)?;
+
+    Ok(Command::EvalSha {
+        sha1: sha1.to_ascii_lowercase(),
+        keys,
+        args,
+        read_only,
+    })
+}
+
+//splits what follows the script in KEYS and ARGV
+fn parse_keys_and_args(
+    args: &[String],
+    cmd: &str,
+) -> Result<(Vec<String>, Vec<String>), io::Error> {
+    let Some((numkeys, args)) = args.split_first() else {
+        return Err(wrong_arity(cmd));
+    };
+
+    let numkeys = numkeys
+        .parse::<i64>()
+        .map_err(|_| io::Error::other("ERR value is not an integer or out of range"))?;
+    if numkeys < 0 {
+        return Err(io::Error::other("ERR Number of keys can't be negative"));
+    }
+    if numkeys as usize > args.len() {
+        return Err(io::Error::other(
+            "ERR Number of keys can't be greater than number of args",
+        ));
+    }
+
+    let (keys, args) = args.split_at(numkeys as usize);
+    Ok((keys.to_vec(), args.to_vec()))
+}
+
+// LOAD <script> | EXISTS <sha1> [<sha1> ...] | FLUSH [ASYNC | SYNC] | KILL
+pub(super) fn parse_script_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "SCRIPT")?;
+    let Some((subcommand, args)) = args.split_first() else {
+        return Err(wrong_arity("SCRIPT"));
+    };
+
+    let subcommand = match (subcommand.to_ascii_uppercase().as_str(), args) {
+        ("LOAD", [script]) => ScriptSubcommand::Load {
+            script: script.clone(),
+        },
+        ("EXISTS", sha1s) if !sha1s.is_empty() => ScriptSubcommand::Exists {
+            sha1s: sha1s.iter().map(|sha1| sha1.to_ascii_lowercase()).collect(),
+        },
+        ("FLUSH", []) => ScriptSubcommand::Flush,
+        ("FLUSH", [mode])
+            if mode.eq_ignore_ascii_case("ASYNC") || mode.eq_ignore_ascii_case("SYNC") =>
+        {
+            ScriptSubcommand::Flush
+        }
+        ("FLUSH", [_]) => {
+            return Err(io::Error::other(
+                "ERR SCRIPT FLUSH only support SYNC|ASYNC option",
+            ));
+        }
+        ("KILL", []) => ScriptSubcommand::Kill,
+        (name @ ("LOAD" | "EXISTS" | "FLUSH" | "KILL"), _) => {
+            return Err(wrong_arity(&format!("SCRIPT|{name}")));
+        }
+        _ => {
+            return Err(io::Error::other(format!(
+                "ERR unknown subcommand '{subcommand}'. Try SCRIPT HELP."
+            )));
+        }
+    };
+
+    Ok(Command::Script { subcommand })
+}
+
+// <function> <numkeys> [<key> ...] [<arg> ...]
+pub(super) fn parse_fcall_cmd(
+    elements: &[RespType],
+    read_only: bool,
+) -> Result<Command, io::Error> {
+    let name = if read_only { "FCALL_RO" } else { "FCALL" };
+    let args = string_args(elements, name)?;
+    let Some((function, args)) = args.split_first() else {
+        return Err(wrong_arity(name));
+    };
+    let (keys, args) = parse_keys_and_args(args, name)?;
+
+    Ok(Command::FCall {
+        function: function.clone(),
+        keys,
+        args,
+        read_only,
+    })
+}
+
+// LOAD [REPLACE] <code> | DELETE <library> | LIST [LIBRARYNAME <pattern>] [WITHCODE] | DUMP |
+// RESTORE <payload> [FLUSH | APPEND | REPLACE] | FLUSH [ASYNC | SYNC] | KILL
+pub(super) fn parse_function_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    //the payload of RESTORE is binary, it is the only argument not read as a string
+    if let Some(RespType::BulkString { data }) = elements.get(1)
+        && data.eq_ignore_ascii_case(b"RESTORE")
+    {
+        return parse_function_restore(elements);
+    }
+
+    let args = string_args(elements, "FUNCTION")?;
+    let Some((subcommand, args)) = args.split_first() else {
+        return Err(wrong_arity("FUNCTION"));
+    };
+
+    let subcommand = match (subcommand.to_ascii_uppercase().as_str(), args) {
+        ("LOAD", [options @ .., code]) => {
+            let mut replace = false;
+            for option in options {
+                if option.eq_ignore_ascii_case("REPLACE") {
+                    replace = true;
+                } else {
+                    return Err(io::Error::other(format!(
+                        "ERR Unknown option given: {option}"
+                    )));
+                }
+            }
+            FunctionSubcommand::Load {
+                code: code.clone(),
+                replace,
+            }
+        }
+        ("DELETE", [library]) => FunctionSubcommand::Delete {
+            library: library.clone(),
+        },
+        ("LIST", options) => {
+            let mut pattern = None;
+            let mut with_code = false;
+            let mut options = options.iter();
+            while let Some(option) = options.next() {
+                match option.to_ascii_uppercase().as_str() {
+                    "WITHCODE" => with_code = true,
+                    "LIBRARYNAME" => match options.next() {
+                        Some(library) => pattern = Some(library.clone()),
+                        None => {
+                            return Err(io::Error::other(
+                                "ERR library name argument was not given",
+                            ));
+                        }
+                    },
+                    _ => {
+                        return Err(io::Error::other(format!("ERR Unknown argument {option}")));
+                    }
+                }
+            }
+            FunctionSubcommand::List { pattern, with_code }
+        }
+        ("DUMP", []) => FunctionSubcommand::Dump,
+        ("FLUSH", []) => FunctionSubcommand::Flush,
+        ("FLUSH", [mode])
+            if mode.eq_ignore_ascii_case("ASYNC") || mode.eq_ignore_ascii_case("SYNC") =>
+        {
+            FunctionSubcommand::Flush
+        }
+        ("FLUSH", [_]) => {
+            return Err(io::Error::other(
+                "ERR FUNCTION FLUSH only supports SYNC|ASYNC option",
+            ));
+        }
+        ("KILL", []) => FunctionSubcommand::Kill,
+        (name @ ("LOAD" | "DELETE" | "DUMP" | "FLUSH" | "KILL"), _) => {
+            return Err(wrong_arity(&format!("FUNCTION|{name}")));
+        }
+        _ => {
+            return Err(io::Error::other(format!(
+                "ERR unknown subcommand '{subcommand}'. Try FUNCTION HELP."
+            )));
+        }
+    };
+
+    Ok(Command::Function { subcommand })
+}
+
+// RESTORE <payload> [FLUSH | APPEND | REPLACE]
+fn parse_function_restore(elements: &[RespType]) -> Result<Command, io::Error> {
+    let Some(RespType::BulkString { data: payload }) = elements.get(2) else {
+        return Err(wrong_arity("FUNCTION|RESTORE"));
+    };
+
+    let policy = match string_args(&elements[2..], "FUNCTION")?.as_slice() {
+        [] => RestorePolicy::Append,
+        [policy] => match policy.to_ascii_uppercase().as_str() {
+            "APPEND" => RestorePolicy::Append,
+            "REPLACE" => RestorePolicy::Replace,
+            "FLUSH" => RestorePolicy::Flush,
+            _ => {
+                return Err(io::Error::other(
+                    "ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE.",
+                ));
+            }
+        },
+        _ => return Err(wrong_arity("FUNCTION|RESTORE")),
+    };
+
+    Ok(Command::Function {
+        subcommand: FunctionSubcommand::Restore {
+            payload: payload.clone(),
+            policy,
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        parse_eval_cmd, parse_evalsha_cmd, parse_fcall_cmd, parse_function_cmd, parse_script_cmd,
+    };
+    use crate::{
+        command::{Command, FunctionSubcommand, RestorePolicy, ScriptSubcommand, bulk_strings},
+        resp::RespType,
+    };
+
+    #[test]
+    fn test_parse_eval_cmd() {
+        let cmd = parse_eval_cmd(
+            &bulk_strings(&["EVAL", "return 1", "2", "k1", "k2", "a1"]),
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            cmd,
+            Command::Eval {
+                script: "return 1".into(),
+                keys: vec!["k1".into(), "k2".into()],
+                args: vec!["a1".into()],
+                read_only: false,
+            }
+        );
+
+        let cmd = parse_evalsha_cmd(&bulk_strings(&["EVALSHA_RO", "ABC", "0"]), true).unwrap();
+        assert_eq!(
+            cmd,
+            Command::EvalSha {
+                sha1: "abc".into(),
+                keys: vec![],
+                args: vec![],
+                read_only: true,
+            }
+        );
+
+        let err =
+            parse_eval_cmd(&bulk_strings(&["EVAL", "return 1", "2", "k1"]), false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR Number of keys can't be greater than number of args"
+        );
+
+        let err = parse_eval_cmd(&bulk_strings(&["EVAL", "return 1", "-1"]), false).unwrap_err();
+        assert_eq!(err.to_string(), "ERR Number of keys can't be negative");
+
+        let err = parse_eval_cmd(&bulk_strings(&["EVAL_RO", "return 1"]), true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'eval_ro' command"
+        );
+    }
+
+    #[test]
+    fn test_parse_script_cmd() {
+        let cmd = parse_script_cmd(&bulk_strings(&["SCRIPT", "exists", "A", "b"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Script {
+                subcommand: ScriptSubcommand::Exists {
+                    sha1s: vec!["a".into(), "b".into()]
+                }
+            }
+        );
+
+        let cmd = parse_script_cmd(&bulk_strings(&["SCRIPT", "FLUSH", "async"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Script {
+                subcommand: ScriptSubcommand::Flush
+            }
+        );
+
+        let err = parse_script_cmd(&bulk_strings(&["SCRIPT", "KILL", "now"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'script|kill' command"
+        );
+
+        let err = parse_script_cmd(&bulk_strings(&["SCRIPT", "foo"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR unknown subcommand 'foo'. Try SCRIPT HELP."
+        );
+    }
+
+    #[test]
+    fn test_parse_function_cmd() {
+        let cmd = parse_fcall_cmd(&bulk_strings(&["FCALL_RO", "f", "1", "k", "a"]), true).unwrap();
+        assert_eq!(
+            cmd,
+            Command::FCall {
+                function: "f".into(),
+                keys: vec!["k".into()],
+                args: vec!["a".into()],
+                read_only: true,
+            }
+        );
+
+        let cmd =
+            parse_function_cmd(&bulk_strings(&["FUNCTION", "load", "replace", "code"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Function {
+                subcommand: FunctionSubcommand::Load {
+                    code: "code".into(),
+                    replace: true
+                }
+            }
+        );
+
+        let cmd = parse_function_cmd(&bulk_strings(&[
+            "FUNCTION",
+            "LIST",
+            "WITHCODE",
+            "libraryname",
+            "my*",
+        ]))
+        .unwrap();
+        assert_eq!(
+            cmd,
+            Command::Function {
+                subcommand: FunctionSubcommand::List {
+                    pattern: Some("my*".into()),
+                    with_code: true
+                }
+            }
+        );
+
+        //the payload doesn't have to be utf8
+        let mut elements = bulk_strings(&["FUNCTION", "RESTORE"]);
+        elements.push(RespType::BulkString {
+            data: vec![0xf5, 0xff],
+        });
+        elements.extend(bulk_strings(&["replace"]));
+        assert_eq!(
+            parse_function_cmd(&elements).unwrap(),
+            Command::Function {
+                subcommand: FunctionSubcommand::Restore {
+                    payload: vec![0xf5, 0xff],
+                    policy: RestorePolicy::Replace
+                }
+            }
+        );
+
+        let err =
+            parse_function_cmd(&bulk_strings(&["FUNCTION", "RESTORE", "x", "KEEP"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE."
+        );
+
+        let err =
+            parse_function_cmd(&bulk_strings(&["FUNCTION", "LOAD", "NOW", "code"])).unwrap_err();
+        assert_eq!(err.to_string(), "ERR Unknown option given: NOW");
+
+        let err = parse_function_cmd(&bulk_strings(&["FUNCTION", "DELETE"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'function|delete' command"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::io;
+
+use crate::{
+    command::{Command, SentinelSubcommand, string_args, wrong_arity},
+    resp::RespType,
+};
+
+// MASTERS | MASTER <name> | REPLICAS <name> | SLAVES <name> | SENTINELS <name> |
+// GET-MASTER-ADDR-BY-NAME <name> | IS-MASTER-DOWN-BY-ADDR <ip> <port> <epoch> <run id> |
+// FAILOVER <name> | MONITOR <name> <ip> <port> <quorum> | REMOVE <name> |
+// SET <name> <option> <value> [<option> <value> ...] | MYID
+pub(super) fn parse_sentinel_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "SENTINEL")?;
+    let Some((subcommand, args)) = args.split_first() else {
+        return Err(wrong_arity("SENTINEL"));
+    };
+    let name = subcommand.to_ascii_uppercase();
+    let arity = || wrong_arity(&format!("SENTINEL|{name}"));
+
+    let subcommand = match (name.as_str(), args) {
+        ("MASTERS", []) => SentinelSubcommand::Masters,
+        ("MASTER", [master]) => SentinelSubcommand::Master {
+            name: master.clone(),
+        },
+        ("REPLICAS" | "SLAVES", [master]) => SentinelSubcommand::Replicas {
+            name: master.clone(),
+        },
+        ("SENTINELS", [master]) => SentinelSubcommand::Sentinels {
+            name: master.clone(),
+        },
+        ("GET-MASTER-ADDR-BY-NAME", [master]) => SentinelSubcommand::GetMasterAddrByName {
+            name: master.clone(),
+        },
+        ("IS-MASTER-DOWN-BY-ADDR", [ip, port, epoch, run_id]) => {
+            let not_an_integer = || io::Error::other("ERR value is not an integer or out of range");
+            SentinelSubcommand::IsMasterDownByAddr {
+                ip: ip.clone(),
+                port: port.parse().map_err(|_| not_an_integer())?,
+                current_epoch: epoch.parse().map_err(|_| not_an_integer())?,
+                run_id: run_id.clone(),
+            }
+        }
+        ("FAILOVER", [master]) => SentinelSubcommand::Failover {
+            name: master.clone(),
+        },
+        ("MONITOR", [master, ip, port, quorum]) => SentinelSubcommand::Monitor {
+            name: master.clone(),
+            ip: ip.clone(),
+            port: port.clone(),
+            quorum: quorum.clone(),
+        },
+        ("REMOVE", [master]) => SentinelSubcommand::Remove {
+            name: master.clone(),
+        },
+        ("SET", [master, options @ ..]) if !options.is_empty() && options.len() % 2 == 0 => {
+            SentinelSubcommand::Set {
+                name: master.clone(),
+                options: options
+                    .chunks_exact(2)
+                    .map(|option| (option[0].clone(), option[1].clone()))
+                    .collect(),
+            }
+        }
+        ("MYID", []) => SentinelSubcommand::MyId,
+        (
+            "MASTERS"
+            | "MASTER"
+            | "REPLICAS"
+            | "SLAVES"
+            | "SENTINELS"
+            | "GET-MASTER-ADDR-BY-NAME"
+            | "IS-MASTER-DOWN-BY-ADDR"
+            | "FAILOVER"
+            | "MONITOR"
+            | "REMOVE"
+            | "SET"
+            | "MYID",
+            _,
+        ) => return Err(arity()),
+        _ => {
+            return Err(io::Error::other(format!(
+                "ERR unknown subcommand '{subcommand}'. Try SENTINEL HELP."
+            )));
+        }
+    };
+
+    Ok(Command::Sentinel { subcommand })
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_sentinel_cmd;
+    use crate::command::{Command, SentinelSubcommand, bulk_strings};
+
+    #[test]
+    fn test_parse_sentinel() {
+        let cmd = parse_sentinel_cmd(&bulk_strings(&[
+            "SENTINEL",
+            "is-master-down-by-addr",
+            "127.0.0.1",
+            "6379",
+            "3",
+            "*",
+        ]))
+        .unwrap();
+        assert_eq!(
+            cmd,
+            Command::Sentinel {
+                subcommand: SentinelSubcommand::IsMasterDownByAddr {
+                    ip: "127.0.0.1".into(),
+                    port: 6379,
+                    current_epoch: 3,
+                    run_id: "*".into(),
+                }
+            }
+        );
+
+        let cmd = parse_sentinel_cmd(&bulk_strings(&["SENTINEL", "slaves", "m"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Sentinel {
+                subcommand: SentinelSubcommand::Replicas { name: "m".into() }
+            }
+        );
+
+        for (args, error) in [
+            (
+                &["SENTINEL", "SET", "m", "quorum"][..],
+                "ERR wrong number of arguments for 'sentinel|set' command",
+            ),
+            (
+                &["SENTINEL", "bogus"],
+                "ERR unknown subcommand 'bogus'. Try SENTINEL HELP.",
+            ),
+        ] {
+            let err = parse_sentinel_cmd(&bulk_strings(args)).unwrap_err();
+            assert_eq!(err.to_string(), error);
+        }
+    }
+}
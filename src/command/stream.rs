@@ -0,0 +1,926 @@
+use std::{io, time::Duration};
+
+use crate::{
+    command::{
+        Command, StreamTrim, TrimStrategy, XClaimOptions, XGroupId, XGroupSubcommand,
+        XInfoSubcommand, XPendingRange, XReadGroupId, XReadId, string_args, wrong_arity,
+    },
+    redis::stream::StreamId,
+    resp::RespType,
+};
+
+#[derive(Clone, Copy)]
+enum BoundSide {
+    Start,
+    End,
+}
+
+/// Parses one end of an XRANGE interval: `-`, `+`, complete or incomplete ids (`<ms>` stands for
+/// `<ms>-0` as a start and `<ms>-<max seq>` as an end) and exclusive ids prefixed by `(`.
+fn parse_range_bound(raw: &str, side: BoundSide) -> Result<StreamId, io::Error> {
+    let invalid_interval = || {
+        io::Error::other(match side {
+            BoundSide::Start => "ERR invalid start ID for the interval",
+            BoundSide::End => "ERR invalid end ID for the interval",
+        })
+    };
+
+    match raw {
+        "-" => return Ok(StreamId::MIN),
+        "+" => return Ok(StreamId::MAX),
+        _ => {}
+    }
+
+    let (exclusive, raw) = match raw.strip_prefix('(') {
+        Some(raw) => (true, raw),
+        None => (false, raw),
+    };
+
+    let id = match (raw.contains('-'), side) {
+        (true, _) | (false, BoundSide::Start) => raw.parse::<StreamId>()?,
+        (false, BoundSide::End) => StreamId::new(raw.parse::<StreamId>()?.ms, u64::MAX),
+    };
+
+    match (exclusive, side) {
+        (false, _) => Ok(id),
+        (true, BoundSide::Start) => id.next().ok_or_else(invalid_interval),
+        (true, BoundSide::End) => id.prev().ok_or_else(invalid_interval),
+    }
+}
+
+// <key> <first bound> <second bound> [COUNT <count>]
+fn parse_range_args(
+    elements: &[RespType],
+    cmd: &str,
+    first: BoundSide,
+) -> Result<(String, StreamId, StreamId, Option<usize>), io::Error> {
+    let args = string_args(elements, cmd)?;
+
+    let (key, first_bound, second_bound, options) = match args.as_slice() {
+        [key, first_bound, second_bound, options @ ..] => (key, first_bound, second_bound, options),
+        _ => return Err(wrong_arity(cmd)),
+    };
+
+    let second = match first {
+        BoundSide::Start => BoundSide::End,
+        BoundSide::End => BoundSide::Start,
+    };
+
+    let first_bound = parse_range_bound(first_bound, first)?;
+    let second_bound = parse_range_bound(second_bound, second)?;
+
+    let count = match options {
+        [] => None,
+        [opt, count] if opt.eq_ignore_ascii_case("COUNT") => {
+            Some(parse_integer(count)?.max(0) as usize)
+        }
+        _ => return Err(io::Error::other("ERR syntax error")),
+    };
+
+    Ok((key.clone(), first_bound, second_bound, count))
+}
+
+pub(super) fn parse_xrange_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let (key, start, end, count) = parse_range_args(elements, "XRANGE", BoundSide::Start)?;
+
+    Ok(Command::XRange {
+        key,
+        start,
+        end,
+        count,
+    })
+}
+
+pub(super) fn parse_xrevrange_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let (key, end, start, count) = parse_range_args(elements, "XREVRANGE", BoundSide::End)?;
+
+    Ok(Command::XRevRange {
+        key,
+        end,
+        start,
+        count,
+    })
+}
+
+pub(super) fn parse_xlen_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    match string_args(elements, "XLEN")?.as_slice() {
+        [key] => Ok(Command::XLen { key: key.clone() }),
+        _ => Err(wrong_arity("XLEN")),
+    }
+}
+
+struct ReadOptions<'a> {
+    count: Option<usize>,
+    block: Option<Duration>,
+    noack: bool,
+    keys: &'a [String],
+    ids: &'a [String],
+}
+
+// [COUNT <count>] [BLOCK <milliseconds>] [NOACK] STREAMS <key> [<key> ...] <id> [<id> ...]
+fn parse_read_options<'a>(
+    args: &'a [String],
+    cmd: &str,
+    allow_noack: bool,
+) -> Result<ReadOptions<'a>, io::Error> {
+    let mut count = None;
+    let mut block = None;
+    let mut noack = false;
+    let mut idx = 0;
+
+    let streams = loop {
+        match args.get(idx..) {
+            Some([opt, count_arg, ..]) if opt.eq_ignore_ascii_case("COUNT") => {
+                count = Some(parse_integer(count_arg)?.max(0) as usize);
+                idx += 2;
+            }
+            Some([opt, block_arg, ..]) if opt.eq_ignore_ascii_case("BLOCK") => {
+                let ms = parse_integer(block_arg)?;
+                if ms < 0 {
+                    return Err(io::Error::other("ERR timeout is negative"));
+                }
+
+                block = Some(Duration::from_millis(ms as u64));
+                idx += 2;
+            }
+            Some([opt, ..]) if allow_noack && opt.eq_ignore_ascii_case("NOACK") => {
+                noack = true;
+                idx += 1;
+            }
+            Some([opt, streams @ ..]) if opt.eq_ignore_ascii_case("STREAMS") => break streams,
+            Some([]) | None => return Err(wrong_arity(cmd)),
+            Some(_) => return Err(io::Error::other("ERR syntax error")),
+        }
+    };
+
+    if streams.is_empty() || streams.len() % 2 != 0 {
+        return Err(io::Error::other(format!(
+            "ERR Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+            cmd.to_ascii_lowercase()
+        )));
+    }
+
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+
+    Ok(ReadOptions {
+        count,
+        block,
+        noack,
+        keys,
+        ids,
+    })
+}
+
+// [COUNT <count>] [BLOCK <milliseconds>] STREAMS <key> [<key> ...] <id> [<id> ...]
+pub(super) fn parse_xread_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "XREAD")?;
+    let options = parse_read_options(&args, "XREAD", false)?;
+
+    let ids = options
+        .ids
+        .iter()
+        .map(|id| match id.as_str() {
+            "$" => Ok(XReadId::NewEntries),
+            "+" => Ok(XReadId::LastEntry),
+            _ => id.parse::<StreamId>().map(|id| XReadId::After { id }),
+        })
+        .collect::<Result<Vec<XReadId>, io::Error>>()?;
+
+    Ok(Command::XRead {
+        count: options.count,
+        block: options.block,
+        keys: options.keys.to_vec(),
+        ids,
+    })
+}
+
+// GROUP <group> <consumer> [COUNT <count>] [BLOCK <milliseconds>] [NOACK] STREAMS <key> ... <id> ...
+pub(super) fn parse_xreadgroup_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "XREADGROUP")?;
+
+    let (group, consumer, rest) = match args.as_slice() {
+        [opt, group, consumer, rest @ ..] if opt.eq_ignore_ascii_case("GROUP") => {
+            (group, consumer, rest)
+        }
+        [_, _, _, ..] => {
+            return Err(io::Error::other("ERR Missing GROUP option for XREADGROUP"));
+        }
+        _ => return Err(wrong_arity("XREADGROUP")),
+    };
+
+    let options = parse_read_options(rest, "XREADGROUP", true)?;
+
+    let ids = options
+        .ids
+        .iter()
+        .map(|id| match id.as_str() {
+            ">" => Ok(XReadGroupId::Undelivered),
+            _ => id
+                .parse::<StreamId>()
+                .map(|after| XReadGroupId::Pending { after }),
+        })
+        .collect::<Result<Vec<XReadGroupId>, io::Error>>()?;
+
+    Ok(Command::XReadGroup {
+        group: group.clone(),
+        consumer: consumer.clone(),
+        count: options.count,
+        block: options.block,
+        noack: options.noack,
+        keys: options.keys.to_vec(),
+        ids,
+    })
+}
+
+fn parse_group_id(raw: &str) -> Result<XGroupId, io::Error> {
+    match raw {
+        "$" => Ok(XGroupId::LastEntry),
+        _ => raw.parse::<StreamId>().map(|id| XGroupId::Id { id }),
+    }
+}
+
+// [MKSTREAM] [ENTRIESREAD <entries-read>]
+fn parse_group_options(
+    options: &[String],
+    allow_mkstream: bool,
+) -> Result<(bool, Option<u64>), io::Error> {
+    let mut mkstream = false;
+    let mut entries_read = None;
+    let mut idx = 0;
+
+    while let Some(opt) = options.get(idx) {
+        match (opt.to_ascii_uppercase().as_str(), options.get(idx + 1)) {
+            ("MKSTREAM", _) if allow_mkstream => {
+                mkstream = true;
+                idx += 1;
+            }
+            ("ENTRIESREAD", Some(read)) => {
+                let read = parse_integer(read)?;
+                if read < 0 && read != -1 {
+                    return Err(io::Error::other(
+                        "ERR value for ENTRIESREAD must be positive or -1",
+                    ));
+                }
+
+                entries_read = (read >= 0).then_some(read as u64);
+                idx += 2;
+            }
+            _ => return Err(io::Error::other("ERR syntax error")),
+        }
+    }
+
+    Ok((mkstream, entries_read))
+}
+
+pub(super) fn parse_xgroup_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "XGROUP")?;
+
+    let Some(subcommand) = args.first() else {
+        return Err(wrong_arity("XGROUP"));
+    };
+
+    let unknown_subcommand = || {
+        io::Error::other(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try XGROUP HELP."
+        ))
+    };
+
+    let subcommand = match (subcommand.to_ascii_uppercase().as_str(), &args[1..]) {
+        ("CREATE", [key, group, id, options @ ..]) => {
+            let (mkstream, entries_read) = parse_group_options(options, true)?;
+
+            XGroupSubcommand::Create {
+                key: key.clone(),
+                group: group.clone(),
+                id: parse_group_id(id)?,
+                mkstream,
+                entries_read,
+            }
+        }
+        ("SETID", [key, group, id, options @ ..]) => {
+            let (_, entries_read) = parse_group_options(options, false)?;
+
+            XGroupSubcommand::SetId {
+                key: key.clone(),
+                group: group.clone(),
+                id: parse_group_id(id)?,
+                entries_read,
+            }
+        }
+        ("DESTROY", [key, group]) => XGroupSubcommand::Destroy {
+            key: key.clone(),
+            group: group.clone(),
+        },
+        ("CREATECONSUMER", [key, group, consumer]) => XGroupSubcommand::CreateConsumer {
+            key: key.clone(),
+            group: group.clone(),
+            consumer: consumer.clone(),
+        },
+        ("DELCONSUMER", [key, group, consumer]) => XGroupSubcommand::DelConsumer {
+            key: key.clone(),
+            group: group.clone(),
+            consumer: consumer.clone(),
+        },
+        _ => return Err(unknown_subcommand()),
+    };
+
+    Ok(Command::XGroup { subcommand })
+}
+
+// <key> <group> <id> [<id> ...]
+pub(super) fn parse_xack_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    match string_args(elements, "XACK")?.as_slice() {
+        [key, group, ids @ ..] if !ids.is_empty() => Ok(Command::XAck {
+            key: key.clone(),
+            group: group.clone(),
+            ids: ids
+                .iter()
+                .map(|id| id.parse::<StreamId>())
+                .collect::<Result<Vec<StreamId>, io::Error>>()?,
+        }),
+        _ => Err(wrong_arity("XACK")),
+    }
+}
+
+// <key> <group> [[IDLE <min-idle-time>] <start> <end> <count> [<consumer>]]
+pub(super) fn parse_xpending_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "XPENDING")?;
+
+    let (key, group, options) = match args.as_slice() {
+        [key, group, options @ ..] => (key.clone(), group.clone(), options),
+        _ => return Err(wrong_arity("XPENDING")),
+    };
+
+    let (min_idle, options) = match options {
+        [opt, idle, rest @ ..] if opt.eq_ignore_ascii_case("IDLE") => {
+            (Some(parse_integer(idle)?.max(0) as u64), rest)
+        }
+        _ => (None, options),
+    };
+
+    let range = match options {
+        [] if min_idle.is_none() => None,
+        [start, end, count, consumer @ ..] if consumer.len() <= 1 => Some(XPendingRange {
+            min_idle,
+            start: parse_range_bound(start, BoundSide::Start)?,
+            end: parse_range_bound(end, BoundSide::End)?,
+            count: parse_integer(count)?.max(0) as usize,
+            consumer: consumer.first().cloned(),
+        }),
+        _ => return Err(io::Error::other("ERR syntax error")),
+    };
+
+    Ok(Command::XPending { key, group, range })
+}
+
+fn parse_min_idle(raw: &str) -> Result<u64, io::Error> {
+    raw.parse::<i64>()
+        .map(|idle| idle.max(0) as u64)
+        .map_err(|_| io::Error::other("ERR Invalid min-idle-time argument for XCLAIM"))
+}
+
+// <key> <group> <consumer> <min-idle-time> <id> [<id> ...] [IDLE <ms>] [TIME <unix-time-ms>]
+// [RETRYCOUNT <count>] [FORCE] [JUSTID] [LASTID <lastid>]
+pub(super) fn parse_xclaim_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "XCLAIM")?;
+
+    let (key, group, consumer, min_idle, rest) = match args.as_slice() {
+        [key, group, consumer, min_idle, rest @ ..] if !rest.is_empty() => {
+            (key, group, consumer, parse_min_idle(min_idle)?, rest)
+        }
+        _ => return Err(wrong_arity("XCLAIM")),
+    };
+
+    //ids come first, the options start at the first argument that is not a valid id
+    let ids: Vec<StreamId> = rest
+        .iter()
+        .map_while(|id| id.parse::<StreamId>().ok())
+        .collect();
+
+    if ids.is_empty() {
+        return Err(io::Error::other(
+            "ERR Invalid stream ID specified as stream command argument",
+        ));
+    }
+
+    let mut options = XClaimOptions::default();
+    let mut opts = rest[ids.len()..].iter();
+
+    while let Some(opt) = opts.next() {
+        let mut value = || {
+            opts.next()
+                .ok_or_else(|| io::Error::other("ERR syntax error"))
+                .and_then(|v| parse_integer(v))
+        };
+
+        match opt.to_ascii_uppercase().as_str() {
+            "IDLE" => options.idle = Some(value()?.max(0) as u64),
+            "TIME" => options.time = Some(value()?.max(0) as u64),
+            "RETRYCOUNT" => options.retry_count = Some(value()?.max(0) as u64),
+            "FORCE" => options.force = true,
+            "JUSTID" => options.justid = true,
+            "LASTID" => {
+                let last_id = opts
+                    .next()
+                    .ok_or_else(|| io::Error::other("ERR syntax error"))?;
+                options.last_id = Some(last_id.parse::<StreamId>()?);
+            }
+            _ => {
+                return Err(io::Error::other(format!(
+                    "ERR Unrecognized XCLAIM option '{opt}'"
+                )));
+            }
+        }
+    }
+
+    Ok(Command::XClaim {
+        key: key.clone(),
+        group: group.clone(),
+        consumer: consumer.clone(),
+        min_idle,
+        ids,
+        options,
+    })
+}
+
+// <key> <group> <consumer> <min-idle-time> <start> [COUNT <count>] [JUSTID]
+pub(super) fn parse_xautoclaim_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "XAUTOCLAIM")?;
+
+    let (key, group, consumer, min_idle, start, rest) = match args.as_slice() {
+        [key, group, consumer, min_idle, start, rest @ ..] => (
+            key,
+            group,
+            consumer,
+            parse_min_idle(min_idle)?,
+            parse_range_bound(start, BoundSide::Start)?,
+            rest,
+        ),
+        _ => return Err(wrong_arity("XAUTOCLAIM")),
+    };
+
+    let mut count = 100;
+    let mut justid = false;
+    let mut idx = 0;
+
+    while let Some(opt) = rest.get(idx) {
+        match (opt.to_ascii_uppercase().as_str(), rest.get(idx + 1)) {
+            ("COUNT", Some(raw)) => {
+                count = match parse_integer(raw)? {
+                    count if (1..=i64::MAX / 10).contains(&count) => count as usize,
+                    _ => {
+                        return Err(io::Error::other("ERR COUNT must be > 0"));
+                    }
+                };
+                idx += 2;
+            }
+            ("JUSTID", _) => {
+                justid = true;
+                idx += 1;
+            }
+            _ => return Err(io::Error::other("ERR syntax error")),
+        }
+    }
+
+    Ok(Command::XAutoClaim {
+        key: key.clone(),
+        group: group.clone(),
+        consumer: consumer.clone(),
+        min_idle,
+        start,
+        count,
+        justid,
+    })
+}
+
+/// Parses `<MAXLEN | MINID> [= | ~] <threshold> [LIMIT <count>]` at the beginning of args,
+/// returning the trimming options and the number of consumed arguments.
+pub(super) fn parse_stream_trim(args: &[String]) -> Result<(StreamTrim, usize), io::Error> {
+    let syntax_error = || io::Error::other("ERR syntax error");
+
+    let strategy = args.first().ok_or_else(syntax_error)?.to_ascii_uppercase();
+    let mut idx = 1;
+
+    let approx = match args.get(idx).map(|s| s.as_str()) {
+        Some("~") => {
+            idx += 1;
+            true
+        }
+        Some("=") => {
+            idx += 1;
+            false
+        }
+        _ => false,
+    };
+
+    let threshold = args.get(idx).ok_or_else(syntax_error)?;
+    idx += 1;
+
+    let strategy = match strategy.as_str() {
+        "MAXLEN" => match parse_integer(threshold)? {
+            len if len < 0 => {
+                return Err(io::Error::other("ERR The MAXLEN argument must be >= 0."));
+            }
+            len => TrimStrategy::MaxLen { len: len as u64 },
+        },
+        "MINID" => TrimStrategy::MinId {
+            id: threshold.parse::<StreamId>()?,
+        },
+        _ => return Err(syntax_error()),
+    };
+
+    let limit = match args.get(idx..) {
+        Some([opt, limit, ..]) if opt.eq_ignore_ascii_case("LIMIT") => {
+            if !approx {
+                return Err(io::Error::other(
+                    "ERR syntax error, LIMIT cannot be used without the special ~ option",
+                ));
+            }
+
+            idx += 2;
+            match parse_integer(limit)? {
+                limit if limit < 0 => {
+                    return Err(io::Error::other("ERR The LIMIT argument must be >= 0."));
+                }
+                limit => Some(limit as usize),
+            }
+        }
+        _ => None,
+    };
+
+    Ok((
+        StreamTrim {
+            strategy,
+            approx,
+            limit,
+        },
+        idx,
+    ))
+}
+
+// <key> <MAXLEN | MINID> [= | ~] <threshold> [LIMIT <count>]
+pub(super) fn parse_xtrim_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "XTRIM")?;
+
+    let (key, rest) = match args.as_slice() {
+        [key, rest @ ..] if rest.len() >= 2 => (key, rest),
+        _ => return Err(wrong_arity("XTRIM")),
+    };
+
+    let (trim, consumed) = parse_stream_trim(rest)?;
+    if consumed != rest.len() {
+        return Err(io::Error::other("ERR syntax error"));
+    }
+
+    Ok(Command::XTrim {
+        key: key.clone(),
+        trim,
+    })
+}
+
+// <key> <id> [<id> ...]
+pub(super) fn parse_xdel_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    match string_args(elements, "XDEL")?.as_slice() {
+        [key, ids @ ..] if !ids.is_empty() => Ok(Command::XDel {
+            key: key.clone(),
+            ids: ids
+                .iter()
+                .map(|id| id.parse::<StreamId>())
+                .collect::<Result<Vec<StreamId>, io::Error>>()?,
+        }),
+        _ => Err(wrong_arity("XDEL")),
+    }
+}
+
+// <key> <last-id> [ENTRIESADDED <entries-added>] [MAXDELETEDID <max-deleted-id>]
+pub(super) fn parse_xsetid_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "XSETID")?;
+
+    let (key, last_id, options) = match args.as_slice() {
+        [key, last_id, options @ ..] => (key, last_id.parse::<StreamId>()?, options),
+        _ => return Err(wrong_arity("XSETID")),
+    };
+
+    let mut entries_added = None;
+    let mut max_deleted_id = None;
+
+    for option in options.chunks(2) {
+        match option {
+            [opt, value] if opt.eq_ignore_ascii_case("ENTRIESADDED") => {
+                entries_added = match parse_integer(value)? {
+                    added if added < 0 => {
+                        return Err(io::Error::other("ERR entries_added must be positive"));
+                    }
+                    added => Some(added as u64),
+                };
+            }
+            [opt, value] if opt.eq_ignore_ascii_case("MAXDELETEDID") => {
+                max_deleted_id = Some(value.parse::<StreamId>()?);
+            }
+            _ => return Err(io::Error::other("ERR syntax error")),
+        }
+    }
+
+    Ok(Command::XSetId {
+        key: key.clone(),
+        last_id,
+        entries_added,
+        max_deleted_id,
+    })
+}
+
+// STREAM <key> [FULL [COUNT <count>]] | GROUPS <key> | CONSUMERS <key> <group>
+pub(super) fn parse_xinfo_cmd(elements: &[RespType]) -> Result<Command, io::Error> {
+    let args = string_args(elements, "XINFO")?;
+
+    let Some(subcommand) = args.first() else {
+        return Err(wrong_arity("XINFO"));
+    };
+
+    let subcommand = match (subcommand.to_ascii_uppercase().as_str(), &args[1..]) {
+        ("STREAM", [key, options @ ..]) => {
+            let full = match options {
+                [] => None,
+                [full] if full.eq_ignore_ascii_case("FULL") => Some(10),
+                [full, opt, count]
+                    if full.eq_ignore_ascii_case("FULL") && opt.eq_ignore_ascii_case("COUNT") =>
+                {
+                    Some(parse_integer(count)?.max(0) as usize)
+                }
+                _ => return Err(io::Error::other("ERR syntax error")),
+            };
+
+            XInfoSubcommand::Stream {
+                key: key.clone(),
+                full,
+            }
+        }
+        ("GROUPS", [key]) => XInfoSubcommand::Groups { key: key.clone() },
+        ("CONSUMERS", [key, group]) => XInfoSubcommand::Consumers {
+            key: key.clone(),
+            group: group.clone(),
+        },
+        _ => {
+            return Err(io::Error::other(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try XINFO HELP."
+            )));
+        }
+    };
+
+    Ok(Command::XInfo { subcommand })
+}
+
+fn parse_integer(raw: &str) -> Result<i64, io::Error> {
+    raw.parse::<i64>()
+        .map_err(|_| io::Error::other("ERR value is not an integer or out of range"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        command::{
+            Command, XClaimOptions, XGroupId, XGroupSubcommand, XPendingRange, XReadGroupId,
+            XReadId, bulk_strings,
+        },
+        redis::stream::StreamId,
+    };
+
+    #[test]
+    fn test_parse_xrange_bounds() {
+        let parsed = super::parse_xrange_cmd(&bulk_strings(&["XRANGE", "key", "-", "+"]));
+        assert_eq!(
+            parsed.unwrap(),
+            Command::XRange {
+                key: "key".into(),
+                start: StreamId::MIN,
+                end: StreamId::MAX,
+                count: None,
+            }
+        );
+
+        let parsed =
+            super::parse_xrange_cmd(&bulk_strings(&["XRANGE", "key", "(5-1", "7", "count", "2"]));
+        assert_eq!(
+            parsed.unwrap(),
+            Command::XRange {
+                key: "key".into(),
+                start: StreamId::new(5, 2),
+                end: StreamId::new(7, u64::MAX),
+                count: Some(2),
+            }
+        );
+
+        let parsed = super::parse_xrevrange_cmd(&bulk_strings(&["XREVRANGE", "key", "(6", "5"]));
+        assert_eq!(
+            parsed.unwrap(),
+            Command::XRevRange {
+                key: "key".into(),
+                end: StreamId::new(6, u64::MAX - 1),
+                start: StreamId::new(5, 0),
+                count: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_xrange_invalid() {
+        let parsed = super::parse_xrange_cmd(&bulk_strings(&["XRANGE", "key", "(-", "+"]));
+        assert!(parsed.is_err());
+
+        let parsed = super::parse_xrange_cmd(&bulk_strings(&[
+            "XRANGE",
+            "key",
+            "-",
+            "(18446744073709551615-18446744073709551615",
+        ]));
+        assert!(parsed.is_ok());
+
+        let parsed = super::parse_xrange_cmd(&bulk_strings(&["XRANGE", "key", "(0-0", "+"]));
+        assert!(parsed.is_ok());
+
+        let parsed = super::parse_xrange_cmd(&bulk_strings(&["XRANGE", "key", "-", "(0-0"]));
+        assert!(parsed.is_err_and(|err| err.to_string() == "ERR invalid end ID for the interval"));
+
+        let parsed = super::parse_xrange_cmd(&bulk_strings(&["XRANGE", "key", "-", "+", "LIMIT"]));
+        assert!(parsed.is_err_and(|err| err.to_string() == "ERR syntax error"));
+
+        let parsed = super::parse_xrange_cmd(&bulk_strings(&["XRANGE", "key", "-"]));
+        assert!(parsed.is_err_and(|err| {
+            err.to_string() == "ERR wrong number of arguments for 'xrange' command"
+        }));
+    }
+
+    #[test]
+    fn test_parse_xread() {
+        let parsed = super::parse_xread_cmd(&bulk_strings(&[
+            "XREAD", "COUNT", "2", "BLOCK", "0", "streams", "a", "b", "c", "$", "+", "5",
+        ]));
+        assert_eq!(
+            parsed.unwrap(),
+            Command::XRead {
+                count: Some(2),
+                block: Some(std::time::Duration::ZERO),
+                keys: vec!["a".into(), "b".into(), "c".into()],
+                ids: vec![
+                    XReadId::NewEntries,
+                    XReadId::LastEntry,
+                    XReadId::After {
+                        id: StreamId::new(5, 0)
+                    },
+                ],
+            }
+        );
+
+        let parsed = super::parse_xread_cmd(&bulk_strings(&["XREAD", "STREAMS", "a", "b", "$"]));
+        assert!(parsed.is_err_and(|err| err.to_string().starts_with("ERR Unbalanced 'xread'")));
+
+        let parsed = super::parse_xread_cmd(&bulk_strings(&["XREAD", "BLOCK", "-1", "STREAMS"]));
+        assert!(parsed.is_err_and(|err| err.to_string() == "ERR timeout is negative"));
+    }
+
+    #[test]
+    fn test_parse_xgroup() {
+        let parsed = super::parse_xgroup_cmd(&bulk_strings(&[
+            "XGROUP",
+            "create",
+            "key",
+            "group",
+            "$",
+            "MKSTREAM",
+            "ENTRIESREAD",
+            "3",
+        ]));
+        assert_eq!(
+            parsed.unwrap(),
+            Command::XGroup {
+                subcommand: XGroupSubcommand::Create {
+                    key: "key".into(),
+                    group: "group".into(),
+                    id: XGroupId::LastEntry,
+                    mkstream: true,
+                    entries_read: Some(3),
+                }
+            }
+        );
+
+        let parsed = super::parse_xgroup_cmd(&bulk_strings(&[
+            "XGROUP", "SETID", "key", "group", "0", "MKSTREAM",
+        ]));
+        assert!(parsed.is_err_and(|err| err.to_string() == "ERR syntax error"));
+
+        let parsed = super::parse_xgroup_cmd(&bulk_strings(&["XGROUP", "DESTROY", "key"]));
+        assert!(parsed.is_err_and(|err| err.to_string().starts_with("ERR unknown subcommand")));
+    }
+
+    #[test]
+    fn test_parse_xreadgroup() {
+        let parsed = super::parse_xreadgroup_cmd(&bulk_strings(&[
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "c",
+            "NOACK",
+            "COUNT",
+            "1",
+            "STREAMS",
+            "a",
+            "b",
+            ">",
+            "0",
+        ]));
+        assert_eq!(
+            parsed.unwrap(),
+            Command::XReadGroup {
+                group: "g".into(),
+                consumer: "c".into(),
+                count: Some(1),
+                block: None,
+                noack: true,
+                keys: vec!["a".into(), "b".into()],
+                ids: vec![
+                    XReadGroupId::Undelivered,
+                    XReadGroupId::Pending {
+                        after: StreamId::MIN
+                    }
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_xclaim() {
+        let parsed = super::parse_xclaim_cmd(&bulk_strings(&[
+            "XCLAIM", "key", "g", "c", "10", "1-1", "2-2", "IDLE", "5", "FORCE", "JUSTID",
+        ]));
+        assert_eq!(
+            parsed.unwrap(),
+            Command::XClaim {
+                key: "key".into(),
+                group: "g".into(),
+                consumer: "c".into(),
+                min_idle: 10,
+                ids: vec![StreamId::new(1, 1), StreamId::new(2, 2)],
+                options: XClaimOptions {
+                    idle: Some(5),
+                    force: true,
+                    justid: true,
+                    ..Default::default()
+                },
+            }
+        );
+
+        let parsed = super::parse_xpending_cmd(&bulk_strings(&[
+            "XPENDING", "key", "g", "IDLE", "10", "-", "+", "5", "c",
+        ]));
+        assert_eq!(
+            parsed.unwrap(),
+            Command::XPending {
+                key: "key".into(),
+                group: "g".into(),
+                range: Some(XPendingRange {
+                    min_idle: Some(10),
+                    start: StreamId::MIN,
+                    end: StreamId::MAX,
+                    count: 5,
+                    consumer: Some("c".into()),
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_xtrim_and_xinfo() {
+        let parsed = super::parse_xtrim_cmd(&bulk_strings(&["XTRIM", "key", "MINID", "=", "5-1"]));
+        assert_eq!(
+            parsed.unwrap(),
+            Command::XTrim {
+                key: "key".into(),
+                trim: crate::command::StreamTrim {
+                    strategy: crate::command::TrimStrategy::MinId {
+                        id: StreamId::new(5, 1)
+                    },
+                    approx: false,
+                    limit: None,
+                },
+            }
+        );
+
+        let parsed = super::parse_xtrim_cmd(&bulk_strings(&["XTRIM", "key", "MAXLEN", "-1"]));
+        assert!(
+            parsed.is_err_and(|err| err.to_string() == "ERR The MAXLEN argument must be >= 0.")
+        );
+
+        let parsed = super::parse_xinfo_cmd(&bulk_strings(&[
+            "XINFO", "stream", "key", "FULL", "COUNT", "0",
+        ]));
+        assert_eq!(
+            parsed.unwrap(),
+            Command::XInfo {
+                subcommand: crate::command::XInfoSubcommand::Stream {
+                    key: "key".into(),
+                    full: Some(0),
+                },
+            }
+        );
+    }
+}
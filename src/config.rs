@@ -0,0 +1,1203 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{glob::glob_match, redis::KeyspaceEvents};
+
+/// A setting that can be given in the configuration file or on the command line.
+struct Param {
+    name: &'static str,
+    kind: Kind,
+    default: &'static str,
+    //only read at startup, CONFIG SET refuses to change it
+    immutable: bool,
+}
+
+impl Param {
+    const fn new(name: &'static str, kind: Kind, default: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            default,
+            immutable: false,
+        }
+    }
+
+    const fn immutable(self) -> Self {
+        Self {
+            immutable: true,
+            ..self
+        }
+    }
+}
+
+enum Kind {
+    //yes or no
+    Bool,
+    Integer { min: i64, max: i64 },
+    //a number of bytes, optionally followed by a unit like 100mb
+    Memory,
+    String,
+    //one of the given names
+    Enum(&'static [&'static str]),
+    //whitespace separated words, given as one or more arguments
+    List,
+    //pairs of integers like the save rules, a list given over several directives at startup
+    Pairs,
+    //a host followed by a port, or nothing
+    Address,
+    //a string checked and normalized by the function
+    Custom(fn(&str) -> Result<String, String>),
+}
+
+const PARAMS: &[Param] = &[
+    Param::new("port", Kind::Integer { min: 0, max: 65535 }, "6379").immutable(),
+    Param::new("bind", Kind::List, "127.0.0.1 -::1").immutable(),
+    Param::new("protected-mode", Kind::Bool, "yes"),
+    Param::new("requirepass", Kind::String, ""),
+    Param::new("aclfile", Kind::String, "").immutable(),
+    Param::new("dir", Kind::String, "."),
+    Param::new("dbfilename", Kind::Custom(file_name), "dump.rdb"),
+    //<seconds> <changes> ...: snapshot once that many changes happened in that many seconds
+    Param::new("save", Kind::Pairs, "3600 1 300 100 60 10000"),
+    Param::new("appendonly", Kind::Bool, "no"),
+    //prefix of the names of the AOF files and of their manifest
+    Param::new(
+        "appendfilename",
+        Kind::Custom(aof_file_name),
+        "appendonly.aof",
+    ),
+    Param::new("appenddirname", Kind::Custom(aof_dir_name), "appendonlydir").immutable(),
+    Param::new(
+        "appendfsync",
+        Kind::Enum(&["always", "everysec", "no"]),
+        "everysec",
+    ),
+    //load the commands of an AOF cut in the middle of one instead of refusing to start
+    Param::new("aof-load-truncated", Kind::Bool, "yes"),
+    //rewrite the AOF once it grew by that percentage since the last rewrite, 0 to never
+    Param::new(
+        "auto-aof-rewrite-percentage",
+        Kind::Integer {
+            min: 0,
+            max: i32::MAX as i64,
+        },
+        "100",
+    ),
+    //AOFs smaller than that are not rewritten automatically
+    Param::new("auto-aof-rewrite-min-size", Kind::Memory, "64mb"),
+    //the master this server is a replica of, REPLICAOF changes it at runtime
+    Param::new("replicaof", Kind::Address, "").immutable(),
+    //the credentials used to authenticate with the master
+    Param::new("masteruser", Kind::String, ""),
+    Param::new("masterauth", Kind::String, ""),
+    Param::new("replica-read-only", Kind::Bool, "yes"),
+    //bytes of the replication stream kept for the replicas to resynchronize partially
+    Param::new("repl-backlog-size", Kind::Memory, "1mb"),
+    //seconds between the pings the master sends to its replicas
+    Param::new(
+        "repl-ping-replica-period",
+        Kind::Integer {
+            min: 1,
+            max: i32::MAX as i64,
+        },
+        "10",
+    ),
+    //seconds without hearing from the other side before a replication link is dropped
+    Param::new(
+        "repl-timeout",
+        Kind::Integer {
+            min: 1,
+            max: i32::MAX as i64,
+        },
+        "60",
+    ),
+    //serve a share of the hash slots as a node of a cluster
+    Param::new("cluster-enabled", Kind::Bool, "no").immutable(),
+    //where the node saves the state of the cluster, it is not meant to be edited
+    Param::new("cluster-config-file", Kind::String, "nodes.conf").immutable(),
+    //the port of the cluster bus, 0 for the one of the clients + 10000
+    Param::new("cluster-port", Kind::Integer { min: 0, max: 65535 }, "0").immutable(),
+    //milliseconds a node can stay unreachable, the handshakes of CLUSTER MEET time out after it
+    Param::new(
+        "cluster-node-timeout",
+        Kind::Integer {
+            min: 1,
+            max: i32::MAX as i64,
+        },
+        "15000",
+    ),
+    //keys are only served while every slot is
+    Param::new("cluster-require-full-coverage", Kind::Bool, "yes"),
+    Param::new("notify-keyspace-events", Kind::Custom(keyspace_events), ""),
+    //seconds a client can stay idle before being disconnected, 0 to never
+    Param::new(
+        "timeout",
+        Kind::Integer {
+            min: 0,
+            max: i32::MAX as i64,
+        },
+        "0",
+    ),
+    //times per second the server runs its periodic tasks
+    Param::new("hz", Kind::Integer { min: 1, max: 500 }, "10"),
+    //milliseconds a script runs before other clients are answered with BUSY
+    Param::new(
+        "busy-reply-threshold",
+        Kind::Integer {
+            min: 0,
+            max: i64::MAX,
+        },
+        "5000",
+    ),
+    //writes evict keys, or are refused, once the allocated memory exceeds it, 0 for no limit
+    Param::new("maxmemory", Kind::Memory, "0"),
+    //the LRU and LFU policies are refused, the access times and counts they need are not kept
+    Param::new(
+        "maxmemory-policy",
+        Kind::Enum(&[
+            "volatile-random",
+            "allkeys-random",
+            "volatile-ttl",
+            "noeviction",
+        ]),
+        "noeviction",
+    ),
+];
+
+//the line CONFIG REWRITE adds before the settings that were not in the file yet
+const REWRITE_SIGNATURE: &str = "# Generated by CONFIG REWRITE";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    List(Vec<String>),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Bool(true) => write!(f, "yes"),
+            Value::Bool(false) => write!(f, "no"),
+            Value::Integer(value) => write!(f, "{value}"),
+            Value::String(value) => write!(f, "{value}"),
+            Value::List(words) => write!(f, "{}", words.join(" ")),
+        }
+    }
+}
+
+/// The server settings: the defaults overridden by the configuration file and then by the
+/// command line, every value is checked against the type of its parameter.
+#[derive(Debug, Clone)]
+pub struct Config {
+    values: BTreeMap<&'static str, Value>,
+    //the file the configuration was read from, the one CONFIG REWRITE updates
+    file: Option<PathBuf>,
+    //started with --sentinel, to monitor masters instead of serving keys
+    sentinel_mode: bool,
+    //the sentinel directives, without the leading sentinel, the state the sentinel saves
+    sentinel: Vec<Vec<String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let values = PARAMS
+            .iter()
+            .map(|param| (param.name, default_value(param)))
+            .collect();
+
+        Self {
+            values,
+            file: None,
+            sentinel_mode: false,
+            sentinel: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the server arguments: an optional configuration file
+    /// followed by `--name value ...` options, which use the same syntax as its directives.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut config = Self::default();
+        let mut given = HashSet::new();
+
+        //a sentinel listens on another port by default, the file may still set it
+        if args.iter().any(|arg| arg == "--sentinel") {
+            config.sentinel_mode = true;
+            config.set("port", &["26379".into()])?;
+        }
+
+        let options = match args.first() {
+            Some(path) if !path.starts_with("--") => {
+                let path = std::path::absolute(path).map_err(|err| {
+                    format!("Fatal error, can't resolve config file '{path}': {err}")
+                })?;
+                config.load_file(&path, &mut given)?;
+                config.file = Some(path);
+                &args[1..]
+            }
+            _ => args,
+        };
+
+        let mut directives: Vec<Vec<String>> = vec![];
+        for arg in options {
+            match (arg.strip_prefix("--"), directives.last_mut()) {
+                (Some(name), _) => directives.push(vec![name.to_string()]),
+                (None, Some(directive)) => directive.push(arg.clone()),
+                (None, None) => {
+                    return Err(format!(
+                        "Invalid argument '{arg}', options are given as --<name> <value>"
+                    ));
+                }
+            }
+        }
+
+        for (number, directive) in directives.iter().enumerate() {
+            config.apply(directive, &mut given).map_err(|reason| {
+                format!(
+                    "Reading the command line, at option {}\n>>> '{}'\n{reason}",
+                    number + 1,
+                    directive.join(" ")
+                )
+            })?;
+        }
+
+        if !config.sentinel_mode && !config.sentinel.is_empty() {
+            return Err("sentinel directive while not in sentinel mode".into());
+        }
+        Ok(config)
+    }
+
+    /// Applies the directives of a configuration file, one per line.
+    fn load_file(&mut self, path: &Path, given: &mut HashSet<&'static str>) -> Result<(), String> {
+        let content = fs::read_to_string(path).map_err(|err| {
+            format!(
+                "Fatal error, can't open config file '{}': {err}",
+                path.display()
+            )
+        })?;
+
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            split_args(line)
+                .and_then(|directive| self.apply(&directive, given))
+                .map_err(|reason| {
+                    format!(
+                        "Reading the configuration file {}, at line {}\n>>> '{line}'\n{reason}",
+                        path.display(),
+                        number + 1
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+
+    //a directive is the parameter name followed by its arguments, the given parameters are the
+    //ones already set at startup, pairs add up to what they set instead of replacing it
+    fn apply(
+        &mut self,
+        directive: &[String],
+        given: &mut HashSet<&'static str>,
+    ) -> Result<(), String> {
+        let [name, args @ ..] = directive else {
+            return Ok(());
+        };
+        if let [path] = args
+            && name.eq_ignore_ascii_case("include")
+        {
+            return self.load_file(Path::new(path), given);
+        }
+        //alone it is the --sentinel switch, otherwise a part of the sentinel state
+        if name.eq_ignore_ascii_case("sentinel") {
+            match args {
+                [] => self.sentinel_mode = true,
+                args => self.sentinel.push(args.to_vec()),
+            }
+            return Ok(());
+        }
+
+        match find_param(name) {
+            Some(param) if matches!(param.kind, Kind::Pairs) && !given.insert(param.name) => {
+                let Value::List(mut words) = parse_value(param, args)? else {
+                    unreachable!("pairs are lists");
+                };
+                if let Some(Value::List(previous)) = self.values.get_mut(param.name) {
+                    previous.append(&mut words);
+                }
+                Ok(())
+            }
+            _ => self.set(name, args),
+        }
+    }
+
+    /// Sets a parameter, the value is left untouched if the arguments are not valid for it.
+    pub fn set(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        let param = find_param(name).ok_or("Bad directive or wrong number of arguments")?;
+
+        let value = parse_value(param, args)?;
+        self.values.insert(param.name, value);
+        Ok(())
+    }
+
+    /// CONFIG SET: changes every parameter or none of them, returns the names of the ones set.
+    pub fn set_all(
+        &mut self,
+        parameters: &[(String, String)],
+    ) -> Result<Vec<&'static str>, String> {
+        let mut values = self.values.clone();
+        let mut names = vec![];
+
+        for (name, arg) in parameters {
+            let Some(param) = find_param(name) else {
+                return Err(format!(
+                    "ERR Unknown option or number of arguments for CONFIG SET - '{name}'"
+                ));
+            };
+            let failure = |reason: &str| {
+                format!("ERR CONFIG SET failed (possibly related to argument '{name}') - {reason}")
+            };
+
+            if param.immutable {
+                return Err(failure("can't set immutable config"));
+            }
+            if names.contains(&param.name) {
+                return Err(failure("duplicate parameter"));
+            }
+
+            let value =
+                parse_value(param, std::slice::from_ref(arg)).map_err(|err| failure(&err))?;
+            values.insert(param.name, value);
+            names.push(param.name);
+        }
+
+        self.values = values;
+        Ok(names)
+    }
+
+    /// CONFIG GET: the parameters whose name matches one of the glob patterns, with their values.
+    pub fn get(&self, patterns: &[String]) -> Vec<(&'static str, String)> {
+        PARAMS
+            .iter()
+            .filter(|param| {
+                patterns.iter().any(|pattern| {
+                    glob_match(
+                        pattern.to_ascii_lowercase().as_bytes(),
+                        param.name.as_bytes(),
+                    )
+                })
+            })
+            .map(|param| (param.name, self.value(param.name).to_string()))
+            .collect()
+    }
+
+    /// The absolute path of the configuration file, None if the server was started without one.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// CONFIG REWRITE: updates the configuration file with the current settings. Comments and
+    /// the order of the directives are kept, the settings missing from the file are appended
+    /// unless they have their default value.
+    pub fn rewrite(&self) -> Result<(), String> {
+        let Some(path) = &self.file else {
+            return Err("ERR The server is running without a config file".into());
+        };
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("ERR Rewriting config file: {err}")),
+        };
+
+        let mut written = HashSet::new();
+        let mut lines = vec![];
+        for line in content.lines() {
+            let args = split_args(line).unwrap_or_default();
+            //the sentinel state is written again as a whole
+            if let [name, _, ..] = args.as_slice()
+                && name.eq_ignore_ascii_case("sentinel")
+            {
+                continue;
+            }
+            let param = args.first().and_then(|name| find_param(name));
+
+            match param {
+                //repeated directives are replaced by a single one
+                Some(param) if written.insert(param.name) => lines.push(self.directive(param)),
+                Some(_) => {}
+                //comments, includes and the like are kept as they are
+                None => lines.push(line.to_string()),
+            }
+        }
+
+        let missing: Vec<_> = PARAMS
+            .iter()
+            .filter(|param| !written.contains(param.name))
+            .filter(|param| *self.value(param.name) != default_value(param))
+            .collect();
+        let appended = !missing.is_empty() || !self.sentinel.is_empty();
+        if appended && !lines.iter().any(|line| line == REWRITE_SIGNATURE) {
+            lines.push(REWRITE_SIGNATURE.to_string());
+        }
+        lines.extend(missing.into_iter().map(|param| self.directive(param)));
+        lines.extend(self.sentinel.iter().map(|args| {
+            std::iter::once("sentinel".to_string())
+                .chain(args.iter().map(|arg| quote(arg)))
+                .collect::<Vec<_>>()
+                .join(" ")
+        }));
+
+        //the file is replaced at once, so that a crash can't leave it half written
+        let mut content = lines.join("\n");
+        content.push('\n');
+        let tmp = PathBuf::from(format!("{}.tmp-{}", path.display(), std::process::id()));
+        fs::write(&tmp, content)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|err| format!("ERR Rewriting config file: {err}"))
+    }
+
+    //the line setting the parameter to its current value
+    fn directive(&self, param: &Param) -> String {
+        let args = match (&param.kind, self.value(param.name)) {
+            //an empty list still needs an argument, or the directive would not parse back
+            (Kind::List | Kind::Pairs | Kind::Address, Value::List(words)) if words.is_empty() => {
+                vec![quote("")]
+            }
+            (Kind::List | Kind::Pairs | Kind::Address, Value::List(words)) => {
+                words.iter().map(|word| quote(word)).collect()
+            }
+            (Kind::Memory, Value::Integer(bytes)) => vec![format_memory(*bytes)],
+            (_, value) => vec![quote(&value.to_string())],
+        };
+
+        std::iter::once(param.name.to_string())
+            .chain(args)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn value(&self, name: &str) -> &Value {
+        &self.values[name]
+    }
+
+    fn bool(&self, name: &str) -> bool {
+        match self.value(name) {
+            Value::Bool(value) => *value,
+            value => unreachable!("{name} is not a bool: {value:?}"),
+        }
+    }
+
+    fn integer(&self, name: &str) -> i64 {
+        match self.value(name) {
+            Value::Integer(value) => *value,
+            value => unreachable!("{name} is not an integer: {value:?}"),
+        }
+    }
+
+    fn string(&self, name: &str) -> &str {
+        match self.value(name) {
+            Value::String(value) => value,
+            value => unreachable!("{name} is not a string: {value:?}"),
+        }
+    }
+
+    fn list(&self, name: &str) -> &[String] {
+        match self.value(name) {
+            Value::List(value) => value,
+            value => unreachable!("{name} is not a list: {value:?}"),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.integer("port") as u16
+    }
+
+    /// The addresses to listen on, the ones starting with - are skipped when unavailable.
+    pub fn bind(&self) -> &[String] {
+        self.list("bind")
+    }
+
+    pub fn protected_mode(&self) -> bool {
+        self.bool("protected-mode")
+    }
+
+    /// The password of the default user, none when empty.
+    pub fn requirepass(&self) -> &str {
+        self.string("requirepass")
+    }
+
+    /// The file users are loaded from, if any.
+    pub fn aclfile(&self) -> Option<&Path> {
+        Some(self.string("aclfile"))
+            .filter(|path| !path.is_empty())
+            .map(Path::new)
+    }
+
+    /// The working directory of the server, where its files are written.
+    pub fn dir(&self) -> &Path {
+        Path::new(self.string("dir"))
+    }
+
+    /// The RDB file, relative to dir.
+    pub fn dbfilename(&self) -> &Path {
+        Path::new(self.string("dbfilename"))
+    }
+
+    pub fn appendonly(&self) -> bool {
+        self.bool("appendonly")
+    }
+
+    /// The prefix of the names of the AOF files.
+    pub fn appendfilename(&self) -> &str {
+        self.string("appendfilename")
+    }
+
+    /// The directory the AOF files are in, relative to dir.
+    pub fn appenddirname(&self) -> &Path {
+        Path::new(self.string("appenddirname"))
+    }
+
+    pub fn appendfsync(&self) -> &str {
+        self.string("appendfsync")
+    }
+
+    pub fn aof_load_truncated(&self) -> bool {
+        self.bool("aof-load-truncated")
+    }
+
+    /// The growth in percent of the AOF since the last rewrite that triggers a new one, None if
+    /// it is never rewritten automatically.
+    pub fn auto_aof_rewrite_percentage(&self) -> Option<u64> {
+        Some(self.integer("auto-aof-rewrite-percentage") as u64)
+            .filter(|percentage| *percentage > 0)
+    }
+
+    pub fn auto_aof_rewrite_min_size(&self) -> u64 {
+        self.integer("auto-aof-rewrite-min-size") as u64
+    }
+
+    /// The host and port of the master to replicate at startup, None for a master.
+    pub fn replicaof(&self) -> Option<(String, u16)> {
+        match self.list("replicaof") {
+            [host, port] => Some((host.clone(), port.parse().expect("checked when set"))),
+            _ => None,
+        }
+    }
+
+    pub fn masteruser(&self) -> Option<&str> {
+        Some(self.string("masteruser")).filter(|user| !user.is_empty())
+    }
+
+    pub fn masterauth(&self) -> Option<&str> {
+        Some(self.string("masterauth")).filter(|password| !password.is_empty())
+    }
+
+    pub fn replica_read_only(&self) -> bool {
+        self.bool("replica-read-only")
+    }
+
+    pub fn repl_backlog_size(&self) -> usize {
+        self.integer("repl-backlog-size") as usize
+    }
+
+    pub fn repl_ping_replica_period(&self) -> Duration {
+        Duration::from_secs(self.integer("repl-ping-replica-period") as u64)
+    }
+
+    pub fn repl_timeout(&self) -> Duration {
+        Duration::from_secs(self.integer("repl-timeout") as u64)
+    }
+
+    pub fn cluster_enabled(&self) -> bool {
+        self.bool("cluster-enabled")
+    }
+
+    /// The file the cluster configuration is saved to, relative to dir.
+    pub fn cluster_config_file(&self) -> &Path {
+        Path::new(self.string("cluster-config-file"))
+    }
+
+    /// The port of the cluster bus, None when the default one would be out of range.
+    pub fn cluster_port(&self) -> Option<u16> {
+        match self.integer("cluster-port") {
+            0 => self.port().checked_add(10000),
+            port => Some(port as u16),
+        }
+    }
+
+    pub fn cluster_node_timeout(&self) -> Duration {
+        Duration::from_millis(self.integer("cluster-node-timeout") as u64)
+    }
+
+    pub fn cluster_require_full_coverage(&self) -> bool {
+        self.bool("cluster-require-full-coverage")
+    }
+
+    pub fn notify_keyspace_events(&self) -> KeyspaceEvents {
+        self.string("notify-keyspace-events")
+            .parse()
+            .expect("the value has been checked when set")
+    }
+
+    /// How long a client can stay idle before being disconnected, None if it never is.
+    pub fn timeout(&self) -> Option<Duration> {
+        Some(self.integer("timeout"))
+            .filter(|seconds| *seconds > 0)
+            .map(|seconds| Duration::from_secs(seconds as u64))
+    }
+
+    pub fn hz(&self) -> i32 {
+        self.integer("hz") as i32
+    }
+
+    pub fn busy_reply_threshold(&self) -> Duration {
+        Duration::from_millis(self.integer("busy-reply-threshold") as u64)
+    }
+
+    /// The memory limit in bytes, None if there is none.
+    pub fn maxmemory(&self) -> Option<usize> {
+        Some(self.integer("maxmemory"))
+            .filter(|bytes| *bytes > 0)
+            .map(|bytes| bytes as usize)
+    }
+
+    /// The save rules as (seconds, changes) pairs: a snapshot is taken once there were at least
+    /// that many changes and the last one is older than that many seconds.
+    pub fn save_rules(&self) -> Vec<(Duration, u64)> {
+        self.list("save")
+            .chunks(2)
+            .map(|rule| {
+                let number = |word: &String| word.parse::<u64>().expect("checked when set");
+                (Duration::from_secs(number(&rule[0])), number(&rule[1]))
+            })
+            .collect()
+    }
+
+    pub fn sentinel_mode(&self) -> bool {
+        self.sentinel_mode
+    }
+
+    pub fn sentinel(&self) -> &[Vec<String>] {
+        &self.sentinel
+    }
+
+    /// Replaces the sentinel state, written to the file by the next rewrite.
+    pub fn set_sentinel(&mut self, directives: Vec<Vec<String>>) {
+        self.sentinel = directives;
+    }
+
+    pub fn maxmemory_policy(&self) -> &str {
+        self.string("maxmemory-policy")
+    }
+}
+
+fn find_param(name: &str) -> Option<&'static Param> {
+    PARAMS
+        .iter()
+        .find(|param| param.name.eq_ignore_ascii_case(name))
+}
+
+fn default_value(param: &Param) -> Value {
+    parse_value(param, &[param.default.to_string()]).expect("defaults are valid")
+}
+
+fn parse_value(param: &Param, args: &[String]) -> Result<Value, String> {
+    if let Kind::List | Kind::Pairs | Kind::Address = param.kind {
+        let words: Vec<String> = args
+            .iter()
+            .flat_map(|arg| arg.split_whitespace())
+            .map(str::to_string)
+            .collect();
+        if let Kind::Pairs = param.kind
+            && (!words.len().is_multiple_of(2)
+                || words.iter().any(|word| word.parse::<u64>().is_err()))
+        {
+            return Err("Invalid save parameters".into());
+        }
+        if let Kind::Address = param.kind
+            && !match words.as_slice() {
+                [] => true,
+                [_, port] => port.parse::<u16>().is_ok(),
+                _ => false,
+            }
+        {
+            return Err("Invalid master address".into());
+        }
+        return Ok(Value::List(words));
+    }
+
+    let [arg] = args else {
+        return Err("wrong number of arguments".into());
+    };
+
+    match param.kind {
+        Kind::Bool => match arg.to_ascii_lowercase().as_str() {
+            "yes" => Ok(Value::Bool(true)),
+            "no" => Ok(Value::Bool(false)),
+            _ => Err("argument must be 'yes' or 'no'".into()),
+        },
+        Kind::Integer { min, max } => {
+            let value = arg
+                .parse::<i64>()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            if !(min..=max).contains(&value) {
+                return Err(format!(
+                    "argument must be between {min} and {max} inclusive"
+                ));
+            }
+            Ok(Value::Integer(value))
+        }
+        Kind::Memory => parse_memory(arg)
+            .map(Value::Integer)
+            .ok_or_else(|| "argument must be a memory value".into()),
+        Kind::String => Ok(Value::String(arg.clone())),
+        Kind::Enum(names) => names
+            .iter()
+            .find(|name| name.eq_ignore_ascii_case(arg))
+            .map(|name| Value::String(name.to_string()))
+            .ok_or_else(|| {
+                format!(
+                    "argument must be one of the following: {}",
+                    names.join(", ")
+                )
+            }),
+        Kind::Custom(check) => check(arg).map(Value::String),
+        Kind::List | Kind::Pairs | Kind::Address => {
+            unreachable!("lists take any number of arguments")
+        }
+    }
+}
+
+//bytes with an optional unit, k is 1000 bytes while kb is 1024 and so on
+fn parse_memory(arg: &str) -> Option<i64> {
+    const UNITS: [(&str, i64); 7] = [
+        ("kb", 1 << 10),
+        ("mb", 1 << 20),
+        ("gb", 1 << 30),
+        ("k", 1000),
+        ("m", 1000 * 1000),
+        ("g", 1000 * 1000 * 1000),
+        ("b", 1),
+    ];
+
+    let arg = arg.to_ascii_lowercase();
+    let (number, unit) = UNITS
+        .iter()
+        .find_map(|(unit, size)| Some((arg.strip_suffix(unit)?, *size)))
+        .unwrap_or((arg.as_str(), 1));
+
+    number
+        .parse::<i64>()
+        .ok()
+        .filter(|number| *number >= 0)
+        .and_then(|number| number.checked_mul(unit))
+}
+
+//the largest binary unit the value is a multiple of
+fn format_memory(bytes: i64) -> String {
+    match bytes {
+        0 => "0".to_string(),
+        bytes if bytes % (1 << 30) == 0 => format!("{}gb", bytes >> 30),
+        bytes if bytes % (1 << 20) == 0 => format!("{}mb", bytes >> 20),
+        bytes if bytes % (1 << 10) == 0 => format!("{}kb", bytes >> 10),
+        bytes => bytes.to_string(),
+    }
+}
+
+//the argument as written in the configuration file, quoted when split_args needs it
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .bytes()
+            .all(|c| c.is_ascii_graphic() && !matches!(c, b'"' | b'\'' | b'\\'));
+    if plain {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\x{:02x}", c as u8)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn keyspace_events(arg: &str) -> Result<String, String> {
+    arg.parse::<KeyspaceEvents>()
+        .map(|events| events.to_string())
+        .map_err(|err| err.to_string())
+}
+
+fn file_name(arg: &str) -> Result<String, String> {
+    plain_name(arg, "dbfilename can't be a path, just a filename")
+}
+
+fn aof_file_name(arg: &str) -> Result<String, String> {
+    plain_name(arg, "appendfilename can't be a path, just a filename")
+}
+
+fn aof_dir_name(arg: &str) -> Result<String, String> {
+    plain_name(arg, "appenddirname can't be a path, just a dirname")
+}
+
+fn plain_name(arg: &str, error: &str) -> Result<String, String> {
+    match arg.contains('/') {
+        true => Err(error.into()),
+        false => Ok(arg.to_string()),
+    }
+}
+
+/// Splits a configuration line into its arguments. They are separated by whitespace and can be
+/// quoted: double quotes support the \n, \r, \t, \b, \a and \xHH escapes, single quotes only \'.
+fn split_args(line: &str) -> Result<Vec<String>, String> {
+    const UNBALANCED: &str = "Unbalanced quotes in configuration line";
+
+    let bytes = line.as_bytes();
+    let mut args = vec![];
+    let mut i = 0;
+
+    loop {
+        while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        if i == bytes.len() {
+            return Ok(args);
+        }
+
+        let mut arg = vec![];
+        //the quote the current argument is in, if any
+        let mut quote = None;
+        loop {
+            let Some(&c) = bytes.get(i) else {
+                if quote.is_some() {
+                    return Err(UNBALANCED.into());
+                }
+                break;
+            };
+
+            match (quote, c) {
+                (None, b'"' | b'\'') => quote = Some(c),
+                (None, c) if c.is_ascii_whitespace() => break,
+                (None, c) => arg.push(c),
+                (Some(q), c) if c == q => {
+                    //the closing quote must end the argument
+                    if bytes.get(i + 1).is_some_and(|c| !c.is_ascii_whitespace()) {
+                        return Err(UNBALANCED.into());
+                    }
+                    i += 1;
+                    break;
+                }
+                (Some(b'"'), b'\\') => {
+                    let hex = bytes.get(i + 2..i + 4).and_then(|hex| {
+                        u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()
+                    });
+                    match (bytes.get(i + 1), hex) {
+                        (Some(b'x'), Some(byte)) => {
+                            arg.push(byte);
+                            i += 3;
+                        }
+                        (Some(&escaped), _) => {
+                            arg.push(match escaped {
+                                b'n' => b'\n',
+                                b'r' => b'\r',
+                                b't' => b'\t',
+                                b'b' => 0x08,
+                                b'a' => 0x07,
+                                c => c,
+                            });
+                            i += 1;
+                        }
+                        (None, _) => arg.push(c),
+                    }
+                }
+                (Some(b'\''), b'\\') if bytes.get(i + 1) == Some(&b'\'') => {
+                    arg.push(b'\'');
+                    i += 1;
+                }
+                (Some(_), c) => arg.push(c),
+            }
+            i += 1;
+        }
+
+        args.push(String::from_utf8_lossy(&arg).into_owned());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path, time::Duration};
+
+    use super::{Config, REWRITE_SIGNATURE, find_param, split_args};
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            split_args("  bind 127.0.0.1   -::1 ").unwrap(),
+            strings(&["bind", "127.0.0.1", "-::1"])
+        );
+        assert_eq!(
+            split_args(r#"requirepass "a b\t\x41\"" 'it\'s' """#).unwrap(),
+            strings(&["requirepass", "a b\tA\"", "it's", ""])
+        );
+        assert_eq!(
+            split_args("requirepass \"secret").unwrap_err(),
+            "Unbalanced quotes in configuration line"
+        );
+        assert_eq!(
+            split_args("requirepass \"secret\"x").unwrap_err(),
+            "Unbalanced quotes in configuration line"
+        );
+    }
+
+    #[test]
+    fn test_save_rules() {
+        let secs = Duration::from_secs;
+        assert_eq!(
+            Config::default().save_rules(),
+            [(secs(3600), 1), (secs(300), 100), (secs(60), 10000)]
+        );
+
+        //the first directive replaces the default rules, the next ones add up
+        let args = strings(&["--save", "900", "1", "--save", "300 10"]);
+        let config = Config::from_args(&args).unwrap();
+        assert_eq!(config.save_rules(), [(secs(900), 1), (secs(300), 10)]);
+
+        let mut config = Config::from_args(&strings(&["--save", ""])).unwrap();
+        assert_eq!(config.save_rules(), []);
+        assert_eq!(config.directive(find_param("save").unwrap()), "save \"\"");
+
+        assert!(Config::from_args(&strings(&["--save", "900"])).is_err());
+        let err = config
+            .set_all(&[("save".into(), "60 many".into())])
+            .unwrap_err();
+        assert!(err.ends_with("Invalid save parameters"));
+        config.set_all(&[("save".into(), "60 5".into())]).unwrap();
+        assert_eq!(config.save_rules(), [(secs(60), 5)]);
+    }
+
+    #[test]
+    fn test_file_and_command_line() {
+        let dir = std::env::temp_dir().join(format!("config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let included = dir.join("included.conf");
+        let main = dir.join("redis.conf");
+        fs::write(&included, "protected-mode no\nnotify-keyspace-events KEA\n").unwrap();
+        fs::write(
+            &main,
+            format!(
+                "# comment\nport 7000\n\nPORT 7001\ninclude \"{}\"\nrequirepass \"p w\"\n",
+                included.display()
+            ),
+        )
+        .unwrap();
+
+        let args = strings(&[
+            main.to_str().unwrap(),
+            "--port",
+            "6380",
+            "--bind",
+            "127.0.0.1",
+            "::1",
+            "--dir",
+            "/data",
+        ]);
+        let config = Config::from_args(&args).unwrap();
+        assert_eq!(config.port(), 6380);
+        assert_eq!(config.bind(), strings(&["127.0.0.1", "::1"]));
+        assert!(!config.protected_mode());
+        assert_eq!(config.requirepass(), "p w");
+        assert_eq!(config.dir(), Path::new("/data"));
+        assert_eq!(config.notify_keyspace_events().to_string(), "AKE");
+        assert_eq!(config.aclfile(), None);
+
+        fs::write(&main, "port 7000\nport 70000\n").unwrap();
+        let err = Config::from_args(&strings(&[main.to_str().unwrap()])).unwrap_err();
+        assert_eq!(
+            err,
+            format!(
+                "Reading the configuration file {}, at line 2\n>>> 'port 70000'\n\
+                 argument must be between 0 and 65535 inclusive",
+                main.display()
+            )
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        let config = Config::from_args(&[]).unwrap();
+        assert_eq!(config.port(), 6379);
+        assert!(config.protected_mode());
+
+        let err = Config::from_args(&strings(&["--protected-mode", "maybe"])).unwrap_err();
+        assert_eq!(
+            err,
+            "Reading the command line, at option 1\n>>> 'protected-mode maybe'\n\
+             argument must be 'yes' or 'no'"
+        );
+        let err = Config::from_args(&strings(&["--no-such-option", "1"])).unwrap_err();
+        assert!(err.ends_with("Bad directive or wrong number of arguments"));
+    }
+
+    #[test]
+    fn test_set_all_and_get() {
+        let mut config = Config::default();
+
+        let names = config
+            .set_all(&[
+                ("maxmemory".into(), "100mb".into()),
+                ("TIMEOUT".into(), "30".into()),
+            ])
+            .unwrap();
+        assert_eq!(names, ["maxmemory", "timeout"]);
+        assert_eq!(config.maxmemory(), Some(100 << 20));
+        assert_eq!(
+            config.get(&strings(&["max*", "timeout"])),
+            [
+                ("timeout", "30".to_string()),
+                ("maxmemory", (100 << 20).to_string()),
+                ("maxmemory-policy", "noeviction".to_string()),
+            ]
+        );
+
+        //one bad value and nothing changes
+        let err = config
+            .set_all(&[
+                ("timeout".into(), "60".into()),
+                ("hz".into(), "1000".into()),
+            ])
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "ERR CONFIG SET failed (possibly related to argument 'hz') - argument must be between 1 and 500 inclusive"
+        );
+        assert_eq!(config.timeout().unwrap().as_secs(), 30);
+
+        let err = config
+            .set_all(&[("port".into(), "7000".into())])
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "ERR CONFIG SET failed (possibly related to argument 'port') - can't set immutable config"
+        );
+        let err = config
+            .set_all(&[("maxmemory-policy".into(), "allkeys-lru".into())])
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "ERR CONFIG SET failed (possibly related to argument 'maxmemory-policy') - argument must be one of the following: volatile-random, allkeys-random, volatile-ttl, noeviction"
+        );
+        let err = config
+            .set_all(&[("hz".into(), "5".into()), ("hz".into(), "6".into())])
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "ERR CONFIG SET failed (possibly related to argument 'hz') - duplicate parameter"
+        );
+        let err = config.set_all(&[("nope".into(), "1".into())]).unwrap_err();
+        assert_eq!(
+            err,
+            "ERR Unknown option or number of arguments for CONFIG SET - 'nope'"
+        );
+        assert_eq!(config.hz(), 10);
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let dir = std::env::temp_dir().join(format!("config-rewrite-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("redis.conf");
+        fs::write(
+            &path,
+            "# the port\nport 7000\n\n# memory\nmaxmemory 1mb\nhz 20\nmaxmemory 2mb\n",
+        )
+        .unwrap();
+
+        let mut config = Config::from_args(&strings(&[path.to_str().unwrap()])).unwrap();
+        config
+            .set_all(&[
+                ("maxmemory".into(), "3mb".into()),
+                ("requirepass".into(), "a b".into()),
+                ("hz".into(), "10".into()),
+            ])
+            .unwrap();
+        config.rewrite().unwrap();
+
+        let expected = format!(
+            "# the port\nport 7000\n\n# memory\nmaxmemory 3mb\nhz 10\n{REWRITE_SIGNATURE}\nrequirepass \"a b\"\n"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+
+        //the rewritten file reads back to the same settings, rewriting again changes nothing
+        let config = Config::from_args(&strings(&[path.to_str().unwrap()])).unwrap();
+        assert_eq!(config.requirepass(), "a b");
+        assert_eq!(config.maxmemory(), Some(3 << 20));
+        config.rewrite().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        let err = Config::default().rewrite().unwrap_err();
+        assert_eq!(err, "ERR The server is running without a config file");
+    }
+
+    #[test]
+    fn test_sentinel_directives() {
+        let dir = std::env::temp_dir().join(format!("config-sentinel-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sentinel.conf");
+        fs::write(
+            &path,
+            "sentinel monitor m 127.0.0.1 6379 2\nsentinel down-after-milliseconds m 5000\n",
+        )
+        .unwrap();
+
+        let config = Config::from_args(&strings(&[path.to_str().unwrap(), "--sentinel"])).unwrap();
+        assert!(config.sentinel_mode());
+        assert_eq!(config.port(), 26379);
+        assert_eq!(
+            config.sentinel(),
+            [
+                strings(&["monitor", "m", "127.0.0.1", "6379", "2"]),
+                strings(&["down-after-milliseconds", "m", "5000"]),
+            ]
+        );
+
+        //the state saved replaces the one read
+        let mut config = config;
+        config.set_sentinel(vec![strings(&["myid", "a b"])]);
+        config.rewrite().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{REWRITE_SIGNATURE}\nport 26379\nsentinel myid \"a b\"\n")
+        );
+
+        let err = Config::from_args(&strings(&[path.to_str().unwrap()])).unwrap_err();
+        assert_eq!(err, "sentinel directive while not in sentinel mode");
+        let config = Config::from_args(&strings(&["--sentinel", "--port", "26380"])).unwrap();
+        assert_eq!(config.port(), 26380);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
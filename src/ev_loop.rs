@@ -1,152 +1,787 @@
 use std::{
     collections::HashMap,
-    io::{self},
+    io::{self, Write as _},
     net::TcpListener,
     os::fd::AsRawFd,
 };
 
+mod acl;
+mod aof;
 mod client;
+mod cluster;
+mod config;
+mod functions;
+mod info;
+mod migrate;
+mod persistence;
+mod pubsub;
+mod replication;
+mod scripting;
+mod sentinel;
+#[cfg(test)]
+mod test_util;
 
 use libc::{EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT, EPOLLRDHUP};
 
-use crate::{command::Command, poll::Poller, redis::Redis, resp::RespType};
+use crate::{
+    acl::{Acl, Context},
+    alloc::used_memory,
+    command::{Command, command_name},
+    config::Config,
+    poll::Poller,
+    redis::{REDIS_VERSION, Redis, RedisError},
+    resp::{Protocol, RespType},
+};
 
 #[derive(Debug)]
 pub struct EventLoop {
     redis: Redis,
-    listener: TcpListener,
+    //one listener per bind address
+    listeners: Vec<TcpListener>,
     clients: HashMap<i32, client::Client>,
+    pubsub: pubsub::PubSub,
     poller: Poller,
+    acl: Acl,
+    config: Config,
+    stats: info::Stats,
+    snapshots: persistence::Snapshots,
+    aof: aof::Aof,
+    replication: replication::Replication,
+    //Some with cluster-enabled
+    cluster: Option<cluster::ClusterState>,
+    //Some with --sentinel
+    sentinel: Option<sentinel::SentinelState>,
+    scripting: scripting::Scripting,
+    functions: functions::Functions,
+    //Some while a script runs, requests served in the meantime are refused
+    running_script: Option<scripting::RunningScript>,
 }
 
 impl EventLoop {
     pub fn new(listener: TcpListener, poller: Poller) -> Self {
         Self {
             redis: Redis::default(),
-            listener,
+            listeners: vec![listener],
             poller,
             clients: HashMap::new(),
+            pubsub: pubsub::PubSub::default(),
+            acl: Acl::default(),
+            config: Config::default(),
+            stats: info::Stats::new(),
+            snapshots: persistence::Snapshots::new(),
+            aof: aof::Aof::new(),
+            replication: replication::Replication::new(),
+            cluster: None,
+            sentinel: None,
+            scripting: scripting::Scripting::new(),
+            functions: functions::Functions::new(),
+            running_script: None,
         }
     }
 
+    /// Accepts connections on one more address, next to the listener given to new().
+    pub fn add_listener(&mut self, listener: TcpListener) -> io::Result<()> {
+        self.poller.watch_listener(&listener)?;
+        self.listeners.push(listener);
+        Ok(())
+    }
+
     pub fn run(&mut self) -> io::Result<()> {
-        println!("Starting main event loop. Listening for connections at port 6379");
-        let listener_fd = self.listener.as_raw_fd() as u64;
+        println!(
+            "Starting main event loop. Listening for connections at port {}",
+            self.config.port()
+        );
 
-        'event_loop: loop {
+        loop {
             // println!("Looper state {self:?}");
 
-            //loop over all waiting and for each expired send back a null bulk str
-            for client_id in self.redis.remove_expired() {
-                if let Some(cl) = self.clients.get_mut(&client_id) {
-                    println!("Timeout occurred for {client_id:?}");
-                    cl.send(RespType::NullBulkString);
-                }
-            }
+            //before the timeouts, for the clients that got enough acknowledgements to be served
+            self.serve_waits();
 
-            self.redis.compute_ready();
+            self.redis.active_expire_cycle();
+            self.serve_unblocked();
 
-            while let Some((client_id, response)) = self.redis.ready.pop() {
-                if let Some(cl) = self.clients.get_mut(&client_id) {
-                    println!("Sending response {response:?} to client {client_id}");
-                    cl.send(response);
-                    println!("Looper state {self:?}");
-                }
+            //keyspace notifications produced by the commands and the expiration of keys
+            for (channel, message) in std::mem::take(&mut self.redis.publications) {
+                self.publish(&channel, &message);
             }
+            self.propagate_changes()?;
+
+            self.close_idle_clients()?;
+            self.snapshots_cron();
+            self.aof_cron();
+            self.replication_cron()?;
+            self.cluster_cron()?;
+            self.sentinel_cron()?;
+
+            //woken up hz times per second at least, for the timeouts to be noticed
+            self.process_events(1000 / self.config.hz())?;
+        }
+    }
+
+    /// Waits up to timeout_ms for socket events and serves them: new connections, requests,
+    /// pending replies and disconnections.
+    fn process_events(&mut self, timeout_ms: i32) -> io::Result<()> {
+        let events = self.poller.poll(timeout_ms)?;
 
-            let events = self.poller.poll(-1)?;
+        for ev in events {
+            let descriptor = ev.u64;
+            let listener = self
+                .listeners
+                .iter()
+                .position(|listener| listener.as_raw_fd() as u64 == descriptor);
 
-            for ev in events {
-                let descriptor = ev.u64;
+            if let Some(listener) = listener
+                && ((EPOLLIN as u32) & ev.events != 0)
+            {
+                //ev.events will be a | mask of all
+                //the events that are ready for the
+                //fd -> thus it will be ready for
+                //read iff EPOLLIN & ev.events != 0
+                println!("Listener ready for connections");
+                self.accept(listener)?;
+            } else if self.replication.master_fd() == Some(descriptor as i32) {
+                self.handle_master_event(ev.events)?;
+            } else if self.is_cluster_socket(descriptor as i32) {
+                self.handle_cluster_event(descriptor as i32, ev.events)?;
+            } else if self.is_sentinel_link(descriptor as i32) {
+                self.handle_sentinel_event(descriptor as i32, ev.events)?;
+            } else if self.clients.contains_key(&(descriptor as i32)) {
+                // println!("Got event from client: {ev:?}");
 
-                if descriptor == listener_fd && ((EPOLLIN as u32) & ev.events != 0) {
-                    //ev.events will be a | mask of all
-                    //the events that are ready for the
-                    //fd -> thus it will be ready for
-                    //read iff EPOLLIN & ev.events != 0
-                    println!("Listener ready for connections");
-                    for conn in self.listener.incoming() {
-                        match conn {
-                            Ok(stream) => {
-                                let client_addr = stream.peer_addr()?;
-                                println!("Accepted connection from {client_addr}");
+                if (EPOLLIN as u32) & ev.events != 0 {
+                    //we're guaranteed that descriptor is a valid key by the top level if and by
+                    //this program being single threaded :D
+                    let client = self.clients.get_mut(&(descriptor as i32)).unwrap();
+                    client.read();
+                    self.serve_requests(descriptor as i32);
+                    //before the replies are sent, for them to only acknowledge durable changes
+                    self.propagate_changes()?;
+                }
 
-                                self.poller.watch_socket(&stream)?;
-                                self.clients
-                                    .insert(stream.as_raw_fd(), client::Client::new(stream));
+                if (EPOLLOUT as u32) & ev.events != 0 {
+                    let client = self.clients.get_mut(&(descriptor as i32)).unwrap();
 
-                                println!("Looper state {self:?}");
+                    if let Err(err) = client.flush() {
+                        match err.kind() {
+                            io::ErrorKind::WouldBlock => {
+                                println!("would block")
+                                /* do nothing we'll come back next time */
                             }
-                            Err(err) => match err.kind() {
-                                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => {
-                                    continue 'event_loop;
-                                }
-                                _ => return Err(err),
-                            },
-                        }
-                    }
-                } else if self.clients.contains_key(&(descriptor as i32)) {
-                    // println!("Got event from client: {ev:?}");
-
-                    if (EPOLLIN as u32) & ev.events != 0 {
-                        //we're guaranteed that descriptor is a valid key by the top level if and by
-                        //this program being single threaded :D
-                        let client = self.clients.get_mut(&(descriptor as i32)).unwrap();
-
-                        let read_bytes = client.read_raw_cmd();
-                        // println!("DEBUG: Read command {}", String::from_utf8(read_bytes.clone()).unwrap());
-
-                        let cmd = RespType::try_from(read_bytes.as_slice())
-                            .map(Command::from)
-                            .unwrap_or_else(|err| Command::ErrorCmd {
-                                msg: format!("Could not parse command, got error: {err}"),
-                            });
-
-                        match self.redis.handle_command(cmd, descriptor as i32) {
-                            Ok(response) => {
-                                println!("Sending response {response:?} to client {descriptor}");
-                                client.send(response);
-                                println!("Looper state {self:?}");
+                            _ => {
+                                return Err(err);
                             }
-                            Err(err) => match err {
-                                crate::redis::RedisError::Failure(_) => todo!(),
-                                crate::redis::RedisError::WouldBlock => {
-                                    /* do nothing, we come back at next iteration */
-                                }
-                            },
                         }
                     }
+                }
 
-                    if (EPOLLOUT as u32) & ev.events != 0 {
-                        let client = self.clients.get_mut(&(descriptor as i32)).unwrap();
-
-                        if let Err(err) = client.flush() {
-                            match err.kind() {
-                                io::ErrorKind::WouldBlock => {
-                                    println!("would block")
-                                    /* do nothing we'll come back next time */
-                                }
-                                _ => {
-                                    return Err(err);
-                                }
-                            }
-                        }
+                //not exclusive cause it could be the case that the file desc is available for
+                //read operation even if EPOLLERR  | EPOLLHUP | EPOLLRDHUP have occurred (events
+                //are | together)
+                if ((EPOLLERR | EPOLLHUP | EPOLLRDHUP) as u32) & ev.events != 0 {
+                    //the if condition guarantees that the key always is present in the clients
+                    //map
+                    println!("removing socket {descriptor}");
+                    self.disconnect(descriptor as i32)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    //pipelined requests are served in order, the ones following a blocking command wait for it
+    //to be served, for the replies to be sent in order too
+    fn serve_requests(&mut self, client_id: i32) {
+        while !self.redis.is_blocked(client_id)
+            && let Some(request) = self
+                .clients
+                .get_mut(&client_id)
+                .and_then(|client| client.next_request())
+        {
+            self.handle_request(client_id, request);
+        }
+    }
+
+    //replies to the blocked clients that timed out or got served, then serves the requests they
+    //sent in the meantime
+    fn serve_unblocked(&mut self) {
+        let mut unblocked = vec![];
+
+        //loop over all waiting and for each expired send back a null bulk str
+        for (client_id, response) in self.redis.remove_expired() {
+            if let Some(cl) = self.clients.get_mut(&client_id) {
+                println!("Timeout occurred for {client_id:?}");
+                cl.send(response);
+                unblocked.push(client_id);
+            }
+        }
+
+        self.redis.compute_ready();
+
+        while let Some((client_id, response)) = self.redis.ready.pop() {
+            if let Some(cl) = self.clients.get_mut(&client_id) {
+                cl.send(response);
+                unblocked.push(client_id);
+            }
+        }
+
+        for client_id in unblocked {
+            self.serve_requests(client_id);
+        }
+    }
+
+    //the changes made to the dataset so far are written to the AOF and sent to the replicas
+    fn propagate_changes(&mut self) -> io::Result<()> {
+        let changes = std::mem::take(&mut self.redis.propagated);
+        self.feed_aof(&changes)?;
+        self.replicate_changes(&changes);
+        Ok(())
+    }
+
+    fn disconnect(&mut self, client_id: i32) -> io::Result<()> {
+        let Some(removed) = self.clients.remove(&client_id) else {
+            return Ok(());
+        };
+        self.redis.remove_waiting(&client_id);
+        self.redis.unwatch(client_id);
+        self.replication.remove_replica(client_id);
+        self.pubsub.remove_client(client_id, &removed);
+        self.poller.remove_socket(removed.stream())
+    }
+
+    //clients idle for longer than the timeout are disconnected, unless they are waiting on a
+    //blocking command or for messages
+    fn close_idle_clients(&mut self) -> io::Result<()> {
+        let Some(timeout) = self.config.timeout() else {
+            return Ok(());
+        };
+
+        let idle: Vec<i32> = self
+            .clients
+            .iter()
+            .filter(|(client_id, client)| {
+                client.idle() > timeout
+                    && client.subscriptions() == 0
+                    && client.shard_channels().is_empty()
+                    && !self.redis.is_blocked(**client_id)
+            })
+            .map(|(client_id, _)| *client_id)
+            .collect();
+
+        for client_id in idle {
+            println!("Closing idle client {client_id}");
+            self.disconnect(client_id)?;
+        }
+
+        Ok(())
+    }
+
+    //accepts every pending connection of the listener
+    fn accept(&mut self, listener: usize) -> io::Result<()> {
+        loop {
+            match self.listeners[listener].accept() {
+                Ok((mut stream, client_addr)) => {
+                    println!("Accepted connection from {client_addr}");
+
+                    self.stats.connections_received += 1;
+                    if self.protected_mode_denies(&client_addr) {
+                        self.stats.rejected_connections += 1;
+                        //best effort, the connection is closed right away anyway
+                        let _ = stream.write_all(&acl::protected_mode_denial().serialize());
+                        continue;
                     }
 
-                    //not exclusive cause it could be the case that the file desc is available for
-                    //read operation even if EPOLLERR  | EPOLLHUP | EPOLLRDHUP have occurred (events
-                    //are | together)
-                    if ((EPOLLERR | EPOLLHUP | EPOLLRDHUP) as u32) & ev.events != 0 {
-                        //the if condition guarantees that the key always is present in the clients
-                        //map
-                        println!("removing socket {descriptor}");
-                        self.redis.remove_waiting(&(descriptor as i32));
-                        let removed = self.clients.remove(&(descriptor as i32)).unwrap();
-                        self.poller.remove_socket(removed.stream())?;
-                        println!("Looper state {self:?}");
+                    self.poller.watch_socket(&stream)?;
+                    let user = self.initial_user();
+                    self.clients
+                        .insert(stream.as_raw_fd(), client::Client::new(stream, user));
+                }
+                Err(err) => match err.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => return Ok(()),
+                    _ => return Err(err),
+                },
+            }
+        }
+    }
+
+    fn handle_request(&mut self, client_id: i32, request: io::Result<RespType>) {
+        let name = request.as_ref().ok().and_then(command_name);
+        let cmd = request
+            .map(Command::from)
+            .unwrap_or_else(|err| Command::ErrorCmd {
+                msg: format!("Could not parse command, got error: {err}"),
+            });
+
+        let Some(client) = self.clients.get(&client_id) else {
+            return;
+        };
+
+        if self.running_script.is_some() {
+            return self.handle_busy_request(client_id, cmd);
+        }
+        self.stats.commands_processed += 1;
+
+        if client.in_subscribe_mode() {
+            match cmd {
+                Command::Subscribe { .. }
+                | Command::Unsubscribe { .. }
+                | Command::PSubscribe { .. }
+                | Command::PUnsubscribe { .. }
+                | Command::SSubscribe { .. }
+                | Command::SUnsubscribe { .. }
+                | Command::ErrorCmd { .. } => {}
+                //in subscribe mode pings are answered like messages
+                Command::Ping { message } => {
+                    let pong = RespType::Array {
+                        elements: vec![
+                            RespType::BulkString {
+                                data: b"pong".to_vec(),
+                            },
+                            RespType::BulkString {
+                                data: message.unwrap_or_default().into_bytes(),
+                            },
+                        ],
+                    };
+                    return self.send(client_id, pong);
+                }
+                _ => {
+                    let content = format!(
+                        "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING are allowed in this context",
+                        name.unwrap_or_default()
+                    );
+                    return self.send(client_id, RespType::SimpleError { content });
+                }
+            }
+        }
+
+        let in_transaction = self
+            .clients
+            .get_mut(&client_id)
+            .and_then(|client| client.transaction_mut())
+            .is_some();
+
+        let denied = self
+            .check_permissions(client_id, &cmd, Context::TopLevel)
+            .and_then(|_| self.check_sentinel(&cmd, name.as_deref()))
+            .and_then(|_| self.check_cluster(client_id, std::slice::from_ref(&cmd)))
+            .and_then(|_| self.check_memory(&cmd))
+            .and_then(|_| self.check_read_only(&cmd));
+        if let Err(response) = denied {
+            //like a command that can't be parsed, a denied one fails the whole transaction
+            if let Some(transaction) = self
+                .clients
+                .get_mut(&client_id)
+                .and_then(|client| client.transaction_mut())
+            {
+                transaction.aborted = true;
+            }
+            return self.send(client_id, response);
+        }
+
+        match cmd {
+            Command::Multi if in_transaction => self.send(
+                client_id,
+                RespType::SimpleError {
+                    content: "ERR MULTI calls can not be nested".into(),
+                },
+            ),
+            Command::Multi => {
+                if let Some(client) = self.clients.get_mut(&client_id) {
+                    client.start_transaction();
+                }
+                self.send(
+                    client_id,
+                    RespType::SimpleString {
+                        content: "OK".into(),
+                    },
+                );
+            }
+            Command::Exec => self.handle_exec(client_id),
+            Command::Discard => self.handle_discard(client_id),
+            cmd if in_transaction => self.queue(client_id, cmd),
+            cmd => {
+                if let Some(response) = self.execute(client_id, cmd) {
+                    self.send(client_id, response);
+                }
+            }
+        }
+    }
+
+    /// Runs the command returning its reply, None when the client blocked or the command
+    /// already took care of replying.
+    fn execute(&mut self, client_id: i32, cmd: Command) -> Option<RespType> {
+        match cmd {
+            Command::Hello {
+                protover,
+                auth,
+                setname,
+            } => Some(self.handle_hello(client_id, protover, auth, setname)),
+            Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::PSubscribe { .. }
+            | Command::PUnsubscribe { .. }
+            | Command::SSubscribe { .. }
+            | Command::SUnsubscribe { .. } => {
+                self.handle_subscriptions(client_id, cmd);
+                None
+            }
+            Command::Publish { channel, message } if self.sentinel.is_some() => {
+                Some(self.handle_sentinel_publish(&channel, &message))
+            }
+            Command::Publish { channel, message } => Some(RespType::Integer {
+                integer: self.publish(&channel, &message) as i64,
+            }),
+            Command::SPublish { channel, message } => Some(RespType::Integer {
+                integer: self.spublish(&channel, &message) as i64,
+            }),
+            Command::PubSub { subcommand } => Some(self.handle_pubsub_introspection(subcommand)),
+            Command::Eval { .. } | Command::EvalSha { .. } => {
+                Some(self.handle_eval(client_id, cmd))
+            }
+            Command::Script { subcommand } => Some(self.handle_script(subcommand)),
+            Command::FCall { .. } => Some(self.handle_fcall(client_id, cmd)),
+            Command::Function { subcommand } => Some(self.handle_function(subcommand)),
+            Command::Auth { username, password } => {
+                Some(self.handle_auth(client_id, username, &password))
+            }
+            Command::Acl { subcommand } => Some(self.handle_acl(client_id, subcommand)),
+            Command::Config { subcommand } => Some(self.handle_config(subcommand)),
+            Command::Info { sections } => Some(self.handle_info(sections)),
+            Command::Save => Some(self.handle_save()),
+            Command::BgSave { schedule } => Some(self.handle_bgsave(schedule)),
+            Command::LastSave => Some(self.handle_lastsave()),
+            Command::BgRewriteAof => Some(self.handle_bgrewriteaof()),
+            Command::ReplicaOf { master } => Some(self.handle_replicaof(master)),
+            Command::ReplConf { options } => self.handle_replconf(client_id, options),
+            Command::PSync { replid, offset } => self.handle_psync(client_id, replid, offset),
+            Command::Role if self.sentinel.is_some() => Some(self.sentinel_role()),
+            Command::Role => Some(self.handle_role()),
+            Command::Cluster { subcommand } => Some(self.handle_cluster(subcommand)),
+            Command::Asking => Some(self.handle_asking(client_id)),
+            Command::Sentinel { subcommand } => Some(self.handle_sentinel(subcommand)),
+            Command::Migrate { .. } => Some(self.handle_migrate(client_id, cmd)),
+            Command::Wait {
+                numreplicas,
+                timeout,
+            } => self.handle_wait(client_id, numreplicas, timeout),
+            Command::WaitAof {
+                numlocal,
+                numreplicas,
+                timeout,
+            } => self.handle_waitaof(client_id, numlocal, numreplicas, timeout),
+            Command::SwapDb { .. } if self.cluster.is_some() => Some(RespType::SimpleError {
+                content: "ERR SWAPDB is not allowed in cluster mode".into(),
+            }),
+            cmd => match self.redis.handle_command(cmd, client_id) {
+                Ok(response) => Some(response),
+                Err(err) => match err {
+                    RedisError::Failure(_) => todo!(),
+                    RedisError::WouldBlock => {
+                        /* do nothing, we come back at next iteration */
+                        None
                     }
+                },
+            },
+        }
+    }
+
+    //once the limit is exceeded, commands that would take more memory first evict keys as the
+    //policy says, and are refused when it has none left to evict
+    fn check_memory(&mut self, cmd: &Command) -> Result<(), RespType> {
+        let Some(limit) = self.config.maxmemory().filter(|_| cmd.grows_dataset()) else {
+            return Ok(());
+        };
+
+        while used_memory() > limit {
+            if !self.redis.evict(self.config.maxmemory_policy()) {
+                return Err(RespType::SimpleError {
+                    content: "OOM command not allowed when used memory > 'maxmemory'.".into(),
+                });
+            }
+            self.stats.evicted_keys += 1;
+        }
+        Ok(())
+    }
+
+    fn queue(&mut self, client_id: i32, cmd: Command) {
+        let Some(transaction) = self
+            .clients
+            .get_mut(&client_id)
+            .and_then(|client| client.transaction_mut())
+        else {
+            return;
+        };
+
+        let response = match cmd {
+            //commands that can't even be parsed make the whole transaction fail
+            Command::ErrorCmd { msg } => {
+                transaction.aborted = true;
+                RespType::SimpleError { content: msg }
+            }
+            //refused, but the transaction can still run
+            Command::Watch { .. } => RespType::SimpleError {
+                content: "ERR WATCH inside MULTI is not allowed".into(),
+            },
+            Command::Hello { .. }
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::PSubscribe { .. }
+            | Command::PUnsubscribe { .. }
+            | Command::SSubscribe { .. }
+            | Command::SUnsubscribe { .. } => {
+                transaction.aborted = true;
+                RespType::SimpleError {
+                    content: "ERR Command not allowed inside a transaction".into(),
+                }
+            }
+            cmd => {
+                transaction.commands.push(cmd);
+                RespType::SimpleString {
+                    content: "QUEUED".into(),
                 }
             }
+        };
+
+        self.send(client_id, response);
+    }
+
+    fn handle_exec(&mut self, client_id: i32) {
+        let Some(transaction) = self
+            .clients
+            .get_mut(&client_id)
+            .and_then(|client| client.take_transaction())
+        else {
+            return self.send(
+                client_id,
+                RespType::SimpleError {
+                    content: "ERR EXEC without MULTI".into(),
+                },
+            );
+        };
+
+        //the transaction ends here whatever the outcome, so do the watches
+        let watched_key_modified = self.redis.unwatch(client_id);
+
+        if transaction.aborted {
+            return self.send(
+                client_id,
+                RespType::SimpleError {
+                    content: "EXECABORT Transaction discarded because of previous errors.".into(),
+                },
+            );
+        }
+
+        //the slots may have moved since the commands were queued
+        if let Err(response) = self.check_cluster(client_id, &transaction.commands) {
+            return self.send(client_id, response);
+        }
+
+        if watched_key_modified {
+            return self.send(client_id, RespType::NullArray);
         }
+
+        //commands run back to back, blocking ones behave as if their timeout elapsed
+        let propagated = self.redis.propagated.len();
+        let elements = transaction
+            .commands
+            .into_iter()
+            .map(|cmd| {
+                //permissions may have changed since the command was queued
+                if let Err(response) = self.check_permissions(client_id, &cmd, Context::Multi) {
+                    return response;
+                }
+                match self.execute(client_id, cmd) {
+                    Some(response) => response,
+                    None => self
+                        .redis
+                        .cancel_blocking(client_id)
+                        .unwrap_or(RespType::Null),
+                }
+            })
+            .collect();
+        self.redis.propagate_atomically(propagated);
+
+        self.send(client_id, RespType::Array { elements });
+    }
+
+    fn handle_discard(&mut self, client_id: i32) {
+        let response = match self
+            .clients
+            .get_mut(&client_id)
+            .and_then(|client| client.take_transaction())
+        {
+            Some(_) => {
+                self.redis.unwatch(client_id);
+                RespType::SimpleString {
+                    content: "OK".into(),
+                }
+            }
+            None => RespType::SimpleError {
+                content: "ERR DISCARD without MULTI".into(),
+            },
+        };
+
+        self.send(client_id, response);
+    }
+
+    //authenticates and names the client before switching protocol, nothing changes when
+    //either fails
+    fn handle_hello(
+        &mut self,
+        client_id: i32,
+        protover: Option<Protocol>,
+        auth: Option<(String, String)>,
+        setname: Option<String>,
+    ) -> RespType {
+        if let Some((username, password)) = auth {
+            let reply = self.handle_auth(client_id, Some(username), &password);
+            if let RespType::SimpleError { .. } = reply {
+                return reply;
+            }
+        }
+
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return RespType::Null;
+        };
+        if client.user().is_none() {
+            return RespType::SimpleError {
+                content: "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time".into(),
+            };
+        }
+        if let Some(name) = setname {
+            if name.chars().any(|c| !c.is_ascii_graphic()) {
+                return RespType::SimpleError {
+                    content:
+                        "ERR Client names cannot contain spaces, newlines or special characters."
+                            .into(),
+                };
+            }
+            client.set_name(name);
+        }
+
+        if let Some(protocol) = protover {
+            client.set_protocol(protocol);
+        }
+
+        let bulk = |s: &str| RespType::BulkString {
+            data: s.as_bytes().to_vec(),
+        };
+        let proto = match client.protocol() {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+
+        RespType::Map {
+            entries: vec![
+                (bulk("server"), bulk("redis")),
+                (bulk("version"), bulk(REDIS_VERSION)),
+                (bulk("proto"), RespType::Integer { integer: proto }),
+                (
+                    bulk("id"),
+                    RespType::Integer {
+                        integer: client_id as i64,
+                    },
+                ),
+                (bulk("mode"), bulk(self.mode())),
+                (bulk("role"), bulk(self.replication.role())),
+                (bulk("modules"), RespType::Array { elements: vec![] }),
+            ],
+        }
+    }
+
+    fn send(&mut self, client_id: i32, response: RespType) {
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.send(response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write as _;
+
+    use crate::{
+        ev_loop::test_util::{client, event_loop, replies, request, send},
+        resp::RespType,
+    };
+
+    #[test]
+    fn test_pipeline_after_blocking_command() {
+        let mut server = event_loop(&[]);
+        let mut client = client(&server);
+
+        //the PING waits for the XREAD to be served
+        client
+            .write_all(
+                b"*6\r\n$5\r\nXREAD\r\n$5\r\nBLOCK\r\n$1\r\n0\r\n$7\r\nSTREAMS\r\n$1\r\ns\r\n$1\r\n$\r\n\
+                  *1\r\n$4\r\nPING\r\n",
+            )
+            .unwrap();
+        assert_eq!(replies(&mut server, &mut client), "");
+
+        server.execute(1, request(&["XADD", "s", "1-1", "f", "v"]));
+        let received = replies(&mut server, &mut client);
+        assert!(
+            received.starts_with("*1\r\n*2\r\n$1\r\ns\r\n"),
+            "{received}"
+        );
+        assert!(received.ends_with("+PONG\r\n"), "{received}");
+
+        //and the ones following a command that times out wait for the timeout
+        client
+            .write_all(
+                b"*6\r\n$5\r\nXREAD\r\n$5\r\nBLOCK\r\n$2\r\n50\r\n$7\r\nSTREAMS\r\n$1\r\ns\r\n$1\r\n$\r\n\
+                  *1\r\n$4\r\nPING\r\n",
+            )
+            .unwrap();
+        assert_eq!(replies(&mut server, &mut client), "*-1\r\n+PONG\r\n");
+    }
+
+    #[test]
+    fn test_watch_inside_multi() {
+        let mut server = event_loop(&[]);
+        let mut client = client(&server);
+
+        //the WATCH is refused, the transaction still runs
+        assert_eq!(send(&mut server, &mut client, "MULTI"), "+OK\r\n");
+        assert_eq!(
+            send(&mut server, &mut client, "WATCH a"),
+            "-ERR WATCH inside MULTI is not allowed\r\n"
+        );
+        assert_eq!(send(&mut server, &mut client, "SET a 1"), "+QUEUED\r\n");
+        assert_eq!(send(&mut server, &mut client, "EXEC"), "*1\r\n+OK\r\n");
+    }
+
+    #[test]
+    fn test_maxmemory() {
+        //a limit of a byte is always exceeded
+        let mut server = event_loop(&["--maxmemory", "1"]);
+        server.execute(1, request(&["SET", "a", "v"]));
+        server.execute(1, request(&["SET", "b", "v", "EX", "100"]));
+        let oom = Err(RespType::SimpleError {
+            content: "OOM command not allowed when used memory > 'maxmemory'.".into(),
+        });
+
+        //nothing is evicted by default, only the writes that take memory are refused
+        assert_eq!(server.check_memory(&request(&["SET", "c", "v"])), oom);
+        assert_eq!(server.check_memory(&request(&["DEL", "a"])), Ok(()));
+        assert_eq!(server.stats.evicted_keys, 0);
+
+        server
+            .config
+            .set_all(&[("maxmemory-policy".into(), "volatile-ttl".into())])
+            .unwrap();
+        assert_eq!(server.check_memory(&request(&["SET", "c", "v"])), oom);
+        assert_eq!(server.stats.evicted_keys, 1);
+        assert!(server.redis.contains_key("a") && !server.redis.contains_key("b"));
+
+        server
+            .config
+            .set_all(&[("maxmemory-policy".into(), "allkeys-random".into())])
+            .unwrap();
+        assert_eq!(server.check_memory(&request(&["SET", "c", "v"])), oom);
+        assert_eq!(server.stats.evicted_keys, 2);
+        assert!(server.redis.is_empty());
     }
 }
@@ -0,0 +1,569 @@
+use std::{io, net::SocketAddr, path::Path};
+
+use crate::{
+    acl::{self, Acl, CATEGORIES, Context, DEFAULT_USER, LogEntry},
+    command::{AclSubcommand, Command},
+    ev_loop::EventLoop,
+    resp::RespType,
+};
+
+impl EventLoop {
+    /// Replaces the users with the ones of the ACL file, done once at startup.
+    pub(super) fn load_acl_file(&mut self, path: &Path) -> io::Result<()> {
+        self.acl = Acl::with_file(path).map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    /// The user new connections are authenticated as: the default one, unless it requires a
+    /// password or has been disabled.
+    pub(super) fn initial_user(&self) -> Option<String> {
+        self.acl
+            .user(DEFAULT_USER)
+            .filter(|user| user.is_enabled() && user.is_nopass())
+            .map(|_| DEFAULT_USER.to_string())
+    }
+
+    /// Applies requirepass: the password of the default user, none when empty.
+    pub(super) fn set_requirepass(&mut self, password: &str) {
+        self.acl.set_default_password(password);
+    }
+
+    /// True if protected mode refuses the connection: it comes from another host while
+    /// anyone could log in as the default user. Local IPv4 clients of a dual-stack listener
+    /// arrive as IPv4-mapped IPv6 addresses.
+    pub(super) fn protected_mode_denies(&self, addr: &SocketAddr) -> bool {
+        self.config.protected_mode()
+            && !addr.ip().to_canonical().is_loopback()
+            && self
+                .acl
+                .user(DEFAULT_USER)
+                .is_some_and(|user| user.is_enabled() && user.is_nopass())
+    }
+
+    //the user the client authenticated as, as long as it still exists
+    fn client_user(&self, client_id: i32) -> Option<(&str, &acl::User)> {
+        let username = self.clients.get(&client_id)?.user()?;
+        let user = self.acl.user(username)?;
+        Some((username, user))
+    }
+
+    /// Checks the command against the permissions of the client user, failures are logged.
+    /// Clients that are not authenticated can only run AUTH.
+    pub(super) fn check_permissions(
+        &mut self,
+        client_id: i32,
+        cmd: &Command,
+        context: Context,
+    ) -> Result<(), RespType> {
+        let Some((username, user)) = self.client_user(client_id) else {
+            //HELLO can authenticate too, it refuses to do anything else by itself
+            if matches!(cmd, Command::Auth { .. } | Command::Hello { .. }) {
+                return Ok(());
+            }
+            return Err(RespType::SimpleError {
+                content: "NOAUTH Authentication required.".into(),
+            });
+        };
+
+        let Err(denial) = user.check(cmd) else {
+            return Ok(());
+        };
+        let content = denial.error(username);
+        let username = username.to_string();
+        let client_info = self.client_info(client_id);
+        self.acl
+            .log_denial(&denial, context, &username, client_info);
+
+        Err(RespType::SimpleError { content })
+    }
+
+    pub(super) fn handle_auth(
+        &mut self,
+        client_id: i32,
+        username: Option<String>,
+        password: &str,
+    ) -> RespType {
+        //the legacy form is pointless while the default user has no password
+        if username.is_none()
+            && self
+                .acl
+                .user(DEFAULT_USER)
+                .is_some_and(|user| user.is_nopass())
+        {
+            return RespType::SimpleError {
+                content: "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".into(),
+            };
+        }
+
+        let username = username.unwrap_or_else(|| DEFAULT_USER.to_string());
+        if self.acl.authenticate(&username, password).is_none() {
+            let client_info = self.client_info(client_id);
+            self.acl.log_auth_failure(&username, client_info);
+            return RespType::SimpleError {
+                content: "WRONGPASS invalid username-password pair or user is disabled.".into(),
+            };
+        }
+
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.set_user(Some(username));
+        }
+        ok()
+    }
+
+    pub(super) fn handle_acl(&mut self, client_id: i32, subcommand: AclSubcommand) -> RespType {
+        let result = match subcommand {
+            AclSubcommand::SetUser { username, rules } => {
+                self.acl.set_user(&username, &rules).map(|_| ok())
+            }
+            AclSubcommand::GetUser { username } => Ok(self.get_user(&username)),
+            AclSubcommand::DelUser { usernames } => {
+                self.acl
+                    .delete_users(&usernames)
+                    .map(|deleted| RespType::Integer {
+                        integer: deleted as i64,
+                    })
+            }
+            AclSubcommand::List => Ok(RespType::Array {
+                elements: self
+                    .acl
+                    .users()
+                    .map(|(name, user)| bulk(&format!("user {name} {}", user.describe())))
+                    .collect(),
+            }),
+            AclSubcommand::Users => Ok(RespType::Array {
+                elements: self.acl.users().map(|(name, _)| bulk(name)).collect(),
+            }),
+            AclSubcommand::WhoAmI => Ok(bulk(
+                self.clients
+                    .get(&client_id)
+                    .and_then(|client| client.user())
+                    .unwrap_or(DEFAULT_USER),
+            )),
+            AclSubcommand::Cat { category: None } => Ok(RespType::Array {
+                elements: CATEGORIES.iter().map(|category| bulk(category)).collect(),
+            }),
+            AclSubcommand::Cat {
+                category: Some(category),
+            } => match acl::category_commands(&category) {
+                Some(commands) => Ok(RespType::Array {
+                    elements: commands.iter().map(|command| bulk(command)).collect(),
+                }),
+                None => Err(format!("ERR Unknown category '{category}'")),
+            },
+            AclSubcommand::DryRun { username, cmd } => match (self.acl.user(&username), *cmd) {
+                (None, _) => Err(format!("ERR User '{username}' not found")),
+                (_, Command::ErrorCmd { msg }) => Err(msg),
+                (Some(user), cmd) => Ok(match user.check(&cmd) {
+                    Ok(()) => ok(),
+                    Err(denial) => bulk(&denial.dry_run_message(&username)),
+                }),
+            },
+            AclSubcommand::Log { count } => Ok(RespType::Array {
+                elements: self
+                    .acl
+                    .log()
+                    .take(count.unwrap_or(10))
+                    .map(log_entry)
+                    .collect(),
+            }),
+            AclSubcommand::LogReset => {
+                self.acl.reset_log();
+                Ok(ok())
+            }
+            AclSubcommand::Load => self.acl.load().map(|_| ok()),
+            AclSubcommand::Save => self.acl.save().map(|_| ok()),
+        };
+
+        result.unwrap_or_else(|content| RespType::SimpleError { content })
+    }
+
+    fn get_user(&self, username: &str) -> RespType {
+        let Some(user) = self.acl.user(username) else {
+            return RespType::NullBulkString;
+        };
+
+        RespType::Map {
+            entries: vec![
+                (
+                    bulk("flags"),
+                    RespType::Array {
+                        elements: user.flags().into_iter().map(bulk).collect(),
+                    },
+                ),
+                (
+                    bulk("passwords"),
+                    RespType::Array {
+                        elements: user.passwords().map(|hash| bulk(hash)).collect(),
+                    },
+                ),
+                (bulk("commands"), bulk(&user.describe_commands())),
+                (bulk("keys"), bulk(&user.describe_keys())),
+                (bulk("channels"), bulk(&user.describe_channels())),
+                (bulk("selectors"), RespType::Array { elements: vec![] }),
+            ],
+        }
+    }
+
+    //how ACL LOG identifies the client
+    fn client_info(&self, client_id: i32) -> String {
+        let Some(client) = self.clients.get(&client_id) else {
+            return format!("id={client_id}");
+        };
+        let addr = client
+            .stream()
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+
+        format!(
+            "id={client_id} addr={addr} name={} user={}",
+            client.name().unwrap_or_default(),
+            client.user().unwrap_or_default()
+        )
+    }
+}
+
+/// The error sent to the connections refused by protected mode, before closing them.
+pub(super) fn protected_mode_denial() -> RespType {
+    let content = "DENIED Redis is running in protected mode because protected mode is enabled and no password is set for the default user. In this mode connections are only accepted from the loopback interface. If you want to connect from external computers to Redis you may adopt one of the following solutions: 1) Just disable protected mode sending the command 'CONFIG SET protected-mode no' from the loopback interface by connecting to Redis from the same host the server is running, however MAKE SURE Redis is not publicly accessible from internet if you do so. Use CONFIG REWRITE to make this change permanent. 2) Alternatively you can just disable the protected mode by editing the Redis configuration file, and setting the protected mode option to 'no', and then restarting the server. 3) If you started the server manually just for testing, restart it with the '--protected-mode no' option. 4) Set up an authentication password for the default user. NOTE: You only need to do one of the above things in order for the server to start accepting connections from the outside.";
+    RespType::SimpleError {
+        content: content.into(),
+    }
+}
+
+fn log_entry(entry: &LogEntry) -> RespType {
+    RespType::Map {
+        entries: vec![
+            (
+                bulk("count"),
+                RespType::Integer {
+                    integer: entry.count as i64,
+                },
+            ),
+            (bulk("reason"), bulk(entry.reason)),
+            (bulk("context"), bulk(entry.context)),
+            (bulk("object"), bulk(&entry.object)),
+            (bulk("username"), bulk(&entry.username)),
+            (
+                bulk("age-seconds"),
+                bulk(&format!("{:.3}", entry.age().as_secs_f64())),
+            ),
+            (bulk("client-info"), bulk(&entry.client_info)),
+            (
+                bulk("entry-id"),
+                RespType::Integer {
+                    integer: entry.entry_id as i64,
+                },
+            ),
+            (
+                bulk("timestamp-created"),
+                RespType::Integer {
+                    integer: LogEntry::timestamp(entry.created),
+                },
+            ),
+            (
+                bulk("timestamp-last-updated"),
+                RespType::Integer {
+                    integer: LogEntry::timestamp(entry.updated),
+                },
+            ),
+        ],
+    }
+}
+
+fn bulk(s: &str) -> RespType {
+    RespType::BulkString {
+        data: s.as_bytes().to_vec(),
+    }
+}
+
+fn ok() -> RespType {
+    RespType::SimpleString {
+        content: "OK".into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        acl::Context,
+        command::{AclSubcommand, Command},
+        ev_loop::{
+            EventLoop,
+            test_util::{client, event_loop, send},
+        },
+        resp::RespType,
+    };
+
+    fn acl(event_loop: &mut EventLoop, subcommand: AclSubcommand) -> RespType {
+        event_loop.handle_acl(1, subcommand)
+    }
+
+    #[test]
+    fn test_auth() {
+        let mut event_loop = event_loop(&[]);
+        let client_id = 1;
+
+        let reply = event_loop.handle_auth(client_id, None, "pw");
+        assert!(
+            matches!(reply, RespType::SimpleError { content } if content.starts_with("ERR AUTH <password> called without any password"))
+        );
+
+        acl(
+            &mut event_loop,
+            AclSubcommand::SetUser {
+                username: "alice".into(),
+                rules: vec!["on".into(), ">pw".into(), "+get".into(), "~k*".into()],
+            },
+        );
+        assert_eq!(
+            event_loop.handle_auth(client_id, Some("alice".into()), "nope"),
+            RespType::SimpleError {
+                content: "WRONGPASS invalid username-password pair or user is disabled.".into()
+            }
+        );
+        assert_eq!(
+            event_loop.handle_auth(client_id, Some("alice".into()), "pw"),
+            RespType::SimpleString {
+                content: "OK".into()
+            }
+        );
+        assert_eq!(
+            acl(&mut event_loop, AclSubcommand::WhoAmI),
+            RespType::BulkString {
+                data: b"alice".to_vec()
+            }
+        );
+
+        let get = Command::Get { key: "k1".into() };
+        assert!(
+            event_loop
+                .check_permissions(client_id, &get, Context::TopLevel)
+                .is_ok()
+        );
+        let get = Command::Get { key: "x".into() };
+        assert_eq!(
+            event_loop.check_permissions(client_id, &get, Context::TopLevel),
+            Err(RespType::SimpleError {
+                content: "NOPERM No permissions to access a key".into()
+            })
+        );
+
+        //the failed AUTH and the denied key were logged
+        let RespType::Array { elements } = acl(&mut event_loop, AclSubcommand::Log { count: None })
+        else {
+            panic!("expected an array");
+        };
+        assert_eq!(elements.len(), 2);
+
+        //deleting the user logs its clients out
+        acl(
+            &mut event_loop,
+            AclSubcommand::DelUser {
+                usernames: vec!["alice".into()],
+            },
+        );
+        assert_eq!(
+            event_loop.check_permissions(
+                client_id,
+                &Command::Ping { message: None },
+                Context::TopLevel
+            ),
+            Err(RespType::SimpleError {
+                content: "NOAUTH Authentication required.".into()
+            })
+        );
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut event_loop = event_loop(&[]);
+        acl(
+            &mut event_loop,
+            AclSubcommand::SetUser {
+                username: "bob".into(),
+                rules: vec!["on".into(), "+@read".into(), "~*".into()],
+            },
+        );
+
+        let dry_run = |cmd| AclSubcommand::DryRun {
+            username: "bob".into(),
+            cmd: Box::new(cmd),
+        };
+        assert_eq!(
+            acl(&mut event_loop, dry_run(Command::Get { key: "k".into() })),
+            RespType::SimpleString {
+                content: "OK".into()
+            }
+        );
+        assert_eq!(
+            acl(&mut event_loop, dry_run(Command::FlushAll)),
+            RespType::BulkString {
+                data: b"User bob has no permissions to run the 'flushall' command".to_vec()
+            }
+        );
+
+        let reply = acl(
+            &mut event_loop,
+            AclSubcommand::GetUser {
+                username: "bob".into(),
+            },
+        );
+        let RespType::Map { entries } = reply else {
+            panic!("expected a map");
+        };
+        assert_eq!(
+            entries[2].1,
+            RespType::BulkString {
+                data: b"-@all +@read".to_vec()
+            }
+        );
+    }
+
+    #[test]
+    fn test_protected_mode() {
+        let mut event_loop = event_loop(&[]);
+        let external = "10.0.0.1:4000".parse().unwrap();
+        let local = "127.0.0.1:4000".parse().unwrap();
+
+        assert!(event_loop.protected_mode_denies(&external));
+        assert!(!event_loop.protected_mode_denies(&local));
+
+        //with requirepass anyone can connect, to authenticate first
+        event_loop.set_requirepass("secret");
+        assert!(!event_loop.protected_mode_denies(&external));
+        assert_eq!(event_loop.initial_user(), None);
+        assert_eq!(
+            event_loop.handle_auth(1, None, "secret"),
+            RespType::SimpleString {
+                content: "OK".into()
+            }
+        );
+
+        event_loop.set_requirepass("");
+        event_loop
+            .config
+            .set("protected-mode", &["no".to_string()])
+            .unwrap();
+        assert!(!event_loop.protected_mode_denies(&external));
+        assert_eq!(event_loop.initial_user(), Some("default".into()));
+    }
+
+    #[test]
+    fn test_requirepass() {
+        let mut server = event_loop(&["--requirepass", "secret"]);
+        let mut first = client(&server);
+        let wrongpass = "-WRONGPASS invalid username-password pair or user is disabled.\r\n";
+
+        //only AUTH is served until the client authenticates
+        assert_eq!(
+            send(&mut server, &mut first, "GET a"),
+            "-NOAUTH Authentication required.\r\n"
+        );
+        assert_eq!(send(&mut server, &mut first, "AUTH wrong"), wrongpass);
+        assert_eq!(
+            send(&mut server, &mut first, "AUTH default wrong"),
+            wrongpass
+        );
+        assert_eq!(send(&mut server, &mut first, "AUTH bob secret"), wrongpass);
+        assert_eq!(send(&mut server, &mut first, "AUTH secret"), "+OK\r\n");
+        assert_eq!(send(&mut server, &mut first, "SET a 1"), "+OK\r\n");
+
+        //a new password is for the next authentications, the clients logged in stay so
+        assert_eq!(
+            send(&mut server, &mut first, "CONFIG SET requirepass other"),
+            "+OK\r\n"
+        );
+        assert_eq!(send(&mut server, &mut first, "GET a"), "$1\r\n1\r\n");
+        let mut second = client(&server);
+        assert_eq!(send(&mut server, &mut second, "AUTH secret"), wrongpass);
+        assert_eq!(
+            send(&mut server, &mut second, "AUTH default other"),
+            "+OK\r\n"
+        );
+
+        //the failures are logged, the ones for the same user as a single entry
+        let RespType::Array { elements } = acl(&mut server, AclSubcommand::Log { count: None })
+        else {
+            panic!("expected an array");
+        };
+        assert_eq!(elements.len(), 2);
+    }
+
+    #[test]
+    fn test_hello_auth() {
+        let mut server = event_loop(&["--requirepass", "secret"]);
+        let mut client = client(&server);
+
+        //HELLO alone needs the client authenticated already
+        let noauth = send(&mut server, &mut client, "HELLO 3");
+        assert!(
+            noauth.starts_with("-NOAUTH HELLO must be called"),
+            "{noauth}"
+        );
+        assert_eq!(
+            send(&mut server, &mut client, "HELLO 3 AUTH default wrong"),
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n"
+        );
+
+        //authenticated and named, then switched to RESP3
+        let hello = send(
+            &mut server,
+            &mut client,
+            "HELLO 3 AUTH default secret SETNAME app",
+        );
+        assert!(hello.starts_with("%7\r\n"), "{hello}");
+        assert_eq!(send(&mut server, &mut client, "SET a 1"), "+OK\r\n");
+        assert!(
+            server
+                .clients
+                .values()
+                .any(|client| client.name() == Some("app"))
+        );
+        assert_eq!(
+            send(&mut server, &mut client, "HELLO 3 SETNAME"),
+            "-ERR Syntax error in HELLO option 'SETNAME'\r\n"
+        );
+        assert_eq!(
+            send(&mut server, &mut client, "HELLO 3 SETNAME a\nb"),
+            "-ERR Client names cannot contain spaces, newlines or special characters.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_protected_mode_edge_cases() {
+        let mut event_loop = event_loop(&[]);
+        let external = "[2001:db8::1]:4000".parse().unwrap();
+
+        //the IPv6 loopback is local too, other IPv6 hosts are not
+        assert!(!event_loop.protected_mode_denies(&"[::1]:4000".parse().unwrap()));
+        assert!(event_loop.protected_mode_denies(&external));
+
+        //so is the IPv4 loopback mapped by a dual-stack listener, unlike other mapped hosts
+        let mapped = "[::ffff:127.0.0.1]:4000".parse().unwrap();
+        assert!(!event_loop.protected_mode_denies(&mapped));
+        let mapped = "[::ffff:192.0.2.1]:4000".parse().unwrap();
+        assert!(event_loop.protected_mode_denies(&mapped));
+
+        //other users with passwords don't matter while the default one can log in freely
+        acl(
+            &mut event_loop,
+            AclSubcommand::SetUser {
+                username: "bob".into(),
+                rules: vec!["on".into(), ">pw".into()],
+            },
+        );
+        assert!(event_loop.protected_mode_denies(&external));
+
+        //nobody can log in as a disabled default user
+        acl(
+            &mut event_loop,
+            AclSubcommand::SetUser {
+                username: "default".into(),
+                rules: vec!["off".into()],
+            },
+        );
+        assert!(!event_loop.protected_mode_denies(&external));
+        assert_eq!(event_loop.initial_user(), None);
+    }
+}
@@ -0,0 +1,968 @@
+//! The append only file: the changes to the dataset are appended to it as the commands making
+//! them, which are run again at startup. It is split in a base, an RDB snapshot, followed by
+//! incremental files holding the commands run since, all listed in a manifest. A rewrite
+//! replaces them with a new base and the incremental file opened when it started.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write as _},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use crate::{command::Command, ev_loop::EventLoop, rdb::RDB_MAGIC, resp::RespType};
+
+use super::persistence::ChildKind;
+
+//the commands found in the AOF are run on behalf of this client, which never exists
+const AOF_CLIENT_ID: i32 = -1;
+//a failed automatic rewrite is only retried after this delay
+const REWRITE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The state of the AOF and what INFO needs to know about its rewrites.
+#[derive(Debug)]
+pub(super) struct Aof {
+    //the incremental file the changes are appended to, None while the AOF is off
+    file: Option<File>,
+    manifest: Manifest,
+    //changes were written since the last fsync
+    unsynced: bool,
+    //the replication offset reached at the last fsync, for WAITAOF
+    fsynced_offset: u64,
+    last_fsync: Instant,
+    last_write_ok: bool,
+    //size of the files after the last rewrite and now, the automatic rewrites compare them
+    base_size: u64,
+    current_size: u64,
+    //BGREWRITEAOF was called while a snapshot was being taken
+    rewrite_scheduled: bool,
+    last_rewrite_ok: bool,
+    last_rewrite_duration: Option<Duration>,
+    last_rewrite_try: Option<Instant>,
+    rewrites: u64,
+}
+
+impl Aof {
+    pub(super) fn new() -> Self {
+        Self {
+            file: None,
+            manifest: Manifest::default(),
+            unsynced: false,
+            fsynced_offset: 0,
+            last_fsync: Instant::now(),
+            last_write_ok: true,
+            base_size: 0,
+            current_size: 0,
+            rewrite_scheduled: false,
+            last_rewrite_ok: true,
+            last_rewrite_duration: None,
+            last_rewrite_try: None,
+            rewrites: 0,
+        }
+    }
+}
+
+/// The files making the AOF, in the order they are loaded.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Manifest {
+    base: Option<AofFile>,
+    //the changes are appended to the last one
+    incrs: Vec<AofFile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct AofFile {
+    name: String,
+    seq: u64,
+}
+
+impl Manifest {
+    //one `file <name> seq <seq> type <b|h|i>` line per file, history files are ignored
+    fn parse(text: &str) -> io::Result<Manifest> {
+        let invalid = || io::Error::other("Invalid AOF manifest file format");
+        let mut manifest = Manifest::default();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if !words.len().is_multiple_of(2) {
+                return Err(invalid());
+            }
+            let field = |name: &str| {
+                words
+                    .chunks(2)
+                    .find(|pair| pair[0] == name)
+                    .map(|pair| pair[1])
+                    .ok_or_else(invalid)
+            };
+
+            let file = AofFile {
+                name: field("file")?.to_string(),
+                seq: field("seq")?.parse().map_err(|_| invalid())?,
+            };
+            match field("type")? {
+                "b" if manifest.base.is_none() => manifest.base = Some(file),
+                "i" => manifest.incrs.push(file),
+                "h" => {}
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    fn to_text(&self) -> String {
+        let base = self.base.iter().map(|file| (file, 'b'));
+        let incrs = self.incrs.iter().map(|file| (file, 'i'));
+
+        base.chain(incrs)
+            .map(|(file, kind)| format!("file {} seq {} type {kind}\n", file.name, file.seq))
+            .collect()
+    }
+
+    fn files(&self) -> impl Iterator<Item = &AofFile> {
+        self.base.iter().chain(&self.incrs)
+    }
+
+    fn next_base(&self, prefix: &str) -> AofFile {
+        let seq = self.base.as_ref().map_or(1, |base| base.seq + 1);
+        AofFile {
+            name: format!("{prefix}.{seq}.base.rdb"),
+            seq,
+        }
+    }
+
+    fn next_incr(&self, prefix: &str) -> AofFile {
+        let seq = self.incrs.last().map_or(1, |incr| incr.seq + 1);
+        AofFile {
+            name: format!("{prefix}.{seq}.incr.aof"),
+            seq,
+        }
+    }
+}
+
+impl EventLoop {
+    /// Loads the dataset from the AOF when it is enabled and from the RDB file otherwise, then
+    /// opens the AOF for the changes to be appended to it.
+    pub fn load(&mut self) -> io::Result<()> {
+        if !self.config.appendonly() {
+            return self.load_rdb();
+        }
+
+        //an RDB file is only used until an AOF is created out of it
+        if !self.load_aof()? {
+            self.load_rdb()?;
+        }
+        self.open_aof()
+    }
+
+    pub(super) fn handle_bgrewriteaof(&mut self) -> RespType {
+        match self.snapshots.child_kind() {
+            Some(ChildKind::AofRewrite { .. }) => RespType::SimpleError {
+                content: "ERR Background append only file rewriting already in progress".into(),
+            },
+            Some(ChildKind::Save) => {
+                self.aof.rewrite_scheduled = true;
+                RespType::SimpleString {
+                    content: "Background append only file rewriting scheduled".into(),
+                }
+            }
+            None => match self.start_aof_rewrite() {
+                Ok(()) => RespType::SimpleString {
+                    content: "Background append only file rewriting started".into(),
+                },
+                Err(err) => {
+                    println!("Can't rewrite append only file in background: {err}");
+                    RespType::SimpleError {
+                        content: "ERR Can't execute an AOF background rewriting. Please check the server logs for more information.".into(),
+                    }
+                }
+            },
+        }
+    }
+
+    /// Turns the AOF on or off after appendonly changed at runtime.
+    pub(super) fn apply_appendonly(&mut self) -> io::Result<()> {
+        match (self.config.appendonly(), self.aof.file.is_some()) {
+            (true, false) => {
+                //a rewrite started while the AOF was off would replace the files created here
+                if let Some(ChildKind::AofRewrite { .. }) = self.snapshots.child_kind() {
+                    self.kill_child();
+                }
+                self.create_aof()
+            }
+            (false, true) => {
+                if let Some(ChildKind::AofRewrite { .. }) = self.snapshots.child_kind() {
+                    self.kill_child();
+                }
+                let file = self.aof.file.take().expect("checked above");
+                self.aof.unsynced = false;
+                file.sync_data()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends the changes to the AOF, if it is on. With appendfsync always they are on disk
+    /// when this returns, a failure to write them is then fatal.
+    pub(super) fn feed_aof(&mut self, changes: &[RespType]) -> io::Result<()> {
+        let Some(file) = self.aof.file.as_mut() else {
+            return Ok(());
+        };
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let data: Vec<u8> = changes.iter().flat_map(RespType::serialize).collect();
+        let always = self.config.appendfsync() == "always";
+        let written = file
+            .write_all(&data)
+            .and_then(|_| if always { file.sync_data() } else { Ok(()) });
+
+        match written {
+            Ok(()) => {
+                self.aof.current_size += data.len() as u64;
+                self.aof.last_write_ok = true;
+                if always {
+                    self.aof.last_fsync = Instant::now();
+                } else if !self.aof.unsynced {
+                    //the changes are only counted in the replication offset once written
+                    self.aof.fsynced_offset = self.replication_offset();
+                    self.aof.unsynced = true;
+                }
+                Ok(())
+            }
+            Err(err) if always => {
+                println!(
+                    "Can't recover from AOF write error when the AOF fsync policy is 'always'. Exiting..."
+                );
+                Err(err)
+            }
+            Err(err) => {
+                println!("Error writing to the AOF file: {err}");
+                self.aof.last_write_ok = false;
+                Ok(())
+            }
+        }
+    }
+
+    /// The replication offset the AOF is synced up to, None while it is off. With appendfsync
+    /// no the system syncs it when it sees fit, the changes count as synced once written.
+    pub(super) fn aof_fsynced_offset(&self) -> Option<u64> {
+        self.aof.file.as_ref()?;
+        match self.aof.unsynced && self.config.appendfsync() != "no" {
+            true => Some(self.aof.fsynced_offset),
+            false => Some(self.replication_offset()),
+        }
+    }
+
+    /// Called from every iteration of the event loop: syncs the AOF every second with
+    /// appendfsync everysec and starts the scheduled or automatic rewrites.
+    pub(super) fn aof_cron(&mut self) {
+        if let Some(file) = &self.aof.file
+            && self.aof.unsynced
+            && self.config.appendfsync() == "everysec"
+            && self.aof.last_fsync.elapsed() >= Duration::from_secs(1)
+        {
+            match file.sync_data() {
+                Ok(()) => self.aof.unsynced = false,
+                Err(err) => println!("Error syncing the AOF file: {err}"),
+            }
+            self.aof.last_fsync = Instant::now();
+        }
+
+        if self.snapshots.child_kind().is_some() {
+            return;
+        }
+
+        if self.aof.rewrite_scheduled {
+            self.aof.rewrite_scheduled = false;
+            if let Err(err) = self.start_aof_rewrite() {
+                println!("Can't rewrite append only file in background: {err}");
+            }
+            return;
+        }
+
+        let can_retry = self.aof.last_rewrite_ok
+            || self
+                .aof
+                .last_rewrite_try
+                .is_none_or(|tried| tried.elapsed() > REWRITE_RETRY_DELAY);
+        let base_size = self.aof.base_size.max(1);
+        let growth = self.aof.current_size.saturating_sub(base_size) * 100 / base_size;
+
+        if self.aof.file.is_some()
+            && can_retry
+            && self.aof.current_size > self.config.auto_aof_rewrite_min_size()
+            && let Some(percentage) = self.config.auto_aof_rewrite_percentage()
+            && growth >= percentage
+        {
+            println!("Starting automatic rewriting of AOF on {growth}% growth");
+            if let Err(err) = self.start_aof_rewrite() {
+                println!("Can't rewrite append only file in background: {err}");
+            }
+        }
+    }
+
+    /// The AOF fields of the persistence section of INFO.
+    pub(super) fn aof_info(&self) -> Vec<(&'static str, String)> {
+        let seconds = |duration: Option<Duration>| match duration {
+            Some(duration) => duration.as_secs().to_string(),
+            None => "-1".to_string(),
+        };
+        let status = |ok: bool| if ok { "ok" } else { "err" }.to_string();
+        let rewriting = self
+            .snapshots
+            .child_running_for(|kind| matches!(kind, ChildKind::AofRewrite { .. }));
+        let aof = &self.aof;
+
+        let mut fields = vec![
+            ("aof_enabled", (aof.file.is_some() as u8).to_string()),
+            (
+                "aof_rewrite_in_progress",
+                (rewriting.is_some() as u8).to_string(),
+            ),
+            (
+                "aof_rewrite_scheduled",
+                (aof.rewrite_scheduled as u8).to_string(),
+            ),
+            (
+                "aof_last_rewrite_time_sec",
+                seconds(aof.last_rewrite_duration),
+            ),
+            ("aof_current_rewrite_time_sec", seconds(rewriting)),
+            ("aof_last_bgrewrite_status", status(aof.last_rewrite_ok)),
+            ("aof_rewrites", aof.rewrites.to_string()),
+            ("aof_last_write_status", status(aof.last_write_ok)),
+        ];
+        if aof.file.is_some() {
+            fields.extend([
+                ("aof_current_size", aof.current_size.to_string()),
+                ("aof_base_size", aof.base_size.to_string()),
+            ]);
+        }
+        fields
+    }
+
+    //false if there is no AOF yet
+    fn load_aof(&mut self) -> io::Result<bool> {
+        let Some(manifest) = self.read_manifest()? else {
+            return Ok(false);
+        };
+        let started = Instant::now();
+
+        if let Some(base) = &manifest.base {
+            let data = fs::read(self.aof_path(&base.name))?;
+            //a base written as commands rather than as a snapshot is loaded like the others
+            let loaded = match data.starts_with(RDB_MAGIC) {
+                true => self.load_snapshot(&data).map(|_| data.len()),
+                false => self.replay(&data),
+            };
+            if loaded.map_err(|err| bad_format(&base.name, err))? < data.len() {
+                return Err(io::Error::other(format!(
+                    "Unexpected end of file reading the append only file {}",
+                    base.name
+                )));
+            }
+        }
+
+        for (i, incr) in manifest.incrs.iter().enumerate() {
+            let path = self.aof_path(&incr.name);
+            let data = fs::read(&path)?;
+            let loaded = self
+                .replay(&data)
+                .map_err(|err| bad_format(&incr.name, err))?;
+            if loaded == data.len() {
+                continue;
+            }
+
+            //only the last file can have been cut short, by a crash while it was written
+            if i + 1 < manifest.incrs.len() || !self.config.aof_load_truncated() {
+                return Err(io::Error::other(format!(
+                    "Unexpected end of file reading the append only file {}. You can set the 'aof-load-truncated' configuration option to yes and restart the server.",
+                    incr.name
+                )));
+            }
+            println!(
+                "!!! Warning: short read while loading the AOF file {}!!!",
+                incr.name
+            );
+            println!(
+                "AOF {} loaded anyway because aof-load-truncated is enabled",
+                incr.name
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(loaded as u64)?;
+        }
+
+        //the replayed commands are already in the files
+        self.redis.propagated.clear();
+        self.aof.manifest = manifest;
+        self.aof.base_size = self.aof_size(true);
+        self.aof.current_size = self.aof_size(false);
+
+        println!(
+            "DB loaded from append only file: {:.3} seconds",
+            started.elapsed().as_secs_f64()
+        );
+        Ok(true)
+    }
+
+    //runs the commands, returning how many bytes hold complete ones: the rest is a command
+    //or a transaction cut short
+    fn replay(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut pos = 0;
+        let mut loaded = 0;
+        let mut transaction: Option<Vec<Command>> = None;
+
+        while pos < data.len() {
+            let (request, len) = match RespType::parse(&data[pos..]) {
+                Ok(parsed) => parsed,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            };
+            pos += len;
+
+            self.replay_command(Command::from(request), AOF_CLIENT_ID, &mut transaction)?;
+
+            if transaction.is_none() {
+                loaded = pos;
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    /// Runs a command read from the AOF or received from the master, the ones between MULTI
+    /// and EXEC are kept in transaction until EXEC runs them all at once.
+    pub(super) fn replay_command(
+        &mut self,
+        cmd: Command,
+        client_id: i32,
+        transaction: &mut Option<Vec<Command>>,
+    ) -> io::Result<()> {
+        match cmd {
+            Command::Multi => *transaction = Some(vec![]),
+            Command::Exec => {
+                for cmd in transaction.take().unwrap_or_default() {
+                    self.replay_command(cmd, client_id, &mut None)?;
+                }
+            }
+            cmd if transaction.is_some() => transaction.as_mut().expect("checked").push(cmd),
+            Command::Function { subcommand } => {
+                self.handle_function(subcommand);
+            }
+            Command::ErrorCmd { msg } => return Err(io::Error::other(msg)),
+            //served by the event loop, never written by this server, i.e. a hand edited file
+            cmd @ (Command::Hello { .. }
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::PSubscribe { .. }
+            | Command::PUnsubscribe { .. }
+            | Command::Publish { .. }
+            | Command::SSubscribe { .. }
+            | Command::SUnsubscribe { .. }
+            | Command::SPublish { .. }
+            | Command::PubSub { .. }
+            | Command::Discard
+            | Command::Eval { .. }
+            | Command::EvalSha { .. }
+            | Command::Script { .. }
+            | Command::FCall { .. }
+            | Command::Auth { .. }
+            | Command::Acl { .. }
+            | Command::Config { .. }
+            | Command::Info { .. }
+            | Command::Save
+            | Command::BgSave { .. }
+            | Command::LastSave
+            | Command::BgRewriteAof
+            | Command::ReplicaOf { .. }
+            | Command::ReplConf { .. }
+            | Command::PSync { .. }
+            | Command::Role
+            | Command::Cluster { .. }
+            | Command::Asking
+            | Command::Sentinel { .. }
+            | Command::Migrate { .. }
+            | Command::Wait { .. }
+            | Command::WaitAof { .. }) => {
+                return Err(io::Error::other(format!(
+                    "unsupported command in AOF: {}",
+                    cmd.full_name().unwrap_or_default()
+                )));
+            }
+            //the replies don't matter, the commands succeeded when they were first run
+            cmd => {
+                let _ = self.redis.handle_command(cmd, client_id);
+            }
+        }
+        Ok(())
+    }
+
+    //opens the last incremental file at startup, the AOF is created if there is none yet
+    fn open_aof(&mut self) -> io::Result<()> {
+        if self.aof.manifest.base.is_none() && self.aof.manifest.incrs.is_empty() {
+            return self.create_aof();
+        }
+
+        if self.aof.manifest.incrs.is_empty() {
+            let incr = self.aof.manifest.next_incr(self.config.appendfilename());
+            self.aof.manifest.incrs.push(incr);
+            self.write_manifest(&self.aof.manifest)?;
+        }
+
+        let incr = self.aof.manifest.incrs.last().expect("checked above");
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.aof_path(&incr.name))?;
+        self.aof.file = Some(file);
+        Ok(())
+    }
+
+    /// Starts the AOF over from the dataset, once a full resynchronization with the master
+    /// replaced it.
+    pub(super) fn restart_aof(&mut self) -> io::Result<()> {
+        if self.aof.file.is_none() {
+            return Ok(());
+        }
+        if let Some(ChildKind::AofRewrite { .. }) = self.snapshots.child_kind() {
+            self.kill_child();
+        }
+        self.create_aof()
+    }
+
+    //writes the dataset as the base of a brand new AOF, the previous files are removed
+    fn create_aof(&mut self) -> io::Result<()> {
+        fs::create_dir_all(self.aof_dir())?;
+        let previous = self.read_manifest().ok().flatten().unwrap_or_default();
+        let prefix = self.config.appendfilename();
+        let (base, incr) = (previous.next_base(prefix), previous.next_incr(prefix));
+
+        println!("Creating AOF base file {}", base.name);
+        self.write_snapshot(&self.aof_path(&base.name), true)?;
+        let file = File::create(self.aof_path(&incr.name))?;
+
+        let manifest = Manifest {
+            base: Some(base),
+            incrs: vec![incr],
+        };
+        self.write_manifest(&manifest)?;
+        self.remove_aof_files(&previous, &manifest);
+
+        self.aof.file = Some(file);
+        self.aof.manifest = manifest;
+        self.aof.base_size = self.aof_size(true);
+        self.aof.current_size = self.aof.base_size;
+        Ok(())
+    }
+
+    //the changes made from now on go to a new incremental file while the child writes the
+    //dataset as it is now to the new base
+    fn start_aof_rewrite(&mut self) -> io::Result<()> {
+        self.aof.last_rewrite_try = Some(Instant::now());
+        fs::create_dir_all(self.aof_dir())?;
+        let prefix = self.config.appendfilename().to_string();
+
+        if let Some(file) = &self.aof.file {
+            file.sync_data()?;
+            let incr = self.aof.manifest.next_incr(&prefix);
+            let file = File::create(self.aof_path(&incr.name))?;
+
+            let mut manifest = self.aof.manifest.clone();
+            manifest.incrs.push(incr);
+            self.write_manifest(&manifest)?;
+            self.aof.manifest = manifest;
+            self.aof.file = Some(file);
+            self.aof.unsynced = false;
+        } else {
+            //the files of an AOF turned off are replaced, the new ones go after them
+            self.aof.manifest = self.read_manifest()?.unwrap_or_default();
+        }
+
+        let base = self.aof.manifest.next_base(&prefix);
+        let path = self.aof_path(&base.name);
+        let forked = self.fork_child(ChildKind::AofRewrite { base }, |event_loop| {
+            event_loop.write_snapshot(&path, true).inspect_err(|err| {
+                println!("Error rewriting the append only file: {err}");
+            })
+        });
+        match forked {
+            Ok(pid) => {
+                println!("Background append only file rewriting started by pid {pid}");
+                Ok(())
+            }
+            Err(err) => {
+                self.aof.last_rewrite_ok = false;
+                Err(err)
+            }
+        }
+    }
+
+    /// Called once the rewrite child is gone: on success the new base replaces the files
+    /// written before the rewrite started.
+    pub(super) fn aof_rewrite_done(&mut self, ok: bool, base: AofFile, duration: Duration) {
+        self.aof.last_rewrite_duration = Some(duration);
+        self.aof.last_rewrite_ok = ok;
+        if !ok {
+            let _ = fs::remove_file(self.aof_path(&base.name));
+            return;
+        }
+
+        let previous = self.aof.manifest.clone();
+        let incrs = match self.aof.file {
+            Some(_) => previous.incrs.last().cloned().into_iter().collect(),
+            None => vec![],
+        };
+        let manifest = Manifest {
+            base: Some(base),
+            incrs,
+        };
+        if let Err(err) = self.write_manifest(&manifest) {
+            println!("Error trying to persist the AOF manifest: {err}");
+            self.aof.last_rewrite_ok = false;
+            let _ = fs::remove_file(self.aof_path(&manifest.base.expect("set above").name));
+            return;
+        }
+
+        self.remove_aof_files(&previous, &manifest);
+        self.aof.manifest = manifest;
+        self.aof.base_size = self.aof_size(true);
+        self.aof.current_size = self.aof_size(false);
+        self.aof.rewrites += 1;
+        println!("Background AOF rewrite finished successfully");
+    }
+
+    fn read_manifest(&self) -> io::Result<Option<Manifest>> {
+        let path = self.aof_path(&format!("{}.manifest", self.config.appendfilename()));
+        match fs::read_to_string(path) {
+            Ok(text) => Manifest::parse(&text).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    //the manifest is replaced at once, so that it always lists complete files
+    fn write_manifest(&self, manifest: &Manifest) -> io::Result<()> {
+        let name = format!("{}.manifest", self.config.appendfilename());
+        let temp = self.aof_path(&format!("temp-{name}"));
+
+        let mut file = File::create(&temp)?;
+        file.write_all(manifest.to_text().as_bytes())
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&temp, self.aof_path(&name)))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&temp);
+            })
+    }
+
+    //the files of the previous manifest that the current one doesn't list anymore
+    fn remove_aof_files(&self, previous: &Manifest, current: &Manifest) {
+        for file in previous.files() {
+            if current.files().all(|kept| kept.name != file.name) {
+                let _ = fs::remove_file(self.aof_path(&file.name));
+            }
+        }
+    }
+
+    //the size of the base only, or of all the files
+    fn aof_size(&self, base_only: bool) -> u64 {
+        let manifest = &self.aof.manifest;
+        let files: Vec<&AofFile> = match base_only {
+            true => manifest.base.iter().collect(),
+            false => manifest.files().collect(),
+        };
+
+        files
+            .iter()
+            .filter_map(|file| fs::metadata(self.aof_path(&file.name)).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    //in the configured dir, like the RDB file
+    fn aof_dir(&self) -> PathBuf {
+        self.config.dir().join(self.config.appenddirname())
+    }
+
+    fn aof_path(&self, name: &str) -> PathBuf {
+        self.aof_dir().join(name)
+    }
+}
+
+fn bad_format(name: &str, err: io::Error) -> io::Error {
+    io::Error::other(format!(
+        "Bad file format reading the append only file {name}: {err}"
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        io::Write as _,
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    use super::{AofFile, Manifest};
+    use crate::{
+        command::Command,
+        ev_loop::{
+            EventLoop,
+            test_util::{self, request},
+        },
+        resp::RespType,
+    };
+
+    fn event_loop(dir: &Path) -> EventLoop {
+        fs::create_dir_all(dir).unwrap();
+        test_util::event_loop(&["--appendonly", "yes", "--dir", dir.to_str().unwrap()])
+    }
+
+    fn get(server: &mut EventLoop, key: &str) -> Option<RespType> {
+        server.execute(1, Command::Get { key: key.into() })
+    }
+
+    #[test]
+    fn test_manifest() {
+        let text = "file appendonly.aof.2.base.rdb seq 2 type b\n\
+                    file appendonly.aof.1.incr.aof seq 1 type h\n\
+                    file appendonly.aof.2.incr.aof seq 2 type i\n\
+                    file appendonly.aof.3.incr.aof seq 3 type i\n";
+        let manifest = Manifest::parse(text).unwrap();
+        let file = |name: &str, seq| AofFile {
+            name: name.into(),
+            seq,
+        };
+
+        assert_eq!(
+            manifest,
+            Manifest {
+                base: Some(file("appendonly.aof.2.base.rdb", 2)),
+                incrs: vec![
+                    file("appendonly.aof.2.incr.aof", 2),
+                    file("appendonly.aof.3.incr.aof", 3)
+                ],
+            }
+        );
+        assert_eq!(
+            manifest.to_text(),
+            text.replace("file appendonly.aof.1.incr.aof seq 1 type h\n", "")
+        );
+        assert_eq!(
+            manifest.next_base("appendonly.aof"),
+            file("appendonly.aof.3.base.rdb", 3)
+        );
+        assert_eq!(
+            Manifest::default().next_incr("appendonly.aof"),
+            file("appendonly.aof.1.incr.aof", 1)
+        );
+
+        //the fields can come in any order, but all of them are needed
+        assert!(Manifest::parse("type b seq 1 file base.rdb").is_ok());
+        assert!(Manifest::parse("file base.rdb seq 1").is_err());
+        assert!(Manifest::parse("file base.rdb seq one type b").is_err());
+        assert!(Manifest::parse("file base.rdb seq 1 type x").is_err());
+    }
+
+    #[test]
+    fn test_load_and_truncated_tail() {
+        let dir = std::env::temp_dir().join(format!("test-aof-load-{}", std::process::id()));
+        let mut server = event_loop(&dir);
+        server.load().unwrap();
+
+        server.execute(1, request(&["SET", "volatile", "1", "PX", "100000"]));
+        server.execute(1, request(&["XADD", "stream", "*", "f", "v"]));
+        server.execute(1, request(&["RPUSH", "list", "a", "b"]));
+        server.propagate_changes().unwrap();
+        server.aof_cron();
+
+        let incr = dir.join("appendonlydir/appendonly.aof.1.incr.aof");
+        let written = String::from_utf8(fs::read(&incr).unwrap()).unwrap();
+        assert!(written.contains("PXAT"));
+        //the generated id rather than *
+        assert!(!written.contains("$1\r\n*\r\n"));
+
+        //a crash in the middle of a transaction
+        let mut file = fs::OpenOptions::new().append(true).open(&incr).unwrap();
+        file.write_all(b"*1\r\n$5\r\nMULTI\r\n*2\r\n$4\r\nLPOP\r\n$4\r\nlist\r\n*3\r\n$3\r\nSET")
+            .unwrap();
+
+        let mut strict = event_loop(&dir);
+        strict
+            .config
+            .set("aof-load-truncated", &["no".to_string()])
+            .unwrap();
+        let refused = strict.load();
+
+        let mut restarted = event_loop(&dir);
+        let loaded = restarted.load();
+        let truncated = fs::read(&incr).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(refused.is_err());
+        loaded.unwrap();
+        assert_eq!(truncated.len(), written.len());
+        assert_eq!(
+            get(&mut restarted, "volatile"),
+            Some(RespType::BulkString {
+                data: b"1".to_vec()
+            })
+        );
+        assert_eq!(
+            restarted.execute(1, request(&["LLEN", "list"])),
+            Some(RespType::Integer { integer: 2 })
+        );
+        assert_eq!(
+            restarted.execute(1, request(&["XLEN", "stream"])),
+            Some(RespType::Integer { integer: 1 })
+        );
+    }
+
+    #[test]
+    fn test_load_unsupported_command() {
+        let dir = std::env::temp_dir().join(format!("test-aof-eval-{}", std::process::id()));
+        let mut server = event_loop(&dir);
+        server.load().unwrap();
+
+        //never written by the server itself, the load fails rather than the server
+        let incr = dir.join("appendonlydir/appendonly.aof.1.incr.aof");
+        fs::write(&incr, b"*3\r\n$4\r\nEVAL\r\n$8\r\nreturn 1\r\n$1\r\n0\r\n").unwrap();
+        let mut restarted = event_loop(&dir);
+        let loaded = restarted.load();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            loaded.unwrap_err().to_string(),
+            "Bad file format reading the append only file appendonly.aof.1.incr.aof: unsupported command in AOF: eval"
+        );
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let dir = std::env::temp_dir().join(format!("test-aof-rewrite-{}", std::process::id()));
+        let mut server = event_loop(&dir);
+        server.load().unwrap();
+        server.execute(1, request(&["SET", "key", "old"]));
+        server.execute(1, request(&["SET", "key", "value"]));
+        server.propagate_changes().unwrap();
+
+        assert_eq!(
+            server.handle_bgrewriteaof(),
+            RespType::SimpleString {
+                content: "Background append only file rewriting started".into()
+            }
+        );
+        //written to the new incremental file while the base is being written
+        server.execute(1, request(&["SET", "other", "1"]));
+        server.propagate_changes().unwrap();
+
+        let started = Instant::now();
+        while server.snapshots.child_kind().is_some() && started.elapsed() < Duration::from_secs(10)
+        {
+            server.snapshots_cron();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let mut files: Vec<String> = fs::read_dir(dir.join("appendonlydir"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        let info = server.aof_info();
+
+        let mut restarted = event_loop(&dir);
+        let loaded = restarted.load();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            files,
+            [
+                "appendonly.aof.2.base.rdb",
+                "appendonly.aof.2.incr.aof",
+                "appendonly.aof.manifest"
+            ]
+        );
+        let field = |name: &str| &info.iter().find(|(field, _)| *field == name).unwrap().1;
+        assert_eq!(field("aof_rewrites"), "1");
+        assert_eq!(field("aof_last_bgrewrite_status"), "ok");
+
+        loaded.unwrap();
+        assert_eq!(
+            get(&mut restarted, "key"),
+            Some(RespType::BulkString {
+                data: b"value".to_vec()
+            })
+        );
+        assert_eq!(
+            get(&mut restarted, "other"),
+            Some(RespType::BulkString {
+                data: b"1".to_vec()
+            })
+        );
+    }
+
+    #[test]
+    fn test_waitaof() {
+        let dir = std::env::temp_dir().join(format!("test-aof-wait-{}", std::process::id()));
+        let mut server = event_loop(&dir);
+        server.load().unwrap();
+        let counts = |local: i64, replicas: i64| RespType::Array {
+            elements: vec![
+                RespType::Integer { integer: local },
+                RespType::Integer { integer: replicas },
+            ],
+        };
+        let write = |server: &mut EventLoop| {
+            server.execute(1, request(&["SET", "key", "value"]));
+            server.propagate_changes().unwrap();
+        };
+
+        //nothing written yet, nothing to wait for
+        assert_eq!(
+            server.execute(1, request(&["WAITAOF", "1", "0", "0"])),
+            Some(counts(1, 0))
+        );
+
+        //with everysec the write is synced within a second
+        write(&mut server);
+        assert_eq!(
+            server.execute(1, request(&["WAITAOF", "1", "0", "0"])),
+            None
+        );
+        server.aof.last_fsync = Instant::now() - Duration::from_secs(1);
+        server.aof_cron();
+        server.serve_waits();
+        assert_eq!(server.redis.ready.pop(), Some((1, counts(1, 0))));
+
+        //without replicas, the counts reached are given at the timeout
+        write(&mut server);
+        assert_eq!(
+            server.execute(1, request(&["WAITAOF", "0", "1", "1"])),
+            None
+        );
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(server.redis.remove_expired(), vec![(1, counts(0, 0))]);
+
+        //a client gone is not waiting anymore
+        assert_eq!(
+            server.execute(1, request(&["WAITAOF", "1", "0", "0"])),
+            None
+        );
+        //the client of the fixture is not polled, only unwatching its socket fails
+        let _ = server.disconnect(1);
+        server.aof.last_fsync = Instant::now() - Duration::from_secs(1);
+        server.aof_cron();
+        server.serve_waits();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(server.redis.ready.is_empty());
+        assert!(!server.redis.is_blocked(1));
+    }
+}
@@ -1,38 +1,192 @@
 use std::{
+    collections::HashSet,
     io::{self, Read as _, Write as _},
     net::TcpStream,
+    time::{Duration, Instant},
 };
 
-use crate::resp::RespType;
+use crate::{
+    command::Command,
+    resp::{Protocol, RespType},
+};
 
 #[derive(Debug)]
 pub(super) struct Client {
     stream: TcpStream,
+    //bytes received but not yet served, the requests following a blocking command wait in
+    //there until it is served
+    input: Vec<u8>,
     buffer: Vec<u8>,
+    protocol: Protocol,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    shard_channels: HashSet<String>,
+    //Some once MULTI has been issued, until EXEC or DISCARD
+    transaction: Option<Transaction>,
+    //the ACL user the client is authenticated as
+    user: Option<String>,
+    //set with HELLO SETNAME
+    name: Option<String>,
+    //when the client last sent something, idle ones are disconnected after the timeout
+    last_interaction: Instant,
+    //ASKING was sent, the next command can be for a slot being imported
+    asking: bool,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Transaction {
+    pub(super) commands: Vec<Command>,
+    //a command could not be queued, EXEC will discard the whole transaction
+    pub(super) aborted: bool,
 }
 
 impl Client {
-    pub(super) fn new(stream: TcpStream) -> Self {
+    pub(super) fn new(stream: TcpStream, user: Option<String>) -> Self {
         Self {
             stream,
+            input: vec![],
             buffer: vec![],
+            protocol: Protocol::default(),
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+            transaction: None,
+            user,
+            name: None,
+            last_interaction: Instant::now(),
+            asking: false,
         }
     }
 
-    pub(crate) fn read_raw_cmd(&mut self) -> Vec<u8> {
-        let mut buf = [0u8; 512];
+    pub(super) fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    pub(super) fn set_user(&mut self, user: Option<String>) {
+        self.user = user;
+    }
+
+    pub(super) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Names the client, an empty name removes it.
+    pub(super) fn set_name(&mut self, name: String) {
+        self.name = (!name.is_empty()).then_some(name);
+    }
+
+    /// How long since the client last sent something.
+    pub(super) fn idle(&self) -> Duration {
+        self.last_interaction.elapsed()
+    }
+
+    pub(super) fn transaction_mut(&mut self) -> Option<&mut Transaction> {
+        self.transaction.as_mut()
+    }
+
+    pub(super) fn start_transaction(&mut self) {
+        self.transaction = Some(Transaction::default());
+    }
+
+    pub(super) fn take_transaction(&mut self) -> Option<Transaction> {
+        self.transaction.take()
+    }
+
+    pub(super) fn set_asking(&mut self) {
+        self.asking = true;
+    }
+
+    //the flag only holds for the command following ASKING
+    pub(super) fn take_asking(&mut self) -> bool {
+        std::mem::take(&mut self.asking)
+    }
+
+    pub(super) fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub(super) fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    pub(super) fn channels(&self) -> &HashSet<String> {
+        &self.channels
+    }
+
+    pub(super) fn patterns(&self) -> &HashSet<String> {
+        &self.patterns
+    }
+
+    pub(super) fn channels_mut(&mut self) -> &mut HashSet<String> {
+        &mut self.channels
+    }
+
+    pub(super) fn patterns_mut(&mut self) -> &mut HashSet<String> {
+        &mut self.patterns
+    }
+
+    pub(super) fn shard_channels(&self) -> &HashSet<String> {
+        &self.shard_channels
+    }
+
+    pub(super) fn shard_channels_mut(&mut self) -> &mut HashSet<String> {
+        &mut self.shard_channels
+    }
+
+    /// Number of channels and patterns the client is subscribed to, shard channels are counted
+    /// on their own.
+    pub(super) fn subscriptions(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// RESP2 clients with at least a subscription can only issue pub/sub commands, RESP3 ones
+    /// receive messages as out of band push frames and are free to run anything.
+    pub(super) fn in_subscribe_mode(&self) -> bool {
+        self.protocol == Protocol::Resp2
+            && (self.subscriptions() > 0 || !self.shard_channels.is_empty())
+    }
+
+    /// Reads what the client sent, next_request then takes the requests one at a time.
+    pub(crate) fn read(&mut self) {
+        let mut buf = [0u8; 4096];
 
         match self.stream.read(&mut buf) {
+            Err(err) => println!("Could not read from socket, got error {err}"),
+            Ok(read) => {
+                self.input.extend_from_slice(&buf[..read]);
+                self.last_interaction = Instant::now();
+            }
+        }
+    }
+
+    /// The next request received, None until it is complete.
+    pub(crate) fn next_request(&mut self) -> Option<Result<RespType, io::Error>> {
+        if self.input.is_empty() {
+            return None;
+        }
+
+        match RespType::parse(&self.input) {
+            Ok((request, consumed)) => {
+                self.input.drain(..consumed);
+                Some(Ok(request))
+            }
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => None,
             Err(err) => {
-                let errmsg = format!("Could not read from socket, got error {err}");
-                errmsg.into_bytes()
+                //there is no way to know where the next request starts
+                self.input.clear();
+                Some(Err(err))
             }
-            Ok(read) => buf[..read].to_vec(),
         }
     }
 
     pub(crate) fn send(&mut self, response: RespType) {
-        self.buffer.append(&mut response.serialize());
+        self.buffer
+            .append(&mut response.for_protocol(self.protocol).serialize());
+    }
+
+    /// Queues bytes that are not a reply, like the stream of changes sent to a replica.
+    pub(super) fn send_raw(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
     }
 
     pub(crate) fn flush(&mut self) -> Result<(), io::Error> {
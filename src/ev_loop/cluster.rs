@@ -0,0 +1,1696 @@
+//! Cluster mode as the event loop serves it. Requests for keys are checked against the slots
+//! this node serves and redirected to the right node otherwise. The nodes keep each other
+//! informed over the cluster bus: every node opens a link to each of the others, on the port
+//! 10000 above the one of the clients, and pings it every second with the slots it serves and
+//! gossip about some of the other nodes, which is how they get to know each other.
+//!
+//! A node that doesn't answer the pings in time is possibly failing (PFAIL), it fails (FAIL)
+//! once the majority of the masters report it. The replicas of a failed master then hold an
+//! election, the one getting the votes of the majority of the masters replaces it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    hash::{BuildHasher as _, Hasher as _, RandomState},
+    io::{self, Read as _, Write as _},
+    net::{IpAddr, TcpListener, TcpStream},
+    os::fd::AsRawFd,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use libc::{EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLRDHUP};
+
+use crate::{
+    cluster::{Cluster, Gossip, Message, MessageType, Node, key_hash_slot, unix_time_ms},
+    command::{ClusterSubcommand, Command, SetSlotAction},
+    ev_loop::{EventLoop, replication::new_replid},
+    resp::RespType,
+};
+
+//delay between two attempts to connect to a node, also how long an attempt can take
+const CONNECT_RETRY_DELAY: Duration = Duration::from_secs(1);
+//every node is pinged this often
+const PING_PERIOD: Duration = Duration::from_secs(1);
+
+/// The cluster as this node knows it and its links to the other nodes.
+#[derive(Debug)]
+pub(super) struct ClusterState {
+    cluster: Cluster,
+    //accepts the links the other nodes open to this one
+    listener: TcpListener,
+    //by file descriptor, the links this node opened to the others and the ones they opened
+    links: HashMap<i32, Link>,
+    //by node id, when this node last tried to connect to it
+    last_connect: HashMap<String, Instant>,
+    last_ping: Instant,
+    messages_sent: u64,
+    messages_received: u64,
+    //whether the cluster was ok, as last logged
+    ok: bool,
+    //the election this node, a replica of a failed master, holds to replace it
+    election: Option<Election>,
+}
+
+#[derive(Debug)]
+struct Link {
+    stream: TcpStream,
+    //bytes received but not yet processed
+    input: Vec<u8>,
+    //for the links this node opened, the id of the node at the other end
+    node: Option<String>,
+    created: Instant,
+}
+
+#[derive(Debug)]
+struct Election {
+    //when the votes are asked for
+    auth_time: Instant,
+    //the number of replicas of the same master ahead of this one
+    rank: usize,
+    //the epoch the votes are asked in, once they are
+    epoch: Option<u64>,
+    //the masters that voted for this node
+    votes: HashSet<String>,
+}
+
+impl EventLoop {
+    /// With cluster-enabled, loads the cluster configuration, or starts a new one made of this
+    /// node alone, and listens for the other nodes on the cluster bus.
+    pub(super) fn configure_cluster(&mut self) -> io::Result<()> {
+        if !self.config.cluster_enabled() {
+            return Ok(());
+        }
+        let Some(cport) = self.config.cluster_port() else {
+            return Err(io::Error::other("The cluster bus port is out of range"));
+        };
+        let port = self.config.port();
+
+        let mut cluster = match fs::read_to_string(self.config.cluster_config_file()) {
+            Ok(config) => Cluster::from_config(&config).map_err(io::Error::other)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let id = new_replid();
+                println!("No cluster configuration found, I'm {id}");
+                Cluster::new(Node::new(id, String::new(), port, cport))
+            }
+            Err(err) => return Err(err),
+        };
+        //the ports may have changed since the configuration was saved
+        let myself = cluster.myself_mut();
+        myself.port = port;
+        myself.cport = cport;
+
+        let ip = self.listeners[0].local_addr()?.ip();
+        let listener = TcpListener::bind((ip, cport)).inspect_err(|err| {
+            eprintln!("Could not bind the cluster bus to {ip}:{cport}: {err}");
+        })?;
+        listener.set_nonblocking(true)?;
+        self.poller.watch_listener(&listener)?;
+
+        self.cluster = Some(ClusterState {
+            cluster,
+            listener,
+            links: HashMap::new(),
+            last_connect: HashMap::new(),
+            last_ping: Instant::now(),
+            messages_sent: 0,
+            messages_received: 0,
+            ok: false,
+            election: None,
+        });
+        self.follow_cluster_master();
+        self.save_cluster_config();
+        Ok(())
+    }
+
+    /// Checks that this node serves the slot of the keys of the commands, a request for another
+    /// slot is redirected to the node serving it. The commands of a transaction are checked
+    /// together when it is executed.
+    pub(super) fn check_cluster(
+        &mut self,
+        client_id: i32,
+        cmds: &[Command],
+    ) -> Result<(), RespType> {
+        let Some(state) = self.cluster.as_ref() else {
+            return Ok(());
+        };
+        let asking = self
+            .clients
+            .get_mut(&client_id)
+            .is_some_and(|client| client.take_asking());
+        //what MIGRATE sends to a node importing the slot
+        let asking = asking
+            || cmds
+                .iter()
+                .any(|cmd| matches!(cmd, Command::Restore { asking: true, .. }));
+        let error = |content: String| Err(RespType::SimpleError { content });
+
+        //shard channels are bound to slots like keys, but don't exist in the keyspace
+        let mut keys = vec![];
+        for cmd in cmds {
+            keys.extend(cmd.keys().into_iter().map(|key| (key, true)));
+            if let Command::SSubscribe { .. } | Command::SPublish { .. } = cmd {
+                keys.extend(cmd.channels().0.into_iter().map(|channel| (channel, false)));
+            }
+        }
+
+        let mut slot = None;
+        let (mut existing, mut missing) = (0, 0);
+        for (key, in_keyspace) in &keys {
+            let key_slot = key_hash_slot(key.as_bytes());
+            if slot.is_some_and(|slot| slot != key_slot) {
+                return error("CROSSSLOT Keys in request don't hash to the same slot".into());
+            }
+            slot = Some(key_slot);
+            match (in_keyspace, self.redis.contains_key(key)) {
+                (false, _) => {}
+                (true, true) => existing += 1,
+                (true, false) => missing += 1,
+            }
+        }
+        let Some(slot) = slot else {
+            return Ok(());
+        };
+
+        let cluster = &state.cluster;
+        let Some(owner) = cluster.owner(slot) else {
+            return error("CLUSTERDOWN Hash slot not served".into());
+        };
+        if !cluster.is_ok(self.config.cluster_require_full_coverage()) {
+            return error("CLUSTERDOWN The cluster is down".into());
+        }
+        //the keys of a slot being moved go from one node to the other whichever has them
+        if let [Command::Migrate { .. }] = cmds
+            && (cluster.migrating(slot).is_some() || cluster.importing(slot).is_some())
+        {
+            return Ok(());
+        }
+
+        //the keys of a slot being migrated are moved one at a time, the missing ones are
+        //already on the other node
+        if owner.id == cluster.myself().id {
+            return match cluster.migrating(slot).and_then(|to| cluster.node(to)) {
+                Some(_) if missing > 0 && existing > 0 => {
+                    error("TRYAGAIN Multiple keys request during rehashing of slot".into())
+                }
+                Some(target) if missing > 0 => error(format!("ASK {slot} {}", target.address())),
+                _ => Ok(()),
+            };
+        }
+        if cluster.importing(slot).is_some() && asking {
+            if keys.len() > 1 && missing > 0 {
+                return error("TRYAGAIN Multiple keys request during rehashing of slot".into());
+            }
+            return Ok(());
+        }
+        error(format!("MOVED {slot} {}", owner.address()))
+    }
+
+    pub(super) fn handle_cluster(&mut self, subcommand: ClusterSubcommand) -> RespType {
+        let require_full_coverage = self.config.cluster_require_full_coverage();
+        let offset = self.replication_offset();
+        let Some(state) = self.cluster.as_mut() else {
+            return cluster_disabled();
+        };
+        let cluster = &mut state.cluster;
+        let myself = cluster.myself().id.clone();
+        let bulk = |data: &str| RespType::BulkString {
+            data: data.as_bytes().to_vec(),
+        };
+        let integer = |integer: usize| RespType::Integer {
+            integer: integer as i64,
+        };
+        let error = |content: String| RespType::SimpleError { content };
+
+        match subcommand {
+            ClusterSubcommand::Info => {
+                let state_name = match cluster.is_ok(require_full_coverage) {
+                    true => "ok",
+                    false => "fail",
+                };
+                let (pfail, fail) = (cluster.slots_failing(false), cluster.slots_failing(true));
+                let myself = cluster.myself();
+                let my_epoch = myself
+                    .master
+                    .as_ref()
+                    .and_then(|id| cluster.node(id))
+                    .unwrap_or(myself)
+                    .config_epoch;
+                let fields = [
+                    ("cluster_state", state_name.to_string()),
+                    (
+                        "cluster_slots_assigned",
+                        cluster.slots_assigned().to_string(),
+                    ),
+                    (
+                        "cluster_slots_ok",
+                        (cluster.slots_assigned() - pfail - fail).to_string(),
+                    ),
+                    ("cluster_slots_pfail", pfail.to_string()),
+                    ("cluster_slots_fail", fail.to_string()),
+                    ("cluster_known_nodes", cluster.nodes().count().to_string()),
+                    ("cluster_size", cluster.size().to_string()),
+                    ("cluster_current_epoch", cluster.current_epoch.to_string()),
+                    ("cluster_my_epoch", my_epoch.to_string()),
+                    (
+                        "cluster_stats_messages_sent",
+                        state.messages_sent.to_string(),
+                    ),
+                    (
+                        "cluster_stats_messages_received",
+                        state.messages_received.to_string(),
+                    ),
+                ];
+                let text: String = fields
+                    .iter()
+                    .map(|(field, value)| format!("{field}:{value}\r\n"))
+                    .collect();
+                bulk(&text)
+            }
+            ClusterSubcommand::MyId => bulk(&myself),
+            ClusterSubcommand::Nodes => bulk(&cluster.describe()),
+            ClusterSubcommand::Slots => {
+                let mut ranges: Vec<(u16, u16, &Node)> = cluster
+                    .nodes()
+                    .filter(|node| !node.handshake)
+                    .flat_map(|node| {
+                        cluster
+                            .slot_ranges(&node.id)
+                            .into_iter()
+                            .map(move |(start, end)| (start, end, node))
+                    })
+                    .collect();
+                ranges.sort_by_key(|(start, ..)| *start);
+
+                //the master first, then its replicas that didn't fail
+                let elements = ranges
+                    .into_iter()
+                    .map(|(start, end, master)| {
+                        let mut elements = vec![integer(start as usize), integer(end as usize)];
+                        let replicas = cluster.replicas(&master.id).filter(|node| !node.failed());
+                        for node in std::iter::once(master).chain(replicas) {
+                            elements.push(RespType::Array {
+                                elements: vec![
+                                    bulk(&node.ip),
+                                    integer(node.port as usize),
+                                    bulk(&node.id),
+                                ],
+                            });
+                        }
+                        RespType::Array { elements }
+                    })
+                    .collect();
+                RespType::Array { elements }
+            }
+            ClusterSubcommand::Shards => {
+                let elements = cluster
+                    .nodes()
+                    .filter(|node| !node.handshake && node.is_master())
+                    .map(|master| {
+                        let slots = cluster
+                            .slot_ranges(&master.id)
+                            .into_iter()
+                            .flat_map(|(start, end)| {
+                                [integer(start as usize), integer(end as usize)]
+                            })
+                            .collect();
+                        let nodes = std::iter::once(master)
+                            .chain(cluster.replicas(&master.id))
+                            .map(|node| {
+                                let role = match node.is_master() {
+                                    true => "master",
+                                    false => "replica",
+                                };
+                                let health = match node.id == myself || node.connected {
+                                    _ if node.failed() => "fail",
+                                    true => "online",
+                                    false => "loading",
+                                };
+                                let replication_offset = match node.id == myself {
+                                    true => offset,
+                                    false => node.repl_offset,
+                                };
+                                RespType::Map {
+                                    entries: vec![
+                                        (bulk("id"), bulk(&node.id)),
+                                        (bulk("port"), integer(node.port as usize)),
+                                        (bulk("ip"), bulk(&node.ip)),
+                                        (bulk("endpoint"), bulk(&node.ip)),
+                                        (bulk("role"), bulk(role)),
+                                        (
+                                            bulk("replication-offset"),
+                                            integer(replication_offset as usize),
+                                        ),
+                                        (bulk("health"), bulk(health)),
+                                    ],
+                                }
+                            })
+                            .collect();
+
+                        RespType::Map {
+                            entries: vec![
+                                (bulk("slots"), RespType::Array { elements: slots }),
+                                (bulk("nodes"), RespType::Array { elements: nodes }),
+                            ],
+                        }
+                    })
+                    .collect();
+                RespType::Array { elements }
+            }
+            ClusterSubcommand::KeySlot { key } => integer(key_hash_slot(key.as_bytes()) as usize),
+            ClusterSubcommand::CountKeysInSlot { slot } => {
+                integer(self.redis.keys_in_slot(slot).count())
+            }
+            ClusterSubcommand::GetKeysInSlot { slot, count } => RespType::Array {
+                elements: self
+                    .redis
+                    .keys_in_slot(slot)
+                    .take(count)
+                    .map(bulk)
+                    .collect(),
+            },
+            ClusterSubcommand::AddSlots { slots } => self.add_slots(slots),
+            ClusterSubcommand::AddSlotsRange { ranges } => self.add_slots(
+                ranges
+                    .into_iter()
+                    .flat_map(|(start, end)| start..=end)
+                    .collect(),
+            ),
+            ClusterSubcommand::DelSlots { slots } => {
+                if let Some(slot) = slots.iter().find(|slot| cluster.owner(**slot).is_none()) {
+                    return error(format!("ERR Slot {slot} is already unassigned"));
+                }
+                for slot in slots {
+                    cluster.unassign(slot);
+                }
+                self.save_cluster_config();
+                ok()
+            }
+            ClusterSubcommand::SetSlot { slot, action } => self.set_slot(slot, action),
+            ClusterSubcommand::Meet { ip, port, cport } => {
+                let Ok(ip) = ip.parse::<IpAddr>() else {
+                    return error(format!("ERR Invalid node address specified: {ip}:{port}"));
+                };
+                let Some(cport) = cport.or(port.checked_add(10000)) else {
+                    return error(format!("ERR Invalid node address specified: {ip}:{port}"));
+                };
+
+                cluster.start_handshake(new_replid(), ip.to_string(), port, cport);
+                ok()
+            }
+            ClusterSubcommand::Replicate { node } => self.cluster_replicate(node),
+            ClusterSubcommand::Replicas { node } => {
+                let Some(master) = cluster.node(&node) else {
+                    return error(format!("ERR Unknown node {node}"));
+                };
+                if !master.is_master() {
+                    return error("ERR The specified node is not a master".into());
+                }
+                RespType::Array {
+                    elements: cluster
+                        .replicas(&node)
+                        .map(|replica| bulk(&cluster.describe_node(replica)))
+                        .collect(),
+                }
+            }
+            ClusterSubcommand::CountFailureReports { node } => {
+                let validity = self.config.cluster_node_timeout() * 2;
+                match cluster.node_mut(&node) {
+                    Some(node) => integer(node.failure_reports(validity)),
+                    None => error(format!("ERR Unknown node {node}")),
+                }
+            }
+        }
+    }
+
+    /// ASKING: the next command can be for a slot this node is importing.
+    pub(super) fn handle_asking(&mut self, client_id: i32) -> RespType {
+        if self.cluster.is_none() {
+            return cluster_disabled();
+        }
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.set_asking();
+        }
+        ok()
+    }
+
+    //this node becomes a replica of the master, only while it has no data it would lose
+    fn cluster_replicate(&mut self, id: String) -> RespType {
+        let empty = self.redis.is_empty();
+        let Some(state) = self.cluster.as_mut() else {
+            return cluster_disabled();
+        };
+        let cluster = &mut state.cluster;
+        let error = |content: String| RespType::SimpleError { content };
+        let myself = cluster.myself();
+
+        let Some(master) = cluster.node(&id) else {
+            return error(format!("ERR Unknown node {id}"));
+        };
+        if master.id == myself.id {
+            return error("ERR Can't replicate myself".into());
+        }
+        if !master.is_master() {
+            return error("ERR I can only replicate a master, not a replica.".into());
+        }
+        if myself.is_master() && (!cluster.slots(&myself.id).is_empty() || !empty) {
+            return error(
+                "ERR To set a master the node must be empty and without assigned slots.".into(),
+            );
+        }
+
+        let myself = myself.id.clone();
+        cluster.set_master(&myself, Some(id));
+        self.follow_cluster_master();
+        self.save_cluster_config();
+        ok()
+    }
+
+    fn add_slots(&mut self, slots: Vec<u16>) -> RespType {
+        let Some(state) = self.cluster.as_mut() else {
+            return cluster_disabled();
+        };
+        let cluster = &mut state.cluster;
+        if let Some(slot) = slots.iter().find(|slot| cluster.owner(**slot).is_some()) {
+            return RespType::SimpleError {
+                content: format!("ERR Slot {slot} is already busy"),
+            };
+        }
+
+        let myself = cluster.myself().id.clone();
+        for slot in slots {
+            cluster.assign(slot, &myself);
+            //this node is the owner now, whatever the other one thinks
+            cluster.set_importing(slot, None);
+        }
+        self.save_cluster_config();
+        ok()
+    }
+
+    fn set_slot(&mut self, slot: u16, action: SetSlotAction) -> RespType {
+        let Some(state) = self.cluster.as_mut() else {
+            return cluster_disabled();
+        };
+        let cluster = &mut state.cluster;
+        let myself = cluster.myself().id.clone();
+        let owned = cluster.owner(slot).is_some_and(|owner| owner.id == myself);
+        let error = |content: String| RespType::SimpleError { content };
+        let unknown = |node: &str| error(format!("ERR I don't know about node {node}"));
+
+        match action {
+            SetSlotAction::Migrating { node } => {
+                if !owned {
+                    return error(format!("ERR I'm not the owner of hash slot {slot}"));
+                }
+                if cluster.node(&node).is_none() {
+                    return unknown(&node);
+                }
+                cluster.set_migrating(slot, Some(node));
+            }
+            SetSlotAction::Importing { node } => {
+                if owned {
+                    return error(format!("ERR I'm already the owner of hash slot {slot}"));
+                }
+                if cluster.node(&node).is_none() {
+                    return unknown(&node);
+                }
+                cluster.set_importing(slot, Some(node));
+            }
+            SetSlotAction::Stable => {
+                cluster.set_migrating(slot, None);
+                cluster.set_importing(slot, None);
+            }
+            SetSlotAction::Node { node } => {
+                if cluster.node(&node).is_none() {
+                    return unknown(&node);
+                }
+                let keys = self.redis.keys_in_slot(slot).count();
+                if owned && node != myself && keys > 0 {
+                    return error(format!(
+                        "ERR Can't assign hashslot {slot} to a different node while I still hold keys for this hash slot."
+                    ));
+                }
+
+                //the migration is over once the keys moved, the import once this node owns it
+                if node != myself && keys == 0 {
+                    cluster.set_migrating(slot, None);
+                }
+                //the others only accept the slot moving to this node with a new epoch, there is
+                //no need to agree on it as the migration is not concurrent
+                if node == myself && cluster.importing(slot).is_some() {
+                    cluster.set_importing(slot, None);
+                    if cluster.myself().config_epoch == 0
+                        || cluster.myself().config_epoch != cluster.max_epoch()
+                    {
+                        cluster.current_epoch += 1;
+                        let epoch = cluster.current_epoch;
+                        cluster.myself_mut().config_epoch = epoch;
+                        println!("configEpoch updated after importing slot {slot}: {epoch}");
+                    }
+                }
+                cluster.assign(slot, &node);
+            }
+        }
+
+        self.save_cluster_config();
+        ok()
+    }
+
+    //saved on every change, for the node to rejoin the cluster as it left it after a restart
+    fn save_cluster_config(&self) {
+        let Some(state) = self.cluster.as_ref() else {
+            return;
+        };
+        let path = self.config.cluster_config_file();
+        let temp = PathBuf::from(format!("{}.tmp-{}", path.display(), std::process::id()));
+
+        let saved =
+            fs::write(&temp, state.cluster.to_config()).and_then(|_| fs::rename(&temp, path));
+        if let Err(err) = saved {
+            println!("Could not save the cluster configuration: {err}");
+            let _ = fs::remove_file(&temp);
+        }
+    }
+
+    /// Whether the socket is one of the cluster bus, the listener or a link.
+    pub(super) fn is_cluster_socket(&self, fd: i32) -> bool {
+        self.cluster
+            .as_ref()
+            .is_some_and(|state| state.listener.as_raw_fd() == fd || state.links.contains_key(&fd))
+    }
+
+    pub(super) fn handle_cluster_event(&mut self, fd: i32, events: u32) -> io::Result<()> {
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+
+        if state.listener.as_raw_fd() == fd {
+            return self.accept_cluster_links();
+        }
+
+        if (EPOLLIN as u32) & events != 0 {
+            let mut buf = [0u8; 16 * 1024];
+            let read = state
+                .links
+                .get_mut(&fd)
+                .map(|link| link.stream.read(&mut buf));
+
+            match read {
+                Some(Ok(read)) if read > 0 => {
+                    if let Some(link) = state.links.get_mut(&fd) {
+                        link.input.extend_from_slice(&buf[..read]);
+                    }
+                    self.process_cluster_input(fd)?;
+                }
+                Some(_) => self.close_cluster_link(fd)?,
+                None => {}
+            }
+        }
+
+        if ((EPOLLERR | EPOLLHUP | EPOLLRDHUP) as u32) & events != 0 {
+            self.close_cluster_link(fd)?;
+        }
+        Ok(())
+    }
+
+    fn accept_cluster_links(&mut self) -> io::Result<()> {
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+
+        loop {
+            match state.listener.accept() {
+                Ok((stream, _)) => {
+                    //the others connect to this node at its address, this is how it learns it
+                    if state.cluster.myself().ip.is_empty()
+                        && let Ok(address) = stream.local_addr()
+                    {
+                        state.cluster.myself_mut().ip = address.ip().to_string();
+                    }
+
+                    self.poller.watch_socket(&stream)?;
+                    let link = Link {
+                        stream,
+                        input: vec![],
+                        node: None,
+                        created: Instant::now(),
+                    };
+                    state.links.insert(link.stream.as_raw_fd(), link);
+                }
+                Err(err) => match err.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => return Ok(()),
+                    _ => return Err(err),
+                },
+            }
+        }
+    }
+
+    fn process_cluster_input(&mut self, fd: i32) -> io::Result<()> {
+        loop {
+            let Some(link) = self
+                .cluster
+                .as_mut()
+                .and_then(|state| state.links.get_mut(&fd))
+            else {
+                return Ok(());
+            };
+
+            match Message::decode(&link.input) {
+                Ok(Some((message, len))) => {
+                    link.input.drain(..len);
+                    self.process_cluster_message(fd, message)?;
+                }
+                Ok(None) => return Ok(()),
+                Err(err) => {
+                    println!("Closing the cluster bus link, got error {err}");
+                    return self.close_cluster_link(fd);
+                }
+            }
+        }
+    }
+
+    fn process_cluster_message(&mut self, fd: i32, message: Message) -> io::Result<()> {
+        let node_timeout = self.config.cluster_node_timeout();
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        let Some(link) = state.links.get_mut(&fd) else {
+            return Ok(());
+        };
+        state.messages_received += 1;
+        let cluster = &mut state.cluster;
+        let sender = message.sender.clone();
+        let mut changed = false;
+
+        match message.kind {
+            MessageType::Meet if cluster.node(&sender).is_none() => {
+                let ip = link
+                    .stream
+                    .peer_addr()
+                    .map(|address| address.ip().to_string())
+                    .unwrap_or_default();
+                println!("Adding node {sender} met at {ip}:{}", message.port);
+                cluster.add_node(Node::new(sender.clone(), ip, message.port, message.cport));
+                changed = true;
+            }
+            //the node met through a handshake answered, it is known by its actual id from now on
+            MessageType::Pong => {
+                if let Some(id) = link.node.clone()
+                    && cluster.node(&id).is_some_and(|node| node.handshake)
+                {
+                    if cluster.node(&sender).is_some() {
+                        cluster.remove_node(&id);
+                        return self.close_cluster_link(fd);
+                    }
+                    cluster.rename_node(&id, &sender);
+                    if let Some(node) = cluster.node_mut(&sender) {
+                        node.handshake = false;
+                    }
+                    link.node = Some(sender.clone());
+                    state.last_connect.remove(&id);
+                    changed = true;
+                }
+            }
+            _ => {}
+        }
+
+        let outbound = link.node.as_deref() == Some(sender.as_str());
+        let myself = cluster.myself().id.clone();
+        let known = sender != myself && cluster.node(&sender).is_some_and(|node| !node.handshake);
+        if known {
+            if message.current_epoch > cluster.current_epoch {
+                cluster.current_epoch = message.current_epoch;
+                changed = true;
+            }
+            let node = cluster.node_mut(&sender).expect("known");
+            //a replica gives the epoch of its master
+            if message.master.is_none() && message.config_epoch > node.config_epoch {
+                node.config_epoch = message.config_epoch;
+                changed = true;
+            }
+            node.repl_offset = message.offset;
+            if (node.port, node.cport) != (message.port, message.cport) {
+                node.port = message.port;
+                node.cport = message.cport;
+                changed = true;
+            }
+        }
+
+        match message.kind {
+            MessageType::Ping | MessageType::Pong | MessageType::Meet if known => {
+                //the node answered a ping on the link this node opened
+                if message.kind == MessageType::Pong
+                    && outbound
+                    && cluster.pong_received(&sender, node_timeout * 2)
+                {
+                    println!("Clear FAIL state for node {sender}: it is reachable again.");
+                    changed = true;
+                }
+
+                changed |= cluster.set_master(&sender, message.master.clone());
+                if message.master.is_none() {
+                    changed |= self.update_cluster_slots(&sender, &message)?;
+                }
+                self.process_gossip(&sender, &message.gossip)?;
+            }
+            MessageType::Fail if known => {
+                let failing = message.failing.clone().unwrap_or_default();
+                if failing != myself
+                    && let Some(node) = cluster.node_mut(&failing)
+                    && !node.failed()
+                {
+                    println!("FAIL message received from {sender} about {failing}");
+                    node.pfail = false;
+                    node.fail_time = Some(Instant::now());
+                    changed = true;
+                }
+            }
+            MessageType::FailoverAuthRequest if known => {
+                self.vote_for_replica(fd, &message)?;
+            }
+            MessageType::FailoverAuthAck if known => {
+                let serving = cluster.node(&sender).is_some_and(|node| node.is_master())
+                    && !cluster.slots(&sender).is_empty();
+                if let Some(election) = state.election.as_mut()
+                    && election
+                        .epoch
+                        .is_some_and(|epoch| message.current_epoch >= epoch)
+                    && serving
+                    && election.votes.insert(sender.clone())
+                {
+                    println!(
+                        "Failover auth granted by {sender}, {} votes so far",
+                        election.votes.len()
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        if changed {
+            self.save_cluster_config();
+        }
+        if let MessageType::Ping | MessageType::Meet = message.kind {
+            let pong = self.cluster_message(MessageType::Pong);
+            self.send_cluster_message(fd, pong)?;
+        }
+        Ok(())
+    }
+
+    //the slots a master claims, this node may lose its own and become its replica. Returns
+    //whether anything changed
+    fn update_cluster_slots(&mut self, sender: &str, message: &Message) -> io::Result<bool> {
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(false);
+        };
+        let cluster = &mut state.cluster;
+        let myself = cluster.myself();
+        let (myself_id, myself_master) = (myself.id.clone(), myself.is_master());
+        let my_master = myself.master.clone().unwrap_or(myself_id.clone());
+
+        //two masters with the same epoch would never agree on a slot, the one with the lowest
+        //id moves on to a new one
+        let colliding = cluster
+            .node(sender)
+            .is_some_and(|node| node.config_epoch == cluster.myself().config_epoch);
+        let mut changed = false;
+        if myself_master && colliding && sender > myself_id.as_str() {
+            cluster.current_epoch += 1;
+            let epoch = cluster.current_epoch;
+            cluster.myself_mut().config_epoch = epoch;
+            println!(
+                "WARNING: configEpoch collision with node {sender}. configEpoch set to {epoch}"
+            );
+            changed = true;
+        }
+
+        let served = cluster.slots(&my_master);
+        if !cluster.update_slots(sender, &message.slots) {
+            return Ok(changed);
+        }
+        //the master of this node, or this node, lost all its slots to the sender
+        let taken = served
+            .iter()
+            .any(|slot| cluster.owner(*slot).is_some_and(|owner| owner.id == sender));
+        if !served.is_empty() && taken && cluster.slots(&my_master).is_empty() {
+            println!(
+                "Configuration change detected. Reconfiguring myself as a replica of {sender}"
+            );
+            cluster.set_master(&myself_id, Some(sender.to_string()));
+            state.election = None;
+            self.follow_cluster_master();
+        }
+        Ok(true)
+    }
+
+    //what the sender tells about the others: the masters report the ones failing, the
+    //unknown ones are met
+    fn process_gossip(&mut self, sender: &str, gossip: &[Gossip]) -> io::Result<()> {
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        let cluster = &mut state.cluster;
+        let myself = cluster.myself().id.clone();
+        let reporter = cluster.node(sender).is_some_and(|node| node.is_master());
+
+        let mut reported = vec![];
+        for entry in gossip.iter().filter(|entry| entry.id != myself) {
+            let Some(node) = cluster.node_mut(&entry.id) else {
+                let (ip, port, cport) = (entry.ip.clone(), entry.port, entry.cport);
+                if !ip.is_empty() && cluster.start_handshake(new_replid(), ip, port, cport) {
+                    println!(
+                        "Start handshake with {}:{port} learned from {sender}",
+                        entry.ip
+                    );
+                }
+                continue;
+            };
+            if node.handshake || !reporter {
+                continue;
+            }
+            if entry.pfail || entry.fail {
+                node.fail_reports.insert(sender.to_string(), Instant::now());
+                reported.push(entry.id.clone());
+            } else {
+                node.fail_reports.remove(sender);
+            }
+        }
+
+        for id in reported {
+            self.mark_failing_if_needed(&id)?;
+        }
+        Ok(())
+    }
+
+    //a node this node can't reach fails once the majority of the masters agree on it, the
+    //others are told right away
+    fn mark_failing_if_needed(&mut self, id: &str) -> io::Result<()> {
+        let validity = self.config.cluster_node_timeout() * 2;
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        let cluster = &mut state.cluster;
+        let quorum = cluster.quorum();
+        let myself_master = cluster.myself().is_master() as usize;
+        let Some(node) = cluster.node_mut(id) else {
+            return Ok(());
+        };
+        if !node.pfail || node.failed() || node.failure_reports(validity) + myself_master < quorum {
+            return Ok(());
+        }
+
+        println!("Marking node {id} as failing (quorum reached).");
+        node.pfail = false;
+        node.fail_time = Some(Instant::now());
+        self.save_cluster_config();
+        let mut message = self.cluster_message(MessageType::Fail);
+        message.failing = Some(id.to_string());
+        self.broadcast_cluster_message(message)
+    }
+
+    //a master votes for a replica of a failed master to replace it, once per epoch and not
+    //twice for the same master in a row
+    fn vote_for_replica(&mut self, fd: i32, request: &Message) -> io::Result<()> {
+        let node_timeout = self.config.cluster_node_timeout();
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        let cluster = &mut state.cluster;
+        let myself = cluster.myself();
+        if !myself.is_master() || cluster.slots(&myself.id).is_empty() {
+            return Ok(());
+        }
+
+        let sender = &request.sender;
+        let master = cluster
+            .node(sender)
+            .and_then(|node| node.master.as_ref())
+            .and_then(|id| cluster.node(id));
+        let refused = match master {
+            None => Some("it is not a replica of a known master".to_string()),
+            Some(_) if request.current_epoch < cluster.current_epoch => Some(format!(
+                "its epoch {} is older than mine",
+                request.current_epoch
+            )),
+            Some(_) if cluster.last_vote_epoch == cluster.current_epoch => Some(format!(
+                "I already voted for epoch {}",
+                cluster.current_epoch
+            )),
+            Some(master) if !master.failed() => Some("its master is up".to_string()),
+            Some(master)
+                if master
+                    .voted_time
+                    .is_some_and(|time| time.elapsed() < node_timeout * 2) =>
+            {
+                Some("I already voted for a replica of its master lately".to_string())
+            }
+            Some(_) => request
+                .slots
+                .iter()
+                .find(|slot| {
+                    cluster
+                        .owner(**slot)
+                        .is_some_and(|owner| owner.config_epoch > request.config_epoch)
+                })
+                .map(|slot| format!("slot {slot} has a greater epoch than it claims")),
+        };
+        if let Some(reason) = refused {
+            println!("Failover auth denied to {sender}: {reason}");
+            return Ok(());
+        }
+
+        let master = master.expect("checked").id.clone();
+        cluster.last_vote_epoch = cluster.current_epoch;
+        if let Some(master) = cluster.node_mut(&master) {
+            master.voted_time = Some(Instant::now());
+        }
+        println!(
+            "Failover auth granted to {sender} for epoch {}",
+            cluster.current_epoch
+        );
+        self.save_cluster_config();
+        let ack = self.cluster_message(MessageType::FailoverAuthAck);
+        self.send_cluster_message(fd, ack)
+    }
+
+    //a message from this node about itself, replicas claim the slots of their master
+    fn cluster_message(&self, kind: MessageType) -> Message {
+        let state = self.cluster.as_ref().expect("cluster mode");
+        let cluster = &state.cluster;
+        let myself = cluster.myself();
+        let served = myself
+            .master
+            .as_ref()
+            .and_then(|id| cluster.node(id))
+            .unwrap_or(myself);
+        let gossip = match kind {
+            MessageType::Ping | MessageType::Pong | MessageType::Meet => {
+                cluster.gossip(random(u64::MAX) as usize)
+            }
+            _ => vec![],
+        };
+
+        Message {
+            kind,
+            sender: myself.id.clone(),
+            current_epoch: cluster.current_epoch,
+            config_epoch: served.config_epoch,
+            offset: self.replication_offset(),
+            master: myself.master.clone(),
+            port: myself.port,
+            cport: myself.cport,
+            slots: cluster.slots(&served.id),
+            gossip,
+            failing: None,
+        }
+    }
+
+    fn send_cluster_message(&mut self, fd: i32, message: Message) -> io::Result<()> {
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        let Some(link) = state.links.get_mut(&fd) else {
+            return Ok(());
+        };
+
+        if let Err(err) = link.stream.write_all(&message.encode()) {
+            println!("Could not write to the cluster bus: {err}");
+            return self.close_cluster_link(fd);
+        }
+        state.messages_sent += 1;
+        //the time of the first ping still waiting for its pong
+        if let MessageType::Ping | MessageType::Meet = message.kind
+            && let Some(node) = link.node.as_ref().and_then(|id| state.cluster.node_mut(id))
+            && node.ping_sent == 0
+        {
+            node.ping_sent = unix_time_ms();
+        }
+        Ok(())
+    }
+
+    //sent to every node over the links this node opened
+    fn broadcast_cluster_message(&mut self, message: Message) -> io::Result<()> {
+        let Some(state) = self.cluster.as_ref() else {
+            return Ok(());
+        };
+        let linked: Vec<i32> = state
+            .links
+            .iter()
+            .filter(|(_, link)| {
+                link.node
+                    .as_ref()
+                    .and_then(|id| state.cluster.node(id))
+                    .is_some_and(|node| !node.handshake)
+            })
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in linked {
+            self.send_cluster_message(fd, message.clone())?;
+        }
+        Ok(())
+    }
+
+    fn close_cluster_link(&mut self, fd: i32) -> io::Result<()> {
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        let Some(link) = state.links.remove(&fd) else {
+            return Ok(());
+        };
+
+        if let Some(node) = link.node.as_ref().and_then(|id| state.cluster.node_mut(id)) {
+            node.connected = false;
+        }
+        self.poller.remove_socket(&link.stream)
+    }
+
+    /// Called from every iteration of the event loop: connects to the nodes this one has no
+    /// link to, pings the others, detects the ones failing and, on a replica of a failed
+    /// master, replaces it.
+    pub(super) fn cluster_cron(&mut self) -> io::Result<()> {
+        let node_timeout = self.config.cluster_node_timeout();
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+
+        let timed_out: Vec<String> = state
+            .cluster
+            .nodes()
+            .filter(|node| node.handshake && node.created.elapsed() > node_timeout)
+            .map(|node| node.id.clone())
+            .collect();
+        for id in timed_out {
+            println!("Handshake with node {id} timed out");
+            if let Some(fd) = self.cluster_link(&id) {
+                self.close_cluster_link(fd)?;
+            }
+            if let Some(state) = self.cluster.as_mut() {
+                state.cluster.remove_node(&id);
+            }
+        }
+
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        let myself = state.cluster.myself().id.clone();
+        let unlinked: Vec<(String, String, u16)> = state
+            .cluster
+            .nodes()
+            .filter(|node| node.id != myself)
+            .filter(|node| {
+                state
+                    .last_connect
+                    .get(&node.id)
+                    .is_none_or(|last| last.elapsed() >= CONNECT_RETRY_DELAY)
+            })
+            .map(|node| (node.id.clone(), node.ip.clone(), node.cport))
+            .collect();
+        for (id, ip, cport) in unlinked {
+            if self.cluster_link(&id).is_none() {
+                self.connect_to_node(id, &ip, cport)?;
+            }
+        }
+
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        if state.last_ping.elapsed() >= PING_PERIOD {
+            state.last_ping = Instant::now();
+            let linked: Vec<i32> = state
+                .links
+                .iter()
+                .filter(|(_, link)| link.node.is_some())
+                .map(|(fd, _)| *fd)
+                .collect();
+            for fd in linked {
+                let ping = self.cluster_message(MessageType::Ping);
+                self.send_cluster_message(fd, ping)?;
+            }
+        }
+
+        self.detect_failures()?;
+        self.replica_failover()?;
+
+        let require_full_coverage = self.config.cluster_require_full_coverage();
+        if let Some(state) = self.cluster.as_mut() {
+            let ok = state.cluster.is_ok(require_full_coverage);
+            if ok != state.ok {
+                state.ok = ok;
+                println!("Cluster state changed: {}", if ok { "ok" } else { "fail" });
+            }
+        }
+        Ok(())
+    }
+
+    //a node that doesn't answer the pings in time is possibly failing, its link is reopened
+    //first in case it is the link that broke
+    fn detect_failures(&mut self) -> io::Result<()> {
+        let node_timeout = self.config.cluster_node_timeout();
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        let now = unix_time_ms();
+        let myself = state.cluster.myself().id.clone();
+        let waiting: Vec<(String, Duration)> = state
+            .cluster
+            .nodes()
+            .filter(|node| node.id != myself && !node.handshake && node.ping_sent != 0)
+            .map(|node| {
+                let waited = Duration::from_millis(now.saturating_sub(node.ping_sent));
+                (node.id.clone(), waited)
+            })
+            .collect();
+
+        for (id, waited) in waiting {
+            if waited > node_timeout / 2
+                && let Some(fd) = self.cluster_link(&id)
+                && let Some(state) = self.cluster.as_ref()
+                && state.links[&fd].created.elapsed() > node_timeout
+            {
+                self.close_cluster_link(fd)?;
+            }
+
+            let Some(node) = self
+                .cluster
+                .as_mut()
+                .and_then(|state| state.cluster.node_mut(&id))
+            else {
+                continue;
+            };
+            if waited > node_timeout && !node.pfail && !node.failed() {
+                println!("*** NODE {id} possibly failing");
+                node.pfail = true;
+            }
+        }
+        Ok(())
+    }
+
+    //a replica of a failed master asks the masters to vote for it to replace it, after a
+    //delay longer for the replicas behind the others. With the votes of the majority, it
+    //takes over the slots of its master with a new epoch
+    fn replica_failover(&mut self) -> io::Result<()> {
+        let node_timeout = self.config.cluster_node_timeout();
+        let offset = self.replication_offset();
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        let cluster = &mut state.cluster;
+        let myself = cluster.myself();
+        let Some(master) = myself
+            .master
+            .as_ref()
+            .and_then(|id| cluster.node(id))
+            .filter(|master| master.failed() && !cluster.slots(&master.id).is_empty())
+        else {
+            state.election = None;
+            return Ok(());
+        };
+        let (myself, master) = (myself.id.clone(), master.id.clone());
+        let rank = cluster
+            .replicas(&master)
+            .filter(|replica| replica.id != myself && !replica.failed())
+            .filter(|replica| replica.repl_offset > offset)
+            .count();
+
+        let auth_timeout = (node_timeout * 2).max(Duration::from_secs(2));
+        let expired = |election: &Election| election.auth_time.elapsed() > auth_timeout * 2;
+        let election = match state.election.as_mut() {
+            Some(election) if !expired(election) => election,
+            _ => {
+                let delay = Duration::from_millis(500 + random(500) + rank as u64 * 1000);
+                println!(
+                    "Start of election delayed for {} milliseconds (rank #{rank}, offset {offset}).",
+                    delay.as_millis()
+                );
+                state.election = Some(Election {
+                    auth_time: Instant::now() + delay,
+                    rank,
+                    epoch: None,
+                    votes: HashSet::new(),
+                });
+                return Ok(());
+            }
+        };
+
+        //another replica got ahead of this one meanwhile
+        if election.epoch.is_none() && rank > election.rank {
+            election.auth_time += Duration::from_secs((rank - election.rank) as u64);
+            election.rank = rank;
+        }
+        if Instant::now() < election.auth_time || election.auth_time.elapsed() > auth_timeout {
+            return Ok(());
+        }
+
+        let Some(epoch) = election.epoch else {
+            cluster.current_epoch += 1;
+            let epoch = cluster.current_epoch;
+            election.epoch = Some(epoch);
+            println!("Starting a failover election for epoch {epoch}.");
+            self.save_cluster_config();
+            let request = self.cluster_message(MessageType::FailoverAuthRequest);
+            return self.broadcast_cluster_message(request);
+        };
+        if election.votes.len() < cluster.quorum() {
+            return Ok(());
+        }
+
+        println!("Failover election won: I'm the new master.");
+        state.election = None;
+        cluster.set_master(&myself, None);
+        for slot in cluster.slots(&master) {
+            cluster.assign(slot, &myself);
+        }
+        let node = cluster.myself_mut();
+        node.config_epoch = node.config_epoch.max(epoch);
+        println!("configEpoch set to {epoch} after successful failover");
+        self.follow_cluster_master();
+        self.save_cluster_config();
+        let pong = self.cluster_message(MessageType::Pong);
+        self.broadcast_cluster_message(pong)
+    }
+
+    //replicates the master of this node in the cluster, or stops replicating for a master
+    fn follow_cluster_master(&mut self) {
+        let Some(state) = self.cluster.as_ref() else {
+            return;
+        };
+        let cluster = &state.cluster;
+        let master = cluster
+            .myself()
+            .master
+            .as_ref()
+            .and_then(|id| cluster.node(id))
+            .map(|master| (master.ip.clone(), master.port));
+
+        let replica = master.is_some();
+        if self.set_master(master) && !replica {
+            println!("MASTER MODE enabled");
+        }
+    }
+
+    //the link this node opened to the node
+    fn cluster_link(&self, id: &str) -> Option<i32> {
+        self.cluster.as_ref().and_then(|state| {
+            state
+                .links
+                .iter()
+                .find(|(_, link)| link.node.as_deref() == Some(id))
+                .map(|(fd, _)| *fd)
+        })
+    }
+
+    fn connect_to_node(&mut self, id: String, ip: &str, cport: u16) -> io::Result<()> {
+        let Some(state) = self.cluster.as_mut() else {
+            return Ok(());
+        };
+        state.last_connect.insert(id.clone(), Instant::now());
+
+        let Ok(ip) = ip.parse::<IpAddr>() else {
+            return Ok(());
+        };
+        let stream = match TcpStream::connect_timeout(&(ip, cport).into(), CONNECT_RETRY_DELAY) {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Could not connect to node {id} at {ip}:{cport}: {err}");
+                //as good as a ping without an answer, for the node to be detected as failing
+                if let Some(node) = state.cluster.node_mut(&id)
+                    && node.ping_sent == 0
+                {
+                    node.ping_sent = unix_time_ms();
+                }
+                return Ok(());
+            }
+        };
+
+        self.poller.watch_socket(&stream)?;
+        let fd = stream.as_raw_fd();
+        state.links.insert(
+            fd,
+            Link {
+                stream,
+                input: vec![],
+                node: Some(id.clone()),
+                created: Instant::now(),
+            },
+        );
+
+        //a node met through CLUSTER MEET doesn't know this one yet
+        let Some(node) = state.cluster.node_mut(&id) else {
+            return Ok(());
+        };
+        node.connected = true;
+        let kind = match std::mem::take(&mut node.meet) {
+            true => MessageType::Meet,
+            false => MessageType::Ping,
+        };
+        let message = self.cluster_message(kind);
+        self.send_cluster_message(fd, message)
+    }
+
+    /// The fields of the cluster section of INFO.
+    pub(super) fn cluster_info(&self) -> Vec<(&'static str, String)> {
+        let enabled = self.cluster.is_some() as u8;
+        vec![("cluster_enabled", enabled.to_string())]
+    }
+}
+
+//a random number below the bound, to spread what all the nodes would otherwise do at once
+pub(super) fn random(bound: u64) -> u64 {
+    RandomState::new().build_hasher().finish() % bound.max(1)
+}
+
+fn cluster_disabled() -> RespType {
+    RespType::SimpleError {
+        content: "ERR This instance has cluster support disabled".into(),
+    }
+}
+
+fn ok() -> RespType {
+    RespType::SimpleString {
+        content: "OK".into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::TcpListener,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        cluster::{Cluster, Gossip, MessageType, Node},
+        ev_loop::{
+            EventLoop,
+            test_util::{event_loop, request},
+        },
+        resp::RespType,
+    };
+
+    //a node with its cluster configuration in a file of its own
+    fn node(name: &str) -> EventLoop {
+        let cport = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let nodes = std::env::temp_dir().join(format!("nodes-{name}-{}.conf", std::process::id()));
+        let _ = std::fs::remove_file(&nodes);
+
+        event_loop(&[
+            "--cluster-enabled",
+            "yes",
+            "--cluster-port",
+            &cport.to_string(),
+            "--cluster-config-file",
+            nodes.to_str().unwrap(),
+        ])
+    }
+
+    fn error(content: &str) -> Result<(), RespType> {
+        Err(RespType::SimpleError {
+            content: content.into(),
+        })
+    }
+
+    //runs both nodes until the condition holds
+    fn run_until(
+        a: &mut EventLoop,
+        b: &mut EventLoop,
+        done: impl Fn(&EventLoop, &EventLoop) -> bool,
+    ) {
+        let started = Instant::now();
+        while !done(a, b) && started.elapsed() < Duration::from_secs(5) {
+            for node in [&mut *a, &mut *b] {
+                node.cluster_cron().unwrap();
+                node.process_events(10).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_meet_and_redirect() {
+        let mut a = node("a");
+        let mut b = node("b");
+        let b_port = b.config.port().to_string();
+        let b_cport = b.config.cluster_port().unwrap().to_string();
+        a.execute(
+            1,
+            request(&["CLUSTER", "MEET", "127.0.0.1", &b_port, &b_cport]),
+        );
+        a.execute(1, request(&["CLUSTER", "ADDSLOTSRANGE", "0", "8191"]));
+        b.execute(1, request(&["CLUSTER", "ADDSLOTSRANGE", "8192", "16383"]));
+
+        //the nodes learn about each other and about the slots the other serves
+        let covered = |node: &EventLoop| {
+            node.cluster
+                .as_ref()
+                .is_some_and(|state| state.cluster.is_ok(true))
+        };
+        run_until(&mut a, &mut b, |a, b| covered(a) && covered(b));
+        assert!(covered(&a) && covered(&b));
+
+        let get = |key: &str| [request(&["GET", key])];
+        assert_eq!(a.check_cluster(1, &get("bar")), Ok(()));
+        assert_eq!(
+            a.check_cluster(1, &get("foo")),
+            error(&format!("MOVED 12182 127.0.0.1:{b_port}"))
+        );
+        assert_eq!(
+            a.check_cluster(1, &[request(&["BLPOP", "foo", "bar", "0"])]),
+            error("CROSSSLOT Keys in request don't hash to the same slot")
+        );
+        assert_eq!(
+            a.execute(1, request(&["SWAPDB", "0", "0"])),
+            Some(RespType::SimpleError {
+                content: "ERR SWAPDB is not allowed in cluster mode".into()
+            })
+        );
+
+        //the keys of a slot being migrated that already moved are asked to the other node
+        let a_id = a.cluster.as_ref().unwrap().cluster.myself().id.clone();
+        let b_id = b.cluster.as_ref().unwrap().cluster.myself().id.clone();
+        b.execute(1, request(&["SET", "foo", "1"]));
+        b.execute(
+            1,
+            request(&["CLUSTER", "SETSLOT", "12182", "MIGRATING", &a_id]),
+        );
+        a.execute(
+            1,
+            request(&["CLUSTER", "SETSLOT", "12182", "IMPORTING", &b_id]),
+        );
+        assert_eq!(b.check_cluster(1, &get("foo")), Ok(()));
+        assert_eq!(
+            b.check_cluster(1, &get("{foo}1")),
+            error(&format!("ASK 12182 127.0.0.1:{}", a.config.port()))
+        );
+        a.execute(1, request(&["ASKING"]));
+        assert_eq!(a.check_cluster(1, &get("{foo}1")), Ok(()));
+        assert!(a.check_cluster(1, &get("{foo}1")).is_err());
+
+        assert_eq!(
+            b.execute(1, request(&["CLUSTER", "SETSLOT", "12182", "NODE", &a_id])),
+            Some(RespType::SimpleError {
+                content: "ERR Can't assign hashslot 12182 to a different node while I still hold keys for this hash slot.".into()
+            })
+        );
+
+        for node in [a, b] {
+            std::fs::remove_file(node.config.cluster_config_file()).unwrap();
+        }
+    }
+
+    //the cluster as the node knows it, other nodes are added to it without any link
+    fn cluster(node: &mut EventLoop) -> &mut Cluster {
+        &mut node.cluster.as_mut().unwrap().cluster
+    }
+
+    //adds a master serving the slots to what the node knows
+    fn add_master(node: &mut EventLoop, id: &str, slots: std::ops::Range<u16>) {
+        let cluster = cluster(node);
+        cluster.add_node(Node::new(id.into(), "127.0.0.1".into(), 7000, 17000));
+        for slot in slots {
+            cluster.assign(slot, id);
+        }
+    }
+
+    fn report(id: &str, pfail: bool) -> Vec<Gossip> {
+        vec![Gossip {
+            id: id.into(),
+            ip: "127.0.0.1".into(),
+            port: 7000,
+            cport: 17000,
+            pfail,
+            fail: false,
+        }]
+    }
+
+    #[test]
+    fn test_failure_detection() {
+        let mut a = node("detection");
+        let myself = cluster(&mut a).myself().id.clone();
+        let (b, c, d, e) = (
+            "b".repeat(40),
+            "c".repeat(40),
+            "d".repeat(40),
+            "e".repeat(40),
+        );
+        cluster(&mut a).assign(0, &myself);
+        add_master(&mut a, &b, 1..2);
+        add_master(&mut a, &c, 2..3);
+        add_master(&mut a, &d, 3..4);
+        cluster(&mut a).add_node(Node::new(e.clone(), "127.0.0.1".into(), 7004, 17004));
+        cluster(&mut a).set_master(&e, Some(b.clone()));
+        assert_eq!(cluster(&mut a).quorum(), 3);
+        let failed = |a: &mut EventLoop| cluster(a).node(&d).unwrap().failed();
+
+        //the reports of the other masters count only once this node can't reach it either
+        a.process_gossip(&b, &report(&d, true)).unwrap();
+        a.process_gossip(&c, &report(&d, true)).unwrap();
+        assert!(!failed(&mut a));
+        cluster(&mut a).node_mut(&d).unwrap().pfail = true;
+
+        //a report withdrawn, one expired and one of a replica don't count toward the majority
+        a.process_gossip(&c, &report(&d, false)).unwrap();
+        a.process_gossip(&e, &report(&d, true)).unwrap();
+        assert!(!failed(&mut a));
+        let expired = Instant::now() - Duration::from_secs(60);
+        let node = cluster(&mut a).node_mut(&d).unwrap();
+        node.fail_reports.insert(c.clone(), expired);
+        a.mark_failing_if_needed(&d).unwrap();
+        assert!(!failed(&mut a));
+        assert_eq!(cluster(&mut a).node(&d).unwrap().fail_reports.len(), 1);
+
+        //PFAIL becomes FAIL with the majority: this node and two other masters
+        a.process_gossip(&c, &report(&d, true)).unwrap();
+        assert!(failed(&mut a));
+        assert!(!cluster(&mut a).node(&d).unwrap().pfail);
+
+        std::fs::remove_file(a.config.cluster_config_file()).unwrap();
+    }
+
+    #[test]
+    fn test_vote_for_replica() {
+        let mut a = node("vote");
+        let myself = cluster(&mut a).myself().id.clone();
+        let (b, c, d) = ("b".repeat(40), "c".repeat(40), "d".repeat(40));
+        cluster(&mut a).assign(0, &myself);
+        add_master(&mut a, &b, 1..2);
+        for replica in [&c, &d] {
+            cluster(&mut a).add_node(Node::new(replica.clone(), "127.0.0.1".into(), 7002, 17002));
+            cluster(&mut a).set_master(replica, Some(b.clone()));
+        }
+        cluster(&mut a).current_epoch = 1;
+        let ask = |a: &mut EventLoop, sender: &str, current_epoch: u64, config_epoch: u64| {
+            let mut request = a.cluster_message(MessageType::FailoverAuthRequest);
+            request.sender = sender.into();
+            request.master = Some(b.clone());
+            request.current_epoch = current_epoch;
+            request.config_epoch = config_epoch;
+            request.slots = vec![1];
+            a.vote_for_replica(-1, &request).unwrap();
+            cluster(a).last_vote_epoch
+        };
+
+        //refused while the master is up, to unknown nodes and to older epochs
+        assert_eq!(ask(&mut a, &c, 1, 0), 0);
+        cluster(&mut a).node_mut(&b).unwrap().fail_time = Some(Instant::now());
+        assert_eq!(ask(&mut a, &"f".repeat(40), 1, 0), 0);
+        assert_eq!(ask(&mut a, &c, 0, 0), 0);
+        //or claiming the slots with an epoch older than the one they are served with
+        cluster(&mut a).node_mut(&b).unwrap().config_epoch = 2;
+        assert_eq!(ask(&mut a, &c, 1, 1), 0);
+
+        assert_eq!(ask(&mut a, &c, 1, 2), 1);
+        assert!(cluster(&mut a).node(&b).unwrap().voted_time.is_some());
+
+        //a single vote per epoch, and none for the same master again until the timeout
+        assert_eq!(ask(&mut a, &d, 1, 2), 1);
+        cluster(&mut a).current_epoch = 2;
+        assert_eq!(ask(&mut a, &d, 2, 2), 1);
+        cluster(&mut a).node_mut(&b).unwrap().voted_time = None;
+        assert_eq!(ask(&mut a, &d, 2, 2), 2);
+
+        std::fs::remove_file(a.config.cluster_config_file()).unwrap();
+    }
+
+    #[test]
+    fn test_replica_election() {
+        let mut a = node("election");
+        let myself = cluster(&mut a).myself().id.clone();
+        let (b, c) = ("b".repeat(40), "c".repeat(40));
+        add_master(&mut a, &b, 0..8192);
+        add_master(&mut a, &c, 8192..16384);
+        cluster(&mut a).set_master(&myself, Some(b.clone()));
+        cluster(&mut a).current_epoch = 4;
+
+        //nothing to do while the master is up
+        a.replica_failover().unwrap();
+        assert!(a.cluster.as_ref().unwrap().election.is_none());
+        cluster(&mut a).node_mut(&b).unwrap().fail_time = Some(Instant::now());
+
+        //the election starts after a delay, in a new epoch
+        a.replica_failover().unwrap();
+        let election = a.cluster.as_mut().unwrap().election.as_mut().unwrap();
+        assert!(election.auth_time > Instant::now() && election.epoch.is_none());
+        election.auth_time = Instant::now();
+        a.replica_failover().unwrap();
+        assert_eq!(cluster(&mut a).current_epoch, 5);
+        let election = a.cluster.as_mut().unwrap().election.as_mut().unwrap();
+        assert_eq!(election.epoch, Some(5));
+
+        //a vote short of the majority of the two masters
+        election.votes.insert(c.clone());
+        a.replica_failover().unwrap();
+        assert!(!cluster(&mut a).myself().is_master());
+
+        a.cluster
+            .as_mut()
+            .unwrap()
+            .election
+            .as_mut()
+            .unwrap()
+            .votes
+            .insert(b.clone());
+        a.replica_failover().unwrap();
+        let cluster = cluster(&mut a);
+        assert!(cluster.myself().is_master());
+        assert_eq!(cluster.myself().config_epoch, 5);
+        assert_eq!(cluster.slot_ranges(&myself), vec![(0, 8191)]);
+        assert!(cluster.slots(&b).is_empty());
+        assert!(a.cluster.as_ref().unwrap().election.is_none());
+
+        std::fs::remove_file(a.config.cluster_config_file()).unwrap();
+    }
+
+    #[test]
+    fn test_config_epoch_collision() {
+        let mut a = node("collision");
+        let myself = cluster(&mut a).myself().id.clone();
+        let (lower, greater) = ("0".repeat(40), "f".repeat(40));
+        cluster(&mut a).assign(0, &myself);
+        add_master(&mut a, &lower, 1..2);
+        add_master(&mut a, &greater, 2..3);
+        for id in [&myself, &lower, &greater] {
+            cluster(&mut a).node_mut(id).unwrap().config_epoch = 1;
+        }
+        cluster(&mut a).current_epoch = 1;
+        let ping = |a: &mut EventLoop, sender: &str, slot: u16| {
+            let mut message = a.cluster_message(MessageType::Ping);
+            message.sender = sender.into();
+            message.config_epoch = 1;
+            message.slots = vec![slot];
+            a.update_cluster_slots(sender, &message).unwrap();
+            cluster(a).myself().config_epoch
+        };
+
+        //the node with the lowest id of the two moves on to a new epoch
+        assert_eq!(ping(&mut a, &lower, 1), 1);
+        assert_eq!(ping(&mut a, &greater, 2), 2);
+        assert_eq!(cluster(&mut a).current_epoch, 2);
+        //once they differ, there is no collision anymore
+        assert_eq!(ping(&mut a, &greater, 2), 2);
+
+        std::fs::remove_file(a.config.cluster_config_file()).unwrap();
+    }
+}
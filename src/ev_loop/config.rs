@@ -0,0 +1,188 @@
+use std::io;
+
+use crate::{command::ConfigSubcommand, config::Config, ev_loop::EventLoop, resp::RespType};
+
+impl EventLoop {
+    /// Applies the settings read at startup.
+    pub fn configure(&mut self, config: Config) -> io::Result<()> {
+        if let Some(path) = config.aclfile() {
+            self.load_acl_file(path)?;
+        }
+        self.config = config;
+
+        //without requirepass the default user is left as the ACL file defines it
+        if !self.config.requirepass().is_empty() {
+            self.apply_config("requirepass").map_err(io::Error::other)?;
+        }
+        self.configure_replication();
+        self.configure_cluster()?;
+        self.configure_sentinel()?;
+        self.apply_config("notify-keyspace-events")
+            .map_err(io::Error::other)
+    }
+
+    pub(super) fn handle_config(&mut self, subcommand: ConfigSubcommand) -> RespType {
+        match subcommand {
+            ConfigSubcommand::Get { parameters } => {
+                let bulk = |s: &str| RespType::BulkString {
+                    data: s.as_bytes().to_vec(),
+                };
+                let entries = self
+                    .config
+                    .get(&parameters)
+                    .into_iter()
+                    .map(|(name, value)| (bulk(name), bulk(&value)))
+                    .collect();
+
+                RespType::Map { entries }
+            }
+            ConfigSubcommand::Set { parameters } => self
+                .set_config(&parameters)
+                .map(|_| ok())
+                .unwrap_or_else(|content| RespType::SimpleError { content }),
+            ConfigSubcommand::ResetStat => {
+                self.stats.reset();
+                ok()
+            }
+            ConfigSubcommand::Rewrite => self
+                .config
+                .rewrite()
+                .map(|_| ok())
+                .unwrap_or_else(|content| RespType::SimpleError { content }),
+        }
+    }
+
+    //sets and applies every parameter, if one can't be applied the previous values are restored
+    fn set_config(&mut self, parameters: &[(String, String)]) -> Result<(), String> {
+        let previous = self.config.clone();
+        let names = self.config.set_all(parameters)?;
+
+        let Some((name, err)) = names
+            .iter()
+            .find_map(|name| self.apply_config(name).err().map(|err| (name, err)))
+        else {
+            return Ok(());
+        };
+
+        self.config = previous;
+        for name in &names {
+            if let Err(err) = self.apply_config(name) {
+                println!("Could not restore {name}, got error {err}");
+            }
+        }
+
+        Err(format!(
+            "ERR CONFIG SET failed (possibly related to argument '{name}') - {err}"
+        ))
+    }
+
+    //makes the current value of the parameter effective, most are simply read when needed
+    fn apply_config(&mut self, name: &str) -> Result<(), String> {
+        match name {
+            "requirepass" => {
+                let password = self.config.requirepass().to_string();
+                self.set_requirepass(&password);
+            }
+            "notify-keyspace-events" => self
+                .redis
+                .set_notify_keyspace_events(self.config.notify_keyspace_events()),
+            "dir" => {
+                std::env::set_current_dir(self.config.dir()).map_err(|err| err.to_string())?;
+                //kept absolute, the files of the dataset are found from it
+                let dir = std::env::current_dir().map_err(|err| err.to_string())?;
+                self.config.set("dir", &[dir.display().to_string()])?;
+            }
+            "appendonly" => self.apply_appendonly().map_err(|err| err.to_string())?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn ok() -> RespType {
+    RespType::SimpleString {
+        content: "OK".into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        command::ConfigSubcommand,
+        ev_loop::{EventLoop, test_util::event_loop},
+        resp::RespType,
+    };
+
+    fn set(event_loop: &mut EventLoop, parameters: &[(&str, &str)]) -> RespType {
+        event_loop.handle_config(ConfigSubcommand::Set {
+            parameters: parameters
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn test_config_set() {
+        let mut event_loop = event_loop(&[]);
+
+        let reply = set(
+            &mut event_loop,
+            &[("notify-keyspace-events", "KEA"), ("requirepass", "secret")],
+        );
+        assert_eq!(
+            reply,
+            RespType::SimpleString {
+                content: "OK".into()
+            }
+        );
+        assert_eq!(event_loop.initial_user(), None);
+
+        let reply = event_loop.handle_config(ConfigSubcommand::Get {
+            parameters: vec!["notify-*".into()],
+        });
+        assert_eq!(
+            reply,
+            RespType::Map {
+                entries: vec![(
+                    RespType::BulkString {
+                        data: b"notify-keyspace-events".to_vec()
+                    },
+                    RespType::BulkString {
+                        data: b"AKE".to_vec()
+                    }
+                )]
+            }
+        );
+
+        //the directory can't be changed, so neither is the password
+        let reply = set(
+            &mut event_loop,
+            &[("requirepass", ""), ("dir", "/no/such/directory")],
+        );
+        let RespType::SimpleError { content } = reply else {
+            panic!("expected an error, got {reply:?}");
+        };
+        assert!(content.starts_with(
+            "ERR CONFIG SET failed (possibly related to argument 'dir') - No such file or directory"
+        ));
+        assert_eq!(event_loop.config.requirepass(), "secret");
+        assert_eq!(event_loop.initial_user(), None);
+    }
+
+    #[test]
+    fn test_resetstat() {
+        let mut event_loop = event_loop(&[]);
+        event_loop.stats.commands_processed = 10;
+
+        event_loop.handle_config(ConfigSubcommand::ResetStat);
+        let RespType::BulkString { data } = event_loop.handle_info(vec!["stats".into()]) else {
+            panic!("expected a bulk string");
+        };
+        let info = String::from_utf8(data).unwrap();
+        assert!(info.starts_with("# Stats\r\n"));
+        assert!(info.contains("total_commands_processed:0\r\n"));
+        assert!(!info.contains("# Server"));
+    }
+}
@@ -0,0 +1,790 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use mlua::{Function, Lua, RegistryKey, Table, Value, Variadic};
+
+use crate::{
+    command::{Command, FunctionSubcommand, RestorePolicy},
+    ev_loop::{
+        EventLoop,
+        scripting::{BUSY, REDIS, RunningScript, error_message, interpreter},
+    },
+    glob::glob_match,
+    rdb::{OPCODE_FUNCTION2, Reader, open_payload, seal_payload, write_string},
+    resp::RespType,
+};
+
+//the code of a library runs only to register its functions, it can't take longer than this
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+const FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oob",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+/// The libraries loaded with FUNCTION LOAD, kept in an interpreter of their own so that they
+/// survive SCRIPT FLUSH and FLUSHALL.
+#[derive(Debug)]
+pub(super) struct Functions {
+    lua: Rc<Lua>,
+    libraries: BTreeMap<String, Library>,
+    //name of the library registering each function
+    index: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+struct Library {
+    code: String,
+    functions: BTreeMap<String, LibraryFunction>,
+}
+
+#[derive(Debug)]
+struct LibraryFunction {
+    callback: RegistryKey,
+    description: Option<String>,
+    flags: Vec<String>,
+}
+
+impl Functions {
+    pub(super) fn new() -> Self {
+        Self {
+            lua: Rc::new(interpreter()),
+            libraries: BTreeMap::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    /// Runs the code of a library, returning its name once its functions are registered.
+    pub(super) fn load(&mut self, code: &str, replace: bool) -> Result<String, String> {
+        let (name, library) = compile(&self.lua, code)?;
+        self.install(vec![(name.clone(), library)], replace)?;
+        Ok(name)
+    }
+
+    /// The code of every library, serialized the way RDB files store them.
+    pub(super) fn dump(&self) -> Vec<u8> {
+        let mut payload = vec![];
+        self.write_libraries(&mut payload);
+        seal_payload(payload)
+    }
+
+    /// Appends the code of every library, each behind its opcode, as RDB files carry them.
+    pub(super) fn write_libraries(&self, out: &mut Vec<u8>) {
+        for library in self.libraries.values() {
+            out.push(OPCODE_FUNCTION2);
+            write_string(out, library.code.as_bytes());
+        }
+    }
+
+    /// Loads the libraries of a DUMP payload, nothing is loaded if any of them fails.
+    pub(super) fn restore(&mut self, payload: &[u8], policy: RestorePolicy) -> Result<(), String> {
+        let codes = library_codes(payload)?;
+        self.restore_libraries(&codes, policy)
+    }
+
+    /// Loads the libraries from their code, nothing is loaded if any of them fails.
+    pub(super) fn restore_libraries(
+        &mut self,
+        codes: &[String],
+        policy: RestorePolicy,
+    ) -> Result<(), String> {
+        match policy {
+            RestorePolicy::Flush => {
+                let mut functions = Functions::new();
+                let libraries = codes
+                    .iter()
+                    .map(|code| compile(&functions.lua, code))
+                    .collect::<Result<_, _>>()?;
+                functions.install(libraries, false)?;
+                *self = functions;
+                Ok(())
+            }
+            RestorePolicy::Append | RestorePolicy::Replace => {
+                let libraries = codes
+                    .iter()
+                    .map(|code| compile(&self.lua, code))
+                    .collect::<Result<_, _>>()?;
+                self.install(libraries, policy == RestorePolicy::Replace)
+            }
+        }
+    }
+
+    //adds the libraries once sure none of them clashes with what is already loaded
+    fn install(&mut self, libraries: Vec<(String, Library)>, replace: bool) -> Result<(), String> {
+        let mut index = self.index.clone();
+        for (name, library) in &libraries {
+            if self.libraries.contains_key(name) || libraries_named(&libraries, name) > 1 {
+                if !replace {
+                    return Err(format!("ERR Library '{name}' already exists"));
+                }
+                index.retain(|_, library| library != name);
+            }
+            for function in library.functions.keys() {
+                if index.insert(function.clone(), name.clone()).is_some() {
+                    return Err(format!("ERR Function {function} already exists"));
+                }
+            }
+        }
+
+        for (name, library) in libraries {
+            self.libraries.insert(name, library);
+        }
+        self.index = index;
+        self.lua.expire_registry_values();
+        Ok(())
+    }
+
+    fn delete(&mut self, name: &str) -> bool {
+        if self.libraries.remove(name).is_none() {
+            return false;
+        }
+        self.index.retain(|_, library| library != name);
+        self.lua.expire_registry_values();
+        true
+    }
+
+    fn get(&self, function: &str) -> Option<&LibraryFunction> {
+        let library = self.index.get(function)?;
+        self.libraries.get(library)?.functions.get(function)
+    }
+
+    fn list(&self, pattern: Option<&str>, with_code: bool) -> RespType {
+        let elements = self
+            .libraries
+            .iter()
+            .filter(|(name, _)| {
+                pattern.is_none_or(|pattern| glob_match(pattern.as_bytes(), name.as_bytes()))
+            })
+            .map(|(name, library)| {
+                let mut entries = vec![
+                    (bulk("library_name"), bulk(name)),
+                    (bulk("engine"), bulk("LUA")),
+                    (
+                        bulk("functions"),
+                        RespType::Array {
+                            elements: library
+                                .functions
+                                .iter()
+                                .map(|(name, function)| function.describe(name))
+                                .collect(),
+                        },
+                    ),
+                ];
+                if with_code {
+                    entries.push((bulk("library_code"), bulk(&library.code)));
+                }
+                RespType::Map { entries }
+            })
+            .collect();
+
+        RespType::Array { elements }
+    }
+}
+
+impl LibraryFunction {
+    fn describe(&self, name: &str) -> RespType {
+        RespType::Map {
+            entries: vec![
+                (bulk("name"), bulk(name)),
+                (
+                    bulk("description"),
+                    self.description
+                        .as_deref()
+                        .map_or(RespType::NullBulkString, bulk),
+                ),
+                (
+                    bulk("flags"),
+                    RespType::Array {
+                        elements: self.flags.iter().map(|flag| bulk(flag)).collect(),
+                    },
+                ),
+            ],
+        }
+    }
+}
+
+impl EventLoop {
+    /// FCALL and FCALL_RO, the function gets the keys and the arguments as two tables.
+    pub(super) fn handle_fcall(&mut self, client_id: i32, cmd: Command) -> RespType {
+        let Command::FCall {
+            function: name,
+            keys,
+            args,
+            read_only,
+        } = cmd
+        else {
+            panic!("Illegal state");
+        };
+
+        let Some(function) = self.functions.get(&name) else {
+            return RespType::SimpleError {
+                content: "ERR Function not found".into(),
+            };
+        };
+        let no_writes = function.flags.iter().any(|flag| flag == "no-writes");
+        if read_only && !no_writes {
+            return RespType::SimpleError {
+                content: "ERR Can not execute a script with write flag using *_ro command.".into(),
+            };
+        }
+
+        //the interpreter is kept alive by this handle while the event loop is lent to the function
+        let lua = Rc::clone(&self.functions.lua);
+        let prepared = lua
+            .registry_value::<Function>(&function.callback)
+            .and_then(|callback| {
+                let keys = Value::Table(lua.create_sequence_from(keys)?);
+                let args = Value::Table(lua.create_sequence_from(args)?);
+                Ok((callback, vec![keys, args]))
+            });
+
+        match prepared {
+            Ok((callback, args)) => self.run_script(
+                &lua,
+                client_id,
+                &name,
+                callback,
+                args,
+                RunningScript::new(true, read_only || no_writes),
+            ),
+            Err(err) => RespType::SimpleError {
+                content: format!("ERR {}", error_message(&err)),
+            },
+        }
+    }
+
+    /// FUNCTION, the subcommands changing the libraries are propagated to the AOF.
+    pub(super) fn handle_function(&mut self, subcommand: FunctionSubcommand) -> RespType {
+        let result = match subcommand {
+            FunctionSubcommand::Load { code, replace } => {
+                self.functions.load(&code, replace).map(|name| {
+                    let option = if replace { &["REPLACE"][..] } else { &[] };
+                    self.redis
+                        .propagate(&[&["FUNCTION", "LOAD"], option, &[code.as_str()]].concat());
+                    RespType::BulkString {
+                        data: name.into_bytes(),
+                    }
+                })
+            }
+            FunctionSubcommand::Delete { library } => {
+                if self.functions.delete(&library) {
+                    self.redis.propagate(&["FUNCTION", "DELETE", &library]);
+                    Ok(ok())
+                } else {
+                    Err("ERR Library not found".to_string())
+                }
+            }
+            FunctionSubcommand::List { pattern, with_code } => {
+                Ok(self.functions.list(pattern.as_deref(), with_code))
+            }
+            FunctionSubcommand::Dump => Ok(RespType::BulkString {
+                data: self.functions.dump(),
+            }),
+            FunctionSubcommand::Restore { payload, policy } => {
+                self.functions.restore(&payload, policy).map(|_| {
+                    let policy: &[u8] = match policy {
+                        RestorePolicy::Append => b"APPEND",
+                        RestorePolicy::Replace => b"REPLACE",
+                        RestorePolicy::Flush => b"FLUSH",
+                    };
+                    self.redis
+                        .propagate(&[b"FUNCTION".as_slice(), b"RESTORE", &payload, policy]);
+                    ok()
+                })
+            }
+            FunctionSubcommand::Flush => {
+                self.functions = Functions::new();
+                self.redis.propagate(&["FUNCTION", "FLUSH"]);
+                Ok(ok())
+            }
+            FunctionSubcommand::Kill => Err("NOTBUSY No scripts in execution right now.".into()),
+        };
+
+        result.unwrap_or_else(|content| RespType::SimpleError { content })
+    }
+}
+
+//runs the code of a library in the interpreter, with redis.register_function available
+fn compile(lua: &Lua, code: &str) -> Result<(String, Library), String> {
+    let (name, body) = parse_metadata(code)?;
+
+    //the metadata line is blanked out so that errors point to the right line
+    let chunk = lua
+        .load(format!("\n{body}"))
+        .set_name("@user_function")
+        .into_function()
+        .map_err(|err| format!("ERR Error compiling function: {}", error_message(&err)))?;
+
+    let registered = RefCell::new(BTreeMap::new());
+    let started = Instant::now();
+    let result = lua.scope(|scope| {
+        let register = scope.create_function(|lua, args: Variadic<Value>| {
+            let (name, function) = register_args(lua, args)?;
+            let mut registered = registered.borrow_mut();
+            if registered.contains_key(&name) {
+                return Err(mlua::Error::RuntimeError(
+                    "Function already exists in the library".into(),
+                ));
+            }
+            registered.insert(name, function);
+            Ok(())
+        })?;
+        let busy = scope.create_function(|_, ()| {
+            Ok((started.elapsed() > LOAD_TIMEOUT).then(|| "FUNCTION LOAD timeout".to_string()))
+        })?;
+
+        let redis: Table = lua.named_registry_value(REDIS)?;
+        redis.raw_set("register_function", register)?;
+        lua.set_named_registry_value(BUSY, busy)?;
+
+        chunk.call::<_, ()>(())
+    });
+
+    //registering functions is only possible while the library loads
+    if let Ok(redis) = lua.named_registry_value::<Table>(REDIS) {
+        let _ = redis.raw_set("register_function", Value::Nil);
+    }
+    let _ = lua.unset_named_registry_value(BUSY);
+
+    result.map_err(|err| format!("ERR Error registering functions: {}", error_message(&err)))?;
+
+    let functions = registered.into_inner();
+    if functions.is_empty() {
+        return Err("ERR No functions registered".into());
+    }
+
+    Ok((
+        name,
+        Library {
+            code: code.to_string(),
+            functions,
+        },
+    ))
+}
+
+// #!<engine> name=<library>
+fn parse_metadata(code: &str) -> Result<(String, &str), String> {
+    let (header, body) = code.split_once('\n').unwrap_or((code, ""));
+    let Some(header) = header.strip_prefix("#!") else {
+        return Err("ERR Missing library metadata".into());
+    };
+
+    let mut parts = header.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("ERR Engine '{engine}' not found"));
+    }
+
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value),
+            None => return Err(format!("ERR Invalid metadata value given: {part}")),
+        }
+    }
+
+    let Some(name) = name else {
+        return Err("ERR Library name was not given".into());
+    };
+    if !valid_name(name) {
+        return Err("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".into());
+    }
+
+    Ok((name.to_string(), body))
+}
+
+// redis.register_function(<name>, <callback>) or
+// redis.register_function{function_name=<name>, callback=<callback>, flags=<flags>, description=<description>}
+fn register_args<'lua>(
+    lua: &'lua Lua,
+    args: Variadic<Value<'lua>>,
+) -> mlua::Result<(String, LibraryFunction)> {
+    let error = |message: &str| mlua::Error::RuntimeError(message.to_string());
+
+    let (name, callback, flags, description) = match args.as_slice() {
+        [Value::Table(table)] => {
+            let (mut name, mut callback, mut flags, mut description) = (None, None, None, None);
+            for pair in table.clone().pairs::<String, Value>() {
+                let (key, value) = pair?;
+                match key.as_str() {
+                    "function_name" => name = Some(value),
+                    "callback" => callback = Some(value),
+                    "flags" => flags = Some(value),
+                    "description" => description = Some(value),
+                    _ => return Err(error("unknown argument given to redis.register_function")),
+                }
+            }
+            (
+                name.ok_or_else(|| {
+                    error("redis.register_function must get a function name argument")
+                })?,
+                callback
+                    .ok_or_else(|| error("redis.register_function must get a callback argument"))?,
+                flags,
+                description,
+            )
+        }
+        [name, callback] => (name.clone(), callback.clone(), None, None),
+        _ => {
+            return Err(error(
+                "wrong number of arguments to redis.register_function",
+            ));
+        }
+    };
+
+    let Value::String(name) = name else {
+        return Err(error(
+            "function_name argument given to redis.register_function must be a string",
+        ));
+    };
+    let name = name.to_str()?.to_string();
+    if !valid_name(&name) {
+        return Err(error(
+            "Function names can only contain letters, numbers, or underscores(_) and must be at least one character long",
+        ));
+    }
+
+    let Value::Function(callback) = callback else {
+        return Err(error(
+            "callback argument given to redis.register_function must be a function",
+        ));
+    };
+
+    let description = match description {
+        None | Some(Value::Nil) => None,
+        Some(Value::String(description)) => Some(description.to_str()?.to_string()),
+        Some(_) => {
+            return Err(error(
+                "description argument given to redis.register_function must be a string",
+            ));
+        }
+    };
+
+    let flags = match flags {
+        None | Some(Value::Nil) => vec![],
+        Some(Value::Table(flags)) => flags
+            .sequence_values::<Value>()
+            .map(|flag| match flag? {
+                Value::String(flag) if FLAGS.contains(&flag.to_str()?) => {
+                    Ok(flag.to_str()?.to_string())
+                }
+                _ => Err(error("Unknown flag given")),
+            })
+            .collect::<mlua::Result<_>>()?,
+        Some(_) => {
+            return Err(error(
+                "flags argument to redis.register_function must be a table representing function flags",
+            ));
+        }
+    };
+
+    Ok((
+        name,
+        LibraryFunction {
+            callback: lua.create_registry_value(callback)?,
+            description,
+            flags,
+        },
+    ))
+}
+
+//the code of each library carried by a FUNCTION DUMP payload
+fn library_codes(payload: &[u8]) -> Result<Vec<String>, String> {
+    let values = open_payload(payload).ok_or("ERR payload version or checksum are wrong")?;
+
+    let not_a_dump = || "ERR given payload is not a function dump".to_string();
+    let mut reader = Reader::new(values);
+    let mut codes = vec![];
+    while !reader.is_empty() {
+        if reader.read_u8().map_err(|_| not_a_dump())? != OPCODE_FUNCTION2 {
+            return Err(not_a_dump());
+        }
+        let code = reader.read_string().map_err(|_| not_a_dump())?;
+        codes.push(String::from_utf8(code).map_err(|_| not_a_dump())?);
+    }
+
+    Ok(codes)
+}
+
+fn libraries_named(libraries: &[(String, Library)], name: &str) -> usize {
+    libraries
+        .iter()
+        .filter(|(library, _)| library == name)
+        .count()
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn bulk(s: &str) -> RespType {
+    RespType::BulkString {
+        data: s.as_bytes().to_vec(),
+    }
+}
+
+fn ok() -> RespType {
+    RespType::SimpleString {
+        content: "OK".into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        command::{Command, FunctionSubcommand, RestorePolicy},
+        ev_loop::{EventLoop, test_util::event_loop},
+        resp::RespType,
+    };
+
+    const LIBRARY: &str = "#!lua name=mylib
+local function set(keys, args)
+    return redis.call('set', keys[1], args[1])
+end
+redis.register_function('myset', set)
+redis.register_function{
+    function_name = 'myget',
+    callback = function(keys) return redis.call('get', keys[1]) end,
+    flags = {'no-writes', 'allow-oob'},
+    description = 'reads a key',
+}";
+
+    fn fcall(event_loop: &mut EventLoop, function: &str, key: &str, read_only: bool) -> RespType {
+        event_loop.handle_fcall(
+            1,
+            Command::FCall {
+                function: function.into(),
+                keys: vec![key.into()],
+                args: vec!["value".into()],
+                read_only,
+            },
+        )
+    }
+
+    fn load(event_loop: &mut EventLoop, code: &str, replace: bool) -> RespType {
+        event_loop.handle_function(FunctionSubcommand::Load {
+            code: code.into(),
+            replace,
+        })
+    }
+
+    fn error_content(resp: RespType) -> String {
+        match resp {
+            RespType::SimpleError { content } => content,
+            other => panic!("expected an error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_load_and_fcall() {
+        let mut event_loop = event_loop(&[]);
+
+        assert_eq!(
+            load(&mut event_loop, LIBRARY, false),
+            RespType::BulkString {
+                data: b"mylib".to_vec()
+            }
+        );
+        assert_eq!(
+            error_content(load(&mut event_loop, LIBRARY, false)),
+            "ERR Library 'mylib' already exists"
+        );
+        assert!(matches!(
+            load(&mut event_loop, LIBRARY, true),
+            RespType::BulkString { .. }
+        ));
+
+        assert_eq!(
+            fcall(&mut event_loop, "myset", "key", false),
+            RespType::SimpleString {
+                content: "OK".into()
+            }
+        );
+        assert_eq!(
+            fcall(&mut event_loop, "myget", "key", true),
+            RespType::BulkString {
+                data: b"value".to_vec()
+            }
+        );
+        assert_eq!(
+            error_content(fcall(&mut event_loop, "myset", "key", true)),
+            "ERR Can not execute a script with write flag using *_ro command."
+        );
+        assert_eq!(
+            error_content(fcall(&mut event_loop, "missing", "key", false)),
+            "ERR Function not found"
+        );
+
+        //functions are not wiped along with the scripts
+        event_loop.handle_script(crate::command::ScriptSubcommand::Flush);
+        assert!(matches!(
+            fcall(&mut event_loop, "myget", "key", false),
+            RespType::BulkString { .. }
+        ));
+
+        let other = "#!lua name=other\nredis.register_function('myget', function() end)";
+        assert_eq!(
+            error_content(load(&mut event_loop, other, false)),
+            "ERR Function myget already exists"
+        );
+
+        assert_eq!(
+            error_content(load(&mut event_loop, "return 1", false)),
+            "ERR Missing library metadata"
+        );
+        assert_eq!(
+            error_content(load(&mut event_loop, "#!js name=lib\n", false)),
+            "ERR Engine 'js' not found"
+        );
+        assert_eq!(
+            error_content(load(
+                &mut event_loop,
+                "#!lua name=empty\nlocal x = 1",
+                false
+            )),
+            "ERR No functions registered"
+        );
+        let bad_flag = "#!lua name=flags\nredis.register_function{function_name='f', callback=function() end, flags={'fast'}}";
+        assert!(
+            error_content(load(&mut event_loop, bad_flag, false))
+                .starts_with("ERR Error registering functions: ")
+        );
+
+        assert_eq!(
+            event_loop.handle_function(FunctionSubcommand::Delete {
+                library: "mylib".into()
+            }),
+            RespType::SimpleString {
+                content: "OK".into()
+            }
+        );
+        assert_eq!(
+            error_content(fcall(&mut event_loop, "myget", "key", false)),
+            "ERR Function not found"
+        );
+    }
+
+    #[test]
+    fn test_libraries_isolated() {
+        let mut event_loop = event_loop(&[]);
+        load(&mut event_loop, LIBRARY, false);
+
+        //the escapes fail both while the library loads and when its function runs
+        let intruder = "#!lua name=intruder
+local function escape()
+    local failed = 0
+    for _, attempt in ipairs({
+        function() redis.call = function() return 'hij' end end,
+        function() rawset(redis, 'call', function() return 'hij' end) end,
+        function() rawset(_G, 'shared', 'hij') end,
+        function() setmetatable(_G, nil) end,
+        function() string.format = function() return 'hij' end end,
+        function() local _, r = pairs(redis); r.call = function() return 'hij' end end,
+        function() local _, g = pairs(_G); g.shared = 'hij' end,
+        function() local _, s = ipairs(string); s.format = function() return 'hij' end end,
+    }) do
+        if not pcall(attempt) then
+            failed = failed + 1
+        end
+    end
+    return failed
+end
+assert(escape() == 8)
+redis.register_function('escape', escape)";
+        assert_eq!(
+            load(&mut event_loop, intruder, false),
+            RespType::BulkString {
+                data: b"intruder".to_vec()
+            }
+        );
+        assert_eq!(
+            fcall(&mut event_loop, "escape", "key", false),
+            RespType::Integer { integer: 8 }
+        );
+
+        fcall(&mut event_loop, "myset", "key", false);
+        assert_eq!(
+            fcall(&mut event_loop, "myget", "key", true),
+            RespType::BulkString {
+                data: b"value".to_vec()
+            }
+        );
+        let probe = "#!lua name=probe
+redis.register_function('probe', function() return string.format('%s', type(shared)) end)";
+        load(&mut event_loop, probe, false);
+        assert!(
+            error_content(fcall(&mut event_loop, "probe", "key", false))
+                .contains("nonexistent global variable 'shared'")
+        );
+    }
+
+    #[test]
+    fn test_list_dump_restore() {
+        let mut other = event_loop(&[]);
+        let mut event_loop = event_loop(&[]);
+        load(&mut event_loop, LIBRARY, false);
+
+        let RespType::Array { elements } = event_loop.handle_function(FunctionSubcommand::List {
+            pattern: Some("my*".into()),
+            with_code: true,
+        }) else {
+            panic!("expected an array");
+        };
+        let [RespType::Map { entries }] = elements.as_slice() else {
+            panic!("expected a single library");
+        };
+        assert_eq!(entries.len(), 4);
+        let RespType::Array {
+            elements: functions,
+        } = &entries[2].1
+        else {
+            panic!("expected the functions");
+        };
+        assert_eq!(functions.len(), 2);
+
+        let RespType::BulkString { data: payload } =
+            event_loop.handle_function(FunctionSubcommand::Dump)
+        else {
+            panic!("expected a payload");
+        };
+
+        let restore = |event_loop: &mut EventLoop, payload: &[u8], policy| {
+            event_loop.handle_function(FunctionSubcommand::Restore {
+                payload: payload.to_vec(),
+                policy,
+            })
+        };
+        assert_eq!(
+            restore(&mut other, &payload, RestorePolicy::Append),
+            RespType::SimpleString {
+                content: "OK".into()
+            }
+        );
+        assert_eq!(
+            error_content(restore(&mut other, &payload, RestorePolicy::Append)),
+            "ERR Library 'mylib' already exists"
+        );
+        assert!(matches!(
+            restore(&mut other, &payload, RestorePolicy::Flush),
+            RespType::SimpleString { .. }
+        ));
+        assert_eq!(
+            fcall(&mut other, "myget", "key", true),
+            RespType::NullBulkString
+        );
+
+        let mut tampered = payload.clone();
+        tampered[1] ^= 1;
+        assert_eq!(
+            error_content(restore(&mut other, &tampered, RestorePolicy::Replace)),
+            "ERR payload version or checksum are wrong"
+        );
+    }
+}
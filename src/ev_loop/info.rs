@@ -0,0 +1,183 @@
+use std::time::Instant;
+
+use crate::{
+    alloc::used_memory,
+    ev_loop::{EventLoop, replication::new_replid},
+    redis::REDIS_VERSION,
+    resp::RespType,
+};
+
+const SECTIONS: [&str; 7] = [
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "replication",
+    "cluster",
+];
+//a sentinel has no dataset to report about
+const SENTINEL_SECTIONS: [&str; 4] = ["server", "clients", "stats", "sentinel"];
+
+/// Counters reported by INFO, CONFIG RESETSTAT sets them back to zero.
+#[derive(Debug)]
+pub(super) struct Stats {
+    //not statistics, the uptime and the id of the process are never reset
+    started: Instant,
+    pub(super) run_id: String,
+    pub(super) connections_received: u64,
+    pub(super) commands_processed: u64,
+    //connections refused by protected mode
+    pub(super) rejected_connections: u64,
+    //keys deleted to honour maxmemory
+    pub(super) evicted_keys: u64,
+}
+
+impl Stats {
+    pub(super) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            run_id: new_replid(),
+            connections_received: 0,
+            commands_processed: 0,
+            rejected_connections: 0,
+            evicted_keys: 0,
+        }
+    }
+
+    pub(super) fn reset(&mut self) {
+        *self = Self {
+            started: self.started,
+            run_id: std::mem::take(&mut self.run_id),
+            ..Self::new()
+        };
+    }
+}
+
+impl EventLoop {
+    /// INFO: the requested sections, every section when none is given.
+    pub(super) fn handle_info(&self, sections: Vec<String>) -> RespType {
+        let all = sections.is_empty()
+            || sections
+                .iter()
+                .any(|section| matches!(section.as_str(), "all" | "default" | "everything"));
+
+        let available: &[&str] = match self.sentinel {
+            Some(_) => &SENTINEL_SECTIONS,
+            None => &SECTIONS,
+        };
+        let text = available
+            .iter()
+            .filter(|name| all || sections.iter().any(|section| section == *name))
+            .map(|name| self.info_section(name))
+            .collect::<Vec<_>>()
+            .join("\r\n");
+
+        RespType::BulkString {
+            data: text.into_bytes(),
+        }
+    }
+
+    /// The mode reported by INFO and HELLO.
+    pub(super) fn mode(&self) -> &'static str {
+        match (self.sentinel.is_some(), self.config.cluster_enabled()) {
+            (true, _) => "sentinel",
+            (false, true) => "cluster",
+            (false, false) => "standalone",
+        }
+    }
+
+    fn info_section(&self, name: &str) -> String {
+        //the names of its fields are not all known in advance
+        let replication;
+        let sentinel;
+        let fields: Vec<(&str, String)> = match name {
+            "server" => {
+                let uptime = self.stats.started.elapsed().as_secs();
+                vec![
+                    ("redis_version", REDIS_VERSION.to_string()),
+                    ("redis_mode", self.mode().to_string()),
+                    ("process_id", std::process::id().to_string()),
+                    ("run_id", self.stats.run_id.clone()),
+                    ("tcp_port", self.config.port().to_string()),
+                    ("uptime_in_seconds", uptime.to_string()),
+                    ("uptime_in_days", (uptime / 86400).to_string()),
+                    ("hz", self.config.hz().to_string()),
+                    (
+                        "config_file",
+                        self.config
+                            .file()
+                            .map(|path| path.display().to_string())
+                            .unwrap_or_default(),
+                    ),
+                ]
+            }
+            "clients" => vec![("connected_clients", self.clients.len().to_string())],
+            "memory" => {
+                let used = used_memory();
+                let maxmemory = self.config.maxmemory().unwrap_or(0);
+                vec![
+                    ("used_memory", used.to_string()),
+                    ("used_memory_human", bytes_to_human(used)),
+                    ("maxmemory", maxmemory.to_string()),
+                    ("maxmemory_human", bytes_to_human(maxmemory)),
+                    (
+                        "maxmemory_policy",
+                        self.config.maxmemory_policy().to_string(),
+                    ),
+                ]
+            }
+            "persistence" => self.persistence_info(),
+            "replication" => {
+                replication = self.replication_info();
+                replication
+                    .iter()
+                    .map(|(field, value)| (field.as_str(), value.clone()))
+                    .collect()
+            }
+            "cluster" => self.cluster_info(),
+            "sentinel" => {
+                sentinel = self.sentinel_info();
+                sentinel
+                    .iter()
+                    .map(|(field, value)| (field.as_str(), value.clone()))
+                    .collect()
+            }
+            _ => vec![
+                (
+                    "total_connections_received",
+                    self.stats.connections_received.to_string(),
+                ),
+                (
+                    "total_commands_processed",
+                    self.stats.commands_processed.to_string(),
+                ),
+                (
+                    "rejected_connections",
+                    self.stats.rejected_connections.to_string(),
+                ),
+                ("evicted_keys", self.stats.evicted_keys.to_string()),
+            ],
+        };
+
+        let mut title = name.to_string();
+        title[..1].make_ascii_uppercase();
+
+        let mut text = format!("# {title}\r\n");
+        for (field, value) in fields {
+            text.push_str(&format!("{field}:{value}\r\n"));
+        }
+        text
+    }
+}
+
+//like 1.50M, with the largest unit below the value
+fn bytes_to_human(bytes: usize) -> String {
+    const UNITS: [(&str, usize); 3] = [("G", 1 << 30), ("M", 1 << 20), ("K", 1 << 10)];
+
+    UNITS
+        .iter()
+        .find(|(_, size)| bytes >= *size)
+        .map(|(unit, size)| format!("{:.2}{unit}", bytes as f64 / *size as f64))
+        .unwrap_or_else(|| format!("{bytes}B"))
+}
@@ -0,0 +1,162 @@
+//! MIGRATE, moving keys to another server as DUMP payloads restored there. The event loop waits
+//! for the target to reply before serving anything else, so clients see the keys either here or
+//! there, never in both places nor nowhere.
+
+use std::{
+    io::{self, Read as _, Write as _},
+    net::{TcpStream, ToSocketAddrs as _},
+    time::Duration,
+};
+
+use crate::{command::Command, ev_loop::EventLoop, resp::RespType};
+
+impl EventLoop {
+    pub(super) fn handle_migrate(&mut self, client_id: i32, cmd: Command) -> RespType {
+        let Command::Migrate {
+            host,
+            port,
+            keys,
+            timeout,
+            copy,
+            replace,
+            auth,
+        } = cmd
+        else {
+            unreachable!("only MIGRATE is handled here");
+        };
+
+        let dumps: Vec<_> = keys
+            .into_iter()
+            .filter_map(|key| {
+                self.redis
+                    .dump(&key)
+                    .map(|(payload, ttl)| (key, payload, ttl))
+            })
+            .collect();
+        if dumps.is_empty() {
+            return RespType::SimpleString {
+                content: "NOKEY".into(),
+            };
+        }
+
+        let mut requests = vec![];
+        match auth {
+            Some((Some(username), password)) => requests.push(request(&[
+                b"AUTH",
+                username.as_bytes(),
+                password.as_bytes(),
+            ])),
+            Some((None, password)) => requests.push(request(&[b"AUTH", password.as_bytes()])),
+            None => {}
+        }
+        //a node importing the slot only accepts its keys this way
+        let restore: &[u8] = match self.cluster {
+            Some(_) => b"RESTORE-ASKING",
+            None => b"RESTORE",
+        };
+        let setup = requests.len();
+        for (key, payload, ttl) in &dumps {
+            //a key about to expire still needs a time to live
+            let ttl = ttl.map_or(0, |ttl| ttl.as_millis().max(1)).to_string();
+            let mut args = vec![restore, key.as_bytes(), ttl.as_bytes(), payload];
+            if replace {
+                args.push(b"REPLACE");
+            }
+            requests.push(request(&args));
+        }
+
+        let replies = match exchange(&host, port, timeout, &requests) {
+            Ok(replies) => replies,
+            Err(err) => {
+                println!("MIGRATE to {host}:{port} failed: {err}");
+                return RespType::SimpleError {
+                    content: format!("IOERR error or timeout {err}"),
+                };
+            }
+        };
+
+        let target_error = |reply: &RespType| match reply {
+            RespType::SimpleError { content } => Some(RespType::SimpleError {
+                content: format!("ERR Target instance replied with error: {content}"),
+            }),
+            _ => None,
+        };
+        if let Some(error) = replies[..setup].iter().find_map(target_error) {
+            return error;
+        }
+
+        //the keys restored are gone from here, even when others failed
+        let mut error = None;
+        let mut moved = vec![];
+        for ((key, _, _), reply) in dumps.into_iter().zip(&replies[setup..]) {
+            match target_error(reply) {
+                Some(reply) => error = error.or(Some(reply)),
+                None => moved.push(key),
+            }
+        }
+        if !copy && !moved.is_empty() {
+            let _ = self
+                .redis
+                .handle_command(Command::Del { keys: moved }, client_id);
+        }
+
+        error.unwrap_or(RespType::SimpleString {
+            content: "OK".into(),
+        })
+    }
+}
+
+//sends the requests all at once, returning a reply for each of them. The error tells at which
+//step it happened
+fn exchange(
+    host: &str,
+    port: u16,
+    timeout: Duration,
+    requests: &[RespType],
+) -> Result<Vec<RespType>, &'static str> {
+    let connect = || -> io::Result<TcpStream> {
+        let address = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other("no address"))?;
+        let stream = TcpStream::connect_timeout(&address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(stream)
+    };
+    let mut stream = connect().map_err(|_| "connecting to the client")?;
+
+    let data: Vec<u8> = requests.iter().flat_map(RespType::serialize).collect();
+    stream
+        .write_all(&data)
+        .map_err(|_| "writing to target instance")?;
+
+    let mut replies = vec![];
+    let mut input = vec![];
+    let mut buf = [0u8; 16 * 1024];
+    while replies.len() < requests.len() {
+        match RespType::parse(&input) {
+            Ok((reply, len)) => {
+                input.drain(..len);
+                replies.push(reply);
+                continue;
+            }
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+            Err(_) => return Err("reading to target instance"),
+        }
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => return Err("reading to target instance"),
+            Ok(read) => input.extend_from_slice(&buf[..read]),
+        }
+    }
+    Ok(replies)
+}
+
+fn request(args: &[&[u8]]) -> RespType {
+    RespType::Array {
+        elements: args
+            .iter()
+            .map(|arg| RespType::BulkString { data: arg.to_vec() })
+            .collect(),
+    }
+}
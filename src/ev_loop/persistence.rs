@@ -0,0 +1,494 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{command::RestorePolicy, ev_loop::EventLoop, resp::RespType};
+
+use super::aof::AofFile;
+
+//a failed background save triggered by the save rules is only retried after this delay
+const BGSAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// What INFO and the save rules need to know about the snapshots taken so far.
+#[derive(Debug)]
+pub(super) struct Snapshots {
+    //the process writing a snapshot or rewriting the AOF in the background, if any
+    child: Option<Child>,
+    //BGSAVE SCHEDULE was called while the AOF was being rewritten
+    bgsave_scheduled: bool,
+    last_save: SystemTime,
+    //value of the dirty counter the last snapshot was taken at
+    saved_dirty: u64,
+    last_bgsave_ok: bool,
+    last_bgsave_duration: Option<Duration>,
+    last_bgsave_try: Option<Instant>,
+    saves: u64,
+}
+
+#[derive(Debug)]
+struct Child {
+    pid: libc::pid_t,
+    started: Instant,
+    //value of the dirty counter when it was forked, the changes made after are not in the file
+    dirty: u64,
+    kind: ChildKind,
+}
+
+/// What a background process is busy with, there is at most one at a time.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum ChildKind {
+    Save,
+    //writing the new base of the AOF
+    AofRewrite { base: AofFile },
+}
+
+impl Snapshots {
+    pub(super) fn new() -> Self {
+        Self {
+            child: None,
+            bgsave_scheduled: false,
+            last_save: SystemTime::now(),
+            saved_dirty: 0,
+            last_bgsave_ok: true,
+            last_bgsave_duration: None,
+            last_bgsave_try: None,
+            saves: 0,
+        }
+    }
+
+    fn saved(&mut self, dirty: u64) {
+        self.last_save = SystemTime::now();
+        self.saved_dirty = dirty;
+        self.saves += 1;
+    }
+
+    pub(super) fn child_kind(&self) -> Option<&ChildKind> {
+        self.child.as_ref().map(|child| &child.kind)
+    }
+
+    /// How long the background process of that kind has been running, if there is one.
+    pub(super) fn child_running_for(&self, kind: fn(&ChildKind) -> bool) -> Option<Duration> {
+        self.child
+            .as_ref()
+            .filter(|child| kind(&child.kind))
+            .map(|child| child.started.elapsed())
+    }
+}
+
+impl EventLoop {
+    /// Loads the dataset from the RDB file of the configured directory, if there is one.
+    pub fn load_rdb(&mut self) -> io::Result<()> {
+        let path = self.rdb_path();
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        self.load_snapshot(&data).inspect_err(|err| {
+            eprintln!("Could not load {}, got error {err}", path.display());
+        })?;
+
+        println!("DB loaded from disk: {} bytes", data.len());
+        Ok(())
+    }
+
+    /// Replaces the dataset and the function libraries with the content of an RDB file.
+    pub(super) fn load_snapshot(&mut self, data: &[u8]) -> io::Result<()> {
+        let libraries = self.redis.load_rdb(data)?;
+        self.functions
+            .restore_libraries(&libraries, RestorePolicy::Flush)
+            .map_err(io::Error::other)
+    }
+
+    pub(super) fn handle_save(&mut self) -> RespType {
+        if self.snapshots.child_kind() == Some(&ChildKind::Save) {
+            return RespType::SimpleError {
+                content: "ERR Background save already in progress".into(),
+            };
+        }
+
+        match self.save() {
+            Ok(()) => {
+                self.snapshots.saved(self.redis.dirty());
+                RespType::SimpleString {
+                    content: "OK".into(),
+                }
+            }
+            Err(err) => {
+                println!("Error saving DB on disk: {err}");
+                RespType::SimpleError {
+                    content: "ERR".into(),
+                }
+            }
+        }
+    }
+
+    pub(super) fn handle_bgsave(&mut self, schedule: bool) -> RespType {
+        match self.snapshots.child_kind() {
+            Some(ChildKind::Save) => {
+                return RespType::SimpleError {
+                    content: "ERR Background save already in progress".into(),
+                };
+            }
+            Some(ChildKind::AofRewrite { .. }) if schedule => {
+                self.snapshots.bgsave_scheduled = true;
+                return RespType::SimpleString {
+                    content: "Background saving scheduled".into(),
+                };
+            }
+            Some(ChildKind::AofRewrite { .. }) => {
+                return RespType::SimpleError {
+                    content: "ERR Another child process is active (AOF?): can't BGSAVE right now. Use BGSAVE SCHEDULE in order to schedule a BGSAVE whenever possible.".into(),
+                };
+            }
+            None => {}
+        }
+
+        match self.start_bgsave() {
+            Ok(()) => RespType::SimpleString {
+                content: "Background saving started".into(),
+            },
+            Err(err) => RespType::SimpleError {
+                content: format!("ERR {err}"),
+            },
+        }
+    }
+
+    pub(super) fn handle_lastsave(&self) -> RespType {
+        RespType::Integer {
+            integer: unix_time(self.snapshots.last_save) as i64,
+        }
+    }
+
+    /// Called from every iteration of the event loop: reaps the background process once it is
+    /// done and starts a save when one is scheduled or a save rule is due.
+    pub(super) fn snapshots_cron(&mut self) {
+        if self.snapshots.child.is_some() {
+            self.reap_child();
+            return;
+        }
+
+        if self.snapshots.bgsave_scheduled {
+            self.snapshots.bgsave_scheduled = false;
+            if let Err(err) = self.start_bgsave() {
+                println!("Can't save in background: fork: {err}");
+            }
+            return;
+        }
+
+        let changes = self.redis.dirty() - self.snapshots.saved_dirty;
+        let since = self.snapshots.last_save.elapsed().unwrap_or_default();
+        let can_retry = self.snapshots.last_bgsave_ok
+            || self
+                .snapshots
+                .last_bgsave_try
+                .is_none_or(|tried| tried.elapsed() > BGSAVE_RETRY_DELAY);
+
+        let due = self
+            .config
+            .save_rules()
+            .into_iter()
+            .find(|(seconds, min_changes)| changes >= *min_changes && since > *seconds);
+        if let Some((seconds, _)) = due
+            && can_retry
+        {
+            println!(
+                "{changes} changes in {} seconds. Saving...",
+                seconds.as_secs()
+            );
+            if let Err(err) = self.start_bgsave() {
+                println!("Can't save in background: fork: {err}");
+            }
+        }
+    }
+
+    fn start_bgsave(&mut self) -> io::Result<()> {
+        self.snapshots.last_bgsave_try = Some(Instant::now());
+
+        let forked = self.fork_child(ChildKind::Save, |event_loop| {
+            event_loop.save().inspect_err(|err| {
+                println!("Error saving DB on disk: {err}");
+            })
+        });
+        match forked {
+            Ok(pid) => {
+                println!("Background saving started by pid {pid}");
+                Ok(())
+            }
+            Err(err) => {
+                self.snapshots.last_bgsave_ok = false;
+                Err(err)
+            }
+        }
+    }
+
+    /// Runs the work in a child process, which sees the dataset as it was when forked while the
+    /// parent goes on serving requests, their memory pages being shared copy-on-write.
+    pub(super) fn fork_child(
+        &mut self,
+        kind: ChildKind,
+        work: impl FnOnce(&mut Self) -> io::Result<()>,
+    ) -> io::Result<libc::pid_t> {
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => {
+                let status = work(self).map_or(1, |_| 0);
+                //the child must not run the destructors of the state it shares with the parent
+                unsafe { libc::_exit(status) }
+            }
+            pid => {
+                self.snapshots.child = Some(Child {
+                    pid,
+                    started: Instant::now(),
+                    dirty: self.redis.dirty(),
+                    kind,
+                });
+                Ok(pid)
+            }
+        }
+    }
+
+    /// Stops the background process right away, its work is lost.
+    pub(super) fn kill_child(&mut self) {
+        let Some(child) = self.snapshots.child.take() else {
+            return;
+        };
+
+        println!("Killing running child process {}", child.pid);
+        let mut status = 0;
+        unsafe {
+            libc::kill(child.pid, libc::SIGUSR1);
+            libc::waitpid(child.pid, &mut status, 0);
+        }
+        if let ChildKind::AofRewrite { base } = child.kind {
+            self.aof_rewrite_done(false, base, child.started.elapsed());
+        }
+    }
+
+    fn reap_child(&mut self) {
+        let Some(child) = &self.snapshots.child else {
+            return;
+        };
+
+        let mut status = 0;
+        let pid = unsafe { libc::waitpid(child.pid, &mut status, libc::WNOHANG) };
+        if pid == 0 {
+            return;
+        }
+
+        let child = self.snapshots.child.take().expect("checked above");
+        let ok = pid == child.pid && libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0;
+        let what = match child.kind {
+            ChildKind::Save => "Background saving",
+            ChildKind::AofRewrite { .. } => "Background AOF rewrite",
+        };
+        if ok {
+            println!("{what} terminated with success");
+        } else if libc::WIFSIGNALED(status) {
+            println!("{what} terminated by signal {}", libc::WTERMSIG(status));
+        } else {
+            println!("{what} error");
+        }
+
+        match child.kind {
+            ChildKind::Save => {
+                self.snapshots.last_bgsave_ok = ok;
+                self.snapshots.last_bgsave_duration = Some(child.started.elapsed());
+                if ok {
+                    self.snapshots.saved(child.dirty);
+                }
+            }
+            ChildKind::AofRewrite { base } => {
+                self.aof_rewrite_done(ok, base, child.started.elapsed())
+            }
+        }
+    }
+
+    /// The fields of the persistence section of INFO.
+    pub(super) fn persistence_info(&self) -> Vec<(&'static str, String)> {
+        let seconds = |duration: Option<Duration>| match duration {
+            Some(duration) => duration.as_secs().to_string(),
+            None => "-1".to_string(),
+        };
+        let snapshots = &self.snapshots;
+
+        let mut fields = vec![
+            ("loading", "0".to_string()),
+            (
+                "rdb_changes_since_last_save",
+                (self.redis.dirty() - snapshots.saved_dirty).to_string(),
+            ),
+            (
+                "rdb_bgsave_in_progress",
+                ((snapshots.child_kind() == Some(&ChildKind::Save)) as u8).to_string(),
+            ),
+            (
+                "rdb_last_save_time",
+                unix_time(snapshots.last_save).to_string(),
+            ),
+            (
+                "rdb_last_bgsave_status",
+                if snapshots.last_bgsave_ok {
+                    "ok"
+                } else {
+                    "err"
+                }
+                .to_string(),
+            ),
+            (
+                "rdb_last_bgsave_time_sec",
+                seconds(snapshots.last_bgsave_duration),
+            ),
+            (
+                "rdb_current_bgsave_time_sec",
+                seconds(snapshots.child_running_for(|kind| *kind == ChildKind::Save)),
+            ),
+            ("rdb_saves", snapshots.saves.to_string()),
+        ];
+        fields.extend(self.aof_info());
+        fields
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        self.write_snapshot(&self.rdb_path(), false)?;
+        println!("DB saved on disk");
+        Ok(())
+    }
+
+    //the snapshot is written to a temporary file first, so that a failure can't leave a
+    //truncated file behind
+    pub(super) fn write_snapshot(&self, path: &Path, aof_base: bool) -> io::Result<()> {
+        let mut functions = vec![];
+        self.functions.write_libraries(&mut functions);
+        let snapshot = self.redis.rdb_snapshot(&functions, aof_base);
+
+        let temp = self
+            .config
+            .dir()
+            .join(format!("temp-{}.rdb", std::process::id()));
+        let mut file = File::create(&temp)?;
+        file.write_all(&snapshot)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&temp, path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&temp);
+            })
+    }
+
+    /// The RDB file, in the configured directory.
+    fn rdb_path(&self) -> PathBuf {
+        self.config.dir().join(self.config.dbfilename())
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::Path,
+        time::{Duration, Instant, UNIX_EPOCH},
+    };
+
+    use crate::{
+        command::{Command, FunctionSubcommand},
+        ev_loop::{
+            EventLoop,
+            test_util::{self, request},
+        },
+        resp::RespType,
+    };
+
+    fn event_loop(dir: &Path) -> EventLoop {
+        fs::create_dir_all(dir).unwrap();
+        test_util::event_loop(&["--dir", dir.to_str().unwrap()])
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("test-save-{}", std::process::id()));
+        let mut server = event_loop(&dir);
+        server.load_rdb().unwrap();
+
+        server.execute(1, request(&["SET", "key", "value"]));
+        server.handle_function(FunctionSubcommand::Load {
+            code: "#!lua name=lib\nredis.register_function('one', function() return 1 end)".into(),
+            replace: false,
+        });
+        assert_eq!(
+            server.handle_save(),
+            RespType::SimpleString {
+                content: "OK".into()
+            }
+        );
+
+        let mut restarted = event_loop(&dir);
+        let loaded = restarted.load_rdb();
+        fs::remove_dir_all(&dir).unwrap();
+        loaded.unwrap();
+
+        assert_eq!(
+            restarted.execute(1, Command::Get { key: "key".into() }),
+            Some(RespType::BulkString {
+                data: b"value".to_vec()
+            })
+        );
+        let reply = restarted.handle_fcall(
+            1,
+            Command::FCall {
+                function: "one".into(),
+                keys: vec![],
+                args: vec![],
+                read_only: false,
+            },
+        );
+        assert_eq!(reply, RespType::Integer { integer: 1 });
+    }
+
+    #[test]
+    fn test_bgsave_and_save_rules() {
+        let dir = std::env::temp_dir().join(format!("test-bgsave-{}", std::process::id()));
+        let mut server = event_loop(&dir);
+        server.config.set("save", &["10 1".to_string()]).unwrap();
+        server.execute(1, request(&["SET", "key", "value"]));
+
+        //the rule applies once the last save is old enough
+        server.snapshots_cron();
+        assert!(server.snapshots.child.is_none());
+        server.snapshots.last_save = UNIX_EPOCH;
+        server.snapshots_cron();
+        assert!(server.snapshots.child.is_some());
+        assert_eq!(
+            server.handle_bgsave(false),
+            RespType::SimpleError {
+                content: "ERR Background save already in progress".into()
+            }
+        );
+
+        let started = Instant::now();
+        while server.snapshots.child.is_some() && started.elapsed() < Duration::from_secs(10) {
+            server.snapshots_cron();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let saved = fs::read(dir.join("dump.rdb"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(saved.is_ok());
+
+        let info = server.persistence_info();
+        let field = |name: &str| &info.iter().find(|(field, _)| *field == name).unwrap().1;
+        assert_eq!(field("rdb_changes_since_last_save"), "0");
+        assert_eq!(field("rdb_bgsave_in_progress"), "0");
+        assert_eq!(field("rdb_last_bgsave_status"), "ok");
+        assert_eq!(field("rdb_saves"), "1");
+        assert_ne!(server.handle_lastsave(), RespType::Integer { integer: 0 });
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    cluster::key_hash_slot,
+    command::{Command, PubSubSubcommand},
+    ev_loop::{EventLoop, client::Client},
+    glob::glob_match,
+    resp::RespType,
+};
+
+/// Reverse index of the subscriptions kept by every client, used to fan messages out without
+/// scanning all the connections.
+#[derive(Debug, Default)]
+pub(super) struct PubSub {
+    channels: HashMap<String, HashSet<i32>>,
+    patterns: HashMap<String, HashSet<i32>>,
+    //shard channels are grouped by the slot they hash to, like keys
+    shard_channels: HashMap<u16, HashMap<String, HashSet<i32>>>,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Channel,
+    Pattern,
+    Shard,
+}
+
+impl Kind {
+    fn subscribe_reply(&self) -> &'static str {
+        match self {
+            Kind::Channel => "subscribe",
+            Kind::Pattern => "psubscribe",
+            Kind::Shard => "ssubscribe",
+        }
+    }
+
+    fn unsubscribe_reply(&self) -> &'static str {
+        match self {
+            Kind::Channel => "unsubscribe",
+            Kind::Pattern => "punsubscribe",
+            Kind::Shard => "sunsubscribe",
+        }
+    }
+
+    fn client_set<'a>(&self, client: &'a mut Client) -> &'a mut HashSet<String> {
+        match self {
+            Kind::Channel => client.channels_mut(),
+            Kind::Pattern => client.patterns_mut(),
+            Kind::Shard => client.shard_channels_mut(),
+        }
+    }
+
+    //the count reported in (un)subscribe replies
+    fn client_count(&self, client: &Client) -> usize {
+        match self {
+            Kind::Channel | Kind::Pattern => client.subscriptions(),
+            Kind::Shard => client.shard_channels().len(),
+        }
+    }
+}
+
+impl PubSub {
+    fn index(&mut self, kind: Kind, name: &str) -> &mut HashMap<String, HashSet<i32>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => self
+                .shard_channels
+                .entry(key_hash_slot(name.as_bytes()))
+                .or_default(),
+        }
+    }
+
+    fn add(&mut self, kind: Kind, name: &str, client_id: i32) {
+        self.index(kind, name)
+            .entry(name.to_string())
+            .or_default()
+            .insert(client_id);
+    }
+
+    fn remove(&mut self, kind: Kind, name: &str, client_id: i32) {
+        let index = self.index(kind, name);
+        if let Some(subscribers) = index.get_mut(name) {
+            subscribers.remove(&client_id);
+            if subscribers.is_empty() {
+                index.remove(name);
+            }
+        }
+
+        if let Kind::Shard = kind {
+            self.shard_channels
+                .retain(|_, channels| !channels.is_empty());
+        }
+    }
+
+    fn shard_subscribers(&self, channel: &str) -> Option<&HashSet<i32>> {
+        self.shard_channels
+            .get(&key_hash_slot(channel.as_bytes()))
+            .and_then(|channels| channels.get(channel))
+    }
+
+    /// Drops every subscription of a disconnected client.
+    pub(super) fn remove_client(&mut self, client_id: i32, client: &Client) {
+        for channel in client.channels() {
+            self.remove(Kind::Channel, channel, client_id);
+        }
+        for pattern in client.patterns() {
+            self.remove(Kind::Pattern, pattern, client_id);
+        }
+        for channel in client.shard_channels() {
+            self.remove(Kind::Shard, channel, client_id);
+        }
+    }
+}
+
+fn bulk(s: &str) -> RespType {
+    RespType::BulkString {
+        data: s.as_bytes().to_vec(),
+    }
+}
+
+impl EventLoop {
+    /// (Un)subscriptions reply with a message per channel, straight into the client buffer.
+    pub(super) fn handle_subscriptions(&mut self, client_id: i32, cmd: Command) {
+        match cmd {
+            Command::Subscribe { channels } => self.subscribe(client_id, Kind::Channel, channels),
+            Command::PSubscribe { patterns } => self.subscribe(client_id, Kind::Pattern, patterns),
+            Command::Unsubscribe { channels } => {
+                self.unsubscribe(client_id, Kind::Channel, channels)
+            }
+            Command::PUnsubscribe { patterns } => {
+                self.unsubscribe(client_id, Kind::Pattern, patterns)
+            }
+            Command::SSubscribe { channels } => self.subscribe(client_id, Kind::Shard, channels),
+            Command::SUnsubscribe { channels } => {
+                self.unsubscribe(client_id, Kind::Shard, channels)
+            }
+            _ => panic!("Illegal state"),
+        }
+    }
+
+    fn subscribe(&mut self, client_id: i32, kind: Kind, names: Vec<String>) {
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return;
+        };
+
+        for name in names {
+            if kind.client_set(client).insert(name.clone()) {
+                self.pubsub.add(kind, &name, client_id);
+            }
+
+            let count = kind.client_count(client);
+            client.send(RespType::Push {
+                elements: vec![
+                    bulk(kind.subscribe_reply()),
+                    bulk(&name),
+                    RespType::Integer {
+                        integer: count as i64,
+                    },
+                ],
+            });
+        }
+    }
+
+    fn unsubscribe(&mut self, client_id: i32, kind: Kind, names: Vec<String>) {
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return;
+        };
+
+        let names = if names.is_empty() {
+            let mut all = kind.client_set(client).iter().cloned().collect::<Vec<_>>();
+            all.sort();
+            all
+        } else {
+            names
+        };
+
+        //even with nothing to unsubscribe from, the client gets a confirmation
+        if names.is_empty() {
+            client.send(RespType::Push {
+                elements: vec![
+                    bulk(kind.unsubscribe_reply()),
+                    RespType::Null,
+                    RespType::Integer {
+                        integer: kind.client_count(client) as i64,
+                    },
+                ],
+            });
+            return;
+        }
+
+        for name in names {
+            if kind.client_set(client).remove(&name) {
+                self.pubsub.remove(kind, &name, client_id);
+            }
+
+            let count = kind.client_count(client);
+            client.send(RespType::Push {
+                elements: vec![
+                    bulk(kind.unsubscribe_reply()),
+                    bulk(&name),
+                    RespType::Integer {
+                        integer: count as i64,
+                    },
+                ],
+            });
+        }
+    }
+
+    /// Queues the message into the output buffer of every subscriber, returning how many
+    /// clients received it (a client matching through several patterns counts once per pattern).
+    pub(super) fn publish(&mut self, channel: &str, message: &str) -> usize {
+        let mut receivers = 0;
+
+        if let Some(subscribers) = self.pubsub.channels.get(channel) {
+            for subscriber in subscribers {
+                if let Some(client) = self.clients.get_mut(subscriber) {
+                    client.send(RespType::Push {
+                        elements: vec![bulk("message"), bulk(channel), bulk(message)],
+                    });
+                    receivers += 1;
+                }
+            }
+        }
+
+        for (pattern, subscribers) in self.pubsub.patterns.iter() {
+            if !glob_match(pattern.as_bytes(), channel.as_bytes()) {
+                continue;
+            }
+
+            for subscriber in subscribers {
+                if let Some(client) = self.clients.get_mut(subscriber) {
+                    client.send(RespType::Push {
+                        elements: vec![
+                            bulk("pmessage"),
+                            bulk(pattern),
+                            bulk(channel),
+                            bulk(message),
+                        ],
+                    });
+                    receivers += 1;
+                }
+            }
+        }
+
+        receivers
+    }
+
+    /// Delivers the message to the subscribers of the shard channel, patterns never match shard
+    /// channels.
+    pub(super) fn spublish(&mut self, channel: &str, message: &str) -> usize {
+        let mut receivers = 0;
+
+        if let Some(subscribers) = self.pubsub.shard_subscribers(channel) {
+            for subscriber in subscribers {
+                if let Some(client) = self.clients.get_mut(subscriber) {
+                    client.send(RespType::Push {
+                        elements: vec![bulk("smessage"), bulk(channel), bulk(message)],
+                    });
+                    receivers += 1;
+                }
+            }
+        }
+
+        receivers
+    }
+
+    pub(super) fn handle_pubsub_introspection(&self, subcommand: PubSubSubcommand) -> RespType {
+        match subcommand {
+            PubSubSubcommand::Channels { pattern } => RespType::Array {
+                elements: self
+                    .pubsub
+                    .channels
+                    .keys()
+                    .filter(|channel| {
+                        pattern
+                            .as_ref()
+                            .is_none_or(|p| glob_match(p.as_bytes(), channel.as_bytes()))
+                    })
+                    .map(|channel| bulk(channel))
+                    .collect(),
+            },
+            PubSubSubcommand::NumSub { channels } => RespType::Map {
+                entries: channels
+                    .iter()
+                    .map(|channel| {
+                        let count = self.pubsub.channels.get(channel).map_or(0, |s| s.len());
+                        (
+                            bulk(channel),
+                            RespType::Integer {
+                                integer: count as i64,
+                            },
+                        )
+                    })
+                    .collect(),
+            },
+            PubSubSubcommand::NumPat => RespType::Integer {
+                integer: self.pubsub.patterns.len() as i64,
+            },
+            PubSubSubcommand::ShardChannels { pattern } => RespType::Array {
+                elements: self
+                    .pubsub
+                    .shard_channels
+                    .values()
+                    .flat_map(|channels| channels.keys())
+                    .filter(|channel| {
+                        pattern
+                            .as_ref()
+                            .is_none_or(|p| glob_match(p.as_bytes(), channel.as_bytes()))
+                    })
+                    .map(|channel| bulk(channel))
+                    .collect(),
+            },
+            PubSubSubcommand::ShardNumSub { channels } => RespType::Map {
+                entries: channels
+                    .iter()
+                    .map(|channel| {
+                        let count = self
+                            .pubsub
+                            .shard_subscribers(channel)
+                            .map_or(0, |s| s.len());
+                        (
+                            bulk(channel),
+                            RespType::Integer {
+                                integer: count as i64,
+                            },
+                        )
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PubSub;
+    use crate::ev_loop::{
+        pubsub::Kind,
+        test_util::{client, event_loop, replies, send},
+    };
+
+    #[test]
+    fn test_pubsub_index() {
+        let mut pubsub = PubSub::default();
+
+        pubsub.add(Kind::Channel, "news", 1);
+        pubsub.add(Kind::Channel, "news", 2);
+        pubsub.add(Kind::Pattern, "news.*", 1);
+        pubsub.add(Kind::Shard, "{user1}.events", 1);
+        pubsub.add(Kind::Shard, "user1", 2);
+
+        assert_eq!(pubsub.channels["news"].len(), 2);
+        //channels sharing a hashtag end up in the same slot
+        assert_eq!(pubsub.shard_channels.len(), 1);
+        assert!(pubsub.shard_subscribers("news").is_none());
+
+        pubsub.remove(Kind::Channel, "news", 1);
+        pubsub.remove(Kind::Channel, "news", 2);
+        pubsub.remove(Kind::Pattern, "news.*", 1);
+        pubsub.remove(Kind::Shard, "{user1}.events", 1);
+        pubsub.remove(Kind::Shard, "user1", 2);
+
+        //empty entries are dropped so that PUBSUB CHANNELS and NUMPAT stay accurate
+        assert!(pubsub.channels.is_empty());
+        assert!(pubsub.patterns.is_empty());
+        assert!(pubsub.shard_channels.is_empty());
+    }
+
+    #[test]
+    fn test_sharded_pubsub() {
+        let mut server = event_loop(&[]);
+        let (mut sub, mut other) = (client(&server), client(&server));
+
+        //subscribing twice to a channel counts once, channels and shard channels apart
+        assert_eq!(
+            send(&mut server, &mut sub, "SSUBSCRIBE a a {a}b"),
+            "*3\r\n$10\r\nssubscribe\r\n$1\r\na\r\n:1\r\n\
+             *3\r\n$10\r\nssubscribe\r\n$1\r\na\r\n:1\r\n\
+             *3\r\n$10\r\nssubscribe\r\n$4\r\n{a}b\r\n:2\r\n"
+        );
+        assert_eq!(
+            send(&mut server, &mut sub, "SUBSCRIBE a"),
+            "*3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n"
+        );
+        assert_eq!(
+            send(&mut server, &mut sub, "GET a"),
+            "-ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING are allowed in this context\r\n"
+        );
+        assert_eq!(
+            send(&mut server, &mut sub, "PING"),
+            "*2\r\n$4\r\npong\r\n$0\r\n\r\n"
+        );
+        assert_eq!(
+            send(&mut server, &mut sub, "PING hello"),
+            "*2\r\n$4\r\npong\r\n$5\r\nhello\r\n"
+        );
+        assert_eq!(
+            send(&mut server, &mut other, "PING hello"),
+            "$5\r\nhello\r\n"
+        );
+
+        //a message reaches the subscribers of its kind of channel only, never the patterns
+        let mut pattern = client(&server);
+        send(&mut server, &mut pattern, "PSUBSCRIBE *");
+        assert_eq!(send(&mut server, &mut other, "SPUBLISH a hi"), ":1\r\n");
+        assert_eq!(
+            replies(&mut server, &mut sub),
+            "*3\r\n$8\r\nsmessage\r\n$1\r\na\r\n$2\r\nhi\r\n"
+        );
+        assert_eq!(replies(&mut server, &mut pattern), "");
+        assert_eq!(send(&mut server, &mut other, "SPUBLISH b hi"), ":0\r\n");
+        assert_eq!(
+            send(&mut server, &mut other, "PUBSUB SHARDNUMSUB a b"),
+            "*4\r\n$1\r\na\r\n:1\r\n$1\r\nb\r\n:0\r\n"
+        );
+
+        //with no channel, from all of them in order, a confirmation even without any
+        assert_eq!(
+            send(&mut server, &mut other, "SUNSUBSCRIBE"),
+            "*3\r\n$12\r\nsunsubscribe\r\n$-1\r\n:0\r\n"
+        );
+        assert_eq!(
+            send(&mut server, &mut sub, "SUNSUBSCRIBE"),
+            "*3\r\n$12\r\nsunsubscribe\r\n$1\r\na\r\n:1\r\n\
+             *3\r\n$12\r\nsunsubscribe\r\n$4\r\n{a}b\r\n:0\r\n"
+        );
+        assert_eq!(send(&mut server, &mut other, "SPUBLISH a hi"), ":0\r\n");
+        assert_eq!(send(&mut server, &mut other, "PUBLISH a hi"), ":2\r\n");
+
+        //the subscriptions of a client go away with it
+        send(&mut server, &mut other, "SSUBSCRIBE c");
+        drop(other);
+        let mut last = client(&server);
+        assert_eq!(
+            send(&mut server, &mut last, "PUBSUB SHARDCHANNELS"),
+            "*0\r\n"
+        );
+        assert_eq!(
+            send(&mut server, &mut last, "SSUBSCRIBE"),
+            "-ERR wrong number of arguments for 'ssubscribe' command\r\n"
+        );
+        assert_eq!(
+            send(&mut server, &mut last, "SPUBLISH a"),
+            "-ERR wrong number of arguments for 'spublish' command\r\n"
+        );
+    }
+}
@@ -0,0 +1,1417 @@
+//! Master-replica replication. A replica connects to its master and receives the dataset as an
+//! RDB snapshot, then the stream of the changes made to it as commands, which it runs in turn.
+//! Both sides count the bytes of the stream: the last ones are kept in a backlog, for a replica
+//! that lost its link to resume from the offset it reached instead of loading the whole dataset
+//! again.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, Read as _, Write as _},
+    net::{IpAddr, Ipv4Addr, TcpStream, ToSocketAddrs as _},
+    os::fd::AsRawFd,
+    time::{Duration, Instant, SystemTime},
+};
+
+use libc::{EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLRDHUP};
+use sha1::{Digest as _, Sha1};
+
+use crate::{
+    command::Command,
+    ev_loop::EventLoop,
+    redis::{Acks, WaitingState},
+    resp::RespType,
+};
+
+//the commands received from the master are run on behalf of this client, which never exists
+const MASTER_CLIENT_ID: i32 = -2;
+//delay between two attempts to connect to the master, also how long an attempt can take
+const CONNECT_RETRY_DELAY: Duration = Duration::from_secs(1);
+//a replica acknowledges the offset it reached this often
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+
+/// Where this server stands in the replication, as a master and as a replica.
+#[derive(Debug)]
+pub(super) struct Replication {
+    //identifies the history of the dataset, an offset is only meaningful along with it
+    replid: String,
+    //the history this one continues, replicas of it can resync partially up to second_offset
+    replid2: String,
+    second_offset: i64,
+    //bytes of stream produced so far or, on a replica, received from the master
+    offset: u64,
+    //the offset after the last write, what WAIT waits for rather than the pings that follow
+    write_offset: u64,
+    //created once the first replica attaches, the offset only moves from then on
+    backlog: Option<Backlog>,
+    //by client id, from the first REPLCONF they send
+    replicas: BTreeMap<i32, Replica>,
+    last_ping: Instant,
+    //clients wait for acknowledgements, the replicas are asked for them
+    get_ack: bool,
+    //Some when this server is a replica
+    master: Option<MasterLink>,
+}
+
+impl Replication {
+    pub(super) fn new() -> Self {
+        Self {
+            replid: new_replid(),
+            replid2: "0".repeat(40),
+            second_offset: -1,
+            offset: 0,
+            write_offset: 0,
+            backlog: None,
+            replicas: BTreeMap::new(),
+            last_ping: Instant::now(),
+            get_ack: false,
+            master: None,
+        }
+    }
+
+    /// "master" or "replica", as HELLO reports it.
+    pub(super) fn role(&self) -> &'static str {
+        match self.master {
+            Some(_) => "replica",
+            None => "master",
+        }
+    }
+
+    /// The socket connected to the master, if any.
+    pub(super) fn master_fd(&self) -> Option<i32> {
+        self.master
+            .as_ref()
+            .and_then(|link| link.stream.as_ref())
+            .map(|stream| stream.as_raw_fd())
+    }
+
+    pub(super) fn remove_replica(&mut self, client_id: i32) {
+        self.replicas.remove(&client_id);
+    }
+
+    //the new history starts where the current one is, its replicas can carry on with it
+    fn shift_replid(&mut self) {
+        self.replid2 = std::mem::replace(&mut self.replid, new_replid());
+        self.second_offset = self.offset as i64 + 1;
+    }
+
+    //the stream from the offset on, None if the replica has to resync fully
+    fn missing_since(&self, replid: &str, offset: i64) -> Option<Vec<u8>> {
+        let same_history =
+            replid == self.replid || (replid == self.replid2 && offset <= self.second_offset);
+        if !same_history || offset < 0 {
+            return None;
+        }
+        self.backlog.as_ref()?.since(offset as u64, self.offset)
+    }
+
+    fn online_replicas(&self) -> impl Iterator<Item = (&i32, &Replica)> {
+        self.replicas.iter().filter(|(_, replica)| replica.online)
+    }
+
+    //the processed and synced offsets acknowledged by the online replicas
+    fn ack_offsets(&self) -> Vec<(u64, u64)> {
+        self.online_replicas()
+            .map(|(_, replica)| (replica.ack_offset, replica.aof_offset))
+            .collect()
+    }
+}
+
+/// The last bytes of the replication stream, as many as repl-backlog-size allows.
+#[derive(Debug, Default)]
+struct Backlog {
+    data: VecDeque<u8>,
+}
+
+impl Backlog {
+    fn feed(&mut self, bytes: &[u8], size: usize) {
+        self.data.extend(bytes);
+        let excess = self.data.len().saturating_sub(size);
+        self.data.drain(..excess);
+    }
+
+    //the offset of the first byte held, offsets start at 1
+    fn first_offset(&self, end: u64) -> u64 {
+        end + 1 - self.data.len() as u64
+    }
+
+    //the bytes from the offset to the end of the stream, None if some are no longer held
+    fn since(&self, offset: u64, end: u64) -> Option<Vec<u8>> {
+        let first = self.first_offset(end);
+        if offset < first || offset > end + 1 {
+            return None;
+        }
+        Some(
+            self.data
+                .iter()
+                .skip((offset - first) as usize)
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Replica {
+    //the address it connects from and the port it announced it listens on
+    ip: IpAddr,
+    port: u16,
+    //false during the handshake, until PSYNC
+    online: bool,
+    //the offsets the replica acknowledged it processed and synced to its AOF
+    ack_offset: u64,
+    aof_offset: u64,
+    last_ack: Instant,
+}
+
+impl Replica {
+    fn new(ip: IpAddr) -> Self {
+        Self {
+            ip,
+            port: 0,
+            online: false,
+            ack_offset: 0,
+            aof_offset: 0,
+            last_ack: Instant::now(),
+        }
+    }
+}
+
+/// The connection of a replica to its master.
+#[derive(Debug)]
+struct MasterLink {
+    host: String,
+    port: u16,
+    state: LinkState,
+    stream: Option<TcpStream>,
+    //bytes received but not yet processed
+    input: Vec<u8>,
+    //the replid and offset of this server are worth a partial resync: they are the ones of a
+    //former master or of a link that went down
+    resumable: bool,
+    //the commands of a MULTI received so far from the master
+    transaction: Option<Vec<Command>>,
+    last_connect: Option<Instant>,
+    last_io: Instant,
+    last_ack: Instant,
+    down_since: Instant,
+}
+
+impl MasterLink {
+    fn new(host: String, port: u16, resumable: bool) -> Self {
+        Self {
+            host,
+            port,
+            state: LinkState::Connect,
+            stream: None,
+            input: vec![],
+            resumable,
+            transaction: None,
+            last_connect: None,
+            last_io: Instant::now(),
+            last_ack: Instant::now(),
+            down_since: Instant::now(),
+        }
+    }
+
+    //as ROLE reports it
+    fn state_name(&self) -> &'static str {
+        match self.state {
+            LinkState::Connect => "connect",
+            LinkState::Transfer { .. } => "sync",
+            LinkState::Connected => "connected",
+            _ => "handshake",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum LinkState {
+    //waiting to (re)connect
+    Connect,
+    //the handshake, each state waits for the reply to a command
+    ReceivePong,
+    ReceiveAuth,
+    ReceivePort,
+    ReceiveCapa,
+    ReceivePsync,
+    //the master accepted a full resync, the snapshot is being received
+    Transfer { replid: String, offset: u64 },
+    Connected,
+}
+
+impl EventLoop {
+    /// Starts replicating the master given by replicaof, if any.
+    pub(super) fn configure_replication(&mut self) {
+        if let Some((host, port)) = self.config.replicaof() {
+            //without a previous history, the master has no choice but to send everything
+            self.replication.master = Some(MasterLink::new(host, port, false));
+        }
+    }
+
+    /// REPLICAOF: replicates another server, or stops replicating with NO ONE.
+    pub(super) fn handle_replicaof(&mut self, master: Option<(String, u16)>) -> RespType {
+        if self.cluster.is_some() {
+            return RespType::SimpleError {
+                content: "ERR REPLICAOF not allowed in cluster mode.".into(),
+            };
+        }
+        let ok = |content: &str| RespType::SimpleString {
+            content: content.into(),
+        };
+
+        let changed = self.set_master(master.clone());
+        match master {
+            None => {
+                if changed {
+                    println!("MASTER MODE enabled (user request from 'REPLICAOF NO ONE')");
+                }
+                let _ = self.config.set("replicaof", &[]);
+                ok("OK")
+            }
+            Some(_) if !changed => ok("OK Already connected to specified master"),
+            Some((host, port)) => {
+                println!("REPLICAOF {host}:{port} enabled (user request)");
+                let _ = self.config.set("replicaof", &[format!("{host} {port}")]);
+                ok("OK")
+            }
+        }
+    }
+
+    /// Replicates another server, or stops replicating with None, a new history starts then.
+    /// Returns whether anything changed.
+    pub(super) fn set_master(&mut self, master: Option<(String, u16)>) -> bool {
+        match master {
+            None => match self.replication.master.take() {
+                Some(link) => {
+                    self.close_master_link(link);
+                    self.replication.shift_replid();
+                    true
+                }
+                None => false,
+            },
+            Some((host, port)) => {
+                if let Some(link) = self.replication.master.as_ref()
+                    && link.host == host
+                    && link.port == port
+                {
+                    return false;
+                }
+
+                if let Some(link) = self.replication.master.take() {
+                    self.close_master_link(link);
+                }
+                self.replication.master = Some(MasterLink::new(host, port, true));
+                true
+            }
+        }
+    }
+
+    /// REPLCONF: the settings a replica gives during the handshake and its acknowledgements.
+    pub(super) fn handle_replconf(
+        &mut self,
+        client_id: i32,
+        options: Vec<(String, String)>,
+    ) -> Option<RespType> {
+        let ip = self
+            .clients
+            .get(&client_id)
+            .and_then(|client| client.stream().peer_addr().ok())
+            .map(|addr| addr.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let mut reply = true;
+
+        for (name, value) in options {
+            let replica = self
+                .replication
+                .replicas
+                .entry(client_id)
+                .or_insert_with(|| Replica::new(ip));
+
+            match name.as_str() {
+                "listening-port" => match value.parse() {
+                    Ok(port) => replica.port = port,
+                    Err(_) => {
+                        return Some(RespType::SimpleError {
+                            content: "ERR value is not an integer or out of range".into(),
+                        });
+                    }
+                },
+                "ip-address" => {
+                    if let Ok(ip) = value.parse() {
+                        replica.ip = ip;
+                    }
+                }
+                "capa" => {}
+                //acknowledgements are not replied to, nor are the requests for them which
+                //only masters send
+                "ack" => {
+                    if let Ok(offset) = value.parse::<u64>() {
+                        replica.ack_offset = replica.ack_offset.max(offset);
+                        replica.last_ack = Instant::now();
+                    }
+                    reply = false;
+                }
+                "fack" => {
+                    if let Ok(offset) = value.parse::<u64>() {
+                        replica.aof_offset = replica.aof_offset.max(offset);
+                    }
+                    reply = false;
+                }
+                "getack" => reply = false,
+                _ => {
+                    return Some(RespType::SimpleError {
+                        content: format!("ERR Unrecognized REPLCONF option: {name}"),
+                    });
+                }
+            }
+        }
+
+        reply.then(|| RespType::SimpleString {
+            content: "OK".into(),
+        })
+    }
+
+    /// PSYNC: attaches the client as a replica, sending it the part of the stream it misses or
+    /// the whole dataset followed by the stream.
+    pub(super) fn handle_psync(
+        &mut self,
+        client_id: i32,
+        replid: String,
+        offset: i64,
+    ) -> Option<RespType> {
+        if let Some(link) = self.replication.master.as_ref()
+            && link.state != LinkState::Connected
+        {
+            return Some(RespType::SimpleError {
+                content: "NOMASTERLINK Can't SYNC while not connected with my master".into(),
+            });
+        }
+
+        let ip = self
+            .clients
+            .get(&client_id)?
+            .stream()
+            .peer_addr()
+            .map(|addr| addr.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let replication = &mut self.replication;
+        replication.backlog.get_or_insert_default();
+        let replica = replication
+            .replicas
+            .entry(client_id)
+            .or_insert_with(|| Replica::new(ip));
+        replica.online = true;
+        replica.last_ack = Instant::now();
+        let address = format!("{}:{}", replica.ip, replica.port);
+
+        match replication.missing_since(&replid, offset) {
+            Some(missing) => {
+                println!(
+                    "Partial resynchronization request from {address} accepted. Sending {} bytes of backlog starting from offset {offset}.",
+                    missing.len()
+                );
+                let content = format!("CONTINUE {}", replication.replid);
+                let client = self.clients.get_mut(&client_id)?;
+                client.send(RespType::SimpleString { content });
+                client.send_raw(&missing);
+            }
+            None => {
+                println!("Full resync requested by replica {address}");
+                let content = format!("FULLRESYNC {} {}", replication.replid, replication.offset);
+                let mut functions = vec![];
+                self.functions.write_libraries(&mut functions);
+                let snapshot = self.redis.rdb_snapshot(&functions, false);
+
+                //the snapshot is sent like a bulk string, without the trailing CRLF
+                let client = self.clients.get_mut(&client_id)?;
+                client.send(RespType::SimpleString { content });
+                client.send_raw(format!("${}\r\n", snapshot.len()).as_bytes());
+                client.send_raw(&snapshot);
+            }
+        }
+        None
+    }
+
+    /// ROLE: whether this server is a master or a replica, and how far the replication went.
+    pub(super) fn handle_role(&self) -> RespType {
+        let bulk = |s: &str| RespType::BulkString {
+            data: s.as_bytes().to_vec(),
+        };
+
+        let elements = match self.replication.master.as_ref() {
+            Some(link) => {
+                let offset = match link.state {
+                    LinkState::Connected => self.replication.offset as i64,
+                    _ => -1,
+                };
+                vec![
+                    bulk("slave"),
+                    bulk(&link.host),
+                    RespType::Integer {
+                        integer: link.port as i64,
+                    },
+                    bulk(link.state_name()),
+                    RespType::Integer { integer: offset },
+                ]
+            }
+            None => {
+                let replicas = self
+                    .replication
+                    .online_replicas()
+                    .map(|(_, replica)| RespType::Array {
+                        elements: vec![
+                            bulk(&replica.ip.to_string()),
+                            bulk(&replica.port.to_string()),
+                            bulk(&replica.ack_offset.to_string()),
+                        ],
+                    })
+                    .collect();
+                vec![
+                    bulk("master"),
+                    RespType::Integer {
+                        integer: self.replication.offset as i64,
+                    },
+                    RespType::Array { elements: replicas },
+                ]
+            }
+        };
+
+        RespType::Array { elements }
+    }
+
+    /// The fields of the replication section of INFO.
+    pub(super) fn replication_info(&self) -> Vec<(String, String)> {
+        let replication = &self.replication;
+        let mut fields = vec![];
+        let mut field = |name: &str, value: String| fields.push((name.to_string(), value));
+
+        match replication.master.as_ref() {
+            Some(link) => {
+                let up = link.state == LinkState::Connected;
+                field("role", "slave".into());
+                field("master_host", link.host.clone());
+                field("master_port", link.port.to_string());
+                field("master_link_status", if up { "up" } else { "down" }.into());
+                field(
+                    "master_last_io_seconds_ago",
+                    match up {
+                        true => link.last_io.elapsed().as_secs().to_string(),
+                        false => "-1".into(),
+                    },
+                );
+                field(
+                    "master_sync_in_progress",
+                    (matches!(link.state, LinkState::Transfer { .. }) as u8).to_string(),
+                );
+                field("slave_read_repl_offset", replication.offset.to_string());
+                field("slave_repl_offset", replication.offset.to_string());
+                if !up {
+                    field(
+                        "master_link_down_since_seconds",
+                        link.down_since.elapsed().as_secs().to_string(),
+                    );
+                }
+                field(
+                    "slave_read_only",
+                    (self.config.replica_read_only() as u8).to_string(),
+                );
+            }
+            None => field("role", "master".into()),
+        }
+
+        field(
+            "connected_slaves",
+            replication.online_replicas().count().to_string(),
+        );
+        for (i, (_, replica)) in replication.online_replicas().enumerate() {
+            field(
+                &format!("slave{i}"),
+                format!(
+                    "ip={},port={},state=online,offset={},lag={}",
+                    replica.ip,
+                    replica.port,
+                    replica.ack_offset,
+                    replica.last_ack.elapsed().as_secs()
+                ),
+            );
+        }
+
+        let backlog = replication.backlog.as_ref();
+        field("master_failover_state", "no-failover".into());
+        field("master_replid", replication.replid.clone());
+        field("master_replid2", replication.replid2.clone());
+        field("master_repl_offset", replication.offset.to_string());
+        field("second_repl_offset", replication.second_offset.to_string());
+        field("repl_backlog_active", (backlog.is_some() as u8).to_string());
+        field(
+            "repl_backlog_size",
+            self.config.repl_backlog_size().to_string(),
+        );
+        field(
+            "repl_backlog_first_byte_offset",
+            backlog
+                .map(|backlog| backlog.first_offset(replication.offset))
+                .unwrap_or(0)
+                .to_string(),
+        );
+        field(
+            "repl_backlog_histlen",
+            backlog
+                .map(|backlog| backlog.data.len())
+                .unwrap_or(0)
+                .to_string(),
+        );
+        fields
+    }
+
+    /// Replicas refuse the writes of their clients, unless replica-read-only is off.
+    pub(super) fn check_read_only(&self, cmd: &Command) -> Result<(), RespType> {
+        if self.replication.master.is_some() && self.config.replica_read_only() && cmd.is_write() {
+            return Err(RespType::SimpleError {
+                content: "READONLY You can't write against a read only replica.".into(),
+            });
+        }
+        Ok(())
+    }
+
+    /// WAIT: blocks the client until enough replicas acknowledge the writes made so far.
+    pub(super) fn handle_wait(
+        &mut self,
+        client_id: i32,
+        numreplicas: u64,
+        timeout: Option<Duration>,
+    ) -> Option<RespType> {
+        if self.replication.master.is_some() {
+            return Some(RespType::SimpleError {
+                content: "ERR WAIT cannot be used with replica instances. Please also note that since Redis 4.0 if a replica is configured to be writable (which is not the default) writes to replicas are just local and are not propagated.".into(),
+            });
+        }
+
+        let offset = self.replication.write_offset;
+        let acked = self.acks(offset).replicas;
+        if acked >= numreplicas {
+            return Some(RespType::Integer {
+                integer: acked as i64,
+            });
+        }
+
+        self.replication.get_ack = true;
+        let state = WaitingState::Wait {
+            offset,
+            numreplicas,
+            acked,
+        };
+        self.redis.wait_for_acks(client_id, state, timeout);
+        None
+    }
+
+    /// WAITAOF: blocks the client until the writes made so far are synced to the local AOF
+    /// and to the ones of enough replicas.
+    pub(super) fn handle_waitaof(
+        &mut self,
+        client_id: i32,
+        numlocal: u64,
+        numreplicas: u64,
+        timeout: Option<Duration>,
+    ) -> Option<RespType> {
+        let error = if self.replication.master.is_some() {
+            "ERR WAITAOF cannot be used with replica instances. Please also note that writes to replicas are just local and are not propagated."
+        } else if numlocal > 0 && self.aof_fsynced_offset().is_none() {
+            "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled."
+        } else {
+            ""
+        };
+        if !error.is_empty() {
+            return Some(RespType::SimpleError {
+                content: error.into(),
+            });
+        }
+
+        let offset = self.replication.write_offset;
+        let acks = self.acks(offset);
+        let (local, acked) = (acks.local_fsynced as u64, acks.replicas_fsynced);
+        if local >= numlocal && acked >= numreplicas {
+            return Some(RespType::Array {
+                elements: vec![
+                    RespType::Integer {
+                        integer: local as i64,
+                    },
+                    RespType::Integer {
+                        integer: acked as i64,
+                    },
+                ],
+            });
+        }
+
+        self.replication.get_ack = true;
+        let state = WaitingState::WaitAof {
+            offset,
+            numlocal,
+            numreplicas,
+            local,
+            acked,
+        };
+        self.redis.wait_for_acks(client_id, state, timeout);
+        None
+    }
+
+    /// Called from every iteration of the event loop: asks the replicas for acknowledgements
+    /// when clients wait for them and serves the clients that got enough.
+    pub(super) fn serve_waits(&mut self) {
+        if std::mem::take(&mut self.replication.get_ack) && self.replication.master.is_none() {
+            self.feed_replicas(&request(&["REPLCONF", "GETACK", "*"]).serialize());
+        }
+
+        let replicas = self.replication.ack_offsets();
+        let local = self.aof_fsynced_offset();
+        self.redis
+            .count_acks(|offset| acks(&replicas, local, offset));
+    }
+
+    /// The replication offset reached, on a replica the one of the stream of its master.
+    pub(super) fn replication_offset(&self) -> u64 {
+        self.replication.offset
+    }
+
+    fn acks(&self, offset: u64) -> Acks {
+        let replicas = self.replication.ack_offsets();
+        acks(&replicas, self.aof_fsynced_offset(), offset)
+    }
+
+    /// Sends the changes made to the dataset to the replicas. A replica forwards the stream of
+    /// its master instead, as it receives it.
+    pub(super) fn replicate_changes(&mut self, changes: &[RespType]) {
+        //WAITAOF waits for offsets, they have to move even without replicas
+        if self.aof_fsynced_offset().is_some() {
+            self.replication.backlog.get_or_insert_default();
+        }
+        if self.replication.master.is_none() && !changes.is_empty() {
+            let data: Vec<u8> = changes.iter().flat_map(RespType::serialize).collect();
+            self.feed_replicas(&data);
+            self.replication.write_offset = self.replication.offset;
+        }
+    }
+
+    /// Called from every iteration of the event loop: connects to the master, acknowledges
+    /// the offset reached to it, pings the replicas and drops the links that timed out.
+    pub(super) fn replication_cron(&mut self) -> io::Result<()> {
+        let timeout = self.config.repl_timeout();
+
+        if let Some(link) = self.replication.master.as_ref() {
+            match link.state {
+                LinkState::Connect => self.connect_to_master()?,
+                LinkState::Connected if link.last_io.elapsed() > timeout => {
+                    println!("MASTER timeout: no data nor PING received...");
+                    self.master_link_lost();
+                }
+                LinkState::Connected if link.last_ack.elapsed() >= ACK_PERIOD => self.send_ack(),
+                LinkState::Connected => {}
+                _ if link.last_io.elapsed() > timeout => {
+                    println!("Timeout connecting to the MASTER...");
+                    self.master_link_lost();
+                }
+                _ => {}
+            }
+        }
+
+        //the pings keep the link alive when nothing is written, a replica forwards the ones of
+        //its master instead
+        if self.replication.master.is_none()
+            && self.replication.online_replicas().next().is_some()
+            && self.replication.last_ping.elapsed() >= self.config.repl_ping_replica_period()
+        {
+            self.replication.last_ping = Instant::now();
+            self.feed_replicas(&request(&["PING"]).serialize());
+        }
+
+        let timed_out: Vec<i32> = self
+            .replication
+            .online_replicas()
+            .filter(|(_, replica)| replica.last_ack.elapsed() > timeout)
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        for client_id in timed_out {
+            println!("Disconnecting timedout replica {client_id}");
+            self.disconnect(client_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serves the events of the socket connected to the master.
+    pub(super) fn handle_master_event(&mut self, events: u32) -> io::Result<()> {
+        if (EPOLLIN as u32) & events != 0 {
+            let mut buf = [0u8; 16 * 1024];
+            let read = self
+                .replication
+                .master
+                .as_mut()
+                .and_then(|link| link.stream.as_mut())
+                .map(|stream| stream.read(&mut buf));
+
+            match read {
+                Some(Ok(read)) if read > 0 => {
+                    let link = self.replication.master.as_mut().expect("read from it");
+                    link.input.extend_from_slice(&buf[..read]);
+                    link.last_io = Instant::now();
+                    self.process_master_input()?;
+                }
+                Some(Ok(_)) => {
+                    println!("Connection with master lost.");
+                    self.master_link_lost();
+                }
+                Some(Err(err)) => {
+                    println!("Error reading from master: {err}");
+                    self.master_link_lost();
+                }
+                None => {}
+            }
+        }
+
+        if ((EPOLLERR | EPOLLHUP | EPOLLRDHUP) as u32) & events != 0
+            && self.replication.master_fd().is_some()
+        {
+            println!("Connection with master lost.");
+            self.master_link_lost();
+        }
+
+        //the changes applied go to the AOF
+        self.propagate_changes()
+    }
+
+    //appends to the backlog and sends to the replicas
+    fn feed_replicas(&mut self, data: &[u8]) {
+        let size = self.config.repl_backlog_size();
+        let Some(backlog) = self.replication.backlog.as_mut() else {
+            return;
+        };
+        backlog.feed(data, size);
+        self.replication.offset += data.len() as u64;
+
+        for (client_id, _) in self.replication.online_replicas() {
+            if let Some(client) = self.clients.get_mut(client_id) {
+                client.send_raw(data);
+            }
+        }
+    }
+
+    fn connect_to_master(&mut self) -> io::Result<()> {
+        let Some(link) = self.replication.master.as_mut() else {
+            return Ok(());
+        };
+        if link
+            .last_connect
+            .is_some_and(|last| last.elapsed() < CONNECT_RETRY_DELAY)
+        {
+            return Ok(());
+        }
+        link.last_connect = Some(Instant::now());
+
+        println!("Connecting to MASTER {}:{}", link.host, link.port);
+        let connected = (link.host.as_str(), link.port)
+            .to_socket_addrs()
+            .and_then(|mut addrs| {
+                addrs
+                    .next()
+                    .ok_or_else(|| io::Error::other("no address found"))
+            })
+            .and_then(|addr| TcpStream::connect_timeout(&addr, CONNECT_RETRY_DELAY));
+        let stream = match connected {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Error condition on socket for SYNC: {err}");
+                return Ok(());
+            }
+        };
+
+        println!("MASTER <-> REPLICA sync started");
+        self.poller.watch_socket(&stream)?;
+        link.stream = Some(stream);
+        link.input.clear();
+        link.last_io = Instant::now();
+        link.state = LinkState::ReceivePong;
+        self.send_to_master(&["PING"]);
+        Ok(())
+    }
+
+    //runs the handshake, loads the snapshot and applies the stream, as far as the input goes
+    fn process_master_input(&mut self) -> io::Result<()> {
+        loop {
+            let Some(link) = self.replication.master.as_mut() else {
+                return Ok(());
+            };
+
+            match link.state.clone() {
+                LinkState::Connect => return Ok(()),
+                LinkState::Connected => return self.apply_master_stream(),
+                LinkState::Transfer { replid, offset } => {
+                    let (header, len) = match snapshot_header(&link.input) {
+                        Ok(Some(sizes)) => sizes,
+                        Ok(None) => return Ok(()),
+                        Err(err) => {
+                            println!("Bad protocol from MASTER, the first byte is not '$': {err}");
+                            self.master_link_lost();
+                            return Ok(());
+                        }
+                    };
+                    if link.input.len() < header + len {
+                        return Ok(());
+                    }
+                    let snapshot: Vec<u8> = link.input.drain(..header + len).skip(header).collect();
+                    self.finish_full_sync(&snapshot, replid, offset)?;
+                }
+                state => {
+                    let reply = match RespType::parse(&link.input) {
+                        Ok((reply, len)) => {
+                            link.input.drain(..len);
+                            reply
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                        Err(err) => {
+                            println!("Bad reply from MASTER during the handshake: {err}");
+                            self.master_link_lost();
+                            return Ok(());
+                        }
+                    };
+                    self.handshake_step(state, reply);
+                }
+            }
+        }
+    }
+
+    //handles the reply to the last command of the handshake and sends the next one
+    fn handshake_step(&mut self, state: LinkState, reply: RespType) {
+        let error = match &reply {
+            RespType::SimpleError { content } => Some(content.as_str()),
+            _ => None,
+        };
+
+        match state {
+            LinkState::ReceivePong => {
+                //a master requiring authentication refuses the PING, AUTH comes next anyway
+                if let Some(error) = error
+                    && !["NOAUTH", "NOPERM", "ERR operation not permitted"]
+                        .iter()
+                        .any(|prefix| error.starts_with(prefix))
+                {
+                    println!("Error reply to PING from master: '{error}'");
+                    return self.master_link_lost();
+                }
+                self.send_auth_or_port();
+            }
+            LinkState::ReceiveAuth => {
+                if let Some(error) = error {
+                    println!("Unable to AUTH to MASTER: {error}");
+                    return self.master_link_lost();
+                }
+                self.send_listening_port();
+            }
+            //older masters don't know these options, they are not required
+            LinkState::ReceivePort => {
+                if let Some(error) = error {
+                    println!(
+                        "(Non critical) Master does not understand REPLCONF listening-port: {error}"
+                    );
+                }
+                self.set_link_state(LinkState::ReceiveCapa);
+                self.send_to_master(&["REPLCONF", "capa", "psync2"]);
+            }
+            LinkState::ReceiveCapa => {
+                if let Some(error) = error {
+                    println!("(Non critical) Master does not understand REPLCONF capa: {error}");
+                }
+                self.send_psync();
+            }
+            LinkState::ReceivePsync => self.psync_reply(reply),
+            _ => unreachable!("not a handshake state"),
+        }
+    }
+
+    fn send_auth_or_port(&mut self) {
+        let Some(password) = self.config.masterauth().map(str::to_string) else {
+            return self.send_listening_port();
+        };
+
+        self.set_link_state(LinkState::ReceiveAuth);
+        match self.config.masteruser().map(str::to_string) {
+            Some(user) => self.send_to_master(&["AUTH", &user, &password]),
+            None => self.send_to_master(&["AUTH", &password]),
+        }
+    }
+
+    fn send_listening_port(&mut self) {
+        self.set_link_state(LinkState::ReceivePort);
+        let port = self.config.port().to_string();
+        self.send_to_master(&["REPLCONF", "listening-port", &port]);
+    }
+
+    fn send_psync(&mut self) {
+        let resumable = self
+            .replication
+            .master
+            .as_ref()
+            .is_some_and(|link| link.resumable);
+
+        self.set_link_state(LinkState::ReceivePsync);
+        if resumable {
+            let replid = self.replication.replid.clone();
+            let offset = (self.replication.offset + 1).to_string();
+            println!("Trying a partial resynchronization (request {replid}:{offset}).");
+            self.send_to_master(&["PSYNC", &replid, &offset]);
+        } else {
+            self.send_to_master(&["PSYNC", "?", "-1"]);
+        }
+    }
+
+    fn psync_reply(&mut self, reply: RespType) {
+        let content = match reply {
+            RespType::SimpleString { content } => content,
+            RespType::SimpleError { content } => {
+                println!("Unexpected reply to PSYNC from master: -{content}");
+                return self.master_link_lost();
+            }
+            reply => {
+                println!("Unexpected reply to PSYNC from master: {reply:?}");
+                return self.master_link_lost();
+            }
+        };
+
+        let words: Vec<&str> = content.split_whitespace().collect();
+        match words.as_slice() {
+            ["FULLRESYNC", replid, offset] if let Ok(offset) = offset.parse() => {
+                println!("Full resync from master: {replid}:{offset}");
+                self.set_link_state(LinkState::Transfer {
+                    replid: replid.to_string(),
+                    offset,
+                });
+            }
+            ["CONTINUE", rest @ ..] => {
+                println!("Successful partial resynchronization with master.");
+                if let [replid] = rest
+                    && *replid != self.replication.replid
+                {
+                    //the master moved on to a new history, the replicas have to follow
+                    self.replication.replid2 =
+                        std::mem::replace(&mut self.replication.replid, replid.to_string());
+                    self.replication.second_offset = self.replication.offset as i64 + 1;
+                    self.disconnect_replicas();
+                }
+                self.replication.backlog.get_or_insert_default();
+                self.set_link_state(LinkState::Connected);
+                println!("MASTER <-> REPLICA sync: Master accepted a Partial Resynchronization.");
+            }
+            _ => {
+                println!("Unexpected reply to PSYNC from master: {content}");
+                self.master_link_lost();
+            }
+        }
+    }
+
+    fn finish_full_sync(&mut self, snapshot: &[u8], replid: String, offset: u64) -> io::Result<()> {
+        println!("MASTER <-> REPLICA sync: Loading DB in memory");
+        if let Err(err) = self.load_snapshot(snapshot) {
+            println!("Failed trying to load the MASTER synchronization DB from socket: {err}");
+            self.master_link_lost();
+            return Ok(());
+        }
+
+        //the replicas of this server still have the previous history
+        self.disconnect_replicas();
+        let replication = &mut self.replication;
+        replication.replid = replid;
+        replication.replid2 = "0".repeat(40);
+        replication.second_offset = -1;
+        replication.offset = offset;
+        replication.write_offset = offset;
+        replication.backlog = Some(Backlog::default());
+        if let Some(link) = replication.master.as_mut() {
+            link.resumable = true;
+        }
+        self.set_link_state(LinkState::Connected);
+
+        if let Err(err) = self.restart_aof() {
+            println!("Failed to restart the AOF after the sync with the master: {err}");
+        }
+        println!("MASTER <-> REPLICA sync: Finished with success");
+        Ok(())
+    }
+
+    //runs the commands of the stream and forwards them to the replicas of this server
+    fn apply_master_stream(&mut self) -> io::Result<()> {
+        //the stream applied but not yet counted in the offset
+        let mut applied = vec![];
+
+        loop {
+            let Some(link) = self.replication.master.as_mut() else {
+                return Ok(());
+            };
+            let (request, len) = match RespType::parse(&link.input) {
+                Ok(parsed) => parsed,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => {
+                    println!("Protocol error from the MASTER: {err}");
+                    self.master_link_lost();
+                    break;
+                }
+            };
+            let raw: Vec<u8> = link.input.drain(..len).collect();
+            let mut transaction = link.transaction.take();
+
+            match Command::from(request) {
+                //the acknowledged offset doesn't include the request itself
+                Command::ReplConf { options } => {
+                    if options.iter().any(|(name, _)| name == "getack") {
+                        self.count_applied(std::mem::take(&mut applied))?;
+                        self.send_ack();
+                    }
+                }
+                cmd => {
+                    if let Err(err) = self.replay_command(cmd, MASTER_CLIENT_ID, &mut transaction) {
+                        println!("Error running a command received from the MASTER: {err}");
+                    }
+                }
+            }
+
+            if let Some(link) = self.replication.master.as_mut() {
+                link.transaction = transaction;
+            }
+            applied.extend(raw);
+        }
+
+        self.count_applied(applied)
+    }
+
+    //like on a master, the changes are written to the AOF before the offset moves past them
+    fn count_applied(&mut self, applied: Vec<u8>) -> io::Result<()> {
+        self.propagate_changes()?;
+        self.feed_replicas(&applied);
+        Ok(())
+    }
+
+    fn send_ack(&mut self) {
+        if let Some(link) = self.replication.master.as_mut() {
+            link.last_ack = Instant::now();
+        }
+        let offset = self.replication.offset.to_string();
+        let fsynced = self.aof_fsynced_offset().unwrap_or(0).to_string();
+        self.send_to_master(&["REPLCONF", "ACK", &offset, "FACK", &fsynced]);
+    }
+
+    fn send_to_master(&mut self, args: &[&str]) {
+        let Some(stream) = self
+            .replication
+            .master
+            .as_mut()
+            .and_then(|link| link.stream.as_mut())
+        else {
+            return;
+        };
+
+        if let Err(err) = stream.write_all(&request(args).serialize()) {
+            println!("Error writing to MASTER: {err}");
+            self.master_link_lost();
+        }
+    }
+
+    fn set_link_state(&mut self, state: LinkState) {
+        if let Some(link) = self.replication.master.as_mut() {
+            link.state = state;
+        }
+    }
+
+    //the link goes back to waiting for a reconnection, keeping the offset reached
+    fn master_link_lost(&mut self) {
+        let Some(link) = self.replication.master.as_mut() else {
+            return;
+        };
+        if let Some(stream) = link.stream.take() {
+            let _ = self.poller.remove_socket(&stream);
+        }
+        if link.state == LinkState::Connected {
+            link.down_since = Instant::now();
+        }
+        link.state = LinkState::Connect;
+        link.input.clear();
+        link.transaction = None;
+    }
+
+    fn close_master_link(&mut self, mut link: MasterLink) {
+        if let Some(stream) = link.stream.take() {
+            let _ = self.poller.remove_socket(&stream);
+        }
+    }
+
+    fn disconnect_replicas(&mut self) {
+        let replicas: Vec<i32> = self.replication.replicas.keys().copied().collect();
+        for client_id in replicas {
+            if let Err(err) = self.disconnect(client_id) {
+                println!("Error disconnecting replica {client_id}: {err}");
+            }
+        }
+    }
+}
+
+//the $<len>\r\n line preceding the snapshot, returning its length and the one of the snapshot,
+//the master may send newlines to keep the link alive before it
+fn snapshot_header(input: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let start = input.iter().take_while(|byte| **byte == b'\n').count();
+    let input = &input[start..];
+    if input.is_empty() {
+        return Ok(None);
+    }
+    if input[0] != b'$' {
+        return Err(io::Error::other("unexpected snapshot header"));
+    }
+    let Some(end) = input.windows(2).position(|pair| pair == b"\r\n") else {
+        return Ok(None);
+    };
+
+    std::str::from_utf8(&input[1..end])
+        .ok()
+        .and_then(|len| len.parse().ok())
+        .map(|len| Some((start + end + 2, len)))
+        .ok_or_else(|| io::Error::other("invalid snapshot length"))
+}
+
+//how many replicas processed and synced the offset, given their acknowledged offsets, and
+//whether the local AOF is synced up to it
+fn acks(replicas: &[(u64, u64)], local: Option<u64>, offset: u64) -> Acks {
+    Acks {
+        replicas: replicas.iter().filter(|(ack, _)| *ack >= offset).count() as u64,
+        replicas_fsynced: replicas.iter().filter(|(_, fack)| *fack >= offset).count() as u64,
+        local_fsynced: local.is_some_and(|local| local >= offset),
+    }
+}
+
+fn request(args: &[&str]) -> RespType {
+    RespType::Array {
+        elements: args
+            .iter()
+            .map(|arg| RespType::BulkString {
+                data: arg.as_bytes().to_vec(),
+            })
+            .collect(),
+    }
+}
+
+//40 hex characters, unique enough to tell histories apart
+pub(super) fn new_replid() -> String {
+    Sha1::digest(format!("{:?} {}", SystemTime::now(), std::process::id()))
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{Backlog, LinkState};
+    use crate::{
+        command::Command,
+        ev_loop::{
+            EventLoop,
+            test_util::{event_loop, request},
+        },
+        resp::RespType,
+    };
+
+    fn get(server: &mut EventLoop, key: &str) -> Option<RespType> {
+        server.execute(1, Command::Get { key: key.into() })
+    }
+
+    //runs both servers until the replica caught up with the master
+    fn sync(master: &mut EventLoop, replica: &mut EventLoop) {
+        let started = Instant::now();
+        let in_sync = |master: &EventLoop, replica: &EventLoop| {
+            replica
+                .replication
+                .master
+                .as_ref()
+                .is_some_and(|link| link.state == LinkState::Connected)
+                && replica.replication.offset == master.replication.offset
+        };
+
+        while !in_sync(master, replica) && started.elapsed() < Duration::from_secs(5) {
+            master.replication_cron().unwrap();
+            master.process_events(10).unwrap();
+            replica.replication_cron().unwrap();
+            replica.process_events(10).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_backlog() {
+        let mut backlog = Backlog::default();
+        backlog.feed(b"abcdef", 4);
+
+        //the stream is 6 bytes long, only the last 4 are held
+        assert_eq!(backlog.first_offset(6), 3);
+        assert_eq!(backlog.since(5, 6), Some(b"ef".to_vec()));
+        assert_eq!(backlog.since(7, 6), Some(vec![]));
+        assert_eq!(backlog.since(2, 6), None);
+        assert_eq!(backlog.since(8, 6), None);
+    }
+
+    #[test]
+    fn test_full_and_partial_resync() {
+        let mut master = event_loop(&[]);
+        let mut replica = event_loop(&[]);
+        let port = master.listeners[0].local_addr().unwrap().port();
+        master.execute(1, request(&["SET", "before", "1"]));
+        master.propagate_changes().unwrap();
+
+        let reply = replica.execute(1, request(&["REPLICAOF", "127.0.0.1", &port.to_string()]));
+        assert_eq!(
+            reply,
+            Some(RespType::SimpleString {
+                content: "OK".into()
+            })
+        );
+        sync(&mut master, &mut replica);
+
+        assert_eq!(replica.replication.replid, master.replication.replid);
+        assert_eq!(
+            get(&mut replica, "before"),
+            Some(RespType::BulkString {
+                data: b"1".to_vec()
+            })
+        );
+        assert_eq!(
+            replica.handle_role(),
+            RespType::Array {
+                elements: vec![
+                    RespType::BulkString {
+                        data: b"slave".to_vec()
+                    },
+                    RespType::BulkString {
+                        data: b"127.0.0.1".to_vec()
+                    },
+                    RespType::Integer {
+                        integer: port as i64
+                    },
+                    RespType::BulkString {
+                        data: b"connected".to_vec()
+                    },
+                    RespType::Integer {
+                        integer: master.replication.offset as i64
+                    },
+                ]
+            }
+        );
+
+        //the writes are streamed once the dataset is loaded
+        master.execute(1, request(&["SET", "after", "2"]));
+        master.propagate_changes().unwrap();
+        sync(&mut master, &mut replica);
+        assert_eq!(
+            get(&mut replica, "after"),
+            Some(RespType::BulkString {
+                data: b"2".to_vec()
+            })
+        );
+        assert!(master.replication.offset > 0);
+        assert_eq!(
+            replica.check_read_only(&request(&["SET", "after", "3"])),
+            Err(RespType::SimpleError {
+                content: "READONLY You can't write against a read only replica.".into()
+            })
+        );
+
+        //after a disconnection only the missing writes are sent, the dataset is not reloaded
+        replica.master_link_lost();
+        let _ = replica
+            .redis
+            .handle_command(request(&["SET", "local", "1"]), 1);
+        master.execute(1, request(&["SET", "during", "3"]));
+        master.propagate_changes().unwrap();
+        sync(&mut master, &mut replica);
+
+        assert_eq!(
+            get(&mut replica, "during"),
+            Some(RespType::BulkString {
+                data: b"3".to_vec()
+            })
+        );
+        assert_eq!(
+            get(&mut replica, "local"),
+            Some(RespType::BulkString {
+                data: b"1".to_vec()
+            })
+        );
+
+        //stopping replicating starts a new history
+        replica.execute(1, request(&["REPLICAOF", "NO", "ONE"]));
+        assert_eq!(replica.replication.replid2, master.replication.replid);
+        assert_eq!(replica.replication.role(), "master");
+    }
+
+    #[test]
+    fn test_wait() {
+        let mut master = event_loop(&[]);
+        let mut replica = event_loop(&[]);
+        let port = master.listeners[0].local_addr().unwrap().port();
+        replica.execute(1, request(&["REPLICAOF", "127.0.0.1", &port.to_string()]));
+        sync(&mut master, &mut replica);
+
+        master.execute(1, request(&["SET", "key", "1"]));
+        master.propagate_changes().unwrap();
+        assert_eq!(master.execute(1, request(&["WAIT", "1", "5000"])), None);
+
+        //the replica is asked for an acknowledgement and the client is served once it got it
+        let started = Instant::now();
+        while master.redis.ready.is_empty() && started.elapsed() < Duration::from_secs(5) {
+            master.serve_waits();
+            master.process_events(10).unwrap();
+            replica.process_events(10).unwrap();
+        }
+        assert_eq!(
+            master.redis.ready.pop(),
+            Some((1, RespType::Integer { integer: 1 }))
+        );
+
+        //there is a single replica, the count acknowledged so far is given at the timeout
+        assert_eq!(master.execute(1, request(&["WAIT", "2", "1"])), None);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(
+            master.redis.remove_expired(),
+            vec![(1, RespType::Integer { integer: 1 })]
+        );
+
+        assert_eq!(
+            master.execute(1, request(&["WAITAOF", "1", "0", "0"])),
+            Some(RespType::SimpleError {
+                content:
+                    "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled."
+                        .into()
+            })
+        );
+        assert!(matches!(
+            replica.execute(1, request(&["WAIT", "0", "0"])),
+            Some(RespType::SimpleError { .. })
+        ));
+        assert_eq!(
+            replica.execute(1, request(&["WAITAOF", "0", "0", "0"])),
+            Some(RespType::SimpleError {
+                content: "ERR WAITAOF cannot be used with replica instances. Please also note that writes to replicas are just local and are not propagated.".into()
+            })
+        );
+
+        //with nothing to wait for, the replicas acknowledged so far are counted right away
+        assert_eq!(
+            master.execute(1, request(&["WAIT", "0", "0"])),
+            Some(RespType::Integer { integer: 1 })
+        );
+        let mut alone = event_loop(&[]);
+        assert_eq!(
+            alone.execute(1, request(&["WAIT", "0", "0"])),
+            Some(RespType::Integer { integer: 0 })
+        );
+        assert_eq!(alone.execute(1, request(&["WAIT", "1", "1"])), None);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(
+            alone.redis.remove_expired(),
+            vec![(1, RespType::Integer { integer: 0 })]
+        );
+    }
+}
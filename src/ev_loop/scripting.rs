@@ -0,0 +1,994 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Instant};
+
+use mlua::{Function, HookTriggers, Lua, LuaOptions, RegistryKey, StdLib, Value, Variadic};
+use sha1::{Digest as _, Sha1};
+
+use crate::{
+    acl::Context,
+    command::{Command, FunctionSubcommand, ScriptSubcommand},
+    ev_loop::EventLoop,
+    resp::RespType,
+};
+
+//number of VM instructions between two checks of a running script
+const HOOK_INSTRUCTIONS: u32 = 100_000;
+
+//registry entries holding the functions bound to the event loop for the duration of a run
+const CALL: &str = "redis_call";
+pub(super) const BUSY: &str = "redis_busy";
+const RUN: &str = "redis_run";
+const ARGUMENTS: &str = "redis_arguments";
+
+//the redis table behind the read-only one scripts see
+pub(super) const REDIS: &str = "redis_table";
+
+//runs once when the interpreter is created, before the global table gets protected
+const PRELUDE: &str = r#"
+local pcall_reply = redis.pcall
+
+redis.LOG_DEBUG = 0
+redis.LOG_VERBOSE = 1
+redis.LOG_NOTICE = 2
+redis.LOG_WARNING = 3
+
+function redis.call(...)
+    local reply = pcall_reply(...)
+    if type(reply) == 'table' and reply.err ~= nil then
+        error(reply, 0)
+    end
+    return reply
+end
+
+function redis.error_reply(message)
+    return { err = message }
+end
+
+function redis.status_reply(message)
+    return { ok = message }
+end
+
+-- scripts have no access to the file system, nor to the environments of other functions
+loadfile = nil
+dofile = nil
+getfenv = nil
+setfenv = nil
+
+-- nothing a script does may outlive it: the globals and the tables they hold are read-only,
+-- their metatables are locked and the functions writing raw fields refuse to touch them
+local _G, pairs, type, error, tostring, setmetatable = _G, pairs, type, error, tostring, setmetatable
+local readonly = {}
+local function refuse()
+    error("Attempt to modify a readonly table", 3)
+end
+local function guard(write)
+    return function(t, ...)
+        if readonly[t] then
+            refuse()
+        end
+        return write(t, ...)
+    end
+end
+
+rawset = guard(rawset)
+for _, name in pairs({ 'insert', 'remove', 'sort' }) do
+    table[name] = guard(table[name])
+end
+getmetatable('').__metatable = false
+
+-- the globals are moved out of _G so that overwriting one goes through __newindex too, the
+-- tables they hold are replaced by read-only proxies
+local globals = {}
+for name, value in pairs(_G) do
+    globals[name] = value
+end
+for name in pairs(globals) do
+    _G[name] = nil
+end
+local behind = {}
+for name, value in pairs(globals) do
+    if type(value) == 'table' and value ~= _G then
+        local proxy = setmetatable({}, { __index = value, __newindex = refuse, __metatable = false })
+        readonly[proxy] = true
+        behind[proxy] = value
+        globals[name] = proxy
+    end
+end
+readonly[_G] = true
+behind[_G] = globals
+
+-- Lua 5.1 has no __pairs, the functions reading raw fields look behind the proxies instead,
+-- never handing the tables behind them out
+local next, rawget = globals.next, globals.rawget
+local function real(t)
+    return behind[t] or t
+end
+local function iterable(t, name)
+    if type(t) ~= 'table' then
+        error("bad argument #1 to '" .. name .. "' (table expected, got " .. type(t) .. ")", 3)
+    end
+    return real(t)
+end
+local function proxy_next(t, key)
+    return next(real(t), key)
+end
+globals.next = proxy_next
+globals.pairs = function(t)
+    iterable(t, 'pairs')
+    return proxy_next, t, nil
+end
+globals.ipairs = function(t)
+    local items = iterable(t, 'ipairs')
+    return function(_, i)
+        i = i + 1
+        local value = rawget(items, i)
+        if value ~= nil then
+            return i, value
+        end
+    end, t, 0
+end
+globals.rawget = function(t, key)
+    return rawget(real(t), key)
+end
+
+-- scripts written for Redis often use these, they are not provided
+local unavailable = { cjson = true, cmsgpack = true, bit = true, struct = true }
+
+setmetatable(_G, {
+    __newindex = function(_, name)
+        if globals[name] ~= nil then
+            error("Attempt to modify a readonly table", 2)
+        end
+        error("Script attempted to create global variable '" .. tostring(name) .. "'", 2)
+    end,
+    __index = function(_, name)
+        local value = globals[name]
+        if value == nil and unavailable[name] then
+            error("Script attempted to access the '" .. name .. "' library, only redis, string, table and math are available", 2)
+        elseif value == nil then
+            error("Script attempted to access nonexistent global variable '" .. tostring(name) .. "'", 2)
+        end
+        return value
+    end,
+    __metatable = false,
+})
+
+-- the keys and the arguments of the script run next, as read-only globals
+local function set_arguments(keys, argv)
+    globals.KEYS = keys
+    globals.ARGV = argv
+end
+
+-- errors raised by redis.call are tables, they become the reply of the script
+return function(script, ...)
+    local ok, result = pcall(script, ...)
+    if ok or (type(result) == 'table' and result.err ~= nil) then
+        return ok, result
+    end
+    error(result, 0)
+end, set_arguments
+"#;
+
+/// The interpreter running EVAL scripts, along with the scripts compiled so far.
+#[derive(Debug)]
+pub(super) struct Scripting {
+    lua: Rc<Lua>,
+    //compiled scripts by the hex sha1 of their body
+    scripts: HashMap<String, RegistryKey>,
+}
+
+/// State of the script being executed, looked at by the requests served while it runs.
+#[derive(Debug)]
+pub(super) struct RunningScript {
+    started: Instant,
+    //FCALL rather than EVAL, it has to be stopped with FUNCTION KILL
+    function: bool,
+    read_only: bool,
+    //a write command has been run, killing the script would leave the dataset half modified
+    wrote: bool,
+    killed: bool,
+}
+
+impl RunningScript {
+    pub(super) fn new(function: bool, read_only: bool) -> Self {
+        Self {
+            started: Instant::now(),
+            function,
+            read_only,
+            wrote: false,
+            killed: false,
+        }
+    }
+}
+
+/// Creates an interpreter with the redis library available and the global table protected,
+/// scripts are interrupted by the hook whenever the function registered as BUSY says so.
+/// Only the string, table and math libraries come along: cjson, cmsgpack, bit and struct are
+/// not provided.
+pub(super) fn interpreter() -> Lua {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )
+    .expect("the Lua standard libraries can be loaded");
+    init(&lua).expect("the scripting environment can be set up");
+
+    lua
+}
+
+fn init(lua: &Lua) -> mlua::Result<()> {
+    let redis = lua.create_table()?;
+    //the event loop is reachable only while a script runs, through the registry
+    redis.set(
+        "pcall",
+        lua.create_function(|lua, args: Variadic<Value>| {
+            let Some(call) = lua.named_registry_value::<Option<Function>>(CALL)? else {
+                return Err(mlua::Error::RuntimeError(
+                    "redis.call can only be used while running a script".into(),
+                ));
+            };
+            call.call::<_, Value>(args)
+        })?,
+    )?;
+    redis.set(
+        "sha1hex",
+        lua.create_function(|_, data: mlua::String| Ok(sha1_hex(data.as_bytes())))?,
+    )?;
+    redis.set(
+        "log",
+        lua.create_function(|_, (level, message): (i64, mlua::String)| {
+            println!("Script log ({level}): {}", message.to_string_lossy());
+            Ok(())
+        })?,
+    )?;
+    lua.globals().set("redis", redis.clone())?;
+    lua.set_named_registry_value(REDIS, redis)?;
+
+    let (run, arguments): (Function, Function) = lua.load(PRELUDE).set_name("=prelude").call(())?;
+    lua.set_named_registry_value(RUN, run)?;
+    lua.set_named_registry_value(ARGUMENTS, arguments)?;
+
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS),
+        |lua, _| {
+            if let Some(busy) = lua.named_registry_value::<Option<Function>>(BUSY)?
+                && let Some(reason) = busy.call::<_, Option<String>>(())?
+            {
+                return Err(mlua::Error::RuntimeError(reason));
+            }
+            Ok(())
+        },
+    );
+
+    Ok(())
+}
+
+impl Scripting {
+    pub(super) fn new() -> Self {
+        Self {
+            lua: Rc::new(interpreter()),
+            scripts: HashMap::new(),
+        }
+    }
+
+    /// Compiles the script unless already known, returning its sha1.
+    pub(super) fn load(&mut self, script: &str) -> Result<String, String> {
+        let sha1 = sha1_hex(script.as_bytes());
+        if self.exists(&sha1) {
+            return Ok(sha1);
+        }
+
+        let compiled = self
+            .lua
+            .load(script)
+            .set_name("@user_script")
+            .into_function()
+            .and_then(|function| self.lua.create_registry_value(function))
+            .map_err(|err| {
+                format!(
+                    "ERR Error compiling script (new function): {}",
+                    error_message(&err)
+                )
+            })?;
+
+        self.scripts.insert(sha1.clone(), compiled);
+        Ok(sha1)
+    }
+
+    pub(super) fn exists(&self, sha1: &str) -> bool {
+        self.scripts.contains_key(sha1)
+    }
+}
+
+impl EventLoop {
+    /// EVAL and EVALSHA, the script runs to completion before anything else is served.
+    pub(super) fn handle_eval(&mut self, client_id: i32, cmd: Command) -> RespType {
+        let (sha1, keys, args, read_only) = match cmd {
+            Command::Eval {
+                script,
+                keys,
+                args,
+                read_only,
+            } => match self.scripting.load(&script) {
+                Ok(sha1) => (sha1, keys, args, read_only),
+                Err(content) => return RespType::SimpleError { content },
+            },
+            Command::EvalSha {
+                sha1,
+                keys,
+                args,
+                read_only,
+            } => (sha1, keys, args, read_only),
+            _ => panic!("Illegal state"),
+        };
+
+        let Some(key) = self.scripting.scripts.get(&sha1) else {
+            return RespType::SimpleError {
+                content: "NOSCRIPT No matching script. Please use EVAL.".into(),
+            };
+        };
+
+        //the interpreter is kept alive by this handle while the event loop is lent to the script
+        let lua = Rc::clone(&self.scripting.lua);
+        let prepared = lua.registry_value::<Function>(key).and_then(|script| {
+            let arguments: Function = lua.named_registry_value(ARGUMENTS)?;
+            arguments.call::<_, ()>((keys, args))?;
+            Ok(script)
+        });
+
+        match prepared {
+            Ok(script) => self.run_script(
+                &lua,
+                client_id,
+                &sha1,
+                script,
+                vec![],
+                RunningScript::new(false, read_only),
+            ),
+            Err(err) => RespType::SimpleError {
+                content: format!("ERR {}", error_message(&err)),
+            },
+        }
+    }
+
+    /// Calls a script on behalf of the client, the event loop is lent to redis.call and to the
+    /// hook serving the other clients while the script is busy.
+    pub(super) fn run_script<'lua>(
+        &mut self,
+        lua: &'lua Lua,
+        client_id: i32,
+        name: &str,
+        script: Function<'lua>,
+        args: Vec<Value<'lua>>,
+        running: RunningScript,
+    ) -> RespType {
+        let killed = if running.function {
+            "Script killed by user with FUNCTION KILL..."
+        } else {
+            "Script killed by user with SCRIPT KILL..."
+        };
+        self.running_script = Some(running);
+        let propagated = self.redis.propagated.len();
+
+        let event_loop = RefCell::new(&mut *self);
+        let result = lua.scope(|scope| {
+            let call = scope.create_function(|lua, args: Variadic<Value>| {
+                let reply = match script_request(args) {
+                    Ok(request) => event_loop.borrow_mut().script_call(client_id, request),
+                    Err(content) => RespType::SimpleError { content },
+                };
+                resp_to_lua(lua, reply)
+            })?;
+            let busy = scope.create_function(|_, ()| {
+                Ok(event_loop
+                    .borrow_mut()
+                    .serve_while_busy()
+                    .then(|| killed.to_string()))
+            })?;
+            lua.set_named_registry_value(CALL, call)?;
+            lua.set_named_registry_value(BUSY, busy)?;
+
+            let run: Function = lua.named_registry_value(RUN)?;
+            let mut run_args = vec![Value::Function(script)];
+            run_args.extend(args);
+            let (ok, result): (bool, Value) = run.call(Variadic::from_iter(run_args))?;
+
+            Ok((ok, lua_to_resp(result)))
+        });
+
+        //the functions bound to the event loop are gone with the scope
+        let _ = lua.unset_named_registry_value(CALL);
+        let _ = lua.unset_named_registry_value(BUSY);
+        self.running_script = None;
+        //the changes made by the script are replayed as a whole
+        self.redis.propagate_atomically(propagated);
+
+        match result {
+            Ok((false, RespType::SimpleError { content })) => RespType::SimpleError {
+                content: format!("{content} script: {name}"),
+            },
+            Ok((_, reply)) => reply,
+            Err(err) => RespType::SimpleError {
+                content: format!("ERR {} script: {name}", error_message(&err)),
+            },
+        }
+    }
+
+    pub(super) fn handle_script(&mut self, subcommand: ScriptSubcommand) -> RespType {
+        match subcommand {
+            ScriptSubcommand::Load { script } => match self.scripting.load(&script) {
+                Ok(sha1) => RespType::BulkString {
+                    data: sha1.into_bytes(),
+                },
+                Err(content) => RespType::SimpleError { content },
+            },
+            ScriptSubcommand::Exists { sha1s } => RespType::Array {
+                elements: sha1s
+                    .iter()
+                    .map(|sha1| RespType::Integer {
+                        integer: self.scripting.exists(sha1) as i64,
+                    })
+                    .collect(),
+            },
+            //a brand new interpreter also drops whatever the scripts left behind
+            ScriptSubcommand::Flush => {
+                self.scripting = Scripting::new();
+                RespType::SimpleString {
+                    content: "OK".into(),
+                }
+            }
+            ScriptSubcommand::Kill => RespType::SimpleError {
+                content: "NOTBUSY No scripts in execution right now.".into(),
+            },
+        }
+    }
+
+    /// Requests received while a script is busy are refused, except for the SCRIPT KILL or
+    /// FUNCTION KILL matching the kind of script. A script that already wrote can't be killed,
+    /// and there is no SHUTDOWN NOSAVE either: the only way out is killing the process.
+    pub(super) fn handle_busy_request(&mut self, client_id: i32, cmd: Command) {
+        let Some(script) = self.running_script.as_mut() else {
+            return;
+        };
+
+        let kill = match cmd {
+            Command::Script {
+                subcommand: ScriptSubcommand::Kill,
+            } => !script.function,
+            Command::Function {
+                subcommand: FunctionSubcommand::Kill,
+            } => script.function,
+            _ => false,
+        };
+
+        let response = if !kill {
+            let kill_command = if script.function {
+                "FUNCTION KILL"
+            } else {
+                "SCRIPT KILL"
+            };
+            RespType::SimpleError {
+                content: format!(
+                    "BUSY Redis is busy running a script. You can only call {kill_command}."
+                ),
+            }
+        } else if script.wrote {
+            RespType::SimpleError {
+                content: "UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server process, SHUTDOWN NOSAVE is not supported.".into(),
+            }
+        } else {
+            script.killed = true;
+            RespType::SimpleString {
+                content: "OK".into(),
+            }
+        };
+
+        self.send(client_id, response);
+    }
+
+    //redis.call and redis.pcall
+    fn script_call(&mut self, client_id: i32, request: RespType) -> RespType {
+        let cmd = Command::from(request);
+
+        match cmd {
+            Command::Hello { .. }
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::PSubscribe { .. }
+            | Command::PUnsubscribe { .. }
+            | Command::SSubscribe { .. }
+            | Command::SUnsubscribe { .. }
+            | Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Watch { .. }
+            | Command::Unwatch
+            | Command::Config { .. }
+            | Command::Save
+            | Command::BgSave { .. }
+            | Command::BgRewriteAof
+            | Command::ReplicaOf { .. }
+            | Command::ReplConf { .. }
+            | Command::PSync { .. }
+            | Command::Role
+            | Command::Cluster { .. }
+            | Command::Asking
+            | Command::Sentinel { .. }
+            | Command::Migrate { .. }
+            | Command::Wait { .. }
+            | Command::WaitAof { .. }
+            | Command::Eval { .. }
+            | Command::EvalSha { .. }
+            | Command::Script { .. }
+            | Command::FCall { .. }
+            | Command::Function { .. }
+            | Command::Auth { .. }
+            | Command::Acl { .. } => {
+                return RespType::SimpleError {
+                    content: "ERR This Redis command is not allowed from script".into(),
+                };
+            }
+            _ => {}
+        }
+
+        if let Err(response) = self.check_permissions(client_id, &cmd, Context::Lua) {
+            return response;
+        }
+
+        if let Some(script) = self.running_script.as_mut()
+            && cmd.is_write()
+        {
+            if script.read_only {
+                return RespType::SimpleError {
+                    content: "ERR Write commands are not allowed from read-only scripts.".into(),
+                };
+            }
+            script.wrote = true;
+        }
+        if let Err(response) = self.check_read_only(&cmd) {
+            return response;
+        }
+
+        //scripts can't block, blocking commands behave as if their timeout elapsed
+        match self.execute(client_id, cmd) {
+            Some(reply) => reply,
+            None => self
+                .redis
+                .cancel_blocking(client_id)
+                .unwrap_or(RespType::Null),
+        }
+    }
+
+    //called by the hook every few instructions, returns true once the script has been killed
+    fn serve_while_busy(&mut self) -> bool {
+        let Some(script) = self.running_script.as_ref() else {
+            return false;
+        };
+        //past the threshold the other clients are let in, to be told to wait or to kill the script
+        if script.started.elapsed() < self.config.busy_reply_threshold() {
+            return false;
+        }
+
+        if let Err(err) = self.process_events(0) {
+            println!("Could not serve clients while running a script, got error {err}");
+        }
+
+        self.running_script
+            .as_ref()
+            .is_some_and(|script| script.killed)
+    }
+}
+
+fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+//the message of the error without the traceback added by the interpreter
+pub(super) fn error_message(err: &mlua::Error) -> String {
+    match err {
+        mlua::Error::RuntimeError(message) | mlua::Error::SyntaxError { message, .. } => message
+            .split("\nstack traceback:")
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        mlua::Error::CallbackError { cause, .. } => error_message(cause),
+        err => err.to_string(),
+    }
+}
+
+//the arguments of redis.call, as a request coming from a client
+fn script_request(args: Variadic<Value>) -> Result<RespType, String> {
+    if args.is_empty() {
+        return Err("ERR Please specify at least one argument for this redis lib call".into());
+    }
+
+    let elements = args
+        .iter()
+        .map(|arg| match arg {
+            Value::String(s) => Ok(s.as_bytes().to_vec()),
+            Value::Integer(i) => Ok(i.to_string().into_bytes()),
+            Value::Number(n) => Ok(n.to_string().into_bytes()),
+            _ => Err("ERR Lua redis lib command arguments must be strings or integers".to_string()),
+        })
+        .map(|data| data.map(|data| RespType::BulkString { data }))
+        .collect::<Result<_, _>>()?;
+
+    Ok(RespType::Array { elements })
+}
+
+/// Converts a reply to the Lua value seen by scripts: nulls become false, status and error
+/// replies become tables with a single ok or err field.
+fn resp_to_lua(lua: &Lua, resp: RespType) -> mlua::Result<Value<'_>> {
+    Ok(match resp {
+        RespType::Integer { integer } => Value::Integer(integer),
+        RespType::BulkString { data } => Value::String(lua.create_string(data)?),
+        RespType::SimpleString { content } => {
+            Value::Table(lua.create_table_from([("ok", content)])?)
+        }
+        RespType::SimpleError { content } => {
+            Value::Table(lua.create_table_from([("err", content)])?)
+        }
+        RespType::NullBulkString | RespType::NullArray | RespType::Null => Value::Boolean(false),
+        RespType::Array { elements } | RespType::Push { elements } => {
+            let table = lua.create_table_with_capacity(elements.len(), 0)?;
+            for element in elements {
+                table.raw_push(resp_to_lua(lua, element)?)?;
+            }
+            Value::Table(table)
+        }
+        //scripts speak RESP2, maps are flattened
+        RespType::Map { entries } => {
+            let table = lua.create_table_with_capacity(entries.len() * 2, 0)?;
+            for (key, value) in entries {
+                table.raw_push(resp_to_lua(lua, key)?)?;
+                table.raw_push(resp_to_lua(lua, value)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+/// Converts the value returned by a script to a reply: numbers are truncated to integers, true
+/// is 1, false and nil are null and arrays stop at the first nil.
+fn lua_to_resp(value: Value) -> RespType {
+    match value {
+        Value::Integer(integer) => RespType::Integer { integer },
+        Value::Number(number) => RespType::Integer {
+            integer: number as i64,
+        },
+        Value::String(s) => RespType::BulkString {
+            data: s.as_bytes().to_vec(),
+        },
+        Value::Boolean(true) => RespType::Integer { integer: 1 },
+        Value::Table(table) => {
+            if let Ok(Value::String(err)) = table.raw_get("err") {
+                return RespType::SimpleError {
+                    content: err.to_string_lossy().into_owned(),
+                };
+            }
+            if let Ok(Value::String(ok)) = table.raw_get("ok") {
+                return RespType::SimpleString {
+                    content: ok.to_string_lossy().into_owned(),
+                };
+            }
+
+            let mut elements = vec![];
+            for i in 1.. {
+                match table.raw_get::<_, Value>(i) {
+                    Ok(Value::Nil) | Err(_) => break,
+                    Ok(value) => elements.push(lua_to_resp(value)),
+                }
+            }
+            RespType::Array { elements }
+        }
+        _ => RespType::NullBulkString,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RunningScript, Scripting, lua_to_resp, resp_to_lua, sha1_hex};
+    use crate::{
+        command::Command,
+        ev_loop::{
+            EventLoop,
+            test_util::{client, event_loop, send},
+        },
+        resp::RespType,
+    };
+
+    fn eval(event_loop: &mut EventLoop, script: &str, keys: &[&str], args: &[&str]) -> RespType {
+        event_loop.handle_eval(
+            1,
+            Command::Eval {
+                script: script.into(),
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+                read_only: false,
+            },
+        )
+    }
+
+    fn error_content(resp: RespType) -> String {
+        match resp {
+            RespType::SimpleError { content } => content,
+            other => panic!("expected an error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_resp_lua_conversion() {
+        let scripting = Scripting::new();
+        let lua = &scripting.lua;
+
+        let reply = RespType::Array {
+            elements: vec![
+                RespType::Integer { integer: 7 },
+                RespType::BulkString {
+                    data: b"value".to_vec(),
+                },
+                RespType::SimpleString {
+                    content: "OK".into(),
+                },
+                RespType::NullBulkString,
+            ],
+        };
+        let value = resp_to_lua(lua, reply).unwrap();
+        //the null element becomes false, not nil, so the array doesn't stop there
+        assert_eq!(
+            lua_to_resp(value),
+            RespType::Array {
+                elements: vec![
+                    RespType::Integer { integer: 7 },
+                    RespType::BulkString {
+                        data: b"value".to_vec(),
+                    },
+                    RespType::SimpleString {
+                        content: "OK".into(),
+                    },
+                    RespType::NullBulkString,
+                ],
+            }
+        );
+
+        let value = lua
+            .load("return {3.99, true, 'a', nil, 'b'}")
+            .eval()
+            .unwrap();
+        assert_eq!(
+            lua_to_resp(value),
+            RespType::Array {
+                elements: vec![
+                    RespType::Integer { integer: 3 },
+                    RespType::Integer { integer: 1 },
+                    RespType::BulkString {
+                        data: b"a".to_vec()
+                    },
+                ],
+            }
+        );
+
+        assert_eq!(
+            sha1_hex(b"return 1"),
+            "e0e1f9fabfc9d4800c877a703b823ac0578ff8db"
+        );
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut event_loop = event_loop(&[]);
+
+        let reply = eval(
+            &mut event_loop,
+            "redis.call('set', KEYS[1], ARGV[1]); return redis.call('get', KEYS[1])",
+            &["key"],
+            &["value"],
+        );
+        assert_eq!(
+            reply,
+            RespType::BulkString {
+                data: b"value".to_vec()
+            }
+        );
+
+        //the script has been cached by its sha1
+        let sha1 = sha1_hex(b"return redis.call('get', KEYS[1])");
+        assert!(!event_loop.scripting.exists(&sha1));
+        eval(
+            &mut event_loop,
+            "return redis.call('get', KEYS[1])",
+            &["key"],
+            &[],
+        );
+        let reply = event_loop.handle_eval(
+            1,
+            Command::EvalSha {
+                sha1,
+                keys: vec!["missing".into()],
+                args: vec![],
+                read_only: true,
+            },
+        );
+        assert_eq!(reply, RespType::NullBulkString);
+
+        let reply = event_loop.handle_eval(
+            1,
+            Command::EvalSha {
+                sha1: "ffffffffffffffffffffffffffffffffffffffff".into(),
+                keys: vec![],
+                args: vec![],
+                read_only: false,
+            },
+        );
+        assert_eq!(
+            error_content(reply),
+            "NOSCRIPT No matching script. Please use EVAL."
+        );
+
+        //pcall hands the error over to the script, call makes the script fail
+        let reply = eval(
+            &mut event_loop,
+            "return redis.pcall('subscribe', 'news')",
+            &[],
+            &[],
+        );
+        assert_eq!(
+            error_content(reply),
+            "ERR This Redis command is not allowed from script"
+        );
+        let reply = eval(&mut event_loop, "return redis.call('multi')", &[], &[]);
+        assert!(
+            error_content(reply)
+                .starts_with("ERR This Redis command is not allowed from script script: ")
+        );
+
+        let reply = eval(&mut event_loop, "x = 1", &[], &[]);
+        assert!(error_content(reply).starts_with(
+            "ERR user_script:1: Script attempted to create global variable 'x' script: "
+        ));
+
+        let reply = eval(&mut event_loop, "return {ok = 'fine'}", &[], &[]);
+        assert_eq!(
+            reply,
+            RespType::SimpleString {
+                content: "fine".into()
+            }
+        );
+
+        let reply = eval(&mut event_loop, "return 'unterminated", &[], &[]);
+        assert!(error_content(reply).starts_with("ERR Error compiling script"));
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let mut event_loop = event_loop(&[]);
+
+        //every escape fails, and leaves nothing behind for the scripts that follow
+        for escape in [
+            "setmetatable(_G, nil)",
+            "rawset(_G, 'x', 1)",
+            "redis.call = function() return 'hij' end",
+            "rawset(redis, 'call', function() return 'hij' end)",
+            "string.rep = function() return 'hij' end",
+            "table.insert(_G, 'hij')",
+            "tostring = function() return 'hij' end",
+            "getmetatable('').__index = {}",
+            "getfenv(0).x = 1",
+            "setfenv(redis.call, {})",
+            "KEYS = {}",
+            "ARGV = {}",
+            "select(2, pairs(_G)).x = 1",
+            "select(2, pairs(string)).len = function() return 0 end",
+            "select(2, ipairs(redis)).call = function() return 'hij' end",
+        ] {
+            let reply = eval(&mut event_loop, escape, &[], &[]);
+            assert!(
+                matches!(reply, RespType::SimpleError { .. }),
+                "{escape} should fail, got {reply:?}"
+            );
+        }
+        assert_eq!(
+            eval(&mut event_loop, "return getmetatable(_G)", &[], &[]),
+            RespType::NullBulkString
+        );
+        assert_eq!(
+            eval(&mut event_loop, "return string.len('abc')", &[], &[]),
+            RespType::Integer { integer: 3 }
+        );
+
+        let reply = eval(
+            &mut event_loop,
+            "return {redis.call('ping'), string.rep('a', 2), tostring(#_G)}",
+            &[],
+            &[],
+        );
+        assert_eq!(
+            reply,
+            RespType::Array {
+                elements: vec![
+                    RespType::SimpleString {
+                        content: "PONG".into()
+                    },
+                    RespType::BulkString {
+                        data: b"aa".to_vec()
+                    },
+                    RespType::BulkString {
+                        data: b"0".to_vec()
+                    },
+                ]
+            }
+        );
+        let reply = eval(&mut event_loop, "return x", &[], &[]);
+        assert!(error_content(reply).contains("nonexistent global variable 'x'"));
+
+        //the read-only tables can still be iterated
+        let reply = eval(
+            &mut event_loop,
+            "local n = 0
+            for _ in pairs(string) do n = n + 1 end
+            for _, key in ipairs(KEYS) do n = n + #key end
+            return {n > 3, next(redis) ~= nil, rawget(_G, 'math') == math, KEYS[1]}",
+            &["key"],
+            &[],
+        );
+        assert_eq!(
+            reply,
+            RespType::Array {
+                elements: vec![
+                    RespType::Integer { integer: 1 },
+                    RespType::Integer { integer: 1 },
+                    RespType::Integer { integer: 1 },
+                    RespType::BulkString {
+                        data: b"key".to_vec()
+                    },
+                ]
+            }
+        );
+
+        let reply = eval(&mut event_loop, "return cjson.encode({})", &[], &[]);
+        assert!(error_content(reply).contains("the 'cjson' library"));
+    }
+
+    #[test]
+    fn test_eval_ro() {
+        let mut event_loop = event_loop(&[]);
+
+        let reply = event_loop.handle_eval(
+            1,
+            Command::Eval {
+                script: "return redis.pcall('set', 'key', 'value')".into(),
+                keys: vec![],
+                args: vec![],
+                read_only: true,
+            },
+        );
+        assert_eq!(
+            error_content(reply),
+            "ERR Write commands are not allowed from read-only scripts."
+        );
+    }
+
+    #[test]
+    fn test_busy_script_that_wrote() {
+        let mut server = event_loop(&[]);
+        let mut client = client(&server);
+        let mut running = RunningScript::new(false, false);
+        running.wrote = true;
+        server.running_script = Some(running);
+
+        //only killing the process is left
+        assert_eq!(
+            send(&mut server, &mut client, "GET a"),
+            "-BUSY Redis is busy running a script. You can only call SCRIPT KILL.\r\n"
+        );
+        let reply = send(&mut server, &mut client, "SCRIPT KILL");
+        assert!(reply.starts_with("-UNKILLABLE"), "{reply}");
+        assert!(
+            reply.contains("SHUTDOWN NOSAVE is not supported"),
+            "{reply}"
+        );
+        assert!(!server.running_script.as_ref().unwrap().killed);
+    }
+}
@@ -0,0 +1,1565 @@
+//! Sentinel mode as the event loop runs it, crate::sentinel holds the state. The sentinel opens
+//! two links to every master and replica it monitors: one for its commands, PING every second,
+//! INFO every ten seconds and the hellos, the other subscribed to the hello channel to learn
+//! about the other sentinels. It opens a link to each of those too, to ask them whether they
+//! see the master down and for their votes.
+//!
+//! The leader of a failover sends REPLICAOF NO ONE to the replica it selected, waits for INFO to
+//! report it is a master and then makes the other replicas replicate it. The clients and the
+//! other sentinels learn about the switch through the +switch-master event and the hellos.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, Read as _, Write as _},
+    net::{IpAddr, TcpStream},
+    os::fd::AsRawFd,
+    time::{Duration, Instant},
+};
+
+use libc::{EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLRDHUP};
+
+use crate::{
+    command::{Command, SentinelSubcommand},
+    ev_loop::{EventLoop, cluster::random, replication::new_replid},
+    resp::RespType,
+    sentinel::{
+        Failover, FailoverState, HELLO_CHANNEL, Hello, InfoReport, Instance, Master, Role, Sentinel,
+    },
+};
+
+//delay between two attempts to connect to an instance, also how long an attempt can take
+const CONNECT_RETRY_DELAY: Duration = Duration::from_secs(1);
+const PING_PERIOD: Duration = Duration::from_secs(1);
+//every second once the master is down
+const INFO_PERIOD: Duration = Duration::from_secs(10);
+const HELLO_PERIOD: Duration = Duration::from_secs(2);
+//how often the other sentinels are asked about a master down, their answers are trusted five
+//times as long
+const ASK_PERIOD: Duration = Duration::from_secs(1);
+//milliseconds, the sentinels wait a random part of it before trying to lead a failover, so that
+//they don't all try at once
+const MAX_DESYNC: u64 = 1000;
+//a sentinel not elected by then gives up the failover, or earlier with a shorter failover timeout
+const ELECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The sentinel state and its links to the instances.
+#[derive(Debug)]
+pub(super) struct SentinelState {
+    sentinel: Sentinel,
+    //by file descriptor
+    links: HashMap<i32, Link>,
+    //when the sentinel last tried to open each link
+    last_connect: HashMap<Target, Instant>,
+    //published to the clients of the sentinel, by channel
+    events: Vec<(String, String)>,
+    //the state changed since it was last saved
+    dirty: bool,
+}
+
+/// What a link is opened to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Target {
+    master: String,
+    kind: Kind,
+    //the link subscribed to the hello channel, it only receives messages
+    pubsub: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Kind {
+    Master,
+    //by address
+    Replica(String),
+    //by run id
+    Sentinel(String),
+}
+
+#[derive(Debug)]
+struct Link {
+    stream: TcpStream,
+    //bytes received but not yet processed
+    input: Vec<u8>,
+    target: Target,
+    //the address it was opened to, the link is closed once the instance moves
+    address: String,
+    //the requests still waiting for their replies, in order
+    pending: VecDeque<Request>,
+    //when the ping still waiting for its reply was sent
+    ping_sent: Option<Instant>,
+    last_ping: Option<Instant>,
+    last_info: Option<Instant>,
+    last_hello: Option<Instant>,
+    last_ask: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Request {
+    Ping,
+    Info,
+    Publish,
+    IsMasterDown,
+    ReplicaOf,
+    Subscribe,
+}
+
+impl EventLoop {
+    /// In sentinel mode, reads the sentinel state from the configuration and saves it back
+    /// right away, with the id of the sentinel when it is a new one.
+    pub(super) fn configure_sentinel(&mut self) -> io::Result<()> {
+        if !self.config.sentinel_mode() {
+            return Ok(());
+        }
+
+        let sentinel = Sentinel::from_config(self.config.sentinel(), new_replid())
+            .map_err(io::Error::other)?;
+        println!("Sentinel ID is {}", sentinel.myid);
+        let events = sentinel
+            .masters
+            .values()
+            .map(|master| {
+                let quorum = master.quorum;
+                let message = format!("{} quorum {quorum}", describe(master, &Kind::Master));
+                ("+monitor".to_string(), message)
+            })
+            .collect();
+
+        self.sentinel = Some(SentinelState {
+            sentinel,
+            links: HashMap::new(),
+            last_connect: HashMap::new(),
+            events,
+            dirty: false,
+        });
+        self.save_sentinel_config();
+        Ok(())
+    }
+
+    /// In sentinel mode only the commands of the sentinel and a few connection ones are
+    /// served, SENTINEL is unknown outside of it.
+    pub(super) fn check_sentinel(&self, cmd: &Command, name: Option<&str>) -> Result<(), RespType> {
+        let served = match cmd {
+            Command::Sentinel { .. } => self.sentinel.is_some(),
+            _ if self.sentinel.is_none() => true,
+            Command::Ping { .. }
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::PSubscribe { .. }
+            | Command::PUnsubscribe { .. }
+            | Command::Publish { .. }
+            | Command::Info { .. }
+            | Command::Role
+            | Command::Config { .. }
+            | Command::Hello { .. }
+            | Command::Auth { .. }
+            | Command::Acl { .. }
+            | Command::ErrorCmd { .. } => true,
+            _ => false,
+        };
+
+        match served {
+            true => Ok(()),
+            false => Err(RespType::SimpleError {
+                content: format!("ERR unknown command '{}'", name.unwrap_or_default()),
+            }),
+        }
+    }
+
+    pub(super) fn handle_sentinel(&mut self, subcommand: SentinelSubcommand) -> RespType {
+        let Some(state) = self.sentinel.as_mut() else {
+            return RespType::SimpleError {
+                content: "ERR unknown command 'sentinel'".into(),
+            };
+        };
+        let SentinelState {
+            sentinel,
+            events,
+            dirty,
+            ..
+        } = state;
+        let no_such_master = || RespType::SimpleError {
+            content: "ERR No such master with that name".into(),
+        };
+
+        match subcommand {
+            SentinelSubcommand::Masters => RespType::Array {
+                elements: sentinel.masters.values().map(master_fields).collect(),
+            },
+            SentinelSubcommand::Master { name } => match sentinel.masters.get(&name) {
+                Some(master) => master_fields(master),
+                None => no_such_master(),
+            },
+            SentinelSubcommand::Replicas { name } => match sentinel.masters.get(&name) {
+                Some(master) => RespType::Array {
+                    elements: master
+                        .replicas
+                        .iter()
+                        .map(|(address, replica)| {
+                            let mut fields = instance_fields(address, replica, "slave");
+                            let (master_host, master_port) = match &replica.master_addr {
+                                Some((host, port)) => (host.clone(), port.to_string()),
+                                None => (String::new(), String::new()),
+                            };
+                            let link = if replica.master_link_up { "ok" } else { "err" };
+                            let role = match replica.role {
+                                Some(Role::Master) => "master",
+                                _ => "slave",
+                            };
+                            fields.extend([
+                                ("role-reported", role.to_string()),
+                                ("master-host", master_host),
+                                ("master-port", master_port),
+                                ("master-link-status", link.to_string()),
+                                ("slave-repl-offset", replica.offset.to_string()),
+                            ]);
+                            fields_map(fields)
+                        })
+                        .collect(),
+                },
+                None => no_such_master(),
+            },
+            SentinelSubcommand::Sentinels { name } => match sentinel.masters.get(&name) {
+                Some(master) => RespType::Array {
+                    elements: master
+                        .sentinels
+                        .iter()
+                        .map(|(run_id, other)| {
+                            let mut fields = instance_fields(run_id, other, "sentinel");
+                            fields.extend([
+                                ("last-hello-message", elapsed_ms(other.last_hello)),
+                                ("voted-leader", other.leader.clone().unwrap_or("?".into())),
+                                ("voted-leader-epoch", other.leader_epoch.to_string()),
+                            ]);
+                            fields_map(fields)
+                        })
+                        .collect(),
+                },
+                None => no_such_master(),
+            },
+            SentinelSubcommand::GetMasterAddrByName { name } => match sentinel.masters.get(&name) {
+                Some(master) => {
+                    let (ip, port) = master.address();
+                    RespType::Array {
+                        elements: vec![bulk(ip), bulk(&port.to_string())],
+                    }
+                }
+                None => RespType::NullArray,
+            },
+            SentinelSubcommand::IsMasterDownByAddr {
+                ip,
+                port,
+                current_epoch,
+                run_id,
+            } => {
+                let Some(master) = sentinel
+                    .masters
+                    .values_mut()
+                    .find(|master| master.instance.ip == ip && master.instance.port == port)
+                else {
+                    return RespType::Array {
+                        elements: vec![
+                            RespType::Integer { integer: 0 },
+                            bulk("*"),
+                            RespType::Integer { integer: 0 },
+                        ],
+                    };
+                };
+
+                //a sentinel trying to lead a failover asks for a vote along
+                if run_id != "*" {
+                    if current_epoch > sentinel.current_epoch {
+                        sentinel.current_epoch = current_epoch;
+                        events.push(("+new-epoch".into(), current_epoch.to_string()));
+                        *dirty = true;
+                    }
+                    //one vote per epoch, the first sentinel asking gets it
+                    if master.leader_epoch < current_epoch
+                        && sentinel.current_epoch <= current_epoch
+                    {
+                        master.leader = Some(run_id.clone());
+                        master.leader_epoch = sentinel.current_epoch;
+                        events.push((
+                            "+vote-for-leader".into(),
+                            format!("{run_id} {}", master.leader_epoch),
+                        ));
+                        *dirty = true;
+                        if run_id != sentinel.myid {
+                            let desync = Duration::from_millis(random(MAX_DESYNC));
+                            master.failover_start = Some(Instant::now() + desync);
+                        }
+                    }
+                }
+
+                let leader = match run_id.as_str() {
+                    "*" => "*",
+                    _ => master.leader.as_deref().unwrap_or("*"),
+                };
+                RespType::Array {
+                    elements: vec![
+                        RespType::Integer {
+                            integer: master.instance.sdown_since.is_some() as i64,
+                        },
+                        bulk(leader),
+                        RespType::Integer {
+                            integer: master.leader_epoch as i64,
+                        },
+                    ],
+                }
+            }
+            SentinelSubcommand::Failover { name } => {
+                let Some(master) = sentinel.masters.get_mut(&name) else {
+                    return no_such_master();
+                };
+                if master.failover.is_some() {
+                    return RespType::SimpleError {
+                        content: "INPROG Failover already in progress".into(),
+                    };
+                }
+                if master.select_replica(info_validity(master)).is_none() {
+                    return RespType::SimpleError {
+                        content: "NOGOODSLAVE No suitable replica to promote".into(),
+                    };
+                }
+
+                sentinel.current_epoch += 1;
+                let epoch = sentinel.current_epoch;
+                master.failover = Some(Failover::new(epoch, true));
+                master.failover_start = Some(Instant::now());
+                master.leader = Some(sentinel.myid.clone());
+                master.leader_epoch = epoch;
+                events.push(("+new-epoch".into(), epoch.to_string()));
+                events.push(("+try-failover".into(), describe(master, &Kind::Master)));
+                *dirty = true;
+                ok()
+            }
+            SentinelSubcommand::Monitor {
+                name,
+                ip,
+                port,
+                quorum,
+            } => {
+                if let Err(err) = sentinel.monitor(&name, &ip, &port, &quorum) {
+                    return RespType::SimpleError {
+                        content: format!("ERR {err}"),
+                    };
+                }
+                let master = &sentinel.masters[&name];
+                let message = format!("{} quorum {quorum}", describe(master, &Kind::Master));
+                events.push(("+monitor".into(), message));
+                *dirty = true;
+                ok()
+            }
+            SentinelSubcommand::Remove { name } => {
+                //its links are closed as stale by the next cron
+                let Some(master) = sentinel.masters.remove(&name) else {
+                    return no_such_master();
+                };
+                events.push(("-monitor".into(), describe(&master, &Kind::Master)));
+                *dirty = true;
+                ok()
+            }
+            SentinelSubcommand::Set { name, options } => {
+                let Some(master) = sentinel.masters.get_mut(&name) else {
+                    return no_such_master();
+                };
+                for (option, value) in options {
+                    if let Err(err) = master.set(&option, &value) {
+                        return RespType::SimpleError {
+                            content: format!("ERR {err}"),
+                        };
+                    }
+                    *dirty = true;
+                }
+                ok()
+            }
+            SentinelSubcommand::MyId => bulk(&sentinel.myid),
+        }
+    }
+
+    /// PUBLISH to a sentinel: the other sentinels say hello this way too.
+    pub(super) fn handle_sentinel_publish(&mut self, channel: &str, message: &str) -> RespType {
+        if channel != HELLO_CHANNEL {
+            return RespType::SimpleError {
+                content: "ERR Only HELLO messages are accepted by Sentinel instances.".into(),
+            };
+        }
+        self.process_hello(message);
+        RespType::Integer { integer: 1 }
+    }
+
+    /// ROLE of a sentinel: the names of the masters it monitors.
+    pub(super) fn sentinel_role(&self) -> RespType {
+        let names = self
+            .sentinel
+            .iter()
+            .flat_map(|state| state.sentinel.masters.keys())
+            .map(|name| bulk(name))
+            .collect();
+        RespType::Array {
+            elements: vec![bulk("sentinel"), RespType::Array { elements: names }],
+        }
+    }
+
+    /// The fields of the sentinel section of INFO.
+    pub(super) fn sentinel_info(&self) -> Vec<(String, String)> {
+        let Some(state) = self.sentinel.as_ref() else {
+            return vec![];
+        };
+
+        let masters = &state.sentinel.masters;
+        let mut fields = vec![("sentinel_masters".to_string(), masters.len().to_string())];
+        for (number, master) in masters.values().enumerate() {
+            let status = match (master.odown_since, master.instance.sdown_since) {
+                (Some(_), _) => "odown",
+                (None, Some(_)) => "sdown",
+                (None, None) => "ok",
+            };
+            let (ip, port) = master.address();
+            fields.push((
+                format!("master{number}"),
+                format!(
+                    "name={},status={status},address={ip}:{port},slaves={},sentinels={}",
+                    master.name,
+                    master.replicas.len(),
+                    master.sentinels.len() + 1
+                ),
+            ));
+        }
+        fields
+    }
+
+    //saved on every change, for the sentinel to restart as it left off
+    fn save_sentinel_config(&mut self) {
+        let Some(state) = self.sentinel.as_mut() else {
+            return;
+        };
+        state.dirty = false;
+        self.config.set_sentinel(state.sentinel.config());
+
+        if self.config.file().is_some()
+            && let Err(err) = self.config.rewrite()
+        {
+            println!("Could not save the sentinel state: {err}");
+        }
+    }
+
+    /// Whether the socket is a link of the sentinel to an instance.
+    pub(super) fn is_sentinel_link(&self, fd: i32) -> bool {
+        self.sentinel
+            .as_ref()
+            .is_some_and(|state| state.links.contains_key(&fd))
+    }
+
+    pub(super) fn handle_sentinel_event(&mut self, fd: i32, events: u32) -> io::Result<()> {
+        let Some(state) = self.sentinel.as_mut() else {
+            return Ok(());
+        };
+
+        if (EPOLLIN as u32) & events != 0 {
+            let mut buf = [0u8; 16 * 1024];
+            let read = state
+                .links
+                .get_mut(&fd)
+                .map(|link| link.stream.read(&mut buf));
+
+            match read {
+                Some(Ok(read)) if read > 0 => {
+                    if let Some(link) = state.links.get_mut(&fd) {
+                        link.input.extend_from_slice(&buf[..read]);
+                    }
+                    self.process_sentinel_input(fd)?;
+                }
+                Some(_) => self.close_sentinel_link(fd)?,
+                None => {}
+            }
+        }
+
+        if ((EPOLLERR | EPOLLHUP | EPOLLRDHUP) as u32) & events != 0 {
+            self.close_sentinel_link(fd)?;
+        }
+        Ok(())
+    }
+
+    fn process_sentinel_input(&mut self, fd: i32) -> io::Result<()> {
+        loop {
+            let Some(link) = self
+                .sentinel
+                .as_mut()
+                .and_then(|state| state.links.get_mut(&fd))
+            else {
+                return Ok(());
+            };
+
+            let (reply, len) = match RespType::parse(&link.input) {
+                Ok(parsed) => parsed,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => {
+                    println!("Closing the link to {}, got error {err}", link.address);
+                    return self.close_sentinel_link(fd);
+                }
+            };
+            link.input.drain(..len);
+            let target = link.target.clone();
+
+            if target.pubsub {
+                if let RespType::Array { elements } | RespType::Push { elements } = reply
+                    && let [
+                        RespType::BulkString { data: kind },
+                        _,
+                        RespType::BulkString { data },
+                    ] = elements.as_slice()
+                    && kind == b"message"
+                {
+                    self.process_hello(&String::from_utf8_lossy(data));
+                }
+                continue;
+            }
+
+            let request = link.pending.pop_front();
+            if request == Some(Request::Ping) {
+                link.ping_sent = None;
+            }
+            self.process_sentinel_reply(fd, &target, request, reply)?;
+        }
+    }
+
+    fn process_sentinel_reply(
+        &mut self,
+        fd: i32,
+        target: &Target,
+        request: Option<Request>,
+        reply: RespType,
+    ) -> io::Result<()> {
+        let Some(state) = self.sentinel.as_mut() else {
+            return Ok(());
+        };
+        let Some(master) = state.sentinel.masters.get_mut(&target.master) else {
+            return Ok(());
+        };
+
+        match (request, reply) {
+            (Some(Request::Ping), reply) => {
+                //a server loading its dataset or a replica cut from its master is still up
+                let up = match &reply {
+                    RespType::SimpleString { content } => content == "PONG",
+                    RespType::SimpleError { content } => {
+                        content.starts_with("LOADING") || content.starts_with("MASTERDOWN")
+                    }
+                    _ => false,
+                };
+                if up && let Some(instance) = instance_mut(master, &target.kind) {
+                    instance.last_avail = Instant::now();
+                    instance.ping_sent = None;
+                }
+            }
+            (Some(Request::Info), RespType::BulkString { data }) => {
+                let report = InfoReport::parse(&String::from_utf8_lossy(&data));
+                return self.process_info(fd, target, report);
+            }
+            (Some(Request::IsMasterDown), RespType::Array { elements }) => {
+                if let Kind::Sentinel(run_id) = &target.kind
+                    && let Some(other) = master.sentinels.get_mut(run_id)
+                    && let [
+                        RespType::Integer { integer: down },
+                        RespType::BulkString { data: leader },
+                        RespType::Integer { integer: epoch },
+                    ] = elements.as_slice()
+                {
+                    other.master_down = *down == 1;
+                    other.down_reply = Some(Instant::now());
+                    let leader = String::from_utf8_lossy(leader);
+                    if leader != "*" {
+                        other.leader = Some(leader.into_owned());
+                        other.leader_epoch = *epoch as u64;
+                    }
+                }
+            }
+            (_, RespType::SimpleError { content }) => {
+                println!("{} replied {content}", describe(master, &target.kind));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    //what INFO tells about an instance: the replicas of a master, the progress of a failover,
+    //or a replica that should replicate another master
+    fn process_info(&mut self, fd: i32, target: &Target, report: InfoReport) -> io::Result<()> {
+        let Some(state) = self.sentinel.as_mut() else {
+            return Ok(());
+        };
+        let SentinelState {
+            sentinel,
+            events,
+            dirty,
+            ..
+        } = state;
+        let Some(master) = sentinel.masters.get_mut(&target.master) else {
+            return Ok(());
+        };
+        let Some(instance) = instance_mut(master, &target.kind) else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        if instance.role != report.role || instance.master_addr != report.master_addr {
+            instance.role_reported = Some(now);
+        }
+        if report.run_id.is_some() {
+            instance.run_id = report.run_id;
+        }
+        instance.role = report.role;
+        instance.master_addr = report.master_addr;
+        instance.master_link_up = report.master_link_up;
+        instance.offset = report.offset;
+        instance.info_refresh = Some(now);
+
+        let address = match &target.kind {
+            Kind::Master => {
+                //the replicas are found through their master
+                for (ip, port) in report.replicas {
+                    let replica = Instance::new(ip, port);
+                    let address = replica.address();
+                    if !master.replicas.contains_key(&address) {
+                        master.replicas.insert(address.clone(), replica);
+                        events.push(("+slave".into(), describe(master, &Kind::Replica(address))));
+                        *dirty = true;
+                    }
+                }
+                return Ok(());
+            }
+            Kind::Replica(address) => address,
+            Kind::Sentinel(_) => return Ok(()),
+        };
+
+        let replica = &master.replicas[address];
+        let replicating = replica
+            .master_addr
+            .as_ref()
+            .map(|(ip, port)| format!("{ip}:{port}"));
+        let (role, sdown, role_reported) =
+            (replica.role, replica.sdown_since, replica.role_reported);
+        let master_address = master.instance.address();
+        let master_sane =
+            master.instance.sdown_since.is_none() && master.instance.role == Some(Role::Master);
+
+        match master.failover.as_mut() {
+            Some(failover)
+                if failover.state == FailoverState::WaitPromotion
+                    && failover.promoted.as_ref() == Some(address)
+                    && role == Some(Role::Master) =>
+            {
+                failover.state = FailoverState::ReconfReplicas;
+                failover.state_time = now;
+                master.config_epoch = failover.epoch;
+                let promoted = describe(master, &target.kind);
+                events.push(("+promoted-slave".into(), promoted.clone()));
+                events.push(("+failover-state-reconf-slaves".into(), promoted));
+                *dirty = true;
+            }
+            Some(failover)
+                if failover.state == FailoverState::ReconfReplicas
+                    && failover.reconf_sent.contains(address)
+                    && replicating == failover.promoted
+                    && master.replicas[address].master_link_up =>
+            {
+                if failover.reconf_done.insert(address.clone()) {
+                    events.push(("+slave-reconf-done".into(), describe(master, &target.kind)));
+                }
+            }
+            Some(_) => {}
+            //a replica of another master, or a master back after its failover, is given some
+            //time to learn about a newer configuration from the other sentinels first
+            None => {
+                let misconfigured =
+                    role == Some(Role::Master) || replicating.as_ref() != Some(&master_address);
+                let settled = role_reported.is_some_and(|time| time.elapsed() > HELLO_PERIOD * 4);
+                if misconfigured && settled && sdown.is_none() && master_sane {
+                    events.push(("+fix-slave-config".into(), describe(master, &target.kind)));
+                    let (ip, port) = (master.instance.ip.clone(), master.instance.port);
+                    let args = ["REPLICAOF", &ip, &port.to_string()];
+                    return self.send_sentinel_request(fd, &args, Request::ReplicaOf);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //a hello tells about another sentinel and its view of the master: a greater configuration
+    //epoch means a failover this sentinel did not lead
+    fn process_hello(&mut self, message: &str) {
+        let Some(state) = self.sentinel.as_mut() else {
+            return;
+        };
+        let SentinelState {
+            sentinel,
+            events,
+            dirty,
+            ..
+        } = state;
+        let Some(hello) = Hello::parse(message) else {
+            return;
+        };
+        if hello.run_id == sentinel.myid {
+            return;
+        }
+
+        if hello.current_epoch > sentinel.current_epoch {
+            sentinel.current_epoch = hello.current_epoch;
+            events.push(("+new-epoch".into(), hello.current_epoch.to_string()));
+            *dirty = true;
+        }
+        let Some(master) = sentinel.masters.get_mut(&hello.master_name) else {
+            return;
+        };
+
+        let kind = Kind::Sentinel(hello.run_id.clone());
+        match master.sentinels.get_mut(&hello.run_id) {
+            Some(other) => {
+                //the link to its old address is closed as stale
+                if other.ip != hello.ip || other.port != hello.port {
+                    other.ip = hello.ip.clone();
+                    other.port = hello.port;
+                    *dirty = true;
+                }
+                other.last_hello = Some(Instant::now());
+            }
+            None => {
+                //a sentinel restarted with another id
+                let restarted: Vec<String> = master
+                    .sentinels
+                    .iter()
+                    .filter(|(_, other)| other.ip == hello.ip && other.port == hello.port)
+                    .map(|(run_id, _)| run_id.clone())
+                    .collect();
+                for run_id in restarted {
+                    let old = describe(master, &Kind::Sentinel(run_id.clone()));
+                    events.push(("-dup-sentinel".into(), old));
+                    master.sentinels.remove(&run_id);
+                }
+
+                let mut other = Instance::new(hello.ip.clone(), hello.port);
+                other.run_id = Some(hello.run_id.clone());
+                other.last_hello = Some(Instant::now());
+                master.sentinels.insert(hello.run_id.clone(), other);
+                events.push(("+sentinel".into(), describe(master, &kind)));
+                *dirty = true;
+            }
+        }
+
+        if hello.master_config_epoch > master.config_epoch {
+            master.config_epoch = hello.master_config_epoch;
+            *dirty = true;
+            let (ip, port) = (&master.instance.ip, master.instance.port);
+            if *ip != hello.master_ip || port != hello.master_port {
+                let switch = format!(
+                    "{} {ip} {port} {} {}",
+                    master.name, hello.master_ip, hello.master_port
+                );
+                events.push(("+config-update-from".into(), describe(master, &kind)));
+                events.push(("+switch-master".into(), switch));
+                master.switch_to(hello.master_ip, hello.master_port);
+            }
+        }
+    }
+
+    fn send_sentinel_request(
+        &mut self,
+        fd: i32,
+        args: &[&str],
+        request: Request,
+    ) -> io::Result<()> {
+        let Some(state) = self.sentinel.as_mut() else {
+            return Ok(());
+        };
+        let Some(link) = state.links.get_mut(&fd) else {
+            return Ok(());
+        };
+
+        let data = RespType::Array {
+            elements: args.iter().map(|arg| bulk(arg)).collect(),
+        }
+        .serialize();
+        if let Err(err) = link.stream.write_all(&data) {
+            println!("Could not write to {}: {err}", link.address);
+            return self.close_sentinel_link(fd);
+        }
+
+        let now = Some(Instant::now());
+        match request {
+            Request::Ping => {
+                link.ping_sent = link.ping_sent.or(now);
+                link.last_ping = now;
+                if let Some(master) = state.sentinel.masters.get_mut(&link.target.master)
+                    && let Some(instance) = instance_mut(master, &link.target.kind)
+                {
+                    instance.ping_sent = instance.ping_sent.or(now);
+                }
+            }
+            Request::Info => link.last_info = now,
+            Request::Publish => link.last_hello = now,
+            Request::IsMasterDown => link.last_ask = now,
+            Request::ReplicaOf | Request::Subscribe => {}
+        }
+        link.pending.push_back(request);
+        Ok(())
+    }
+
+    fn close_sentinel_link(&mut self, fd: i32) -> io::Result<()> {
+        let Some(link) = self
+            .sentinel
+            .as_mut()
+            .and_then(|state| state.links.remove(&fd))
+        else {
+            return Ok(());
+        };
+        self.poller.remove_socket(&link.stream)
+    }
+
+    //the link for the commands to the instance
+    fn sentinel_link(&self, master: &str, kind: &Kind) -> Option<i32> {
+        self.sentinel.as_ref().and_then(|state| {
+            state
+                .links
+                .iter()
+                .find(|(_, link)| {
+                    !link.target.pubsub && link.target.master == master && link.target.kind == *kind
+                })
+                .map(|(fd, _)| *fd)
+        })
+    }
+
+    fn connect_to_instance(&mut self, target: Target, ip: &str, port: u16) -> io::Result<()> {
+        let Some(state) = self.sentinel.as_mut() else {
+            return Ok(());
+        };
+        state.last_connect.insert(target.clone(), Instant::now());
+
+        let Ok(address) = ip.parse::<IpAddr>() else {
+            return Ok(());
+        };
+        let stream = match TcpStream::connect_timeout(&(address, port).into(), CONNECT_RETRY_DELAY)
+        {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Could not connect to {ip}:{port}: {err}");
+                return Ok(());
+            }
+        };
+
+        self.poller.watch_socket(&stream)?;
+        let fd = stream.as_raw_fd();
+        let pubsub = target.pubsub;
+        state.links.insert(
+            fd,
+            Link {
+                stream,
+                input: vec![],
+                target,
+                address: format!("{ip}:{port}"),
+                pending: VecDeque::new(),
+                ping_sent: None,
+                last_ping: None,
+                last_info: None,
+                last_hello: None,
+                last_ask: None,
+            },
+        );
+
+        if pubsub {
+            self.send_sentinel_request(fd, &["SUBSCRIBE", HELLO_CHANNEL], Request::Subscribe)?;
+        }
+        Ok(())
+    }
+
+    /// Called from every iteration of the event loop: keeps the links to the instances open,
+    /// sends them the periodic commands, detects the ones down and moves the failovers along.
+    pub(super) fn sentinel_cron(&mut self) -> io::Result<()> {
+        let Some(state) = self.sentinel.as_ref() else {
+            return Ok(());
+        };
+
+        //the instance moved or is no longer monitored
+        let stale: Vec<i32> = state
+            .links
+            .iter()
+            .filter(|(_, link)| {
+                let master = state.sentinel.masters.get(&link.target.master);
+                let instance = master.and_then(|master| instance(master, &link.target.kind));
+                instance.map(Instance::address).as_ref() != Some(&link.address)
+            })
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in stale {
+            self.close_sentinel_link(fd)?;
+        }
+
+        self.connect_to_instances()?;
+        self.send_periodic_requests()?;
+        self.check_instances_down();
+        self.ask_other_sentinels()?;
+
+        let names: Vec<String> = self
+            .sentinel
+            .iter()
+            .flat_map(|state| state.sentinel.masters.keys().cloned())
+            .collect();
+        for name in names {
+            self.failover_step(&name)?;
+        }
+
+        let Some(state) = self.sentinel.as_mut() else {
+            return Ok(());
+        };
+        let events = std::mem::take(&mut state.events);
+        if state.dirty {
+            self.save_sentinel_config();
+        }
+        for (channel, message) in events {
+            println!("{channel} {message}");
+            self.publish(&channel, &message);
+        }
+        Ok(())
+    }
+
+    //two links to each master and replica, one to each of the other sentinels
+    fn connect_to_instances(&mut self) -> io::Result<()> {
+        let Some(state) = self.sentinel.as_ref() else {
+            return Ok(());
+        };
+
+        let linked: HashSet<&Target> = state.links.values().map(|link| &link.target).collect();
+        let mut unlinked = vec![];
+        for master in state.sentinel.masters.values() {
+            let mut instances = vec![(Kind::Master, &master.instance, true)];
+            for (address, replica) in &master.replicas {
+                instances.push((Kind::Replica(address.clone()), replica, true));
+            }
+            for (run_id, other) in &master.sentinels {
+                instances.push((Kind::Sentinel(run_id.clone()), other, false));
+            }
+
+            for (kind, instance, subscribed) in instances {
+                for pubsub in [false, true] {
+                    if pubsub && !subscribed {
+                        continue;
+                    }
+                    let target = Target {
+                        master: master.name.clone(),
+                        kind: kind.clone(),
+                        pubsub,
+                    };
+                    let retry = state
+                        .last_connect
+                        .get(&target)
+                        .is_none_or(|last| last.elapsed() >= CONNECT_RETRY_DELAY);
+                    if retry && !linked.contains(&target) {
+                        unlinked.push((target, instance.ip.clone(), instance.port));
+                    }
+                }
+            }
+        }
+
+        for (target, ip, port) in unlinked {
+            self.connect_to_instance(target, &ip, port)?;
+        }
+        Ok(())
+    }
+
+    //PING every second, INFO to the masters and replicas and the hellos, over the links for
+    //the commands
+    fn send_periodic_requests(&mut self) -> io::Result<()> {
+        let Some(state) = self.sentinel.as_ref() else {
+            return Ok(());
+        };
+        let port = self.config.port();
+
+        let mut broken = vec![];
+        let mut requests = vec![];
+        for (fd, link) in &state.links {
+            let Some(master) = state.sentinel.masters.get(&link.target.master) else {
+                continue;
+            };
+            if link.target.pubsub {
+                continue;
+            }
+            //unanswered for that long, the link itself may be broken rather than the instance
+            if link
+                .ping_sent
+                .is_some_and(|sent| sent.elapsed() > master.down_after / 2)
+            {
+                broken.push(*fd);
+                continue;
+            }
+
+            let ping_period = PING_PERIOD.min(master.down_after);
+            if link.ping_sent.is_none()
+                && link
+                    .last_ping
+                    .is_none_or(|last| last.elapsed() >= ping_period)
+            {
+                requests.push((*fd, vec!["PING".to_string()], Request::Ping));
+            }
+
+            let info_period = match master.odown_since.is_some() || master.failover.is_some() {
+                true => PING_PERIOD,
+                false => INFO_PERIOD,
+            };
+            if !matches!(link.target.kind, Kind::Sentinel(_))
+                && link
+                    .last_info
+                    .is_none_or(|last| last.elapsed() >= info_period)
+            {
+                requests.push((*fd, vec!["INFO".to_string()], Request::Info));
+            }
+
+            //the others reach this sentinel at the address it is connected from
+            if link
+                .last_hello
+                .is_none_or(|last| last.elapsed() >= HELLO_PERIOD)
+                && let Ok(local) = link.stream.local_addr()
+            {
+                let hello = state.sentinel.hello(master, &local.ip().to_string(), port);
+                let args = vec!["PUBLISH".to_string(), HELLO_CHANNEL.to_string(), hello];
+                requests.push((*fd, args, Request::Publish));
+            }
+        }
+
+        for fd in broken {
+            println!("No reply to the ping, reconnecting");
+            self.close_sentinel_link(fd)?;
+        }
+        for (fd, args, request) in requests {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            self.send_sentinel_request(fd, &args, request)?;
+        }
+        Ok(())
+    }
+
+    //an instance that didn't answer the pings for long enough is subjectively down, the master
+    //is objectively down once a quorum of the sentinels agree. The wait counts from the oldest
+    //ping still unanswered, or from the last reply while there is no link to send one
+    fn check_instances_down(&mut self) {
+        let Some(state) = self.sentinel.as_mut() else {
+            return;
+        };
+        let SentinelState {
+            sentinel,
+            events,
+            links,
+            ..
+        } = state;
+        let now = Instant::now();
+        let connected: HashSet<&Target> = links
+            .values()
+            .filter(|link| !link.target.pubsub)
+            .map(|link| &link.target)
+            .collect();
+
+        for master in sentinel.masters.values_mut() {
+            let mut kinds = vec![Kind::Master];
+            kinds.extend(master.replicas.keys().cloned().map(Kind::Replica));
+            kinds.extend(master.sentinels.keys().cloned().map(Kind::Sentinel));
+            for kind in kinds {
+                let down_after = master.down_after;
+                let target = Target {
+                    master: master.name.clone(),
+                    kind: kind.clone(),
+                    pubsub: false,
+                };
+                let Some(instance) = instance_mut(master, &kind) else {
+                    continue;
+                };
+                let waited = match instance.ping_sent {
+                    Some(sent) => sent.elapsed(),
+                    None if !connected.contains(&target) => instance.last_avail.elapsed(),
+                    None => Duration::ZERO,
+                };
+                let down = waited > down_after;
+                let event = match (down, instance.sdown_since) {
+                    (true, None) => {
+                        instance.sdown_since = Some(now);
+                        "+sdown"
+                    }
+                    (false, Some(_)) => {
+                        instance.sdown_since = None;
+                        "-sdown"
+                    }
+                    _ => continue,
+                };
+                events.push((event.into(), describe(master, &kind)));
+            }
+
+            let agreeing = match master.instance.sdown_since {
+                Some(_) => 1 + master.down_reports(ASK_PERIOD * 5),
+                None => {
+                    for other in master.sentinels.values_mut() {
+                        other.master_down = false;
+                    }
+                    0
+                }
+            };
+            match (agreeing >= master.quorum, master.odown_since) {
+                (true, None) => {
+                    master.odown_since = Some(now);
+                    let message = format!(
+                        "{} #quorum {agreeing}/{}",
+                        describe(master, &Kind::Master),
+                        master.quorum
+                    );
+                    events.push(("+odown".into(), message));
+                }
+                (false, Some(_)) => {
+                    master.odown_since = None;
+                    events.push(("-odown".into(), describe(master, &Kind::Master)));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    //while the master is down, every second, along with a vote request during a failover
+    fn ask_other_sentinels(&mut self) -> io::Result<()> {
+        let Some(state) = self.sentinel.as_ref() else {
+            return Ok(());
+        };
+        let sentinel = &state.sentinel;
+
+        let mut requests = vec![];
+        for (fd, link) in &state.links {
+            let Kind::Sentinel(_) = &link.target.kind else {
+                continue;
+            };
+            let Some(master) = sentinel.masters.get(&link.target.master) else {
+                continue;
+            };
+            if master.instance.sdown_since.is_none()
+                || link
+                    .last_ask
+                    .is_some_and(|last| last.elapsed() < ASK_PERIOD)
+                || link.pending.contains(&Request::IsMasterDown)
+            {
+                continue;
+            }
+
+            let run_id = match master.failover {
+                Some(_) => sentinel.myid.clone(),
+                None => "*".to_string(),
+            };
+            let args = vec![
+                "SENTINEL".to_string(),
+                "IS-MASTER-DOWN-BY-ADDR".to_string(),
+                master.instance.ip.clone(),
+                master.instance.port.to_string(),
+                sentinel.current_epoch.to_string(),
+                run_id,
+            ];
+            requests.push((*fd, args));
+        }
+
+        for (fd, args) in requests {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            self.send_sentinel_request(fd, &args, Request::IsMasterDown)?;
+        }
+        Ok(())
+    }
+
+    //starts a failover of the master objectively down, or moves the one in progress along
+    fn failover_step(&mut self, name: &str) -> io::Result<()> {
+        let Some(state) = self.sentinel.as_mut() else {
+            return Ok(());
+        };
+        let SentinelState {
+            sentinel,
+            links,
+            events,
+            dirty,
+            ..
+        } = state;
+        let myid = sentinel.myid.clone();
+        let Some(master) = sentinel.masters.get_mut(name) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+
+        let Some(failover) = master.failover.as_ref() else {
+            //the sentinels find the master down at about the same time, each waits a random part
+            //of a second for one of them to ask for the votes before the others
+            let desync = Duration::from_millis(random(MAX_DESYNC));
+            let start = master
+                .odown_since
+                .is_some_and(|since| since.elapsed() >= desync)
+                && master
+                    .failover_start
+                    .is_none_or(|start| start.elapsed() > master.failover_timeout * 2);
+            if start {
+                sentinel.current_epoch += 1;
+                let epoch = sentinel.current_epoch;
+                master.failover = Some(Failover::new(epoch, false));
+                master.failover_start = Some(now + desync);
+                //a sentinel votes for itself and asks the others for their votes right away
+                master.leader = Some(myid);
+                master.leader_epoch = epoch;
+                for link in links.values_mut() {
+                    if link.target.master == name && matches!(link.target.kind, Kind::Sentinel(_)) {
+                        link.last_ask = None;
+                    }
+                }
+                events.push(("+new-epoch".into(), epoch.to_string()));
+                events.push(("+try-failover".into(), describe(master, &Kind::Master)));
+                *dirty = true;
+            }
+            return Ok(());
+        };
+        let (epoch, forced, state_time) = (failover.epoch, failover.forced, failover.state_time);
+        let promoted = failover.promoted.clone();
+        let abort = |master: &mut Master, events: &mut Vec<(String, String)>, reason: &str| {
+            events.push((reason.into(), describe(master, &Kind::Master)));
+            master.failover = None;
+        };
+
+        let mut requests = vec![];
+        match failover.state {
+            FailoverState::WaitStart => {
+                if !forced && !master.elected(&myid, epoch) {
+                    if state_time.elapsed() > ELECTION_TIMEOUT.min(master.failover_timeout) {
+                        abort(master, events, "-failover-abort-not-elected");
+                    }
+                    return Ok(());
+                }
+                events.push(("+elected-leader".into(), describe(master, &Kind::Master)));
+
+                let Some(address) = master.select_replica(info_validity(master)) else {
+                    abort(master, events, "-failover-abort-no-good-slave");
+                    return Ok(());
+                };
+                let kind = Kind::Replica(address.clone());
+                events.push(("+selected-slave".into(), describe(master, &kind)));
+                events.push((
+                    "+failover-state-send-slaveof-noone".into(),
+                    describe(master, &kind),
+                ));
+                if let Some(failover) = master.failover.as_mut() {
+                    failover.state = FailoverState::WaitPromotion;
+                    failover.state_time = now;
+                    failover.promoted = Some(address);
+                }
+                requests.push((kind, vec!["REPLICAOF".into(), "NO".into(), "ONE".into()]));
+            }
+            FailoverState::WaitPromotion => {
+                if state_time.elapsed() > master.failover_timeout {
+                    abort(master, events, "-failover-abort-slave-timeout");
+                }
+                return Ok(());
+            }
+            FailoverState::ReconfReplicas => {
+                let Some((ip, port)) = promoted
+                    .as_ref()
+                    .and_then(|address| master.replicas.get(address))
+                    .map(|replica| (replica.ip.clone(), replica.port))
+                else {
+                    abort(master, events, "-failover-abort-slave-timeout");
+                    return Ok(());
+                };
+                let others: Vec<String> = master
+                    .replicas
+                    .iter()
+                    .filter(|(address, replica)| {
+                        Some(*address) != promoted.as_ref() && replica.sdown_since.is_none()
+                    })
+                    .map(|(address, _)| address.clone())
+                    .collect();
+                let Some(failover) = master.failover.as_mut() else {
+                    return Ok(());
+                };
+
+                //the replicas down are reconfigured once they are back
+                let done = others
+                    .iter()
+                    .all(|address| failover.reconf_done.contains(address));
+                if done || state_time.elapsed() > master.failover_timeout {
+                    if !done {
+                        let timeout = describe(master, &Kind::Master);
+                        events.push(("+failover-end-for-timeout".into(), timeout));
+                    }
+                    events.push(("+failover-end".into(), describe(master, &Kind::Master)));
+                    let switch = format!(
+                        "{} {} {} {ip} {port}",
+                        master.name, master.instance.ip, master.instance.port
+                    );
+                    events.push(("+switch-master".into(), switch));
+                    master.switch_to(ip, port);
+                    *dirty = true;
+                    return Ok(());
+                }
+
+                let unsent: Vec<String> = others
+                    .into_iter()
+                    .filter(|address| failover.reconf_sent.insert(address.clone()))
+                    .collect();
+                for address in unsent {
+                    let kind = Kind::Replica(address);
+                    events.push(("+slave-reconf-sent".into(), describe(master, &kind)));
+                    requests.push((kind, vec!["REPLICAOF".into(), ip.clone(), port.to_string()]));
+                }
+            }
+        }
+
+        for (kind, args) in requests {
+            //without a link the failover times out
+            if let Some(fd) = self.sentinel_link(name, &kind) {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                self.send_sentinel_request(fd, &args, Request::ReplicaOf)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn instance<'a>(master: &'a Master, kind: &Kind) -> Option<&'a Instance> {
+    match kind {
+        Kind::Master => Some(&master.instance),
+        Kind::Replica(address) => master.replicas.get(address),
+        Kind::Sentinel(run_id) => master.sentinels.get(run_id),
+    }
+}
+
+fn instance_mut<'a>(master: &'a mut Master, kind: &Kind) -> Option<&'a mut Instance> {
+    match kind {
+        Kind::Master => Some(&mut master.instance),
+        Kind::Replica(address) => master.replicas.get_mut(address),
+        Kind::Sentinel(run_id) => master.sentinels.get_mut(run_id),
+    }
+}
+
+//the instance in the events, like master mymaster 127.0.0.1 6379, or for the others
+//slave 127.0.0.1:6380 127.0.0.1 6380 @ mymaster 127.0.0.1 6379
+fn describe(master: &Master, kind: &Kind) -> String {
+    let (ip, port) = (&master.instance.ip, master.instance.port);
+    let at = format!("@ {} {ip} {port}", master.name);
+    let name = match kind {
+        Kind::Master => return format!("master {} {ip} {port}", master.name),
+        Kind::Replica(address) => format!("slave {address}"),
+        Kind::Sentinel(run_id) => format!("sentinel {run_id}"),
+    };
+    match instance(master, kind) {
+        Some(instance) => format!("{name} {} {} {at}", instance.ip, instance.port),
+        None => format!("{name} {at}"),
+    }
+}
+
+//how recently INFO must have reported about a replica for it to be promoted
+fn info_validity(master: &Master) -> Duration {
+    match master.instance.sdown_since {
+        Some(_) => PING_PERIOD * 5,
+        None => INFO_PERIOD * 3,
+    }
+}
+
+fn master_fields(master: &Master) -> RespType {
+    let mut flags = "master".to_string();
+    if master.odown_since.is_some() {
+        flags.push_str(",o_down");
+    }
+    if master.failover.is_some() {
+        flags.push_str(",failover_in_progress");
+    }
+
+    let mut fields = instance_fields(&master.name, &master.instance, &flags);
+    fields.extend([
+        ("num-slaves", master.replicas.len().to_string()),
+        ("num-other-sentinels", master.sentinels.len().to_string()),
+        ("quorum", master.quorum.to_string()),
+        (
+            "down-after-milliseconds",
+            master.down_after.as_millis().to_string(),
+        ),
+        (
+            "failover-timeout",
+            master.failover_timeout.as_millis().to_string(),
+        ),
+        ("config-epoch", master.config_epoch.to_string()),
+    ]);
+    if let Some(failover) = &master.failover {
+        fields.push(("failover-state", failover.state.name().to_string()));
+    }
+    fields_map(fields)
+}
+
+fn instance_fields(name: &str, instance: &Instance, flags: &str) -> Vec<(&'static str, String)> {
+    let mut flags = flags.to_string();
+    if instance.sdown_since.is_some() {
+        flags.push_str(",s_down");
+    }
+    vec![
+        ("name", name.to_string()),
+        ("ip", instance.ip.clone()),
+        ("port", instance.port.to_string()),
+        ("runid", instance.run_id.clone().unwrap_or_default()),
+        ("flags", flags),
+        (
+            "last-ok-ping-reply",
+            instance.last_avail.elapsed().as_millis().to_string(),
+        ),
+        ("info-refresh", elapsed_ms(instance.info_refresh)),
+    ]
+}
+
+fn fields_map(fields: Vec<(&str, String)>) -> RespType {
+    RespType::Map {
+        entries: fields
+            .into_iter()
+            .map(|(field, value)| (bulk(field), bulk(&value)))
+            .collect(),
+    }
+}
+
+//milliseconds since then, 0 for never
+fn elapsed_ms(time: Option<Instant>) -> String {
+    time.map_or(0, |time| time.elapsed().as_millis())
+        .to_string()
+}
+
+fn bulk(s: &str) -> RespType {
+    RespType::BulkString {
+        data: s.as_bytes().to_vec(),
+    }
+}
+
+fn ok() -> RespType {
+    RespType::SimpleString {
+        content: "OK".into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        ev_loop::{
+            EventLoop,
+            test_util::{event_loop, request},
+        },
+        resp::RespType,
+    };
+
+    //runs the sentinel, and the master while there is one, until the condition holds
+    fn run_until(
+        sentinel: &mut EventLoop,
+        mut master: Option<&mut EventLoop>,
+        done: impl Fn(&EventLoop) -> bool,
+    ) {
+        let started = Instant::now();
+        while !done(sentinel) && started.elapsed() < Duration::from_secs(5) {
+            sentinel.sentinel_cron().unwrap();
+            sentinel.process_events(10).unwrap();
+            if let Some(master) = master.as_deref_mut() {
+                master.process_events(10).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_monitor_and_odown() {
+        let mut master = event_loop(&[]);
+        let port = master.config.port().to_string();
+        let mut sentinel = event_loop(&[
+            "--sentinel",
+            "--sentinel",
+            "monitor",
+            "m",
+            "127.0.0.1",
+            &port,
+            "1",
+            "--sentinel",
+            "down-after-milliseconds",
+            "m",
+            "300",
+        ]);
+
+        //the run id comes with the first INFO
+        let run_id = master.stats.run_id.clone();
+        let monitored = |sentinel: &EventLoop| {
+            let state = sentinel.sentinel.as_ref().unwrap();
+            state.sentinel.masters["m"].instance.run_id.as_ref() == Some(&run_id)
+        };
+        run_until(&mut sentinel, Some(&mut master), monitored);
+        assert!(monitored(&sentinel));
+
+        assert_eq!(
+            sentinel.execute(1, request(&["SENTINEL", "GET-MASTER-ADDR-BY-NAME", "m"])),
+            Some(RespType::Array {
+                elements: vec![
+                    RespType::BulkString {
+                        data: b"127.0.0.1".to_vec()
+                    },
+                    RespType::BulkString {
+                        data: port.as_bytes().to_vec()
+                    },
+                ]
+            })
+        );
+        assert_eq!(
+            sentinel.execute(1, request(&["SENTINEL", "FAILOVER", "m"])),
+            Some(RespType::SimpleError {
+                content: "NOGOODSLAVE No suitable replica to promote".into()
+            })
+        );
+
+        //only the sentinel commands are served in sentinel mode, and only there
+        assert_eq!(
+            sentinel.check_sentinel(&request(&["GET", "foo"]), Some("get")),
+            Err(RespType::SimpleError {
+                content: "ERR unknown command 'get'".into()
+            })
+        );
+        assert_eq!(
+            master.check_sentinel(&request(&["SENTINEL", "MYID"]), Some("sentinel")),
+            Err(RespType::SimpleError {
+                content: "ERR unknown command 'sentinel'".into()
+            })
+        );
+
+        //alone with a quorum of 1, the sentinel finds the master objectively down by itself
+        drop(master);
+        let odown = |sentinel: &EventLoop| {
+            let state = sentinel.sentinel.as_ref().unwrap();
+            state.sentinel.masters["m"].odown_since.is_some()
+        };
+        run_until(&mut sentinel, None, odown);
+        assert!(odown(&sentinel));
+    }
+
+    #[test]
+    fn test_answering_master_never_sdown() {
+        let mut master = event_loop(&[]);
+        let port = master.config.port().to_string();
+        //down after as long as the ping period, the next ping only leaves a period after the last
+        let mut sentinel = event_loop(&[
+            "--sentinel",
+            "--sentinel",
+            "monitor",
+            "m",
+            "127.0.0.1",
+            &port,
+            "1",
+            "--sentinel",
+            "down-after-milliseconds",
+            "m",
+            "100",
+        ]);
+
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(1500) {
+            sentinel.sentinel_cron().unwrap();
+            sentinel.process_events(10).unwrap();
+            master.process_events(10).unwrap();
+
+            let state = sentinel.sentinel.as_ref().unwrap();
+            let instance = &state.sentinel.masters["m"].instance;
+            assert!(instance.sdown_since.is_none());
+        }
+        let state = sentinel.sentinel.as_ref().unwrap();
+        assert!(state.sentinel.masters["m"].instance.run_id.is_some());
+    }
+}
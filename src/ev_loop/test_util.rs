@@ -0,0 +1,89 @@
+//! Fixtures shared by the tests of the event loop.
+
+use std::{
+    io::{Read as _, Write as _},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    command::Command,
+    config::Config,
+    ev_loop::{EventLoop, client::Client},
+    poll::Poller,
+    resp::RespType,
+};
+
+/// An event loop configured as by the given command line arguments, listening on a port of its
+/// own, with client 1 connected as the default user. Its events can be served as by the event
+/// loop, i.e. for a server to talk to another one.
+pub(super) fn event_loop(args: &[&str]) -> EventLoop {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let port = listener.local_addr().unwrap().port().to_string();
+    let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    args.extend(["--port".to_string(), port]);
+
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let poller = Poller::new(&listener).unwrap();
+    let mut event_loop = EventLoop::new(listener, poller);
+    event_loop
+        .configure(Config::from_args(&args).unwrap())
+        .unwrap();
+    let user = event_loop.initial_user();
+    event_loop.clients.insert(1, Client::new(stream, user));
+    event_loop
+}
+
+/// A command as a client sends it.
+pub(super) fn request(args: &[&str]) -> Command {
+    Command::from(RespType::Array {
+        elements: args
+            .iter()
+            .map(|arg| RespType::BulkString {
+                data: arg.as_bytes().to_vec(),
+            })
+            .collect(),
+    })
+}
+
+/// Serves the events and the blocked clients for a while, then returns what the client got.
+pub(super) fn replies(server: &mut EventLoop, client: &mut TcpStream) -> String {
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_millis(200) {
+        server.serve_unblocked();
+        server.process_events(10).unwrap();
+    }
+
+    let mut received = vec![];
+    let mut buf = [0u8; 1024];
+    while let Ok(read) = client.read(&mut buf)
+        && read > 0
+    {
+        received.extend_from_slice(&buf[..read]);
+    }
+    String::from_utf8(received).unwrap()
+}
+
+/// A client connected to the server, reading with a short timeout.
+pub(super) fn client(server: &EventLoop) -> TcpStream {
+    let client = TcpStream::connect(("127.0.0.1", server.config.port())).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_millis(50)))
+        .unwrap();
+    client
+}
+
+/// Sends the request, its arguments split on spaces, and returns what the client got.
+pub(super) fn send(server: &mut EventLoop, client: &mut TcpStream, request: &str) -> String {
+    let request = RespType::Array {
+        elements: request
+            .split(' ')
+            .map(|arg| RespType::BulkString {
+                data: arg.as_bytes().to_vec(),
+            })
+            .collect(),
+    };
+    client.write_all(&request.serialize()).unwrap();
+    replies(server, client)
+}
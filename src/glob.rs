@@ -0,0 +1,127 @@
+/// Glob-style matching with the same rules used by redis for PSUBSCRIBE and KEYS:
+/// `*` matches any sequence, `?` any single byte, `[...]` a set of bytes (supporting `^`
+/// negation and `a-z` ranges) and `\` escapes the following byte.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                //collapse consecutive stars, a trailing one matches everything left
+                while pattern.get(p + 1) == Some(&b'*') {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+
+                return (s..=string.len())
+                    .any(|from| glob_match(&pattern[p + 1..], &string[from..]));
+            }
+            b'?' => {
+                if s == string.len() {
+                    return false;
+                }
+                s += 1;
+            }
+            b'[' => {
+                let Some(&c) = string.get(s) else {
+                    return false;
+                };
+
+                p += 1;
+                let negate = pattern.get(p) == Some(&b'^');
+                if negate {
+                    p += 1;
+                }
+
+                let mut matched = false;
+                loop {
+                    match pattern.get(p) {
+                        //an unterminated set is closed by the end of the pattern
+                        None => {
+                            p -= 1;
+                            break;
+                        }
+                        Some(b']') => break,
+                        Some(b'\\') if p + 1 < pattern.len() => {
+                            p += 1;
+                            matched |= pattern[p] == c;
+                        }
+                        Some(&start)
+                            if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() =>
+                        {
+                            let end = pattern[p + 2];
+                            let (low, high) = if start <= end {
+                                (start, end)
+                            } else {
+                                (end, start)
+                            };
+                            matched |= (low..=high).contains(&c);
+                            p += 2;
+                        }
+                        Some(&other) => matched |= other == c,
+                    }
+                    p += 1;
+                }
+
+                if matched == negate {
+                    return false;
+                }
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if string.get(s) != Some(&pattern[p]) {
+                    return false;
+                }
+                s += 1;
+            }
+            other => {
+                if string.get(s) != Some(&other) {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+
+        p += 1;
+    }
+
+    s == string.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::glob_match;
+
+    #[test]
+    fn test_glob_match() {
+        let cases: [(&str, &str, bool); 16] = [
+            ("*", "", true),
+            ("*", "news.it", true),
+            ("news.*", "news.it", true),
+            ("news.*", "new.it", false),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "heeeello", true),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[b-a]llo", "hallo", true),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("a*b*c", "axxbyyc", true),
+        ];
+
+        for (pattern, string, expected) in cases {
+            assert_eq!(
+                glob_match(pattern.as_bytes(), string.as_bytes()),
+                expected,
+                "{pattern} against {string}"
+            );
+        }
+    }
+}
@@ -1,27 +1,128 @@
-use std::
-    net::TcpListener
-;
+use std::{io, net::TcpListener};
 
+mod acl;
+mod alloc;
+mod cluster;
+mod command;
+mod config;
 mod ev_loop;
+mod glob;
 mod poll;
-mod resp;
-mod command;
+mod rdb;
 mod redis;
+mod resp;
+mod sentinel;
 
-use crate::{ev_loop::EventLoop, poll::Poller};
+use crate::{config::Config, ev_loop::EventLoop, poll::Poller};
 
 fn main() -> std::io::Result<()> {
-    const PORT: u32 = 6379;
+    let args: Vec<String> = std::env::args().skip(1).collect();
 
-    let listener = TcpListener::bind(format!("127.0.0.1:{PORT}").as_str())
-        .unwrap_or_else(|_| panic!("Error while binding to port {PORT}"));
+    //[<config file>] [--<name> <value> ...]
+    let mut config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("\n*** FATAL CONFIG FILE ERROR ***\n{err}");
+            std::process::exit(1);
+        }
+    };
 
-    listener.set_nonblocking(true).inspect_err(|_| {
-        eprintln!("Could not set listener to non blocking mode");
+    //files are written to the configured directory, kept absolute as the working directory
+    std::env::set_current_dir(config.dir()).inspect_err(|err| {
+        eprintln!("Can't chdir to '{}': {err}", config.dir().display());
     })?;
+    let dir = std::env::current_dir()?.display().to_string();
+    config.set("dir", &[dir]).map_err(io::Error::other)?;
+
+    let mut listeners = listen(config.bind(), config.port())?.into_iter();
+    let listener = listeners.next().expect("at least a listener");
 
     let poller = Poller::new(&listener)?;
     let mut looper = EventLoop::new(listener, poller);
+    for listener in listeners {
+        looper.add_listener(listener)?;
+    }
 
+    //a sentinel has no dataset
+    let sentinel_mode = config.sentinel_mode();
+    looper.configure(config)?;
+    if !sentinel_mode {
+        looper.load()?;
+    }
     looper.run()
 }
+
+//a listener per address, the ones starting with - are skipped when unavailable
+fn listen(addresses: &[String], port: u16) -> io::Result<Vec<TcpListener>> {
+    let mut listeners = vec![];
+    for address in addresses {
+        let (address, optional) = match address.strip_prefix('-') {
+            Some(address) => (address, true),
+            None => (address.as_str(), false),
+        };
+
+        match bind(address, port) {
+            Ok(listener) => listeners.push(listener),
+            Err(err) if optional => eprintln!("Skipping address {address}, got error {err}"),
+            Err(err) => {
+                eprintln!("Error while binding to {address}:{port}");
+                return Err(err);
+            }
+        }
+    }
+
+    if listeners.is_empty() {
+        return Err(io::Error::other("None of the bind addresses is available"));
+    }
+    Ok(listeners)
+}
+
+fn bind(address: &str, port: u16) -> io::Result<TcpListener> {
+    //IPv6 addresses have to be bracketed to be followed by the port
+    let listener = if address.contains(':') {
+        TcpListener::bind(format!("[{address}]:{port}"))?
+    } else {
+        TcpListener::bind(format!("{address}:{port}"))?
+    };
+
+    listener.set_nonblocking(true).inspect_err(|_| {
+        eprintln!("Could not set listener to non blocking mode");
+    })?;
+
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use super::listen;
+
+    fn addresses(addresses: &[&str]) -> Vec<String> {
+        addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_listen() {
+        //the IPv6 loopback may be missing, it is optional as by default
+        let listeners = listen(&addresses(&["127.0.0.1", "-::1"]), 0).unwrap();
+        assert!(!listeners.is_empty() && listeners.len() <= 2);
+        assert!(listeners[0].local_addr().unwrap().ip().is_loopback());
+
+        //an address of another host can't be bound
+        let err = listen(&addresses(&["192.0.2.1"]), 0).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrNotAvailable);
+        let err = listen(&addresses(&["-192.0.2.1"]), 0).unwrap_err();
+        assert_eq!(err.to_string(), "None of the bind addresses is available");
+        assert!(listen(&addresses(&["-192.0.2.1", "127.0.0.1"]), 0).is_ok());
+
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let err = listen(&addresses(&["127.0.0.1"]), port).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+        assert!(listen(&addresses(&["not an address"]), 0).is_err());
+    }
+}
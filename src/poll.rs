@@ -1,6 +1,6 @@
 use std::{
-    io,
     collections::HashSet,
+    io,
     net::{TcpListener, TcpStream},
     os::fd::AsRawFd,
 };
@@ -18,8 +18,6 @@ impl Poller {
 
     //SAFETY if any syscall fails we return with the os error
     pub fn new(listener: &TcpListener) -> std::io::Result<Self> {
-        let listener_fd = listener.as_raw_fd();
-
         let epoll_fd = unsafe {
             let epfd = epoll_create1(Self::EPOLL_FLAGS_IGNORED);
             if epfd < 0 {
@@ -27,23 +25,39 @@ impl Poller {
                 return Err(std::io::Error::last_os_error());
             }
 
-            let mut event = epoll_event {
-                events: EPOLLIN as u32,
-                u64: listener_fd as u64,
-            };
+            epfd
+        };
+
+        let mut poller = Self {
+            epoll_fd,
+            watched: HashSet::new(),
+        };
+        poller.watch_listener(listener)?;
+
+        Ok(poller)
+    }
+
+    //listeners are only polled for incoming connections
+    pub fn watch_listener(&mut self, listener: &TcpListener) -> io::Result<()> {
+        let listener_fd = listener.as_raw_fd();
+        if !self.watched.insert(listener_fd) {
+            return Err(io::Error::other("TcpListener is already being watched"));
+        }
+
+        let mut event = epoll_event {
+            events: EPOLLIN as u32,
+            u64: listener_fd as u64,
+        };
 
-            let res = epoll_ctl(epfd, EPOLL_CTL_ADD, listener_fd, &mut event);
+        unsafe {
+            let res = epoll_ctl(self.epoll_fd, EPOLL_CTL_ADD, listener_fd, &mut event);
             if res == -1 {
                 eprintln!("Error while registering listener file descriptor for polling");
                 return Err(std::io::Error::last_os_error());
             }
+        }
 
-            epfd
-        };
-        let mut watched = HashSet::new();
-        watched.insert(listener_fd);
-
-        Ok(Self { epoll_fd, watched })
+        Ok(())
     }
 
     pub fn watch_socket(&mut self, to_watch: &TcpStream) -> io::Result<()> {
@@ -86,12 +100,7 @@ impl Poller {
         };
 
         unsafe {
-            let res = epoll_ctl(
-                self.epoll_fd,
-                EPOLL_CTL_DEL,
-                to_remove_fd,
-                &mut ignored,
-            );
+            let res = epoll_ctl(self.epoll_fd, EPOLL_CTL_DEL, to_remove_fd, &mut ignored);
             if res < 0 {
                 let err = io::Error::last_os_error();
                 return Err(err);
@@ -0,0 +1,368 @@
+use std::io;
+
+mod listpack;
+mod lzf;
+
+pub use listpack::{Listpack, intset_entries, listpack_entries, ziplist_entries};
+
+/// Version of the RDB format written by this server, payloads of newer versions are refused.
+pub const RDB_VERSION: u16 = 12;
+
+/// Files start with the magic string followed by the version on 4 ASCII digits.
+pub const RDB_MAGIC: &[u8] = b"REDIS";
+
+//opcodes, they share the byte that otherwise holds the type of the next value
+pub const OPCODE_SLOT_INFO: u8 = 244;
+/// Opcode preceding the code of a function library.
+pub const OPCODE_FUNCTION2: u8 = 245;
+pub const OPCODE_MODULE_AUX: u8 = 247;
+pub const OPCODE_IDLE: u8 = 248;
+pub const OPCODE_FREQ: u8 = 249;
+pub const OPCODE_AUX: u8 = 250;
+pub const OPCODE_RESIZEDB: u8 = 251;
+pub const OPCODE_EXPIRETIME_MS: u8 = 252;
+pub const OPCODE_EXPIRETIME: u8 = 253;
+pub const OPCODE_SELECTDB: u8 = 254;
+pub const OPCODE_EOF: u8 = 255;
+
+//value types, the ones after TYPE_ZSET_2 are compact encodings of the same data types
+pub const TYPE_STRING: u8 = 0;
+pub const TYPE_LIST: u8 = 1;
+pub const TYPE_SET: u8 = 2;
+pub const TYPE_ZSET: u8 = 3;
+pub const TYPE_HASH: u8 = 4;
+pub const TYPE_ZSET_2: u8 = 5;
+pub const TYPE_LIST_ZIPLIST: u8 = 10;
+pub const TYPE_SET_INTSET: u8 = 11;
+pub const TYPE_ZSET_ZIPLIST: u8 = 12;
+pub const TYPE_HASH_ZIPLIST: u8 = 13;
+pub const TYPE_LIST_QUICKLIST: u8 = 14;
+pub const TYPE_STREAM_LISTPACKS: u8 = 15;
+pub const TYPE_HASH_LISTPACK: u8 = 16;
+pub const TYPE_ZSET_LISTPACK: u8 = 17;
+pub const TYPE_LIST_QUICKLIST_2: u8 = 18;
+pub const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+pub const TYPE_SET_LISTPACK: u8 = 20;
+pub const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+//special string encodings, flagged by the two most significant bits of the length
+const ENCODING_INT8: u8 = 0;
+const ENCODING_INT16: u8 = 1;
+const ENCODING_INT32: u8 = 2;
+const ENCODING_LZF: u8 = 3;
+
+//CRC-64/Jones as used by redis: reflected, 0xad93d23594c935a9 polynomial, no final xor
+const CRC64_TABLE: [u64; 256] = {
+    const POLY: u64 = 0x95ac9329ac4bc9b5; //reflected polynomial
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    for byte in data {
+        crc = CRC64_TABLE[((crc ^ *byte as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Appends a length: 6 bits, 14 bits, 32 bits or 64 bits big endian, depending on its size.
+pub fn write_len(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.push(0x40 | (len >> 8) as u8);
+        out.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Appends a string as Redis does: small integers in binary form, long strings compressed when
+/// that makes them shorter, everything else as it is.
+pub fn write_string(out: &mut Vec<u8>, data: &[u8]) {
+    if data.len() <= 11
+        && let Some(value) = std::str::from_utf8(data)
+            .ok()
+            .and_then(|s| s.parse::<i32>().ok())
+            .filter(|value| value.to_string().as_bytes() == data)
+    {
+        if let Ok(value) = i8::try_from(value) {
+            out.push(0xc0 | ENCODING_INT8);
+            out.extend_from_slice(&value.to_le_bytes());
+        } else if let Ok(value) = i16::try_from(value) {
+            out.push(0xc0 | ENCODING_INT16);
+            out.extend_from_slice(&value.to_le_bytes());
+        } else {
+            out.push(0xc0 | ENCODING_INT32);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        return;
+    }
+
+    if data.len() > 20
+        && let Some(compressed) = lzf::compress(data, data.len() - 4)
+    {
+        out.push(0xc0 | ENCODING_LZF);
+        write_len(out, compressed.len() as u64);
+        write_len(out, data.len() as u64);
+        out.extend_from_slice(&compressed);
+        return;
+    }
+
+    write_len(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+/// Appends a unix time in milliseconds, 8 bytes little endian.
+pub fn write_millis(out: &mut Vec<u8>, millis: u64) {
+    out.extend_from_slice(&millis.to_le_bytes());
+}
+
+/// Appends a double in its binary form, 8 bytes little endian.
+pub fn write_double(out: &mut Vec<u8>, value: f64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Reads RDB encoded values out of a byte slice.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    /// Number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_len(&mut self) -> io::Result<u64> {
+        match self.read_len_or_encoding()? {
+            (len, false) => Ok(len),
+            (encoding, true) => Err(io::Error::other(format!(
+                "Unexpected string encoding {encoding}"
+            ))),
+        }
+    }
+
+    //a length, or with true the special encoding of the string that follows
+    fn read_len_or_encoding(&mut self) -> io::Result<(u64, bool)> {
+        let first = self.read_u8()?;
+        match first >> 6 {
+            0 => Ok(((first & 0x3f) as u64, false)),
+            1 => Ok((
+                (((first & 0x3f) as u64) << 8) | self.read_u8()? as u64,
+                false,
+            )),
+            3 => Ok(((first & 0x3f) as u64, true)),
+            _ if first == 0x80 => Ok((self.read_u32_be()? as u64, false)),
+            _ if first == 0x81 => Ok((
+                u64::from_be_bytes(self.read_bytes(8)?.try_into().expect("8 bytes")),
+                false,
+            )),
+            _ => Err(io::Error::other(format!("Unknown length encoding {first}"))),
+        }
+    }
+
+    fn read_u32_be(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(
+            self.read_bytes(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    pub fn read_u32_le(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(
+            self.read_bytes(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    /// Reads a unix time in milliseconds.
+    pub fn read_millis(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(
+            self.read_bytes(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+
+    /// Reads a double in its binary form.
+    pub fn read_double(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(
+            self.read_bytes(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+
+    /// Reads a double written as a string prefixed by its length, as the oldest sorted sets are.
+    pub fn read_double_string(&mut self) -> io::Result<f64> {
+        match self.read_u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => std::str::from_utf8(self.read_bytes(len as usize)?)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| io::Error::other("Invalid double value")),
+        }
+    }
+
+    /// Reads a string in any of its encodings: raw, integer or compressed.
+    pub fn read_string(&mut self) -> io::Result<Vec<u8>> {
+        let (len, encoded) = self.read_len_or_encoding()?;
+        if !encoded {
+            return Ok(self.read_bytes(len as usize)?.to_vec());
+        }
+
+        let value = match len as u8 {
+            ENCODING_INT8 => self.read_bytes(1)?[0] as i8 as i64,
+            ENCODING_INT16 => {
+                i16::from_le_bytes(self.read_bytes(2)?.try_into().expect("2 bytes")) as i64
+            }
+            ENCODING_INT32 => {
+                i32::from_le_bytes(self.read_bytes(4)?.try_into().expect("4 bytes")) as i64
+            }
+            ENCODING_LZF => {
+                let compressed_len = self.read_len()? as usize;
+                let len = self.read_len()? as usize;
+                let compressed = self.read_bytes(compressed_len)?;
+                return lzf::decompress(compressed, len)
+                    .ok_or_else(|| io::Error::other("Invalid LZF compressed string"));
+            }
+            encoding => {
+                return Err(io::Error::other(format!(
+                    "Unknown string encoding {encoding}"
+                )));
+            }
+        };
+
+        Ok(value.to_string().into_bytes())
+    }
+}
+
+/// Converts a string read from a file where only UTF-8 can be held, i.e. keys and names.
+pub fn into_string(data: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(data).map_err(|_| io::Error::other("Invalid UTF-8 string in the RDB file"))
+}
+
+/// Terminates a DUMP payload with the RDB version and the checksum of everything before it.
+pub fn seal_payload(mut payload: Vec<u8>) -> Vec<u8> {
+    payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(0, &payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    payload
+}
+
+/// Checks the footer of a DUMP payload, returning the serialized values it carries.
+pub fn open_payload(payload: &[u8]) -> Option<&[u8]> {
+    let (body, crc) = payload.split_at_checked(payload.len().checked_sub(8)?)?;
+    let (values, version) = body.split_at_checked(body.len().checked_sub(2)?)?;
+
+    let version = u16::from_le_bytes(version.try_into().ok()?);
+    let crc = u64::from_le_bytes(crc.try_into().ok()?);
+    if version > RDB_VERSION || crc64(0, body) != crc {
+        return None;
+    }
+
+    Some(values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Reader, crc64, open_payload, seal_payload, write_len, write_string};
+
+    #[test]
+    fn test_string_encodings() {
+        let long = "abcdefgh".repeat(10);
+        let values = [
+            "",
+            "0",
+            "-128",
+            "300",
+            "-70000",
+            "2147483647",
+            "007",
+            "12345678901",
+            &long,
+        ];
+
+        let mut out = vec![];
+        for value in values {
+            write_string(&mut out, value.as_bytes());
+        }
+        //integers are written in binary form, the long string is compressed
+        assert_eq!(&out[..5], &[0, 0xc0, 0, 0xc0, 0x80]);
+        assert!(out.len() < 60);
+
+        let mut reader = Reader::new(&out);
+        for value in values {
+            assert_eq!(reader.read_string().unwrap(), value.as_bytes());
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+        assert_eq!(crc64(0, b""), 0);
+    }
+
+    #[test]
+    fn test_lengths_and_payloads() {
+        let mut out = vec![];
+        for len in [0, 63, 64, 16383, 16384, u32::MAX as u64 + 1] {
+            write_len(&mut out, len);
+        }
+        write_string(&mut out, b"code");
+        assert_eq!(&out[..4], &[0, 63, 0x40, 64]);
+
+        let payload = seal_payload(out);
+        let values = open_payload(&payload).unwrap();
+        let mut reader = Reader::new(values);
+        for len in [0, 63, 64, 16383, 16384, u32::MAX as u64 + 1] {
+            assert_eq!(reader.read_len().unwrap(), len);
+        }
+        assert_eq!(reader.read_string().unwrap(), b"code");
+        assert!(reader.is_empty());
+
+        let mut tampered = payload.clone();
+        tampered[0] ^= 1;
+        assert!(open_payload(&tampered).is_none());
+        assert!(open_payload(b"short").is_none());
+    }
+}
@@ -0,0 +1,327 @@
+//! The compact encodings Redis serializes small collections with: listpacks, which are also
+//! written for streams, plus the older ziplists and intsets that are only ever read.
+
+use std::io;
+
+/// Builds a listpack, a sequence of strings and integers with a small header.
+#[derive(Debug, Default)]
+pub struct Listpack {
+    entries: Vec<u8>,
+    len: usize,
+}
+
+impl Listpack {
+    pub fn push_int(&mut self, value: i64) {
+        let start = self.entries.len();
+        match value {
+            0..=127 => self.entries.push(value as u8),
+            -4096..=4095 => {
+                let value = value as u16 & 0x1fff;
+                self.entries.push(0xc0 | (value >> 8) as u8);
+                self.entries.push(value as u8);
+            }
+            _ if i16::try_from(value).is_ok() => {
+                self.entries.push(0xf1);
+                self.entries
+                    .extend_from_slice(&(value as i16).to_le_bytes());
+            }
+            -8_388_608..8_388_608 => {
+                self.entries.push(0xf2);
+                self.entries
+                    .extend_from_slice(&(value as i32).to_le_bytes()[..3]);
+            }
+            _ if i32::try_from(value).is_ok() => {
+                self.entries.push(0xf3);
+                self.entries
+                    .extend_from_slice(&(value as i32).to_le_bytes());
+            }
+            _ => {
+                self.entries.push(0xf4);
+                self.entries.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        self.push_backlen(start);
+    }
+
+    pub fn push_str(&mut self, data: &[u8]) {
+        let start = self.entries.len();
+        let len = data.len();
+        if len < 1 << 6 {
+            self.entries.push(0x80 | len as u8);
+        } else if len < 1 << 12 {
+            self.entries.push(0xe0 | (len >> 8) as u8);
+            self.entries.push(len as u8);
+        } else {
+            self.entries.push(0xf0);
+            self.entries.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        self.entries.extend_from_slice(data);
+        self.push_backlen(start);
+    }
+
+    //every entry ends with its own size, for the listpack to be walked backwards
+    fn push_backlen(&mut self, start: usize) {
+        let size = self.entries.len() - start;
+        match size {
+            0..=127 => self.entries.push(size as u8),
+            128..16383 => self
+                .entries
+                .extend_from_slice(&[(size >> 7) as u8, (size & 127) as u8 | 128]),
+            16383..2097151 => self.entries.extend_from_slice(&[
+                (size >> 14) as u8,
+                ((size >> 7) & 127) as u8 | 128,
+                (size & 127) as u8 | 128,
+            ]),
+            2097151..268435455 => self.entries.extend_from_slice(&[
+                (size >> 21) as u8,
+                ((size >> 14) & 127) as u8 | 128,
+                ((size >> 7) & 127) as u8 | 128,
+                (size & 127) as u8 | 128,
+            ]),
+            _ => self.entries.extend_from_slice(&[
+                (size >> 28) as u8,
+                ((size >> 21) & 127) as u8 | 128,
+                ((size >> 14) & 127) as u8 | 128,
+                ((size >> 7) & 127) as u8 | 128,
+                (size & 127) as u8 | 128,
+            ]),
+        }
+        self.len += 1;
+    }
+
+    /// The serialized listpack: total size and number of entries, the entries and a terminator.
+    pub fn into_bytes(self) -> Vec<u8> {
+        let total = 6 + self.entries.len() + 1;
+        let mut out = Vec::with_capacity(total);
+        out.extend_from_slice(&(total as u32).to_le_bytes());
+        out.extend_from_slice(&(self.len.min(u16::MAX as usize) as u16).to_le_bytes());
+        out.extend(self.entries);
+        out.push(0xff);
+        out
+    }
+}
+
+/// The entries of a serialized listpack, integers as their decimal representation.
+pub fn listpack_entries(data: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut pos = 6;
+    let mut entries = vec![];
+
+    loop {
+        let start = pos;
+        let encoding = *data.get(pos).ok_or_else(corrupted)?;
+        let entry = match encoding {
+            0xff => return Ok(entries),
+            0x00..=0x7f => {
+                pos += 1;
+                (encoding as i64).to_string().into_bytes()
+            }
+            0x80..=0xbf => {
+                let len = (encoding & 0x3f) as usize;
+                pos += 1 + len;
+                slice(data, start + 1, len)?.to_vec()
+            }
+            0xc0..=0xdf => {
+                let value = ((encoding as i64 & 0x1f) << 8) | slice(data, pos + 1, 1)?[0] as i64;
+                pos += 2;
+                //13 bits two's complement
+                let value = if value >= 1 << 12 {
+                    value - (1 << 13)
+                } else {
+                    value
+                };
+                value.to_string().into_bytes()
+            }
+            0xe0..=0xef => {
+                let len = ((encoding as usize & 0x0f) << 8) | slice(data, pos + 1, 1)?[0] as usize;
+                pos += 2 + len;
+                slice(data, start + 2, len)?.to_vec()
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(slice(data, pos + 1, 4)?.try_into().unwrap()) as usize;
+                pos += 5 + len;
+                slice(data, start + 5, len)?.to_vec()
+            }
+            0xf1..=0xf4 => {
+                let width = [2, 3, 4, 8][(encoding - 0xf1) as usize];
+                pos += 1 + width;
+                signed_le(slice(data, start + 1, width)?)
+                    .to_string()
+                    .into_bytes()
+            }
+            _ => return Err(corrupted()),
+        };
+
+        let size = pos - start;
+        pos += match size {
+            0..=127 => 1,
+            128..16383 => 2,
+            16383..2097151 => 3,
+            2097151..268435455 => 4,
+            _ => 5,
+        };
+        entries.push(entry);
+    }
+}
+
+/// The entries of a serialized ziplist, the listpack predecessor.
+pub fn ziplist_entries(data: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut pos = 10;
+    let mut entries = vec![];
+
+    loop {
+        //the size of the previous entry, on 1 or 5 bytes
+        match *data.get(pos).ok_or_else(corrupted)? {
+            0xff => return Ok(entries),
+            0xfe => pos += 5,
+            _ => pos += 1,
+        }
+
+        let encoding = *data.get(pos).ok_or_else(corrupted)?;
+        let entry = match encoding >> 6 {
+            0 => {
+                let len = (encoding & 0x3f) as usize;
+                pos += 1 + len;
+                slice(data, pos - len, len)?.to_vec()
+            }
+            1 => {
+                let len = ((encoding as usize & 0x3f) << 8) | slice(data, pos + 1, 1)?[0] as usize;
+                pos += 2 + len;
+                slice(data, pos - len, len)?.to_vec()
+            }
+            2 => {
+                let len = u32::from_be_bytes(slice(data, pos + 1, 4)?.try_into().unwrap()) as usize;
+                pos += 5 + len;
+                slice(data, pos - len, len)?.to_vec()
+            }
+            _ => {
+                let width = match encoding {
+                    0xc0 => 2,
+                    0xd0 => 4,
+                    0xe0 => 8,
+                    0xf0 => 3,
+                    0xfe => 1,
+                    //4 bits immediate values, from 0 to 12
+                    0xf1..=0xfd => 0,
+                    _ => return Err(corrupted()),
+                };
+                let value = match width {
+                    0 => (encoding & 0x0f) as i64 - 1,
+                    width => signed_le(slice(data, pos + 1, width)?),
+                };
+                pos += 1 + width;
+                value.to_string().into_bytes()
+            }
+        };
+        entries.push(entry);
+    }
+}
+
+/// The members of a serialized intset, a sorted array of integers of the same width.
+pub fn intset_entries(data: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let width = u32::from_le_bytes(slice(data, 0, 4)?.try_into().unwrap()) as usize;
+    let len = u32::from_le_bytes(slice(data, 4, 4)?.try_into().unwrap()) as usize;
+    if !matches!(width, 2 | 4 | 8) {
+        return Err(corrupted());
+    }
+
+    (0..len)
+        .map(|i| {
+            let value = signed_le(slice(data, 8 + i * width, width)?);
+            Ok(value.to_string().into_bytes())
+        })
+        .collect()
+}
+
+fn slice(data: &[u8], start: usize, len: usize) -> io::Result<&[u8]> {
+    data.get(start..start + len).ok_or_else(corrupted)
+}
+
+//a little endian two's complement integer of up to 8 bytes
+fn signed_le(bytes: &[u8]) -> i64 {
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    let shift = 64 - 8 * bytes.len() as u32;
+    (i64::from_le_bytes(buf) << shift) >> shift
+}
+
+fn corrupted() -> io::Error {
+    io::Error::other("Corrupted listpack, ziplist or intset")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Listpack, intset_entries, listpack_entries, ziplist_entries};
+
+    fn strings(entries: Vec<Vec<u8>>) -> Vec<String> {
+        entries
+            .into_iter()
+            .map(|entry| String::from_utf8(entry).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_listpack() {
+        let long = "x".repeat(100);
+        let huge = "y".repeat(5000);
+        let mut listpack = Listpack::default();
+        for value in [
+            0,
+            127,
+            128,
+            -1,
+            -4096,
+            4095,
+            30000,
+            -70000,
+            1 << 40,
+            i64::MIN,
+        ] {
+            listpack.push_int(value);
+        }
+        for value in ["", "field", &long, &huge] {
+            listpack.push_str(value.as_bytes());
+        }
+        let data = listpack.into_bytes();
+        assert_eq!(
+            u32::from_le_bytes(data[..4].try_into().unwrap()) as usize,
+            data.len()
+        );
+
+        assert_eq!(
+            strings(listpack_entries(&data).unwrap()),
+            [
+                "0",
+                "127",
+                "128",
+                "-1",
+                "-4096",
+                "4095",
+                "30000",
+                "-70000",
+                &(1i64 << 40).to_string(),
+                &i64::MIN.to_string(),
+                "",
+                "field",
+                &long,
+                &huge,
+            ]
+        );
+
+        //as written by Redis for the list a, 1024
+        let data = [0x0d, 0, 0, 0, 2, 0, 0x81, b'a', 2, 0xc4, 0, 2, 0xff];
+        assert_eq!(strings(listpack_entries(&data).unwrap()), ["a", "1024"]);
+        assert!(listpack_entries(&data[..8]).is_err());
+    }
+
+    #[test]
+    fn test_ziplist_and_intset() {
+        //header, "ab", 12 as an immediate, -2 on 16 bits, terminator
+        let data = [
+            0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 2, b'a', b'b', 4, 0xfd, 2, 0xc0, 0xfe, 0xff, 0xff,
+        ];
+        assert_eq!(strings(ziplist_entries(&data).unwrap()), ["ab", "12", "-2"]);
+
+        let data = [2, 0, 0, 0, 2, 0, 0, 0, 0xff, 0xff, 7, 0];
+        assert_eq!(strings(intset_entries(&data).unwrap()), ["-1", "7"]);
+    }
+}
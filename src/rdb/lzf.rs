@@ -0,0 +1,139 @@
+//! The LZF compression RDB files use for long strings, compatible with liblzf.
+
+//bits of the hash of the next 3 bytes, used to find earlier occurrences
+const HASH_LOG: u32 = 14;
+//the farthest back a reference can point
+const MAX_OFFSET: usize = 1 << 13;
+//literal runs are at most 32 bytes long
+const MAX_LITERAL: usize = 1 << 5;
+//back references copy at most 264 bytes
+const MAX_REFERENCE: usize = (1 << 8) + (1 << 3);
+
+/// Compresses the data, None if the result would not be shorter than max_len bytes.
+pub fn compress(data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(max_len);
+    let mut table = vec![0usize; 1 << HASH_LOG];
+    let mut literals: Vec<u8> = Vec::with_capacity(MAX_LITERAL);
+    let mut pos = 0;
+
+    let flush = |out: &mut Vec<u8>, literals: &mut Vec<u8>| {
+        if !literals.is_empty() {
+            out.push(literals.len() as u8 - 1);
+            out.append(literals);
+        }
+    };
+
+    while pos + 2 < data.len() {
+        let hash = hash(&data[pos..pos + 3]);
+        //positions are stored plus one, zero meaning none
+        let candidate = table[hash];
+        table[hash] = pos + 1;
+
+        let reference = candidate.checked_sub(1).filter(|reference| {
+            pos - reference <= MAX_OFFSET && data[*reference..reference + 3] == data[pos..pos + 3]
+        });
+
+        let Some(reference) = reference else {
+            literals.push(data[pos]);
+            if literals.len() == MAX_LITERAL {
+                flush(&mut out, &mut literals);
+            }
+            pos += 1;
+            continue;
+        };
+
+        let longest = MAX_REFERENCE.min(data.len() - pos);
+        let len = (3..longest)
+            .find(|len| data[reference + len] != data[pos + len])
+            .unwrap_or(longest);
+
+        flush(&mut out, &mut literals);
+        let offset = pos - reference - 1;
+        let encoded_len = len - 2;
+        if encoded_len < 7 {
+            out.push(((encoded_len << 5) | (offset >> 8)) as u8);
+        } else {
+            out.push(((7 << 5) | (offset >> 8)) as u8);
+            out.push((encoded_len - 7) as u8);
+        }
+        out.push(offset as u8);
+        pos += len;
+
+        if out.len() >= max_len {
+            return None;
+        }
+    }
+
+    for byte in &data[pos..] {
+        literals.push(*byte);
+        if literals.len() == MAX_LITERAL {
+            flush(&mut out, &mut literals);
+        }
+    }
+    flush(&mut out, &mut literals);
+
+    Some(out).filter(|out| out.len() < max_len)
+}
+
+/// Decompresses data known to expand to len bytes.
+pub fn decompress(data: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let mut pos = 0;
+
+    while pos < data.len() && out.len() <= len {
+        let control = data[pos] as usize;
+        pos += 1;
+
+        if control < MAX_LITERAL {
+            let literal = data.get(pos..pos + control + 1)?;
+            out.extend_from_slice(literal);
+            pos += control + 1;
+            continue;
+        }
+
+        let mut count = control >> 5;
+        if count == 7 {
+            count += *data.get(pos)? as usize;
+            pos += 1;
+        }
+        let offset = ((control & 0x1f) << 8) + *data.get(pos)? as usize + 1;
+        pos += 1;
+
+        //the reference can overlap what is being written, so bytes are copied one at a time
+        let start = out.len().checked_sub(offset)?;
+        for i in 0..count + 2 {
+            out.push(out[start + i]);
+        }
+    }
+
+    Some(out).filter(|out| out.len() == len)
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (value.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress, decompress};
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"hello hello hello hello hello world, hello world!".repeat(20);
+        let compressed = compress(&data, data.len()).unwrap();
+        assert!(compressed.len() < data.len() / 4);
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+
+        //random looking data does not compress
+        let noise: Vec<u8> = (0..200u32).map(|i| (i * 7919 % 251) as u8).collect();
+        assert_eq!(compress(&noise, noise.len() - 4), None);
+
+        //as produced by liblzf for "aaaaaaaaaaaaaaaaaaaa"
+        assert_eq!(
+            decompress(&[0, b'a', 0xe0, 10, 0], 20).unwrap(),
+            b"a".repeat(20)
+        );
+        assert_eq!(decompress(&[0, b'a'], 2), None);
+    }
+}
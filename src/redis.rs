@@ -1,9 +1,29 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Debug;
+use std::hash::{BuildHasher as _, RandomState};
 use std::time::Instant;
 use std::{ops::Add as _, time};
 
-use crate::{command::Command, resp::RespType};
+use crate::{
+    cluster::key_hash_slot,
+    command::{
+        Command, StreamTrim, XAddId, XAddOptions, XClaimOptions, XGroupId, XGroupSubcommand,
+        XInfoSubcommand, XPendingRange, XReadGroupId, XReadId,
+    },
+    resp::RespType,
+};
+
+mod notify;
+mod propagate;
+mod rdb;
+pub mod stream;
+mod watch;
+
+pub use notify::KeyspaceEvents;
+use stream::{ConsumerGroup, Stream, StreamId};
+use watch::Watches;
+
+pub const REDIS_VERSION: &str = "7.4.0";
 
 #[derive(Debug, Default)]
 pub struct Redis {
@@ -15,38 +35,93 @@ pub struct Redis {
     to_be_notified: Vec<(i32, NotificationEvent)>,
 
     blpop_blocking_keys: HashMap<String, Vec<i32>>,
+    xread_blocking_keys: HashMap<String, Vec<i32>>,
+
+    //keys with a time to live, ordered by expiration so that the due ones are at the front
+    expires: BTreeSet<(time::Instant, String)>,
+    //the same times to live by key, whatever the type of the value
+    ttls: HashMap<String, time::Instant>,
+
+    notify_keyspace_events: KeyspaceEvents,
+
+    watches: Watches,
+
+    //number of changes to the dataset ever made, for the snapshots to tell how stale they are
+    dirty: u64,
 
     pub ready: Vec<(i32, RespType)>,
+    //(channel, message) pairs to be published by the event loop
+    pub publications: Vec<(String, String)>,
+    //the changes to the dataset as commands, to be written to the AOF by the event loop
+    pub propagated: Vec<RespType>,
 }
 
 #[derive(Debug)]
 pub enum NotificationEvent {
     BlPopEvent { key: String },
+    XReadEvent,
 }
 
 #[derive(Debug)]
 pub enum WaitingState {
-    BlPop { keys: Vec<String> },
+    BlPop {
+        keys: Vec<String>,
+    },
+    //ids are resolved when the client blocks, so that $ refers to the last id at that time
+    XRead {
+        keys: Vec<String>,
+        ids: Vec<StreamId>,
+        count: Option<usize>,
+    },
+    //only reads of never delivered entries (>) block
+    XReadGroup {
+        group: String,
+        consumer: String,
+        keys: Vec<String>,
+        count: Option<usize>,
+        noack: bool,
+    },
+    //WAIT, with the number of replicas that acknowledged the offset so far
+    Wait {
+        offset: u64,
+        numreplicas: u64,
+        acked: u64,
+    },
+    //WAITAOF, with whether the local AOF and how many replicas synced the offset so far
+    WaitAof {
+        offset: u64,
+        numlocal: u64,
+        numreplicas: u64,
+        local: u64,
+        acked: u64,
+    },
+}
+
+/// How far the replication went for a given offset, as counted by the event loop.
+#[derive(Debug, Default)]
+pub struct Acks {
+    //replicas that processed the offset
+    pub replicas: u64,
+    //replicas whose AOF is synced up to the offset
+    pub replicas_fsynced: u64,
+    pub local_fsynced: bool,
 }
 
 #[derive(Debug)]
 struct StoredValue {
-    data: String,
-    ttl: Option<time::Instant>,
+    data: Vec<u8>,
 }
 
 #[derive(Debug)]
 //string, list, set, zset, hash, stream, and vectorset
 enum RedisType {
     String { value: StoredValue },
-    List { elements: Vec<String> },
-    Stream { elements: Vec<StreamElement> },
-}
-
-#[derive(Debug)]
-struct StreamElement {
-    id: String,
-    data: HashMap<String, String>,
+    List { elements: Vec<Vec<u8>> },
+    Stream { value: Stream },
+    //no command works on these yet, they are kept for RDB files to be loaded and saved back
+    Set { members: BTreeSet<Vec<u8>> },
+    Hash { fields: BTreeMap<Vec<u8>, Vec<u8>> },
+    SortedSet { members: BTreeMap<Vec<u8>, f64> },
 }
 
 #[allow(unused)] //TODO [LS]: remove the allow once we use the failure error
@@ -58,10 +133,11 @@ pub enum RedisError {
 
 impl Redis {
     pub fn handle_command(&mut self, cmd: Command, client_id: i32) -> Result<RespType, RedisError> {
-        println!("Handling command {cmd:?} from client {client_id}");
+        let dirty = self.dirty;
+        let write = cmd.is_write().then(|| cmd.clone());
 
-        match cmd {
-            Command::Ping => handle_ping(),
+        let reply = match cmd {
+            Command::Ping { message } => handle_ping(message),
             Command::Echo { to_echo } => handle_echo(to_echo),
             Command::Set {
                 key,
@@ -76,11 +152,310 @@ impl Redis {
             Command::LRange { key, start, stop } => self.handle_lrange(key, start, stop),
             Command::BlPop { keys, timeout } => self.handle_blpop(client_id, keys, timeout),
             Command::Type { key } => self.handle_type(key),
-            Command::XAdd { key, id, elements } => self.handle_xadd(key, id, elements),
+            Command::XAdd {
+                key,
+                id,
+                elements,
+                options,
+            } => self.handle_xadd(key, id, elements, options),
+            Command::XTrim { key, trim } => self.handle_xtrim(key, trim),
+            Command::XDel { key, ids } => self.handle_xdel(key, ids),
+            Command::XSetId {
+                key,
+                last_id,
+                entries_added,
+                max_deleted_id,
+            } => self.handle_xsetid(key, last_id, entries_added, max_deleted_id),
+            Command::XInfo { subcommand } => self.handle_xinfo(subcommand),
+            Command::XRange {
+                key,
+                start,
+                end,
+                count,
+            } => self.handle_xrange(key, start, end, count, false),
+            Command::XRevRange {
+                key,
+                end,
+                start,
+                count,
+            } => self.handle_xrange(key, start, end, count, true),
+            Command::XLen { key } => self.handle_xlen(key),
+            Command::XRead {
+                count,
+                block,
+                keys,
+                ids,
+            } => self.handle_xread(client_id, count, block, keys, ids),
+            Command::XGroup { subcommand } => self.handle_xgroup(subcommand),
+            Command::XReadGroup {
+                group,
+                consumer,
+                count,
+                block,
+                noack,
+                keys,
+                ids,
+            } => self.handle_xreadgroup(client_id, group, consumer, count, block, noack, keys, ids),
+            Command::XAck { key, group, ids } => self.handle_xack(key, group, ids),
+            Command::XPending { key, group, range } => self.handle_xpending(key, group, range),
+            Command::XClaim {
+                key,
+                group,
+                consumer,
+                min_idle,
+                ids,
+                options,
+            } => self.handle_xclaim(key, group, consumer, min_idle, ids, options),
+            Command::XAutoClaim {
+                key,
+                group,
+                consumer,
+                min_idle,
+                start,
+                count,
+                justid,
+            } => self.handle_xautoclaim(key, group, consumer, min_idle, start, count, justid),
+            Command::Hello { .. }
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::PSubscribe { .. }
+            | Command::PUnsubscribe { .. }
+            | Command::Publish { .. }
+            | Command::SSubscribe { .. }
+            | Command::SUnsubscribe { .. }
+            | Command::SPublish { .. }
+            | Command::PubSub { .. }
+            | Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Eval { .. }
+            | Command::EvalSha { .. }
+            | Command::Script { .. }
+            | Command::FCall { .. }
+            | Command::Function { .. }
+            | Command::Auth { .. }
+            | Command::Acl { .. }
+            | Command::Config { .. }
+            | Command::Info { .. }
+            | Command::Save
+            | Command::BgSave { .. }
+            | Command::LastSave
+            | Command::BgRewriteAof
+            | Command::ReplicaOf { .. }
+            | Command::ReplConf { .. }
+            | Command::PSync { .. }
+            | Command::Role
+            | Command::Cluster { .. }
+            | Command::Asking
+            | Command::Sentinel { .. }
+            | Command::Migrate { .. }
+            | Command::Wait { .. }
+            | Command::WaitAof { .. } => {
+                unreachable!("connection commands are handled by the event loop")
+            }
+            Command::Watch { keys } => self.handle_watch(client_id, keys),
+            Command::Unwatch => {
+                self.watches.unwatch(client_id);
+                Ok(RespType::SimpleString {
+                    content: "OK".into(),
+                })
+            }
+            Command::FlushAll | Command::FlushDb => self.handle_flushall(),
+            Command::SwapDb { index1, index2 } => self.handle_swapdb(index1, index2),
+            Command::Del { keys } => self.handle_del(keys),
+            Command::Dump { key } => self.handle_dump(key),
+            Command::Restore {
+                key,
+                ttl,
+                payload,
+                replace,
+                absttl,
+                ..
+            } => self.handle_restore(key, ttl, &payload, replace, absttl),
             Command::ErrorCmd { msg } => handle_error(msg),
+        };
+
+        if let Some(cmd) = write
+            && self.dirty > dirty
+            && let Ok(reply) = &reply
+        {
+            self.propagate_command(cmd, reply);
+        }
+        reply
+    }
+
+    pub fn set_notify_keyspace_events(&mut self, events: KeyspaceEvents) {
+        self.notify_keyspace_events = events;
+    }
+
+    fn handle_watch(&mut self, client_id: i32, keys: Vec<String>) -> Result<RespType, RedisError> {
+        for key in keys {
+            self.watches.watch(client_id, &key);
+        }
+
+        Ok(RespType::SimpleString {
+            content: "OK".into(),
+        })
+    }
+
+    /// Forgets the keys watched by the client, returning true if any of them has been modified
+    /// in the meantime, meaning that the transaction of the client has to fail.
+    pub(crate) fn unwatch(&mut self, client_id: i32) -> bool {
+        self.watches.unwatch(client_id)
+    }
+
+    //there is a single database, FLUSHDB empties it as FLUSHALL does
+    fn handle_flushall(&mut self) -> Result<RespType, RedisError> {
+        let store = &self.store;
+        self.watches.touch_all(|key| store.contains_key(key));
+
+        self.dirty += self.store.len() as u64 + 1;
+        self.store.clear();
+        self.expires.clear();
+        self.ttls.clear();
+
+        Ok(RespType::SimpleString {
+            content: "OK".into(),
+        })
+    }
+
+    //with a single database, the only swap is of database 0 with itself, which changes nothing
+    //and so invalidates no watcher
+    fn handle_swapdb(&mut self, index1: i64, index2: i64) -> Result<RespType, RedisError> {
+        if index1 != 0 || index2 != 0 {
+            return Ok(RespType::SimpleError {
+                content: "ERR DB index is out of range".into(),
+            });
+        }
+
+        self.dirty += 1;
+        Ok(RespType::SimpleString {
+            content: "OK".into(),
+        })
+    }
+
+    fn handle_del(&mut self, keys: Vec<String>) -> Result<RespType, RedisError> {
+        let mut deleted = 0;
+        for key in keys {
+            if self.remove_key(&key) {
+                self.signal_modified_key(&key);
+                self.notify_keyspace_event(KeyspaceEvents::GENERIC, "del", &key);
+                deleted += 1;
+            }
+        }
+
+        Ok(RespType::Integer { integer: deleted })
+    }
+
+    //removes the key along with its time to live, false if it did not exist or had expired
+    fn remove_key(&mut self, key: &str) -> bool {
+        let now = time::Instant::now();
+        let existed = self.store.remove(key).is_some();
+        let ttl = self.ttls.get(key).copied();
+        self.set_ttl(key, None);
+
+        existed && ttl.is_none_or(|ttl| ttl > now)
+    }
+
+    //replaces the time to live of the key, None making it persistent
+    fn set_ttl(&mut self, key: &str, ttl: Option<time::Instant>) {
+        if let Some(previous) = self.ttls.remove(key) {
+            self.expires.remove(&(previous, key.to_string()));
+        }
+        if let Some(ttl) = ttl {
+            self.ttls.insert(key.to_string(), ttl);
+            self.expires.insert((ttl, key.to_string()));
+        }
+    }
+
+    /// Deletes a key chosen by the maxmemory policy to make room, false if the policy has no
+    /// key to offer.
+    pub(crate) fn evict(&mut self, policy: &str) -> bool {
+        let random = RandomState::new().hash_one(self.dirty) as usize;
+        let key = match policy {
+            "allkeys-random" => self.store.keys().nth(random % self.store.len().max(1)),
+            "volatile-random" => self.ttls.keys().nth(random % self.ttls.len().max(1)),
+            "volatile-ttl" => self.expires.first().map(|(_, key)| key),
+            _ => None,
+        };
+        let Some(key) = key.cloned() else {
+            return false;
+        };
+
+        self.remove_key(&key);
+        self.signal_modified_key(&key);
+        self.notify_keyspace_event(KeyspaceEvents::EVICTED, "evicted", &key);
+        self.propagate(&["DEL", &key]);
+        true
+    }
+
+    //every write goes through here so that clients watching the key fail their transaction
+    fn signal_modified_key(&mut self, key: &str) {
+        self.watches.touch(key);
+        self.dirty += 1;
+    }
+
+    /// Whether the key holds a value, for the cluster to tell the keys of a slot being
+    /// migrated that already moved.
+    pub(crate) fn contains_key(&self, key: &str) -> bool {
+        self.store.contains_key(key)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    /// The keys of the hash slot, in no particular order.
+    pub(crate) fn keys_in_slot(&self, slot: u16) -> impl Iterator<Item = &str> {
+        self.store
+            .keys()
+            .filter(move |key| key_hash_slot(key.as_bytes()) == slot)
+            .map(String::as_str)
+    }
+
+    /// The number of changes made to the dataset since the server started.
+    pub(crate) fn dirty(&self) -> u64 {
+        self.dirty
+    }
+
+    /// Queues the keyspace (`__keyspace@0__:<key>`) and keyevent (`__keyevent@0__:<event>`)
+    /// messages for the event, according to the configured classes.
+    fn notify_keyspace_event(&mut self, class: KeyspaceEvents, event: &str, key: &str) {
+        let flags = self.notify_keyspace_events;
+        if !flags.enabled(class) {
+            return;
+        }
+
+        if flags.contains(KeyspaceEvents::KEYSPACE) {
+            self.publications
+                .push((format!("__keyspace@0__:{key}"), event.to_string()));
+        }
+        if flags.contains(KeyspaceEvents::KEYEVENT) {
+            self.publications
+                .push((format!("__keyevent@0__:{event}"), key.to_string()));
+        }
+    }
+
+    /// Removes the keys whose time to live has elapsed, without waiting for them to be accessed.
+    pub(crate) fn active_expire_cycle(&mut self) {
+        let now = time::Instant::now();
+
+        while let Some((ttl, key)) = self.expires.first()
+            && *ttl <= now
+        {
+            let key = key.clone();
+            self.expire_key(&key);
         }
     }
 
+    fn expire_key(&mut self, key: &str) {
+        self.store.remove(key);
+        self.set_ttl(key, None);
+
+        self.signal_modified_key(key);
+        self.notify_keyspace_event(KeyspaceEvents::EXPIRED, "expired", key);
+    }
+
     fn handle_type(&mut self, key: String) -> Result<RespType, RedisError> {
         //TODO [LS]: only handle this case for now, refactor will be needed later
         if let Some(t) = self.store.get(&key) {
@@ -91,9 +466,18 @@ impl Redis {
                 RedisType::List { elements: _ } => Ok(RespType::SimpleString {
                     content: "list".into(),
                 }),
-                RedisType::Stream { elements: _ } => Ok(RespType::SimpleString {
+                RedisType::Stream { value: _ } => Ok(RespType::SimpleString {
                     content: "stream".into(),
                 }),
+                RedisType::Set { members: _ } => Ok(RespType::SimpleString {
+                    content: "set".into(),
+                }),
+                RedisType::Hash { fields: _ } => Ok(RespType::SimpleString {
+                    content: "hash".into(),
+                }),
+                RedisType::SortedSet { members: _ } => Ok(RespType::SimpleString {
+                    content: "zset".into(),
+                }),
             }
         } else {
             Ok(RespType::SimpleString {
@@ -130,6 +514,7 @@ impl Redis {
                         })
                         .unwrap()
                         .remove(0);
+                    self.list_popped(key);
 
                     //as soon as we find a matching entry we return ok
                     return Ok(RespType::Array {
@@ -137,9 +522,7 @@ impl Redis {
                             RespType::BulkString {
                                 data: key.clone().into_bytes(),
                             },
-                            RespType::BulkString {
-                                data: val.into_bytes(),
-                            },
+                            RespType::BulkString { data: val },
                         ],
                     });
                 }
@@ -199,15 +582,16 @@ impl Redis {
                 } else {
                     elements[start..=stop]
                         .iter()
-                        .map(|val| RespType::BulkString {
-                            data: val.as_bytes().to_vec(),
-                        })
+                        .map(|val| RespType::BulkString { data: val.clone() })
                         .collect()
                 };
 
                 Ok(RespType::Array { elements })
             }
-            Some(RedisType::List { elements: _ }) => Ok(RespType::Array { elements: vec![] }),
+            //a list is deleted once emptied, a missing key reads as an empty list
+            Some(RedisType::List { elements: _ }) | None => {
+                Ok(RespType::Array { elements: vec![] })
+            }
             _ => panic!("Illegal state"),
         }
     }
@@ -236,18 +620,31 @@ impl Redis {
             });
         }
 
+        let created = !self.store.contains_key(&key);
+
         let entry = self
             .store
             .entry(key.clone())
             .and_modify(|l| match l {
-                RedisType::List { elements: els } => {
-                    elements.iter().for_each(|el| els.insert(0, el.clone()))
-                }
+                RedisType::List { elements: els } => elements
+                    .iter()
+                    .for_each(|el| els.insert(0, el.as_bytes().to_vec())),
                 _ => panic!("Illegal state"),
             })
             .or_insert(RedisType::List {
-                elements: elements.into_iter().rev().collect(),
+                elements: elements.into_iter().rev().map(String::into_bytes).collect(),
             });
+        let len = match entry {
+            RedisType::List { elements } => elements.len(),
+            _ => panic!("Illegal state"),
+        };
+
+        if created {
+            self.notify_keyspace_event(KeyspaceEvents::NEW, "new", &key);
+        }
+        self.signal_modified_key(&key);
+        self.notify_keyspace_event(KeyspaceEvents::LIST, "lpush", &key);
+
         //"notify" waiting clients
         if let Some(clients) = self
             .blpop_blocking_keys
@@ -262,18 +659,11 @@ impl Redis {
         }
 
         Ok(RespType::Integer {
-            integer: match entry {
-                RedisType::List { elements } => elements.len() as i64,
-                _ => panic!("Illegal state"),
-            },
+            integer: len as i64,
         })
     }
 
-    fn handle_rpush(
-        &mut self,
-        key: String,
-        mut elements: Vec<String>,
-    ) -> Result<RespType, RedisError> {
+    fn handle_rpush(&mut self, key: String, elements: Vec<String>) -> Result<RespType, RedisError> {
         if !self.ensure_type(&key, "list") {
             return Ok(RespType::SimpleError {
                 content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
@@ -281,6 +671,9 @@ impl Redis {
         }
 
         let elements_len = elements.len();
+        let mut elements: Vec<_> = elements.into_iter().map(String::into_bytes).collect();
+
+        let created = !self.store.contains_key(&key);
 
         let entry = self
             .store
@@ -290,6 +683,16 @@ impl Redis {
                 _ => panic!("Illegal state"),
             })
             .or_insert(RedisType::List { elements });
+        let len = match entry {
+            RedisType::List { elements } => elements.len(),
+            _ => panic!("Illegal state"),
+        };
+
+        if created {
+            self.notify_keyspace_event(KeyspaceEvents::NEW, "new", &key);
+        }
+        self.signal_modified_key(&key);
+        self.notify_keyspace_event(KeyspaceEvents::LIST, "rpush", &key);
 
         //"notify" waiting clients
         if let Some(clients) = self
@@ -298,9 +701,7 @@ impl Redis {
             .filter(|v| !v.is_empty())
         {
             let mut notified = 0;
-            while let Some(_) = clients.get(0)
-                && notified < elements_len
-            {
+            while !clients.is_empty() && notified < elements_len {
                 let longest = clients.remove(0);
                 //a client can only be waiting for a single event at a time, if it stops
                 //waiting then it is removed from the to_be_notified map
@@ -312,10 +713,7 @@ impl Redis {
         }
 
         Ok(RespType::Integer {
-            integer: match entry {
-                RedisType::List { elements } => elements.len() as i64,
-                _ => panic!("Illegal state"),
-            },
+            integer: len as i64,
         })
     }
 
@@ -327,19 +725,26 @@ impl Redis {
         }
 
         match self.store.get(&key) {
-            Some(RedisType::String { value: v })
-                if v.ttl.is_some_and(|ttl| time::Instant::now().gt(&ttl)) =>
+            Some(RedisType::String { value: _ })
+                if self
+                    .ttls
+                    .get(&key)
+                    .is_some_and(|ttl| time::Instant::now().ge(ttl)) =>
             {
-                self.store.remove(&key);
+                self.expire_key(&key);
+                self.notify_keyspace_event(KeyspaceEvents::KEY_MISS, "keymiss", &key);
                 Ok(RespType::NullBulkString)
             }
             Some(RedisType::String { value: v }) => Ok(RespType::BulkString {
-                data: v.data.as_bytes().to_vec(),
+                data: v.data.clone(),
             }),
             Some(_) => {
                 panic!("Should be unreachable, due to type check at the beginning of this function")
             }
-            None => Ok(RespType::NullBulkString),
+            None => {
+                self.notify_keyspace_event(KeyspaceEvents::KEY_MISS, "keymiss", &key);
+                Ok(RespType::NullBulkString)
+            }
         }
     }
 
@@ -356,17 +761,88 @@ impl Redis {
         }
 
         let value = StoredValue {
-            data: value,
-            ttl: options.expire().map(|exp| time::Instant::now().add(exp)),
+            data: value.into_bytes(),
         };
+        let ttl = options.expire().map(|exp| time::Instant::now().add(exp));
+
+        match self.store.insert(key.clone(), RedisType::String { value }) {
+            Some(RedisType::String { value: _ }) => {}
+            Some(_) => panic!("Illegal state"),
+            None => self.notify_keyspace_event(KeyspaceEvents::NEW, "new", &key),
+        }
+        self.set_ttl(&key, ttl);
+
+        self.signal_modified_key(&key);
+        self.notify_keyspace_event(KeyspaceEvents::STRING, "set", &key);
 
-        self.store.insert(key, RedisType::String { value });
+        if ttl.is_some() {
+            self.notify_keyspace_event(KeyspaceEvents::GENERIC, "expire", &key);
+        }
 
         Ok(RespType::SimpleString {
             content: "OK".into(),
         })
     }
 
+    /// True while the client waits on a blocking command.
+    pub(crate) fn is_blocked(&self, client_id: i32) -> bool {
+        self.waiting_clients.contains_key(&client_id)
+    }
+
+    /// Blocks the client in WAIT or WAITAOF until count_acks finds enough acknowledgements of
+    /// the offset of the state, or until the timeout elapses.
+    pub(crate) fn wait_for_acks(
+        &mut self,
+        client_id: i32,
+        state: WaitingState,
+        timeout: Option<time::Duration>,
+    ) {
+        let timeout = timeout.map(|dur| Instant::now() + dur);
+        self.waiting_clients.insert(client_id, (state, timeout));
+    }
+
+    /// Records the acknowledgements received for the offsets the clients blocked in WAIT and
+    /// WAITAOF wait for, acks giving them for an offset, and serves the clients that have
+    /// enough of them.
+    pub(crate) fn count_acks(&mut self, acks: impl Fn(u64) -> Acks) {
+        let mut served = vec![];
+
+        for (client_id, (state, _)) in self.waiting_clients.iter_mut() {
+            let done = match state {
+                WaitingState::Wait {
+                    offset,
+                    numreplicas,
+                    acked,
+                } => {
+                    *acked = acks(*offset).replicas;
+                    acked >= numreplicas
+                }
+                WaitingState::WaitAof {
+                    offset,
+                    numlocal,
+                    numreplicas,
+                    local,
+                    acked,
+                } => {
+                    let acks = acks(*offset);
+                    *local = acks.local_fsynced as u64;
+                    *acked = acks.replicas_fsynced;
+                    local >= numlocal && acked >= numreplicas
+                }
+                _ => false,
+            };
+            if done {
+                served.push(*client_id);
+            }
+        }
+
+        for client_id in served {
+            if let Some((state, _)) = self.waiting_clients.remove(&client_id) {
+                self.ready.push((client_id, timeout_reply(&state)));
+            }
+        }
+    }
+
     pub(crate) fn remove_waiting(&mut self, client_id: &i32) {
         if let Some(idx) = self
             .to_be_notified
@@ -381,61 +857,89 @@ impl Redis {
         }
 
         if let Some((state, _)) = self.waiting_clients.remove(client_id) {
-            match state {
-                WaitingState::BlPop { keys } => {
-                    for key in keys {
-                        if let Some(blocked) = self.blpop_blocking_keys.get_mut(&key)
-                            && let Some(idx) = blocked.iter().position(|bl| bl == client_id)
-                        {
-                            blocked.remove(idx);
-                        }
-                    }
+            self.unblock(*client_id, &state);
+        }
+    }
+
+    //removes the client from the blocking keys indexes of the given state
+    fn unblock(&mut self, client_id: i32, state: &WaitingState) {
+        let (keys, index) = match state {
+            WaitingState::BlPop { keys } => (keys, &mut self.blpop_blocking_keys),
+            WaitingState::XRead { keys, .. } | WaitingState::XReadGroup { keys, .. } => {
+                (keys, &mut self.xread_blocking_keys)
+            }
+            WaitingState::Wait { .. } | WaitingState::WaitAof { .. } => return,
+        };
+
+        for key in keys {
+            if let Some(blocked) = index.get_mut(key) {
+                blocked.retain(|bl| *bl != client_id);
+                if blocked.is_empty() {
+                    index.remove(key);
                 }
             }
         }
     }
 
-    pub(crate) fn remove_expired(&mut self) -> Vec<i32> {
-        let expired: Vec<i32> = self
+    /// Unblocks the clients whose timeout has elapsed, returning the reply each of them should
+    /// receive.
+    pub(crate) fn remove_expired(&mut self) -> Vec<(i32, RespType)> {
+        let expired: Vec<(i32, RespType)> = self
             .waiting_clients
             .iter()
             .filter(|(_, (_, timeout))| timeout.is_some_and(|t| time::Instant::now() >= t))
-            .map(|(k, _)| *k)
+            .map(|(k, (state, _))| (*k, timeout_reply(state)))
             .collect();
 
-        for cl in &expired {
+        for (cl, _) in &expired {
             self.remove_waiting(cl);
         }
 
         expired
     }
 
+    /// Unblocks the client right away as if its timeout had elapsed, used where blocking is not
+    /// an option (i.e. inside transactions).
+    pub(crate) fn cancel_blocking(&mut self, client_id: i32) -> Option<RespType> {
+        let reply = self
+            .waiting_clients
+            .get(&client_id)
+            .map(|(state, _)| timeout_reply(state));
+        self.remove_waiting(&client_id);
+
+        reply
+    }
+
     pub(crate) fn compute_ready(&mut self) {
-        while let Some(_) = self.to_be_notified.first() {
+        while !self.to_be_notified.is_empty() {
             let (client_id, notification) = self.to_be_notified.remove(0);
 
             match notification {
                 NotificationEvent::BlPopEvent { key } => {
-                    let (state, _) = self
-                        .waiting_clients
-                        .remove(&client_id)
-                        .expect("Invalid state: waiting client without keys");
-
-                    //this check will be useful for when we'll introduce new
-                    //blocking states in the future
-                    #[allow(irrefutable_let_patterns)]
-                    if let WaitingState::BlPop { keys } = state {
-                        for k in keys {
-                            self.blpop_blocking_keys
-                                .entry(k)
-                                .and_modify(|v| v.retain(|c| *c != client_id));
-                        }
-                    } else {
-                        panic!(
-                            "Invalid state: received blpop notification event but waiting state does not match"
-                        )
+                    //the client could have already been served by a previous notification
+                    if !matches!(
+                        self.waiting_clients.get(&client_id),
+                        Some((WaitingState::BlPop { .. }, _))
+                    ) {
+                        continue;
                     }
 
+                    //the list could have been drained since the push (i.e. in the same
+                    //transaction or script), the client then keeps waiting, first in line
+                    if !matches!(
+                        self.store.get(&key),
+                        Some(RedisType::List { elements }) if !elements.is_empty()
+                    ) {
+                        self.blpop_blocking_keys
+                            .entry(key)
+                            .or_default()
+                            .insert(0, client_id);
+                        continue;
+                    }
+
+                    let (state, _) = self.waiting_clients.remove(&client_id).unwrap();
+                    self.unblock(client_id, &state);
+
                     let resp = self
                         .handle_lpop(&key, 1)
                         .map(|val| RespType::Array {
@@ -447,9 +951,60 @@ impl Redis {
                             ],
                         })
                         .unwrap();
+                    self.propagate(&["LPOP", &key]);
 
                     self.ready.push((client_id, resp));
                 }
+                NotificationEvent::XReadEvent => {
+                    //the client could have already been served by a previous notification
+                    let response = match self.waiting_clients.get(&client_id) {
+                        Some((WaitingState::XRead { keys, ids, count }, _)) => {
+                            let read = self.xread_entries(keys, ids, *count);
+                            (!read.is_empty()).then_some(RespType::Array { elements: read })
+                        }
+                        Some((
+                            WaitingState::XReadGroup {
+                                group,
+                                consumer,
+                                keys,
+                                count,
+                                noack,
+                            },
+                            _,
+                        )) => {
+                            let (group, consumer, keys) =
+                                (group.clone(), consumer.clone(), keys.clone());
+                            let (count, noack) = (*count, *noack);
+
+                            if keys.iter().any(|key| !self.has_group(key, &group)) {
+                                Some(RespType::SimpleError {
+                                    content: "NOGROUP the consumer group this client was blocked on no longer exists".into(),
+                                })
+                            } else {
+                                let read = self
+                                    .xreadgroup_new_entries(&group, &consumer, &keys, count, noack);
+                                if !read.is_empty() {
+                                    let ids = vec![">".to_string(); keys.len()];
+                                    let args = propagate::xreadgroup_args(
+                                        group, consumer, count, noack, keys, ids,
+                                    );
+                                    self.propagate(&args);
+                                }
+                                (!read.is_empty()).then_some(RespType::Array { elements: read })
+                            }
+                        }
+                        _ => None,
+                    };
+
+                    let Some(response) = response else {
+                        continue;
+                    };
+
+                    let (state, _) = self.waiting_clients.remove(&client_id).unwrap();
+                    self.unblock(client_id, &state);
+
+                    self.ready.push((client_id, response));
+                }
             }
         }
     }
@@ -461,7 +1016,7 @@ impl Redis {
             });
         }
 
-        let mut pop_list = Vec::<String>::with_capacity(count);
+        let mut pop_list = Vec::<Vec<u8>>::with_capacity(count);
 
         while let Some(list) = self.store.get_mut(key).and_then(|v| {
             if let RedisType::List { elements } = v
@@ -476,28 +1031,47 @@ impl Redis {
             pop_list.push(list.remove(0));
         }
 
+        if !pop_list.is_empty() {
+            self.list_popped(key);
+        }
+
         match pop_list.len() {
             0 => Ok(RespType::NullBulkString),
             1 => Ok(RespType::BulkString {
-                data: pop_list[0].as_bytes().to_vec(),
+                data: pop_list.remove(0),
             }),
             _ => Ok(RespType::Array {
                 elements: pop_list
-                    .iter()
-                    .map(|popped| RespType::BulkString {
-                        data: popped.as_bytes().to_vec(),
-                    })
+                    .into_iter()
+                    .map(|popped| RespType::BulkString { data: popped })
                     .collect(),
             }),
         }
     }
 
+    //emits the pop event and deletes the list once it has been emptied
+    fn list_popped(&mut self, key: &str) {
+        self.signal_modified_key(key);
+        self.notify_keyspace_event(KeyspaceEvents::LIST, "lpop", key);
+
+        if let Some(RedisType::List { elements }) = self.store.get(key)
+            && elements.is_empty()
+        {
+            self.store.remove(key);
+            self.set_ttl(key, None);
+            self.notify_keyspace_event(KeyspaceEvents::GENERIC, "del", key);
+        }
+    }
+
     fn ensure_type(&self, key: &str, wanted: &str) -> bool {
         match self.store.get(key) {
             Some(t) => match t {
                 RedisType::String { value: _ } => wanted == "string",
                 RedisType::List { elements: _ } => wanted == "list",
-                RedisType::Stream { elements: _ } => wanted == "stream",
+                RedisType::Stream { value: _ } => wanted == "stream",
+                RedisType::Set { members: _ } => wanted == "set",
+                RedisType::Hash { fields: _ } => wanted == "hash",
+                RedisType::SortedSet { members: _ } => wanted == "zset",
             },
             None => true,
         }
@@ -506,8 +1080,9 @@ impl Redis {
     fn handle_xadd(
         &mut self,
         key: String,
-        id: String,
+        id: XAddId,
         elements: Vec<(String, String)>,
+        options: XAddOptions,
     ) -> Result<RespType, RedisError> {
         if !self.ensure_type(&key, "stream") {
             return Ok(RespType::SimpleError {
@@ -515,72 +1090,1218 @@ impl Redis {
             });
         }
 
-        let id_utf8 = id.as_bytes().to_vec();
-        let data: HashMap<String, String> = elements.into_iter().collect();
+        let last_id = match self.store.get(&key) {
+            Some(RedisType::Stream { value }) => Some(value.last_id()),
+            Some(_) => panic!("Illegal state"),
+            None if options.nomkstream => return Ok(RespType::NullBulkString),
+            None => None,
+        };
 
-        self.store
-            .entry(key)
-            .and_modify(|t| match t {
-                RedisType::Stream { elements } => elements.push(StreamElement {
-                    id: id.clone(),
-                    data: data.clone(),
-                }),
-                _ => panic!("Illegal state"),
-            })
-            .or_insert(RedisType::Stream {
-                elements: vec![StreamElement { id, data }],
-            });
+        let id = match next_stream_id(id, last_id) {
+            Ok(id) => id,
+            Err(content) => return Ok(RespType::SimpleError { content }),
+        };
 
-        Ok(RespType::BulkString { data: id_utf8 })
-    }
-}
+        let trimmed = match self.store.entry(key.clone()).or_insert(RedisType::Stream {
+            value: Stream::default(),
+        }) {
+            RedisType::Stream { value } => {
+                value.push(id, elements);
+                options.trim.map_or(0, |trim| value.trim(&trim))
+            }
+            _ => panic!("Illegal state"),
+        };
 
-fn handle_error(msg: String) -> Result<RespType, RedisError> {
-    Ok(RespType::SimpleError { content: msg })
-}
+        if last_id.is_none() {
+            self.notify_keyspace_event(KeyspaceEvents::NEW, "new", &key);
+        }
+        self.signal_modified_key(&key);
+        self.notify_keyspace_event(KeyspaceEvents::STREAM, "xadd", &key);
+        if trimmed > 0 {
+            self.notify_keyspace_event(KeyspaceEvents::STREAM, "xtrim", &key);
+        }
 
-fn handle_echo(to_echo: String) -> Result<RespType, RedisError> {
-    Ok(RespType::BulkString {
-        data: to_echo.into_bytes(),
-    })
-}
+        self.notify_stream_readers(&key);
 
-fn handle_ping() -> Result<RespType, RedisError> {
-    Ok(RespType::SimpleString {
-        content: "PONG".into(),
-    })
-}
+        Ok(RespType::BulkString {
+            data: id.to_bytes(),
+        })
+    }
 
-#[cfg(test)]
-mod test {
-    use crate::{command::Command, resp::RespType};
+    //"notify" waiting clients, every reader blocked on the stream gets a chance to read
+    fn notify_stream_readers(&mut self, key: &str) {
+        if let Some(clients) = self.xread_blocking_keys.get(key) {
+            for client in clients {
+                if !self.to_be_notified.iter().any(|(id, _)| id == client) {
+                    self.to_be_notified
+                        .push((*client, NotificationEvent::XReadEvent));
+                }
+            }
+        }
+    }
 
-    #[test]
-    fn test_handle_lrange() {
-        let mut rds = super::Redis::default();
+    fn handle_xtrim(&mut self, key: String, trim: StreamTrim) -> Result<RespType, RedisError> {
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
 
-        let key = String::from("test key");
+        let removed = self.stream_mut(&key).map_or(0, |stream| stream.trim(&trim));
+        if removed > 0 {
+            self.signal_modified_key(&key);
+            self.notify_keyspace_event(KeyspaceEvents::STREAM, "xtrim", &key);
+        }
 
-        let rpush_cmd = crate::command::Command::RPush {
-            key: key.clone(),
-            elements: vec![
-                String::from("first"),
-                String::from("second"),
-                String::from("third"),
-                String::from("fourth"),
-                String::from("fifth"),
-            ],
-        };
+        Ok(RespType::Integer {
+            integer: removed as i64,
+        })
+    }
 
-        let sz = rds.handle_command(rpush_cmd, 0).unwrap();
-        assert_eq!(sz, RespType::Integer { integer: 5 });
+    fn handle_xdel(&mut self, key: String, ids: Vec<StreamId>) -> Result<RespType, RedisError> {
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
 
-        //test stop > len
-        let lrange_cmd = Command::LRange {
-            key: key.clone(),
-            start: 0,
-            stop: 1234,
-        };
+        let deleted = self.stream_mut(&key).map_or(0, |stream| {
+            ids.iter().filter(|id| stream.delete(id)).count()
+        });
+        if deleted > 0 {
+            self.signal_modified_key(&key);
+            self.notify_keyspace_event(KeyspaceEvents::STREAM, "xdel", &key);
+        }
+
+        Ok(RespType::Integer {
+            integer: deleted as i64,
+        })
+    }
+
+    fn handle_xsetid(
+        &mut self,
+        key: String,
+        last_id: StreamId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamId>,
+    ) -> Result<RespType, RedisError> {
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
+
+        let Some(stream) = self.stream_mut(&key) else {
+            return Ok(RespType::SimpleError {
+                content: "ERR no such key".into(),
+            });
+        };
+
+        let error = if max_deleted_id.is_some_and(|max_deleted| last_id < max_deleted) {
+            Some("ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id")
+        } else if entries_added.is_some_and(|added| added < stream.len() as u64) {
+            Some(
+                "ERR The entries_added specified in XSETID is smaller than the target stream length",
+            )
+        } else if stream.len() > 0 && last_id < stream.last().map_or(StreamId::MIN, |el| el.id) {
+            Some("ERR The ID specified in XSETID is smaller than the target stream top item")
+        } else {
+            None
+        };
+
+        if let Some(content) = error {
+            return Ok(RespType::SimpleError {
+                content: content.into(),
+            });
+        }
+
+        stream.set_id(last_id, entries_added, max_deleted_id);
+        self.signal_modified_key(&key);
+        self.notify_keyspace_event(KeyspaceEvents::STREAM, "xsetid", &key);
+
+        Ok(RespType::SimpleString {
+            content: "OK".into(),
+        })
+    }
+
+    fn handle_xinfo(&mut self, subcommand: XInfoSubcommand) -> Result<RespType, RedisError> {
+        let key = match &subcommand {
+            XInfoSubcommand::Stream { key, .. }
+            | XInfoSubcommand::Groups { key }
+            | XInfoSubcommand::Consumers { key, .. } => key.clone(),
+        };
+
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
+
+        let Some(RedisType::Stream { value: stream }) = self.store.get(&key) else {
+            return Ok(RespType::SimpleError {
+                content: "ERR no such key".into(),
+            });
+        };
+
+        let now = stream::now_ms();
+        let bulk = |s: &str| RespType::BulkString {
+            data: s.as_bytes().to_vec(),
+        };
+        let int = |i: u64| RespType::Integer { integer: i as i64 };
+        let id = |id: StreamId| RespType::BulkString {
+            data: id.to_bytes(),
+        };
+        let optional_int = |i: Option<u64>| i.map_or(RespType::NullBulkString, int);
+
+        let elements = match subcommand {
+            XInfoSubcommand::Stream { full, .. } => {
+                let mut elements = vec![
+                    bulk("length"),
+                    int(stream.len() as u64),
+                    bulk("radix-tree-keys"),
+                    int(stream.nodes() as u64),
+                    bulk("radix-tree-nodes"),
+                    int(stream.nodes() as u64 + 1),
+                    bulk("last-generated-id"),
+                    id(stream.last_id()),
+                    bulk("max-deleted-entry-id"),
+                    id(stream.max_deleted_id()),
+                    bulk("entries-added"),
+                    int(stream.entries_added()),
+                    bulk("recorded-first-entry-id"),
+                    id(stream.first().map_or(StreamId::MIN, |el| el.id)),
+                ];
+
+                match full {
+                    None => elements.extend([
+                        bulk("groups"),
+                        int(stream.groups().len() as u64),
+                        bulk("first-entry"),
+                        stream
+                            .first()
+                            .map_or(RespType::NullBulkString, |el| el.to_resp()),
+                        bulk("last-entry"),
+                        stream
+                            .last()
+                            .map_or(RespType::NullBulkString, |el| el.to_resp()),
+                    ]),
+                    Some(count) => {
+                        let count = if count == 0 { usize::MAX } else { count };
+
+                        let entries = stream
+                            .range(StreamId::MIN, StreamId::MAX)
+                            .take(count)
+                            .map(|el| el.to_resp())
+                            .collect();
+
+                        let groups = stream
+                            .groups()
+                            .iter()
+                            .map(|(name, group)| {
+                                let pending = group
+                                    .pending()
+                                    .iter()
+                                    .take(count)
+                                    .map(|(pending_id, entry)| RespType::Array {
+                                        elements: vec![
+                                            id(*pending_id),
+                                            bulk(&entry.consumer),
+                                            int(entry.delivery_time),
+                                            int(entry.delivery_count),
+                                        ],
+                                    })
+                                    .collect();
+
+                                let consumers = group
+                                    .consumers()
+                                    .iter()
+                                    .map(|(consumer_name, consumer)| {
+                                        let pending = group
+                                            .pending()
+                                            .iter()
+                                            .filter(|(_, entry)| entry.consumer == *consumer_name)
+                                            .take(count)
+                                            .map(|(pending_id, entry)| RespType::Array {
+                                                elements: vec![
+                                                    id(*pending_id),
+                                                    int(entry.delivery_time),
+                                                    int(entry.delivery_count),
+                                                ],
+                                            })
+                                            .collect();
+
+                                        RespType::Array {
+                                            elements: vec![
+                                                bulk("name"),
+                                                bulk(consumer_name),
+                                                bulk("seen-time"),
+                                                int(consumer.seen_time),
+                                                bulk("active-time"),
+                                                consumer
+                                                    .active_time
+                                                    .map_or(RespType::Integer { integer: -1 }, int),
+                                                bulk("pel-count"),
+                                                int(consumer.pending_count() as u64),
+                                                bulk("pending"),
+                                                RespType::Array { elements: pending },
+                                            ],
+                                        }
+                                    })
+                                    .collect();
+
+                                RespType::Array {
+                                    elements: vec![
+                                        bulk("name"),
+                                        bulk(name),
+                                        bulk("last-delivered-id"),
+                                        id(group.last_delivered),
+                                        bulk("entries-read"),
+                                        optional_int(group.entries_read),
+                                        bulk("lag"),
+                                        optional_int(stream.lag(group)),
+                                        bulk("pel-count"),
+                                        int(group.pending().len() as u64),
+                                        bulk("pending"),
+                                        RespType::Array { elements: pending },
+                                        bulk("consumers"),
+                                        RespType::Array {
+                                            elements: consumers,
+                                        },
+                                    ],
+                                }
+                            })
+                            .collect();
+
+                        elements.extend([
+                            bulk("entries"),
+                            RespType::Array { elements: entries },
+                            bulk("groups"),
+                            RespType::Array { elements: groups },
+                        ]);
+                    }
+                }
+
+                elements
+            }
+            XInfoSubcommand::Groups { .. } => stream
+                .groups()
+                .iter()
+                .map(|(name, group)| RespType::Array {
+                    elements: vec![
+                        bulk("name"),
+                        bulk(name),
+                        bulk("consumers"),
+                        int(group.consumers().len() as u64),
+                        bulk("pending"),
+                        int(group.pending().len() as u64),
+                        bulk("last-delivered-id"),
+                        id(group.last_delivered),
+                        bulk("entries-read"),
+                        optional_int(group.entries_read),
+                        bulk("lag"),
+                        optional_int(stream.lag(group)),
+                    ],
+                })
+                .collect(),
+            XInfoSubcommand::Consumers { group, .. } => {
+                let Some(group_state) = stream.groups().get(&group) else {
+                    return Ok(RespType::SimpleError {
+                        content: format!(
+                            "NOGROUP No such consumer group '{group}' for key name '{key}'"
+                        ),
+                    });
+                };
+
+                group_state
+                    .consumers()
+                    .iter()
+                    .map(|(name, consumer)| RespType::Array {
+                        elements: vec![
+                            bulk("name"),
+                            bulk(name),
+                            bulk("pending"),
+                            int(consumer.pending_count() as u64),
+                            bulk("idle"),
+                            int(now.saturating_sub(consumer.seen_time)),
+                            bulk("inactive"),
+                            consumer
+                                .active_time
+                                .map_or(RespType::Integer { integer: -1 }, |t| {
+                                    int(now.saturating_sub(t))
+                                }),
+                        ],
+                    })
+                    .collect()
+            }
+        };
+
+        Ok(RespType::Array { elements })
+    }
+
+    fn handle_xrange(
+        &mut self,
+        key: String,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        reverse: bool,
+    ) -> Result<RespType, RedisError> {
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
+
+        let Some(RedisType::Stream { value }) = self.store.get(&key) else {
+            return Ok(RespType::Array { elements: vec![] });
+        };
+
+        let range = value.range(start, end);
+        let count = count.unwrap_or(usize::MAX);
+
+        let elements = if reverse {
+            range.rev().take(count).map(|el| el.to_resp()).collect()
+        } else {
+            range.take(count).map(|el| el.to_resp()).collect()
+        };
+
+        Ok(RespType::Array { elements })
+    }
+
+    fn handle_xread(
+        &mut self,
+        client_id: i32,
+        count: Option<usize>,
+        block: Option<time::Duration>,
+        keys: Vec<String>,
+        ids: Vec<XReadId>,
+    ) -> Result<RespType, RedisError> {
+        for key in keys.iter() {
+            if !self.ensure_type(key, "stream") {
+                return Ok(RespType::SimpleError {
+                    content: "WRONGTYPE Operation against a key holding the wrong kind of value"
+                        .into(),
+                });
+            }
+        }
+
+        let ids: Vec<StreamId> = keys
+            .iter()
+            .zip(ids)
+            .map(|(key, id)| {
+                let stream = match self.store.get(key) {
+                    Some(RedisType::Stream { value }) => Some(value),
+                    Some(_) => panic!("Illegal state"),
+                    None => None,
+                };
+
+                match id {
+                    XReadId::After { id } => id,
+                    XReadId::NewEntries => stream.map_or(StreamId::MIN, |s| s.last_id()),
+                    //the last entry is returned by reading right after its predecessor
+                    XReadId::LastEntry => stream
+                        .and_then(|s| s.last())
+                        .map_or(StreamId::MIN, |el| el.id.prev().unwrap_or(StreamId::MIN)),
+                }
+            })
+            .collect();
+
+        let read = self.xread_entries(&keys, &ids, count);
+        if !read.is_empty() {
+            return Ok(RespType::Array { elements: read });
+        }
+
+        let Some(block) = block else {
+            return Ok(RespType::NullArray);
+        };
+
+        //BLOCK 0 means waiting forever
+        let timeout = (!block.is_zero()).then(|| Instant::now() + block);
+
+        for key in keys.iter() {
+            self.xread_blocking_keys
+                .entry(key.clone())
+                .or_default()
+                .push(client_id);
+        }
+
+        self.waiting_clients.insert(
+            client_id,
+            (WaitingState::XRead { keys, ids, count }, timeout),
+        );
+
+        Err(RedisError::WouldBlock)
+    }
+
+    //1) 1) <key> 2) <entries> ... for each stream that has entries newer than the given id
+    fn xread_entries(
+        &self,
+        keys: &[String],
+        ids: &[StreamId],
+        count: Option<usize>,
+    ) -> Vec<RespType> {
+        keys.iter()
+            .zip(ids)
+            .filter_map(|(key, id)| match self.store.get(key) {
+                Some(RedisType::Stream { value }) => {
+                    let entries: Vec<RespType> = value
+                        .range(id.next()?, StreamId::MAX)
+                        .take(count.unwrap_or(usize::MAX))
+                        .map(|el| el.to_resp())
+                        .collect();
+
+                    (!entries.is_empty()).then(|| RespType::Array {
+                        elements: vec![
+                            RespType::BulkString {
+                                data: key.as_bytes().to_vec(),
+                            },
+                            RespType::Array { elements: entries },
+                        ],
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn handle_xlen(&mut self, key: String) -> Result<RespType, RedisError> {
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
+
+        let len = self.store.get(&key).map_or(0, |t| match t {
+            RedisType::Stream { value } => value.len(),
+            _ => panic!("Illegal state"),
+        });
+
+        Ok(RespType::Integer {
+            integer: len as i64,
+        })
+    }
+
+    fn stream_mut(&mut self, key: &str) -> Option<&mut Stream> {
+        match self.store.get_mut(key) {
+            Some(RedisType::Stream { value }) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn has_group(&self, key: &str, group: &str) -> bool {
+        match self.store.get(key) {
+            Some(RedisType::Stream { value }) => value.groups().contains_key(group),
+            _ => false,
+        }
+    }
+
+    fn handle_xgroup(&mut self, subcommand: XGroupSubcommand) -> Result<RespType, RedisError> {
+        let key = match &subcommand {
+            XGroupSubcommand::Create { key, .. }
+            | XGroupSubcommand::SetId { key, .. }
+            | XGroupSubcommand::Destroy { key, .. }
+            | XGroupSubcommand::CreateConsumer { key, .. }
+            | XGroupSubcommand::DelConsumer { key, .. } => key.clone(),
+        };
+
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
+
+        if let XGroupSubcommand::Create { mkstream: true, .. } = subcommand
+            && !self.store.contains_key(&key)
+        {
+            self.store.insert(
+                key.clone(),
+                RedisType::Stream {
+                    value: Stream::default(),
+                },
+            );
+            self.notify_keyspace_event(KeyspaceEvents::NEW, "new", &key);
+        }
+
+        let Some(stream) = self.stream_mut(&key) else {
+            return Ok(RespType::SimpleError {
+                content: "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.".into(),
+            });
+        };
+
+        let no_group = |group: &str| {
+            Ok(RespType::SimpleError {
+                content: format!("NOGROUP No such consumer group '{group}' for key name '{key}'"),
+            })
+        };
+
+        let resolve = |stream: &Stream, id: XGroupId| match id {
+            XGroupId::LastEntry => stream.last_id(),
+            XGroupId::Id { id } => id,
+        };
+
+        let now = stream::now_ms();
+
+        let (response, event) = match subcommand {
+            XGroupSubcommand::Create {
+                group,
+                id,
+                entries_read,
+                ..
+            } => {
+                let id = resolve(stream, id);
+                let entries_read = entries_read.or(stream.entries_read_at(id));
+
+                if !stream.create_group(&group, ConsumerGroup::new(id, entries_read)) {
+                    return Ok(RespType::SimpleError {
+                        content: "BUSYGROUP Consumer Group name already exists".into(),
+                    });
+                }
+
+                let response = RespType::SimpleString {
+                    content: "OK".into(),
+                };
+                (response, Some("xgroup-create"))
+            }
+            XGroupSubcommand::SetId {
+                group,
+                id,
+                entries_read,
+                ..
+            } => {
+                let id = resolve(stream, id);
+                let entries_read = entries_read.or(stream.entries_read_at(id));
+
+                let Some(group_state) = stream.group_mut(&group) else {
+                    return no_group(&group);
+                };
+
+                group_state.last_delivered = id;
+                group_state.entries_read = entries_read;
+
+                let response = RespType::SimpleString {
+                    content: "OK".into(),
+                };
+                (response, Some("xgroup-setid"))
+            }
+            XGroupSubcommand::Destroy { group, .. } => {
+                let destroyed = stream.destroy_group(&group);
+
+                let response = RespType::Integer {
+                    integer: destroyed as i64,
+                };
+                (response, destroyed.then_some("xgroup-destroy"))
+            }
+            XGroupSubcommand::CreateConsumer {
+                group, consumer, ..
+            } => match stream.group_mut(&group) {
+                Some(group) => {
+                    let created = group.create_consumer(&consumer, now);
+                    let response = RespType::Integer {
+                        integer: created as i64,
+                    };
+                    (response, created.then_some("xgroup-createconsumer"))
+                }
+                None => return no_group(&group),
+            },
+            XGroupSubcommand::DelConsumer {
+                group, consumer, ..
+            } => match stream.group_mut(&group) {
+                Some(group) => {
+                    let pending = group.delete_consumer(&consumer);
+                    let response = RespType::Integer {
+                        integer: pending.unwrap_or(0) as i64,
+                    };
+                    (response, pending.map(|_| "xgroup-delconsumer"))
+                }
+                None => return no_group(&group),
+            },
+        };
+
+        //readers blocked on a destroyed group are woken up, they will get an error
+        if event == Some("xgroup-destroy") {
+            self.notify_stream_readers(&key);
+        }
+
+        if let Some(event) = event {
+            self.signal_modified_key(&key);
+            self.notify_keyspace_event(KeyspaceEvents::STREAM, event, &key);
+        }
+
+        Ok(response)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_xreadgroup(
+        &mut self,
+        client_id: i32,
+        group: String,
+        consumer: String,
+        count: Option<usize>,
+        block: Option<time::Duration>,
+        noack: bool,
+        keys: Vec<String>,
+        ids: Vec<XReadGroupId>,
+    ) -> Result<RespType, RedisError> {
+        for key in keys.iter() {
+            if !self.ensure_type(key, "stream") {
+                return Ok(RespType::SimpleError {
+                    content: "WRONGTYPE Operation against a key holding the wrong kind of value"
+                        .into(),
+                });
+            }
+
+            if !self.has_group(key, &group) {
+                return Ok(RespType::SimpleError {
+                    content: format!(
+                        "NOGROUP No such key '{key}' or consumer group '{group}' in XREADGROUP with GROUP option"
+                    ),
+                });
+            }
+        }
+
+        let now = stream::now_ms();
+        let mut read = vec![];
+
+        for (key, id) in keys.iter().zip(ids.iter()) {
+            match id {
+                XReadGroupId::Undelivered => {
+                    read.extend(self.xreadgroup_new_entries(
+                        &group,
+                        &consumer,
+                        std::slice::from_ref(key),
+                        count,
+                        noack,
+                    ));
+                }
+                XReadGroupId::Pending { after } => {
+                    let stream = self.stream_mut(key).unwrap();
+                    let group = stream.group_mut(&group).unwrap();
+                    group.touch_consumer(&consumer, now);
+
+                    let history =
+                        group.consumer_history(&consumer, *after, count.unwrap_or(usize::MAX));
+
+                    //entries deleted from the stream while pending are returned with nil fields
+                    let entries = history
+                        .iter()
+                        .map(|id| match stream.get(id) {
+                            Some(el) => el.to_resp(),
+                            None => RespType::Array {
+                                elements: vec![
+                                    RespType::BulkString {
+                                        data: id.to_bytes(),
+                                    },
+                                    RespType::NullArray,
+                                ],
+                            },
+                        })
+                        .collect();
+
+                    read.push(RespType::Array {
+                        elements: vec![
+                            RespType::BulkString {
+                                data: key.as_bytes().to_vec(),
+                            },
+                            RespType::Array { elements: entries },
+                        ],
+                    });
+                }
+            }
+        }
+
+        if !read.is_empty() {
+            return Ok(RespType::Array { elements: read });
+        }
+
+        let Some(block) = block.filter(|_| ids.iter().all(|id| *id == XReadGroupId::Undelivered))
+        else {
+            return Ok(RespType::NullArray);
+        };
+
+        //BLOCK 0 means waiting forever
+        let timeout = (!block.is_zero()).then(|| Instant::now() + block);
+
+        for key in keys.iter() {
+            self.xread_blocking_keys
+                .entry(key.clone())
+                .or_default()
+                .push(client_id);
+        }
+
+        self.waiting_clients.insert(
+            client_id,
+            (
+                WaitingState::XReadGroup {
+                    group,
+                    consumer,
+                    keys,
+                    count,
+                    noack,
+                },
+                timeout,
+            ),
+        );
+
+        Err(RedisError::WouldBlock)
+    }
+
+    //delivers the entries never delivered to the group, same reply format as XREAD
+    fn xreadgroup_new_entries(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        keys: &[String],
+        count: Option<usize>,
+        noack: bool,
+    ) -> Vec<RespType> {
+        let now = stream::now_ms();
+
+        keys.iter()
+            .filter_map(|key| {
+                let stream = self.stream_mut(key)?;
+                let entries: Vec<RespType> = stream
+                    .read_group(group, consumer, count.unwrap_or(usize::MAX), noack, now)
+                    .iter()
+                    .map(|el| el.to_resp())
+                    .collect();
+                self.dirty += entries.len() as u64;
+
+                (!entries.is_empty()).then(|| RespType::Array {
+                    elements: vec![
+                        RespType::BulkString {
+                            data: key.as_bytes().to_vec(),
+                        },
+                        RespType::Array { elements: entries },
+                    ],
+                })
+            })
+            .collect()
+    }
+
+    fn handle_xack(
+        &mut self,
+        key: String,
+        group: String,
+        ids: Vec<StreamId>,
+    ) -> Result<RespType, RedisError> {
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
+
+        let acked = self
+            .stream_mut(&key)
+            .and_then(|stream| stream.group_mut(&group))
+            .map_or(0, |group| ids.iter().filter(|id| group.ack(id)).count());
+        self.dirty += acked as u64;
+
+        Ok(RespType::Integer {
+            integer: acked as i64,
+        })
+    }
+
+    fn handle_xpending(
+        &mut self,
+        key: String,
+        group: String,
+        range: Option<XPendingRange>,
+    ) -> Result<RespType, RedisError> {
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
+
+        let Some(group_state) = self
+            .stream_mut(&key)
+            .and_then(|stream| stream.group_mut(&group))
+        else {
+            return Ok(RespType::SimpleError {
+                content: format!("NOGROUP No such key '{key}' or consumer group '{group}'"),
+            });
+        };
+
+        let pending = group_state.pending();
+
+        let Some(range) = range else {
+            //1) <count> 2) <smallest id> 3) <greatest id> 4) 1) 1) <consumer> 2) <count> ...
+            let (Some((first, _)), Some((last, _))) =
+                (pending.first_key_value(), pending.last_key_value())
+            else {
+                return Ok(RespType::Array {
+                    elements: vec![
+                        RespType::Integer { integer: 0 },
+                        RespType::NullBulkString,
+                        RespType::NullBulkString,
+                        RespType::NullArray,
+                    ],
+                });
+            };
+
+            let consumers = group_state
+                .consumers()
+                .iter()
+                .filter(|(_, c)| c.pending_count() > 0)
+                .map(|(name, c)| RespType::Array {
+                    elements: vec![
+                        RespType::BulkString {
+                            data: name.as_bytes().to_vec(),
+                        },
+                        RespType::BulkString {
+                            data: c.pending_count().to_string().into_bytes(),
+                        },
+                    ],
+                })
+                .collect();
+
+            return Ok(RespType::Array {
+                elements: vec![
+                    RespType::Integer {
+                        integer: pending.len() as i64,
+                    },
+                    RespType::BulkString {
+                        data: first.to_bytes(),
+                    },
+                    RespType::BulkString {
+                        data: last.to_bytes(),
+                    },
+                    RespType::Array {
+                        elements: consumers,
+                    },
+                ],
+            });
+        };
+
+        let now = stream::now_ms();
+
+        //1) 1) <id> 2) <consumer> 3) <idle ms> 4) <delivery count> ...
+        let elements = if range.start > range.end {
+            vec![]
+        } else {
+            pending
+                .range(range.start..=range.end)
+                .filter(|(_, entry)| {
+                    range
+                        .consumer
+                        .as_ref()
+                        .is_none_or(|consumer| *consumer == entry.consumer)
+                })
+                .filter(|(_, entry)| range.min_idle.is_none_or(|idle| entry.idle(now) >= idle))
+                .take(range.count)
+                .map(|(id, entry)| RespType::Array {
+                    elements: vec![
+                        RespType::BulkString {
+                            data: id.to_bytes(),
+                        },
+                        RespType::BulkString {
+                            data: entry.consumer.as_bytes().to_vec(),
+                        },
+                        RespType::Integer {
+                            integer: entry.idle(now) as i64,
+                        },
+                        RespType::Integer {
+                            integer: entry.delivery_count as i64,
+                        },
+                    ],
+                })
+                .collect()
+        };
+
+        Ok(RespType::Array { elements })
+    }
+
+    fn handle_xclaim(
+        &mut self,
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+        options: XClaimOptions,
+    ) -> Result<RespType, RedisError> {
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
+
+        if !self.has_group(&key, &group) {
+            return Ok(RespType::SimpleError {
+                content: format!("NOGROUP No such key '{key}' or consumer group '{group}'"),
+            });
+        }
+
+        let now = stream::now_ms();
+        let delivery_time = match (options.time, options.idle) {
+            (Some(time), _) => time,
+            (None, Some(idle)) => now.saturating_sub(idle),
+            (None, None) => now,
+        };
+
+        let stream = self.stream_mut(&key).unwrap();
+        let mut claimed = vec![];
+
+        for id in ids {
+            let exists = stream.get(&id).is_some();
+            let group_state = stream.group_mut(&group).unwrap();
+
+            let delivery_count = match group_state.pending_entry(&id) {
+                //entries deleted from the stream are not claimable anymore
+                Some(_) if !exists => {
+                    group_state.ack(&id);
+                    continue;
+                }
+                Some(entry) if min_idle > 0 && entry.idle(now) < min_idle => continue,
+                Some(entry) => entry.delivery_count,
+                None if options.force && exists => 0,
+                None => continue,
+            };
+
+            let delivery_count = match (options.retry_count, options.justid) {
+                (Some(retry_count), _) => retry_count,
+                (None, true) => delivery_count,
+                (None, false) => delivery_count + 1,
+            };
+
+            group_state.claim(id, &consumer, now, delivery_time, delivery_count);
+            claimed.push(id);
+        }
+
+        let group_state = stream.group_mut(&group).unwrap();
+        group_state.touch_consumer(&consumer, now);
+        if let Some(last_id) = options.last_id
+            && last_id > group_state.last_delivered
+        {
+            group_state.last_delivered = last_id;
+        }
+
+        let elements = claimed
+            .iter()
+            .map(|id| match options.justid {
+                true => RespType::BulkString {
+                    data: id.to_bytes(),
+                },
+                false => stream.get(id).map(|el| el.to_resp()).unwrap(),
+            })
+            .collect();
+        self.dirty += claimed.len() as u64;
+
+        Ok(RespType::Array { elements })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_xautoclaim(
+        &mut self,
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: u64,
+        start: StreamId,
+        count: usize,
+        justid: bool,
+    ) -> Result<RespType, RedisError> {
+        if !self.ensure_type(&key, "stream") {
+            return Ok(RespType::SimpleError {
+                content: "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+            });
+        }
+
+        if !self.has_group(&key, &group) {
+            return Ok(RespType::SimpleError {
+                content: format!("NOGROUP No such key '{key}' or consumer group '{group}'"),
+            });
+        }
+
+        let now = stream::now_ms();
+        let stream = self.stream_mut(&key).unwrap();
+
+        //the scan is bounded so that a huge pending list does not block the server
+        let mut attempts = count * 10;
+        let mut to_claim = vec![];
+        let mut deleted = vec![];
+        let mut next = StreamId::MIN;
+
+        for (id, entry) in stream.groups()[&group].pending().range(start..) {
+            if attempts == 0 || to_claim.len() == count {
+                next = *id;
+                break;
+            }
+
+            attempts -= 1;
+
+            if stream.get(id).is_none() {
+                deleted.push(*id);
+            } else if entry.idle(now) >= min_idle {
+                to_claim.push((*id, entry.delivery_count));
+            }
+        }
+
+        let group_state = stream.group_mut(&group).unwrap();
+
+        for id in deleted.iter() {
+            group_state.ack(id);
+        }
+
+        for (id, delivery_count) in to_claim.iter() {
+            let delivery_count = if justid {
+                *delivery_count
+            } else {
+                delivery_count + 1
+            };
+
+            group_state.claim(*id, &consumer, now, now, delivery_count);
+        }
+
+        let claimed: Vec<StreamId> = to_claim.into_iter().map(|(id, _)| id).collect();
+
+        stream
+            .group_mut(&group)
+            .unwrap()
+            .touch_consumer(&consumer, now);
+
+        let claimed: Vec<RespType> = claimed
+            .iter()
+            .map(|id| match justid {
+                true => RespType::BulkString {
+                    data: id.to_bytes(),
+                },
+                false => stream.get(id).map(|el| el.to_resp()).unwrap(),
+            })
+            .collect();
+        self.dirty += (claimed.len() + deleted.len()) as u64;
+
+        //1) <next start id> 2) <claimed entries> 3) <deleted ids>
+        Ok(RespType::Array {
+            elements: vec![
+                RespType::BulkString {
+                    data: next.to_bytes(),
+                },
+                RespType::Array { elements: claimed },
+                RespType::Array {
+                    elements: deleted
+                        .iter()
+                        .map(|id| RespType::BulkString {
+                            data: id.to_bytes(),
+                        })
+                        .collect(),
+                },
+            ],
+        })
+    }
+}
+
+/// Resolves the id requested by an XADD against the current top item of the stream, enforcing
+/// strictly increasing ids and filling in the auto generated parts.
+fn next_stream_id(requested: XAddId, last: Option<StreamId>) -> Result<StreamId, String> {
+    let last = last.unwrap_or(StreamId::MIN);
+
+    let id = match requested {
+        XAddId::Explicit { id } => id,
+        XAddId::AutoSeq { ms } if ms == last.ms => StreamId::new(
+            ms,
+            last.seq.checked_add(1).ok_or(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+            )?,
+        ),
+        XAddId::AutoSeq { ms: 0 } => StreamId::new(0, 1),
+        XAddId::AutoSeq { ms } => StreamId::new(ms, 0),
+        //if the clock went backwards we keep generating ids from the last one
+        XAddId::Auto => match stream::now_ms() {
+            ms if ms > last.ms => StreamId::new(ms, 0),
+            _ => last.next().ok_or(
+                "ERR The stream has exhausted the last possible ID, unable to add more items",
+            )?,
+        },
+    };
+
+    if id == StreamId::MIN {
+        return Err("ERR The ID specified in XADD must be greater than 0-0".into());
+    }
+
+    if id <= last {
+        return Err(
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                .into(),
+        );
+    }
+
+    Ok(id)
+}
+
+//reply sent to a blocked client when it stops waiting without being served, the ones waiting
+//for acknowledgements are told how many they got
+fn timeout_reply(state: &WaitingState) -> RespType {
+    match state {
+        WaitingState::BlPop { .. } => RespType::NullBulkString,
+        WaitingState::XRead { .. } | WaitingState::XReadGroup { .. } => RespType::NullArray,
+        WaitingState::Wait { acked, .. } => RespType::Integer {
+            integer: *acked as i64,
+        },
+        WaitingState::WaitAof { local, acked, .. } => RespType::Array {
+            elements: vec![
+                RespType::Integer {
+                    integer: *local as i64,
+                },
+                RespType::Integer {
+                    integer: *acked as i64,
+                },
+            ],
+        },
+    }
+}
+
+fn handle_error(msg: String) -> Result<RespType, RedisError> {
+    Ok(RespType::SimpleError { content: msg })
+}
+
+fn handle_echo(to_echo: String) -> Result<RespType, RedisError> {
+    Ok(RespType::BulkString {
+        data: to_echo.into_bytes(),
+    })
+}
+
+fn handle_ping(message: Option<String>) -> Result<RespType, RedisError> {
+    Ok(match message {
+        Some(message) => RespType::BulkString {
+            data: message.into_bytes(),
+        },
+        None => RespType::SimpleString {
+            content: "PONG".into(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{command::Command, resp::RespType};
+
+    #[test]
+    fn test_handle_lrange() {
+        let mut rds = super::Redis::default();
+
+        let key = String::from("test key");
+
+        let rpush_cmd = crate::command::Command::RPush {
+            key: key.clone(),
+            elements: vec![
+                String::from("first"),
+                String::from("second"),
+                String::from("third"),
+                String::from("fourth"),
+                String::from("fifth"),
+            ],
+        };
+
+        let sz = rds.handle_command(rpush_cmd, 0).unwrap();
+        assert_eq!(sz, RespType::Integer { integer: 5 });
+
+        //test stop > len
+        let lrange_cmd = Command::LRange {
+            key: key.clone(),
+            start: 0,
+            stop: 1234,
+        };
         let res = rds.handle_command(lrange_cmd, 0);
         let expected = RespType::Array {
             elements: vec![
@@ -604,7 +2325,7 @@ mod test {
 
         assert!(res.is_ok_and(|val| {
             assert_eq!(val, expected);
-            return true;
+            true
         }));
 
         //negative start and stop, non empty
@@ -630,7 +2351,7 @@ mod test {
 
         assert!(res.is_ok_and(|val| {
             assert_eq!(val, expected);
-            return true;
+            true
         }));
 
         let lrange_cmd = Command::LRange {
@@ -643,7 +2364,877 @@ mod test {
 
         assert!(res.is_ok_and(|val| {
             assert_eq!(val, expected);
-            return true;
+            true
+        }));
+
+        //popped to empty, the list is gone and reads as empty
+        let lpop_cmd = Command::LPop {
+            key: key.clone(),
+            count: 5,
+        };
+        rds.handle_command(lpop_cmd, 0).unwrap();
+        let lrange_cmd = Command::LRange {
+            key: key.clone(),
+            start: 0,
+            stop: -1,
+        };
+        let res = rds.handle_command(lrange_cmd, 0).unwrap();
+        assert_eq!(res, RespType::Array { elements: vec![] });
+        let res = rds.handle_command(Command::LLen { key: key.clone() }, 0);
+        assert_eq!(res.unwrap(), RespType::Integer { integer: 0 });
+        let lpop_cmd = Command::LPop { key, count: 1 };
+        let res = rds.handle_command(lpop_cmd, 0).unwrap();
+        assert_eq!(res, RespType::NullBulkString);
+    }
+
+    #[test]
+    fn test_handle_xadd_ids() {
+        use crate::command::XAddId;
+        use crate::redis::stream::StreamId;
+
+        let mut rds = super::Redis::default();
+        let xadd = |id: XAddId| Command::XAdd {
+            key: "stream".into(),
+            id,
+            elements: vec![("field".into(), "value".into())],
+            options: Default::default(),
+        };
+
+        let res = rds.handle_command(xadd(XAddId::Explicit { id: StreamId::MIN }), 0);
+        assert_eq!(
+            res.unwrap(),
+            RespType::SimpleError {
+                content: "ERR The ID specified in XADD must be greater than 0-0".into()
+            }
+        );
+
+        let res = rds.handle_command(xadd(XAddId::AutoSeq { ms: 0 }), 0);
+        assert_eq!(
+            res.unwrap(),
+            RespType::BulkString {
+                data: b"0-1".to_vec()
+            }
+        );
+
+        let res = rds.handle_command(
+            xadd(XAddId::Explicit {
+                id: StreamId::new(5, 3),
+            }),
+            0,
+        );
+        assert_eq!(
+            res.unwrap(),
+            RespType::BulkString {
+                data: b"5-3".to_vec()
+            }
+        );
+
+        let res = rds.handle_command(xadd(XAddId::AutoSeq { ms: 5 }), 0);
+        assert_eq!(
+            res.unwrap(),
+            RespType::BulkString {
+                data: b"5-4".to_vec()
+            }
+        );
+
+        let res = rds.handle_command(
+            xadd(XAddId::Explicit {
+                id: StreamId::new(5, 4),
+            }),
+            0,
+        );
+        assert_eq!(
+            res.unwrap(),
+            RespType::SimpleError {
+                content:
+                    "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                        .into()
+            }
+        );
+
+        let res = rds.handle_command(xadd(XAddId::AutoSeq { ms: 4 }), 0);
+        assert!(matches!(res.unwrap(), RespType::SimpleError { .. }));
+
+        let res = rds.handle_command(xadd(XAddId::Auto), 0).unwrap();
+        let RespType::BulkString { data } = res else {
+            panic!("expected a bulk string, got {res:?}")
+        };
+        let id = String::from_utf8(data)
+            .unwrap()
+            .parse::<StreamId>()
+            .unwrap();
+        assert!(id > StreamId::new(5, 4));
+        assert_eq!(id.seq, 0);
+    }
+
+    #[test]
+    fn test_handle_xrange() {
+        use crate::command::XAddId;
+        use crate::redis::stream::StreamId;
+
+        let mut rds = super::Redis::default();
+        for seq in 1..=4 {
+            let xadd = Command::XAdd {
+                key: "stream".into(),
+                id: XAddId::Explicit {
+                    id: StreamId::new(1, seq),
+                },
+                elements: vec![("b".into(), seq.to_string()), ("a".into(), "x".into())],
+                options: Default::default(),
+            };
+            rds.handle_command(xadd, 0).unwrap();
+        }
+
+        let entry = |seq: u64| RespType::Array {
+            elements: vec![
+                RespType::BulkString {
+                    data: StreamId::new(1, seq).to_bytes(),
+                },
+                RespType::Array {
+                    elements: vec![
+                        RespType::BulkString {
+                            data: b"b".to_vec(),
+                        },
+                        RespType::BulkString {
+                            data: seq.to_string().into_bytes(),
+                        },
+                        RespType::BulkString {
+                            data: b"a".to_vec(),
+                        },
+                        RespType::BulkString {
+                            data: b"x".to_vec(),
+                        },
+                    ],
+                },
+            ],
+        };
+
+        let xrange = Command::XRange {
+            key: "stream".into(),
+            start: StreamId::new(1, 2),
+            end: StreamId::MAX,
+            count: Some(2),
+        };
+        assert_eq!(
+            rds.handle_command(xrange, 0).unwrap(),
+            RespType::Array {
+                elements: vec![entry(2), entry(3)]
+            }
+        );
+
+        let xrevrange = Command::XRevRange {
+            key: "stream".into(),
+            end: StreamId::MAX,
+            start: StreamId::MIN,
+            count: Some(3),
+        };
+        assert_eq!(
+            rds.handle_command(xrevrange, 0).unwrap(),
+            RespType::Array {
+                elements: vec![entry(4), entry(3), entry(2)]
+            }
+        );
+
+        let xlen = Command::XLen {
+            key: "stream".into(),
+        };
+        assert_eq!(
+            rds.handle_command(xlen, 0).unwrap(),
+            RespType::Integer { integer: 4 }
+        );
+    }
+
+    #[test]
+    fn test_handle_xread_block() {
+        use crate::command::{XAddId, XReadId};
+        use crate::redis::{RedisError, stream::StreamId};
+
+        let mut rds = super::Redis::default();
+        let xadd = |ms: u64| Command::XAdd {
+            key: "stream".into(),
+            id: XAddId::Explicit {
+                id: StreamId::new(ms, 0),
+            },
+            elements: vec![("f".into(), "v".into())],
+            options: Default::default(),
+        };
+        let xread = |id: XReadId| Command::XRead {
+            count: None,
+            block: Some(std::time::Duration::ZERO),
+            keys: vec!["other".into(), "stream".into()],
+            ids: vec![XReadId::NewEntries, id],
+        };
+
+        rds.handle_command(xadd(1), 0).unwrap();
+
+        //there is nothing newer than the current last id, both readers block
+        let res = rds.handle_command(xread(XReadId::NewEntries), 1);
+        assert!(matches!(res, Err(RedisError::WouldBlock)));
+        let res = rds.handle_command(
+            xread(XReadId::After {
+                id: StreamId::new(1, 0),
+            }),
+            2,
+        );
+        assert!(matches!(res, Err(RedisError::WouldBlock)));
+
+        rds.handle_command(xadd(2), 0).unwrap();
+        rds.compute_ready();
+
+        let expected = RespType::Array {
+            elements: vec![RespType::Array {
+                elements: vec![
+                    RespType::BulkString {
+                        data: b"stream".to_vec(),
+                    },
+                    RespType::Array {
+                        elements: vec![RespType::Array {
+                            elements: vec![
+                                RespType::BulkString {
+                                    data: b"2-0".to_vec(),
+                                },
+                                RespType::Array {
+                                    elements: vec![
+                                        RespType::BulkString {
+                                            data: b"f".to_vec(),
+                                        },
+                                        RespType::BulkString {
+                                            data: b"v".to_vec(),
+                                        },
+                                    ],
+                                },
+                            ],
+                        }],
+                    },
+                ],
+            }],
+        };
+
+        let mut ready = std::mem::take(&mut rds.ready);
+        ready.sort_by_key(|(client, _)| *client);
+        assert_eq!(ready, vec![(1, expected.clone()), (2, expected)]);
+
+        //the last entry is returned right away
+        let res = rds.handle_command(xread(XReadId::LastEntry), 1);
+        assert!(
+            res.is_ok_and(|val| matches!(val, RespType::Array { elements } if elements.len() == 1))
+        );
+    }
+
+    #[test]
+    fn test_handle_consumer_groups() {
+        use crate::command::{XAddId, XClaimOptions, XGroupId, XGroupSubcommand, XReadGroupId};
+        use crate::redis::{RedisError, stream::StreamId};
+
+        let mut rds = super::Redis::default();
+        let bulk = |s: &str| RespType::BulkString {
+            data: s.as_bytes().to_vec(),
+        };
+        let xreadgroup = |consumer: &str, id: XReadGroupId| Command::XReadGroup {
+            group: "group".into(),
+            consumer: consumer.into(),
+            count: Some(2),
+            block: Some(std::time::Duration::ZERO),
+            noack: false,
+            keys: vec!["stream".into()],
+            ids: vec![id],
+        };
+
+        let create = Command::XGroup {
+            subcommand: XGroupSubcommand::Create {
+                key: "stream".into(),
+                group: "group".into(),
+                id: XGroupId::LastEntry,
+                mkstream: false,
+                entries_read: None,
+            },
+        };
+        assert!(matches!(
+            rds.handle_command(create.clone(), 0).unwrap(),
+            RespType::SimpleError { content } if content.starts_with("ERR The XGROUP subcommand requires the key to exist")
+        ));
+
+        for seq in 1..=3 {
+            let xadd = Command::XAdd {
+                key: "stream".into(),
+                id: XAddId::Explicit {
+                    id: StreamId::new(1, seq),
+                },
+                elements: vec![("f".into(), seq.to_string())],
+                options: Default::default(),
+            };
+            rds.handle_command(xadd, 0).unwrap();
+        }
+
+        let create = Command::XGroup {
+            subcommand: XGroupSubcommand::Create {
+                key: "stream".into(),
+                group: "group".into(),
+                id: XGroupId::Id { id: StreamId::MIN },
+                mkstream: false,
+                entries_read: None,
+            },
+        };
+        assert_eq!(
+            rds.handle_command(create.clone(), 0).unwrap(),
+            RespType::SimpleString {
+                content: "OK".into()
+            }
+        );
+        assert_eq!(
+            rds.handle_command(create, 0).unwrap(),
+            RespType::SimpleError {
+                content: "BUSYGROUP Consumer Group name already exists".into()
+            }
+        );
+
+        //alice gets the first two entries, bob the last one
+        let res = rds.handle_command(xreadgroup("alice", XReadGroupId::Undelivered), 1);
+        assert!(res.is_ok_and(|val| {
+            val.serialize()
+                .windows(3)
+                .filter(|w| *w == b"1-1" || *w == b"1-2")
+                .count()
+                == 2
         }));
+        let res = rds.handle_command(xreadgroup("bob", XReadGroupId::Undelivered), 2);
+        assert!(res.is_ok_and(|val| val.serialize().windows(3).any(|w| w == b"1-3")));
+
+        //nothing left to deliver, bob blocks until a new entry is added
+        let res = rds.handle_command(xreadgroup("bob", XReadGroupId::Undelivered), 2);
+        assert!(matches!(res, Err(RedisError::WouldBlock)));
+
+        let xadd = Command::XAdd {
+            key: "stream".into(),
+            id: XAddId::Explicit {
+                id: StreamId::new(2, 0),
+            },
+            elements: vec![("f".into(), "4".into())],
+            options: Default::default(),
+        };
+        rds.handle_command(xadd, 0).unwrap();
+        rds.compute_ready();
+        assert_eq!(rds.ready.len(), 1);
+        assert_eq!(rds.ready[0].0, 2);
+
+        //alice history
+        let res = rds
+            .handle_command(
+                xreadgroup(
+                    "alice",
+                    XReadGroupId::Pending {
+                        after: StreamId::MIN,
+                    },
+                ),
+                1,
+            )
+            .unwrap();
+        assert!(
+            res.serialize()
+                .windows(3)
+                .filter(|w| *w == b"1-1" || *w == b"1-2")
+                .count()
+                == 2
+        );
+
+        let xpending = Command::XPending {
+            key: "stream".into(),
+            group: "group".into(),
+            range: None,
+        };
+        assert_eq!(
+            rds.handle_command(xpending.clone(), 0).unwrap(),
+            RespType::Array {
+                elements: vec![
+                    RespType::Integer { integer: 4 },
+                    bulk("1-1"),
+                    bulk("2-0"),
+                    RespType::Array {
+                        elements: vec![
+                            RespType::Array {
+                                elements: vec![bulk("alice"), bulk("2")]
+                            },
+                            RespType::Array {
+                                elements: vec![bulk("bob"), bulk("2")]
+                            },
+                        ]
+                    },
+                ]
+            }
+        );
+
+        let xclaim = Command::XClaim {
+            key: "stream".into(),
+            group: "group".into(),
+            consumer: "bob".into(),
+            min_idle: 0,
+            ids: vec![StreamId::new(1, 1)],
+            options: XClaimOptions {
+                justid: true,
+                ..Default::default()
+            },
+        };
+        assert_eq!(
+            rds.handle_command(xclaim, 0).unwrap(),
+            RespType::Array {
+                elements: vec![bulk("1-1")]
+            }
+        );
+
+        let xautoclaim = Command::XAutoClaim {
+            key: "stream".into(),
+            group: "group".into(),
+            consumer: "carol".into(),
+            min_idle: 0,
+            start: StreamId::MIN,
+            count: 3,
+            justid: true,
+        };
+        assert_eq!(
+            rds.handle_command(xautoclaim, 0).unwrap(),
+            RespType::Array {
+                elements: vec![
+                    bulk("2-0"),
+                    RespType::Array {
+                        elements: vec![bulk("1-1"), bulk("1-2"), bulk("1-3")]
+                    },
+                    RespType::Array { elements: vec![] },
+                ]
+            }
+        );
+
+        let xack = Command::XAck {
+            key: "stream".into(),
+            group: "group".into(),
+            ids: vec![
+                StreamId::new(1, 1),
+                StreamId::new(1, 1),
+                StreamId::new(9, 9),
+            ],
+        };
+        assert_eq!(
+            rds.handle_command(xack, 0).unwrap(),
+            RespType::Integer { integer: 1 }
+        );
+
+        let delconsumer = Command::XGroup {
+            subcommand: XGroupSubcommand::DelConsumer {
+                key: "stream".into(),
+                group: "group".into(),
+                consumer: "carol".into(),
+            },
+        };
+        assert_eq!(
+            rds.handle_command(delconsumer, 0).unwrap(),
+            RespType::Integer { integer: 2 }
+        );
+    }
+
+    #[test]
+    fn test_handle_xdel_xtrim_xinfo() {
+        use crate::command::{StreamTrim, TrimStrategy, XAddId, XAddOptions, XInfoSubcommand};
+        use crate::redis::stream::StreamId;
+
+        let mut rds = super::Redis::default();
+        let xadd = |ms: u64, options: XAddOptions| Command::XAdd {
+            key: "stream".into(),
+            id: XAddId::Explicit {
+                id: StreamId::new(ms, 0),
+            },
+            elements: vec![("f".into(), ms.to_string())],
+            options,
+        };
+        let length = |rds: &mut super::Redis| {
+            rds.handle_command(
+                Command::XLen {
+                    key: "stream".into(),
+                },
+                0,
+            )
+            .unwrap()
+        };
+
+        //NOMKSTREAM does not create the key
+        let nomkstream = XAddOptions {
+            nomkstream: true,
+            trim: None,
+        };
+        let res = rds.handle_command(xadd(1, nomkstream), 0).unwrap();
+        assert_eq!(res, RespType::NullBulkString);
+        assert_eq!(length(&mut rds), RespType::Integer { integer: 0 });
+
+        for ms in 1..=5 {
+            rds.handle_command(xadd(ms, Default::default()), 0).unwrap();
+        }
+
+        let res = rds
+            .handle_command(
+                Command::XDel {
+                    key: "stream".into(),
+                    ids: vec![
+                        StreamId::new(2, 0),
+                        StreamId::new(2, 0),
+                        StreamId::new(9, 0),
+                    ],
+                },
+                0,
+            )
+            .unwrap();
+        assert_eq!(res, RespType::Integer { integer: 1 });
+        assert_eq!(length(&mut rds), RespType::Integer { integer: 4 });
+
+        let res = rds
+            .handle_command(
+                Command::XTrim {
+                    key: "stream".into(),
+                    trim: StreamTrim {
+                        strategy: TrimStrategy::MaxLen { len: 2 },
+                        approx: false,
+                        limit: None,
+                    },
+                },
+                0,
+            )
+            .unwrap();
+        assert_eq!(res, RespType::Integer { integer: 2 });
+
+        //MAXLEN on XADD keeps the stream capped
+        let maxlen = XAddOptions {
+            nomkstream: false,
+            trim: Some(StreamTrim {
+                strategy: TrimStrategy::MaxLen { len: 2 },
+                approx: false,
+                limit: None,
+            }),
+        };
+        rds.handle_command(xadd(6, maxlen), 0).unwrap();
+        assert_eq!(length(&mut rds), RespType::Integer { integer: 2 });
+
+        let res = rds
+            .handle_command(
+                Command::XSetId {
+                    key: "stream".into(),
+                    last_id: StreamId::new(5, 0),
+                    entries_added: None,
+                    max_deleted_id: None,
+                },
+                0,
+            )
+            .unwrap();
+        assert!(matches!(res, RespType::SimpleError { .. }));
+
+        let res = rds
+            .handle_command(
+                Command::XInfo {
+                    subcommand: XInfoSubcommand::Stream {
+                        key: "stream".into(),
+                        full: None,
+                    },
+                },
+                0,
+            )
+            .unwrap();
+        let RespType::Array { elements } = res else {
+            panic!("XINFO STREAM should return an array");
+        };
+        let field = |name: &str| {
+            let pos = elements
+                .iter()
+                .position(|el| {
+                    *el == RespType::BulkString {
+                        data: name.as_bytes().to_vec(),
+                    }
+                })
+                .unwrap();
+            elements[pos + 1].clone()
+        };
+        assert_eq!(field("length"), RespType::Integer { integer: 2 });
+        assert_eq!(field("entries-added"), RespType::Integer { integer: 6 });
+        //only XDEL moves the max deleted id, trimming does not
+        assert_eq!(
+            field("max-deleted-entry-id"),
+            RespType::BulkString {
+                data: b"2-0".to_vec()
+            }
+        );
+        assert_eq!(
+            field("last-generated-id"),
+            RespType::BulkString {
+                data: b"6-0".to_vec()
+            }
+        );
+    }
+
+    #[test]
+    fn test_keyspace_notifications() {
+        let mut rds = super::Redis::default();
+        let request = |args: &[&str]| {
+            Command::from(RespType::Array {
+                elements: args
+                    .iter()
+                    .map(|arg| RespType::BulkString {
+                        data: arg.as_bytes().to_vec(),
+                    })
+                    .collect(),
+            })
+        };
+
+        //nothing is published by default
+        rds.handle_command(request(&["SET", "k", "v"]), 0).unwrap();
+        assert!(rds.publications.is_empty());
+
+        rds.set_notify_keyspace_events("Elgx".parse().unwrap());
+
+        //string events are not enabled
+        rds.handle_command(request(&["SET", "k", "v"]), 0).unwrap();
+        assert!(rds.publications.is_empty());
+
+        rds.handle_command(request(&["RPUSH", "list", "a"]), 0)
+            .unwrap();
+        rds.handle_command(request(&["LPOP", "list"]), 0).unwrap();
+        rds.handle_command(request(&["SET", "ttl", "v", "PX", "0"]), 0)
+            .unwrap();
+        rds.active_expire_cycle();
+
+        let expected = [
+            ("__keyevent@0__:rpush", "list"),
+            ("__keyevent@0__:lpop", "list"),
+            ("__keyevent@0__:del", "list"),
+            ("__keyevent@0__:expire", "ttl"),
+            ("__keyevent@0__:expired", "ttl"),
+        ]
+        .map(|(channel, message)| (channel.to_string(), message.to_string()));
+        assert_eq!(rds.publications, expected);
+
+        //the expired key is gone, the emptied list too
+        let res = rds.handle_command(request(&["TYPE", "ttl"]), 0).unwrap();
+        assert_eq!(
+            res,
+            RespType::SimpleString {
+                content: "none".into()
+            }
+        );
+        let res = rds.handle_command(request(&["TYPE", "list"]), 0).unwrap();
+        assert_eq!(
+            res,
+            RespType::SimpleString {
+                content: "none".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_evict() {
+        let mut rds = super::Redis::default();
+        let request = |args: &[&str]| {
+            Command::from(RespType::Array {
+                elements: args
+                    .iter()
+                    .map(|arg| RespType::BulkString {
+                        data: arg.as_bytes().to_vec(),
+                    })
+                    .collect(),
+            })
+        };
+        rds.handle_command(request(&["SET", "persistent", "v"]), 0)
+            .unwrap();
+        rds.handle_command(request(&["SET", "later", "v", "EX", "200"]), 0)
+            .unwrap();
+        rds.handle_command(request(&["SET", "sooner", "v", "EX", "100"]), 0)
+            .unwrap();
+        rds.propagated.clear();
+
+        assert!(!rds.evict("noeviction"));
+
+        //the key closest to expiring goes first, the volatile ones only
+        assert!(rds.evict("volatile-ttl"));
+        assert!(!rds.store.contains_key("sooner"));
+        assert!(rds.evict("volatile-random"));
+        assert!(!rds.store.contains_key("later") && rds.expires.is_empty());
+        assert!(!rds.evict("volatile-ttl"));
+        assert!(!rds.evict("volatile-random"));
+
+        assert!(rds.evict("allkeys-random"));
+        assert!(rds.store.is_empty());
+        assert!(!rds.evict("allkeys-random"));
+
+        //replicas and the AOF delete the same keys
+        let propagated: Vec<_> = rds.propagated.iter().map(RespType::serialize).collect();
+        assert_eq!(
+            propagated,
+            ["sooner", "later", "persistent"].map(|key| format!(
+                "*2\r\n$3\r\nDEL\r\n${}\r\n{key}\r\n",
+                key.len()
+            )
+            .into_bytes())
+        );
+    }
+
+    #[test]
+    fn test_cancel_blocking() {
+        let mut rds = super::Redis::default();
+
+        let res = rds.handle_command(
+            Command::BlPop {
+                keys: vec!["list".into()],
+                timeout: None,
+            },
+            1,
+        );
+        assert!(matches!(res, Err(super::RedisError::WouldBlock)));
+
+        assert_eq!(rds.cancel_blocking(1), Some(RespType::NullBulkString));
+        assert_eq!(rds.cancel_blocking(1), None);
+
+        //the client is no longer waiting, nobody gets served by the push
+        rds.handle_command(
+            Command::RPush {
+                key: "list".into(),
+                elements: vec!["a".into()],
+            },
+            0,
+        )
+        .unwrap();
+        rds.compute_ready();
+        assert!(rds.ready.is_empty());
+    }
+
+    #[test]
+    fn test_blpop_drained_list() {
+        let mut rds = super::Redis::default();
+
+        let res = rds.handle_command(
+            Command::BlPop {
+                keys: vec!["list".into()],
+                timeout: None,
+            },
+            1,
+        );
+        assert!(matches!(res, Err(super::RedisError::WouldBlock)));
+
+        //pushed then drained before the blocked client is served, as in a transaction
+        rds.handle_command(
+            Command::RPush {
+                key: "list".into(),
+                elements: vec!["a".into(), "b".into()],
+            },
+            0,
+        )
+        .unwrap();
+        rds.handle_command(
+            Command::LPop {
+                key: "list".into(),
+                count: 2,
+            },
+            0,
+        )
+        .unwrap();
+        rds.compute_ready();
+        assert!(rds.ready.is_empty());
+
+        //still blocked, served by the next push
+        rds.handle_command(
+            Command::RPush {
+                key: "list".into(),
+                elements: vec!["c".into()],
+            },
+            0,
+        )
+        .unwrap();
+        rds.compute_ready();
+        assert_eq!(
+            rds.ready,
+            vec![(
+                1,
+                RespType::Array {
+                    elements: vec![
+                        RespType::BulkString {
+                            data: b"list".to_vec()
+                        },
+                        RespType::BulkString {
+                            data: b"c".to_vec()
+                        },
+                    ]
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut rds = super::Redis::default();
+        let set = |key: &str| {
+            Command::from(RespType::Array {
+                elements: ["SET", key, "v"]
+                    .iter()
+                    .map(|arg| RespType::BulkString {
+                        data: arg.as_bytes().to_vec(),
+                    })
+                    .collect(),
+            })
+        };
+        let watch = |key: &str| Command::Watch {
+            keys: vec![key.into()],
+        };
+
+        rds.handle_command(watch("a"), 1).unwrap();
+        rds.handle_command(set("b"), 2).unwrap();
+        assert!(!rds.unwatch(1));
+
+        rds.handle_command(watch("a"), 1).unwrap();
+        rds.handle_command(set("a"), 2).unwrap();
+        assert!(rds.unwatch(1));
+
+        //flushing only affects the watchers of keys that existed
+        rds.handle_command(watch("a"), 1).unwrap();
+        rds.handle_command(watch("missing"), 2).unwrap();
+        rds.handle_command(Command::FlushAll, 0).unwrap();
+        assert!(rds.unwatch(1));
+        assert!(!rds.unwatch(2));
+
+        //and so does FLUSHDB, while swapping the only database with itself changes nothing
+        rds.handle_command(set("a"), 0).unwrap();
+        rds.handle_command(watch("a"), 1).unwrap();
+        let swapdb = |index2| Command::SwapDb { index1: 0, index2 };
+        assert_eq!(
+            rds.handle_command(swapdb(0), 0).unwrap(),
+            RespType::SimpleString {
+                content: "OK".into()
+            }
+        );
+        assert_eq!(
+            rds.handle_command(swapdb(1), 0).unwrap(),
+            RespType::SimpleError {
+                content: "ERR DB index is out of range".into()
+            }
+        );
+        assert!(!rds.unwatch(1));
+        rds.handle_command(watch("a"), 2).unwrap();
+        rds.handle_command(Command::FlushDb, 0).unwrap();
+        assert!(rds.unwatch(2));
+        assert!(rds.store.is_empty());
+
+        //emptying a list deletes it, which counts as a modification
+        rds.handle_command(
+            Command::RPush {
+                key: "list".into(),
+                elements: vec!["x".into()],
+            },
+            0,
+        )
+        .unwrap();
+        rds.handle_command(watch("list"), 1).unwrap();
+        rds.handle_command(Command::Unwatch, 1).unwrap();
+        rds.handle_command(watch("list"), 2).unwrap();
+        rds.handle_command(
+            Command::LPop {
+                key: "list".into(),
+                count: 1,
+            },
+            0,
+        )
+        .unwrap();
+        assert!(!rds.unwatch(1));
+        assert!(rds.unwatch(2));
     }
 }
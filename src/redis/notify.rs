@@ -0,0 +1,149 @@
+use std::{fmt::Display, io, str::FromStr};
+
+/// Classes of keyspace events to publish, as configured through `notify-keyspace-events`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeyspaceEvents(u16);
+
+impl KeyspaceEvents {
+    //K, events published on __keyspace@<db>__:<key> with the event as message
+    pub const KEYSPACE: Self = Self(1 << 0);
+    //E, events published on __keyevent@<db>__:<event> with the key as message
+    pub const KEYEVENT: Self = Self(1 << 1);
+    //g, generic commands (DEL, EXPIRE, ...)
+    pub const GENERIC: Self = Self(1 << 2);
+    //$
+    pub const STRING: Self = Self(1 << 3);
+    //l
+    pub const LIST: Self = Self(1 << 4);
+    //s
+    pub const SET: Self = Self(1 << 5);
+    //h
+    pub const HASH: Self = Self(1 << 6);
+    //z
+    pub const ZSET: Self = Self(1 << 7);
+    //x, a key reached its time to live
+    pub const EXPIRED: Self = Self(1 << 8);
+    //e, a key was evicted to honour the memory limit
+    pub const EVICTED: Self = Self(1 << 9);
+    //t
+    pub const STREAM: Self = Self(1 << 10);
+    //m, read of a missing key, not part of A as it is very noisy
+    pub const KEY_MISS: Self = Self(1 << 11);
+    //n, creation of a new key, not part of A either
+    pub const NEW: Self = Self(1 << 12);
+    //A, alias for g$lshzxet
+    pub const ALL: Self = Self(
+        Self::GENERIC.0
+            | Self::STRING.0
+            | Self::LIST.0
+            | Self::SET.0
+            | Self::HASH.0
+            | Self::ZSET.0
+            | Self::EXPIRED.0
+            | Self::EVICTED.0
+            | Self::STREAM.0,
+    );
+
+    const FLAGS: [(char, Self); 11] = [
+        ('g', Self::GENERIC),
+        ('$', Self::STRING),
+        ('l', Self::LIST),
+        ('s', Self::SET),
+        ('h', Self::HASH),
+        ('z', Self::ZSET),
+        ('x', Self::EXPIRED),
+        ('e', Self::EVICTED),
+        ('t', Self::STREAM),
+        ('m', Self::KEY_MISS),
+        ('n', Self::NEW),
+    ];
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn intersects(&self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// True if an event of the given class has to be published on at least one channel kind.
+    pub fn enabled(&self, class: Self) -> bool {
+        self.intersects(class) && self.intersects(Self(Self::KEYSPACE.0 | Self::KEYEVENT.0))
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.chars().try_fold(Self::default(), |flags, c| {
+            let flag = match c {
+                'A' => Self::ALL,
+                'K' => Self::KEYSPACE,
+                'E' => Self::KEYEVENT,
+                c => Self::FLAGS
+                    .iter()
+                    .find(|(flag, _)| *flag == c)
+                    .map(|(_, class)| *class)
+                    .ok_or(io::Error::other(
+                        "Invalid event class character. Use 'Ag$lshzxeKEtmn'.",
+                    ))?,
+            };
+
+            Ok(Self(flags.0 | flag.0))
+        })
+    }
+}
+
+impl Display for KeyspaceEvents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut classes = if self.contains(Self::ALL) {
+            "A".to_string()
+        } else {
+            Self::FLAGS
+                .iter()
+                .filter(|(_, class)| self.intersects(*class) && Self::ALL.contains(*class))
+                .map(|(flag, _)| *flag)
+                .collect()
+        };
+
+        if self.contains(Self::KEYSPACE) {
+            classes.push('K');
+        }
+        if self.contains(Self::KEYEVENT) {
+            classes.push('E');
+        }
+        if self.contains(Self::KEY_MISS) {
+            classes.push('m');
+        }
+        if self.contains(Self::NEW) {
+            classes.push('n');
+        }
+
+        write!(f, "{classes}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeyspaceEvents;
+
+    #[test]
+    fn test_keyspace_events_flags() {
+        let flags = "Ex".parse::<KeyspaceEvents>().unwrap();
+        assert!(flags.enabled(KeyspaceEvents::EXPIRED));
+        assert!(!flags.enabled(KeyspaceEvents::STRING));
+        assert_eq!(flags.to_string(), "xE");
+
+        //without K or E nothing is published
+        let flags = "g$".parse::<KeyspaceEvents>().unwrap();
+        assert!(!flags.enabled(KeyspaceEvents::STRING));
+
+        let flags = "KEA".parse::<KeyspaceEvents>().unwrap();
+        assert_eq!(flags.to_string(), "AKE");
+        assert!(!flags.enabled(KeyspaceEvents::KEY_MISS));
+
+        assert_eq!("".parse::<KeyspaceEvents>().unwrap().to_string(), "");
+        assert!("Kw".parse::<KeyspaceEvents>().is_err());
+    }
+}
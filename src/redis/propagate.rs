@@ -0,0 +1,508 @@
+//! The changes made to the dataset, expressed as the commands that replay them exactly: the
+//! parts depending on the time or on generated values are replaced by what they resolved to.
+
+use crate::{
+    command::{Command, StreamTrim, TrimStrategy, XGroupId, XGroupSubcommand, XReadGroupId},
+    resp::RespType,
+};
+
+use super::{Redis, RedisType, stream};
+
+impl Redis {
+    /// Queues a change for the event loop to write to the AOF.
+    pub(crate) fn propagate<A: AsRef<[u8]>>(&mut self, args: &[A]) {
+        self.propagated.push(request(args));
+    }
+
+    /// Wraps the changes queued since the given number of them in MULTI/EXEC, for them to be
+    /// replayed all together or not at all. Transactions can't be nested, a script run by EXEC
+    /// is part of the outer one.
+    pub(crate) fn propagate_atomically(&mut self, since: usize) {
+        let (multi, exec) = (request(&["MULTI"]), request(&["EXEC"]));
+        let mut changes: Vec<RespType> = self
+            .propagated
+            .drain(since..)
+            .filter(|change| *change != multi && *change != exec)
+            .collect();
+
+        if changes.len() > 1 {
+            changes.insert(0, multi);
+            changes.push(exec);
+        }
+        self.propagated.extend(changes);
+    }
+
+    //called after a write command changed the dataset, with its reply
+    pub(super) fn propagate_command(&mut self, cmd: Command, reply: &RespType) {
+        match cmd {
+            Command::Set {
+                key,
+                value,
+                options,
+            } => {
+                let mut args = vec!["SET".to_string(), key, value];
+                if let Some(expire) = options.expire() {
+                    let at = stream::now_ms() + expire.as_millis() as u64;
+                    args.extend(["PXAT".to_string(), at.to_string()]);
+                }
+                self.propagate(&args);
+            }
+            Command::RPush { key, elements } => {
+                self.propagate(&[&["RPUSH".to_string(), key][..], &elements].concat())
+            }
+            Command::LPush { key, elements } => {
+                self.propagate(&[&["LPUSH".to_string(), key][..], &elements].concat())
+            }
+            Command::LPop { key, count } => self.propagate(&["LPOP", &key, &count.to_string()]),
+            //served right away, from the first key holding elements
+            Command::BlPop { .. } => {
+                if let RespType::Array { elements } = reply
+                    && let Some(RespType::BulkString { data: key }) = elements.first()
+                {
+                    self.propagate(&[b"LPOP".as_slice(), key.as_slice()]);
+                }
+            }
+            Command::XAdd {
+                key,
+                elements,
+                options,
+                ..
+            } => {
+                let RespType::BulkString { data: id } = reply else {
+                    return;
+                };
+                let mut args = vec!["XADD".to_string(), key];
+                if options.nomkstream {
+                    args.push("NOMKSTREAM".into());
+                }
+                if let Some(trim) = options.trim {
+                    args.extend(self.trim_args(&args[1], trim));
+                }
+                args.push(String::from_utf8_lossy(id).into_owned());
+                for (field, value) in elements {
+                    args.extend([field, value]);
+                }
+                self.propagate(&args);
+            }
+            Command::XTrim { key, trim } => {
+                let trim = self.trim_args(&key, trim);
+                self.propagate(&[&["XTRIM".to_string(), key][..], &trim].concat())
+            }
+            Command::XDel { key, ids } => {
+                let mut args = vec!["XDEL".to_string(), key];
+                args.extend(ids.iter().map(|id| id.to_string()));
+                self.propagate(&args);
+            }
+            Command::XSetId {
+                key,
+                last_id,
+                entries_added,
+                max_deleted_id,
+            } => {
+                let mut args = vec!["XSETID".to_string(), key, last_id.to_string()];
+                if let Some(entries_added) = entries_added {
+                    args.extend(["ENTRIESADDED".into(), entries_added.to_string()]);
+                }
+                if let Some(max_deleted_id) = max_deleted_id {
+                    args.extend(["MAXDELETEDID".into(), max_deleted_id.to_string()]);
+                }
+                self.propagate(&args);
+            }
+            Command::XGroup { subcommand } => self.propagate(&xgroup_args(subcommand)),
+            //the same entries are delivered again when replayed in order, never blocking
+            Command::XReadGroup {
+                group,
+                consumer,
+                count,
+                noack,
+                keys,
+                ids,
+                ..
+            } => {
+                let ids = ids
+                    .iter()
+                    .map(|id| match id {
+                        XReadGroupId::Undelivered => ">".to_string(),
+                        XReadGroupId::Pending { after } => after.to_string(),
+                    })
+                    .collect();
+                self.propagate(&xreadgroup_args(group, consumer, count, noack, keys, ids));
+            }
+            Command::XAck { key, group, ids } => {
+                let mut args = vec!["XACK".to_string(), key, group];
+                args.extend(ids.iter().map(|id| id.to_string()));
+                self.propagate(&args);
+            }
+            //only the claimed entries are claimed again, regardless of their idle time then
+            Command::XClaim {
+                key,
+                group,
+                consumer,
+                options,
+                ..
+            } => {
+                let time = match (options.time, options.idle) {
+                    (Some(time), _) => time,
+                    (None, idle) => stream::now_ms().saturating_sub(idle.unwrap_or(0)),
+                };
+                let mut args = vec!["XCLAIM".to_string(), key, group, consumer, "0".into()];
+                args.extend(claimed_ids(reply));
+                args.extend(["TIME".into(), time.to_string()]);
+                if let Some(retry_count) = options.retry_count {
+                    args.extend(["RETRYCOUNT".into(), retry_count.to_string()]);
+                }
+                if options.force {
+                    args.push("FORCE".into());
+                }
+                if options.justid {
+                    args.push("JUSTID".into());
+                }
+                if let Some(last_id) = options.last_id {
+                    args.extend(["LASTID".into(), last_id.to_string()]);
+                }
+                self.propagate(&args);
+            }
+            //1) <next start id> 2) <claimed entries> 3) <deleted ids>
+            Command::XAutoClaim {
+                key,
+                group,
+                consumer,
+                justid,
+                ..
+            } => {
+                let RespType::Array { elements } = reply else {
+                    return;
+                };
+                let (claimed, deleted) = match elements.as_slice() {
+                    [_, claimed, deleted] => (claimed_ids(claimed), claimed_ids(deleted)),
+                    _ => return,
+                };
+
+                if !claimed.is_empty() {
+                    let mut args = vec![
+                        "XCLAIM".to_string(),
+                        key.clone(),
+                        group.clone(),
+                        consumer,
+                        "0".into(),
+                    ];
+                    args.extend(claimed);
+                    args.extend(["TIME".into(), stream::now_ms().to_string()]);
+                    if justid {
+                        args.push("JUSTID".into());
+                    }
+                    self.propagate(&args);
+                }
+                if !deleted.is_empty() {
+                    self.propagate(&[&["XACK".to_string(), key, group][..], &deleted].concat());
+                }
+            }
+            Command::FlushAll => self.propagate(&["FLUSHALL"]),
+            Command::FlushDb => self.propagate(&["FLUSHDB"]),
+            Command::SwapDb { index1, index2 } => {
+                self.propagate(&["SWAPDB", &index1.to_string(), &index2.to_string()])
+            }
+            Command::Del { keys } => self.propagate(&[&["DEL".to_string()][..], &keys].concat()),
+            //restored already expired, the key was only deleted
+            Command::Restore { key, .. } if !self.store.contains_key(&key) => {
+                self.propagate(&["DEL", &key])
+            }
+            //with the time to live the key got, whenever the change is replayed
+            Command::Restore {
+                key,
+                ttl,
+                payload,
+                replace,
+                absttl,
+                ..
+            } => {
+                let at = match (ttl, absttl) {
+                    (0, _) | (_, true) => ttl,
+                    (ttl, false) => stream::now_ms() + ttl,
+                };
+                let mut args = vec![
+                    b"RESTORE".to_vec(),
+                    key.into_bytes(),
+                    at.to_string().into_bytes(),
+                    payload,
+                    b"ABSTTL".to_vec(),
+                ];
+                if replace {
+                    args.push(b"REPLACE".to_vec());
+                }
+                self.propagate(&args);
+            }
+            cmd => unreachable!("{cmd:?} is not a write command"),
+        }
+    }
+
+    //an approximate trim stops at node boundaries, which a reload does not keep: the exact trim
+    //to the entries it left is propagated instead
+    fn trim_args(&self, key: &str, trim: StreamTrim) -> Vec<String> {
+        let strategy = match (trim.approx, self.store.get(key)) {
+            (false, _) => trim.strategy,
+            (true, Some(RedisType::Stream { value })) => match (trim.strategy, value.first()) {
+                (TrimStrategy::MinId { .. }, Some(first)) => TrimStrategy::MinId { id: first.id },
+                _ => TrimStrategy::MaxLen {
+                    len: value.len() as u64,
+                },
+            },
+            (true, _) => TrimStrategy::MaxLen { len: 0 },
+        };
+
+        match strategy {
+            TrimStrategy::MaxLen { len } => vec!["MAXLEN".into(), "=".into(), len.to_string()],
+            TrimStrategy::MinId { id } => vec!["MINID".into(), "=".into(), id.to_string()],
+        }
+    }
+}
+
+fn request<A: AsRef<[u8]>>(args: &[A]) -> RespType {
+    RespType::Array {
+        elements: args
+            .iter()
+            .map(|arg| RespType::BulkString {
+                data: arg.as_ref().to_vec(),
+            })
+            .collect(),
+    }
+}
+
+/// The arguments of an XREADGROUP reading the given ids, without BLOCK.
+pub(super) fn xreadgroup_args(
+    group: String,
+    consumer: String,
+    count: Option<usize>,
+    noack: bool,
+    keys: Vec<String>,
+    ids: Vec<String>,
+) -> Vec<String> {
+    let mut args = vec!["XREADGROUP".to_string(), "GROUP".into(), group, consumer];
+    if let Some(count) = count {
+        args.extend(["COUNT".into(), count.to_string()]);
+    }
+    if noack {
+        args.push("NOACK".into());
+    }
+    args.push("STREAMS".into());
+    args.extend(keys);
+    args.extend(ids);
+    args
+}
+
+fn xgroup_args(subcommand: XGroupSubcommand) -> Vec<String> {
+    let group_id = |id: XGroupId| match id {
+        XGroupId::LastEntry => "$".to_string(),
+        XGroupId::Id { id } => id.to_string(),
+    };
+
+    match subcommand {
+        XGroupSubcommand::Create {
+            key,
+            group,
+            id,
+            mkstream,
+            entries_read,
+        } => {
+            let mut args = vec!["XGROUP".into(), "CREATE".into(), key, group, group_id(id)];
+            if mkstream {
+                args.push("MKSTREAM".into());
+            }
+            if let Some(entries_read) = entries_read {
+                args.extend(["ENTRIESREAD".into(), entries_read.to_string()]);
+            }
+            args
+        }
+        XGroupSubcommand::SetId {
+            key,
+            group,
+            id,
+            entries_read,
+        } => {
+            let mut args = vec!["XGROUP".into(), "SETID".into(), key, group, group_id(id)];
+            if let Some(entries_read) = entries_read {
+                args.extend(["ENTRIESREAD".into(), entries_read.to_string()]);
+            }
+            args
+        }
+        XGroupSubcommand::Destroy { key, group } => {
+            vec!["XGROUP".into(), "DESTROY".into(), key, group]
+        }
+        XGroupSubcommand::CreateConsumer {
+            key,
+            group,
+            consumer,
+        } => vec![
+            "XGROUP".into(),
+            "CREATECONSUMER".into(),
+            key,
+            group,
+            consumer,
+        ],
+        XGroupSubcommand::DelConsumer {
+            key,
+            group,
+            consumer,
+        } => vec!["XGROUP".into(), "DELCONSUMER".into(), key, group, consumer],
+    }
+}
+
+//the ids of a reply listing entries, or just their ids with JUSTID
+fn claimed_ids(reply: &RespType) -> Vec<String> {
+    let RespType::Array { elements } = reply else {
+        return vec![];
+    };
+
+    elements
+        .iter()
+        .filter_map(|element| match element {
+            RespType::BulkString { data } => Some(data),
+            RespType::Array { elements } => match elements.first() {
+                Some(RespType::BulkString { data }) => Some(data),
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|id| String::from_utf8_lossy(id).into_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{command::Command, redis::Redis, resp::RespType};
+
+    fn run(rds: &mut Redis, args: &[&str]) -> RespType {
+        let request = RespType::Array {
+            elements: args
+                .iter()
+                .map(|arg| RespType::BulkString {
+                    data: arg.as_bytes().to_vec(),
+                })
+                .collect(),
+        };
+        rds.handle_command(Command::from(request), 1).unwrap()
+    }
+
+    //the propagated changes as lists of arguments
+    fn take_propagated(rds: &mut Redis) -> Vec<Vec<String>> {
+        std::mem::take(&mut rds.propagated)
+            .into_iter()
+            .map(|change| match change {
+                RespType::Array { elements } => elements
+                    .into_iter()
+                    .map(|arg| match arg {
+                        RespType::BulkString { data } => String::from_utf8(data).unwrap(),
+                        arg => panic!("unexpected argument {arg:?}"),
+                    })
+                    .collect(),
+                change => panic!("unexpected change {change:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_propagate_commands() {
+        let mut rds = Redis::default();
+
+        run(&mut rds, &["SET", "key", "value", "EX", "100"]);
+        let RespType::BulkString { data: id } = run(&mut rds, &["XADD", "s", "*", "f", "v"]) else {
+            panic!("XADD replies with the id");
+        };
+        let id = String::from_utf8(id).unwrap();
+        run(&mut rds, &["RPUSH", "list", "a"]);
+        run(&mut rds, &["BLPOP", "list", "0"]);
+        //nothing changed, nothing to propagate
+        run(&mut rds, &["LPOP", "list"]);
+        run(&mut rds, &["GET", "key"]);
+
+        let propagated = take_propagated(&mut rds);
+        assert_eq!(propagated.len(), 4);
+        let [set, key, value, pxat, at] = propagated[0].as_slice() else {
+            panic!("unexpected SET {:?}", propagated[0]);
+        };
+        assert_eq!(
+            (set.as_str(), key.as_str(), value.as_str()),
+            ("SET", "key", "value")
+        );
+        assert_eq!(pxat, "PXAT");
+        let at = at.parse::<u64>().unwrap();
+        assert!(at > super::stream::now_ms() + 99_000);
+        assert_eq!(propagated[1], ["XADD", "s", &id, "f", "v"]);
+        assert_eq!(propagated[2], ["RPUSH", "list", "a"]);
+        assert_eq!(propagated[3], ["LPOP", "list"]);
+
+        //the claimed entries are claimed again whatever their idle time, the deleted ones acked
+        run(&mut rds, &["XADD", "s", "*", "f", "w"]);
+        run(&mut rds, &["XGROUP", "CREATE", "s", "g", "0"]);
+        run(
+            &mut rds,
+            &["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", ">"],
+        );
+        run(&mut rds, &["XDEL", "s", &id]);
+        take_propagated(&mut rds);
+
+        run(
+            &mut rds,
+            &["XAUTOCLAIM", "s", "g", "other", "0", "0", "JUSTID"],
+        );
+        let propagated = take_propagated(&mut rds);
+        assert_eq!(propagated.len(), 2);
+        assert_eq!(propagated[0][..5], ["XCLAIM", "s", "g", "other", "0"]);
+        assert_eq!(propagated[0][6], "TIME");
+        assert_eq!(propagated[0][8], "JUSTID");
+        assert_eq!(propagated[1], ["XACK", "s", "g", &id]);
+
+        rds.propagate(&["SET", "a", "1"]);
+        rds.propagate(&["MULTI"]);
+        rds.propagate(&["SET", "b", "2"]);
+        rds.propagate(&["EXEC"]);
+        rds.propagate_atomically(0);
+        assert_eq!(
+            take_propagated(&mut rds),
+            [
+                vec!["MULTI"],
+                vec!["SET", "a", "1"],
+                vec!["SET", "b", "2"],
+                vec!["EXEC"]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_propagate_approximate_trims() {
+        let mut rds = Redis::default();
+        for seq in 1..=300 {
+            run(&mut rds, &["XADD", "s", &format!("1-{seq}"), "f", "v"]);
+        }
+        for seq in 1..=60 {
+            run(&mut rds, &["XDEL", "s", &format!("1-{seq}")]);
+        }
+
+        //the replica loads the stream without the deleted entries, so with other node boundaries
+        let mut replica = Redis::default();
+        replica.load_rdb(&rds.rdb_snapshot(&[], false)).unwrap();
+        take_propagated(&mut rds);
+
+        run(&mut rds, &["XTRIM", "s", "MAXLEN", "~", "50"]);
+        run(
+            &mut rds,
+            &[
+                "XADD", "s", "MINID", "~", "1-250", "LIMIT", "100", "1-301", "f", "v",
+            ],
+        );
+        let propagated = take_propagated(&mut rds);
+        assert_eq!(propagated[0], ["XTRIM", "s", "MAXLEN", "=", "100"]);
+        assert_eq!(
+            propagated[1],
+            ["XADD", "s", "MINID", "=", "1-201", "1-301", "f", "v"]
+        );
+
+        for change in propagated {
+            let args: Vec<&str> = change.iter().map(String::as_str).collect();
+            run(&mut replica, &args);
+        }
+        for request in [&["XLEN", "s"][..], &["XRANGE", "s", "-", "+"]] {
+            assert_eq!(run(&mut replica, request), run(&mut rds, request));
+        }
+    }
+}
@@ -0,0 +1,887 @@
+//! Snapshots of the keyspace in the RDB format, as written by SAVE and loaded at startup.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    alloc::used_memory,
+    rdb::{
+        OPCODE_AUX, OPCODE_EOF, OPCODE_EXPIRETIME, OPCODE_EXPIRETIME_MS, OPCODE_FREQ,
+        OPCODE_FUNCTION2, OPCODE_IDLE, OPCODE_MODULE_AUX, OPCODE_RESIZEDB, OPCODE_SELECTDB,
+        OPCODE_SLOT_INFO, RDB_MAGIC, RDB_VERSION, Reader, TYPE_HASH, TYPE_HASH_LISTPACK,
+        TYPE_HASH_ZIPLIST, TYPE_LIST, TYPE_LIST_QUICKLIST, TYPE_LIST_QUICKLIST_2,
+        TYPE_LIST_ZIPLIST, TYPE_SET, TYPE_SET_INTSET, TYPE_SET_LISTPACK, TYPE_STREAM_LISTPACKS,
+        TYPE_STREAM_LISTPACKS_2, TYPE_STREAM_LISTPACKS_3, TYPE_STRING, TYPE_ZSET, TYPE_ZSET_2,
+        TYPE_ZSET_LISTPACK, TYPE_ZSET_ZIPLIST, crc64, into_string, intset_entries,
+        listpack_entries, open_payload, seal_payload, write_double, write_len, write_millis,
+        write_string, ziplist_entries,
+    },
+    resp::RespType,
+};
+
+use super::{
+    KeyspaceEvents, REDIS_VERSION, Redis, RedisError, RedisType, StoredValue, stream::Stream,
+    stream::now_ms,
+};
+
+//quicklist nodes holding a single large element instead of a listpack
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+
+impl Redis {
+    /// Serializes the whole dataset, the function libraries already written by the caller.
+    /// aof_base tells that the snapshot is the base of an AOF rather than a dump file.
+    pub fn rdb_snapshot(&self, functions: &[u8], aof_base: bool) -> Vec<u8> {
+        let mut out = RDB_MAGIC.to_vec();
+        out.extend_from_slice(format!("{RDB_VERSION:04}").as_bytes());
+
+        let ctime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        for (name, value) in [
+            ("redis-ver", REDIS_VERSION.to_string()),
+            ("redis-bits", "64".to_string()),
+            ("ctime", ctime.to_string()),
+            ("used-mem", used_memory().to_string()),
+            ("aof-base", (aof_base as u8).to_string()),
+        ] {
+            out.push(OPCODE_AUX);
+            write_string(&mut out, name.as_bytes());
+            write_string(&mut out, value.as_bytes());
+        }
+        out.extend_from_slice(functions);
+
+        let now = Instant::now();
+        let keys: Vec<_> = self
+            .store
+            .iter()
+            .filter(|(key, _)| self.ttls.get(*key).is_none_or(|ttl| *ttl > now))
+            .collect();
+        if !keys.is_empty() {
+            out.push(OPCODE_SELECTDB);
+            write_len(&mut out, 0);
+            out.push(OPCODE_RESIZEDB);
+            write_len(&mut out, keys.len() as u64);
+            let volatile = keys
+                .iter()
+                .filter(|(key, _)| self.ttls.contains_key(*key))
+                .count();
+            write_len(&mut out, volatile as u64);
+        }
+
+        let now_ms = now_ms();
+        for (key, value) in keys {
+            if let Some(ttl) = self.ttls.get(key) {
+                out.push(OPCODE_EXPIRETIME_MS);
+                write_millis(&mut out, now_ms + (*ttl - now).as_millis() as u64);
+            }
+            out.push(value_type(value));
+            write_string(&mut out, key.as_bytes());
+            write_value(&mut out, value);
+        }
+
+        out.push(OPCODE_EOF);
+        let crc = crc64(0, &out);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out
+    }
+
+    /// Replaces the dataset with the content of an RDB file, returning the code of the function
+    /// libraries it carries. Nothing changes if the file can't be read in full.
+    pub fn load_rdb(&mut self, data: &[u8]) -> io::Result<Vec<String>> {
+        let mut reader = Reader::new(data);
+        if reader.read_bytes(RDB_MAGIC.len())? != RDB_MAGIC {
+            return Err(io::Error::other(
+                "Wrong signature trying to load DB from file",
+            ));
+        }
+        let version = std::str::from_utf8(reader.read_bytes(4)?)
+            .ok()
+            .and_then(|version| version.parse::<u16>().ok())
+            .filter(|version| (1..=RDB_VERSION).contains(version))
+            .ok_or_else(|| io::Error::other("Can't handle RDB format version"))?;
+
+        let mut store = HashMap::new();
+        let mut expires = BTreeSet::new();
+        let mut ttls = HashMap::new();
+        let mut libraries = vec![];
+        let mut expire_at = None;
+        let (now, now_ms) = (Instant::now(), now_ms());
+
+        loop {
+            let value_type = match reader.read_u8()? {
+                OPCODE_EOF => break,
+                OPCODE_AUX => {
+                    reader.read_string()?;
+                    reader.read_string()?;
+                    continue;
+                }
+                OPCODE_SELECTDB => {
+                    let db = reader.read_len()?;
+                    if db != 0 {
+                        return Err(io::Error::other(format!(
+                            "Database {db} found in the file, only database 0 is supported"
+                        )));
+                    }
+                    continue;
+                }
+                OPCODE_RESIZEDB => {
+                    reader.read_len()?;
+                    reader.read_len()?;
+                    continue;
+                }
+                OPCODE_SLOT_INFO => {
+                    for _ in 0..3 {
+                        reader.read_len()?;
+                    }
+                    continue;
+                }
+                OPCODE_EXPIRETIME_MS => {
+                    expire_at = Some(reader.read_millis()?);
+                    continue;
+                }
+                OPCODE_EXPIRETIME => {
+                    expire_at = Some(reader.read_u32_le()? as u64 * 1000);
+                    continue;
+                }
+                //eviction hints, every key is kept regardless
+                OPCODE_FREQ => {
+                    reader.read_u8()?;
+                    continue;
+                }
+                OPCODE_IDLE => {
+                    reader.read_len()?;
+                    continue;
+                }
+                OPCODE_FUNCTION2 => {
+                    libraries.push(into_string(reader.read_string()?)?);
+                    continue;
+                }
+                OPCODE_MODULE_AUX => {
+                    return Err(io::Error::other("Modules are not supported"));
+                }
+                value_type => value_type,
+            };
+
+            let key = into_string(reader.read_string()?)?;
+            let value = read_value(&mut reader, value_type)?;
+            let expire_at = expire_at.take();
+
+            if expire_at.is_some_and(|expire_at| expire_at <= now_ms) {
+                continue;
+            }
+            if is_empty(&value) {
+                continue;
+            }
+
+            if let Some(expire_at) = expire_at {
+                let ttl = now + Duration::from_millis(expire_at - now_ms);
+                ttls.insert(key.clone(), ttl);
+                expires.insert((ttl, key.clone()));
+            }
+            store.insert(key, value);
+        }
+
+        //files older than version 5 have no checksum, a zero one means it was not computed
+        if version >= 5 {
+            let body = reader.position();
+            let crc = u64::from_le_bytes(reader.read_bytes(8)?.try_into().expect("8 bytes"));
+            if crc != 0 && crc != crc64(0, &data[..body]) {
+                return Err(io::Error::other("Wrong RDB checksum"));
+            }
+        }
+
+        let current = &self.store;
+        self.watches
+            .touch_all(|key| current.contains_key(key) || store.contains_key(key));
+
+        self.store = store;
+        self.expires = expires;
+        self.ttls = ttls;
+        Ok(libraries)
+    }
+
+    /// The value of the key serialized for RESTORE, along with its remaining time to live.
+    /// None if the key does not exist.
+    pub(crate) fn dump(&self, key: &str) -> Option<(Vec<u8>, Option<Duration>)> {
+        let value = self.store.get(key)?;
+        let now = Instant::now();
+        let ttl = match self.ttls.get(key).copied() {
+            Some(ttl) if ttl <= now => return None,
+            ttl => ttl.map(|ttl| ttl - now),
+        };
+
+        let mut payload = vec![value_type(value)];
+        write_value(&mut payload, value);
+        Some((seal_payload(payload), ttl))
+    }
+
+    pub(super) fn handle_dump(&mut self, key: String) -> Result<RespType, RedisError> {
+        Ok(match self.dump(&key) {
+            Some((payload, _)) => RespType::BulkString { data: payload },
+            None => RespType::NullBulkString,
+        })
+    }
+
+    //the time to live is in milliseconds, a unix time with absttl, 0 meaning none
+    pub(super) fn handle_restore(
+        &mut self,
+        key: String,
+        millis: u64,
+        payload: &[u8],
+        replace: bool,
+        absttl: bool,
+    ) -> Result<RespType, RedisError> {
+        let error = |content: &str| {
+            Ok(RespType::SimpleError {
+                content: content.into(),
+            })
+        };
+
+        let Some(values) = open_payload(payload) else {
+            return error("ERR DUMP payload version or checksum are wrong");
+        };
+        let now = Instant::now();
+        let exists =
+            self.store.contains_key(&key) && self.ttls.get(&key).is_none_or(|ttl| *ttl > now);
+        if exists && !replace {
+            return error("BUSYKEY Target key name already exists.");
+        }
+
+        let mut reader = Reader::new(values);
+        let value = reader
+            .read_u8()
+            .and_then(|value_type| read_value(&mut reader, value_type))
+            .ok()
+            .filter(|value| reader.is_empty() && !is_empty(value));
+        let Some(value) = value else {
+            return error("ERR Bad data format");
+        };
+
+        let now_ms = now_ms();
+        let expire_at = match (millis, absttl) {
+            (0, _) => None,
+            (at, true) => Some(at),
+            (millis, false) => Some(now_ms + millis),
+        };
+        //a key restored already expired is only deleted
+        if expire_at.is_some_and(|expire_at| expire_at <= now_ms) {
+            if self.remove_key(&key) {
+                self.signal_modified_key(&key);
+                self.notify_keyspace_event(KeyspaceEvents::GENERIC, "del", &key);
+            }
+            return Ok(RespType::SimpleString {
+                content: "OK".into(),
+            });
+        }
+
+        self.remove_key(&key);
+        let volatile = expire_at.is_some();
+        self.set_ttl(
+            &key,
+            expire_at.map(|expire_at| now + Duration::from_millis(expire_at - now_ms)),
+        );
+        self.store.insert(key.clone(), value);
+
+        self.signal_modified_key(&key);
+        self.notify_keyspace_event(KeyspaceEvents::GENERIC, "restore", &key);
+        if volatile {
+            self.notify_keyspace_event(KeyspaceEvents::GENERIC, "expire", &key);
+        }
+        Ok(RespType::SimpleString {
+            content: "OK".into(),
+        })
+    }
+}
+
+fn is_empty(value: &RedisType) -> bool {
+    match value {
+        RedisType::String { .. } | RedisType::Stream { .. } => false,
+        RedisType::List { elements } => elements.is_empty(),
+        RedisType::Set { members } => members.is_empty(),
+        RedisType::Hash { fields } => fields.is_empty(),
+        RedisType::SortedSet { members } => members.is_empty(),
+    }
+}
+
+fn value_type(value: &RedisType) -> u8 {
+    match value {
+        RedisType::String { .. } => TYPE_STRING,
+        RedisType::List { .. } => TYPE_LIST,
+        RedisType::Set { .. } => TYPE_SET,
+        RedisType::Hash { .. } => TYPE_HASH,
+        RedisType::SortedSet { .. } => TYPE_ZSET_2,
+        RedisType::Stream { .. } => TYPE_STREAM_LISTPACKS_3,
+    }
+}
+
+//values are written in the plain encodings, which every Redis version can read
+fn write_value(out: &mut Vec<u8>, value: &RedisType) {
+    match value {
+        RedisType::String { value } => write_string(out, &value.data),
+        RedisType::List { elements } => {
+            write_len(out, elements.len() as u64);
+            for element in elements {
+                write_string(out, element);
+            }
+        }
+        RedisType::Set { members } => {
+            write_len(out, members.len() as u64);
+            for member in members {
+                write_string(out, member);
+            }
+        }
+        RedisType::Hash { fields } => {
+            write_len(out, fields.len() as u64);
+            for (field, value) in fields {
+                write_string(out, field);
+                write_string(out, value);
+            }
+        }
+        RedisType::SortedSet { members } => {
+            write_len(out, members.len() as u64);
+            for (member, score) in members {
+                write_string(out, member);
+                write_double(out, *score);
+            }
+        }
+        RedisType::Stream { value } => value.write_rdb(out),
+    }
+}
+
+fn read_value(reader: &mut Reader, value_type: u8) -> io::Result<RedisType> {
+    let value = match value_type {
+        TYPE_STRING => RedisType::String {
+            value: StoredValue {
+                data: reader.read_string()?,
+            },
+        },
+        TYPE_LIST => RedisType::List {
+            elements: read_strings(reader)?,
+        },
+        TYPE_LIST_ZIPLIST => RedisType::List {
+            elements: ziplist_entries(&reader.read_string()?)?,
+        },
+        TYPE_LIST_QUICKLIST | TYPE_LIST_QUICKLIST_2 => {
+            let mut elements = vec![];
+            for _ in 0..reader.read_len()? {
+                let container = match value_type {
+                    TYPE_LIST_QUICKLIST_2 => reader.read_len()?,
+                    _ => 0,
+                };
+                let node = reader.read_string()?;
+                match (value_type, container) {
+                    (TYPE_LIST_QUICKLIST, _) => elements.extend(ziplist_entries(&node)?),
+                    (_, QUICKLIST_NODE_PLAIN) => elements.push(node),
+                    _ => elements.extend(listpack_entries(&node)?),
+                }
+            }
+            RedisType::List { elements }
+        }
+        TYPE_SET => RedisType::Set {
+            members: read_strings(reader)?.into_iter().collect(),
+        },
+        TYPE_SET_INTSET => RedisType::Set {
+            members: intset_entries(&reader.read_string()?)?
+                .into_iter()
+                .collect(),
+        },
+        TYPE_SET_LISTPACK => RedisType::Set {
+            members: listpack_entries(&reader.read_string()?)?
+                .into_iter()
+                .collect(),
+        },
+        TYPE_HASH => {
+            let mut fields = BTreeMap::new();
+            for _ in 0..reader.read_len()? {
+                let field = reader.read_string()?;
+                fields.insert(field, reader.read_string()?);
+            }
+            RedisType::Hash { fields }
+        }
+        TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK => {
+            let entries = reader.read_string()?;
+            let entries = match value_type {
+                TYPE_HASH_ZIPLIST => ziplist_entries(&entries)?,
+                _ => listpack_entries(&entries)?,
+            };
+            RedisType::Hash {
+                fields: pairs(entries)?.into_iter().collect(),
+            }
+        }
+        TYPE_ZSET | TYPE_ZSET_2 => {
+            let mut members = BTreeMap::new();
+            for _ in 0..reader.read_len()? {
+                let member = reader.read_string()?;
+                let score = match value_type {
+                    TYPE_ZSET => reader.read_double_string()?,
+                    _ => reader.read_double()?,
+                };
+                members.insert(member, score);
+            }
+            RedisType::SortedSet { members }
+        }
+        TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+            let entries = reader.read_string()?;
+            let entries = match value_type {
+                TYPE_ZSET_ZIPLIST => ziplist_entries(&entries)?,
+                _ => listpack_entries(&entries)?,
+            };
+            let members = pairs(entries)?
+                .into_iter()
+                .map(|(member, score)| {
+                    let score = std::str::from_utf8(&score)
+                        .ok()
+                        .and_then(|score| score.parse::<f64>().ok())
+                        .ok_or_else(|| io::Error::other("Invalid sorted set score"))?;
+                    Ok((member, score))
+                })
+                .collect::<io::Result<_>>()?;
+            RedisType::SortedSet { members }
+        }
+        TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+            RedisType::Stream {
+                value: Stream::read_rdb(reader, value_type)?,
+            }
+        }
+        value_type => {
+            return Err(io::Error::other(format!(
+                "Unknown RDB encoding type {value_type}"
+            )));
+        }
+    };
+
+    Ok(value)
+}
+
+fn read_strings(reader: &mut Reader) -> io::Result<Vec<Vec<u8>>> {
+    (0..reader.read_len()?)
+        .map(|_| reader.read_string())
+        .collect()
+}
+
+//field and value alternate in the compact encodings of hashes and sorted sets
+fn pairs(entries: Vec<Vec<u8>>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if !entries.len().is_multiple_of(2) {
+        return Err(io::Error::other("Odd number of entries in a map encoding"));
+    }
+
+    let mut entries = entries.into_iter();
+    let mut pairs = vec![];
+    while let (Some(first), Some(second)) = (entries.next(), entries.next()) {
+        pairs.push((first, second));
+    }
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        command::Command,
+        rdb::{
+            Listpack, OPCODE_AUX, OPCODE_EOF, OPCODE_EXPIRETIME_MS, OPCODE_RESIZEDB,
+            OPCODE_SELECTDB, TYPE_HASH_LISTPACK, TYPE_LIST_QUICKLIST_2, TYPE_SET_INTSET,
+            TYPE_STRING, TYPE_ZSET_LISTPACK, crc64, write_len, write_millis, write_string,
+        },
+        redis::{Redis, RedisType, stream::now_ms},
+        resp::RespType,
+    };
+
+    fn run(rds: &mut Redis, args: &[&str]) -> RespType {
+        let request = RespType::Array {
+            elements: args
+                .iter()
+                .map(|arg| RespType::BulkString {
+                    data: arg.as_bytes().to_vec(),
+                })
+                .collect(),
+        };
+        rds.handle_command(Command::from(request), 1).unwrap()
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut rds = Redis::default();
+        let long = "value".repeat(20);
+        run(&mut rds, &["SET", "string", "42"]);
+        run(&mut rds, &["SET", "volatile", &long, "PX", "100000"]);
+        run(&mut rds, &["RPUSH", "list", "a", "1", &long]);
+        rds.store.insert(
+            "set".into(),
+            RedisType::Set {
+                members: BTreeSet::from(["x".into(), "-7".into()]),
+            },
+        );
+        rds.store.insert(
+            "hash".into(),
+            RedisType::Hash {
+                fields: BTreeMap::from([("f".into(), "v".into()), ("n".into(), "300".into())]),
+            },
+        );
+        rds.set_ttl("hash", Some(Instant::now() + Duration::from_secs(100)));
+        rds.store.insert(
+            "zset".into(),
+            RedisType::SortedSet {
+                members: BTreeMap::from([("m".into(), 1.5), ("inf".into(), f64::INFINITY)]),
+            },
+        );
+
+        //enough entries for several nodes, some with fields of their own
+        for seq in 1..=250 {
+            let id = format!("1-{seq}");
+            match seq % 3 {
+                0 => run(&mut rds, &["XADD", "stream", &id, "other", "field"]),
+                _ => run(&mut rds, &["XADD", "stream", &id, "f", &seq.to_string()]),
+            };
+        }
+        run(&mut rds, &["XDEL", "stream", "1-2"]);
+        run(&mut rds, &["XGROUP", "CREATE", "stream", "group", "0"]);
+        run(
+            &mut rds,
+            &["XGROUP", "CREATECONSUMER", "stream", "group", "bob"],
+        );
+        run(
+            &mut rds,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "group",
+                "alice",
+                "COUNT",
+                "3",
+                "STREAMS",
+                "stream",
+                ">",
+            ],
+        );
+        run(&mut rds, &["XACK", "stream", "group", "1-3"]);
+
+        let snapshot = rds.rdb_snapshot(&[], false);
+        let mut loaded = Redis::default();
+        assert_eq!(loaded.load_rdb(&snapshot).unwrap(), Vec::<String>::new());
+
+        assert_eq!(loaded.store.len(), rds.store.len());
+        for key in ["string", "list", "set", "hash", "zset"] {
+            assert_eq!(
+                format!("{:?}", loaded.store[key]),
+                format!("{:?}", rds.store[key])
+            );
+        }
+
+        let RedisType::String { value } = &loaded.store["volatile"] else {
+            panic!("volatile should be a string");
+        };
+        assert_eq!(value.data, long.as_bytes());
+        for key in ["volatile", "hash"] {
+            let ttl = loaded.ttls[key] - Instant::now();
+            assert!(ttl > Duration::from_secs(99) && ttl <= Duration::from_secs(100));
+        }
+        assert_eq!(loaded.expires.len(), 2);
+
+        for request in [
+            &["XRANGE", "stream", "-", "+"][..],
+            &["XINFO", "STREAM", "stream"],
+            &["XINFO", "GROUPS", "stream"],
+            &["XINFO", "CONSUMERS", "stream", "group"],
+            &["XPENDING", "stream", "group", "-", "+", "10"],
+        ] {
+            assert_eq!(run(&mut loaded, request), run(&mut rds, request));
+        }
+    }
+
+    #[test]
+    fn test_dump_restore() {
+        let mut rds = Redis::default();
+        run(&mut rds, &["RPUSH", "list", "a", "b"]);
+        run(&mut rds, &["SET", "volatile", "v", "PX", "100000"]);
+        let dump = |rds: &mut Redis, key: &str| {
+            let request = RespType::Array {
+                elements: vec![
+                    RespType::BulkString {
+                        data: b"DUMP".to_vec(),
+                    },
+                    RespType::BulkString {
+                        data: key.as_bytes().to_vec(),
+                    },
+                ],
+            };
+            match rds.handle_command(Command::from(request), 1).unwrap() {
+                RespType::BulkString { data } => Some(data),
+                _ => None,
+            }
+        };
+        let restore = |rds: &mut Redis, key: &str, ttl: u64, payload: &[u8], options: &[&str]| {
+            let cmd = Command::Restore {
+                key: key.into(),
+                ttl,
+                payload: payload.to_vec(),
+                replace: options.contains(&"REPLACE"),
+                absttl: options.contains(&"ABSTTL"),
+                asking: false,
+            };
+            rds.handle_command(cmd, 1).unwrap()
+        };
+        let ok = RespType::SimpleString {
+            content: "OK".into(),
+        };
+        let list = dump(&mut rds, "list").unwrap();
+        let volatile = dump(&mut rds, "volatile").unwrap();
+        assert_eq!(dump(&mut rds, "missing"), None);
+
+        let mut other = Redis::default();
+        assert_eq!(restore(&mut other, "copy", 0, &list, &[]), ok);
+        assert_eq!(
+            format!("{:?}", other.store["copy"]),
+            format!("{:?}", rds.store["list"])
+        );
+        assert_eq!(
+            restore(&mut other, "copy", 0, &volatile, &[]),
+            RespType::SimpleError {
+                content: "BUSYKEY Target key name already exists.".into()
+            }
+        );
+        assert_eq!(
+            restore(&mut other, "copy", 5000, &volatile, &["REPLACE"]),
+            ok
+        );
+        assert_eq!(
+            run(&mut other, &["GET", "copy"]),
+            run(&mut rds, &["GET", "volatile"])
+        );
+        assert_eq!(other.expires.len(), 1);
+
+        let mut corrupted = list.clone();
+        corrupted[1] ^= 1;
+        assert_eq!(
+            restore(&mut other, "bad", 0, &corrupted, &[]),
+            RespType::SimpleError {
+                content: "ERR DUMP payload version or checksum are wrong".into()
+            }
+        );
+
+        //the change is replayed with the same expiration, an already expired key only deleted
+        other.propagated.clear();
+        restore(&mut other, "copy", 1, &list, &["REPLACE", "ABSTTL"]);
+        assert!(other.store.is_empty() && other.expires.is_empty());
+        restore(&mut other, "copy", 60_000, &list, &[]);
+        assert!(other.ttls.contains_key("copy"));
+        let propagated: Vec<_> = other.propagated.iter().map(RespType::serialize).collect();
+        assert_eq!(propagated[0], b"*2\r\n$3\r\nDEL\r\n$4\r\ncopy\r\n");
+        let restored = String::from_utf8_lossy(&propagated[1]).into_owned();
+        assert!(restored.starts_with("*5\r\n$7\r\nRESTORE\r\n$4\r\ncopy\r\n$13\r\n"));
+        assert!(restored.ends_with("$6\r\nABSTTL\r\n"));
+    }
+
+    #[test]
+    fn test_collection_ttls() {
+        let mut rds = Redis::default();
+        let in_100s = Some(Instant::now() + Duration::from_secs(100));
+        let volatile = |rds: &mut Redis| {
+            run(rds, &["RPUSH", "list", "a", "b"]);
+            rds.store.insert(
+                "set".into(),
+                RedisType::Set {
+                    members: BTreeSet::from(["x".into()]),
+                },
+            );
+            run(rds, &["XADD", "stream", "1-1", "f", "v"]);
+            for key in ["list", "set", "stream"] {
+                rds.set_ttl(key, in_100s);
+            }
+        };
+        volatile(&mut rds);
+
+        //kept by the writes that leave the key in place, and through a reload
+        run(&mut rds, &["RPUSH", "list", "c"]);
+        run(&mut rds, &["XADD", "stream", "1-2", "f", "v"]);
+        let mut loaded = Redis::default();
+        loaded.load_rdb(&rds.rdb_snapshot(&[], false)).unwrap();
+        for key in ["list", "set", "stream"] {
+            let ttl = loaded.ttls[key] - Instant::now();
+            assert!(ttl > Duration::from_secs(99) && ttl <= Duration::from_secs(100));
+        }
+        assert_eq!(loaded.expires.len(), 3);
+
+        //cleared by DEL, the keys created again are persistent
+        run(&mut rds, &["DEL", "list", "set", "stream"]);
+        assert!(rds.ttls.is_empty() && rds.expires.is_empty());
+        run(&mut rds, &["RPUSH", "list", "a"]);
+        run(&mut rds, &["XADD", "stream", "1-1", "f", "v"]);
+        assert!(rds.ttls.is_empty());
+
+        //cleared by an overwrite
+        run(&mut rds, &["DEL", "list", "stream"]);
+        volatile(&mut rds);
+        let RespType::BulkString { data: list } = run(&mut rds, &["DUMP", "list"]) else {
+            panic!("the list should be dumped");
+        };
+        for key in ["list", "set"] {
+            let restore = Command::Restore {
+                key: key.into(),
+                ttl: 0,
+                payload: list.clone(),
+                replace: true,
+                absttl: false,
+                asking: false,
+            };
+            rds.handle_command(restore, 1).unwrap();
+        }
+        assert_eq!(rds.ttls.keys().collect::<Vec<_>>(), ["stream"]);
+        assert_eq!(rds.expires.len(), 1);
+
+        //cleared once a list is emptied, while an emptied stream keeps its key and expiration
+        run(&mut rds, &["DEL", "list", "set", "stream"]);
+        volatile(&mut rds);
+        run(&mut rds, &["LPOP", "list", "2"]);
+        assert!(!rds.store.contains_key("list") && !rds.ttls.contains_key("list"));
+        run(&mut rds, &["RPUSH", "list", "a"]);
+        assert!(!rds.ttls.contains_key("list"));
+        run(&mut rds, &["XDEL", "stream", "1-1"]);
+        assert_eq!(
+            run(&mut rds, &["XLEN", "stream"]),
+            RespType::Integer { integer: 0 }
+        );
+        assert!(rds.ttls.contains_key("stream"));
+        assert_eq!(rds.expires.len(), 2);
+    }
+
+    #[test]
+    fn test_load_redis_encodings() {
+        let mut file = b"REDIS0011".to_vec();
+        file.push(OPCODE_AUX);
+        write_string(&mut file, b"redis-ver");
+        write_string(&mut file, b"7.2.4");
+        file.push(OPCODE_SELECTDB);
+        write_len(&mut file, 0);
+        file.push(OPCODE_RESIZEDB);
+        write_len(&mut file, 7);
+        write_len(&mut file, 3);
+
+        let listpack = |entries: &[&str]| {
+            let mut listpack = Listpack::default();
+            for entry in entries {
+                match entry.parse::<i64>() {
+                    Ok(value) => listpack.push_int(value),
+                    Err(_) => listpack.push_str(entry.as_bytes()),
+                }
+            }
+            listpack.into_bytes()
+        };
+
+        file.push(TYPE_LIST_QUICKLIST_2);
+        write_string(&mut file, b"list");
+        write_len(&mut file, 2);
+        write_len(&mut file, 2);
+        write_string(&mut file, &listpack(&["a", "1024"]));
+        write_len(&mut file, 1);
+        write_string(&mut file, b"plain");
+
+        file.push(TYPE_SET_INTSET);
+        write_string(&mut file, b"set");
+        write_string(&mut file, &[2, 0, 0, 0, 2, 0, 0, 0, 0xff, 0xff, 7, 0]);
+
+        file.push(OPCODE_EXPIRETIME_MS);
+        write_millis(&mut file, now_ms() + 60_000);
+        file.push(TYPE_HASH_LISTPACK);
+        write_string(&mut file, b"hash");
+        write_string(&mut file, &listpack(&["f", "v", "n", "-5"]));
+
+        file.push(TYPE_ZSET_LISTPACK);
+        write_string(&mut file, b"zset");
+        write_string(&mut file, &listpack(&["m", "2.5"]));
+
+        file.push(OPCODE_EXPIRETIME_MS);
+        write_millis(&mut file, now_ms() + 60_000);
+        file.push(TYPE_STRING);
+        write_string(&mut file, b"volatile");
+        write_string(&mut file, b"v");
+
+        //values are kept as they are, only keys have to be UTF-8
+        file.push(TYPE_STRING);
+        write_string(&mut file, b"binary");
+        write_string(&mut file, &[0xff, 0, 0xfe]);
+
+        file.push(OPCODE_EXPIRETIME_MS);
+        write_millis(&mut file, now_ms() - 1);
+        file.push(TYPE_STRING);
+        write_string(&mut file, b"expired");
+        write_string(&mut file, b"v");
+
+        //only the first database is supported
+        let mut other_db = file.clone();
+        other_db.push(OPCODE_SELECTDB);
+        write_len(&mut other_db, 1);
+        other_db.push(TYPE_STRING);
+        write_string(&mut other_db, b"other");
+        write_string(&mut other_db, b"v");
+
+        let mut binary_key = file.clone();
+        binary_key.push(TYPE_STRING);
+        write_string(&mut binary_key, &[0xff]);
+        write_string(&mut binary_key, b"v");
+
+        for file in [&mut file, &mut other_db, &mut binary_key] {
+            file.push(OPCODE_EOF);
+            let crc = crc64(0, file);
+            file.extend_from_slice(&crc.to_le_bytes());
+        }
+
+        let mut rds = Redis::default();
+        run(&mut rds, &["SET", "previous", "v"]);
+        let mut corrupted = file.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(rds.load_rdb(&corrupted).is_err());
+        assert!(rds.load_rdb(&other_db).is_err());
+        assert!(rds.load_rdb(&binary_key).is_err());
+        assert!(rds.store.contains_key("previous"));
+
+        rds.load_rdb(&file).unwrap();
+        let mut keys: Vec<_> = rds.store.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, ["binary", "hash", "list", "set", "volatile", "zset"]);
+        assert_eq!(rds.expires.len(), 2);
+        assert!(rds.ttls.contains_key("hash"));
+
+        let bulk = |s: &str| RespType::BulkString {
+            data: s.as_bytes().to_vec(),
+        };
+        assert_eq!(
+            run(&mut rds, &["LRANGE", "list", "0", "-1"]),
+            RespType::Array {
+                elements: vec![bulk("a"), bulk("1024"), bulk("plain")]
+            }
+        );
+        assert_eq!(
+            run(&mut rds, &["GET", "binary"]),
+            RespType::BulkString {
+                data: vec![0xff, 0, 0xfe]
+            }
+        );
+        let RedisType::Set { members } = &rds.store["set"] else {
+            panic!("set should be a set");
+        };
+        assert_eq!(members, &BTreeSet::from([b"-1".to_vec(), b"7".to_vec()]));
+        let RedisType::Hash { fields } = &rds.store["hash"] else {
+            panic!("hash should be a hash");
+        };
+        assert_eq!(
+            fields,
+            &BTreeMap::from([
+                (b"f".to_vec(), b"v".to_vec()),
+                (b"n".to_vec(), b"-5".to_vec())
+            ])
+        );
+        let RedisType::SortedSet { members } = &rds.store["zset"] else {
+            panic!("zset should be a sorted set");
+        };
+        assert_eq!(members, &BTreeMap::from([(b"m".to_vec(), 2.5)]));
+    }
+}
@@ -0,0 +1,526 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    command::{StreamTrim, TrimStrategy},
+    resp::RespType,
+};
+
+mod group;
+mod rdb;
+
+pub use group::{ConsumerGroup, PendingEntry};
+
+//<milliseconds-time>-<sequence-number>
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Smallest id strictly greater than self, None if self is already the maximum id.
+    pub fn next(&self) -> Option<StreamId> {
+        match (self.ms, self.seq) {
+            (u64::MAX, u64::MAX) => None,
+            (ms, u64::MAX) => Some(StreamId::new(ms + 1, 0)),
+            (ms, seq) => Some(StreamId::new(ms, seq + 1)),
+        }
+    }
+
+    /// Biggest id strictly smaller than self, None if self is 0-0.
+    pub fn prev(&self) -> Option<StreamId> {
+        match (self.ms, self.seq) {
+            (0, 0) => None,
+            (ms, 0) => Some(StreamId::new(ms - 1, u64::MAX)),
+            (ms, seq) => Some(StreamId::new(ms, seq - 1)),
+        }
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl Display for StreamId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = io::Error;
+
+    //accepts both <ms>-<seq> and <ms>, in the latter case the sequence number defaults to 0
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || io::Error::other("ERR Invalid stream ID specified as stream command argument");
+
+        match s.split_once('-') {
+            Some((ms, seq)) => Ok(StreamId {
+                ms: ms.parse::<u64>().map_err(|_| invalid())?,
+                seq: seq.parse::<u64>().map_err(|_| invalid())?,
+            }),
+            None => Ok(StreamId {
+                ms: s.parse::<u64>().map_err(|_| invalid())?,
+                seq: 0,
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StreamElement {
+    pub id: StreamId,
+    //fields are kept in insertion order, as they are returned by XRANGE and friends
+    pub data: Vec<(String, String)>,
+    //entries removed by XDEL are only flagged, they are dropped when trimmed or compacted
+    deleted: bool,
+}
+
+impl StreamElement {
+    //1) <id> 2) 1) <field> 2) <value> ...
+    pub fn to_resp(&self) -> RespType {
+        RespType::Array {
+            elements: vec![
+                RespType::BulkString {
+                    data: self.id.to_bytes(),
+                },
+                RespType::Array {
+                    elements: self
+                        .data
+                        .iter()
+                        .flat_map(|(k, v)| [k, v])
+                        .map(|s| RespType::BulkString {
+                            data: s.as_bytes().to_vec(),
+                        })
+                        .collect(),
+                },
+            ],
+        }
+    }
+}
+
+//number of entries per node when trimming with ~, mirrors stream-node-max-entries
+pub const NODE_MAX_ENTRIES: usize = 100;
+
+/// Entries are kept sorted by id (ids are strictly increasing on insertion), so every lookup by
+/// id is a binary search over the underlying vector.
+#[derive(Debug, Default)]
+pub struct Stream {
+    elements: Vec<StreamElement>,
+    //number of entries not deleted, tombstones excluded
+    len: usize,
+    tombstones: usize,
+    last_id: StreamId,
+    max_deleted_id: StreamId,
+    //every entry ever added, including deleted and trimmed ones
+    entries_added: u64,
+    groups: BTreeMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    //number of nodes the entries would take in Redis' radix tree
+    pub fn nodes(&self) -> usize {
+        self.elements.len().div_ceil(NODE_MAX_ENTRIES)
+    }
+
+    pub fn first(&self) -> Option<&StreamElement> {
+        self.elements.iter().find(|el| !el.deleted)
+    }
+
+    pub fn last(&self) -> Option<&StreamElement> {
+        self.elements.iter().rev().find(|el| !el.deleted)
+    }
+
+    /// Appends a new entry, the caller is responsible for checking that id > last_id.
+    pub fn push(&mut self, id: StreamId, data: Vec<(String, String)>) {
+        debug_assert!(self.elements.is_empty() || id > self.last_id);
+
+        self.elements.push(StreamElement {
+            id,
+            data,
+            deleted: false,
+        });
+        self.last_id = id;
+        self.len += 1;
+        self.entries_added += 1;
+    }
+
+    /// Overrides the metadata of the stream as XSETID does, the caller is responsible for the
+    /// validation of the new values.
+    pub fn set_id(
+        &mut self,
+        last_id: StreamId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamId>,
+    ) {
+        self.last_id = last_id;
+        self.entries_added = entries_added.unwrap_or(self.entries_added);
+        self.max_deleted_id = max_deleted_id.unwrap_or(self.max_deleted_id);
+    }
+
+    pub fn get(&self, id: &StreamId) -> Option<&StreamElement> {
+        self.elements
+            .binary_search_by_key(id, |el| el.id)
+            .ok()
+            .map(|idx| &self.elements[idx])
+            .filter(|el| !el.deleted)
+    }
+
+    /// Marks the entry as deleted, returns false if there was no such entry.
+    pub fn delete(&mut self, id: &StreamId) -> bool {
+        let Ok(idx) = self.elements.binary_search_by_key(id, |el| el.id) else {
+            return false;
+        };
+
+        let el = &mut self.elements[idx];
+        if el.deleted {
+            return false;
+        }
+
+        el.deleted = true;
+        el.data = vec![];
+        self.len -= 1;
+        self.tombstones += 1;
+        self.max_deleted_id = self.max_deleted_id.max(*id);
+
+        //once tombstones are the majority the vector is compacted, keeping lookups cheap
+        if self.tombstones > NODE_MAX_ENTRIES && self.tombstones > self.len {
+            self.elements.retain(|el| !el.deleted);
+            self.tombstones = 0;
+        }
+
+        true
+    }
+
+    /// Evicts the oldest entries according to the trimming strategy, returning how many entries
+    /// were removed. With the approximate form only whole nodes are evicted.
+    pub fn trim(&mut self, trim: &StreamTrim) -> usize {
+        let cut = match trim.approx {
+            false => self.exact_trim_position(trim.strategy),
+            true => self.approx_trim_position(trim.strategy, trim.limit),
+        };
+
+        let removed = self.elements.drain(..cut).filter(|el| !el.deleted).count();
+        self.tombstones -= cut - removed;
+        self.len -= removed;
+
+        removed
+    }
+
+    //number of leading positions of the vector to drop to satisfy the strategy
+    fn exact_trim_position(&self, strategy: TrimStrategy) -> usize {
+        match strategy {
+            TrimStrategy::MinId { id } => self.elements.partition_point(|el| el.id < id),
+            TrimStrategy::MaxLen { len } => {
+                let mut to_remove = self.len.saturating_sub(len as usize);
+                let mut cut = 0;
+
+                while to_remove > 0 {
+                    if !self.elements[cut].deleted {
+                        to_remove -= 1;
+                    }
+                    cut += 1;
+                }
+
+                cut
+            }
+        }
+    }
+
+    fn approx_trim_position(&self, strategy: TrimStrategy, limit: Option<usize>) -> usize {
+        //LIMIT 0 means no limit at all
+        let limit = match limit {
+            Some(0) => usize::MAX,
+            Some(limit) => limit,
+            None => 100 * NODE_MAX_ENTRIES,
+        };
+
+        let mut cut = 0;
+        let mut live = self.len;
+
+        for node in self.elements.chunks(NODE_MAX_ENTRIES) {
+            let node_live = node.iter().filter(|el| !el.deleted).count();
+
+            let evictable = match strategy {
+                TrimStrategy::MaxLen { len } => live - node_live >= len as usize,
+                TrimStrategy::MinId { id } => node.last().is_some_and(|el| el.id < id),
+            };
+
+            if !evictable || cut + node.len() > limit {
+                break;
+            }
+
+            cut += node.len();
+            live -= node_live;
+        }
+
+        cut
+    }
+
+    pub fn groups(&self) -> &BTreeMap<String, ConsumerGroup> {
+        &self.groups
+    }
+
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Returns false if a group with the same name already exists.
+    pub fn create_group(&mut self, name: &str, group: ConsumerGroup) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+
+        self.groups.insert(name.to_string(), group);
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &str) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Delivers to the consumer up to `count` entries never delivered to the group before.
+    pub fn read_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        noack: bool,
+        now: u64,
+    ) -> Vec<&StreamElement> {
+        let Some(group_state) = self.groups.get_mut(group) else {
+            return vec![];
+        };
+
+        group_state.touch_consumer(consumer, now);
+
+        let Some(start) = group_state.last_delivered.next() else {
+            return vec![];
+        };
+
+        let from = self.elements.partition_point(|el| el.id < start);
+        let live = |els: &[StreamElement]| {
+            els.iter()
+                .filter(|el| !el.deleted)
+                .take(count)
+                .map(|el| el.id)
+                .collect::<Vec<StreamId>>()
+        };
+        let delivered = live(&self.elements[from..]);
+
+        for id in delivered.iter() {
+            group_state.deliver(*id, consumer, now, noack);
+        }
+
+        if group_state.entries_read.is_none() {
+            let last_delivered = group_state.last_delivered;
+            let entries_read = self.entries_read_at(last_delivered);
+            self.groups.get_mut(group).unwrap().entries_read = entries_read;
+        }
+
+        self.elements[from..]
+            .iter()
+            .filter(|el| !el.deleted)
+            .take(delivered.len())
+            .collect()
+    }
+
+    /// Number of entries added to the stream up to id (included), used as the entries read counter
+    /// of a group whose last delivered id is moved to id. None when deletions make it unknowable.
+    pub fn entries_read_at(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 || id >= self.last_id || self.len == 0 {
+            return Some(self.entries_added);
+        }
+
+        //nothing after id has ever been deleted, so everything after it is still in the stream
+        if self.max_deleted_id <= id {
+            let live_after = self.range(id.next()?, StreamId::MAX).count() as u64;
+            return Some(self.entries_added - live_after);
+        }
+
+        None
+    }
+
+    /// Number of entries still to be delivered to the group, None when it can't be computed.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 || group.last_delivered >= self.last_id {
+            return Some(0);
+        }
+
+        //deleted entries after the last delivered one would be counted as lag
+        if self.max_deleted_id > group.last_delivered {
+            return None;
+        }
+
+        group
+            .entries_read
+            .or(self.entries_read_at(group.last_delivered))
+            .map(|read| self.entries_added.saturating_sub(read))
+    }
+
+    /// All the entries with start <= id <= end, in ascending order.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl DoubleEndedIterator<Item = &StreamElement> {
+        let (from, to) = match start > end {
+            true => (0, 0),
+            false => (
+                self.elements.partition_point(|el| el.id < start),
+                self.elements.partition_point(|el| el.id <= end),
+            ),
+        };
+
+        self.elements[from..to].iter().filter(|el| !el.deleted)
+    }
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::StreamId;
+
+    #[test]
+    fn test_parse_stream_id() {
+        assert_eq!(
+            StreamId::new(1526919030474, 55),
+            "1526919030474-55".parse().unwrap()
+        );
+        assert_eq!(StreamId::new(5, 0), "5".parse().unwrap());
+
+        assert!("5-".parse::<StreamId>().is_err());
+        assert!("-5".parse::<StreamId>().is_err());
+        assert!("a-1".parse::<StreamId>().is_err());
+        assert!("1-2-3".parse::<StreamId>().is_err());
+    }
+
+    #[test]
+    fn test_stream_id_ordering() {
+        assert!(StreamId::new(1, 5) < StreamId::new(2, 0));
+        assert!(StreamId::new(1, 5) < StreamId::new(1, 6));
+        assert_eq!(StreamId::new(1, u64::MAX).next(), Some(StreamId::new(2, 0)));
+        assert_eq!(StreamId::new(2, 0).prev(), Some(StreamId::new(1, u64::MAX)));
+        assert_eq!(StreamId::MIN.prev(), None);
+        assert_eq!(StreamId::MAX.next(), None);
+    }
+
+    #[test]
+    fn test_stream_range() {
+        let mut stream = super::Stream::default();
+        for ms in 1..=5 {
+            stream.push(StreamId::new(ms, 0), vec![("f".into(), ms.to_string())]);
+            stream.push(StreamId::new(ms, 1), vec![("f".into(), ms.to_string())]);
+        }
+
+        let ids = |els: &mut dyn Iterator<Item = &super::StreamElement>| {
+            els.map(|el| el.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ids(&mut stream.range(StreamId::new(2, 1), StreamId::new(3, u64::MAX))),
+            vec![
+                StreamId::new(2, 1),
+                StreamId::new(3, 0),
+                StreamId::new(3, 1)
+            ]
+        );
+        assert_eq!(stream.range(StreamId::MIN, StreamId::MAX).count(), 10);
+        assert!(
+            stream
+                .range(StreamId::new(6, 0), StreamId::MAX)
+                .next()
+                .is_none()
+        );
+        assert!(
+            stream
+                .range(StreamId::new(3, 0), StreamId::new(2, 0))
+                .next()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_stream_delete_and_trim() {
+        use crate::command::{StreamTrim, TrimStrategy};
+
+        let mut stream = super::Stream::default();
+        for seq in 1..=250 {
+            stream.push(StreamId::new(1, seq), vec![("f".into(), "v".into())]);
+        }
+
+        assert!(stream.delete(&StreamId::new(1, 10)));
+        assert!(!stream.delete(&StreamId::new(1, 10)));
+        assert!(stream.get(&StreamId::new(1, 10)).is_none());
+        assert_eq!(stream.len(), 249);
+        assert_eq!(stream.max_deleted_id(), StreamId::new(1, 10));
+        assert_eq!(stream.entries_read_at(StreamId::new(1, 5)), None);
+        assert_eq!(stream.entries_read_at(StreamId::new(1, 20)), Some(20));
+
+        //only the first node can go without going below 140 entries
+        let approx = StreamTrim {
+            strategy: TrimStrategy::MaxLen { len: 140 },
+            approx: true,
+            limit: None,
+        };
+        assert_eq!(stream.trim(&approx), 99);
+        assert_eq!(stream.len(), 150);
+
+        let exact = StreamTrim {
+            strategy: TrimStrategy::MinId {
+                id: StreamId::new(1, 201),
+            },
+            approx: false,
+            limit: None,
+        };
+        assert_eq!(stream.trim(&exact), 100);
+        assert_eq!(stream.first().unwrap().id, StreamId::new(1, 201));
+        assert_eq!(stream.entries_added(), 250);
+
+        let exact = StreamTrim {
+            strategy: TrimStrategy::MaxLen { len: 10 },
+            approx: false,
+            limit: None,
+        };
+        assert_eq!(stream.trim(&exact), 40);
+        assert_eq!(stream.len(), 10);
+    }
+}
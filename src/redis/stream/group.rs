@@ -0,0 +1,253 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::StreamId;
+
+/// An entry delivered to a consumer and not yet acknowledged.
+#[derive(Debug, Clone)]
+pub struct PendingEntry {
+    pub consumer: String,
+    //unix time in milliseconds of the last delivery
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+impl PendingEntry {
+    pub fn idle(&self, now: u64) -> u64 {
+        now.saturating_sub(self.delivery_time)
+    }
+}
+
+#[derive(Debug)]
+pub struct Consumer {
+    //last time the consumer attempted an interaction (reads, claims...)
+    pub seen_time: u64,
+    //last time the consumer was actually delivered or claimed something
+    pub active_time: Option<u64>,
+    pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    fn new(now: u64) -> Self {
+        Self {
+            seen_time: now,
+            active_time: None,
+            pending: BTreeSet::new(),
+        }
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn pending(&self) -> &BTreeSet<StreamId> {
+        &self.pending
+    }
+}
+
+/// The group pending entries list (PEL) is the source of truth, every consumer only keeps the set
+/// of ids it currently owns so that its own history can be served without a full scan.
+#[derive(Debug)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    //number of entries delivered to the group, None when it can't be known (i.e. after a SETID)
+    pub entries_read: Option<u64>,
+    pending: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_delivered: StreamId, entries_read: Option<u64>) -> Self {
+        Self {
+            last_delivered,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    pub fn consumers(&self) -> &BTreeMap<String, Consumer> {
+        &self.consumers
+    }
+
+    pub fn pending(&self) -> &BTreeMap<StreamId, PendingEntry> {
+        &self.pending
+    }
+
+    /// Adds a consumer as found in a snapshot, its pending entries are restored afterwards.
+    pub fn restore_consumer(&mut self, name: &str, seen_time: u64, active_time: Option<u64>) {
+        self.consumers.insert(
+            name.to_string(),
+            Consumer {
+                seen_time,
+                active_time,
+                pending: BTreeSet::new(),
+            },
+        );
+    }
+
+    /// Adds an entry to the PEL and to the ones its consumer owns, as found in a snapshot.
+    pub fn restore_pending(&mut self, id: StreamId, entry: PendingEntry) {
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.insert(id);
+            self.pending.insert(id, entry);
+        }
+    }
+
+    /// Returns true if the consumer did not exist and has been created.
+    pub fn create_consumer(&mut self, name: &str, now: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+
+        self.consumers.insert(name.to_string(), Consumer::new(now));
+        true
+    }
+
+    /// Looks up the consumer, creating it if needed, and marks it as seen.
+    pub fn touch_consumer(&mut self, name: &str, now: u64) -> &mut Consumer {
+        let consumer = self
+            .consumers
+            .entry(name.to_string())
+            .or_insert_with(|| Consumer::new(now));
+        consumer.seen_time = now;
+
+        consumer
+    }
+
+    /// Removes the consumer along with its pending entries, returning how many were pending.
+    pub fn delete_consumer(&mut self, name: &str) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in consumer.pending.iter() {
+            self.pending.remove(id);
+        }
+
+        Some(consumer.pending.len())
+    }
+
+    /// Records the delivery of a new entry (i.e. one read through `>`) to the consumer.
+    pub fn deliver(&mut self, id: StreamId, consumer: &str, now: u64, noack: bool) {
+        self.last_delivered = self.last_delivered.max(id);
+        self.entries_read = self.entries_read.map(|read| read + 1);
+
+        let owner = self.touch_consumer(consumer, now);
+        owner.active_time = Some(now);
+
+        if noack {
+            return;
+        }
+
+        owner.pending.insert(id);
+
+        //the entry could already be pending if the group id was moved backwards with SETID
+        let previous = self.pending.insert(
+            id,
+            PendingEntry {
+                consumer: consumer.to_string(),
+                delivery_time: now,
+                delivery_count: 1,
+            },
+        );
+
+        if let Some(previous) = previous
+            && previous.consumer != consumer
+            && let Some(previous_owner) = self.consumers.get_mut(&previous.consumer)
+        {
+            previous_owner.pending.remove(&id);
+        }
+    }
+
+    /// Ids pending for the given consumer greater than `after`.
+    pub fn consumer_history(&self, consumer: &str, after: StreamId, count: usize) -> Vec<StreamId> {
+        let Some(start) = after.next() else {
+            return vec![];
+        };
+
+        self.consumers.get(consumer).map_or(vec![], |c| {
+            c.pending.range(start..).take(count).copied().collect()
+        })
+    }
+
+    /// Returns true if the entry was pending.
+    pub fn ack(&mut self, id: &StreamId) -> bool {
+        match self.pending.remove(id) {
+            Some(entry) => {
+                if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+                    consumer.pending.remove(id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Assigns the entry to the consumer, creating the pending entry if it does not exist.
+    pub fn claim(
+        &mut self,
+        id: StreamId,
+        consumer: &str,
+        now: u64,
+        delivery_time: u64,
+        delivery_count: u64,
+    ) {
+        let entry = self.pending.entry(id).or_insert_with(|| PendingEntry {
+            consumer: consumer.to_string(),
+            delivery_time,
+            delivery_count,
+        });
+
+        if entry.consumer != consumer
+            && let Some(previous_owner) = self.consumers.get_mut(&entry.consumer)
+        {
+            previous_owner.pending.remove(&id);
+        }
+
+        entry.consumer = consumer.to_string();
+        entry.delivery_time = delivery_time;
+        entry.delivery_count = delivery_count;
+
+        let owner = self.touch_consumer(consumer, now);
+        owner.active_time = Some(now);
+        owner.pending.insert(id);
+    }
+
+    pub fn pending_entry(&self, id: &StreamId) -> Option<&PendingEntry> {
+        self.pending.get(id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConsumerGroup;
+    use crate::redis::stream::StreamId;
+
+    #[test]
+    fn test_group_pending_entries() {
+        let mut group = ConsumerGroup::new(StreamId::MIN, Some(0));
+
+        for seq in 1..=3 {
+            group.deliver(StreamId::new(1, seq), "alice", 100, false);
+        }
+        group.deliver(StreamId::new(1, 4), "bob", 100, false);
+        group.deliver(StreamId::new(1, 5), "bob", 100, true);
+
+        assert_eq!(group.last_delivered, StreamId::new(1, 5));
+        assert_eq!(group.entries_read, Some(5));
+        assert_eq!(group.pending().len(), 4);
+        assert_eq!(
+            group.consumer_history("alice", StreamId::new(1, 1), 10),
+            vec![StreamId::new(1, 2), StreamId::new(1, 3)]
+        );
+
+        group.claim(StreamId::new(1, 2), "bob", 200, 200, 2);
+        assert_eq!(group.consumers()["alice"].pending_count(), 2);
+        assert_eq!(group.consumers()["bob"].pending_count(), 2);
+
+        assert!(group.ack(&StreamId::new(1, 1)));
+        assert!(!group.ack(&StreamId::new(1, 1)));
+        assert_eq!(group.consumers()["alice"].pending_count(), 1);
+
+        assert_eq!(group.delete_consumer("bob"), Some(2));
+        assert_eq!(group.pending().len(), 1);
+        assert_eq!(group.delete_consumer("bob"), None);
+    }
+}
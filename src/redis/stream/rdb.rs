@@ -0,0 +1,261 @@
+//! Streams in RDB files: entries packed in listpacks of up to NODE_MAX_ENTRIES entries, each
+//! keyed by the id of its first ("master") entry, followed by the metadata and the groups.
+
+use std::{collections::BTreeMap, io, vec};
+
+use crate::rdb::{
+    Listpack, Reader, TYPE_STREAM_LISTPACKS_2, TYPE_STREAM_LISTPACKS_3, into_string,
+    listpack_entries, write_len, write_millis, write_string,
+};
+
+use super::{ConsumerGroup, NODE_MAX_ENTRIES, PendingEntry, Stream, StreamElement, StreamId};
+
+//flags of the entries in a node
+const FLAG_DELETED: i64 = 1;
+const FLAG_SAMEFIELDS: i64 = 2;
+
+impl Stream {
+    /// Appends the stream in the TYPE_STREAM_LISTPACKS_3 encoding.
+    pub fn write_rdb(&self, out: &mut Vec<u8>) {
+        let live: Vec<&StreamElement> = self.elements.iter().filter(|el| !el.deleted).collect();
+
+        write_len(out, live.len().div_ceil(NODE_MAX_ENTRIES) as u64);
+        for node in live.chunks(NODE_MAX_ENTRIES) {
+            write_string(out, &raw_id(node[0].id));
+            write_string(out, &node_listpack(node));
+        }
+
+        write_len(out, self.len as u64);
+        write_len(out, self.last_id.ms);
+        write_len(out, self.last_id.seq);
+        let first_id = live.first().map(|el| el.id).unwrap_or_default();
+        write_len(out, first_id.ms);
+        write_len(out, first_id.seq);
+        write_len(out, self.max_deleted_id.ms);
+        write_len(out, self.max_deleted_id.seq);
+        write_len(out, self.entries_added);
+
+        write_len(out, self.groups.len() as u64);
+        for (name, group) in &self.groups {
+            write_string(out, name.as_bytes());
+            write_len(out, group.last_delivered.ms);
+            write_len(out, group.last_delivered.seq);
+            //an unknown counter is written as -1
+            write_len(out, group.entries_read.unwrap_or(u64::MAX));
+
+            write_len(out, group.pending().len() as u64);
+            for (id, entry) in group.pending() {
+                out.extend_from_slice(&raw_id(*id));
+                write_millis(out, entry.delivery_time);
+                write_len(out, entry.delivery_count);
+            }
+
+            write_len(out, group.consumers().len() as u64);
+            for (name, consumer) in group.consumers() {
+                write_string(out, name.as_bytes());
+                write_millis(out, consumer.seen_time);
+                write_millis(out, consumer.active_time.unwrap_or(u64::MAX));
+                write_len(out, consumer.pending().len() as u64);
+                for id in consumer.pending() {
+                    out.extend_from_slice(&raw_id(*id));
+                }
+            }
+        }
+    }
+
+    /// Reads a stream in any of the listpacks encodings.
+    pub fn read_rdb(reader: &mut Reader, value_type: u8) -> io::Result<Stream> {
+        let mut stream = Stream::default();
+
+        for _ in 0..reader.read_len()? {
+            let master = parse_raw_id(&reader.read_string()?)?;
+            let entries = listpack_entries(&reader.read_string()?)?;
+            read_node(&mut stream.elements, master, entries)?;
+        }
+
+        stream.len = stream.elements.len();
+        reader.read_len()?;
+        stream.last_id = StreamId::new(reader.read_len()?, reader.read_len()?);
+        stream.entries_added = stream.len as u64;
+        if value_type >= TYPE_STREAM_LISTPACKS_2 {
+            //the first id can be derived from the entries
+            reader.read_len()?;
+            reader.read_len()?;
+            stream.max_deleted_id = StreamId::new(reader.read_len()?, reader.read_len()?);
+            stream.entries_added = reader.read_len()?;
+        }
+
+        for _ in 0..reader.read_len()? {
+            let name = into_string(reader.read_string()?)?;
+            let last_delivered = StreamId::new(reader.read_len()?, reader.read_len()?);
+            let entries_read = match value_type >= TYPE_STREAM_LISTPACKS_2 {
+                true => Some(reader.read_len()?).filter(|read| *read != u64::MAX),
+                false => None,
+            };
+            let mut group = ConsumerGroup::new(last_delivered, entries_read);
+
+            //the consumer owning each pending entry comes with the consumers
+            let mut pending = BTreeMap::new();
+            for _ in 0..reader.read_len()? {
+                let id = parse_raw_id(reader.read_bytes(16)?)?;
+                let delivery_time = reader.read_millis()?;
+                let delivery_count = reader.read_len()?;
+                pending.insert(id, (delivery_time, delivery_count));
+            }
+
+            for _ in 0..reader.read_len()? {
+                let consumer = into_string(reader.read_string()?)?;
+                let seen_time = reader.read_millis()?;
+                let active_time = match value_type >= TYPE_STREAM_LISTPACKS_3 {
+                    true => Some(reader.read_millis()?).filter(|time| *time != u64::MAX),
+                    false => Some(seen_time),
+                };
+                group.restore_consumer(&consumer, seen_time, active_time);
+
+                for _ in 0..reader.read_len()? {
+                    let id = parse_raw_id(reader.read_bytes(16)?)?;
+                    let (delivery_time, delivery_count) = pending
+                        .remove(&id)
+                        .ok_or_else(|| io::Error::other("Consumer pending entry not in the PEL"))?;
+                    let entry = PendingEntry {
+                        consumer: consumer.clone(),
+                        delivery_time,
+                        delivery_count,
+                    };
+                    group.restore_pending(id, entry);
+                }
+            }
+
+            if !pending.is_empty() {
+                return Err(io::Error::other("PEL entries without a consumer"));
+            }
+            stream.groups.insert(name, group);
+        }
+
+        Ok(stream)
+    }
+}
+
+//<count> <deleted> <num fields> <field> ... 0, then for every entry:
+//<flags> <ms diff> <seq diff> [<num fields>] [<field>] <value> ... <lp count>
+fn node_listpack(node: &[&StreamElement]) -> Vec<u8> {
+    let master = node[0];
+    let mut listpack = Listpack::default();
+
+    listpack.push_int(node.len() as i64);
+    listpack.push_int(0);
+    listpack.push_int(master.data.len() as i64);
+    for (field, _) in &master.data {
+        listpack.push_str(field.as_bytes());
+    }
+    listpack.push_int(0);
+
+    for el in node {
+        let same_fields = el.data.len() == master.data.len()
+            && el
+                .data
+                .iter()
+                .zip(&master.data)
+                .all(|((field, _), (master_field, _))| field == master_field);
+        let fields = el.data.len() as i64;
+
+        listpack.push_int(if same_fields { FLAG_SAMEFIELDS } else { 0 });
+        listpack.push_int(el.id.ms.wrapping_sub(master.id.ms) as i64);
+        listpack.push_int(el.id.seq.wrapping_sub(master.id.seq) as i64);
+        if same_fields {
+            for (_, value) in &el.data {
+                listpack.push_str(value.as_bytes());
+            }
+            listpack.push_int(fields + 3);
+        } else {
+            listpack.push_int(fields);
+            for (field, value) in &el.data {
+                listpack.push_str(field.as_bytes());
+                listpack.push_str(value.as_bytes());
+            }
+            listpack.push_int(2 * fields + 4);
+        }
+    }
+
+    listpack.into_bytes()
+}
+
+fn read_node(
+    elements: &mut Vec<StreamElement>,
+    master: StreamId,
+    entries: Vec<Vec<u8>>,
+) -> io::Result<()> {
+    let mut entries = Entries(entries.into_iter());
+
+    entries.next_int()?;
+    entries.next_int()?;
+    let master_fields = (0..entries.next_int()?)
+        .map(|_| entries.next_string())
+        .collect::<io::Result<Vec<String>>>()?;
+    entries.next_int()?;
+
+    while !entries.is_empty() {
+        let flags = entries.next_int()?;
+        let id = StreamId::new(
+            master.ms.wrapping_add(entries.next_int()? as u64),
+            master.seq.wrapping_add(entries.next_int()? as u64),
+        );
+
+        let data = match flags & FLAG_SAMEFIELDS != 0 {
+            true => master_fields
+                .iter()
+                .map(|field| Ok((field.clone(), entries.next_string()?)))
+                .collect::<io::Result<Vec<_>>>()?,
+            false => (0..entries.next_int()?)
+                .map(|_| Ok((entries.next_string()?, entries.next_string()?)))
+                .collect::<io::Result<Vec<_>>>()?,
+        };
+        entries.next_int()?;
+
+        if flags & FLAG_DELETED == 0 {
+            elements.push(StreamElement {
+                id,
+                data,
+                deleted: false,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+struct Entries(vec::IntoIter<Vec<u8>>);
+
+impl Entries {
+    fn is_empty(&self) -> bool {
+        self.0.len() == 0
+    }
+
+    fn next_string(&mut self) -> io::Result<String> {
+        self.0.next().ok_or_else(corrupted).and_then(into_string)
+    }
+
+    fn next_int(&mut self) -> io::Result<i64> {
+        self.next_string()?.parse().map_err(|_| corrupted())
+    }
+}
+
+//ids are stored big endian so that they sort as bytes do
+fn raw_id(id: StreamId) -> [u8; 16] {
+    let mut raw = [0; 16];
+    raw[..8].copy_from_slice(&id.ms.to_be_bytes());
+    raw[8..].copy_from_slice(&id.seq.to_be_bytes());
+    raw
+}
+
+fn parse_raw_id(raw: &[u8]) -> io::Result<StreamId> {
+    let raw: [u8; 16] = raw.try_into().map_err(|_| corrupted())?;
+    Ok(StreamId::new(
+        u64::from_be_bytes(raw[..8].try_into().expect("8 bytes")),
+        u64::from_be_bytes(raw[8..].try_into().expect("8 bytes")),
+    ))
+}
+
+fn corrupted() -> io::Error {
+    io::Error::other("Corrupted stream node")
+}
@@ -0,0 +1,103 @@
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Default)]
+struct WatchState {
+    keys: HashSet<String>,
+    //one of the keys has been modified since the client started watching it
+    dirty: bool,
+}
+
+/// Keys watched by clients for optimistic locking. The key to watchers index lets every write
+/// flag the interested clients without scanning them all.
+#[derive(Debug, Default)]
+pub struct Watches {
+    watchers: HashMap<String, HashSet<i32>>,
+    clients: HashMap<i32, WatchState>,
+}
+
+impl Watches {
+    pub fn watch(&mut self, client_id: i32, key: &str) {
+        let state = self.clients.entry(client_id).or_default();
+        if state.keys.insert(key.to_string()) {
+            self.watchers
+                .entry(key.to_string())
+                .or_default()
+                .insert(client_id);
+        }
+    }
+
+    /// Forgets every key watched by the client, returning whether any of them was modified.
+    pub fn unwatch(&mut self, client_id: i32) -> bool {
+        let Some(state) = self.clients.remove(&client_id) else {
+            return false;
+        };
+
+        for key in state.keys.iter() {
+            if let Some(watchers) = self.watchers.get_mut(key) {
+                watchers.remove(&client_id);
+                if watchers.is_empty() {
+                    self.watchers.remove(key);
+                }
+            }
+        }
+
+        state.dirty
+    }
+
+    /// Flags every client watching the key.
+    pub fn touch(&mut self, key: &str) {
+        if let Some(watchers) = self.watchers.get(key) {
+            for watcher in watchers {
+                if let Some(state) = self.clients.get_mut(watcher) {
+                    state.dirty = true;
+                }
+            }
+        }
+    }
+
+    /// Flags every client watching a key the predicate holds for, how flushing or swapping a
+    /// whole database invalidates the watchers of the keys it had or gets.
+    pub fn touch_all(&mut self, replaced: impl Fn(&str) -> bool) {
+        for (key, watchers) in self.watchers.iter() {
+            if !replaced(key) {
+                continue;
+            }
+            for watcher in watchers {
+                if let Some(state) = self.clients.get_mut(watcher) {
+                    state.dirty = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Watches;
+
+    #[test]
+    fn test_watches() {
+        let mut watches = Watches::default();
+
+        watches.watch(1, "a");
+        watches.watch(1, "b");
+        watches.watch(2, "b");
+
+        watches.touch("a");
+        watches.touch("c");
+
+        assert!(watches.unwatch(1));
+        assert!(!watches.unwatch(1));
+        assert_eq!(watches.watchers.keys().collect::<Vec<_>>(), vec!["b"]);
+
+        assert!(!watches.unwatch(2));
+        assert!(watches.watchers.is_empty());
+
+        //a whole database replaced flags the watchers of the keys it had or gets
+        watches.watch(1, "a");
+        watches.watch(2, "b");
+        watches.touch_all(|key| key == "b" || key == "c");
+        assert!(!watches.unwatch(1));
+        assert!(watches.unwatch(2));
+    }
+}
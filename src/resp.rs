@@ -1,6 +1,6 @@
 use std::{fmt::Display, io, str::FromStr};
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum RespType {
     //*<number-of-elements>\r\n<element-1>...<element-n>
     Array { elements: Vec<RespType> },
@@ -14,19 +14,43 @@ pub enum RespType {
     Integer { integer: i64 },
     //$-1\r\n
     NullBulkString,
+    //*-1\r\n
+    NullArray,
+    //RESP3 only, downgraded to the RESP2 equivalent when the client did not negotiate it
+    //_\r\n
+    Null,
+    //%<number-of-entries>\r\n<key-1><value-1>...<key-n><value-n>
+    Map { entries: Vec<(RespType, RespType)> },
+    //><number-of-elements>\r\n<element-1>...<element-n>
+    Push { elements: Vec<RespType> },
+}
+
+/// Protocol version negotiated by a client through HELLO.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
 }
 
 impl TryFrom<&[u8]> for RespType {
     type Error = io::Error;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let c = value.first().ok_or(io::Error::other("Empty value"))?;
-
-        Ok(parse_single_value(value, c, 0)?.0)
+        Ok(RespType::parse(value)?.0)
     }
 }
 
 impl RespType {
+    /// Parses the first value of the buffer, returning it along with the number of bytes it
+    /// spans so that pipelined values can be parsed one after the other. Errors of kind
+    /// `UnexpectedEof` mean that the value is not complete yet.
+    pub fn parse(value: &[u8]) -> Result<(Self, usize), io::Error> {
+        let c = value.first().ok_or(incomplete("Empty value"))?;
+
+        parse_single_value(value, c, 0)
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         let mut result = Vec::<u8>::new();
 
@@ -71,10 +95,74 @@ impl RespType {
                     .for_each(|c| result.push(*c));
             }
             RespType::NullBulkString => b"$-1\r\n".iter().for_each(|c| result.push(*c)),
+            RespType::NullArray => b"*-1\r\n".iter().for_each(|c| result.push(*c)),
+            RespType::Null => b"_\r\n".iter().for_each(|c| result.push(*c)),
+            RespType::Map { entries } => {
+                format!("%{}\r\n", entries.len())
+                    .as_bytes()
+                    .iter()
+                    .for_each(|c| result.push(*c));
+
+                entries
+                    .iter()
+                    .flat_map(|(k, v)| k.serialize().into_iter().chain(v.serialize()))
+                    .for_each(|e| result.push(e));
+            }
+            RespType::Push { elements } => {
+                format!(">{}\r\n", elements.len())
+                    .as_bytes()
+                    .iter()
+                    .for_each(|c| result.push(*c));
+
+                elements
+                    .iter()
+                    .flat_map(|el| el.serialize())
+                    .for_each(|e| result.push(e));
+            }
         };
 
         result
     }
+
+    /// Rewrites the value using only the types available in the given protocol version.
+    pub fn for_protocol(self, protocol: Protocol) -> RespType {
+        match (self, protocol) {
+            (RespType::Array { elements }, _) => RespType::Array {
+                elements: elements
+                    .into_iter()
+                    .map(|el| el.for_protocol(protocol))
+                    .collect(),
+            },
+            (RespType::Push { elements }, Protocol::Resp2) => RespType::Array {
+                elements: elements
+                    .into_iter()
+                    .map(|el| el.for_protocol(protocol))
+                    .collect(),
+            },
+            (RespType::Push { elements }, Protocol::Resp3) => RespType::Push {
+                elements: elements
+                    .into_iter()
+                    .map(|el| el.for_protocol(protocol))
+                    .collect(),
+            },
+            //maps are sent as flat arrays of alternating keys and values
+            (RespType::Map { entries }, Protocol::Resp2) => RespType::Array {
+                elements: entries
+                    .into_iter()
+                    .flat_map(|(k, v)| [k.for_protocol(protocol), v.for_protocol(protocol)])
+                    .collect(),
+            },
+            (RespType::Map { entries }, Protocol::Resp3) => RespType::Map {
+                entries: entries
+                    .into_iter()
+                    .map(|(k, v)| (k.for_protocol(protocol), v.for_protocol(protocol)))
+                    .collect(),
+            },
+            (RespType::Null, Protocol::Resp2) => RespType::NullBulkString,
+            (RespType::NullBulkString | RespType::NullArray, Protocol::Resp3) => RespType::Null,
+            (value, _) => value,
+        }
+    }
 }
 
 fn parse_single_value(value: &[u8], c: &u8, cursor: usize) -> Result<(RespType, usize), io::Error> {
@@ -84,47 +172,58 @@ fn parse_single_value(value: &[u8], c: &u8, cursor: usize) -> Result<(RespType,
         b'-' => parse_simple_error(value, cursor + 1)?,
         b'$' => parse_bulk_string(value, cursor + 1)?,
         b'*' => parse_array(value, cursor + 1)?,
-        _ => todo!("Unsupported prefix {c}"),
+        _ => {
+            return Err(io::Error::other(format!(
+                "Unsupported prefix {}",
+                *c as char
+            )));
+        }
     })
 }
 
+fn incomplete(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, msg)
+}
+
 fn parse_integer(value: &[u8], cursor: usize) -> Result<(RespType, usize), io::Error> {
     parse_simple_data(value, cursor, SimpleDataType::Integer)
 }
 
 fn parse_array(value: &[u8], cursor: usize) -> Result<(RespType, usize), io::Error> {
     //*<number-of-elements>\r\n<element-1>...<element-n>
-    let sep_idx =
-        find_separator_index(value, cursor).ok_or(io::Error::other("Invalid array size"))?;
+    let sep_idx = find_separator_index(value, cursor).ok_or(incomplete("Invalid array size"))?;
 
-    let size = usize::from_str(
+    let size = isize::from_str(
         String::from_utf8(value[cursor..sep_idx].to_vec())
             .map_err(|_| io::Error::other("Invalid array size"))?
             .as_str(),
     )
     .map_err(|_| io::Error::other("Invalid array size"))?;
 
+    let size = match size {
+        -1 => return Ok((RespType::NullArray, sep_idx + 2)),
+        size if size < 0 => return Err(io::Error::other("Invalid array size")),
+        size => size as usize,
+    };
+
     let mut cursor = sep_idx + 2;
     let mut elements: Vec<RespType> = Vec::with_capacity(size);
 
-    while let Some(c) = value.get(cursor) {
+    while elements.len() < size {
+        let c = value
+            .get(cursor)
+            .ok_or(incomplete("Array declared size does not match actual size"))?;
         let (parsed, new_pos) = parse_single_value(value, c, cursor)?;
         elements.push(parsed);
         cursor = new_pos;
     }
 
-    if size != elements.len() {
-        return Err(io::Error::other(
-            "Array declared size does not match actual size",
-        ));
-    }
-
     Ok((RespType::Array { elements }, cursor))
 }
 
 fn parse_bulk_string(value: &[u8], cursor: usize) -> Result<(RespType, usize), io::Error> {
-    let sep_idx = find_separator_index(value, cursor)
-        .ok_or(io::Error::other("Invalid bulk string length"))?;
+    let sep_idx =
+        find_separator_index(value, cursor).ok_or(incomplete("Invalid bulk string length"))?;
 
     let length = isize::from_str(
         String::from_utf8(value[cursor..sep_idx].to_vec())
@@ -137,19 +236,18 @@ fn parse_bulk_string(value: &[u8], cursor: usize) -> Result<(RespType, usize), i
         let length = length as usize;
         let cursor = sep_idx + 2;
 
-        let data = value[cursor..cursor+length].to_vec();
+        if value.len() < cursor + length + 2 {
+            return Err(incomplete("Bulk strings must end with \\r\\n"));
+        }
+
+        let data = value[cursor..cursor + length].to_vec();
         let cursor = cursor + length;
 
-        if &value[cursor..=cursor+1] != b"\r\n" {
-            return Err(io::Error::other("Bulk strings must end with \\r\\n"))
+        if &value[cursor..=cursor + 1] != b"\r\n" {
+            return Err(io::Error::other("Bulk strings must end with \\r\\n"));
         }
-        
-        Ok((
-            RespType::BulkString {
-                data
-            },
-            cursor + 2,
-        ))
+
+        Ok((RespType::BulkString { data }, cursor + 2))
     } else {
         if length != -1 {
             return Err(io::Error::other("Only null bulk strings can start with -"));
@@ -172,7 +270,7 @@ fn parse_simple_data(
     cursor: usize,
     data_type: SimpleDataType,
 ) -> Result<(RespType, usize), io::Error> {
-    let end_idx = find_separator_index(value, cursor).ok_or(io::Error::other(format!(
+    let end_idx = find_separator_index(value, cursor).ok_or(incomplete(&format!(
         "Simple {} must end with \\r\\n",
         &data_type
     )))?;
@@ -236,7 +334,7 @@ impl Display for &SimpleDataType {
 
 #[cfg(test)]
 mod test {
-    use crate::resp::RespType;
+    use crate::resp::{Protocol, RespType};
 
     #[test]
     fn resptype_parse_integer() {
@@ -345,6 +443,34 @@ mod test {
         assert_eq!(to_ser.serialize(), b":-43\r\n");
     }
 
+    #[test]
+    fn resptype_for_protocol() {
+        let push = RespType::Push {
+            elements: vec![
+                RespType::BulkString {
+                    data: b"message".to_vec(),
+                },
+                RespType::Map {
+                    entries: vec![(
+                        RespType::BulkString {
+                            data: b"k".to_vec(),
+                        },
+                        RespType::NullBulkString,
+                    )],
+                },
+            ],
+        };
+
+        assert_eq!(
+            push.clone().for_protocol(Protocol::Resp2).serialize(),
+            b"*2\r\n$7\r\nmessage\r\n*2\r\n$1\r\nk\r\n$-1\r\n"
+        );
+        assert_eq!(
+            push.for_protocol(Protocol::Resp3).serialize(),
+            b">2\r\n$7\r\nmessage\r\n%1\r\n$1\r\nk\r\n_\r\n"
+        );
+    }
+
     #[test]
     fn resptype_serialize_simplestring() {
         let to_ser = RespType::SimpleString {
@@ -499,6 +625,11 @@ mod test {
             RespType::try_from(empty_array_literal.as_bytes()).unwrap()
         );
 
+        assert_eq!(
+            RespType::NullArray,
+            RespType::try_from("*-1\r\n".as_bytes()).unwrap()
+        );
+
         let invalid_size = "*3\r\n+ciao\r\n$4\r\nciao\r\n";
         let error = RespType::try_from(invalid_size.as_bytes());
 
@@ -512,6 +643,28 @@ mod test {
         );
     }
 
+    #[test]
+    fn resptype_parse_pipelined() {
+        let pipeline = b"*1\r\n$5\r\nMULTI\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n*1\r\n$4\r\nEX";
+
+        let (multi, consumed) = RespType::parse(pipeline).unwrap();
+        assert_eq!(consumed, 15);
+        assert_eq!(
+            multi,
+            RespType::Array {
+                elements: vec![RespType::BulkString {
+                    data: b"MULTI".to_vec()
+                }]
+            }
+        );
+
+        let (_, get_len) = RespType::parse(&pipeline[consumed..]).unwrap();
+
+        //the last command has not been fully received yet
+        let err = RespType::parse(&pipeline[consumed + get_len..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
     #[test]
     fn resptype_serialize_array() {
         let array = RespType::Array {
@@ -532,5 +685,7 @@ mod test {
         let empty_array_literal = b"*0\r\n";
 
         assert_eq!(empty.serialize(), empty_array_literal);
+
+        assert_eq!(RespType::NullArray.serialize(), b"*-1\r\n");
     }
 }
@@ -0,0 +1,715 @@
+//! Sentinel mode. A sentinel monitors masters along with their replicas, other sentinels watch
+//! the same masters. A master that doesn't answer the pings for long enough is subjectively
+//! down (SDOWN) for a sentinel, and objectively down (ODOWN) once a quorum of the sentinels
+//! agree. The sentinels then elect one of them to lead the failover: it promotes the best
+//! replica and makes the others replicate it. The other sentinels learn about the new master
+//! from its configuration epoch, greater than the one of the old master.
+//!
+//! Sentinels find each other through the instances they monitor: each of them publishes hello
+//! messages on a channel of the masters and replicas, to which the others subscribe.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// The channel, of the monitored instances, the sentinels say hello on.
+pub const HELLO_CHANNEL: &str = "__sentinel__:hello";
+
+const DEFAULT_DOWN_AFTER: Duration = Duration::from_secs(30);
+const DEFAULT_FAILOVER_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// What a sentinel knows about the masters it monitors.
+#[derive(Debug)]
+pub struct Sentinel {
+    pub myid: String,
+    //greater than every epoch this sentinel voted or started a failover in
+    pub current_epoch: u64,
+    pub masters: BTreeMap<String, Master>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Master,
+    Replica,
+}
+
+/// A monitored server or another sentinel, as last seen.
+#[derive(Debug)]
+pub struct Instance {
+    pub ip: String,
+    pub port: u16,
+    pub run_id: Option<String>,
+    //when it last answered a ping, or since when it is monitored
+    pub last_avail: Instant,
+    //the oldest ping still waiting for a reply, kept when the link is reopened
+    pub ping_sent: Option<Instant>,
+    //subjectively down, since then
+    pub sdown_since: Option<Instant>,
+    //when INFO last reported the fields below
+    pub info_refresh: Option<Instant>,
+    pub role: Option<Role>,
+    //when it last reported another role or another master
+    pub role_reported: Option<Instant>,
+    //for a replica, its master and whether it is connected to it
+    pub master_addr: Option<(String, u16)>,
+    pub master_link_up: bool,
+    pub offset: u64,
+    //for a sentinel, when it last said hello
+    pub last_hello: Option<Instant>,
+    //for a sentinel, its last answer about the master being down and when it was received
+    pub master_down: bool,
+    pub down_reply: Option<Instant>,
+    //for a sentinel, the one it voted for to lead the failover, in leader_epoch
+    pub leader: Option<String>,
+    pub leader_epoch: u64,
+}
+
+impl Instance {
+    pub fn new(ip: String, port: u16) -> Self {
+        Self {
+            ip,
+            port,
+            run_id: None,
+            last_avail: Instant::now(),
+            ping_sent: None,
+            sdown_since: None,
+            info_refresh: None,
+            role: None,
+            role_reported: None,
+            master_addr: None,
+            master_link_up: false,
+            offset: 0,
+            last_hello: None,
+            master_down: false,
+            down_reply: None,
+            leader: None,
+            leader_epoch: 0,
+        }
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+}
+
+/// A monitored master, with the replicas and the other sentinels known to watch it.
+#[derive(Debug)]
+pub struct Master {
+    pub name: String,
+    pub instance: Instance,
+    //the number of sentinels that have to agree for the master to be objectively down
+    pub quorum: usize,
+    pub down_after: Duration,
+    pub failover_timeout: Duration,
+    //the epoch of the failover that made it the master
+    pub config_epoch: u64,
+    //the sentinel this one voted for to lead the failover, in leader_epoch
+    pub leader: Option<String>,
+    pub leader_epoch: u64,
+    pub odown_since: Option<Instant>,
+    //when the last failover was attempted, the next attempt waits twice the failover timeout
+    pub failover_start: Option<Instant>,
+    pub failover: Option<Failover>,
+    //by address
+    pub replicas: BTreeMap<String, Instance>,
+    //by run id
+    pub sentinels: BTreeMap<String, Instance>,
+}
+
+#[derive(Debug)]
+pub struct Failover {
+    pub epoch: u64,
+    pub state: FailoverState,
+    //when the failover entered its current state
+    pub state_time: Instant,
+    //SENTINEL FAILOVER, this sentinel leads it without being elected
+    pub forced: bool,
+    //the address of the replica promoted
+    pub promoted: Option<String>,
+    //the other replicas told to replicate the promoted one, and the ones that do
+    pub reconf_sent: BTreeSet<String>,
+    pub reconf_done: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailoverState {
+    //waiting to be elected by the other sentinels
+    WaitStart,
+    //the promoted replica was told to stop replicating, until it reports it is a master
+    WaitPromotion,
+    //the other replicas are told to replicate the promoted one
+    ReconfReplicas,
+}
+
+impl Failover {
+    pub fn new(epoch: u64, forced: bool) -> Self {
+        Self {
+            epoch,
+            state: FailoverState::WaitStart,
+            state_time: Instant::now(),
+            forced,
+            promoted: None,
+            reconf_sent: BTreeSet::new(),
+            reconf_done: BTreeSet::new(),
+        }
+    }
+}
+
+impl FailoverState {
+    pub fn name(self) -> &'static str {
+        match self {
+            FailoverState::WaitStart => "wait_start",
+            FailoverState::WaitPromotion => "wait_promotion",
+            FailoverState::ReconfReplicas => "reconf_slaves",
+        }
+    }
+}
+
+impl Master {
+    pub fn new(name: String, ip: String, port: u16, quorum: usize) -> Self {
+        Self {
+            name,
+            instance: Instance::new(ip, port),
+            quorum,
+            down_after: DEFAULT_DOWN_AFTER,
+            failover_timeout: DEFAULT_FAILOVER_TIMEOUT,
+            config_epoch: 0,
+            leader: None,
+            leader_epoch: 0,
+            odown_since: None,
+            failover_start: None,
+            failover: None,
+            replicas: BTreeMap::new(),
+            sentinels: BTreeMap::new(),
+        }
+    }
+
+    /// The address clients should use: the one of the replica promoted once it is a master,
+    /// while the others are told to replicate it.
+    pub fn address(&self) -> (&str, u16) {
+        let promoted = self
+            .failover
+            .as_ref()
+            .filter(|failover| failover.state == FailoverState::ReconfReplicas)
+            .and_then(|failover| failover.promoted.as_ref())
+            .and_then(|address| self.replicas.get(address));
+        let instance = promoted.unwrap_or(&self.instance);
+        (&instance.ip, instance.port)
+    }
+
+    /// SENTINEL SET and the configuration directives of the same name.
+    pub fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+        let millis = || {
+            value
+                .parse::<u64>()
+                .ok()
+                .filter(|millis| *millis > 0)
+                .map(Duration::from_millis)
+        };
+        match option.to_ascii_lowercase().as_str() {
+            "down-after-milliseconds" => {
+                self.down_after = millis().ok_or("Invalid down-after-milliseconds")?
+            }
+            "failover-timeout" => {
+                self.failover_timeout = millis().ok_or("Invalid failover-timeout")?
+            }
+            "quorum" => self.quorum = parse_quorum(value)?,
+            _ => return Err(format!("Invalid argument '{option}' to SENTINEL SET")),
+        }
+        Ok(())
+    }
+
+    /// The number of other sentinels that recently answered that the master is down.
+    pub fn down_reports(&self, validity: Duration) -> usize {
+        self.sentinels
+            .values()
+            .filter(|sentinel| {
+                sentinel.master_down
+                    && sentinel
+                        .down_reply
+                        .is_some_and(|reply| reply.elapsed() < validity)
+            })
+            .count()
+    }
+
+    /// Whether the sentinel got the votes of the majority of the sentinels, and of at least a
+    /// quorum of them, to lead the failover of the epoch.
+    pub fn elected(&self, myid: &str, epoch: u64) -> bool {
+        let voted = |leader: &Option<String>, leader_epoch: u64| {
+            leader.as_deref() == Some(myid) && leader_epoch == epoch
+        };
+        let votes = self
+            .sentinels
+            .values()
+            .filter(|sentinel| voted(&sentinel.leader, sentinel.leader_epoch))
+            .count()
+            + voted(&self.leader, self.leader_epoch) as usize;
+
+        let voters = self.sentinels.len() + 1;
+        votes >= self.quorum.max(voters / 2 + 1)
+    }
+
+    /// The replica to promote: one that is up and replicated the most, its run id breaking
+    /// ties. INFO must have reported about it within the validity.
+    pub fn select_replica(&self, validity: Duration) -> Option<String> {
+        self.replicas
+            .iter()
+            .filter(|(_, replica)| {
+                replica.sdown_since.is_none()
+                    && replica.role == Some(Role::Replica)
+                    && replica
+                        .info_refresh
+                        .is_some_and(|refresh| refresh.elapsed() < validity)
+            })
+            //the ones without a run id come last
+            .min_by(|(_, a), (_, b)| {
+                b.offset
+                    .cmp(&a.offset)
+                    .then_with(|| a.run_id.is_none().cmp(&b.run_id.is_none()))
+                    .then_with(|| a.run_id.cmp(&b.run_id))
+            })
+            .map(|(address, _)| address.clone())
+    }
+
+    /// Makes the instance at the address the master, the old one becomes one of its replicas.
+    pub fn switch_to(&mut self, ip: String, port: u16) {
+        let old = self.instance.address();
+        let mut replicas: Vec<_> = std::mem::take(&mut self.replicas)
+            .into_values()
+            .map(|replica| (replica.ip, replica.port))
+            .collect();
+        replicas.push((self.instance.ip.clone(), self.instance.port));
+
+        self.instance = Instance::new(ip, port);
+        let new = self.instance.address();
+        for (ip, port) in replicas {
+            let replica = Instance::new(ip, port);
+            if replica.address() != new {
+                self.replicas.insert(replica.address(), replica);
+            }
+        }
+        debug_assert!(old == new || self.replicas.contains_key(&old));
+
+        self.odown_since = None;
+        self.failover = None;
+        for sentinel in self.sentinels.values_mut() {
+            sentinel.master_down = false;
+            sentinel.down_reply = None;
+        }
+    }
+}
+
+impl Sentinel {
+    /// Builds the state saved by config(), the myid given is used when none was saved.
+    pub fn from_config(directives: &[Vec<String>], myid: String) -> Result<Self, String> {
+        let mut sentinel = Sentinel {
+            myid,
+            current_epoch: 0,
+            masters: BTreeMap::new(),
+        };
+
+        for directive in directives {
+            let args: Vec<&str> = directive.iter().map(String::as_str).collect();
+            let Some((statement, args)) = args.split_first() else {
+                continue;
+            };
+            let statement = statement.to_ascii_lowercase();
+            let number = |arg: &str| {
+                arg.parse::<u64>()
+                    .map_err(|_| format!("Invalid number '{arg}'"))
+            };
+
+            match (statement.as_str(), args) {
+                ("myid", [id]) if id.len() == 40 => sentinel.myid = id.to_string(),
+                ("myid", [_]) => return Err("Malformed Sentinel id in myid option.".into()),
+                ("current-epoch", [epoch]) => sentinel.current_epoch = number(epoch)?,
+                ("monitor", [name, ip, port, quorum]) => {
+                    sentinel.monitor(name, ip, port, quorum)?;
+                }
+                (_, [name, rest @ ..]) => {
+                    let master = sentinel
+                        .masters
+                        .get_mut(*name)
+                        .ok_or("No such master with specified name.")?;
+                    match (statement.as_str(), rest) {
+                        ("down-after-milliseconds" | "failover-timeout", [value]) => {
+                            master.set(&statement, value)?
+                        }
+                        ("config-epoch", [epoch]) => master.config_epoch = number(epoch)?,
+                        ("leader-epoch", [epoch]) => master.leader_epoch = number(epoch)?,
+                        ("known-replica" | "known-slave", [ip, port]) => {
+                            let replica = Instance::new(ip.to_string(), parse_port(port)?);
+                            master.replicas.insert(replica.address(), replica);
+                        }
+                        ("known-sentinel", [ip, port, run_id]) => {
+                            let mut known = Instance::new(ip.to_string(), parse_port(port)?);
+                            known.run_id = Some(run_id.to_string());
+                            master.sentinels.insert(run_id.to_string(), known);
+                        }
+                        _ => return Err("Unrecognized sentinel configuration statement.".into()),
+                    }
+                }
+                _ => return Err("Unrecognized sentinel configuration statement.".into()),
+            }
+        }
+
+        Ok(sentinel)
+    }
+
+    /// The directives saving the state, each one without the leading sentinel.
+    pub fn config(&self) -> Vec<Vec<String>> {
+        let mut directives = vec![vec!["myid".to_string(), self.myid.clone()]];
+        for master in self.masters.values() {
+            let name = master.name.clone();
+            directives.push(vec![
+                "monitor".into(),
+                name.clone(),
+                master.instance.ip.clone(),
+                master.instance.port.to_string(),
+                master.quorum.to_string(),
+            ]);
+            if master.down_after != DEFAULT_DOWN_AFTER {
+                directives.push(vec![
+                    "down-after-milliseconds".into(),
+                    name.clone(),
+                    master.down_after.as_millis().to_string(),
+                ]);
+            }
+            if master.failover_timeout != DEFAULT_FAILOVER_TIMEOUT {
+                directives.push(vec![
+                    "failover-timeout".into(),
+                    name.clone(),
+                    master.failover_timeout.as_millis().to_string(),
+                ]);
+            }
+            for (statement, epoch) in [
+                ("config-epoch", master.config_epoch),
+                ("leader-epoch", master.leader_epoch),
+            ] {
+                directives.push(vec![statement.into(), name.clone(), epoch.to_string()]);
+            }
+            for replica in master.replicas.values() {
+                directives.push(vec![
+                    "known-replica".into(),
+                    name.clone(),
+                    replica.ip.clone(),
+                    replica.port.to_string(),
+                ]);
+            }
+            for (run_id, sentinel) in &master.sentinels {
+                directives.push(vec![
+                    "known-sentinel".into(),
+                    name.clone(),
+                    sentinel.ip.clone(),
+                    sentinel.port.to_string(),
+                    run_id.clone(),
+                ]);
+            }
+        }
+        directives.push(vec!["current-epoch".into(), self.current_epoch.to_string()]);
+        directives
+    }
+
+    /// SENTINEL MONITOR and the configuration directive.
+    pub fn monitor(
+        &mut self,
+        name: &str,
+        ip: &str,
+        port: &str,
+        quorum: &str,
+    ) -> Result<(), String> {
+        if self.masters.contains_key(name) {
+            return Err("Duplicated master name.".into());
+        }
+        if ip.parse::<IpAddr>().is_err() {
+            return Err("Invalid IP address or hostname specified".into());
+        }
+        let master = Master::new(
+            name.to_string(),
+            ip.to_string(),
+            parse_port(port)?,
+            parse_quorum(quorum)?,
+        );
+        self.masters.insert(name.to_string(), master);
+        Ok(())
+    }
+
+    /// The hello message this sentinel, reachable at the address, publishes about the master.
+    pub fn hello(&self, master: &Master, ip: &str, port: u16) -> String {
+        let (master_ip, master_port) = master.address();
+        format!(
+            "{ip},{port},{},{},{},{master_ip},{master_port},{}",
+            self.myid, self.current_epoch, master.name, master.config_epoch
+        )
+    }
+}
+
+/// A hello message: a sentinel, and the master it monitors as it knows it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Hello {
+    pub ip: String,
+    pub port: u16,
+    pub run_id: String,
+    pub current_epoch: u64,
+    pub master_name: String,
+    pub master_ip: String,
+    pub master_port: u16,
+    pub master_config_epoch: u64,
+}
+
+impl Hello {
+    pub fn parse(message: &str) -> Option<Hello> {
+        let [
+            ip,
+            port,
+            run_id,
+            current_epoch,
+            master_name,
+            master_ip,
+            master_port,
+            master_config_epoch,
+        ] = message.split(',').collect::<Vec<_>>()[..]
+        else {
+            return None;
+        };
+
+        Some(Hello {
+            ip: ip.to_string(),
+            port: port.parse().ok()?,
+            run_id: run_id.to_string(),
+            current_epoch: current_epoch.parse().ok()?,
+            master_name: master_name.to_string(),
+            master_ip: master_ip.to_string(),
+            master_port: master_port.parse().ok()?,
+            master_config_epoch: master_config_epoch.parse().ok()?,
+        })
+    }
+}
+
+/// The fields of INFO a sentinel cares about.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct InfoReport {
+    pub run_id: Option<String>,
+    pub role: Option<Role>,
+    //for a replica
+    pub master_addr: Option<(String, u16)>,
+    pub master_link_up: bool,
+    pub offset: u64,
+    //for a master, the addresses of its replicas
+    pub replicas: Vec<(String, u16)>,
+}
+
+impl InfoReport {
+    pub fn parse(info: &str) -> InfoReport {
+        let mut report = InfoReport::default();
+        let (mut master_host, mut master_port) = (None, None);
+
+        for (field, value) in info.lines().filter_map(|line| line.trim().split_once(':')) {
+            match field {
+                "run_id" => report.run_id = Some(value.to_string()),
+                "role" => {
+                    report.role = match value {
+                        "master" => Some(Role::Master),
+                        "slave" => Some(Role::Replica),
+                        _ => None,
+                    }
+                }
+                "master_host" => master_host = Some(value.to_string()),
+                "master_port" => master_port = value.parse().ok(),
+                "master_link_status" => report.master_link_up = value == "up",
+                "slave_repl_offset" => report.offset = value.parse().unwrap_or(0),
+                //slave<n>:ip=<ip>,port=<port>,state=online,...
+                field if field.starts_with("slave") && field[5..].parse::<u32>().is_ok() => {
+                    let properties: BTreeMap<_, _> = value
+                        .split(',')
+                        .filter_map(|property| property.split_once('='))
+                        .collect();
+                    if let (Some(ip), Some(Ok(port))) = (
+                        properties.get("ip"),
+                        properties.get("port").map(|port| port.parse()),
+                    ) {
+                        report.replicas.push((ip.to_string(), port));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        report.master_addr = master_host.zip(master_port);
+        report
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    port.parse::<u16>()
+        .ok()
+        .filter(|port| *port > 0)
+        .ok_or_else(|| "Invalid port number".to_string())
+}
+
+fn parse_quorum(quorum: &str) -> Result<usize, String> {
+    quorum
+        .parse::<usize>()
+        .ok()
+        .filter(|quorum| *quorum > 0)
+        .ok_or_else(|| "Quorum must be 1 or greater.".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{Hello, InfoReport, Instance, Master, Role, Sentinel};
+
+    fn directives(lines: &[&str]) -> Vec<Vec<String>> {
+        lines
+            .iter()
+            .map(|line| line.split_whitespace().map(String::from).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_sentinel_config() {
+        let id = "a".repeat(40);
+        let sentinel = Sentinel::from_config(
+            &directives(&[
+                "monitor mymaster 127.0.0.1 6379 2",
+                "down-after-milliseconds mymaster 5000",
+                "known-slave mymaster 127.0.0.1 6380",
+                &format!("known-sentinel mymaster 127.0.0.1 26380 {}", "b".repeat(40)),
+                "config-epoch mymaster 3",
+                "current-epoch 4",
+            ]),
+            id.clone(),
+        )
+        .unwrap();
+        assert_eq!(sentinel.myid, id);
+        assert_eq!(sentinel.current_epoch, 4);
+        let master = &sentinel.masters["mymaster"];
+        assert_eq!(master.instance.address(), "127.0.0.1:6379");
+        assert_eq!(master.down_after, Duration::from_secs(5));
+        assert_eq!(master.config_epoch, 3);
+        assert!(master.replicas.contains_key("127.0.0.1:6380"));
+        assert_eq!(master.sentinels.len(), 1);
+
+        //what is saved reads back the same
+        let config = sentinel.config();
+        let reloaded = Sentinel::from_config(&config, "c".repeat(40)).unwrap();
+        assert_eq!(reloaded.myid, id);
+        assert_eq!(reloaded.config(), config);
+
+        for (lines, error) in [
+            (
+                &["monitor m 127.0.0.1 6379 0"][..],
+                "Quorum must be 1 or greater.",
+            ),
+            (
+                &["down-after-milliseconds m 10"],
+                "No such master with specified name.",
+            ),
+            (&["bogus"], "Unrecognized sentinel configuration statement."),
+        ] {
+            let err = Sentinel::from_config(&directives(lines), id.clone()).unwrap_err();
+            assert_eq!(err, error);
+        }
+    }
+
+    #[test]
+    fn test_hello_and_info() {
+        let sentinel =
+            Sentinel::from_config(&directives(&["monitor m 127.0.0.1 6379 1"]), "a".repeat(40))
+                .unwrap();
+        let hello = sentinel.hello(&sentinel.masters["m"], "127.0.0.1", 26379);
+        assert_eq!(
+            Hello::parse(&hello),
+            Some(Hello {
+                ip: "127.0.0.1".into(),
+                port: 26379,
+                run_id: "a".repeat(40),
+                current_epoch: 0,
+                master_name: "m".into(),
+                master_ip: "127.0.0.1".into(),
+                master_port: 6379,
+                master_config_epoch: 0,
+            })
+        );
+        assert_eq!(Hello::parse("1,2,3"), None);
+
+        let info = "# Replication\r\nrole:master\r\nconnected_slaves:2\r\n\
+            slave0:ip=127.0.0.1,port=6380,state=online,offset=10,lag=0\r\n\
+            slave1:ip=127.0.0.1,port=6381,state=online,offset=10,lag=1\r\n\
+            slave_read_repl_offset:3\r\nrun_id:abc\r\n";
+        assert_eq!(
+            InfoReport::parse(info),
+            InfoReport {
+                run_id: Some("abc".into()),
+                role: Some(Role::Master),
+                master_addr: None,
+                master_link_up: false,
+                offset: 0,
+                replicas: vec![("127.0.0.1".into(), 6380), ("127.0.0.1".into(), 6381)],
+            }
+        );
+
+        let info = "role:slave\r\nmaster_host:127.0.0.1\r\nmaster_port:6379\r\n\
+            master_link_status:up\r\nslave_repl_offset:42\r\n";
+        let report = InfoReport::parse(info);
+        assert_eq!(report.master_addr, Some(("127.0.0.1".into(), 6379)));
+        assert!(report.master_link_up);
+        assert_eq!(report.offset, 42);
+    }
+
+    #[test]
+    fn test_election_and_promotion() {
+        let myid = "a".repeat(40);
+        let mut master = Master::new("m".into(), "127.0.0.1".into(), 6379, 2);
+        for id in ["b", "c"] {
+            master
+                .sentinels
+                .insert(id.repeat(40), Instance::new("127.0.0.1".into(), 26380));
+        }
+
+        //the majority of the three sentinels is needed
+        master.leader = Some(myid.clone());
+        master.leader_epoch = 5;
+        assert!(!master.elected(&myid, 5));
+        let other = master.sentinels.get_mut(&"b".repeat(40)).unwrap();
+        other.leader = Some(myid.clone());
+        other.leader_epoch = 4;
+        assert!(!master.elected(&myid, 5));
+        master
+            .sentinels
+            .get_mut(&"b".repeat(40))
+            .unwrap()
+            .leader_epoch = 5;
+        assert!(master.elected(&myid, 5));
+
+        //the replica that replicated the most wins, the run id breaks ties
+        for (port, offset, run_id) in [(6380, 10, "z"), (6381, 20, "y"), (6382, 20, "x")] {
+            let mut replica = Instance::new("127.0.0.1".into(), port);
+            replica.role = Some(Role::Replica);
+            replica.offset = offset;
+            replica.run_id = Some(run_id.into());
+            replica.info_refresh = Some(Instant::now());
+            master.replicas.insert(replica.address(), replica);
+        }
+        let validity = Duration::from_secs(5);
+        assert_eq!(master.select_replica(validity).unwrap(), "127.0.0.1:6382");
+        master
+            .replicas
+            .get_mut("127.0.0.1:6382")
+            .unwrap()
+            .sdown_since = Some(Instant::now());
+        assert_eq!(master.select_replica(validity).unwrap(), "127.0.0.1:6381");
+
+        master.switch_to("127.0.0.1".into(), 6381);
+        assert_eq!(master.instance.address(), "127.0.0.1:6381");
+        let replicas: Vec<_> = master.replicas.keys().cloned().collect();
+        assert_eq!(
+            replicas,
+            ["127.0.0.1:6379", "127.0.0.1:6380", "127.0.0.1:6382"]
+        );
+    }
+}